@@ -0,0 +1,154 @@
+/////////////////////////////////////////////////////////////
+// benches/audio.rs
+//
+// Criterion benchmarks for the CPU work done on each captured
+// chunk before it's uploaded, run on 5s buffers in the formats
+// the mic backends produce:
+//   arecord -f cd  - 44.1 kHz stereo 16-bit, size in the header
+//   SoX rec        - 16 kHz mono 16-bit, 0xFFFFFFFF placeholder
+//                    sizes (written to a pipe)
+//
+//   wav_validate - wav::parse
+//   wav_speech   - wav::speech, how much of a chunk is speech
+//                  (batching, see pipeline.rs)
+//   segmenter    - cutting a running mic's audio at pauses
+//                  (audio.chunking = "speech"), fed as the mic
+//                  pipe delivers it
+//
+//   cargo bench --bench audio
+//
+// Compare runs on the Pi itself with --save-baseline/--baseline.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::time::Duration;
+
+// The binary has no library target, so the modules are built in directly
+#[allow(dead_code)]
+#[path = "../src/wav.rs"]
+mod wav;
+
+#[allow(dead_code)]
+#[path = "../src/segmenter.rs"]
+mod segmenter;
+
+// Just what segmenter.rs uses from the rest of the crate
+mod config {
+    pub struct AudioConfig {
+        pub min_chunk_secs: u32,
+        pub max_chunk_secs: u32,
+        pub pause_ms: u32,
+    }
+}
+
+mod error {
+    #[derive(Debug)]
+    pub enum AudioError {
+        Stopped,
+        InvalidWav(#[allow(dead_code)] crate::wav::WavError),
+    }
+}
+
+mod recorder {
+    use actix_web::web::Bytes;
+    use futures_util::Stream;
+    use std::pin::Pin;
+
+    pub type AudioStream = Pin<Box<dyn Stream<Item = Result<Bytes, crate::error::AudioError>> + Send>>;
+}
+
+const CHUNK_SECS: u32 = 5;
+// What a read from the mic's pipe usually returns
+const PIPE_READ: usize = 4096;
+
+struct Format {
+    name: &'static str,
+    sample_rate: u32,
+    channels: u16,
+    // Sizes left as placeholders, like a header written to a pipe
+    streamed: bool,
+}
+
+const FORMATS: [Format; 2] = [
+    Format { name: "arecord_cd", sample_rate: 44_100, channels: 2, streamed: false },
+    Format { name: "sox_16k_mono", sample_rate: 16_000, channels: 1, streamed: true },
+];
+
+// CHUNK_SECS of a 440 Hz tone, 16-bit PCM
+fn chunk(format: &Format) -> Vec<u8> {
+    let frames = format.sample_rate * CHUNK_SECS;
+    let block_align = format.channels * 2;
+    let data_len = frames * block_align as u32;
+    let (riff_len, data_size) = if format.streamed { (u32::MAX, u32::MAX) } else { (36 + data_len, data_len) };
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend(b"RIFF");
+    wav.extend(riff_len.to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(format.channels.to_le_bytes());
+    wav.extend(format.sample_rate.to_le_bytes());
+    wav.extend((format.sample_rate * block_align as u32).to_le_bytes());
+    wav.extend(block_align.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_size.to_le_bytes());
+    for frame in 0..frames {
+        let t = frame as f32 / format.sample_rate as f32;
+        let sample = ((t * 440.0 * std::f32::consts::TAU).sin() * 8_000.0) as i16;
+        for _ in 0..format.channels {
+            wav.extend(sample.to_le_bytes());
+        }
+    }
+    wav
+}
+
+fn validate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wav_validate");
+    for format in &FORMATS {
+        let wav = chunk(format);
+        group.bench_with_input(BenchmarkId::from_parameter(format.name), &wav, |b, wav| {
+            b.iter(|| wav::parse(black_box(wav)).expect("valid chunk"))
+        });
+    }
+    group.finish();
+}
+
+fn speech(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wav_speech");
+    for format in &FORMATS {
+        let wav = chunk(format);
+        let info = wav::parse(&wav).expect("valid chunk");
+        group.bench_with_input(BenchmarkId::from_parameter(format.name), &wav, |b, wav| {
+            b.iter(|| wav::speech(black_box(wav), &info))
+        });
+    }
+    group.finish();
+}
+
+fn segment(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().expect("runtime");
+    let settings = segmenter::Settings {
+        min: Duration::from_secs(2),
+        max: Duration::from_secs(15),
+        pause: Duration::from_millis(700),
+    };
+    let mut group = c.benchmark_group("segmenter");
+    for format in &FORMATS {
+        let wav = chunk(format);
+        group.bench_with_input(BenchmarkId::from_parameter(format.name), &wav, |b, wav| {
+            b.iter(|| {
+                let reads: Vec<_> = wav.chunks(PIPE_READ).map(|read| Ok(Bytes::copy_from_slice(read))).collect();
+                let mut listening = segmenter::Listening::new(Box::pin(futures_util::stream::iter(reads)));
+                // No pause in the tone: all of it comes out when the pipe ends
+                runtime.block_on(listening.next_chunk(settings)).expect("a chunk")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validate, speech, segment);
+criterion_main!(benches);
@@ -0,0 +1,398 @@
+/////////////////////////////////////////////////////////////
+// src/admin.rs
+//
+// Live tuning for operators:
+//   GET   /admin/settings  - the runtime tunables
+//   PATCH /admin/settings  - change some of them, e.g.
+//         {"audio": {"chunk_secs": 8}, "openai": {"history_messages": 20}}
+//   POST  /admin/shutdown  - stop the server gracefully
+//   POST  /admin/restart   - same, then let systemd start it again
+//                            (only when running under systemd)
+//   POST  /admin/keys      - rotate a provider's key (see secrets.rs)
+//
+// Field names match silentnight.toml. Changes apply at the
+// next chunk/request and last until the next restart or config
+// reload; copy them into the config file to keep them.
+//
+// Shutdown stops every recording source, tells systemd we're
+// stopping, then lets in-flight requests finish before exiting.
+//
+// Access: with admin.token (ADMIN_TOKEN) set, requests need
+// "Authorization: Bearer <token>", or an admin-scoped token (see
+// tokens.rs). Without a token, a logged-in web UI session is
+// enough. With neither configured the admin
+// endpoints stay off, since they'd otherwise be open to the LAN.
+/////////////////////////////////////////////////////////////
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{get, patch, post, web, Error, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+use crate::auth::constant_time_eq;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::tokens::{self, Scope};
+use crate::{logging, secrets, sessions, systemd, AppState};
+
+/////////////////////////////////////////////////////////////
+// has_admin_token
+//
+// True if admin.token is set and the request carries it.
+/////////////////////////////////////////////////////////////
+pub fn has_admin_token(req: &ServiceRequest, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+    req.headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/////////////////////////////////////////////////////////////
+// require_admin (middleware)
+//
+// Runs after auth::require_login, so without a token a request
+// reaching here already has a valid session if login is on.
+// Also guards /rules and /lights (see rules.rs, lights.rs).
+/////////////////////////////////////////////////////////////
+pub(crate) async fn require_admin(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let app_data = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .expect("AppState not registered");
+
+    let token = app_data.config.read().await.admin.token.clone();
+    let login_enabled = app_data.login_config.read().await.is_some();
+
+    let rejection = if !token.is_empty() {
+        let scoped = tokens::granted(&req, &app_data).await.is_some_and(|granted| granted.scope == Scope::Admin);
+        (!has_admin_token(&req, &token) && !scoped)
+            .then(|| ApiError::new(StatusCode::UNAUTHORIZED, "admin_token_required", "Admin token required"))
+    } else if !login_enabled {
+        Some(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "admin_disabled",
+            "Set admin.token (ADMIN_TOKEN) or a login username/password to use the admin endpoints",
+        ))
+    } else {
+        None
+    };
+
+    if let Some(error) = rejection {
+        tracing::warn!(reason = %error, "admin request rejected");
+        return Ok(req.into_response(error.error_response()));
+    }
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/////////////////////////////////////////////////////////////
+// AdminSettings
+//
+// The tunables, grouped like the config file.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct AdminSettings {
+    audio: AudioTunables,
+    openai: OpenAiTunables,
+    rate_limit: RateLimitTunables,
+    logging: LoggingTunables,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AudioTunables {
+    chunk_secs: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OpenAiTunables {
+    stt_model: String,
+    chat_model: String,
+    max_tokens: u32,
+    temperature: f32,
+    history_messages: usize,
+    history_tokens: usize,
+    system_prompt: String,
+    // One of openai.personas, or empty for system_prompt
+    persona: String,
+    max_concurrent: usize,
+    max_queued: usize,
+    timeout_secs: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RateLimitTunables {
+    per_minute: u32,
+    burst: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LoggingTunables {
+    level: String,
+}
+
+impl AdminSettings {
+    fn from_config(config: &Config) -> AdminSettings {
+        AdminSettings {
+            audio: AudioTunables {
+                chunk_secs: config.audio.chunk_secs,
+            },
+            openai: OpenAiTunables {
+                stt_model: config.openai.stt_model.clone(),
+                chat_model: config.openai.chat_model.clone(),
+                max_tokens: config.openai.max_tokens,
+                temperature: config.openai.temperature,
+                history_messages: config.openai.history_messages,
+                history_tokens: config.openai.history_tokens,
+                system_prompt: config.openai.system_prompt.clone(),
+                persona: config.openai.persona.clone(),
+                max_concurrent: config.openai.max_concurrent,
+                max_queued: config.openai.max_queued,
+                timeout_secs: config.openai.timeout_secs,
+            },
+            rate_limit: RateLimitTunables {
+                per_minute: config.rate_limit.per_minute,
+                burst: config.rate_limit.burst,
+            },
+            logging: LoggingTunables {
+                level: config.logging.level.clone(),
+            },
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// SettingsPatch
+//
+// Same shape as AdminSettings with everything optional.
+// Unknown fields are rejected so typos don't silently no-op.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SettingsPatch {
+    audio: AudioPatch,
+    openai: OpenAiPatch,
+    rate_limit: RateLimitPatch,
+    logging: LoggingPatch,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AudioPatch {
+    chunk_secs: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+struct OpenAiPatch {
+    stt_model: Option<String>,
+    chat_model: Option<String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    history_messages: Option<usize>,
+    history_tokens: Option<usize>,
+    system_prompt: Option<String>,
+    persona: Option<String>,
+    max_concurrent: Option<usize>,
+    max_queued: Option<usize>,
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RateLimitPatch {
+    per_minute: Option<u32>,
+    burst: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingPatch {
+    level: Option<String>,
+}
+
+impl SettingsPatch {
+    fn apply(self, config: &mut Config) {
+        set(&mut config.audio.chunk_secs, self.audio.chunk_secs);
+        set(&mut config.openai.stt_model, self.openai.stt_model);
+        set(&mut config.openai.chat_model, self.openai.chat_model);
+        set(&mut config.openai.max_tokens, self.openai.max_tokens);
+        set(&mut config.openai.temperature, self.openai.temperature);
+        set(&mut config.openai.history_messages, self.openai.history_messages);
+        set(&mut config.openai.history_tokens, self.openai.history_tokens);
+        set(&mut config.openai.system_prompt, self.openai.system_prompt);
+        set(&mut config.openai.persona, self.openai.persona);
+        set(&mut config.openai.max_concurrent, self.openai.max_concurrent);
+        set(&mut config.openai.max_queued, self.openai.max_queued);
+        set(&mut config.openai.timeout_secs, self.openai.timeout_secs);
+        set(&mut config.rate_limit.per_minute, self.rate_limit.per_minute);
+        set(&mut config.rate_limit.burst, self.rate_limit.burst);
+        set(&mut config.logging.level, self.logging.level);
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /admin/settings
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "admin",
+    path = "/admin/settings",
+    responses(
+        (status = 200, description = "Current runtime tunables", body = AdminSettings),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("/settings")]
+async fn get_settings(app_data: web::Data<AppState>) -> HttpResponse {
+    let config = app_data.config.read().await;
+    HttpResponse::Ok().json(AdminSettings::from_config(&config))
+}
+
+/////////////////////////////////////////////////////////////
+// PATCH /admin/settings
+//
+// Validated as a whole before anything changes.
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "admin",
+    path = "/admin/settings",
+    request_body = SettingsPatch,
+    responses(
+        (status = 200, description = "Updated runtime tunables", body = AdminSettings),
+        (status = 400, description = "Unknown field or invalid value (code invalid_json / invalid_settings)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[patch("/settings")]
+async fn patch_settings(
+    app_data: web::Data<AppState>,
+    patch: web::Json<SettingsPatch>,
+) -> Result<HttpResponse, ApiError> {
+    let mut live = app_data.config.write().await;
+    let mut updated = live.clone();
+    patch.into_inner().apply(&mut updated);
+    updated.validate().map_err(|e| {
+        ApiError::bad_request("invalid_settings", "Invalid settings").with_detail(format!("{e:#}"))
+    })?;
+
+    logging::set_level(&updated.logging.level).map_err(|e| {
+        ApiError::bad_request("invalid_settings", "Invalid settings").with_detail(format!("{e:#}"))
+    })?;
+    app_data.rate_limiter.update(&updated.rate_limit);
+    app_data.openai_limiter.update(&updated.openai);
+    *live = updated;
+
+    tracing::info!("admin settings updated");
+    Ok(HttpResponse::Ok().json(AdminSettings::from_config(&live)))
+}
+
+/////////////////////////////////////////////////////////////
+// Shutdown
+//
+// Set by the shutdown/restart endpoints; main() waits on it and
+// stops the server.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Shutdown {
+    requested: Notify,
+    restart: AtomicBool,
+}
+
+impl Shutdown {
+    fn request(&self, then_restart: bool) {
+        self.restart.store(then_restart, Ordering::SeqCst);
+        self.requested.notify_one();
+    }
+
+    pub async fn requested(&self) {
+        self.requested.notified().await;
+    }
+
+    pub fn restart_requested(&self) -> bool {
+        self.restart.load(Ordering::SeqCst)
+    }
+}
+
+// Stops recording so no chunk is cut off mid-pipeline by the exit
+pub async fn prepare_shutdown(app_data: &AppState) {
+    systemd::stopping();
+    for source in app_data.sources.all(app_data).await {
+        sessions::stop_source(app_data, &source).await;
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// POST /admin/shutdown, POST /admin/restart
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "admin",
+    path = "/admin/shutdown",
+    responses(
+        (status = 202, description = "Shutting down once in-flight requests finish"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("/shutdown")]
+async fn shutdown(app_data: web::Data<AppState>) -> HttpResponse {
+    tracing::warn!("shutdown requested through the admin API");
+    app_data.shutdown.request(false);
+    HttpResponse::Accepted().json(serde_json::json!({ "status": "shutting_down" }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/admin/restart",
+    responses(
+        (status = 202, description = "Restarting: exits once in-flight requests finish and systemd starts it again"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+        (status = 409, description = "Not running under systemd, so nothing would start it again (code not_supervised)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("/restart")]
+async fn restart(app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    if !systemd::supervised() {
+        return Err(ApiError::conflict(
+            "not_supervised",
+            "Not running under systemd; use /admin/shutdown and start the server again yourself",
+        ));
+    }
+    tracing::warn!("restart requested through the admin API");
+    app_data.shutdown.request(true);
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "restarting" })))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(from_fn(require_admin))
+            .service(get_settings)
+            .service(patch_settings)
+            .service(shutdown)
+            .service(restart)
+            .service(secrets::rotate_key),
+    );
+}
@@ -0,0 +1,90 @@
+/////////////////////////////////////////////////////////////
+// src/assets.rs
+//
+// The web UI (static/) is compiled into the binary, so copying
+// just the executable to a Pi still gives a working UI. Set
+// server.static_dir (STATIC_DIR) to serve the files from disk
+// instead, e.g. while editing the UI or to customise it without
+// rebuilding.
+//
+// Embedded files get an ETag (their SHA-256) so browsers can
+// revalidate with If-None-Match, like actix-files does for
+// files on disk.
+/////////////////////////////////////////////////////////////
+
+use actix_files::{Files, NamedFile};
+use actix_web::http::header;
+use actix_web::{middleware, web, HttpRequest, HttpResponse};
+use rust_embed::RustEmbed;
+use std::path::Path;
+
+use crate::error::ApiError;
+use crate::AppState;
+
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Embedded;
+
+/////////////////////////////////////////////////////////////
+// static_file
+//
+// Serves one UI file for the handlers that show a page under a
+// nicer route (/login, /docs). A missing file becomes a 404.
+/////////////////////////////////////////////////////////////
+pub async fn static_file(req: &HttpRequest, app_data: &AppState, name: &str) -> actix_web::Result<HttpResponse> {
+    let static_dir = app_data.config.read().await.server.static_dir.clone();
+    match static_dir {
+        Some(dir) => Ok(NamedFile::open_async(Path::new(&dir).join(name)).await?.into_response(req)),
+        None => Ok(embedded_file(req, name)?),
+    }
+}
+
+fn embedded_file(req: &HttpRequest, name: &str) -> Result<HttpResponse, ApiError> {
+    let Some(file) = Embedded::get(name) else {
+        return Err(ApiError::not_found("not_found", format!("No such file: /{name}")));
+    };
+
+    let hash: String = file.metadata.sha256_hash().iter().map(|b| format!("{b:02x}")).collect();
+    let etag = format!("\"{hash}\"");
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if unchanged {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(file.metadata.mimetype())
+        .insert_header((header::ETAG, etag))
+        .body(file.data.into_owned()))
+}
+
+// Catch-all for embedded mode: "/" and directories get index.html
+async fn embedded_asset(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let path = req.path().trim_start_matches('/');
+    let name = if path.is_empty() || path.ends_with('/') {
+        format!("{path}index.html")
+    } else {
+        path.to_string()
+    };
+    embedded_file(&req, &name)
+}
+
+/////////////////////////////////////////////////////////////
+// configure
+//
+// Everything not matched by another route: the UI files.
+// Register last since it matches every path. "no-cache" makes
+// browsers revalidate with the ETag (and Last-Modified for files
+// on disk), so UI changes show up on reload.
+/////////////////////////////////////////////////////////////
+pub fn configure(cfg: &mut web::ServiceConfig, static_dir: Option<&str>) {
+    let scope = web::scope("").wrap(middleware::DefaultHeaders::new().add(("Cache-Control", "no-cache")));
+    let scope = match static_dir {
+        Some(dir) => scope.service(Files::new("/", dir).index_file("index.html")),
+        None => scope.default_service(web::to(embedded_asset)),
+    };
+    cfg.service(scope);
+}
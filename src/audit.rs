@@ -0,0 +1,249 @@
+/////////////////////////////////////////////////////////////
+// src/audit.rs
+//
+// Audit log of control actions, in its own append-only file
+// (audit.file, one JSON entry per line) that nothing here
+// rewrites or deletes:
+//   - every HTTP request that changes something (any method
+//     but GET/HEAD/OPTIONS): starting and stopping, settings,
+//     rules, deletions, exports, reloads, shutdowns, logouts,
+//     including ones that were refused
+//   - logins, with the username tried
+//   - start/stop and other commands from Discord, Telegram,
+//     MQTT, gRPC, the remote, voice, HomeKit and the calendar
+// An entry is
+//   {"id", "timestamp", "actor", "via", "ip", "action",
+//    "status", "ok"}
+// where actor is the logged-in username, "admin token",
+// "token:hallway" (see tokens.rs), "anonymous", or e.g.
+// "discord:alice", and ip is the client's
+// address for HTTP. Node chunks, sync pulls and GraphQL queries
+// aren't control actions and aren't logged.
+//
+//   GET /audit  - newest first; ?actor=, ?since=, ?limit=
+// Same access as /admin (see admin.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{get, web, Error, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::sync::Mutex as AsyncMutex;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::ApiError;
+use crate::{admin, auth, logging, tokens, AppState};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+// Requests that don't change anything, or that happen on every
+// chunk or poll
+const NOT_AUDITED: [&str; 3] = ["/login", "/graphql", "/nodes/"];
+
+/////////////////////////////////////////////////////////////
+// AuditEntry
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct AuditEntry {
+    id: String,
+    // RFC 3339
+    timestamp: String,
+    // Who: a username, "admin token", "anonymous", "discord:alice", ...
+    actor: String,
+    // How: "http", "discord", "telegram", "mqtt", "grpc", "remote",
+    // "voice", "homekit" or "calendar"
+    via: String,
+    // The client's address, for HTTP
+    ip: Option<String>,
+    // "POST /sources/kitchen/start", "login", "stop kitchen", ...
+    action: String,
+    // The response's status, for HTTP
+    status: Option<u16>,
+    // Whether it was allowed and went through
+    ok: bool,
+}
+
+/////////////////////////////////////////////////////////////
+// Audit
+//
+// The audit file; appends are serialized so lines never
+// interleave.
+/////////////////////////////////////////////////////////////
+pub struct Audit {
+    path: PathBuf,
+    lock: AsyncMutex<()>,
+}
+
+impl Audit {
+    pub fn new(path: &str) -> Audit {
+        Audit {
+            path: PathBuf::from(path),
+            lock: AsyncMutex::new(()),
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) {
+        let _guard = self.lock.lock().await;
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => return tracing::error!(error = %e, "couldn't serialize an audit entry"),
+        };
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(e) = written {
+            tracing::error!(path = %self.path.display(), error = %e, "couldn't write the audit log");
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// record
+//
+// Logs a control action from outside HTTP, e.g.
+// record(app_data, "telegram", "telegram:alice", "start kitchen", true).
+/////////////////////////////////////////////////////////////
+pub async fn record(app_data: &AppState, via: &str, actor: &str, action: &str, ok: bool) {
+    write(app_data, via, actor, None, action, None, ok).await;
+}
+
+async fn write(app_data: &AppState, via: &str, actor: &str, ip: Option<String>, action: &str, status: Option<u16>, ok: bool) {
+    if !app_data.config.read().await.audit.enabled {
+        return;
+    }
+    tracing::debug!(via, actor, action, ok, "audit");
+    let entry = AuditEntry {
+        id: logging::new_id(),
+        timestamp: Utc::now().to_rfc3339(),
+        actor: actor.to_string(),
+        via: via.to_string(),
+        ip,
+        action: action.to_string(),
+        status,
+        ok,
+    };
+    app_data.audit.append(&entry).await;
+}
+
+// For POST /login, which the middleware leaves out since only the
+// handler knows the username
+pub async fn record_login(app_data: &AppState, req: &HttpRequest, username: &str, ok: bool) {
+    let ip = req.peer_addr().map(|a| a.ip().to_canonical().to_string());
+    let status = Some(303);
+    write(app_data, "http", username, ip, "login", status, ok).await;
+}
+
+/////////////////////////////////////////////////////////////
+// record_requests (middleware)
+//
+// Wraps every route, outside auth::require_login so refused
+// requests are logged too.
+/////////////////////////////////////////////////////////////
+pub async fn record_requests(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let audited = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        && !NOT_AUDITED.iter().any(|path| req.path() == *path || (path.ends_with('/') && req.path().starts_with(path)));
+    if !audited {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+    let app_data = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .expect("AppState not registered");
+
+    // Before the request runs: a logout ends the session it names
+    let actor = actor(&req, &app_data).await;
+    let ip = req.peer_addr().map(|a| a.ip().to_canonical().to_string());
+    let action = format!("{} {}", req.method(), req.path());
+    let res = next.call(req).await?.map_into_boxed_body();
+    let status = res.status();
+    let ok = status.is_success() || status.is_redirection();
+    write(&app_data, "http", &actor, ip, &action, Some(status.as_u16()), ok).await;
+    Ok(res)
+}
+
+async fn actor(req: &ServiceRequest, app_data: &AppState) -> String {
+    let admin_token = app_data.config.read().await.admin.token.clone();
+    if admin::has_admin_token(req, &admin_token) {
+        return "admin token".to_string();
+    }
+    if let Some(granted) = tokens::granted(req, app_data).await {
+        return format!("token:{}", granted.name);
+    }
+    if let Some(username) = auth::session_user(req, app_data).await {
+        return username;
+    }
+    "anonymous".to_string()
+}
+
+/////////////////////////////////////////////////////////////
+// GET /audit
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct AuditQuery {
+    // Only this actor's entries
+    actor: Option<String>,
+    // RFC 3339, inclusive
+    since: Option<String>,
+    // Default 100, at most 1000
+    limit: Option<usize>,
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit entries, newest first", body = [AuditEntry]),
+        (status = 400, description = "Bad since or limit (code invalid_query)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+        (status = 500, description = "The audit log couldn't be read (code audit_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("")]
+async fn list_audit(app_data: web::Data<AppState>, query: web::Query<AuditQuery>) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::bad_request("invalid_query", format!("limit must be between 1 and {MAX_LIMIT}")));
+    }
+    let since = match &query.since {
+        Some(since) => Some(DateTime::parse_from_rfc3339(since).map_err(|_| {
+            ApiError::bad_request("invalid_query", format!("since must be an RFC 3339 timestamp, got {since:?}"))
+        })?),
+        None => None,
+    };
+
+    let contents = {
+        let _guard = app_data.audit.lock.lock().await;
+        match tokio::fs::read_to_string(&app_data.audit.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                let path = app_data.audit.path.display();
+                return Err(ApiError::internal("audit_unreadable", format!("Failed to read {path}")).with_detail(e));
+            }
+        }
+    };
+    let entries: Vec<AuditEntry> = contents
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+        .filter(|e| query.actor.as_ref().is_none_or(|actor| &e.actor == actor))
+        .filter(|e| {
+            since.is_none_or(|since| DateTime::parse_from_rfc3339(&e.timestamp).is_ok_and(|t| t >= since))
+        })
+        .take(limit)
+        .collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/audit").wrap(from_fn(admin::require_admin)).service(list_audit));
+}
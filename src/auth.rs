@@ -0,0 +1,380 @@
+/////////////////////////////////////////////////////////////
+// src/auth.rs
+//
+// Simple login for the web UI.
+//
+// If a login username and password are configured ([login]
+// in the config file, or UI_USERNAME/UI_PASSWORD), every route
+// except /login and its strings at /i18n (and the /health
+// probes, Discord's signed /discord/interactions and QR pairing's
+// /pair, see pairing.rs) requires a session cookie. Logging in
+// with the right credentials creates a random session token
+// kept in memory (so a restart logs everyone out). If either
+// variable is missing, login is disabled and everything stays
+// open like before.
+//
+// With login on, scoped tokens ([tokens.<name>]) get in too, but
+// only as far as their scope allows (see tokens.rs), and their
+// workspace if they have one (see workspaces.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::LOCATION;
+use actix_web::middleware::Next;
+use actix_web::{get, post, web, Error, HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError};
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::Mutex as AsyncMutex;
+use utoipa::ToSchema;
+
+use crate::config::LoginSettings;
+use crate::error::ApiError;
+use crate::assets::static_file;
+use crate::tokens::{self, Scope};
+use crate::{admin, audit, hub, sync, workspaces, AppState};
+
+// Who a request got in as, put in its extensions by
+// require_login; QR pairing hands the same on (see pairing.rs)
+#[derive(Clone)]
+pub struct Caller {
+    pub scope: Scope,
+    // The [tokens] entry; None for the login or admin.token
+    pub token: Option<String>,
+}
+
+// Name of the cookie holding the session token
+const SESSION_COOKIE: &str = "sn_session";
+// How long a login stays valid
+const SESSION_TTL_HOURS: i64 = 12;
+
+/////////////////////////////////////////////////////////////
+// LoginConfig
+//
+// Username/password for the web UI, from the [login] settings.
+/////////////////////////////////////////////////////////////
+pub struct LoginConfig {
+    username: String,
+    password: String,
+}
+
+impl LoginConfig {
+    // Returns None (login disabled) unless both are set
+    pub fn from_settings(settings: &LoginSettings) -> Option<LoginConfig> {
+        if settings.username.is_empty() || settings.password.is_empty() {
+            return None;
+        }
+        Some(LoginConfig {
+            username: settings.username.clone(),
+            password: settings.password.clone(),
+        })
+    }
+
+    fn matches(&self, username: &str, password: &str) -> bool {
+        // Check both so timing doesn't reveal which one was wrong
+        let user_ok = constant_time_eq(self.username.as_bytes(), username.as_bytes());
+        let pass_ok = constant_time_eq(self.password.as_bytes(), password.as_bytes());
+        user_ok & pass_ok
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// LoginSessions
+//
+// Active session tokens, when they expire, and what they may do.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct LoginSessions {
+    tokens: AsyncMutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    expires: DateTime<Utc>,
+    scope: Scope,
+    // The [tokens] entry it came from; None for the login
+    token: Option<String>,
+}
+
+impl LoginSessions {
+    async fn create(&self, scope: Scope, token_name: Option<String>) -> String {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(48)
+            .map(char::from)
+            .collect();
+
+        let mut tokens = self.tokens.lock().await;
+        // Drop expired sessions while we're here
+        let now = Utc::now();
+        tokens.retain(|_, session| session.expires > now);
+        let session = Session { expires: now + Duration::hours(SESSION_TTL_HOURS), scope, token: token_name };
+        tokens.insert(token.clone(), session);
+        token
+    }
+
+    // The session's scope and token name, if it's valid
+    async fn get(&self, token: &str) -> Option<(Scope, Option<String>)> {
+        let tokens = self.tokens.lock().await;
+        tokens
+            .get(token)
+            .filter(|session| session.expires > Utc::now())
+            .map(|session| (session.scope, session.token.clone()))
+    }
+
+    async fn remove(&self, token: &str) {
+        self.tokens.lock().await.remove(token);
+    }
+}
+
+// A valid session, as it stands now
+struct Current {
+    scope: Scope,
+    token: Option<String>,
+    // The token's workspace; "" = none (see workspaces.rs)
+    workspace: String,
+}
+
+// One made from a token is held to that token as it's configured
+// now: it never outranks the token's scope, stays in its current
+// workspace, and revoking the token on a reload ends it
+async fn current_session(app_data: &AppState, session: &str) -> Option<Current> {
+    let (scope, token) = app_data.login_sessions.get(session).await?;
+    let Some(name) = token else {
+        return Some(Current { scope, token: None, workspace: String::new() });
+    };
+    let now = app_data
+        .config
+        .read()
+        .await
+        .tokens
+        .get(&name)
+        .filter(|settings| !settings.token.is_empty())
+        .and_then(|settings| Some((Scope::parse(&settings.scope)?, settings.workspace.clone())));
+    match now {
+        Some((now, workspace)) => Some(Current { scope: scope.min(now), token: Some(name), workspace }),
+        None => {
+            tracing::info!(token = %name, "ending a revoked token's session");
+            app_data.login_sessions.remove(session).await;
+            None
+        }
+    }
+}
+
+// The username behind the request's session cookie, if it's a
+// valid one (there's only the one login), or "token:<name>" for
+// a token's session
+pub(crate) async fn session_user(req: &ServiceRequest, app_data: &AppState) -> Option<String> {
+    let cookie = req.cookie(SESSION_COOKIE)?;
+    match current_session(app_data, cookie.value()).await?.token {
+        Some(token) => Some(format!("token:{token}")),
+        None => app_data.login_config.read().await.as_ref().map(|config| config.username.clone()),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// require_login (middleware)
+//
+// Lets the request through if login is disabled, the path is
+// the login page or a /health probe, or the session cookie
+// or a scoped token is valid and its scope allows the request
+// (else a 403).
+// Otherwise browsers asking for a page get redirected to
+// /login and everything else gets a 401.
+/////////////////////////////////////////////////////////////
+pub async fn require_login(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let app_data = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .expect("AppState not registered");
+
+    let login_enabled = app_data.login_config.read().await.is_some();
+    if !login_enabled
+        || matches!(req.path(), "/login" | "/i18n" | "/pair" | "/health" | "/health/ready" | "/discord/interactions")
+        // Nodes have hub.token instead (see hub.rs), peers sync.token
+        || hub::is_node_request(&req)
+        || sync::is_peer_request(&req)
+    {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    // Scripts holding the admin token don't need a UI session
+    let admin_token = app_data.config.read().await.admin.token.clone();
+    if admin::has_admin_token(&req, &admin_token) {
+        req.extensions_mut().insert(Caller { scope: Scope::Admin, token: None });
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let required = tokens::required(req.method(), req.path());
+    if let Some(granted) = tokens::granted(&req, &app_data).await {
+        if granted.scope < required {
+            let error = tokens::insufficient(&format!("The {} token", granted.name), granted.scope, &req);
+            tracing::warn!(token = %granted.name, reason = %error, "token request refused");
+            return Ok(req.into_response(error.error_response()));
+        }
+        if let Err(error) = confine(&req, &app_data, &granted.workspace).await {
+            tracing::warn!(token = %granted.name, reason = %error, "token request refused");
+            return Ok(req.into_response(error.error_response()));
+        }
+        req.extensions_mut().insert(Caller { scope: granted.scope, token: Some(granted.name.clone()) });
+        let mut res = next.call(req).await?.map_into_boxed_body();
+        if granted.from_query {
+            let cookie = new_scoped_session(&app_data, granted.scope, Some(granted.name)).await;
+            res.response_mut().add_cookie(&cookie)?;
+        }
+        return Ok(res);
+    }
+
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        if let Some(session) = current_session(&app_data, cookie.value()).await {
+            if session.scope < required {
+                let who = session.token.map_or_else(|| "This session".to_string(), |name| format!("The {name} token's session"));
+                let error = tokens::insufficient(&who, session.scope, &req);
+                return Ok(req.into_response(error.error_response()));
+            }
+            if let Err(error) = confine(&req, &app_data, &session.workspace).await {
+                return Ok(req.into_response(error.error_response()));
+            }
+            req.extensions_mut().insert(Caller { scope: session.scope, token: session.token });
+            return Ok(next.call(req).await?.map_into_boxed_body());
+        }
+    }
+
+    tracing::info!("rejecting unauthenticated request");
+    let wants_html = req
+        .headers()
+        .get("Accept")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false);
+
+    let resp = if wants_html {
+        HttpResponse::SeeOther().insert_header((LOCATION, "/login")).finish()
+    } else {
+        ApiError::unauthorized("Login required").error_response()
+    };
+    Ok(req.into_response(resp))
+}
+
+// Keeps a request from a token with a workspace inside it, and
+// tells the handlers which one it is (see workspaces.rs)
+async fn confine(req: &ServiceRequest, app_data: &AppState, workspace: &str) -> Result<(), ApiError> {
+    if workspace.is_empty() {
+        return Ok(());
+    }
+    if !workspaces::allows(req, &*app_data.config.read().await, workspace) {
+        return Err(workspaces::outside(workspace, req));
+    }
+    req.extensions_mut().insert(workspaces::Confined(workspace.to_string()));
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// GET /login  => Serve static/login.html
+/////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "auth", responses((status = 200, description = "Login form", content_type = "text/html")))]
+#[get("/login")]
+async fn login_page(req: HttpRequest, app_data: web::Data<AppState>) -> actix_web::Result<HttpResponse> {
+    static_file(&req, &app_data, "login.html").await
+}
+
+/////////////////////////////////////////////////////////////
+// POST /login
+//
+// Checks the submitted form against LoginConfig and sets the
+// session cookie on success.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct LoginForm {
+    username: String,
+    password: String,
+}
+
+#[utoipa::path(
+    tag = "auth",
+    request_body(content = LoginForm, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 303, description = "Redirect to / on success (with session cookie) or /login?failed=1")),
+)]
+#[post("/login")]
+async fn login(req: HttpRequest, form: web::Form<LoginForm>, app_data: web::Data<AppState>) -> impl Responder {
+    let login_config = app_data.login_config.read().await;
+    let Some(config) = login_config.as_ref() else {
+        // Nothing to log into, just send them to the UI
+        return HttpResponse::SeeOther().insert_header((LOCATION, "/")).finish();
+    };
+
+    let ok = config.matches(&form.username, &form.password);
+    drop(login_config);
+    audit::record_login(&app_data, &req, &form.username, ok).await;
+    if !ok {
+        tracing::warn!(username = %form.username, "bad username or password");
+        return HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/login?failed=1"))
+            .finish();
+    }
+
+    let cookie = new_session(&app_data).await;
+    tracing::info!(username = %form.username, "login ok, session created");
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/"))
+        .cookie(cookie)
+        .finish()
+}
+
+// A new login session, as the cookie that holds it
+async fn new_session(app_data: &AppState) -> Cookie<'static> {
+    new_scoped_session(app_data, Scope::Admin, None).await
+}
+
+pub(crate) async fn new_scoped_session(app_data: &AppState, scope: Scope, token_name: Option<String>) -> Cookie<'static> {
+    let token = app_data.login_sessions.create(scope, token_name).await;
+    Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .max_age(actix_web::cookie::time::Duration::hours(SESSION_TTL_HOURS))
+        .finish()
+}
+
+/////////////////////////////////////////////////////////////
+// POST /logout
+//
+// Forgets the session and clears the cookie.
+/////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "auth", responses((status = 303, description = "Session cleared, redirect to /login")))]
+#[post("/logout")]
+async fn logout(req: HttpRequest, app_data: web::Data<AppState>) -> impl Responder {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        app_data.login_sessions.remove(cookie.value()).await;
+    }
+
+    let mut removal = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    removal.make_removal();
+
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(removal)
+        .finish()
+}
+
+/////////////////////////////////////////////////////////////
+// configure
+//
+// Registers the login/logout routes on the App.
+/////////////////////////////////////////////////////////////
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(login_page).service(login).service(logout);
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
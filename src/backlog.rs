@@ -0,0 +1,421 @@
+/////////////////////////////////////////////////////////////
+// src/backlog.rs
+//
+// Record-only mode. When Whisper can't be used at all (no API
+// key, or OpenAI unreachable after the stage's retries), the
+// recording loop keeps capturing and each chunk waits on disk in
+// backlog.dir instead of being dropped:
+//
+//   <recorded_at>-<chunk_id>.wav   the audio
+//   <recorded_at>-<chunk_id>.json  source, session and chunk ID
+//
+// A transcript GPT can't answer for the same reasons waits there
+// too, as a .json alone with its text (and its entry_id, if
+// deadline_stage logged it ahead). The JSON is written last, so
+// it marks a complete entry, and the names sort oldest first by
+// when the chunk was recorded. The backlog survives restarts.
+//
+// Every backlog.retry_secs (30), while anything is waiting and
+// there's an API key, the backlog is worked through in the
+// background: each chunk transcribed, answered and logged,
+// oldest first, under the session it was recorded in. Set
+// backlog.auto_drain = false to leave that to
+//
+//   POST /process_backlog - the same, now
+//
+// Processing stops (leaving the rest for the next try) at the
+// first entry that fails because OpenAI is still unavailable; an
+// entry that fails for any other reason is moved to
+// <dir>/failed/ so it doesn't block the others. /live_log gets a
+// "backlog_progress" SSE event per entry taken out:
+//
+//   {"chunk_id":"...","audio_source":"default","session_id":"...",
+//    "result":"processed","remaining":12}
+//
+// ("failed" when it went to failed/), and a "backlog_done" when
+// a pass that took any out ends, with what it did and what's
+// left:
+//
+//   {"processed":13,"failed":0,"remaining":0}
+//
+// GET /status shows the counts under `backlog`.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{middleware, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::error::{ApiError, StorageError};
+use crate::sessions::{self, SourceSession};
+use crate::pipeline::{self, Details};
+use crate::{rate_limit, secrets, AppState};
+
+const FAILED_DIR: &str = "failed";
+
+#[derive(Default)]
+pub struct Backlog {
+    processing: AtomicBool,
+    // Chunks taken out of the backlog since startup
+    processed: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+// The .json half of an entry
+#[derive(Serialize, Deserialize)]
+struct Deferred {
+    audio_source: String,
+    session_id: Option<String>,
+    chunk_id: String,
+    recorded_at: String,
+    // Set for a transcript waiting for GPT, which has no .wav
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transcript: Option<Unanswered>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Unanswered {
+    pub text: String,
+    pub details: Details,
+    // Its entry_id, if it was logged ahead (see deadline_stage)
+    pub logged: Option<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// defer
+//
+// Puts a chunk in the backlog, or with `transcript` only what
+// Whisper made of it. Full (backlog.max_chunks) is a
+// StorageError, which stops the recording.
+/////////////////////////////////////////////////////////////
+pub async fn defer(
+    app_data: &AppState,
+    source: &SourceSession,
+    chunk_id: &str,
+    recorded_at: DateTime<Utc>,
+    audio_data: &[u8],
+) -> Result<(), StorageError> {
+    write_entry(app_data, source, chunk_id, recorded_at, Some(audio_data), None).await
+}
+
+pub(crate) async fn defer_transcript(
+    app_data: &AppState,
+    source: &SourceSession,
+    chunk_id: &str,
+    recorded_at: DateTime<Utc>,
+    transcript: Unanswered,
+) -> Result<(), StorageError> {
+    write_entry(app_data, source, chunk_id, recorded_at, None, Some(transcript)).await
+}
+
+async fn write_entry(
+    app_data: &AppState,
+    source: &SourceSession,
+    chunk_id: &str,
+    recorded_at: DateTime<Utc>,
+    audio_data: Option<&[u8]>,
+    transcript: Option<Unanswered>,
+) -> Result<(), StorageError> {
+    let settings = app_data.config.read().await.backlog.clone();
+    let dir = Path::new(&settings.dir);
+    if queued(dir).await.len() >= settings.max_chunks {
+        return Err(StorageError::BacklogFull { dir: settings.dir, max: settings.max_chunks });
+    }
+
+    let entry = Deferred {
+        audio_source: source.name.clone(),
+        session_id: source.session_id.borrow().clone(),
+        chunk_id: chunk_id.to_string(),
+        recorded_at: recorded_at.to_rfc3339(),
+        transcript,
+    };
+    let meta_path = dir.join(format!("{}-{chunk_id}.json", recorded_at.format("%Y%m%dT%H%M%S%.3fZ")));
+    let failed = |path: &Path| {
+        let path = path.display().to_string();
+        move |source| StorageError::SaveAudio { path, source }
+    };
+
+    tokio::fs::create_dir_all(dir).await.map_err(failed(dir))?;
+    if let Some(audio_data) = audio_data {
+        let audio_path = meta_path.with_extension("wav");
+        tokio::fs::write(&audio_path, audio_data).await.map_err(failed(&audio_path))?;
+    }
+    let meta = serde_json::to_vec(&entry).map_err(StorageError::Serialize)?;
+    tokio::fs::write(&meta_path, meta).await.map_err(failed(&meta_path))?;
+    tracing::info!(path = %meta_path.display(), "added to the backlog");
+    Ok(())
+}
+
+// Removes `source_name`'s chunks recorded since `since` (see
+// forget.rs); returns how many
+pub async fn forget(dir: &Path, source_name: &str, since: DateTime<Utc>) -> usize {
+    let mut removed = 0;
+    for meta_path in queued(dir).await {
+        let Ok(entry) = read_entry(&meta_path).await else { continue };
+        let recorded_since = DateTime::parse_from_rfc3339(&entry.recorded_at).is_ok_and(|t| t >= since);
+        if entry.audio_source == source_name && recorded_since {
+            remove_entry(&meta_path).await;
+            removed += 1;
+        }
+    }
+    removed
+}
+
+// The .json files of complete entries, oldest first
+async fn queued(dir: &Path) -> Vec<PathBuf> {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+    let mut queued = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            queued.push(path);
+        }
+    }
+    queued.sort();
+    queued
+}
+
+/////////////////////////////////////////////////////////////
+// process
+//
+// Works through the backlog once, see the top of the file.
+/////////////////////////////////////////////////////////////
+async fn process(app_data: &web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    let dir = app_data.config.read().await.backlog.dir.clone();
+    let entries = queued(Path::new(&dir)).await;
+    tracing::info!(queued = entries.len(), "processing the backlog");
+
+    let (mut processed, mut failed) = (0, 0);
+    for (taken, meta_path) in entries.iter().enumerate() {
+        if shutdown.is_cancelled() {
+            break;
+        }
+        let entry = match read_entry(meta_path).await {
+            Ok(entry) => entry,
+            Err(reason) => {
+                set_aside(app_data, meta_path, reason).await;
+                failed += 1;
+                continue;
+            }
+        };
+        let remaining = entries.len() - taken - 1;
+        let result = match take_out(app_data, meta_path, &entry).await {
+            Ok(()) => {
+                remove_entry(meta_path).await;
+                app_data.backlog.processed.fetch_add(1, Ordering::Relaxed);
+                processed += 1;
+                "processed"
+            }
+            Err(Taken::Offline(message)) => {
+                tracing::warn!(error = %message, "OpenAI still unavailable, leaving the rest of the backlog");
+                *app_data.backlog.last_error.lock().unwrap() = Some(message);
+                break;
+            }
+            Err(Taken::Failed(reason)) => {
+                set_aside(app_data, meta_path, reason).await;
+                failed += 1;
+                "failed"
+            }
+        };
+        let progress = serde_json::json!({
+            "chunk_id": entry.chunk_id,
+            "audio_source": entry.audio_source,
+            "session_id": entry.session_id,
+            "result": result,
+            "remaining": remaining,
+        });
+        app_data.events.publish_notice("backlog_progress", progress.to_string());
+    }
+
+    let remaining = queued(Path::new(&dir)).await.len();
+    if processed + failed > 0 {
+        let done = serde_json::json!({"processed": processed, "failed": failed, "remaining": remaining});
+        app_data.events.publish_notice("backlog_done", done.to_string());
+    }
+    tracing::info!(processed, failed, remaining, "done processing the backlog");
+}
+
+enum Taken {
+    // OpenAI is still unavailable; the entry stays
+    Offline(String),
+    Failed(String),
+}
+
+// Transcribes (unless it's a transcript), answers and logs an entry
+async fn take_out(app_data: &web::Data<AppState>, meta_path: &Path, entry: &Deferred) -> Result<(), Taken> {
+    let Some(source) = app_data.sources.get(app_data, &entry.audio_source).await else {
+        return Err(Taken::Failed(format!("audio source {:?} is no longer configured", entry.audio_source)));
+    };
+    let span = tracing::info_span!("chunk", source = %source.name, chunk_id = %entry.chunk_id, stage = "backlog");
+    let session_id = entry.session_id.as_deref();
+    let result = match &entry.transcript {
+        Some(Unanswered { text, details, logged }) => {
+            pipeline::answer_deferred(app_data, &source, &entry.chunk_id, session_id, text, details, logged.as_deref())
+                .instrument(span)
+                .await
+        }
+        None => {
+            let audio_data = read_audio(meta_path).await.map_err(Taken::Failed)?;
+            pipeline::process_deferred(app_data, &source, &entry.chunk_id, session_id, &audio_data)
+                .instrument(span)
+                .await
+        }
+    };
+    result.map_err(|e| {
+        if e.is_offline() || e.is_retryable() {
+            Taken::Offline(e.report())
+        } else {
+            Taken::Failed(e.report())
+        }
+    })
+}
+
+async fn read_entry(meta_path: &Path) -> Result<Deferred, String> {
+    let meta = tokio::fs::read(meta_path).await.map_err(|e| format!("reading {}: {e}", meta_path.display()))?;
+    serde_json::from_slice(&meta).map_err(|e| format!("parsing {}: {e}", meta_path.display()))
+}
+
+async fn read_audio(meta_path: &Path) -> Result<web::Bytes, String> {
+    let audio_path = meta_path.with_extension("wav");
+    let audio_data = tokio::fs::read(&audio_path)
+        .await
+        .map_err(|e| format!("reading {}: {e}", audio_path.display()))?;
+    Ok(audio_data.into())
+}
+
+async fn remove_entry(meta_path: &Path) {
+    // The .json first, so a half-removed entry is never picked up again
+    for path in [meta_path.to_path_buf(), meta_path.with_extension("wav")] {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            // A transcript has no .wav
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "couldn't remove backlog file"),
+        }
+    }
+}
+
+// Moves an entry that won't go through to <dir>/failed/
+async fn set_aside(app_data: &AppState, meta_path: &Path, reason: String) {
+    tracing::warn!(entry = %meta_path.display(), error = %reason, "moving backlog entry to failed/");
+    *app_data.backlog.last_error.lock().unwrap() = Some(reason);
+    let Some(dir) = meta_path.parent() else { return };
+    let failed_dir = dir.join(FAILED_DIR);
+    if let Err(e) = tokio::fs::create_dir_all(&failed_dir).await {
+        tracing::warn!(path = %failed_dir.display(), error = %e, "couldn't create the failed backlog directory");
+        return;
+    }
+    // The .wav first, so the .json still marks the entry if this fails halfway
+    for path in [meta_path.with_extension("wav"), meta_path.to_path_buf()] {
+        let Some(name) = path.file_name() else { continue };
+        match tokio::fs::rename(&path, failed_dir.join(name)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(path = %path.display(), error = %e, "couldn't move backlog file"),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// BacklogStatus (GET /status `backlog`)
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct BacklogStatus {
+    enabled: bool,
+    auto_drain: bool,
+    dir: String,
+    // Chunks waiting, and chunks moved to failed/
+    pub queued: usize,
+    pub failed: usize,
+    // Being worked through, automatically or for POST /process_backlog
+    processing: bool,
+    processed: u64,
+    pub last_error: Option<String>,
+}
+
+pub(crate) async fn status(app_data: &AppState) -> BacklogStatus {
+    let settings = app_data.config.read().await.backlog.clone();
+    let dir = Path::new(&settings.dir);
+    BacklogStatus {
+        enabled: settings.enabled,
+        auto_drain: settings.auto_drain,
+        queued: queued(dir).await.len(),
+        failed: queued(&dir.join(FAILED_DIR)).await.len(),
+        processing: app_data.backlog.processing.load(Ordering::SeqCst),
+        processed: app_data.backlog.processed.load(Ordering::Relaxed),
+        last_error: secrets::redact_opt(app_data.backlog.last_error.lock().unwrap().clone()),
+        dir: settings.dir,
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// POST /process_backlog
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "recording",
+    responses(
+        (status = 202, description = "Processing the backlog in the background", body = BacklogStatus),
+        (status = 409, description = "Already processing it (code backlog_busy)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 503, description = "No OpenAI API key configured (code openai_not_configured)", body = ErrorBody),
+    ),
+)]
+#[post("/process_backlog", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn process_backlog(app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    sessions::require_openai(&app_data).await?;
+    if app_data.backlog.processing.swap(true, Ordering::SeqCst) {
+        return Err(ApiError::conflict("backlog_busy", "The backlog is already being processed"));
+    }
+    *app_data.backlog.last_error.lock().unwrap() = None;
+
+    let shared_state = app_data.clone();
+    app_data.tasks.spawn("backlog", async move {
+        process(&shared_state).await;
+        shared_state.backlog.processing.store(false, Ordering::SeqCst);
+    });
+    Ok(HttpResponse::Accepted().json(status(&app_data).await))
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Works through the backlog every backlog.retry_secs while
+// anything waits in it, unless backlog.auto_drain is off.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "backlog");
+    app_data.tasks.spawn("backlog_drain", drain(app_data.clone()).instrument(span));
+}
+
+async fn drain(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    loop {
+        let settings = app_data.config.read().await.backlog.clone();
+        let waiting = !queued(Path::new(&settings.dir)).await.is_empty();
+        if settings.auto_drain
+            && waiting
+            && sessions::require_openai(&app_data).await.is_ok()
+            && !app_data.backlog.processing.swap(true, Ordering::SeqCst)
+        {
+            *app_data.backlog.last_error.lock().unwrap() = None;
+            process(&app_data).await;
+            app_data.backlog.processing.store(false, Ordering::SeqCst);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(settings.retry_secs)) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(process_backlog);
+}
@@ -0,0 +1,122 @@
+/////////////////////////////////////////////////////////////
+// src/bookmarks.rs
+//
+// Bookmarks: "that was important", marked on a source's current
+// moment from the remote (see control.rs). Each one is
+//   {"id", "timestamp", "audio_source", "session_id",
+//    "transcript", "gpt_response"}
+// with the source's latest transcript and response, so it reads
+// on its own. It's appended to bookmarks.json (one per line),
+// sent to SSE clients as a "bookmark" event and to webhooks
+// subscribed to "bookmark". "Forget the last ..." removes the
+// source's recent ones (see forget.rs).
+//
+//   GET /bookmarks[?session=<id>]  - oldest first
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use utoipa::IntoParams;
+
+use crate::error::ApiError;
+use crate::sessions::SourceSession;
+use crate::{forget, logging, webhooks, AppState};
+
+const BOOKMARKS_LOG: &str = "bookmarks.json";
+
+/////////////////////////////////////////////////////////////
+// add
+//
+// Bookmarks what `source` just heard; returns the bookmark.
+/////////////////////////////////////////////////////////////
+pub async fn add(app_data: &web::Data<AppState>, source: &SourceSession) -> Result<Value> {
+    let latest = source.latest.borrow().clone();
+    let bookmark = json!({
+        "id": logging::new_id(),
+        "timestamp": Utc::now().to_rfc3339(),
+        "audio_source": source.name,
+        "session_id": *source.session_id.borrow(),
+        "transcript": latest.transcript,
+        "gpt_response": latest.gpt_response,
+    });
+    let line = bookmark.to_string();
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(BOOKMARKS_LOG)
+        .with_context(|| format!("Failed to open {BOOKMARKS_LOG}"))?;
+    writeln!(file, "{line}").with_context(|| format!("Failed to write {BOOKMARKS_LOG}"))?;
+    tracing::info!(source = %source.name, id = %bookmark["id"], "bookmarked");
+
+    app_data.events.publish_notice("bookmark", line.clone());
+    source.events.publish_notice("bookmark", line);
+    webhooks::send(app_data, "bookmark", bookmark.clone()).await;
+    Ok(bookmark)
+}
+
+/////////////////////////////////////////////////////////////
+// GET /bookmarks
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct BookmarkFilter {
+    // Only this session's
+    session: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(BookmarkFilter),
+    responses(
+        (status = 200, description = "Bookmarks, oldest first", body = [Object]),
+        (status = 500, description = "bookmarks.json couldn't be read (code bookmarks_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/bookmarks")]
+async fn list_bookmarks(filter: web::Query<BookmarkFilter>) -> Result<HttpResponse, ApiError> {
+    let contents = match tokio::fs::read_to_string(BOOKMARKS_LOG).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(ApiError::internal("bookmarks_unreadable", format!("Failed to read {BOOKMARKS_LOG}")).with_detail(e));
+        }
+    };
+    let bookmarks: Vec<Value> = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|b| filter.session.as_ref().is_none_or(|id| b["session_id"] == id.as_str()))
+        .collect();
+    Ok(HttpResponse::Ok().json(bookmarks))
+}
+
+// Removes `source_name`'s bookmarks since `since` (see
+// forget.rs); returns how many
+pub fn forget(source_name: &str, since: DateTime<Utc>) -> Result<usize> {
+    let contents = match std::fs::read_to_string(BOOKMARKS_LOG) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {BOOKMARKS_LOG}")),
+    };
+    let mut kept = String::with_capacity(contents.len());
+    let mut removed = 0;
+    for line in contents.lines() {
+        match serde_json::from_str::<Value>(line) {
+            Ok(b) if b["audio_source"] == source_name && forget::recorded_since(&b, since) => removed += 1,
+            _ => {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+    }
+    if removed > 0 {
+        std::fs::write(BOOKMARKS_LOG, kept).with_context(|| format!("Failed to write {BOOKMARKS_LOG}"))?;
+    }
+    Ok(removed)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_bookmarks);
+}
@@ -0,0 +1,317 @@
+/////////////////////////////////////////////////////////////
+// src/browse.rs
+//
+// A transcript browser rendered on the server, for looking back
+// at past sessions from any browser (the live UI only shows what
+// happens while it's open):
+//   GET /sessions       - every session in conversation_log.json
+//                         (or ?workspace=, see workspaces.rs),
+//                         newest first, with its title, source,
+//                         start, length and chunk count
+//   GET /sessions/{id}  - one session's transcript: each chunk's
+//                         time, who spoke (see speakers.rs) and
+//                         GPT's response, when it had one;
+//                         ?lang=es shows its Spanish translation
+//                         with the original under each line (see
+//                         translate.rs)
+// Pages are templates/layout.html with the content filled in
+// here; both are compiled into the binary, and no JavaScript is
+// involved. Everything from the log is HTML-escaped. The words
+// around it are in the reader's language (see i18n.rs).
+//
+// Chunks recorded outside a session (POST /record_once) and
+// entries synced from peers (see sync.rs) aren't listed. Behind
+// the login like the rest of the UI.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use utoipa::IntoParams;
+
+use crate::error::ApiError;
+use crate::i18n::{self, Strings};
+use crate::{digest, pipeline, timezone, translate, workspaces, AppState};
+
+const LAYOUT: &str = include_str!("../templates/layout.html");
+
+// A session, as listed on /sessions
+struct SessionRow {
+    id: String,
+    title: Option<String>,
+    audio_source: String,
+    started: DateTime<Utc>,
+    ended: DateTime<Utc>,
+    chunks: usize,
+}
+
+// A transcribed chunk, as shown on /sessions/{id}
+struct Entry {
+    at: DateTime<Utc>,
+    speakers: Vec<String>,
+    transcript: String,
+    // What was said, when the transcript is a translation
+    original: Option<String>,
+    response: Option<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sessions
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    path = "/sessions",
+    params(workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "Past sessions, newest first", content_type = "text/html"),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 500, description = "The log couldn't be read (code log_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/sessions")]
+async fn list_sessions(req: HttpRequest, app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let language = i18n::language(&app_data, &req).await;
+    let strings = i18n::strings(language);
+    let records = read_log(&req, &app_data).await?;
+    let mut sessions: HashMap<String, SessionRow> = HashMap::new();
+    for record in &records {
+        let (Some(id), Some(at)) = (record["session_id"].as_str(), timestamp(record)) else {
+            continue;
+        };
+        let row = sessions.entry(id.to_string()).or_insert_with(|| SessionRow {
+            id: id.to_string(),
+            title: None,
+            audio_source: record["audio_source"].as_str().unwrap_or_default().to_string(),
+            started: at,
+            ended: at,
+            chunks: 0,
+        });
+        row.started = row.started.min(at);
+        row.ended = row.ended.max(at);
+        if record["source"] == "Microphone" {
+            row.chunks += 1;
+        }
+        if row.title.is_none() {
+            row.title = record["session_title"].as_str().map(str::to_string);
+        }
+    }
+    let mut sessions: Vec<SessionRow> = sessions.into_values().collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.started));
+
+    let mut content = String::new();
+    if sessions.is_empty() {
+        let _ = writeln!(content, "  <p class=\"empty\">{}</p>", escape(strings.no_sessions));
+    } else {
+        let _ = writeln!(
+            content,
+            "  <table>\n    <tr><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>",
+            escape(strings.session),
+            escape(strings.source),
+            escape(strings.started),
+            escape(strings.length),
+            escape(strings.chunks),
+        );
+        for session in &sessions {
+            let minutes = (session.ended - session.started).num_minutes();
+            let _ = writeln!(
+                content,
+                "    <tr><td><a href=\"/sessions/{id}\">{name}</a></td><td>{source}</td><td class=\"time\">{started}</td><td>{length}</td><td>{chunks}</td></tr>",
+                id = escape(&session.id),
+                name = escape(session.title.as_deref().unwrap_or(&session.id)),
+                source = escape(&session.audio_source),
+                started = local(session.started, "%Y-%m-%d %H:%M"),
+                length = escape(&strings.minutes.replace("{minutes}", &minutes.to_string())),
+                chunks = session.chunks,
+            );
+        }
+        content.push_str("  </table>\n");
+    }
+    Ok(page(language, strings, strings.sessions, strings.sessions, "", &content))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sessions/{id}
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct SessionQuery {
+    // Show the session's translation into this language
+    lang: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "log",
+    path = "/sessions/{id}",
+    params(("id" = String, Path, description = "Session id"), SessionQuery, workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "The session's transcript", content_type = "text/html"),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "Nothing logged for that session in the workspace (code unknown_session), no such translation (code unknown_translation) or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 500, description = "The log couldn't be read (code log_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/sessions/{id}")]
+async fn show_session(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<SessionQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let language = i18n::language(&app_data, &req).await;
+    let strings = i18n::strings(language);
+    let records: Vec<Value> = read_log(&req, &app_data).await?.into_iter().filter(|r| r["session_id"] == id.as_str()).collect();
+    let Some(first) = records.first() else {
+        return Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")));
+    };
+    let translation = match query.lang.as_deref() {
+        Some(lang) => Some(
+            translate::load(&id, lang)
+                .await
+                .map_err(|e| {
+                    ApiError::internal("translation_unreadable", "Failed to read the translation").with_detail(format!("{e:#}"))
+                })?
+                .ok_or_else(|| {
+                    ApiError::not_found(
+                        "unknown_translation",
+                        format!("Session {id} hasn't been translated to {lang}; POST /sessions/{id}/translate?lang={lang} translates it"),
+                    )
+                })?,
+        ),
+        None => None,
+    };
+    let title = records.iter().find_map(|r| r["session_title"].as_str()).unwrap_or(id.as_str()).to_string();
+    let audio_source = first["audio_source"].as_str().unwrap_or_default();
+
+    let mut entries: Vec<Entry> = Vec::new();
+    for record in &records {
+        let Some(at) = timestamp(record) else { continue };
+        let text = record["text"].as_str().unwrap_or_default().trim().to_string();
+        if record["source"] == "Microphone" {
+            let speakers = record["speakers"]
+                .as_array()
+                .map(|s| s.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+                .unwrap_or_default();
+            let translated = translation.as_ref().and_then(|t| t.text(record["chunk_id"].as_str().unwrap_or_default()));
+            let entry = match translated {
+                Some(translated) => Entry { at, speakers, transcript: translated.to_string(), original: Some(text), response: None },
+                None => Entry { at, speakers, transcript: text, original: None, response: None },
+            };
+            entries.push(entry);
+        } else if !pipeline::is_listening(&text) {
+            // Responses follow their transcript
+            if let Some(last) = entries.last_mut().filter(|e| e.response.is_none()) {
+                last.response = Some(text);
+            }
+        }
+    }
+
+    let mut content = String::new();
+    let summary = strings
+        .session_summary
+        .replace("{source}", audio_source)
+        .replace("{started}", &entries.first().map(|e| local(e.at, "%Y-%m-%d %H:%M")).unwrap_or_default())
+        .replace("{chunks}", &entries.len().to_string());
+    let _ = writeln!(content, "  <p class=\"meta\">{}</p>", escape(&summary));
+    let langs = translate::available(&id).await;
+    if !langs.is_empty() {
+        let current = query.lang.as_deref();
+        let link = |label: &str, href: String, here: bool| {
+            if here { escape(label) } else { format!("<a href=\"{}\">{}</a>", escape(&href), escape(label)) }
+        };
+        let mut links = vec![link(strings.original, format!("/sessions/{id}"), current.is_none())];
+        for lang in &langs {
+            links.push(link(lang, format!("/sessions/{id}?lang={lang}"), current == Some(lang.as_str())));
+        }
+        let _ = writeln!(content, "  <p class=\"meta\">{}: {}</p>", escape(strings.translations), links.join(" | "));
+    }
+    content.push_str("  <table>\n");
+    for entry in &entries {
+        let speakers = if entry.speakers.is_empty() {
+            String::new()
+        } else {
+            format!("<span class=\"speakers\">{}:</span> ", escape(&entry.speakers.join(", ")))
+        };
+        let original = match &entry.original {
+            Some(original) => format!("<div class=\"original\">{}</div>", escape(original)),
+            None => String::new(),
+        };
+        let response = match &entry.response {
+            Some(response) => format!("<div class=\"response\">&gt; {}</div>", escape(response)),
+            None => String::new(),
+        };
+        let _ = writeln!(
+            content,
+            "    <tr><td class=\"time\">{time}</td><td>{speakers}{transcript}{original}{response}</td></tr>",
+            time = local(entry.at, "%H:%M:%S"),
+            transcript = escape(&entry.transcript),
+        );
+    }
+    content.push_str("  </table>\n");
+    Ok(page(language, strings, &title, &title, "", &content))
+}
+
+// The log of the workspace asked for (see workspaces.rs)
+async fn read_log(req: &HttpRequest, app_data: &AppState) -> Result<Vec<Value>, ApiError> {
+    digest::read_log(&workspaces::requested(req, app_data).await?)
+        .await
+        .map_err(|e| ApiError::internal("log_unreadable", "Failed to read the conversation log").with_detail(format!("{e:#}")))
+}
+
+fn timestamp(record: &Value) -> Option<DateTime<Utc>> {
+    let at = record["timestamp"].as_str().and_then(|t| DateTime::parse_from_rfc3339(t).ok())?;
+    Some(at.with_timezone(&Utc))
+}
+
+fn local(at: DateTime<Utc>, format: &str) -> String {
+    timezone::local(at).format(format).to_string()
+}
+
+// The layout with `content` (already HTML) in it, and `extra_head`
+// (also HTML) at the end of its <head>; only the part before the
+// content is filled in, so nothing from the log is read as a
+// placeholder. Also used by dashboard.rs.
+pub(crate) fn page(
+    language: &str,
+    strings: &Strings,
+    title: &str,
+    heading: &str,
+    extra_head: &str,
+    content: &str,
+) -> HttpResponse {
+    let (head, tail) = LAYOUT.split_once("{{content}}").expect("the layout has a {{content}} placeholder");
+    let head = head
+        .replace("{{head}}", extra_head)
+        .replace("{{lang}}", language)
+        .replace("{{live}}", &escape(strings.live))
+        .replace("{{sessions}}", &escape(strings.sessions))
+        .replace("{{dashboard}}", &escape(strings.dashboard))
+        .replace("{{title}}", &escape(title))
+        .replace("{{heading}}", &escape(heading));
+    let html = format!("{head}{content}{tail}");
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("Content-Language", language))
+        .body(html)
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_sessions).service(show_session);
+}
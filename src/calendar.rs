@@ -0,0 +1,437 @@
+/////////////////////////////////////////////////////////////
+// src/calendar.rs
+//
+// Meeting capture ([calendar] enabled): the calendar at
+// calendar.url is fetched every poll_secs, and when one of its
+// events starts, calendar.source starts recording a session
+// titled after the event, with its attendees (see
+// SessionDetails). With stop_at_end the session stops when the
+// event ends, unless someone already stopped or restarted it.
+//
+// calendar.url is either
+//   - a CalDAV calendar collection (Nextcloud, Fastmail, iCloud,
+//     Radicale, ...), asked with a calendar-query REPORT for the
+//     events around now, expanded by the server so recurring
+//     meetings arrive as separate instances in UTC, or
+//   - an iCalendar feed, when its path ends in ".ics" (Google
+//     Calendar's "secret address in iCal format"). Feeds aren't
+//     expanded: a recurring event only counts at its first
+//     occurrence, and times with a TZID are taken as the
+//     server's local time.
+// All-day and cancelled events, and attendees who declined, are
+// skipped. A meeting that's already under way when the server
+// starts is joined late; one the source is busy for is logged
+// and skipped.
+//
+// Settings are restart-only. While the calendar can't be
+// fetched the last events fetched still count.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::config::CalendarSettings;
+use crate::sessions::{self, SessionDetails};
+use crate::{audit, privacy, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// How often events are checked against the clock
+const TICK: Duration = Duration::from_secs(5);
+// Events fetched from CalDAV: up to a day either side of now
+const WINDOW_DAYS: i64 = 1;
+
+/////////////////////////////////////////////////////////////
+// Meeting
+//
+// One event instance.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+struct Meeting {
+    uid: String,
+    title: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    // "Name <address>", or whichever of the two is known
+    attendees: Vec<String>,
+}
+
+// A session this started, to stop at the meeting's end
+struct Started {
+    session_id: String,
+    end: DateTime<Utc>,
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: CalendarSettings) {
+    tracing::info!(source = %settings.source, poll_secs = settings.poll_secs, "recording calendar meetings");
+    let span = tracing::info_span!(parent: None, "calendar", source = %settings.source);
+    app_data.tasks.spawn("calendar", run(app_data.clone(), settings).instrument(span));
+}
+
+async fn run(app_data: web::Data<AppState>, settings: CalendarSettings) {
+    let shutdown = app_data.tasks.token();
+    let client = privacy::client();
+    let poll = Duration::from_secs(settings.poll_secs);
+    let mut meetings = Vec::new();
+    let mut fetched_at: Option<Instant> = None;
+    let mut failing = false;
+    // (uid, start) of every meeting already handled
+    let mut handled: HashSet<(String, DateTime<Utc>)> = HashSet::new();
+    let mut started: Vec<Started> = Vec::new();
+    loop {
+        if fetched_at.is_none_or(|at| at.elapsed() >= poll) {
+            match fetch(&client, &settings).await {
+                Ok(fetched) => {
+                    if failing {
+                        tracing::info!("fetched the calendar again");
+                    }
+                    tracing::debug!(meetings = fetched.len(), "fetched the calendar");
+                    meetings = fetched;
+                    failing = false;
+                }
+                Err(e) => {
+                    // Once per outage
+                    if !failing {
+                        tracing::warn!(error = %format!("{e:#}"), "couldn't fetch the calendar, keeping the events from before");
+                    }
+                    failing = true;
+                }
+            }
+            fetched_at = Some(Instant::now());
+        }
+
+        let now = Utc::now();
+        if settings.stop_at_end {
+            for ended in started.iter().filter(|s| s.end <= now) {
+                stop(&app_data, &settings.source, &ended.session_id).await;
+            }
+        }
+        started.retain(|s| s.end > now);
+        for meeting in meetings.iter().filter(|m| m.start <= now && now < m.end) {
+            if handled.insert((meeting.uid.clone(), meeting.start)) {
+                if let Some(session_id) = start(&app_data, &settings.source, meeting).await {
+                    started.push(Started { session_id, end: meeting.end });
+                }
+            }
+        }
+        handled.retain(|(_, start)| *start > now - chrono::Duration::days(WINDOW_DAYS + 1));
+
+        tokio::select! {
+            _ = tokio::time::sleep(TICK) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+// The new session's ID, if it started
+async fn start(app_data: &web::Data<AppState>, name: &str, meeting: &Meeting) -> Option<String> {
+    let Some(source) = app_data.sources.get(app_data, name).await else {
+        tracing::warn!(title = %meeting.title, "there's no audio source named {name} to record the meeting");
+        return None;
+    };
+    let details = SessionDetails {
+        title: Some(meeting.title.clone()),
+        attendees: meeting.attendees.clone(),
+        ..SessionDetails::default()
+    };
+    let started = sessions::start_session(app_data, source.clone(), details).await;
+    audit::record(app_data, "calendar", "calendar", &format!("start {}", source.name), started.is_ok()).await;
+    match started {
+        Ok(()) => {
+            tracing::info!(title = %meeting.title, attendees = meeting.attendees.len(), "meeting started, recording");
+            source.session_id.borrow().clone()
+        }
+        Err(e) => {
+            tracing::info!(title = %meeting.title, error = %e, "meeting started, but the source can't record it");
+            None
+        }
+    }
+}
+
+// Only if `session_id` is still the one recording
+async fn stop(app_data: &web::Data<AppState>, name: &str, session_id: &str) {
+    let Some(source) = app_data.sources.get(app_data, name).await else { return };
+    if source.session_id.borrow().as_deref() != Some(session_id) {
+        return;
+    }
+    tracing::info!(session_id, "meeting ended, stopping");
+    sessions::stop_source(app_data, &source).await;
+    audit::record(app_data, "calendar", "calendar", &format!("stop {name}"), true).await;
+}
+
+/////////////////////////////////////////////////////////////
+// fetch
+//
+// The meetings around now, from CalDAV or an iCalendar feed.
+/////////////////////////////////////////////////////////////
+async fn fetch(client: &reqwest::Client, settings: &CalendarSettings) -> Result<Vec<Meeting>> {
+    let feed = reqwest::Url::parse(&settings.url).is_ok_and(|url| url.path().ends_with(".ics"));
+    let mut request = if feed {
+        client.get(&settings.url)
+    } else {
+        let now = Utc::now();
+        let at = |t: DateTime<Utc>| t.format("%Y%m%dT%H%M%SZ").to_string();
+        let (start, end) = (at(now - chrono::Duration::days(WINDOW_DAYS)), at(now + chrono::Duration::days(WINDOW_DAYS)));
+        let query = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><C:calendar-data><C:expand start="{start}" end="{end}"/></C:calendar-data></D:prop>
+  <C:filter><C:comp-filter name="VCALENDAR"><C:comp-filter name="VEVENT">
+    <C:time-range start="{start}" end="{end}"/>
+  </C:comp-filter></C:comp-filter></C:filter>
+</C:calendar-query>"#
+        );
+        client
+            .request(reqwest::Method::from_bytes(b"REPORT")?, &settings.url)
+            .header("Depth", "1")
+            .header(reqwest::header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(query)
+    };
+    if !settings.username.is_empty() {
+        request = request.basic_auth(&settings.username, Some(&settings.password));
+    }
+    let resp = request.timeout(REQUEST_TIMEOUT).send().await.context("calendar unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("calendar server replied {status}");
+    }
+    let body = resp.text().await.context("Failed to read the calendar")?;
+
+    let calendars = if feed { vec![body] } else { calendar_data(&body) };
+    Ok(calendars.iter().flat_map(|ics| parse_meetings(ics)).collect())
+}
+
+// The iCalendar text in each calendar-data element of a CalDAV
+// multistatus response, whatever namespace prefix it uses
+fn calendar_data(xml: &str) -> Vec<String> {
+    const NAME: &str = "calendar-data";
+    let mut calendars = Vec::new();
+    let mut rest = xml;
+    while let Some(i) = rest.find(NAME) {
+        let (before, after) = (&rest[..i], &rest[i + NAME.len()..]);
+        rest = after;
+        // An opening tag: "<" and maybe a prefix right before the name
+        let Some(open) = before.rfind('<') else { continue };
+        let prefix = &before[open + 1..];
+        if !prefix.chars().all(|c| c.is_alphanumeric() || c == ':' || c == '_' || c == '-')
+            || !after.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace())
+        {
+            continue;
+        }
+        let Some(close) = after.find('>') else { break };
+        if after[..close].ends_with('/') {
+            continue;
+        }
+        let content = &after[close + 1..];
+        let text = match content.strip_prefix("<![CDATA[") {
+            Some(cdata) => cdata[..cdata.find("]]>").unwrap_or(cdata.len())].to_string(),
+            None => xml_unescape(&content[..content.find('<').unwrap_or(content.len())]),
+        };
+        calendars.push(text);
+    }
+    calendars
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&#xD;", "\r")
+        .replace("&amp;", "&")
+}
+
+/////////////////////////////////////////////////////////////
+// parse_meetings
+//
+// The VEVENTs of an iCalendar object (RFC 5545) that can be
+// recorded.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+struct Draft {
+    uid: String,
+    title: String,
+    start: Option<String>,
+    end: Option<String>,
+    duration: Option<String>,
+    attendees: Vec<String>,
+    cancelled: bool,
+}
+
+fn parse_meetings(ics: &str) -> Vec<Meeting> {
+    let unfolded = ics.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+    let mut meetings = Vec::new();
+    let mut draft: Option<Draft> = None;
+    // Components inside the VEVENT (VALARM), whose properties
+    // aren't the event's
+    let mut nested = 0;
+    for line in unfolded.lines() {
+        let Some(event) = draft.as_mut() else {
+            if line == "BEGIN:VEVENT" {
+                draft = Some(Draft::default());
+            }
+            continue;
+        };
+        if line.starts_with("BEGIN:") {
+            nested += 1;
+            continue;
+        }
+        if line == "END:VEVENT" && nested == 0 {
+            meetings.extend(draft.take().and_then(Draft::finish));
+            continue;
+        }
+        if line.starts_with("END:") {
+            nested -= 1;
+            continue;
+        }
+        if nested > 0 {
+            continue;
+        }
+        let Some(Property { name, params, value }) = property(line) else { continue };
+        let param = |wanted: &str| {
+            params.iter().find(|(k, _)| k.eq_ignore_ascii_case(wanted)).map(|(_, v)| v.as_str())
+        };
+        match name.to_ascii_uppercase().as_str() {
+            "UID" => event.uid = value.to_string(),
+            "SUMMARY" => event.title = unescape_text(value),
+            "DTSTART" => event.start = Some(value.to_string()),
+            "DTEND" => event.end = Some(value.to_string()),
+            "DURATION" => event.duration = Some(value.to_string()),
+            "STATUS" => event.cancelled = value.eq_ignore_ascii_case("CANCELLED"),
+            "ATTENDEE" if !param("PARTSTAT").is_some_and(|p| p.eq_ignore_ascii_case("DECLINED")) => {
+                let address = value.get(..7).filter(|s| s.eq_ignore_ascii_case("mailto:")).map_or(value, |_| &value[7..]);
+                let attendee = match (param("CN"), address) {
+                    (Some(name), "") => name.to_string(),
+                    (Some(name), address) => format!("{name} <{address}>"),
+                    (None, address) => address.to_string(),
+                };
+                if !attendee.is_empty() {
+                    event.attendees.push(attendee);
+                }
+            }
+            _ => {}
+        }
+    }
+    meetings
+}
+
+impl Draft {
+    fn finish(self) -> Option<Meeting> {
+        if self.cancelled {
+            return None;
+        }
+        // All-day events (VALUE=DATE) aren't meetings
+        let start = parse_time(self.start.as_deref()?)?;
+        let end = match (&self.end, &self.duration) {
+            (Some(end), _) => parse_time(end)?,
+            (None, Some(duration)) => start + parse_duration(duration)?,
+            (None, None) => return None,
+        };
+        if end <= start {
+            return None;
+        }
+        let title = if self.title.trim().is_empty() { "Untitled meeting".to_string() } else { self.title };
+        Some(Meeting { uid: self.uid, title, start, end, attendees: self.attendees })
+    }
+}
+
+// "NAME;PARAM=x;PARAM="y:z":value" split at the first ':' and
+// ';' outside quotes; parameter values lose their quotes
+struct Property<'a> {
+    name: &'a str,
+    params: Vec<(String, String)>,
+    value: &'a str,
+}
+
+fn property(line: &str) -> Option<Property<'_>> {
+    let mut quoted = false;
+    let mut splits = Vec::new();
+    let mut colon = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => splits.push(i),
+            ':' if !quoted => {
+                colon = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let colon = colon?;
+    let head = &line[..colon];
+    let mut parts = Vec::new();
+    let mut from = 0;
+    for split in splits {
+        parts.push(&head[from..split]);
+        from = split + 1;
+    }
+    parts.push(&head[from..]);
+    let params = parts[1..]
+        .iter()
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.trim_matches('"').to_string()))
+        .collect();
+    Some(Property { name: parts[0], params, value: &line[colon + 1..] })
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => {}
+        }
+    }
+    text
+}
+
+// "20261016T140000Z" is UTC; without the Z (floating, or with a
+// TZID) it's taken as local time. A date alone is None
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(|t| t.and_utc());
+    }
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    local.and_local_timezone(Local).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+// "PT1H30M", "P1D", "P2W", ...
+fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    let value = value.strip_prefix('+').unwrap_or(value).strip_prefix('P')?;
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    let mut time = false;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => time = true,
+            unit => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                total += match (unit, time) {
+                    ('W', false) => chrono::Duration::weeks(n),
+                    ('D', false) => chrono::Duration::days(n),
+                    ('H', true) => chrono::Duration::hours(n),
+                    ('M', true) => chrono::Duration::minutes(n),
+                    ('S', true) => chrono::Duration::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    Some(total)
+}
@@ -0,0 +1,197 @@
+/////////////////////////////////////////////////////////////
+// src/captions.rs
+//
+// Live captions, a word at a time, for a kiosk that shows them
+// karaoke-style to people who can't follow the audio:
+//   GET /captions?source=default  - SSE of "caption" events
+//   {"audio_source": "default", "session_id": ..., "chunk_id": ...,
+//    "index": 3, "word": "fixing.", "start": 1.2, "end": 1.6,
+//    "last": true}
+// With captions.enabled, Whisper is asked for word timestamps
+// and, as soon as a recording source's chunk is transcribed,
+// its words are sent at the pace they were spoken: each one
+// `start` seconds (into the chunk) after the first. So captions
+// run a chunk behind the room, but word by word rather than in
+// blocks. When a provider sends no timestamps, or the transcript
+// changed after Whisper (redaction, a spoken command cut out),
+// the words are spread evenly over the chunk instead.
+//
+// The channel is separate from the live log: captions aren't
+// logged, replayed, or sent to webhooks, and a client that
+// falls behind just misses words. Nothing is sent while nobody
+// listens. Backlog and /record_once chunks have no captions.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpResponse};
+use futures_util::{future, stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::ApiError;
+use crate::events::SSE_KEEPALIVE_SECS;
+use crate::sessions::SourceSession;
+use crate::AppState;
+
+// Words in flight to slow subscribers
+const CAPACITY: usize = 1024;
+
+// A word and when it was said, in seconds into the chunk, as
+// Whisper's verbose_json has them
+#[derive(Clone, Debug, Deserialize)]
+pub struct Word {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct Caption {
+    audio_source: String,
+    session_id: Option<String>,
+    chunk_id: String,
+    // Its place in the chunk's transcript, from 0
+    index: usize,
+    word: String,
+    start: f64,
+    end: f64,
+    // The chunk's last word
+    last: bool,
+}
+
+pub struct Captions {
+    sender: broadcast::Sender<Caption>,
+}
+
+impl Default for Captions {
+    fn default() -> Self {
+        Captions { sender: broadcast::channel(CAPACITY).0 }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// timed
+//
+// The transcript's words with their times: Whisper's when it
+// has one for each, otherwise evenly over `duration` seconds.
+/////////////////////////////////////////////////////////////
+fn timed(transcript: &str, words: &[Word], duration: f64) -> Vec<Word> {
+    let spoken: Vec<&str> = transcript.split_whitespace().collect();
+    if spoken.len() == words.len() {
+        // The transcript's spelling, which has the punctuation
+        return spoken
+            .iter()
+            .zip(words)
+            .map(|(text, word)| Word { word: text.to_string(), start: word.start, end: word.end })
+            .collect();
+    }
+    let each = duration / spoken.len().max(1) as f64;
+    spoken
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Word { word: text.to_string(), start: each * i as f64, end: each * (i + 1) as f64 })
+        .collect()
+}
+
+/////////////////////////////////////////////////////////////
+// publish
+//
+// Sends a chunk's words to caption clients in the background,
+// each as far after the first as it was spoken.
+/////////////////////////////////////////////////////////////
+pub async fn publish(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    chunk_id: &str,
+    transcript: &str,
+    words: &[Word],
+    // The chunk's length, in seconds
+    duration: f64,
+) {
+    if !app_data.config.read().await.captions.enabled || app_data.captions.sender.receiver_count() == 0 {
+        return;
+    }
+    let words = timed(transcript, words, duration);
+    let count = words.len();
+    let sender = app_data.captions.sender.clone();
+    let (audio_source, chunk_id) = (source.name.clone(), chunk_id.to_string());
+    let session_id = source.session_id.borrow().clone();
+    app_data.tasks.spawn("captions", async move {
+        let begun = Instant::now();
+        for (index, word) in words.into_iter().enumerate() {
+            tokio::time::sleep_until(begun + Duration::from_secs_f64(word.start.max(0.0))).await;
+            let caption = Caption {
+                audio_source: audio_source.clone(),
+                session_id: session_id.clone(),
+                chunk_id: chunk_id.clone(),
+                index,
+                word: word.word,
+                start: word.start,
+                end: word.end,
+                last: index + 1 == count,
+            };
+            // Everyone has gone
+            if sender.send(caption).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/////////////////////////////////////////////////////////////
+// GET /captions
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct CaptionQuery {
+    // Only this audio source's words; default every source's
+    source: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(CaptionQuery),
+    responses(
+        (status = 200, description = "SSE stream of \"caption\" events, one per word", content_type = "text/event-stream"),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+        (status = 409, description = "Captions are off (code captions_disabled)", body = ErrorBody),
+    ),
+)]
+#[get("/captions")]
+async fn captions(app_data: web::Data<AppState>, query: web::Query<CaptionQuery>) -> Result<HttpResponse, ApiError> {
+    if !app_data.config.read().await.captions.enabled {
+        return Err(ApiError::conflict("captions_disabled", "Captions are off (captions.enabled)"));
+    }
+    let only = query.into_inner().source;
+    if let Some(name) = &only {
+        if app_data.sources.get(&app_data, name).await.is_none() {
+            return Err(crate::sessions::unknown_source(name));
+        }
+    }
+
+    let words = BroadcastStream::new(app_data.captions.sender.subscribe())
+        // A client that fell behind skips what it missed
+        .filter_map(|res| future::ready(res.ok()))
+        .filter(move |caption| future::ready(only.as_ref().is_none_or(|name| *name == caption.audio_source)))
+        .map(|caption| {
+            let data = serde_json::to_string(&caption).unwrap_or_default();
+            Ok::<Bytes, std::io::Error>(Bytes::from(format!("event: caption\ndata: {data}\n\n")))
+        });
+    let mut keepalive = tokio::time::interval(Duration::from_secs(SSE_KEEPALIVE_SECS));
+    keepalive.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let keepalive_stream = IntervalStream::new(keepalive)
+        .skip(1) // the first tick fires immediately
+        .map(|_| Ok::<Bytes, std::io::Error>(Bytes::from_static(b": keepalive\n\n")));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream::select(words, keepalive_stream)))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(captions);
+}
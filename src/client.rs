@@ -0,0 +1,247 @@
+/////////////////////////////////////////////////////////////
+// src/client.rs
+//
+// Client subcommands: talk to a running server over HTTP so
+// day-to-day checks don't need curl.
+//   silentnight status [--json]
+//   silentnight start [source] / stop [source]
+//   silentnight tail [--audio-source A] [--source S] [--session current]
+//   silentnight export <session> [--format json|text]
+//
+// The server is --url (SILENTNIGHT_URL), or else this config's
+// port on localhost (https if TLS is configured). The admin
+// token, when set, is sent as a bearer token so these work with
+// web UI login on.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use reqwest::{Method, RequestBuilder, Response};
+use serde_json::Value;
+
+use crate::config::{Cli, Command, Config};
+use crate::listen;
+
+/////////////////////////////////////////////////////////////
+// run
+/////////////////////////////////////////////////////////////
+pub async fn run(cli: &Cli, config: &Config, command: &Command) -> Result<()> {
+    let client = Client::new(cli, config)?;
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Status { json } => status(&client, *json).await,
+        Command::Start { source } => {
+            let resp = client.send(Method::POST, &format!("/sources/{source}/start")).await?;
+            println!("✅ {}", resp.text().await?);
+            Ok(())
+        }
+        Command::Stop { source } => {
+            let resp = client.send(Method::POST, &format!("/sources/{source}/stop")).await?;
+            println!("✅ {}", resp.text().await?);
+            Ok(())
+        }
+        Command::Tail {
+            audio_source,
+            source,
+            session,
+            json,
+        } => tail(&client, audio_source.as_deref(), source.as_deref(), session.as_deref(), *json).await,
+        Command::Export { session, format } => export(&client, session, format).await,
+    }
+}
+
+struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    admin_token: String,
+}
+
+impl Client {
+    fn new(cli: &Cli, config: &Config) -> Result<Client> {
+        let tls = config.tls.cert_path.is_some() || config.tls.self_signed;
+        let base_url = match &cli.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => {
+                anyhow::ensure!(
+                    config.server.listen_tcp,
+                    "The server only listens on a Unix socket; pass --url (e.g. through your proxy)"
+                );
+                format!("{}://{}:{}", if tls { "https" } else { "http" }, local_host(config), config.server.port)
+            }
+        };
+        // Our own self-signed certificate won't verify
+        let insecure = cli.insecure || (cli.url.is_none() && config.tls.self_signed);
+
+        let http = reqwest::Client::builder()
+            .danger_accept_invalid_certs(insecure)
+            .build()
+            .context("Failed to create the HTTP client")?;
+        Ok(Client {
+            http,
+            base_url,
+            admin_token: config.admin.token.clone(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        if self.admin_token.is_empty() {
+            request
+        } else {
+            request.bearer_auth(&self.admin_token)
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str) -> Result<Response> {
+        check(self.request(method, path)).await
+    }
+}
+
+// Where to reach a server bound to bind_addr from this machine
+fn local_host(config: &Config) -> String {
+    match listen::parse_bind_addr(&config.server.bind_addr) {
+        Some(ip) if ip.is_unspecified() => "localhost".to_string(),
+        Some(ip) if ip.is_ipv6() => format!("[{ip}]"),
+        Some(ip) => ip.to_string(),
+        None => "localhost".to_string(),
+    }
+}
+
+// Sends the request and turns non-2xx replies into an error with
+// the ErrorBody code and message
+async fn check(request: RequestBuilder) -> Result<Response> {
+    let resp = request.send().await.context("Couldn't reach the server (is it running?)")?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body: Value = resp.json().await.unwrap_or_default();
+    match (body["code"].as_str(), body["message"].as_str()) {
+        (Some(code), Some(message)) => anyhow::bail!("{status}: {message} ({code})"),
+        _ => anyhow::bail!("Server replied {status}"),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// status
+/////////////////////////////////////////////////////////////
+async fn status(client: &Client, json: bool) -> Result<()> {
+    let status: Value = client.send(Method::GET, "/status").await?.json().await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    let uptime = status["uptime_secs"].as_i64().unwrap_or_default();
+    println!(
+        "Up {}h{:02}m, {} chunks processed",
+        uptime / 3600,
+        uptime % 3600 / 60,
+        status["chunks_processed"]
+    );
+    println!(
+        "Backends: mic {}, stt {}, llm {}",
+        text(&status["backends"]["mic"]),
+        text(&status["backends"]["stt"]),
+        text(&status["backends"]["llm"])
+    );
+    for source in status["sources"].as_array().into_iter().flatten() {
+        let name = source["state"]["name"].as_str().unwrap_or("idle");
+        let state = match source["session_id"].as_str() {
+            Some(session) => format!("{name} (session {session})"),
+            None => name.to_string(),
+        };
+        println!("  {:<12} {state}, {} chunks", text(&source["name"]), source["chunks_processed"]);
+        if let Some(error) = source["last_error"].as_str() {
+            println!("  {:<12} last error: {error}", "");
+        }
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// tail
+//
+// Reads the /live_log SSE stream until interrupted.
+/////////////////////////////////////////////////////////////
+async fn tail(
+    client: &Client,
+    audio_source: Option<&str>,
+    source: Option<&str>,
+    session: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let path = match audio_source {
+        Some(name) => format!("/sources/{name}/live_log"),
+        None => "/live_log".to_string(),
+    };
+    let query: Vec<(&str, &str)> = [("source", source), ("session", session)]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|v| (key, v)))
+        .collect();
+    let mut resp = check(client.request(Method::GET, &path).query(&query)).await?;
+
+    let mut buffer = String::new();
+    while let Some(chunk) = resp.chunk().await.context("Live log connection dropped")? {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            // Skip ids, keepalive comments and blank separators
+            let Some(data) = line.trim_end().strip_prefix("data: ") else {
+                continue;
+            };
+            if json {
+                println!("{data}");
+            } else if let Ok(record) = serde_json::from_str::<Value>(data) {
+                println!("{}", format_record(&record));
+            }
+        }
+    }
+    anyhow::bail!("The server closed the live log")
+}
+
+/////////////////////////////////////////////////////////////
+// export
+/////////////////////////////////////////////////////////////
+async fn export(client: &Client, session: &str, format: &str) -> Result<()> {
+    anyhow::ensure!(
+        matches!(format, "json" | "text"),
+        "--format must be \"json\" or \"text\", got {format:?}"
+    );
+    let log = client.send(Method::GET, "/conversation_log").await?.text().await?;
+
+    let mut found = 0;
+    for line in log.lines() {
+        let Ok(record) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if record["session_id"].as_str() != Some(session) {
+            continue;
+        }
+        found += 1;
+        match format {
+            "json" => println!("{line}"),
+            _ => println!("{}", format_record(&record)),
+        }
+    }
+    anyhow::ensure!(found > 0, "No records for session {session:?}");
+    Ok(())
+}
+
+// "[10:42:07] default | Microphone: text"
+fn format_record(record: &Value) -> String {
+    let time = record["timestamp"]
+        .as_str()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| crate::timezone::local(t.with_timezone(&chrono::Utc)).format("%H:%M:%S").to_string())
+        .unwrap_or_default();
+    format!(
+        "[{time}] {} | {}: {}",
+        text(&record["audio_source"]),
+        text(&record["source"]),
+        text(&record["text"])
+    )
+}
+
+fn text(value: &Value) -> &str {
+    value.as_str().unwrap_or("-")
+}
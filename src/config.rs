@@ -0,0 +1,2880 @@
+/////////////////////////////////////////////////////////////
+// src/config.rs
+//
+// Layered configuration, lowest to highest priority:
+//   1) built-in defaults (below)
+//   2) TOML file: --config <path>, $SILENTNIGHT_CONFIG, or
+//      ./silentnight.toml if it exists
+//   3) environment variables (PORT, MIC_BACKEND,
+//      OPENAI_API_KEY, ... - the names we've always used)
+//   4) command-line flags (see `--help`)
+//
+// The result is validated once at startup so typos fail fast
+// with a readable message instead of at the first chunk.
+// See silentnight.example.toml for every setting.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CONFIG_FILE: &str = "silentnight.toml";
+
+// Name of the audio source configured directly under [audio]
+pub const DEFAULT_SOURCE: &str = "default";
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are listening in on a conversation. You will display your response on a monitor mounted on the wall, so the goal should be 50 words or less so they are not too small. If there is something said that you could provide some interesting information about, return a response. If there is nothing interesting to share, just return Listening...";
+
+/////////////////////////////////////////////////////////////
+// Cli
+//
+// Command-line flags. Anything left out falls back to env,
+// then the config file, then defaults.
+/////////////////////////////////////////////////////////////
+#[derive(Parser, Debug)]
+#[command(name = "silentnight", version, about = "Audio -> Whisper -> GPT conversation listener")]
+pub struct Cli {
+    /// Path to a TOML config file
+    #[arg(short, long, env = "SILENTNIGHT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Port to listen on
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Microphone backend: "linux" (arecord), "mac" (SoX rec) or "file" (WAV fixtures)
+    #[arg(long)]
+    pub mic_backend: Option<String>,
+
+    /// Length of each recorded chunk in seconds
+    #[arg(long)]
+    pub chunk_secs: Option<u32>,
+
+    /// Feed every source from WAV fixtures (a file or directory) or a .txt script, in real time
+    #[arg(long, value_name = "PATH")]
+    pub simulate: Option<PathBuf>,
+
+    /// Chat model used for responses
+    #[arg(long)]
+    pub chat_model: Option<String>,
+
+    /// Log level or tracing filter, e.g. "debug" or "info,my_project=trace"
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Print the effective config (secrets masked) and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Write a systemd unit file for this binary and exit ("-" prints it)
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = crate::systemd::DEFAULT_UNIT_PATH
+    )]
+    pub install_service: Option<PathBuf>,
+
+    /// Server the client commands talk to; default = this config's port on localhost
+    #[arg(long, global = true, env = "SILENTNIGHT_URL")]
+    pub url: Option<String>,
+
+    /// Skip TLS certificate checks in client commands (self-signed certs)
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/////////////////////////////////////////////////////////////
+// Command
+//
+// Subcommands. Without one, the server runs (same as `serve`);
+// the rest are HTTP clients for a running server (client.rs).
+/////////////////////////////////////////////////////////////
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Show recording state, counters and sources
+    Status {
+        /// Print the raw /status JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Start recording an audio source
+    Start {
+        /// Audio source name
+        #[arg(default_value = DEFAULT_SOURCE)]
+        source: String,
+    },
+    /// Stop recording an audio source after the current chunk
+    Stop {
+        /// Audio source name
+        #[arg(default_value = DEFAULT_SOURCE)]
+        source: String,
+    },
+    /// Follow the live log
+    Tail {
+        /// Only this audio source
+        #[arg(long)]
+        audio_source: Option<String>,
+        /// Only these record sources, comma-separated ("Microphone", "OPENAI RESPONSE")
+        #[arg(long)]
+        source: Option<String>,
+        /// "current" or a session ID
+        #[arg(long)]
+        session: Option<String>,
+        /// Print each record as a JSON line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print one session's records from the conversation log
+    Export {
+        /// Session ID
+        session: String,
+        /// "json" (one record per line) or "text"
+        #[arg(long, default_value = "json")]
+        format: String,
+    },
+}
+
+/////////////////////////////////////////////////////////////
+// Config sections
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub audio: AudioConfig,
+    pub openai: OpenAiConfig,
+    pub login: LoginSettings,
+    pub tls: TlsSettings,
+    pub rate_limit: RateLimitSettings,
+    pub logging: LoggingSettings,
+    pub discovery: DiscoverySettings,
+    pub admin: AdminSettings,
+    // Scoped API tokens, by name (see tokens.rs)
+    pub tokens: BTreeMap<String, TokenSettings>,
+    // Teams sharing the hub that keep separate logs, by name (see
+    // workspaces.rs)
+    pub workspaces: BTreeMap<String, WorkspaceSettings>,
+    pub webhooks: Vec<WebhookTarget>,
+    pub grpc: GrpcSettings,
+    pub backlog: BacklogSettings,
+    pub metrics: MetricsSettings,
+    pub mqtt: MqttSettings,
+    pub alerts: AlertSettings,
+    pub discord: DiscordSettings,
+    pub telegram: TelegramSettings,
+    pub email: EmailSettings,
+    pub calendar: CalendarSettings,
+    pub rules: RulesSettings,
+    pub export: ExportSettings,
+    pub lights: LightSettings,
+    pub remote: RemoteSettings,
+    pub homekit: HomeKitSettings,
+    pub voice: VoiceSettings,
+    pub reminders: ReminderSettings,
+    pub weather: WeatherSettings,
+    pub hub: HubSettings,
+    pub node: NodeSettings,
+    pub sync: SyncSettings,
+    pub sentry: SentrySettings,
+    pub audit: AuditSettings,
+    pub redaction: RedactionSettings,
+    pub privacy: PrivacySettings,
+    pub quiet_hours: QuietHoursSettings,
+    pub opt_out: OptOutSettings,
+    pub speakers: SpeakerSettings,
+    pub captions: CaptionSettings,
+    pub outputs: OutputSettings,
+    pub dedup: DedupSettings,
+    pub feedback: FeedbackSettings,
+    pub disk: DiskSettings,
+    // OpenAI-compatible endpoints besides [openai], by name, and
+    // which of them each kind of data may go to (see routing.rs)
+    pub providers: BTreeMap<String, ProviderSettings>,
+    pub routing: RoutingSettings,
+    pub pairing: PairingSettings,
+    // How each wall display or monitor looks, by name (see display.rs)
+    pub displays: BTreeMap<String, DisplayProfile>,
+    pub ui: UiSettings,
+    // Secret settings that were file:, keyring: or systemd:
+    // references, and what they said (see secrets.rs)
+    #[serde(skip)]
+    pub secret_references: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // IP to listen on; "::" = every IPv6 and IPv4 address
+    pub bind_addr: String,
+    pub port: u16,
+    // Set false to only listen on unix_socket
+    pub listen_tcp: bool,
+    // Also (or only) serve plain HTTP on this Unix socket, e.g. for
+    // an nginx on the same machine that terminates TLS
+    pub unix_socket: Option<String>,
+    // Largest request body accepted, in KiB
+    pub max_body_kb: usize,
+    // Records a live log subscriber may fall behind before it
+    // misses some
+    pub sse_capacity: usize,
+    // Serve the web UI from this directory instead of the copy
+    // built into the binary
+    pub static_dir: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub mic_backend: String,
+    // Capture device (arecord -D / SoX AUDIODEV); None = system default.
+    // For the "file" backend, the WAV file or directory to read,
+    // or a .txt script (see recorder.rs)
+    pub device: Option<String>,
+    // "file": play fixtures at the pace they were recorded, not
+    // as fast as they can be read
+    pub realtime: bool,
+    pub chunk_secs: u32,
+    // "fixed" (chunk_secs each) or "speech" (cut at pauses, see
+    // segmenter.rs)
+    pub chunking: String,
+    // For "speech": no chunk ends before min_chunk_secs, every
+    // chunk ends by max_chunk_secs, and a pause is pause_ms of quiet
+    pub min_chunk_secs: u32,
+    pub max_chunk_secs: u32,
+    pub pause_ms: u32,
+    // Also write every chunk there as a WAV file; None = memory only
+    pub save_dir: Option<String>,
+    // Longest run of adjacent short chunks sent to Whisper as one,
+    // in seconds; 0 = each chunk on its own
+    pub batch_max_secs: u32,
+    // A chunk with less speech than this is short
+    pub batch_speech_ms: u32,
+    // Seconds after a chunk is recorded that its transcript waits
+    // for GPT before it's sent ahead (see pipeline.rs); 0 = always
+    pub deadline_secs: u32,
+    // Extra inputs that can record alongside the default one
+    pub sources: Vec<AudioSource>,
+}
+
+/////////////////////////////////////////////////////////////
+// AudioSource
+//
+// One [[audio.sources]] entry. Unset fields fall back to the
+// [audio] values.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSource {
+    pub name: String,
+    pub mic_backend: Option<String>,
+    pub device: Option<String>,
+}
+
+// Where a source records from, after falling back to [audio]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MicInput {
+    pub backend: String,
+    pub device: Option<String>,
+    pub realtime: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    pub api_key: String,
+    // Where the Whisper and ChatCompletion endpoints live; change it
+    // for an OpenAI-compatible proxy or a mock server in tests
+    pub base_url: String,
+    pub stt_model: String,
+    // The language Whisper is told to expect (ISO-639-1, e.g. "de");
+    // empty = it detects it per chunk
+    pub language: String,
+    pub chat_model: String,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub system_prompt: String,
+    // Named alternatives to system_prompt ("meeting", "kids", ...),
+    // and the one in use; empty = system_prompt. Switched from
+    // the remote (see control.rs)
+    pub personas: BTreeMap<String, String>,
+    pub persona: String,
+    // How many user/assistant messages of history to send to GPT
+    pub history_messages: usize,
+    // Roughly how many tokens of history to keep; older turns are
+    // folded into a summary (see memory.rs). 0 = no limit
+    pub history_tokens: usize,
+    // Whisper/GPT requests in flight at once, across every source
+    pub max_concurrent: usize,
+    // Requests allowed to wait for a slot before new ones fail
+    pub max_queued: usize,
+    // Longest a single Whisper/GPT request may take, in seconds
+    pub timeout_secs: u64,
+    // Upload audio to Whisper while it's recorded (see pipeline.rs)
+    pub stream_audio: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginSettings {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub self_signed: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    // 0 disables rate limiting
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    // tracing filter directives ("info", "warn,my_project=debug")
+    pub level: String,
+    // "text", "json" or "journald"
+    pub format: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoverySettings {
+    // Advertise on mDNS and browse for other instances
+    pub enabled: bool,
+    // Name shown to other devices; empty = hostname
+    pub instance_name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    // Bearer token for /admin/*; empty = use the login session
+    pub token: String,
+}
+
+// A scoped API token (see tokens.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenSettings {
+    // Sent as "Authorization: Bearer <token>" or ?access_token=
+    pub token: String,
+    // "display", "control" or "admin"
+    pub scope: String,
+    // Confines it to one of [workspaces]; empty = not confined
+    pub workspace: String,
+}
+
+// A team's share of the hub (see workspaces.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceSettings {
+    // Audio source names whose chunks it logs
+    pub sources: Vec<String>,
+    // One of openai.personas for its sessions; empty = openai.persona
+    pub persona: String,
+    // Log entries older than this many days are deleted; 0 = kept
+    pub retention_days: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcSettings {
+    // Serve the gRPC API (needs the "grpc" cargo feature)
+    pub enabled: bool,
+    // Port on server.bind_addr
+    pub port: u16,
+}
+
+// Record-only mode (see backlog.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BacklogSettings {
+    // Keep recording without OpenAI, queueing chunks in dir
+    pub enabled: bool,
+    pub dir: String,
+    // Chunks that may wait; recording stops with an error beyond this
+    pub max_chunks: usize,
+    // Work through it once OpenAI is back, trying every retry_secs
+    pub auto_drain: bool,
+    pub retry_secs: u64,
+}
+
+// Telemetry (see telemetry.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    // Seconds between "telemetry" SSE events; 0 = none
+    pub telemetry_secs: u64,
+}
+
+// Home Assistant over MQTT (see mqtt.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttSettings {
+    // Connect to the broker (needs the "mqtt" cargo feature)
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    // Empty = connect without credentials
+    pub username: String,
+    pub password: String,
+    // Where Home Assistant looks for discovery configs
+    pub discovery_prefix: String,
+    // Our state and command topics go under this; also the MQTT
+    // client ID and the Home Assistant device ID
+    pub base_topic: String,
+}
+
+// Keyword alerts (see notify.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertSettings {
+    // Words or phrases that trigger an alert; empty = none
+    pub keywords: Vec<String>,
+    // Transcript characters shown either side of the match
+    pub context_chars: usize,
+    // Where people reach the web UI, e.g. "http://pi.local:8080",
+    // for the session link; empty = no link
+    pub public_url: String,
+    // Slack incoming webhook; empty = no Slack alerts
+    pub slack_webhook_url: String,
+}
+
+// Discord bot (see discord.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordSettings {
+    pub enabled: bool,
+    // From the Discord developer portal: the bot's token, and the
+    // application's ID and public key (hex)
+    pub bot_token: String,
+    pub application_id: String,
+    pub public_key: String,
+    // Register /silentnight in this server only (shows up at
+    // once); empty = in every server the bot is in
+    pub guild_id: String,
+    // Where session summaries and responses are posted
+    pub channel_id: String,
+    // Also post each GPT response as it comes in
+    pub post_responses: bool,
+    // Discord's REST API; only changed for tests
+    pub api_url: String,
+}
+
+// Telegram bot (see telegram.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramSettings {
+    pub enabled: bool,
+    // From @BotFather
+    pub bot_token: String,
+    // Chats that may send commands, and that get the digest and
+    // alerts
+    pub chat_ids: Vec<i64>,
+    // Local time ("HH:MM") of the daily digest; empty = none
+    pub digest_time: String,
+    // Also send keyword alerts (see notify.rs)
+    pub alerts: bool,
+    // Telegram's Bot API; only changed for tests
+    pub api_url: String,
+}
+
+// Digests and session summaries by email (see email.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailSettings {
+    // Send mail (needs the "email" cargo feature)
+    pub enabled: bool,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    // "starttls" (usually port 587), "tls" (465) or "none"
+    pub security: String,
+    // Empty = don't log in
+    pub username: String,
+    pub password: String,
+    // "SilentNight <silentnight@example.com>" or a bare address
+    pub from: String,
+    pub to: Vec<String>,
+    // "daily", "weekly" or "off"
+    pub digest: String,
+    // Local time ("HH:MM") the digest goes out
+    pub digest_time: String,
+    // Day of the weekly digest ("monday", "mon", ...)
+    pub digest_weekday: String,
+    // Also mail each session's summary when it stops
+    pub session_summaries: bool,
+}
+
+// Recording calendar meetings (see calendar.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalendarSettings {
+    pub enabled: bool,
+    // A CalDAV calendar collection, or an iCalendar feed whose
+    // path ends in ".ics" (e.g. Google Calendar's secret address)
+    pub url: String,
+    // Basic auth; empty = none
+    pub username: String,
+    pub password: String,
+    // The audio source that records meetings
+    pub source: String,
+    // How often the calendar is fetched
+    pub poll_secs: u64,
+    // Stop recording when the meeting ends
+    pub stop_at_end: bool,
+}
+
+// Automation rules (see rules.rs); the rules themselves are
+// managed through /rules and kept in `file`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesSettings {
+    pub file: String,
+    // Linux sysfs GPIO, for gpio actions
+    pub gpio_dir: String,
+    // Speaks the text it's given on stdin, for tts actions;
+    // split on whitespace, no shell
+    pub tts_command: String,
+}
+
+// Session notes for Obsidian/Notion (see export.rs); each
+// destination is on when it's configured
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportSettings {
+    // Export every session when it stops, not just on request
+    pub on_session_close: bool,
+    // A folder in an Obsidian vault; empty = off
+    pub obsidian_dir: String,
+    // Notion integration token and the database pages go in;
+    // empty = off
+    pub notion_token: String,
+    pub notion_database_id: String,
+    // The database's title property
+    pub notion_title_property: String,
+    pub notion_api_url: String,
+}
+
+// A light that pulses when GPT has something to say (see
+// lights.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightSettings {
+    pub enabled: bool,
+    // "hue" or "wled"
+    pub kind: String,
+    // The Hue bridge or WLED device, host[:port]
+    pub host: String,
+    // Hue: the bridge's app key (from POST /lights/pair) and the
+    // light's ID
+    pub username: String,
+    pub light: String,
+    // WLED: how long it's lit, and how brightly (0-255)
+    pub pulse_ms: u64,
+    pub brightness: u8,
+    // Where Hue bridges are looked up for /lights/discover
+    pub hue_discovery_url: String,
+}
+
+// IR remote / media keys (see remote.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteSettings {
+    pub enabled: bool,
+    // lircd's socket; empty = no LIRC
+    pub lirc_socket: String,
+    // A keyboard's /dev/input/event* device; empty = none
+    pub evdev_device: String,
+    // The audio source the keys control
+    pub source: String,
+    // Key name (KEY_PLAYPAUSE, ...) to command (see control.rs)
+    pub keys: BTreeMap<String, String>,
+}
+
+// Spoken commands (see voice.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceSettings {
+    pub enabled: bool,
+    // Said before each command
+    pub wake_phrase: String,
+    // Phrase to command (see control.rs); "{persona}" in both
+    // stands for each of openai.personas, "{minutes}" for 2 to 60
+    pub phrases: BTreeMap<String, String>,
+}
+
+// Timers and reminders GPT can set (see reminders.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReminderSettings {
+    // Offer GPT the reminder tools; ones already set still fire
+    // when this is off
+    pub enabled: bool,
+    // Pending reminders, so they survive a restart
+    pub file: String,
+    // Also announce them through rules.tts_command
+    pub speak: bool,
+}
+
+// Weather and local info GPT can look up (see weather.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeatherSettings {
+    // Offer GPT the weather tools
+    pub enabled: bool,
+    // Where the listeners are
+    pub latitude: f64,
+    pub longitude: f64,
+    // What to call it, e.g. "Portland, OR"; empty = unnamed
+    pub place: String,
+    // "metric" or "imperial"
+    pub units: String,
+    // Open-Meteo's forecast API
+    pub api_url: String,
+}
+
+// Taking audio from nodes (see hub.rs); each node is an audio
+// source with mic_backend = "node"
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HubSettings {
+    // Nodes send it as a bearer token; empty = no nodes
+    pub token: String,
+    // A node that hasn't checked in for this long is unhealthy
+    pub node_timeout_secs: u64,
+}
+
+// Running as a node of a hub (see node.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeSettings {
+    pub enabled: bool,
+    // e.g. http://hub.local:8080
+    pub hub_url: String,
+    // The hub's audio source for this node
+    pub name: String,
+    // Shown on the hub, e.g. "Kitchen"
+    pub label: String,
+    // The hub's hub.token
+    pub token: String,
+    // The local source whose mic is sent
+    pub source: String,
+    pub heartbeat_secs: u64,
+}
+
+// Sharing the log with other full instances (see sync.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    pub enabled: bool,
+    // This instance's name in synced entries; empty = hostname
+    pub instance: String,
+    // Shared by every instance; peers send it as a bearer token
+    pub token: String,
+    // Base URLs of the instances to pull from
+    pub peers: Vec<String>,
+    pub interval_secs: u64,
+    // Entries pulled from peers
+    pub file: String,
+}
+
+// Audit log of control actions (see audit.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSettings {
+    pub enabled: bool,
+    // Appended to, one JSON entry per line
+    pub file: String,
+}
+
+// Masking PII in transcripts (see redact.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionSettings {
+    pub enabled: bool,
+    pub phones: bool,
+    pub emails: bool,
+    pub cards: bool,
+    pub addresses: bool,
+    // A Presidio-style analyzer; empty = regexes only
+    pub ner_url: String,
+    pub ner_language: String,
+    // 0.0-1.0; weaker entities are left alone
+    pub ner_min_score: f64,
+}
+
+// Speakers who opted out of being recorded (see optout.rs); their
+// voice profiles are managed through /opt_out and kept in `file`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptOutSettings {
+    pub enabled: bool,
+    // A speaker-embedding (or diarization) service
+    pub embedding_url: String,
+    // Cosine similarity, 0.0-1.0, from which a voice is the profile's
+    pub threshold: f64,
+    // Share of a chunk's speech, 0.0-1.0, from which it's discarded
+    pub dominance: f64,
+    // Silence their speech in chunks they don't dominate
+    pub redact_segments: bool,
+    pub file: String,
+}
+
+// Who spoke, as "Speaker A", "Speaker B", ... (see speakers.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeakerSettings {
+    // "off" or "anonymous"
+    pub mode: String,
+    // A diarization service, answering like opt_out.embedding_url
+    pub embedding_url: String,
+    // Cosine similarity, 0.0-1.0, from which two segments are the
+    // same speaker
+    pub threshold: f64,
+}
+
+// An OpenAI-compatible endpoint (see routing.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderSettings {
+    // e.g. "http://127.0.0.1:8081/v1" for whisper.cpp, or
+    // "https://<resource>.openai.azure.com/openai/deployments/<name>"
+    pub base_url: String,
+    // Empty = sent without credentials, e.g. to a local server
+    pub api_key: String,
+    // "bearer" (Authorization: Bearer) or "api-key" (Azure)
+    pub auth: String,
+    // Sent as ?api-version= (Azure); empty = none
+    pub api_version: String,
+    // Empty = openai.stt_model / openai.chat_model
+    pub stt_model: String,
+    pub chat_model: String,
+    // Where it keeps and processes data: "local", "eu", "us", ...;
+    // checked against routing.regions
+    pub region: String,
+    // Takes audio uploaded while it's recorded, as a chunked
+    // request body (see pipeline.rs)
+    pub stream_audio: bool,
+}
+
+// Which providers each kind of data may be sent to, in order of
+// preference; "openai" is [openai] itself (see routing.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingSettings {
+    // Recorded audio, for transcription; empty = ["openai"]
+    pub audio: Vec<String>,
+    // Transcripts, for live responses; empty = ["openai"]
+    pub transcripts: Vec<String>,
+    // Digests and session summaries; empty = ["openai"]
+    pub summaries: Vec<String>,
+    // The only regions any of it may go to; empty = anywhere
+    pub regions: Vec<String>,
+}
+
+// Phones joining by scanning a QR code (see pairing.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PairingSettings {
+    // What phones should open, e.g. "https://silentnight.home";
+    // empty = this machine's LAN address and server.port
+    pub url: String,
+    // How long a code's login token can be used, once
+    pub token_ttl_secs: u64,
+}
+
+// How one display shows the conversation (see display.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayProfile {
+    pub font_size_px: u32,
+    // CSS colors, "#rgb" or "#rrggbb"
+    pub foreground: String,
+    pub background: String,
+    // "transcript_and_response" or "response_only"
+    pub layout: String,
+    // "live" (over /live_log) or "poll" (GET /transcript every
+    // poll_secs)
+    pub refresh: String,
+    pub poll_secs: u64,
+}
+
+// The language pages and displays use (see i18n.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiSettings {
+    // "en", "de", "es" or "fr"; empty = each browser's
+    // Accept-Language
+    pub language: String,
+    // IANA zone times are shown in, e.g. "Europe/Berlin"; empty =
+    // the server's (see timezone.rs)
+    pub timezone: String,
+}
+
+// Word-by-word live captions (see captions.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptionSettings {
+    // Ask Whisper for word timestamps and stream the words
+    pub enabled: bool,
+}
+
+// The sinks each kind of record goes to (see outputs.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSettings {
+    // From "log", "sse", "webhook", "mqtt", "tts", "eink"
+    pub transcript: Vec<String>,
+    pub response: Vec<String>,
+    // Gets the text on stdin, for the eink sink; split on
+    // whitespace, no shell
+    pub eink_command: String,
+}
+
+// Repeated transcripts from chunk overlap or a looping Whisper
+// (see dedup.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupSettings {
+    pub enabled: bool,
+    // How alike (0-1, by edit distance) a transcript must be to the
+    // one before to be dropped as a repeat
+    pub similarity: f64,
+    // At least this many words repeated from the end of the one
+    // before are cut from its start; 0 = keep them
+    pub overlap_words: usize,
+}
+
+// Free space checks (see disk.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskSettings {
+    // How often to measure, in seconds
+    pub check_secs: u64,
+    // Below this many MB free a volume is low, and warned about
+    pub low_mb: u64,
+    // Below this, chunks stop being saved to audio.save_dir
+    pub critical_mb: u64,
+}
+
+// Ratings of GPT's responses (see feedback.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedbackSettings {
+    // How many of the latest ratings GPT is shown; 0 = none
+    pub prompt_items: usize,
+}
+
+// When nothing is recorded (see quiet.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuietHoursSettings {
+    // "HH:MM-HH:MM" in local time, e.g. "22:00-07:00"
+    pub windows: Vec<String>,
+}
+
+// Local-only mode (see privacy.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacySettings {
+    // "off", or "local" to refuse connections outside the local network
+    pub mode: String,
+}
+
+// Error reports to Sentry or GlitchTip (see sentry.rs)
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SentrySettings {
+    // https://<key>@<host>/<project>; empty = no reports
+    pub dsn: String,
+    // e.g. "production"; empty = none
+    pub environment: String,
+}
+
+// Recording as a HomeKit switch (see homekit.rs)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HomeKitSettings {
+    // Serve the accessory (needs the "homekit" cargo feature)
+    pub enabled: bool,
+    // As shown in the Home app
+    pub name: String,
+    // HAP's own port, next to the web server's
+    pub port: u16,
+    // "XXX-XX-XXX" to type in when adding it; empty = generated
+    // and kept in state_file
+    pub setup_code: String,
+    // The audio source the switch controls
+    pub source: String,
+    // The accessory's identity and pairings
+    pub state_file: String,
+}
+
+/////////////////////////////////////////////////////////////
+// WebhookTarget
+//
+// One [[webhooks]] entry (see webhooks.rs).
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookTarget {
+    pub url: String,
+    // Key for the X-SilentNight-Signature HMAC; empty = unsigned
+    pub secret: String,
+    // Events to send; empty = all of them
+    pub events: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addr: "::".to_string(),
+            port: 8080,
+            listen_tcp: true,
+            unix_socket: None,
+            max_body_kb: 64,
+            sse_capacity: 100,
+            static_dir: None,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            mic_backend: "linux".to_string(),
+            device: None,
+            realtime: false,
+            chunk_secs: 5,
+            chunking: "fixed".to_string(),
+            min_chunk_secs: 2,
+            max_chunk_secs: 15,
+            pause_ms: 700,
+            save_dir: None,
+            batch_max_secs: 0,
+            batch_speech_ms: 2000,
+            deadline_secs: 0,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl AudioConfig {
+    // "default" followed by every [[audio.sources]] name
+    pub fn source_names(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_SOURCE.to_string())
+            .chain(self.sources.iter().map(|s| s.name.clone()))
+            .collect()
+    }
+
+    // None if no source by that name is configured
+    pub fn input(&self, name: &str) -> Option<MicInput> {
+        if name == DEFAULT_SOURCE {
+            return Some(MicInput {
+                backend: self.mic_backend.clone(),
+                device: self.device.clone(),
+                realtime: self.realtime,
+            });
+        }
+        self.sources.iter().find(|s| s.name == name).map(|s| MicInput {
+            backend: s.mic_backend.clone().unwrap_or_else(|| self.mic_backend.clone()),
+            device: s.device.clone().or_else(|| self.device.clone()),
+            realtime: self.realtime,
+        })
+    }
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        OpenAiConfig {
+            api_key: String::new(),
+            base_url: "https://api.openai.com/v1".to_string(),
+            stt_model: "whisper-1".to_string(),
+            language: String::new(),
+            chat_model: "gpt-4o".to_string(),
+            max_tokens: 100,
+            temperature: 0.7,
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            personas: BTreeMap::new(),
+            persona: String::new(),
+            history_messages: 40,
+            history_tokens: 3000,
+            max_concurrent: 2,
+            max_queued: 16,
+            timeout_secs: 60,
+            stream_audio: false,
+        }
+    }
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        RateLimitSettings {
+            per_minute: 10,
+            burst: 5,
+        }
+    }
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        LoggingSettings {
+            level: "info".to_string(),
+            format: "text".to_string(),
+        }
+    }
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        DiscoverySettings {
+            enabled: true,
+            instance_name: String::new(),
+        }
+    }
+}
+
+impl Default for BacklogSettings {
+    fn default() -> Self {
+        BacklogSettings {
+            enabled: true,
+            dir: "backlog".to_string(),
+            max_chunks: 2000,
+            auto_drain: true,
+            retry_secs: 30,
+        }
+    }
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings { telemetry_secs: 10 }
+    }
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        MqttSettings {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            discovery_prefix: "homeassistant".to_string(),
+            base_topic: "silentnight".to_string(),
+        }
+    }
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        AlertSettings {
+            keywords: Vec::new(),
+            context_chars: 200,
+            public_url: String::new(),
+            slack_webhook_url: String::new(),
+        }
+    }
+}
+
+impl Default for DiscordSettings {
+    fn default() -> Self {
+        DiscordSettings {
+            enabled: false,
+            bot_token: String::new(),
+            application_id: String::new(),
+            public_key: String::new(),
+            guild_id: String::new(),
+            channel_id: String::new(),
+            post_responses: false,
+            api_url: "https://discord.com/api/v10".to_string(),
+        }
+    }
+}
+
+impl Default for TelegramSettings {
+    fn default() -> Self {
+        TelegramSettings {
+            enabled: false,
+            bot_token: String::new(),
+            chat_ids: Vec::new(),
+            digest_time: "08:00".to_string(),
+            alerts: true,
+            api_url: "https://api.telegram.org".to_string(),
+        }
+    }
+}
+
+impl Default for EmailSettings {
+    fn default() -> Self {
+        EmailSettings {
+            enabled: false,
+            smtp_host: String::new(),
+            smtp_port: 587,
+            security: "starttls".to_string(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+            to: Vec::new(),
+            digest: "daily".to_string(),
+            digest_time: "08:00".to_string(),
+            digest_weekday: "monday".to_string(),
+            session_summaries: false,
+        }
+    }
+}
+
+impl Default for CalendarSettings {
+    fn default() -> Self {
+        CalendarSettings {
+            enabled: false,
+            url: String::new(),
+            username: String::new(),
+            password: String::new(),
+            source: DEFAULT_SOURCE.to_string(),
+            poll_secs: 300,
+            stop_at_end: true,
+        }
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            on_session_close: false,
+            obsidian_dir: String::new(),
+            notion_token: String::new(),
+            notion_database_id: String::new(),
+            notion_title_property: "Name".to_string(),
+            notion_api_url: "https://api.notion.com".to_string(),
+        }
+    }
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        LightSettings {
+            enabled: false,
+            kind: "hue".to_string(),
+            host: String::new(),
+            username: String::new(),
+            light: String::new(),
+            pulse_ms: 1000,
+            brightness: 255,
+            hue_discovery_url: "https://discovery.meethue.com".to_string(),
+        }
+    }
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        let phrases = [
+            ("stop listening", "stop"),
+            ("stop recording", "stop"),
+            ("bookmark that", "bookmark"),
+            ("bookmark this", "bookmark"),
+            ("switch to {persona} mode", "persona:{persona}"),
+            ("switch to normal mode", "persona:"),
+            ("forget the last {minutes} minutes", "forget:{minutes}"),
+            ("forget the last minute", "forget:1"),
+            ("forget the last hour", "forget:60"),
+        ];
+        VoiceSettings {
+            enabled: false,
+            wake_phrase: "silent night".to_string(),
+            phrases: phrases.iter().map(|(phrase, command)| (phrase.to_string(), command.to_string())).collect(),
+        }
+    }
+}
+
+impl Default for ReminderSettings {
+    fn default() -> Self {
+        ReminderSettings {
+            enabled: false,
+            file: "reminders.json".to_string(),
+            speak: true,
+        }
+    }
+}
+
+impl Default for WeatherSettings {
+    fn default() -> Self {
+        WeatherSettings {
+            enabled: false,
+            latitude: 0.0,
+            longitude: 0.0,
+            place: String::new(),
+            units: "metric".to_string(),
+            api_url: "https://api.open-meteo.com".to_string(),
+        }
+    }
+}
+
+impl Default for HubSettings {
+    fn default() -> Self {
+        HubSettings {
+            token: String::new(),
+            node_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for NodeSettings {
+    fn default() -> Self {
+        NodeSettings {
+            enabled: false,
+            hub_url: String::new(),
+            name: String::new(),
+            label: String::new(),
+            token: String::new(),
+            source: DEFAULT_SOURCE.to_string(),
+            heartbeat_secs: 5,
+        }
+    }
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        SyncSettings {
+            enabled: false,
+            instance: String::new(),
+            token: String::new(),
+            peers: Vec::new(),
+            interval_secs: 60,
+            file: "synced_log.json".to_string(),
+        }
+    }
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        AuditSettings {
+            enabled: true,
+            file: "audit_log.json".to_string(),
+        }
+    }
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        RedactionSettings {
+            enabled: false,
+            phones: true,
+            emails: true,
+            cards: true,
+            addresses: true,
+            ner_url: String::new(),
+            ner_language: "en".to_string(),
+            ner_min_score: 0.5,
+        }
+    }
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        ProviderSettings {
+            base_url: String::new(),
+            api_key: String::new(),
+            auth: "bearer".to_string(),
+            api_version: String::new(),
+            stt_model: String::new(),
+            chat_model: String::new(),
+            region: String::new(),
+            stream_audio: false,
+        }
+    }
+}
+
+impl Default for PairingSettings {
+    fn default() -> Self {
+        PairingSettings {
+            url: String::new(),
+            token_ttl_secs: 600,
+        }
+    }
+}
+
+impl Default for DisplayProfile {
+    fn default() -> Self {
+        DisplayProfile {
+            font_size_px: 24,
+            foreground: "#0f0".to_string(),
+            background: "#000".to_string(),
+            layout: "transcript_and_response".to_string(),
+            refresh: "live".to_string(),
+            poll_secs: 5,
+        }
+    }
+}
+
+impl Default for SpeakerSettings {
+    fn default() -> Self {
+        SpeakerSettings {
+            mode: "off".to_string(),
+            embedding_url: String::new(),
+            threshold: 0.75,
+        }
+    }
+}
+
+impl Default for OptOutSettings {
+    fn default() -> Self {
+        OptOutSettings {
+            enabled: false,
+            embedding_url: String::new(),
+            threshold: 0.75,
+            dominance: 0.5,
+            redact_segments: true,
+            file: "voice_profiles.json".to_string(),
+        }
+    }
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings { mode: "off".to_string() }
+    }
+}
+
+impl Default for HomeKitSettings {
+    fn default() -> Self {
+        HomeKitSettings {
+            enabled: false,
+            name: "SilentNight".to_string(),
+            port: 51826,
+            setup_code: String::new(),
+            source: DEFAULT_SOURCE.to_string(),
+            state_file: "homekit.json".to_string(),
+        }
+    }
+}
+
+impl Default for RemoteSettings {
+    fn default() -> Self {
+        let keys = [
+            ("KEY_PLAYPAUSE", "toggle"),
+            ("KEY_PLAY", "start"),
+            ("KEY_STOP", "stop"),
+            ("KEY_STOPCD", "stop"),
+            ("KEY_RECORD", "bookmark"),
+            ("KEY_MUTE", "mute"),
+            ("KEY_NEXTSONG", "persona"),
+        ];
+        RemoteSettings {
+            enabled: false,
+            lirc_socket: "/var/run/lirc/lircd".to_string(),
+            evdev_device: String::new(),
+            source: DEFAULT_SOURCE.to_string(),
+            keys: keys.iter().map(|(key, command)| (key.to_string(), command.to_string())).collect(),
+        }
+    }
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        let sinks = vec!["log".to_string(), "sse".to_string(), "webhook".to_string()];
+        OutputSettings {
+            transcript: sinks.clone(),
+            response: sinks,
+            eink_command: String::new(),
+        }
+    }
+}
+
+impl Default for DedupSettings {
+    fn default() -> Self {
+        DedupSettings {
+            enabled: false,
+            similarity: 0.9,
+            overlap_words: 3,
+        }
+    }
+}
+
+impl Default for DiskSettings {
+    fn default() -> Self {
+        DiskSettings {
+            check_secs: 30,
+            low_mb: 1024,
+            critical_mb: 256,
+        }
+    }
+}
+
+impl Default for RulesSettings {
+    fn default() -> Self {
+        RulesSettings {
+            file: "rules.json".to_string(),
+            gpio_dir: "/sys/class/gpio".to_string(),
+            tts_command: "espeak-ng --stdin".to_string(),
+        }
+    }
+}
+
+impl Default for GrpcSettings {
+    fn default() -> Self {
+        GrpcSettings {
+            enabled: false,
+            port: 50051,
+        }
+    }
+}
+
+impl Config {
+    /////////////////////////////////////////////////////////
+    // load
+    //
+    // Builds the effective config from file + env + CLI and
+    // validates it.
+    /////////////////////////////////////////////////////////
+    pub fn load(cli: &Cli) -> Result<Config> {
+        let mut config = match config_file_path(cli) {
+            Some(path) => {
+                tracing::debug!(path = %path.display(), "loading config file");
+                Config::from_file(&path)?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_cli(cli);
+        crate::secrets::resolve(&mut config)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(port) = env_parsed::<u16>("PORT")? {
+            self.server.port = port;
+        }
+        if let Some(addr) = env_string("BIND_ADDR") {
+            self.server.bind_addr = addr;
+        }
+        if let Some(kb) = env_parsed::<usize>("MAX_BODY_KB")? {
+            self.server.max_body_kb = kb;
+        }
+        if let Some(n) = env_parsed::<usize>("SSE_CAPACITY")? {
+            self.server.sse_capacity = n;
+        }
+        if let Some(flag) = env_string("LISTEN_TCP") {
+            self.server.listen_tcp = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(dir) = env_string("STATIC_DIR") {
+            self.server.static_dir = Some(dir);
+        }
+        if let Some(path) = env_string("UNIX_SOCKET") {
+            self.server.unix_socket = Some(path);
+        }
+        if let Some(backend) = env_string("MIC_BACKEND") {
+            self.audio.mic_backend = backend;
+        }
+        if let Some(flag) = env_string("AUDIO_REALTIME") {
+            self.audio.realtime = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(dir) = env_string("SAVE_AUDIO_DIR") {
+            self.audio.save_dir = Some(dir);
+        }
+        if let Some(chunking) = env_string("AUDIO_CHUNKING") {
+            self.audio.chunking = chunking;
+        }
+        if let Some(secs) = env_parsed::<u32>("AUDIO_MIN_CHUNK_SECS")? {
+            self.audio.min_chunk_secs = secs;
+        }
+        if let Some(secs) = env_parsed::<u32>("AUDIO_MAX_CHUNK_SECS")? {
+            self.audio.max_chunk_secs = secs;
+        }
+        if let Some(ms) = env_parsed::<u32>("AUDIO_PAUSE_MS")? {
+            self.audio.pause_ms = ms;
+        }
+        if let Some(secs) = env_parsed::<u32>("AUDIO_BATCH_MAX_SECS")? {
+            self.audio.batch_max_secs = secs;
+        }
+        if let Some(ms) = env_parsed::<u32>("AUDIO_BATCH_SPEECH_MS")? {
+            self.audio.batch_speech_ms = ms;
+        }
+        if let Some(secs) = env_parsed::<u32>("AUDIO_DEADLINE_SECS")? {
+            self.audio.deadline_secs = secs;
+        }
+        if let Some(key) = env_string("OPENAI_API_KEY") {
+            self.openai.api_key = key;
+        }
+        if let Some(url) = env_string("OPENAI_BASE_URL") {
+            self.openai.base_url = url;
+        }
+        if let Some(n) = env_parsed::<usize>("OPENAI_HISTORY_TOKENS")? {
+            self.openai.history_tokens = n;
+        }
+        if let Some(n) = env_parsed::<usize>("OPENAI_MAX_CONCURRENT")? {
+            self.openai.max_concurrent = n;
+        }
+        if let Some(n) = env_parsed::<usize>("OPENAI_MAX_QUEUED")? {
+            self.openai.max_queued = n;
+        }
+        if let Some(n) = env_parsed::<u64>("OPENAI_TIMEOUT_SECS")? {
+            self.openai.timeout_secs = n;
+        }
+        if let Some(flag) = env_string("OPENAI_STREAM_AUDIO") {
+            self.openai.stream_audio = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(persona) = env_string("OPENAI_PERSONA") {
+            self.openai.persona = persona;
+        }
+        if let Some(language) = env_string("OPENAI_LANGUAGE") {
+            self.openai.language = language;
+        }
+        if let Some(username) = env_string("UI_USERNAME") {
+            self.login.username = username;
+        }
+        if let Some(password) = env_string("UI_PASSWORD") {
+            self.login.password = password;
+        }
+        if let Some(path) = env_string("TLS_CERT_PATH") {
+            self.tls.cert_path = Some(path);
+        }
+        if let Some(path) = env_string("TLS_KEY_PATH") {
+            self.tls.key_path = Some(path);
+        }
+        if let Some(flag) = env_string("TLS_SELF_SIGNED") {
+            self.tls.self_signed = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(n) = env_parsed::<u32>("RATE_LIMIT_PER_MINUTE")? {
+            self.rate_limit.per_minute = n;
+        }
+        if let Some(n) = env_parsed::<u32>("RATE_LIMIT_BURST")? {
+            self.rate_limit.burst = n;
+        }
+        if let Some(level) = env_string("LOG_LEVEL") {
+            self.logging.level = level;
+        }
+        if let Some(format) = env_string("LOG_FORMAT") {
+            self.logging.format = format;
+        }
+        if let Some(token) = env_string("ADMIN_TOKEN") {
+            self.admin.token = token;
+        }
+        if let Some(flag) = env_string("DISCOVERY_ENABLED") {
+            self.discovery.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("GRPC_ENABLED") {
+            self.grpc.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(port) = env_parsed::<u16>("GRPC_PORT")? {
+            self.grpc.port = port;
+        }
+        if let Some(flag) = env_string("BACKLOG_ENABLED") {
+            self.backlog.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(dir) = env_string("BACKLOG_DIR") {
+            self.backlog.dir = dir;
+        }
+        if let Some(flag) = env_string("BACKLOG_AUTO_DRAIN") {
+            self.backlog.auto_drain = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(secs) = env_parsed::<u64>("BACKLOG_RETRY_SECS")? {
+            self.backlog.retry_secs = secs;
+        }
+        if let Some(secs) = env_parsed::<u64>("TELEMETRY_SECS")? {
+            self.metrics.telemetry_secs = secs;
+        }
+        if let Some(flag) = env_string("MQTT_ENABLED") {
+            self.mqtt.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(host) = env_string("MQTT_HOST") {
+            self.mqtt.host = host;
+        }
+        if let Some(port) = env_parsed::<u16>("MQTT_PORT")? {
+            self.mqtt.port = port;
+        }
+        if let Some(username) = env_string("MQTT_USERNAME") {
+            self.mqtt.username = username;
+        }
+        if let Some(password) = env_string("MQTT_PASSWORD") {
+            self.mqtt.password = password;
+        }
+        if let Some(keywords) = env_string("ALERT_KEYWORDS") {
+            self.alerts.keywords = keywords.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect();
+        }
+        if let Some(url) = env_string("PUBLIC_URL") {
+            self.alerts.public_url = url;
+        }
+        if let Some(url) = env_string("SLACK_WEBHOOK_URL") {
+            self.alerts.slack_webhook_url = url;
+        }
+        if let Some(flag) = env_string("DISCORD_ENABLED") {
+            self.discord.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(token) = env_string("DISCORD_BOT_TOKEN") {
+            self.discord.bot_token = token;
+        }
+        if let Some(id) = env_string("DISCORD_APPLICATION_ID") {
+            self.discord.application_id = id;
+        }
+        if let Some(key) = env_string("DISCORD_PUBLIC_KEY") {
+            self.discord.public_key = key;
+        }
+        if let Some(id) = env_string("DISCORD_GUILD_ID") {
+            self.discord.guild_id = id;
+        }
+        if let Some(id) = env_string("DISCORD_CHANNEL_ID") {
+            self.discord.channel_id = id;
+        }
+        if let Some(flag) = env_string("DISCORD_POST_RESPONSES") {
+            self.discord.post_responses = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("DISCORD_API_URL") {
+            self.discord.api_url = url;
+        }
+        if let Some(flag) = env_string("TELEGRAM_ENABLED") {
+            self.telegram.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(token) = env_string("TELEGRAM_BOT_TOKEN") {
+            self.telegram.bot_token = token;
+        }
+        if let Some(ids) = env_string("TELEGRAM_CHAT_IDS") {
+            self.telegram.chat_ids = ids
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| anyhow::anyhow!("Environment variable TELEGRAM_CHAT_IDS={ids:?} is not valid"))?;
+        }
+        if let Some(time) = env_string("TELEGRAM_DIGEST_TIME") {
+            self.telegram.digest_time = time;
+        }
+        if let Some(flag) = env_string("TELEGRAM_ALERTS") {
+            self.telegram.alerts = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("TELEGRAM_API_URL") {
+            self.telegram.api_url = url;
+        }
+        if let Some(flag) = env_string("EMAIL_ENABLED") {
+            self.email.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(host) = env_string("SMTP_HOST") {
+            self.email.smtp_host = host;
+        }
+        if let Some(port) = env_parsed::<u16>("SMTP_PORT")? {
+            self.email.smtp_port = port;
+        }
+        if let Some(security) = env_string("SMTP_SECURITY") {
+            self.email.security = security;
+        }
+        if let Some(username) = env_string("SMTP_USERNAME") {
+            self.email.username = username;
+        }
+        if let Some(password) = env_string("SMTP_PASSWORD") {
+            self.email.password = password;
+        }
+        if let Some(from) = env_string("EMAIL_FROM") {
+            self.email.from = from;
+        }
+        if let Some(to) = env_string("EMAIL_TO") {
+            self.email.to = to.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+        }
+        if let Some(digest) = env_string("EMAIL_DIGEST") {
+            self.email.digest = digest;
+        }
+        if let Some(time) = env_string("EMAIL_DIGEST_TIME") {
+            self.email.digest_time = time;
+        }
+        if let Some(day) = env_string("EMAIL_DIGEST_WEEKDAY") {
+            self.email.digest_weekday = day;
+        }
+        if let Some(flag) = env_string("EMAIL_SESSION_SUMMARIES") {
+            self.email.session_summaries = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("CALENDAR_ENABLED") {
+            self.calendar.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("CALENDAR_URL") {
+            self.calendar.url = url;
+        }
+        if let Some(username) = env_string("CALENDAR_USERNAME") {
+            self.calendar.username = username;
+        }
+        if let Some(password) = env_string("CALENDAR_PASSWORD") {
+            self.calendar.password = password;
+        }
+        if let Some(source) = env_string("CALENDAR_SOURCE") {
+            self.calendar.source = source;
+        }
+        if let Some(secs) = env_parsed::<u64>("CALENDAR_POLL_SECS")? {
+            self.calendar.poll_secs = secs;
+        }
+        if let Some(flag) = env_string("CALENDAR_STOP_AT_END") {
+            self.calendar.stop_at_end = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(file) = env_string("RULES_FILE") {
+            self.rules.file = file;
+        }
+        if let Some(dir) = env_string("GPIO_DIR") {
+            self.rules.gpio_dir = dir;
+        }
+        if let Some(command) = env_string("TTS_COMMAND") {
+            self.rules.tts_command = command;
+        }
+        if let Some(flag) = env_string("EXPORT_ON_SESSION_CLOSE") {
+            self.export.on_session_close = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(dir) = env_string("OBSIDIAN_DIR") {
+            self.export.obsidian_dir = dir;
+        }
+        if let Some(token) = env_string("NOTION_TOKEN") {
+            self.export.notion_token = token;
+        }
+        if let Some(id) = env_string("NOTION_DATABASE_ID") {
+            self.export.notion_database_id = id;
+        }
+        if let Some(property) = env_string("NOTION_TITLE_PROPERTY") {
+            self.export.notion_title_property = property;
+        }
+        if let Some(url) = env_string("NOTION_API_URL") {
+            self.export.notion_api_url = url;
+        }
+        if let Some(flag) = env_string("LIGHTS_ENABLED") {
+            self.lights.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(kind) = env_string("LIGHTS_KIND") {
+            self.lights.kind = kind;
+        }
+        if let Some(host) = env_string("LIGHTS_HOST") {
+            self.lights.host = host;
+        }
+        if let Some(username) = env_string("HUE_USERNAME") {
+            self.lights.username = username;
+        }
+        if let Some(light) = env_string("HUE_LIGHT") {
+            self.lights.light = light;
+        }
+        if let Some(ms) = env_parsed::<u64>("LIGHTS_PULSE_MS")? {
+            self.lights.pulse_ms = ms;
+        }
+        if let Some(brightness) = env_parsed::<u8>("LIGHTS_BRIGHTNESS")? {
+            self.lights.brightness = brightness;
+        }
+        if let Some(url) = env_string("HUE_DISCOVERY_URL") {
+            self.lights.hue_discovery_url = url;
+        }
+        if let Some(flag) = env_string("REMOTE_ENABLED") {
+            self.remote.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(socket) = env_string("LIRC_SOCKET") {
+            self.remote.lirc_socket = socket;
+        }
+        if let Some(device) = env_string("EVDEV_DEVICE") {
+            self.remote.evdev_device = device;
+        }
+        if let Some(source) = env_string("REMOTE_SOURCE") {
+            self.remote.source = source;
+        }
+        if let Some(flag) = env_string("VOICE_COMMANDS") {
+            self.voice.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(phrase) = env_string("VOICE_WAKE_PHRASE") {
+            self.voice.wake_phrase = phrase;
+        }
+        if let Some(flag) = env_string("REMINDERS_ENABLED") {
+            self.reminders.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(file) = env_string("REMINDERS_FILE") {
+            self.reminders.file = file;
+        }
+        if let Some(flag) = env_string("REMINDERS_SPEAK") {
+            self.reminders.speak = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("WEATHER_ENABLED") {
+            self.weather.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(latitude) = env_parsed::<f64>("WEATHER_LATITUDE")? {
+            self.weather.latitude = latitude;
+        }
+        if let Some(longitude) = env_parsed::<f64>("WEATHER_LONGITUDE")? {
+            self.weather.longitude = longitude;
+        }
+        if let Some(place) = env_string("WEATHER_PLACE") {
+            self.weather.place = place;
+        }
+        if let Some(units) = env_string("WEATHER_UNITS") {
+            self.weather.units = units;
+        }
+        if let Some(url) = env_string("WEATHER_API_URL") {
+            self.weather.api_url = url;
+        }
+        if let Some(token) = env_string("HUB_TOKEN") {
+            self.hub.token = token;
+        }
+        if let Some(secs) = env_parsed::<u64>("NODE_TIMEOUT_SECS")? {
+            self.hub.node_timeout_secs = secs;
+        }
+        if let Some(flag) = env_string("NODE_ENABLED") {
+            self.node.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("HUB_URL") {
+            self.node.hub_url = url;
+        }
+        if let Some(name) = env_string("NODE_NAME") {
+            self.node.name = name;
+        }
+        if let Some(label) = env_string("NODE_LABEL") {
+            self.node.label = label;
+        }
+        if let Some(token) = env_string("NODE_TOKEN") {
+            self.node.token = token;
+        }
+        if let Some(source) = env_string("NODE_SOURCE") {
+            self.node.source = source;
+        }
+        if let Some(secs) = env_parsed::<u64>("NODE_HEARTBEAT_SECS")? {
+            self.node.heartbeat_secs = secs;
+        }
+        if let Some(flag) = env_string("SYNC_ENABLED") {
+            self.sync.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(instance) = env_string("SYNC_INSTANCE") {
+            self.sync.instance = instance;
+        }
+        if let Some(token) = env_string("SYNC_TOKEN") {
+            self.sync.token = token;
+        }
+        if let Some(peers) = env_string("SYNC_PEERS") {
+            self.sync.peers = peers.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+        }
+        if let Some(secs) = env_parsed::<u64>("SYNC_INTERVAL_SECS")? {
+            self.sync.interval_secs = secs;
+        }
+        if let Some(file) = env_string("SYNC_FILE") {
+            self.sync.file = file;
+        }
+        if let Some(flag) = env_string("AUDIT_ENABLED") {
+            self.audit.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(file) = env_string("AUDIT_FILE") {
+            self.audit.file = file;
+        }
+        if let Some(flag) = env_string("REDACTION_ENABLED") {
+            self.redaction.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("REDACTION_PHONES") {
+            self.redaction.phones = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("REDACTION_EMAILS") {
+            self.redaction.emails = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("REDACTION_CARDS") {
+            self.redaction.cards = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(flag) = env_string("REDACTION_ADDRESSES") {
+            self.redaction.addresses = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("REDACTION_NER_URL") {
+            self.redaction.ner_url = url;
+        }
+        if let Some(language) = env_string("REDACTION_NER_LANGUAGE") {
+            self.redaction.ner_language = language;
+        }
+        if let Some(score) = env_parsed::<f64>("REDACTION_NER_MIN_SCORE")? {
+            self.redaction.ner_min_score = score;
+        }
+        if let Some(flag) = env_string("OPT_OUT_ENABLED") {
+            self.opt_out.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(url) = env_string("OPT_OUT_EMBEDDING_URL") {
+            self.opt_out.embedding_url = url;
+        }
+        if let Some(threshold) = env_parsed::<f64>("OPT_OUT_THRESHOLD")? {
+            self.opt_out.threshold = threshold;
+        }
+        if let Some(dominance) = env_parsed::<f64>("OPT_OUT_DOMINANCE")? {
+            self.opt_out.dominance = dominance;
+        }
+        if let Some(flag) = env_string("OPT_OUT_REDACT_SEGMENTS") {
+            self.opt_out.redact_segments = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(file) = env_string("OPT_OUT_FILE") {
+            self.opt_out.file = file;
+        }
+        if let Some(flag) = env_string("CAPTIONS_ENABLED") {
+            self.captions.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(sinks) = env_string("OUTPUTS_TRANSCRIPT") {
+            self.outputs.transcript = sinks.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+        }
+        if let Some(sinks) = env_string("OUTPUTS_RESPONSE") {
+            self.outputs.response = sinks.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+        }
+        if let Some(command) = env_string("OUTPUTS_EINK_COMMAND") {
+            self.outputs.eink_command = command;
+        }
+        if let Some(flag) = env_string("DEDUP_ENABLED") {
+            self.dedup.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(similarity) = env_parsed::<f64>("DEDUP_SIMILARITY")? {
+            self.dedup.similarity = similarity;
+        }
+        if let Some(words) = env_parsed::<usize>("DEDUP_OVERLAP_WORDS")? {
+            self.dedup.overlap_words = words;
+        }
+        if let Some(items) = env_parsed::<usize>("FEEDBACK_PROMPT_ITEMS")? {
+            self.feedback.prompt_items = items;
+        }
+        if let Some(secs) = env_parsed::<u64>("DISK_CHECK_SECS")? {
+            self.disk.check_secs = secs;
+        }
+        if let Some(mb) = env_parsed::<u64>("DISK_LOW_MB")? {
+            self.disk.low_mb = mb;
+        }
+        if let Some(mb) = env_parsed::<u64>("DISK_CRITICAL_MB")? {
+            self.disk.critical_mb = mb;
+        }
+        if let Some(mode) = env_string("SPEAKERS_MODE") {
+            self.speakers.mode = mode;
+        }
+        if let Some(url) = env_string("SPEAKERS_EMBEDDING_URL") {
+            self.speakers.embedding_url = url;
+        }
+        if let Some(threshold) = env_parsed::<f64>("SPEAKERS_THRESHOLD")? {
+            self.speakers.threshold = threshold;
+        }
+        if let Some(providers) = env_string("ROUTING_AUDIO") {
+            self.routing.audio = providers.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+        }
+        if let Some(providers) = env_string("ROUTING_TRANSCRIPTS") {
+            self.routing.transcripts =
+                providers.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+        }
+        if let Some(providers) = env_string("ROUTING_SUMMARIES") {
+            self.routing.summaries = providers.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+        }
+        if let Some(regions) = env_string("ROUTING_REGIONS") {
+            self.routing.regions = regions.split(',').map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect();
+        }
+        if let Some(url) = env_string("PAIRING_URL") {
+            self.pairing.url = url;
+        }
+        if let Some(secs) = env_parsed::<u64>("PAIRING_TOKEN_TTL_SECS")? {
+            self.pairing.token_ttl_secs = secs;
+        }
+        if let Some(language) = env_string("UI_LANGUAGE") {
+            self.ui.language = language;
+        }
+        if let Some(zone) = env_string("UI_TIMEZONE") {
+            self.ui.timezone = zone;
+        }
+        if let Some(windows) = env_string("QUIET_HOURS") {
+            self.quiet_hours.windows = windows.split(',').map(|w| w.trim().to_string()).filter(|w| !w.is_empty()).collect();
+        }
+        if let Some(mode) = env_string("PRIVACY_MODE") {
+            self.privacy.mode = mode;
+        }
+        if let Some(dsn) = env_string("SENTRY_DSN") {
+            self.sentry.dsn = dsn;
+        }
+        if let Some(environment) = env_string("SENTRY_ENVIRONMENT") {
+            self.sentry.environment = environment;
+        }
+        if let Some(flag) = env_string("HOMEKIT_ENABLED") {
+            self.homekit.enabled = flag == "1" || flag.eq_ignore_ascii_case("true");
+        }
+        if let Some(name) = env_string("HOMEKIT_NAME") {
+            self.homekit.name = name;
+        }
+        if let Some(port) = env_parsed::<u16>("HOMEKIT_PORT")? {
+            self.homekit.port = port;
+        }
+        if let Some(code) = env_string("HOMEKIT_SETUP_CODE") {
+            self.homekit.setup_code = code;
+        }
+        if let Some(source) = env_string("HOMEKIT_SOURCE") {
+            self.homekit.source = source;
+        }
+        if let Some(file) = env_string("HOMEKIT_STATE_FILE") {
+            self.homekit.state_file = file;
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(backend) = &cli.mic_backend {
+            self.audio.mic_backend = backend.clone();
+        }
+        if let Some(secs) = cli.chunk_secs {
+            self.audio.chunk_secs = secs;
+        }
+        // Every source plays the recording, whatever it's set up with
+        if let Some(path) = &cli.simulate {
+            self.audio.mic_backend = "file".to_string();
+            self.audio.device = Some(path.display().to_string());
+            self.audio.realtime = true;
+            for source in &mut self.audio.sources {
+                source.mic_backend = None;
+                source.device = None;
+            }
+        }
+        if let Some(model) = &cli.chat_model {
+            self.openai.chat_model = model.clone();
+        }
+        if let Some(level) = &cli.log_level {
+            self.logging.level = level.clone();
+        }
+    }
+
+    /////////////////////////////////////////////////////////
+    // validate
+    //
+    // Collects every problem so they can all be fixed in one go.
+    /////////////////////////////////////////////////////////
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if crate::listen::parse_bind_addr(&self.server.bind_addr).is_none() {
+            problems.push(format!(
+                "server.bind_addr (BIND_ADDR) must be an IPv4 or IPv6 address, got {:?}",
+                self.server.bind_addr
+            ));
+        }
+        if !(1..=1024 * 1024).contains(&self.server.max_body_kb) {
+            problems.push(format!(
+                "server.max_body_kb (MAX_BODY_KB) must be between 1 and 1048576, got {}",
+                self.server.max_body_kb
+            ));
+        }
+        if !(1..=10_000).contains(&self.server.sse_capacity) {
+            problems.push(format!(
+                "server.sse_capacity (SSE_CAPACITY) must be between 1 and 10000, got {}",
+                self.server.sse_capacity
+            ));
+        }
+        if self.metrics.telemetry_secs > 3600 {
+            problems.push(format!(
+                "metrics.telemetry_secs (TELEMETRY_SECS) must be at most 3600, got {}",
+                self.metrics.telemetry_secs
+            ));
+        }
+        if !self.server.listen_tcp && self.server.unix_socket.is_none() {
+            problems.push(
+                "server.listen_tcp (LISTEN_TCP) is off but no server.unix_socket (UNIX_SOCKET) is set"
+                    .to_string(),
+            );
+        }
+        if self.server.unix_socket.is_some() && !cfg!(unix) {
+            problems.push("server.unix_socket (UNIX_SOCKET) is only supported on Unix".to_string());
+        }
+        if !matches!(self.audio.mic_backend.as_str(), "linux" | "mac" | "file" | "node") {
+            problems.push(format!(
+                "audio.mic_backend (MIC_BACKEND) must be \"linux\", \"mac\", \"file\" or \"node\", got {:?}",
+                self.audio.mic_backend
+            ));
+        }
+        let mut seen = vec![DEFAULT_SOURCE];
+        for source in &self.audio.sources {
+            let valid_name = !source.name.is_empty()
+                && source.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                problems.push(format!(
+                    "audio.sources names must be non-empty and use only letters, digits, '-' or '_', got {:?}",
+                    source.name
+                ));
+            } else if seen.contains(&source.name.as_str()) {
+                problems.push(format!("audio.sources name {:?} is used more than once", source.name));
+            }
+            seen.push(&source.name);
+
+            if let Some(backend) = &source.mic_backend {
+                if !matches!(backend.as_str(), "linux" | "mac" | "file" | "node") {
+                    problems.push(format!(
+                        "audio.sources {:?}: mic_backend must be \"linux\", \"mac\", \"file\" or \"node\", got {:?}",
+                        source.name, backend
+                    ));
+                }
+            }
+        }
+        for name in self.audio.source_names() {
+            let needs_device = self
+                .audio
+                .input(&name)
+                .is_some_and(|input| input.backend == "file" && input.device.is_none());
+            if needs_device {
+                problems.push(format!(
+                    "audio source {name:?} uses the \"file\" mic_backend but has no device (the WAV file or directory to read)"
+                ));
+            }
+        }
+        if !(1..=60).contains(&self.audio.chunk_secs) {
+            problems.push(format!(
+                "audio.chunk_secs must be between 1 and 60, got {}",
+                self.audio.chunk_secs
+            ));
+        }
+        if !["fixed", "speech"].contains(&self.audio.chunking.as_str()) {
+            problems.push(format!(
+                "audio.chunking (AUDIO_CHUNKING) must be \"fixed\" or \"speech\", got {:?}",
+                self.audio.chunking
+            ));
+        }
+        if !(1..=60).contains(&self.audio.max_chunk_secs) || !(1..=self.audio.max_chunk_secs).contains(&self.audio.min_chunk_secs) {
+            problems.push(format!(
+                "audio.min_chunk_secs and audio.max_chunk_secs must satisfy 1 <= min <= max <= 60, got {} and {}",
+                self.audio.min_chunk_secs, self.audio.max_chunk_secs
+            ));
+        }
+        if !(100..=5000).contains(&self.audio.pause_ms) {
+            problems.push(format!(
+                "audio.pause_ms (AUDIO_PAUSE_MS) must be between 100 and 5000, got {}",
+                self.audio.pause_ms
+            ));
+        }
+        // Whisper takes files up to 25 MB, about 2 minutes of CD-quality audio
+        if self.audio.batch_max_secs > 120 {
+            problems.push(format!(
+                "audio.batch_max_secs (AUDIO_BATCH_MAX_SECS) must be at most 120, got {}",
+                self.audio.batch_max_secs
+            ));
+        }
+        if self.audio.batch_speech_ms > 60_000 {
+            problems.push(format!(
+                "audio.batch_speech_ms (AUDIO_BATCH_SPEECH_MS) must be at most 60000, got {}",
+                self.audio.batch_speech_ms
+            ));
+        }
+        if self.audio.deadline_secs > 600 {
+            problems.push(format!(
+                "audio.deadline_secs (AUDIO_DEADLINE_SECS) must be at most 600, got {}",
+                self.audio.deadline_secs
+            ));
+        }
+        if !(0.0..=2.0).contains(&self.openai.temperature) {
+            problems.push(format!(
+                "openai.temperature must be between 0.0 and 2.0, got {}",
+                self.openai.temperature
+            ));
+        }
+        if !(1..=32).contains(&self.openai.max_concurrent) {
+            problems.push(format!(
+                "openai.max_concurrent (OPENAI_MAX_CONCURRENT) must be between 1 and 32, got {}",
+                self.openai.max_concurrent
+            ));
+        }
+        if self.openai.max_queued > 1000 {
+            problems.push(format!(
+                "openai.max_queued (OPENAI_MAX_QUEUED) must be at most 1000, got {}",
+                self.openai.max_queued
+            ));
+        }
+        if self.openai.history_tokens > 100_000 {
+            problems.push(format!(
+                "openai.history_tokens (OPENAI_HISTORY_TOKENS) must be at most 100000, got {}",
+                self.openai.history_tokens
+            ));
+        }
+        if !(1..=600).contains(&self.openai.timeout_secs) {
+            problems.push(format!(
+                "openai.timeout_secs (OPENAI_TIMEOUT_SECS) must be between 1 and 600, got {}",
+                self.openai.timeout_secs
+            ));
+        }
+        if !self.openai.base_url.starts_with("https://") && !self.openai.base_url.starts_with("http://") {
+            problems.push(format!(
+                "openai.base_url (OPENAI_BASE_URL) must be an http:// or https:// URL, got {:?}",
+                self.openai.base_url
+            ));
+        }
+        if self.openai.chat_model.trim().is_empty() || self.openai.stt_model.trim().is_empty() {
+            problems.push("openai.chat_model and openai.stt_model must not be empty".to_string());
+        }
+        if !self.openai.persona.is_empty() && !self.openai.personas.contains_key(&self.openai.persona) {
+            problems.push(format!(
+                "openai.persona (OPENAI_PERSONA) {:?} isn't one of openai.personas",
+                self.openai.persona
+            ));
+        }
+        if !crate::session_settings::valid_language(&self.openai.language) {
+            problems.push(format!(
+                "openai.language (OPENAI_LANGUAGE) must be an ISO-639-1 code such as \"de\", or empty, got {:?}",
+                self.openai.language
+            ));
+        }
+        if self.login.username.is_empty() != self.login.password.is_empty() {
+            problems.push(
+                "login.username (UI_USERNAME) and login.password (UI_PASSWORD) must be set together"
+                    .to_string(),
+            );
+        }
+        for (name, token) in &self.tokens {
+            if token.token.is_empty() {
+                problems.push(format!("tokens.{name}.token can't be empty"));
+            } else if token.token == self.admin.token {
+                problems.push(format!("tokens.{name}.token is admin.token (ADMIN_TOKEN); pick another"));
+            } else if self.tokens.iter().any(|(other, t)| other < name && t.token == token.token) {
+                problems.push(format!("tokens.{name}.token is another token's too; pick another"));
+            }
+            if crate::tokens::Scope::parse(&token.scope).is_none() {
+                problems.push(format!(
+                    "tokens.{name}.scope must be one of {}, got {:?}",
+                    crate::tokens::SCOPES.join(", "),
+                    token.scope
+                ));
+            }
+        }
+        for (name, token) in &self.tokens {
+            if !token.workspace.is_empty() && !self.workspaces.contains_key(&token.workspace) {
+                problems.push(format!("tokens.{name}.workspace {:?} isn't one of [workspaces]", token.workspace));
+            }
+        }
+        for (name, workspace) in &self.workspaces {
+            // It names a directory
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                problems.push(format!("workspaces names must use only letters, digits, '-' or '_', got {name:?}"));
+            }
+            for source in &workspace.sources {
+                if !self.audio.source_names().contains(source) {
+                    problems.push(format!("workspaces.{name}.sources: no audio source named {source:?}"));
+                } else if self.workspaces.iter().any(|(other, w)| other < name && w.sources.contains(source)) {
+                    problems.push(format!("workspaces.{name}.sources: {source:?} is another workspace's already"));
+                }
+            }
+            if !workspace.persona.is_empty() && !self.openai.personas.contains_key(&workspace.persona) {
+                problems.push(format!("workspaces.{name}.persona {:?} isn't one of openai.personas", workspace.persona));
+            }
+            if workspace.retention_days > 36_500 {
+                problems.push(format!(
+                    "workspaces.{name}.retention_days must be at most 36500, got {}",
+                    workspace.retention_days
+                ));
+            }
+        }
+        // Without a login everything is open, so a token would limit nothing
+        if !self.tokens.is_empty() && self.login.username.is_empty() {
+            problems.push("tokens need login.username (UI_USERNAME) and login.password (UI_PASSWORD) set".to_string());
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            problems.push(
+                "tls.cert_path (TLS_CERT_PATH) and tls.key_path (TLS_KEY_PATH) must be set together"
+                    .to_string(),
+            );
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            problems.push(format!(
+                "logging.level (LOG_LEVEL) {:?} is not a valid level or filter: {}",
+                self.logging.level, e
+            ));
+        }
+        for target in &self.webhooks {
+            if !(target.url.starts_with("http://") || target.url.starts_with("https://")) {
+                problems.push(format!("webhooks url must start with http:// or https://, got {:?}", target.url));
+            }
+            for event in &target.events {
+                if !crate::webhooks::EVENT_TYPES.contains(&event.as_str()) {
+                    problems.push(format!(
+                        "webhooks {:?}: unknown event {:?} (expected one of {})",
+                        target.url,
+                        event,
+                        crate::webhooks::EVENT_TYPES.join(", ")
+                    ));
+                }
+            }
+        }
+        if self.grpc.enabled && !cfg!(feature = "grpc") {
+            problems.push("grpc.enabled (GRPC_ENABLED) needs a build with --features grpc".to_string());
+        }
+        // gRPC has no login session, so a protected UI means a token-protected API
+        if self.grpc.enabled && !self.login.username.is_empty() && self.admin.token.is_empty() {
+            problems.push(
+                "grpc.enabled (GRPC_ENABLED) with login set also needs admin.token (ADMIN_TOKEN) for gRPC clients"
+                    .to_string(),
+            );
+        }
+        if self.grpc.enabled && self.server.listen_tcp && self.grpc.port == self.server.port {
+            problems.push(format!("grpc.port (GRPC_PORT) {} is already server.port", self.grpc.port));
+        }
+        if self.mqtt.enabled && !cfg!(feature = "mqtt") {
+            problems.push("mqtt.enabled (MQTT_ENABLED) needs a build with --features mqtt".to_string());
+        }
+        if self.mqtt.enabled && self.mqtt.host.is_empty() {
+            problems.push("mqtt.host (MQTT_HOST) must be set when mqtt.enabled is on".to_string());
+        }
+        for (name, topic) in [("mqtt.discovery_prefix", &self.mqtt.discovery_prefix), ("mqtt.base_topic", &self.mqtt.base_topic)] {
+            if topic.is_empty() || topic.contains(['+', '#']) || topic.starts_with('/') || topic.ends_with('/') {
+                problems.push(format!(
+                    "{name} must be a non-empty MQTT topic without wildcards or leading/trailing '/', got {topic:?}"
+                ));
+            }
+        }
+        if self.alerts.keywords.iter().any(|k| k.trim().is_empty()) {
+            problems.push("alerts.keywords (ALERT_KEYWORDS) can't contain an empty keyword".to_string());
+        }
+        for (name, url) in [
+            ("alerts.public_url (PUBLIC_URL)", &self.alerts.public_url),
+            ("alerts.slack_webhook_url (SLACK_WEBHOOK_URL)", &self.alerts.slack_webhook_url),
+        ] {
+            if !url.is_empty() && !url.starts_with("https://") && !url.starts_with("http://") {
+                problems.push(format!("{name} must be an http:// or https:// URL, got {url:?}"));
+            }
+        }
+        if self.discord.enabled {
+            for (name, value) in [
+                ("discord.bot_token (DISCORD_BOT_TOKEN)", &self.discord.bot_token),
+                ("discord.application_id (DISCORD_APPLICATION_ID)", &self.discord.application_id),
+                ("discord.channel_id (DISCORD_CHANNEL_ID)", &self.discord.channel_id),
+            ] {
+                if value.is_empty() {
+                    problems.push(format!("{name} must be set when discord.enabled is on"));
+                }
+            }
+            if !self.discord.api_url.starts_with("https://") && !self.discord.api_url.starts_with("http://") {
+                problems.push(format!(
+                    "discord.api_url (DISCORD_API_URL) must be an http:// or https:// URL, got {:?}",
+                    self.discord.api_url
+                ));
+            }
+            if crate::discord::public_key(&self.discord.public_key).is_none() {
+                problems.push(format!(
+                    "discord.public_key (DISCORD_PUBLIC_KEY) must be the application's 64-digit hex public key, got {:?}",
+                    self.discord.public_key
+                ));
+            }
+        }
+        if self.telegram.enabled {
+            if self.telegram.bot_token.is_empty() {
+                problems.push("telegram.bot_token (TELEGRAM_BOT_TOKEN) must be set when telegram.enabled is on".to_string());
+            }
+            if self.telegram.chat_ids.is_empty() {
+                problems.push("telegram.chat_ids (TELEGRAM_CHAT_IDS) must list at least one chat".to_string());
+            }
+            if !self.telegram.api_url.starts_with("https://") && !self.telegram.api_url.starts_with("http://") {
+                problems.push(format!(
+                    "telegram.api_url (TELEGRAM_API_URL) must be an http:// or https:// URL, got {:?}",
+                    self.telegram.api_url
+                ));
+            }
+        }
+        if !self.telegram.digest_time.is_empty()
+            && chrono::NaiveTime::parse_from_str(&self.telegram.digest_time, "%H:%M").is_err()
+        {
+            problems.push(format!(
+                "telegram.digest_time (TELEGRAM_DIGEST_TIME) must be \"HH:MM\" or empty, got {:?}",
+                self.telegram.digest_time
+            ));
+        }
+        if self.email.enabled {
+            if !cfg!(feature = "email") {
+                problems.push("email.enabled (EMAIL_ENABLED) needs a build with --features email".to_string());
+            }
+            for (name, value) in [("email.smtp_host (SMTP_HOST)", &self.email.smtp_host), ("email.from (EMAIL_FROM)", &self.email.from)] {
+                if value.is_empty() {
+                    problems.push(format!("{name} must be set when email.enabled is on"));
+                }
+            }
+            if self.email.to.is_empty() {
+                problems.push("email.to (EMAIL_TO) must list at least one recipient".to_string());
+            }
+            #[cfg(feature = "email")]
+            for address in std::iter::once(&self.email.from).chain(&self.email.to).filter(|a| !a.is_empty()) {
+                if address.parse::<lettre::message::Mailbox>().is_err() {
+                    problems.push(format!("email address {address:?} is not valid"));
+                }
+            }
+        }
+        if !matches!(self.email.security.as_str(), "starttls" | "tls" | "none") {
+            problems.push(format!(
+                "email.security (SMTP_SECURITY) must be \"starttls\", \"tls\" or \"none\", got {:?}",
+                self.email.security
+            ));
+        }
+        if !matches!(self.email.digest.as_str(), "daily" | "weekly" | "off") {
+            problems.push(format!(
+                "email.digest (EMAIL_DIGEST) must be \"daily\", \"weekly\" or \"off\", got {:?}",
+                self.email.digest
+            ));
+        }
+        if chrono::NaiveTime::parse_from_str(&self.email.digest_time, "%H:%M").is_err() {
+            problems.push(format!(
+                "email.digest_time (EMAIL_DIGEST_TIME) must be \"HH:MM\", got {:?}",
+                self.email.digest_time
+            ));
+        }
+        if self.email.digest_weekday.parse::<chrono::Weekday>().is_err() {
+            problems.push(format!(
+                "email.digest_weekday (EMAIL_DIGEST_WEEKDAY) must be a day of the week, got {:?}",
+                self.email.digest_weekday
+            ));
+        }
+        if self.calendar.enabled && !self.calendar.url.starts_with("https://") && !self.calendar.url.starts_with("http://") {
+            problems.push(format!(
+                "calendar.url (CALENDAR_URL) must be an http:// or https:// URL, got {:?}",
+                self.calendar.url
+            ));
+        }
+        if self.calendar.poll_secs == 0 {
+            problems.push("calendar.poll_secs (CALENDAR_POLL_SECS) must be at least 1".to_string());
+        }
+        if self.rules.file.is_empty() {
+            problems.push("rules.file (RULES_FILE) can't be empty".to_string());
+        }
+        if self.export.notion_token.is_empty() != self.export.notion_database_id.is_empty() {
+            problems.push("export.notion_token (NOTION_TOKEN) and export.notion_database_id (NOTION_DATABASE_ID) go together".to_string());
+        }
+        if !self.export.notion_token.is_empty() && self.export.notion_title_property.is_empty() {
+            problems.push("export.notion_title_property (NOTION_TITLE_PROPERTY) can't be empty".to_string());
+        }
+        if !matches!(self.lights.kind.as_str(), "hue" | "wled") {
+            problems.push(format!("lights.kind (LIGHTS_KIND) must be \"hue\" or \"wled\", got {:?}", self.lights.kind));
+        }
+        if self.lights.enabled && self.lights.host.is_empty() {
+            problems.push("lights.host (LIGHTS_HOST) is required when lights.enabled".to_string());
+        }
+        if self.voice.enabled && !self.voice.wake_phrase.chars().any(char::is_alphanumeric) {
+            problems.push("voice.wake_phrase (VOICE_WAKE_PHRASE) must have words in it".to_string());
+        }
+        for (phrase, command) in &self.voice.phrases {
+            let command = command.replace("{persona}", "persona").replace("{minutes}", "2");
+            if let Err(e) = command.parse::<crate::control::Command>() {
+                problems.push(format!("voice.phrases.{phrase:?}: {e}"));
+            }
+        }
+        if self.reminders.file.is_empty() {
+            problems.push("reminders.file (REMINDERS_FILE) can't be empty".to_string());
+        }
+        if self.weather.enabled && self.weather.latitude == 0.0 && self.weather.longitude == 0.0 {
+            problems.push(
+                "weather.latitude (WEATHER_LATITUDE) and weather.longitude (WEATHER_LONGITUDE) are required when weather.enabled"
+                    .to_string(),
+            );
+        }
+        if !(-90.0..=90.0).contains(&self.weather.latitude) || !(-180.0..=180.0).contains(&self.weather.longitude) {
+            problems.push(format!(
+                "weather.latitude must be within ±90 and weather.longitude within ±180, got {}, {}",
+                self.weather.latitude, self.weather.longitude
+            ));
+        }
+        if !matches!(self.weather.units.as_str(), "metric" | "imperial") {
+            problems.push(format!("weather.units (WEATHER_UNITS) must be \"metric\" or \"imperial\", got {:?}", self.weather.units));
+        }
+        if !self.weather.api_url.starts_with("https://") && !self.weather.api_url.starts_with("http://") {
+            problems.push(format!(
+                "weather.api_url (WEATHER_API_URL) must be an http:// or https:// URL, got {:?}",
+                self.weather.api_url
+            ));
+        }
+        if self.hub.node_timeout_secs == 0 {
+            problems.push("hub.node_timeout_secs (NODE_TIMEOUT_SECS) must be at least 1".to_string());
+        }
+        if self.node.enabled {
+            if !self.node.hub_url.starts_with("https://") && !self.node.hub_url.starts_with("http://") {
+                problems.push(format!(
+                    "node.hub_url (HUB_URL) must be an http:// or https:// URL, got {:?}",
+                    self.node.hub_url
+                ));
+            }
+            let valid_name = !self.node.name.is_empty()
+                && self.node.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                problems.push(format!(
+                    "node.name (NODE_NAME) must be the hub's audio source for this node (letters, digits, '-' or '_'), got {:?}",
+                    self.node.name
+                ));
+            }
+            if self.node.token.is_empty() {
+                problems.push("node.token (NODE_TOKEN) is required when node.enabled; it's the hub's hub.token".to_string());
+            }
+            if self.audio.input(&self.node.source).is_none_or(|input| input.backend == "node") {
+                problems.push(format!(
+                    "node.source (NODE_SOURCE) must be a local audio source with a mic, got {:?}",
+                    self.node.source
+                ));
+            }
+            if self.node.heartbeat_secs == 0 {
+                problems.push("node.heartbeat_secs (NODE_HEARTBEAT_SECS) must be at least 1".to_string());
+            }
+        }
+        if self.audit.file.is_empty() {
+            problems.push("audit.file (AUDIT_FILE) can't be empty".to_string());
+        }
+        if self.sync.file.is_empty() {
+            problems.push("sync.file (SYNC_FILE) can't be empty".to_string());
+        }
+        if self.sync.enabled {
+            if self.sync.token.is_empty() {
+                problems.push("sync.token (SYNC_TOKEN) is required when sync.enabled; use the same one on every instance".to_string());
+            }
+            for peer in &self.sync.peers {
+                if !peer.starts_with("https://") && !peer.starts_with("http://") {
+                    problems.push(format!("sync.peers (SYNC_PEERS) must be http:// or https:// URLs, got {peer:?}"));
+                }
+            }
+            if self.sync.interval_secs == 0 {
+                problems.push("sync.interval_secs (SYNC_INTERVAL_SECS) must be at least 1".to_string());
+            }
+        }
+        if self.opt_out.enabled
+            && !self.opt_out.embedding_url.starts_with("https://")
+            && !self.opt_out.embedding_url.starts_with("http://")
+        {
+            problems.push(format!(
+                "opt_out.embedding_url (OPT_OUT_EMBEDDING_URL) must be an http:// or https:// URL when opt_out is enabled, got {:?}",
+                self.opt_out.embedding_url
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.opt_out.threshold) {
+            problems.push(format!(
+                "opt_out.threshold (OPT_OUT_THRESHOLD) must be between 0 and 1, got {}",
+                self.opt_out.threshold
+            ));
+        }
+        if !(self.opt_out.dominance > 0.0 && self.opt_out.dominance <= 1.0) {
+            problems.push(format!(
+                "opt_out.dominance (OPT_OUT_DOMINANCE) must be above 0 and at most 1, got {}",
+                self.opt_out.dominance
+            ));
+        }
+        if self.opt_out.file.is_empty() {
+            problems.push("opt_out.file (OPT_OUT_FILE) can't be empty".to_string());
+        }
+        if !["off", "anonymous"].contains(&self.speakers.mode.as_str()) {
+            problems.push(format!(
+                "speakers.mode (SPEAKERS_MODE) must be \"off\" or \"anonymous\", got {:?}",
+                self.speakers.mode
+            ));
+        }
+        if self.speakers.mode == "anonymous"
+            && !self.speakers.embedding_url.starts_with("https://")
+            && !self.speakers.embedding_url.starts_with("http://")
+        {
+            problems.push(format!(
+                "speakers.embedding_url (SPEAKERS_EMBEDDING_URL) must be an http:// or https:// URL when speakers.mode is anonymous, got {:?}",
+                self.speakers.embedding_url
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.speakers.threshold) {
+            problems.push(format!(
+                "speakers.threshold (SPEAKERS_THRESHOLD) must be between 0 and 1, got {}",
+                self.speakers.threshold
+            ));
+        }
+        for (name, provider) in &self.providers {
+            if name == crate::routing::BUILT_IN {
+                problems.push(format!("providers.{name}: \"{name}\" is [openai] itself; pick another name"));
+            }
+            if !provider.base_url.starts_with("https://") && !provider.base_url.starts_with("http://") {
+                problems.push(format!(
+                    "providers.{name}.base_url must be an http:// or https:// URL, got {:?}",
+                    provider.base_url
+                ));
+            }
+            if !matches!(provider.auth.as_str(), "bearer" | "api-key") {
+                problems.push(format!(
+                    "providers.{name}.auth must be \"bearer\" or \"api-key\", got {:?}",
+                    provider.auth
+                ));
+            }
+        }
+        if !self.pairing.url.is_empty()
+            && !self.pairing.url.starts_with("https://")
+            && !self.pairing.url.starts_with("http://")
+        {
+            problems.push(format!(
+                "pairing.url (PAIRING_URL) must be an http:// or https:// URL, got {:?}",
+                self.pairing.url
+            ));
+        }
+        if !(60..=86_400).contains(&self.pairing.token_ttl_secs) {
+            problems.push(format!(
+                "pairing.token_ttl_secs (PAIRING_TOKEN_TTL_SECS) must be between 60 and 86400, got {}",
+                self.pairing.token_ttl_secs
+            ));
+        }
+        for (name, display) in &self.displays {
+            if !(8..=200).contains(&display.font_size_px) {
+                problems.push(format!(
+                    "displays.{name}.font_size_px must be between 8 and 200, got {}",
+                    display.font_size_px
+                ));
+            }
+            for (field, color) in [("foreground", &display.foreground), ("background", &display.background)] {
+                if !is_hex_color(color) {
+                    problems.push(format!("displays.{name}.{field} must be a \"#rgb\" or \"#rrggbb\" color, got {color:?}"));
+                }
+            }
+            if !matches!(display.layout.as_str(), "transcript_and_response" | "response_only") {
+                problems.push(format!(
+                    "displays.{name}.layout must be \"transcript_and_response\" or \"response_only\", got {:?}",
+                    display.layout
+                ));
+            }
+            if !matches!(display.refresh.as_str(), "live" | "poll") {
+                problems.push(format!("displays.{name}.refresh must be \"live\" or \"poll\", got {:?}", display.refresh));
+            }
+            if !(1..=3600).contains(&display.poll_secs) {
+                problems.push(format!("displays.{name}.poll_secs must be between 1 and 3600, got {}", display.poll_secs));
+            }
+        }
+        if !self.ui.language.is_empty() && !crate::i18n::LANGUAGES.contains(&self.ui.language.as_str()) {
+            problems.push(format!(
+                "ui.language (UI_LANGUAGE) must be one of {} or empty, got {:?}",
+                crate::i18n::LANGUAGES.join(", "),
+                self.ui.language
+            ));
+        }
+        if !crate::timezone::valid(&self.ui.timezone) {
+            problems.push(format!(
+                "ui.timezone (UI_TIMEZONE) must be an IANA time zone such as \"Europe/Berlin\" or empty, got {:?}",
+                self.ui.timezone
+            ));
+        }
+        for (kind, sinks) in crate::outputs::KINDS.iter().zip([&self.outputs.transcript, &self.outputs.response]) {
+            let env = format!("OUTPUTS_{}", kind.to_uppercase());
+            for sink in sinks.iter().filter(|sink| !crate::outputs::SINKS.contains(&sink.as_str())) {
+                problems.push(format!(
+                    "outputs.{kind} ({env}): unknown sink {sink:?} (expected one of {})",
+                    crate::outputs::SINKS.join(", ")
+                ));
+            }
+            if sinks.iter().any(|sink| sink == "mqtt") && !self.mqtt.enabled {
+                problems.push(format!("outputs.{kind} ({env}) sends to mqtt, which needs mqtt.enabled (MQTT_ENABLED)"));
+            }
+            if sinks.iter().any(|sink| sink == "eink") && self.outputs.eink_command.trim().is_empty() {
+                problems.push(format!(
+                    "outputs.{kind} ({env}) sends to eink, which needs outputs.eink_command (OUTPUTS_EINK_COMMAND)"
+                ));
+            }
+        }
+        if !(self.dedup.similarity > 0.0 && self.dedup.similarity <= 1.0) {
+            problems.push(format!(
+                "dedup.similarity (DEDUP_SIMILARITY) must be above 0 and at most 1, got {}",
+                self.dedup.similarity
+            ));
+        }
+        if self.feedback.prompt_items > 50 {
+            problems.push(format!(
+                "feedback.prompt_items (FEEDBACK_PROMPT_ITEMS) must be at most 50, got {}",
+                self.feedback.prompt_items
+            ));
+        }
+        if !(1..=3600).contains(&self.backlog.retry_secs) {
+            problems.push(format!(
+                "backlog.retry_secs (BACKLOG_RETRY_SECS) must be between 1 and 3600, got {}",
+                self.backlog.retry_secs
+            ));
+        }
+        if !(1..=3600).contains(&self.disk.check_secs) {
+            problems.push(format!(
+                "disk.check_secs (DISK_CHECK_SECS) must be between 1 and 3600, got {}",
+                self.disk.check_secs
+            ));
+        }
+        if self.disk.critical_mb > self.disk.low_mb {
+            problems.push(format!(
+                "disk.critical_mb (DISK_CRITICAL_MB) must be at most disk.low_mb (DISK_LOW_MB), got {} and {}",
+                self.disk.critical_mb, self.disk.low_mb
+            ));
+        }
+        for category in crate::routing::Category::ALL {
+            if let Err(e) = crate::routing::providers(self, category) {
+                problems.push(e.to_string());
+            }
+        }
+        for window in &self.quiet_hours.windows {
+            if let Err(e) = crate::quiet::Window::parse(window) {
+                problems.push(format!("quiet_hours.windows (QUIET_HOURS) {e}"));
+            }
+        }
+        if !matches!(self.privacy.mode.as_str(), "off" | "local") {
+            problems.push(format!("privacy.mode (PRIVACY_MODE) must be \"off\" or \"local\", got {:?}", self.privacy.mode));
+        }
+        if !self.redaction.ner_url.is_empty() {
+            if !self.redaction.ner_url.starts_with("https://") && !self.redaction.ner_url.starts_with("http://") {
+                problems.push(format!(
+                    "redaction.ner_url (REDACTION_NER_URL) must be an http:// or https:// URL, got {:?}",
+                    self.redaction.ner_url
+                ));
+            }
+            if !(0.0..=1.0).contains(&self.redaction.ner_min_score) {
+                problems.push(format!(
+                    "redaction.ner_min_score (REDACTION_NER_MIN_SCORE) must be between 0 and 1, got {}",
+                    self.redaction.ner_min_score
+                ));
+            }
+        }
+        if !self.sentry.dsn.is_empty() {
+            if let Err(e) = crate::sentry::Dsn::parse(&self.sentry.dsn) {
+                problems.push(format!("sentry.dsn (SENTRY_DSN): {e}"));
+            }
+        }
+        if self.homekit.enabled && !cfg!(feature = "homekit") {
+            problems.push("homekit.enabled (HOMEKIT_ENABLED) needs a build with --features homekit".to_string());
+        }
+        if self.homekit.enabled && (self.homekit.port == 0 || self.homekit.port == self.server.port) {
+            problems.push("homekit.port (HOMEKIT_PORT) must be set, and differ from server.port".to_string());
+        }
+        if !self.homekit.setup_code.is_empty() && !valid_setup_code(&self.homekit.setup_code) {
+            problems.push(
+                "homekit.setup_code (HOMEKIT_SETUP_CODE) must be eight digits like 031-45-154, and not one like 111-11-111 or 123-45-678"
+                    .to_string(),
+            );
+        }
+        for (key, command) in &self.remote.keys {
+            if let Err(e) = command.parse::<crate::control::Command>() {
+                problems.push(format!("remote.keys.{key}: {e}"));
+            }
+        }
+        if self.lights.enabled
+            && self.lights.kind == "hue"
+            && (self.lights.username.is_empty() || self.lights.light.is_empty())
+        {
+            problems.push(
+                "lights.username (HUE_USERNAME) and lights.light (HUE_LIGHT) are required for a Hue light".to_string(),
+            );
+        }
+        if !matches!(self.logging.format.as_str(), "text" | "json" | "journald") {
+            problems.push(format!(
+                "logging.format (LOG_FORMAT) must be \"text\", \"json\" or \"journald\", got {:?}",
+                self.logging.format
+            ));
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    // Settings that are valid but probably not what the operator wants
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        // A node only captures; its hub talks to OpenAI
+        let uses_openai =
+            crate::routing::Category::ALL.into_iter().any(|category| crate::routing::uses_built_in(self, category));
+        if self.openai.api_key.is_empty() && uses_openai && !self.node.enabled {
+            warnings.push(
+                "no OpenAI API key (openai.api_key / OPENAI_API_KEY); recording will fail".to_string(),
+            );
+        }
+        if self.speakers.mode == "anonymous" && self.audio.save_dir.is_some() {
+            warnings.push(
+                "speakers.mode is anonymous but audio.save_dir keeps every chunk's audio, and with it their voices".to_string(),
+            );
+        }
+        if self.sync.enabled && self.sync.peers.is_empty() {
+            warnings.push("sync is on but sync.peers is empty; peers can pull from here, but nothing is pulled".to_string());
+        }
+        if self.privacy.mode == "local" && self.openai.base_url.contains("api.openai.com") && uses_openai && !self.node.enabled {
+            warnings.push(
+                "privacy.mode is local but openai.base_url is OpenAI's; point it at a local Whisper/GPT server or recording will fail"
+                    .to_string(),
+            );
+        }
+        if self.grpc.enabled && (self.tls.cert_path.is_some() || self.tls.self_signed) {
+            warnings.push("gRPC is served without TLS even though HTTPS is on".to_string());
+        }
+        warnings
+    }
+
+    // Copy that's safe to print or return from an endpoint
+    pub fn masked(&self) -> Config {
+        let mut copy = self.clone();
+        for (_, value) in copy.secrets_mut() {
+            if !value.is_empty() {
+                *value = "********".to_string();
+            }
+        }
+        copy
+    }
+
+    // Every setting that holds a credential, by dotted name
+    pub fn secrets_mut(&mut self) -> Vec<(String, &mut String)> {
+        let mut secrets = vec![
+            ("openai.api_key".to_string(), &mut self.openai.api_key),
+            ("login.password".to_string(), &mut self.login.password),
+            ("admin.token".to_string(), &mut self.admin.token),
+            ("mqtt.password".to_string(), &mut self.mqtt.password),
+            // The URL is the credential
+            ("alerts.slack_webhook_url".to_string(), &mut self.alerts.slack_webhook_url),
+            ("discord.bot_token".to_string(), &mut self.discord.bot_token),
+            ("telegram.bot_token".to_string(), &mut self.telegram.bot_token),
+            ("email.password".to_string(), &mut self.email.password),
+            ("calendar.password".to_string(), &mut self.calendar.password),
+            ("export.notion_token".to_string(), &mut self.export.notion_token),
+            ("hub.token".to_string(), &mut self.hub.token),
+            ("node.token".to_string(), &mut self.node.token),
+            ("sync.token".to_string(), &mut self.sync.token),
+            ("sentry.dsn".to_string(), &mut self.sentry.dsn),
+            ("homekit.setup_code".to_string(), &mut self.homekit.setup_code),
+            ("lights.username".to_string(), &mut self.lights.username),
+        ];
+        for (name, provider) in self.providers.iter_mut() {
+            secrets.push((format!("providers.{name}.api_key"), &mut provider.api_key));
+        }
+        for (name, token) in self.tokens.iter_mut() {
+            secrets.push((format!("tokens.{name}.token"), &mut token.token));
+        }
+        for (i, target) in self.webhooks.iter_mut().enumerate() {
+            secrets.push((format!("webhooks.{i}.secret"), &mut target.secret));
+        }
+        secrets
+    }
+}
+
+// --config / $SILENTNIGHT_CONFIG, else ./silentnight.toml if present
+pub fn config_file_path(cli: &Cli) -> Option<PathBuf> {
+    cli.config.clone().or_else(|| {
+        let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+        default.exists().then_some(default)
+    })
+}
+
+fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+// HAP setup codes: "XXX-XX-XXX", not all one digit and not
+// 123-45-678 or 876-54-321
+pub fn valid_setup_code(code: &str) -> bool {
+    let digits: String = code.chars().filter(|c| *c != '-').collect();
+    let shaped = code.len() == 10
+        && code.char_indices().all(|(i, c)| if i == 3 || i == 6 { c == '-' } else { c.is_ascii_digit() });
+    shaped && !digits.chars().all(|c| digits.starts_with(c)) && digits != "12345678" && digits != "87654321"
+}
+
+// "#rgb" or "#rrggbb", which is all the display pages accept
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn env_parsed<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+    match env_string(name) {
+        Some(raw) => raw
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("Environment variable {name}={raw:?} is not valid")),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,178 @@
+/////////////////////////////////////////////////////////////
+// src/control.rs
+//
+// Commands for controlling a source without the web UI (the
+// remote, see remote.rs):
+//   start, stop, toggle  - recording
+//   bookmark             - mark the moment (see bookmarks.rs)
+//   mute                 - pause capture without ending the
+//                          session; again to resume
+//   persona              - the next of openai.personas, then
+//                          back to openai.system_prompt
+//   persona:<name>       - that persona; "persona:" alone goes
+//                          back to system_prompt
+//   forget:<minutes>     - delete what the source recorded in
+//                          the last <minutes> (see forget.rs)
+// A persona change lasts until the next restart or reload, like
+// /admin/settings, and applies from the next chunk. Every command
+// goes in the audit log (see audit.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::ApiError;
+use crate::lifecycle::RecordingState;
+use crate::sessions::SourceSession;
+use crate::{audit, bookmarks, forget, sessions, AppState};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Start,
+    Stop,
+    Toggle,
+    Bookmark,
+    Mute,
+    // None = the next one
+    Persona(Option<String>),
+    // Minutes
+    Forget(u32),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Command, String> {
+        Ok(match s.trim() {
+            "start" => Command::Start,
+            "stop" => Command::Stop,
+            "toggle" => Command::Toggle,
+            "bookmark" => Command::Bookmark,
+            "mute" => Command::Mute,
+            "persona" => Command::Persona(None),
+            other => match (other.strip_prefix("persona:"), other.strip_prefix("forget:")) {
+                (Some(name), _) => Command::Persona(Some(name.trim().to_string())),
+                (_, Some(minutes)) => match minutes.trim().parse() {
+                    Ok(minutes) if (1..=forget::MAX_MINUTES).contains(&minutes) => Command::Forget(minutes),
+                    _ => return Err(format!("forget:<minutes> takes 1 to {} minutes, got {other:?}", forget::MAX_MINUTES)),
+                },
+                _ => {
+                    return Err(format!(
+                        "unknown command {other:?}; use start, stop, toggle, bookmark, mute, persona, persona:<name> or forget:<minutes>"
+                    ))
+                }
+            },
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Start => f.write_str("start"),
+            Command::Stop => f.write_str("stop"),
+            Command::Toggle => f.write_str("toggle"),
+            Command::Bookmark => f.write_str("bookmark"),
+            Command::Mute => f.write_str("mute"),
+            Command::Persona(None) => f.write_str("persona"),
+            Command::Persona(Some(name)) => write!(f, "persona:{name}"),
+            Command::Forget(minutes) => write!(f, "forget:{minutes}"),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// run
+//
+// Runs `command` on the source named `source_name`; Ok says
+// what happened, for logs and replies.
+/////////////////////////////////////////////////////////////
+// `via` is where it came from ("remote", "voice", ...), for the
+// audit log
+pub async fn run(app_data: &web::Data<AppState>, via: &str, source_name: &str, command: &Command) -> Result<String> {
+    let result = execute(app_data, source_name, command).await;
+    audit::record(app_data, via, via, &format!("{command} {source_name}"), result.is_ok()).await;
+    result
+}
+
+async fn execute(app_data: &web::Data<AppState>, source_name: &str, command: &Command) -> Result<String> {
+    if let Command::Persona(name) = command {
+        return switch_persona(app_data, name.as_deref()).await;
+    }
+    let Some(source) = app_data.sources.get(app_data, source_name).await else {
+        anyhow::bail!("there's no audio source named {source_name}");
+    };
+    let idle = matches!(*source.state.borrow(), RecordingState::Idle | RecordingState::Error { .. });
+    match command {
+        Command::Start | Command::Toggle if idle => {
+            start_source(app_data, source.clone())
+                .await
+                .map_err(|e| anyhow::anyhow!("couldn't start {source_name}: {e}"))?;
+            let session_id = source.session_id.borrow().clone().unwrap_or_default();
+            Ok(format!("recording {source_name} (session {session_id})"))
+        }
+        Command::Start => Ok(format!("{source_name} is already recording")),
+        Command::Stop | Command::Toggle if !idle => {
+            sessions::stop_source(app_data, &source).await;
+            Ok(format!("stopping {source_name}"))
+        }
+        Command::Stop | Command::Toggle => Ok(format!("{source_name} isn't recording")),
+        Command::Bookmark => {
+            bookmarks::add(app_data, &source).await?;
+            Ok(format!("bookmarked {source_name}"))
+        }
+        Command::Mute => {
+            if idle {
+                anyhow::bail!("{source_name} isn't recording");
+            }
+            let mut muted = false;
+            source.muted.send_modify(|m| {
+                *m = !*m;
+                muted = *m;
+            });
+            Ok(format!("{} {source_name}", if muted { "muted" } else { "unmuted" }))
+        }
+        Command::Forget(minutes) => {
+            let forgotten = forget::last(app_data, &source, *minutes).await?;
+            Ok(format!("forgot the last {minutes} min of {source_name}: {forgotten}"))
+        }
+        Command::Persona(_) => unreachable!("handled above"),
+    }
+}
+
+// Boxed, with its type spelled out: recording runs the pipeline,
+// whose voice commands (see voice.rs) come back to run
+fn start_source(app_data: &web::Data<AppState>, source: Arc<SourceSession>) -> BoxFuture<'_, Result<(), ApiError>> {
+    Box::pin(sessions::start_source(app_data, source))
+}
+
+// `name`, or the one after the current one
+async fn switch_persona(app_data: &AppState, name: Option<&str>) -> Result<String> {
+    let mut config = app_data.config.write().await;
+    let openai = &mut config.openai;
+    let next = match name {
+        Some(name) => {
+            if !name.is_empty() && !openai.personas.contains_key(name) {
+                anyhow::bail!("there's no persona named {name} in openai.personas");
+            }
+            name.to_string()
+        }
+        // "" (system_prompt), then each persona in order
+        None => {
+            let names: Vec<&String> = openai.personas.keys().collect();
+            match names.iter().position(|n| **n == openai.persona) {
+                Some(i) if i + 1 < names.len() => names[i + 1].clone(),
+                Some(_) => String::new(),
+                None => names.first().map(|n| n.to_string()).unwrap_or_default(),
+            }
+        }
+    };
+    openai.persona = next;
+    let shown = if openai.persona.is_empty() { "the default prompt" } else { openai.persona.as_str() };
+    tracing::info!(persona = %shown, "persona switched");
+    Ok(format!("switched to {shown}"))
+}
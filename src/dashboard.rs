@@ -0,0 +1,206 @@
+/////////////////////////////////////////////////////////////
+// src/dashboard.rs
+//
+// A page for operators to see at a glance why responses stopped
+// appearing:
+//   GET /dashboard?refresh=5  - per source: its state (with the
+//                               reason for an error or restart),
+//                               session, chunks, what waits in
+//                               front of each pipeline stage,
+//                               failed chunks, end-to-end latency
+//                               and last error; Whisper/GPT
+//                               requests running and waiting,
+//                               with a sparkline of their latest
+//                               times; the backlog, live log
+//                               clients, task restarts and uptime
+// It's the same numbers as /status and /metrics (see
+// telemetry.rs), rendered with templates/layout.html like the
+// session pages (see browse.rs). The browser reloads it every
+// `refresh` seconds (default 5, 0 = never), so it needs no
+// JavaScript. Errors have secrets masked (see secrets.rs).
+// Behind the login like the rest of the UI.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use utoipa::IntoParams;
+
+use crate::browse::{escape, page};
+use crate::i18n::{self, Strings};
+use crate::lifecycle::RecordingState;
+use crate::telemetry::LatencyStats;
+use crate::{backlog, secrets, AppState};
+
+const DEFAULT_REFRESH_SECS: u64 = 5;
+// Sparkline size, in pixels
+const SPARK_WIDTH: usize = 120;
+const SPARK_HEIGHT: u64 = 20;
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct DashboardQuery {
+    // Seconds between reloads; 0 = never, default 5
+    refresh: Option<u64>,
+}
+
+#[utoipa::path(
+    tag = "monitoring",
+    path = "/dashboard",
+    params(DashboardQuery),
+    responses((status = 200, description = "Pipeline health, reloading itself", content_type = "text/html")),
+)]
+#[get("/dashboard")]
+async fn dashboard(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    query: web::Query<DashboardQuery>,
+) -> HttpResponse {
+    let language = i18n::language(&app_data, &req).await;
+    let strings = i18n::strings(language);
+    let mut content = String::new();
+
+    let _ = writeln!(content, "  <h2>{}</h2>", escape(strings.audio_sources));
+    let _ = writeln!(
+        content,
+        "  <table>\n    <tr><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>",
+        escape(strings.source),
+        escape(strings.state_column),
+        escape(strings.session),
+        escape(strings.chunks),
+        escape(strings.queued),
+        escape(strings.failed_chunks),
+        escape(strings.latency),
+        escape(strings.last_error),
+    );
+    for source in app_data.sources.all(&app_data).await {
+        // Watch borrows must not be held across an await
+        let state = source.state.borrow().clone();
+        let session_id = source.session_id.borrow().clone();
+        let muted = *source.muted.borrow();
+        let last_error = secrets::redact_opt(source.last_error.lock().await.clone());
+        let pipeline = source.pipeline.status();
+        let queued: Vec<String> =
+            pipeline.stages().iter().map(|(stage, status)| format!("{stage} {}", status.queued)).collect();
+        let failed: u64 = pipeline.stages().iter().map(|(_, status)| status.failed).sum();
+        let _ = writeln!(
+            content,
+            "    <tr><td>{name}{muted}</td><td>{state}</td><td>{session}</td><td>{chunks}</td><td>{queued}</td><td>{failed}</td><td>{latency}</td><td>{error}</td></tr>",
+            name = escape(&source.name),
+            muted = if muted { format!(" ({})", escape(strings.muted)) } else { String::new() },
+            state = state_cell(&state),
+            session = escape(session_id.as_deref().unwrap_or("-")),
+            chunks = source.chunks_processed.load(Ordering::Relaxed),
+            queued = escape(&queued.join(", ")),
+            latency = latency_cell(strings, &pipeline.end_to_end, &source.pipeline.recent_end_to_end_ms()),
+            error = error_cell(strings, last_error.as_deref()),
+        );
+    }
+    content.push_str("  </table>\n");
+
+    let openai = app_data.openai_limiter.usage();
+    content.push_str("  <h2>OpenAI</h2>\n");
+    let requests = strings
+        .requests
+        .replace("{running}", &openai.in_flight.to_string())
+        .replace("{waiting}", &openai.queued.to_string())
+        .replace("{rejected}", &openai.rejected.to_string());
+    let _ = writeln!(content, "  <p class=\"meta\">{}</p>", escape(&requests));
+    content.push_str("  <table>\n");
+    for (api, stats) in [("whisper", &openai.whisper), ("gpt", &openai.gpt)] {
+        let _ = writeln!(
+            content,
+            "    <tr><td>{api}</td><td>{}</td></tr>",
+            latency_cell(strings, stats, &app_data.openai_limiter.recent_ms(api)),
+        );
+    }
+    content.push_str("  </table>\n");
+
+    let last_error = secrets::redact_opt(app_data.last_error.lock().await.clone());
+    let backlog = backlog::status(&app_data).await;
+    let backlog_summary = strings
+        .backlog_summary
+        .replace("{queued}", &backlog.queued.to_string())
+        .replace("{failed}", &backlog.failed.to_string());
+    let restarts: Vec<String> =
+        app_data.tasks.restarts().iter().map(|(kind, count)| format!("{kind} {count}")).collect();
+    let restarts = if restarts.is_empty() { "0".to_string() } else { restarts.join(", ") };
+    let uptime = (chrono::Utc::now() - app_data.started_at).to_std().unwrap_or_default();
+    let _ = writeln!(content, "  <h2>{}</h2>\n  <table>", escape(strings.server));
+    let rows = [
+        (strings.last_error, error_cell(strings, last_error.as_deref())),
+        (strings.backlog, format!("{}{}", escape(&backlog_summary), error_suffix(backlog.last_error.as_deref()))),
+        (strings.live_clients, app_data.events.subscribers().to_string()),
+        (strings.task_restarts, escape(&restarts)),
+        (strings.uptime, format!("{}h {:02}m", uptime.as_secs() / 3600, uptime.as_secs() / 60 % 60)),
+    ];
+    for (label, value) in rows {
+        let _ = writeln!(content, "    <tr><th>{}</th><td>{value}</td></tr>", escape(label));
+    }
+    content.push_str("  </table>\n");
+
+    let refresh = query.refresh.unwrap_or(DEFAULT_REFRESH_SECS);
+    let head = if refresh == 0 { String::new() } else { format!("  <meta http-equiv=\"refresh\" content=\"{refresh}\"/>") };
+    page(language, strings, strings.dashboard, strings.dashboard, &head, &content)
+}
+
+// The state's name, and why for an error or restart
+fn state_cell(state: &RecordingState) -> String {
+    match state {
+        RecordingState::Error { reason } => {
+            format!("<span class=\"error\">error: {}</span>", escape(&secrets::redact(reason)))
+        }
+        RecordingState::Restarting { reason, attempt } => {
+            format!("restarting ({attempt}): {}", escape(&secrets::redact(reason)))
+        }
+        state => state.name().to_string(),
+    }
+}
+
+fn error_cell(strings: &Strings, error: Option<&str>) -> String {
+    match error {
+        Some(error) => format!("<span class=\"error\">{}</span>", escape(error)),
+        None => escape(strings.no_error),
+    }
+}
+
+fn error_suffix(error: Option<&str>) -> String {
+    error.map(|e| format!(" <span class=\"error\">{}</span>", escape(e))).unwrap_or_default()
+}
+
+fn latency_cell(strings: &Strings, stats: &LatencyStats, recent_ms: &[u64]) -> String {
+    let summary = strings
+        .latency_summary
+        .replace("{avg}", &stats.avg_ms.to_string())
+        .replace("{max}", &stats.max_ms.to_string());
+    match sparkline(recent_ms) {
+        Some(line) => format!("{line} {}", escape(&summary)),
+        None => escape(&summary),
+    }
+}
+
+// The timings as a line, the highest at the top; None with
+// fewer than two
+fn sparkline(values: &[u64]) -> Option<String> {
+    if values.len() < 2 {
+        return None;
+    }
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    let step = SPARK_WIDTH as f64 / (values.len() - 1) as f64;
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let y = SPARK_HEIGHT as f64 - (*value as f64 / max as f64) * (SPARK_HEIGHT - 2) as f64 - 1.0;
+            format!("{:.1},{y:.1}", i as f64 * step)
+        })
+        .collect();
+    Some(format!(
+        "<svg class=\"spark\" width=\"{SPARK_WIDTH}\" height=\"{SPARK_HEIGHT}\" viewBox=\"0 0 {SPARK_WIDTH} {SPARK_HEIGHT}\"><polyline fill=\"none\" stroke=\"currentColor\" stroke-width=\"1.5\" points=\"{}\"/></svg>",
+        points.join(" ")
+    ))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(dashboard);
+}
@@ -0,0 +1,138 @@
+/////////////////////////////////////////////////////////////
+// src/dedup.rs
+//
+// Repeated transcripts ([dedup] enabled). Whisper sometimes
+// hears the same words twice: the end of one chunk again at the
+// start of the next, or the last sentence over and over when it
+// loops on a quiet chunk. Each transcript of a recording source
+// is compared with the one before it, ignoring case and
+// punctuation, before it goes to GPT:
+//   - one at least dedup.similarity (0.9) alike, by edit
+//     distance over the longer of the two, is a repeat: the
+//     chunk is dropped, so it's neither answered nor logged
+//   - otherwise dedup.overlap_words (3) or more words that end
+//     the one before and start this one are cut from it
+// Dropped chunks are counted as `duplicates` under the source's
+// pipeline on GET /status. The transcript before is forgotten
+// when the session ends. Settings apply from the next chunk.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+
+use crate::sessions::SourceSession;
+use crate::AppState;
+
+/////////////////////////////////////////////////////////////
+// filter
+//
+// What's new in `transcript` since the source's last one; None
+// if nothing is.
+/////////////////////////////////////////////////////////////
+pub async fn filter(app_data: &web::Data<AppState>, source: &SourceSession, transcript: String) -> Option<String> {
+    let settings = app_data.config.read().await.dedup.clone();
+    if !settings.enabled {
+        return Some(transcript);
+    }
+    let previous = source.last_transcript.lock().await.replace(transcript.clone());
+    let Some(previous) = previous else {
+        return Some(transcript);
+    };
+    let alike = similarity(&normalize(&previous), &normalize(&transcript));
+    if alike >= settings.similarity {
+        tracing::info!(similarity = alike, "dropped a repeated transcript");
+        return None;
+    }
+    let rest = without_overlap(&previous, &transcript, settings.overlap_words.max(1));
+    if rest.len() < transcript.len() {
+        tracing::info!(cut = %transcript[..transcript.len() - rest.len()].trim(), "cut words repeated from the last chunk");
+    }
+    (!rest.is_empty()).then(|| rest.to_string())
+}
+
+// Lowercase words of letters and digits, one space apart
+fn normalize(text: &str) -> String {
+    words(text).into_iter().map(|(word, _)| word).collect::<Vec<_>>().join(" ")
+}
+
+// Each word, normalized, with where it ends in `text`; words
+// with no letters or digits are left out
+fn words(text: &str) -> Vec<(String, usize)> {
+    let mut found = Vec::new();
+    let mut start = None;
+    for (at, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(at),
+            (true, Some(from)) => {
+                let word: String = text[from..at].chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect();
+                if !word.is_empty() {
+                    found.push((word, at));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    found
+}
+
+/////////////////////////////////////////////////////////////
+// similarity
+//
+// 1 minus the Levenshtein distance over the longer length, by
+// character; 1 for two empty strings.
+/////////////////////////////////////////////////////////////
+fn similarity(a: &str, b: &str) -> f64 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / longest as f64
+}
+
+// `transcript` after the longest run of at least `min_words`
+// words it starts with that `previous` ends with
+fn without_overlap<'a>(previous: &str, transcript: &'a str, min_words: usize) -> &'a str {
+    let before: Vec<String> = words(previous).into_iter().map(|(word, _)| word).collect();
+    let now = words(transcript);
+    let longest = before.len().min(now.len());
+    (min_words..=longest)
+        .rev()
+        .find(|&n| before[before.len() - n..].iter().zip(&now[..n]).all(|(a, (b, _))| a == b))
+        .map_or(transcript, |n| transcript[now[n - 1].1..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_ignores_case_and_punctuation() {
+        let a = normalize("Turn the lights off.");
+        assert_eq!(similarity(&a, &normalize("turn the lights off")), 1.0);
+        assert!(similarity(&a, &normalize("Turn the light off!")) > 0.9);
+        assert!(similarity(&a, &normalize("Is anyone there?")) < 0.5);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+    }
+
+    #[test]
+    fn overlapping_words_are_cut() {
+        let previous = "We should repaint the hallway before the guests arrive.";
+        assert_eq!(without_overlap(previous, "before the guests arrive, and buy wine.", 3), "and buy wine.");
+        // Too few words in common to be sure
+        assert_eq!(without_overlap(previous, "Arrive early, please.", 3), "Arrive early, please.");
+        assert_eq!(without_overlap(previous, "The guests arrive.", 3), "");
+        assert_eq!(without_overlap("", "Hello there.", 1), "Hello there.");
+    }
+}
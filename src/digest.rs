@@ -0,0 +1,213 @@
+/////////////////////////////////////////////////////////////
+// src/digest.rs
+//
+// Plain-text digests of what was recorded over a period, for
+// channels people read away from the wall display (telegram.rs,
+// email.rs):
+//
+//   Digest for 2026-10-15 08:00 to 2026-10-16 08:00
+//   2 sessions, 41 chunks
+//   - 20261015-140200 on default: 14:02, 33 min, 40 chunks
+//   - 20261015-191500-kitchen on kitchen: 19:15, 0 min, 1 chunk
+//
+//   <GPT's summary of the period's transcripts>
+//
+// Times are the server's local time. Built from
+// conversation_log.json (the default workspace's, see
+// workspaces.rs); only the newest MAX_TRANSCRIPT_CHARS of
+// transcript go to GPT, so a busy day stays one request.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::routing::Category;
+use crate::{pipeline, timezone, workspaces, AppState};
+
+const MAX_TRANSCRIPT_CHARS: usize = 12_000;
+const SUMMARY_MAX_TOKENS: u32 = 500;
+const SUMMARY_PROMPT: &str = "You are given the transcripts recorded over a period, in order. Write a digest for someone who wasn't there: the main topics, any decisions, and anything someone should follow up on. Keep it under 200 words.";
+const SESSION_MAX_TOKENS: u32 = 400;
+const SESSION_PROMPT: &str = "You are given the transcript of a recorded conversation, in order. Summarize it for people who weren't there: the main topics, any decisions, and anything someone should follow up on. Keep it under 150 words.";
+
+struct Session<'a> {
+    id: &'a str,
+    audio_source: &'a str,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    chunks: usize,
+}
+
+/////////////////////////////////////////////////////////////
+// build
+//
+// The digest of records from `since` up to now.
+/////////////////////////////////////////////////////////////
+pub async fn build(app_data: &AppState, since: DateTime<Utc>) -> Result<String> {
+    let until = Utc::now();
+    let records: Vec<(DateTime<Utc>, Value)> = read_log("")
+        .await?
+        .into_iter()
+        .filter_map(|record| {
+            let at = DateTime::parse_from_rfc3339(record["timestamp"].as_str()?).ok()?.with_timezone(&Utc);
+            (at >= since && at <= until).then_some((at, record))
+        })
+        .collect();
+
+    let mut text = format!("Digest for {} to {}\n", local(since, "%Y-%m-%d %H:%M"), local(until, "%Y-%m-%d %H:%M"));
+    let transcripts: Vec<&str> = records
+        .iter()
+        .filter(|(_, r)| r["source"] == "Microphone")
+        .filter_map(|(_, r)| r["text"].as_str())
+        .filter(|t| !t.trim().is_empty())
+        .collect();
+    if transcripts.is_empty() {
+        text.push_str("Nothing was recorded.");
+        return Ok(text);
+    }
+
+    // Sessions in the order they started; one-off chunks have none
+    let mut sessions: Vec<Session> = Vec::new();
+    for (at, record) in &records {
+        let Some(id) = record["session_id"].as_str() else { continue };
+        let chunk = usize::from(record["source"] == "Microphone");
+        match sessions.iter_mut().find(|s| s.id == id) {
+            Some(session) => {
+                session.last = *at;
+                session.chunks += chunk;
+            }
+            None => sessions.push(Session {
+                id,
+                audio_source: record["audio_source"].as_str().unwrap_or("?"),
+                first: *at,
+                last: *at,
+                chunks: chunk,
+            }),
+        }
+    }
+    text.push_str(&format!("{}, {}\n", plural(sessions.len(), "session"), plural(transcripts.len(), "chunk")));
+    for session in &sessions {
+        text.push_str(&format!(
+            "- {} on {}: {}, {} min, {}\n",
+            session.id,
+            session.audio_source,
+            local(session.first, "%H:%M"),
+            (session.last - session.first).num_minutes(),
+            plural(session.chunks, "chunk")
+        ));
+    }
+
+    // The newest transcripts that fit
+    let mut budget = MAX_TRANSCRIPT_CHARS;
+    let mut kept = Vec::new();
+    for transcript in transcripts.iter().rev() {
+        let len = transcript.chars().count() + 1;
+        if len > budget {
+            break;
+        }
+        budget -= len;
+        kept.push(*transcript);
+    }
+    kept.reverse();
+
+    let openai = app_data.config.read().await.openai.clone();
+    let messages = vec![
+        json!({ "role": "system", "content": SUMMARY_PROMPT }),
+        json!({ "role": "user", "content": kept.join("\n") }),
+    ];
+    match pipeline::chat_completion(app_data, &openai, Category::Summaries, messages, SUMMARY_MAX_TOKENS).await {
+        Ok(summary) => text.push_str(&format!("\n{summary}")),
+        Err(e) => {
+            tracing::warn!(error = %e, "GPT couldn't summarize the digest");
+            text.push_str(&format!("\n(No summary: {e})"));
+        }
+    }
+    Ok(text)
+}
+
+/////////////////////////////////////////////////////////////
+// session
+//
+// One recording session, for the summaries posted when it
+// stops (Discord, email). Err only if the log can't be read;
+// without GPT the summary says why it's missing.
+/////////////////////////////////////////////////////////////
+pub struct SessionSummary {
+    // First and last record; None if nothing was logged
+    pub span: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    pub chunks: usize,
+    pub summary: String,
+}
+
+pub async fn session(app_data: &AppState, session_id: &str) -> Result<SessionSummary> {
+    let records = session_records(session_id).await?;
+    let transcripts: Vec<&str> = records
+        .iter()
+        .filter(|r| r["source"] == "Microphone")
+        .filter_map(|r| r["text"].as_str())
+        .filter(|text| !text.trim().is_empty())
+        .collect();
+    let times: Vec<DateTime<Utc>> = records
+        .iter()
+        .filter_map(|r| DateTime::parse_from_rfc3339(r["timestamp"].as_str()?).ok())
+        .map(|at| at.with_timezone(&Utc))
+        .collect();
+    let span = times.first().zip(times.last()).map(|(first, last)| (*first, *last));
+    let chunks = transcripts.len();
+    if transcripts.is_empty() {
+        return Ok(SessionSummary { span, chunks, summary: "Nothing was transcribed.".to_string() });
+    }
+
+    let openai = app_data.config.read().await.openai.clone();
+    let messages = vec![
+        json!({ "role": "system", "content": SESSION_PROMPT }),
+        json!({ "role": "user", "content": transcripts.join("\n") }),
+    ];
+    let summary = match pipeline::chat_completion(app_data, &openai, Category::Summaries, messages, SESSION_MAX_TOKENS).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            tracing::warn!(error = %e, "GPT couldn't summarize the session");
+            format!("(No summary: {e})")
+        }
+    };
+    Ok(SessionSummary { span, chunks, summary })
+}
+
+// Every record in `workspace`'s log (see workspaces.rs), oldest
+// first; none before the first one is written
+pub(crate) async fn read_log(workspace: &str) -> Result<Vec<Value>> {
+    read_file(&workspaces::log_path(workspace)).await
+}
+
+// A session's records, from whichever workspace's log has them
+pub(crate) async fn session_records(session_id: &str) -> Result<Vec<Value>> {
+    for path in workspaces::log_paths() {
+        let records: Vec<Value> = read_file(&path).await?.into_iter().filter(|r| r["session_id"] == session_id).collect();
+        if !records.is_empty() {
+            return Ok(records);
+        }
+    }
+    Ok(Vec::new())
+}
+
+async fn read_file(path: &str) -> Result<Vec<Value>> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {path}")),
+    };
+    Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+fn local(at: DateTime<Utc>, format: &str) -> String {
+    timezone::local(at).format(format).to_string()
+}
+
+fn plural(n: usize, what: &str) -> String {
+    if n == 1 {
+        format!("1 {what}")
+    } else {
+        format!("{n} {what}s")
+    }
+}
@@ -0,0 +1,398 @@
+/////////////////////////////////////////////////////////////
+// src/discord.rs
+//
+// Discord bot ([discord] enabled). It posts to one channel:
+//   - a summary of each recording session when it stops (GPT's
+//     digest of the session's transcripts, with when it ran)
+//   - with post_responses, every GPT response as it comes in,
+//     except the "Listening..." that means GPT had nothing to say
+// and answers the /silentnight slash command:
+//   /silentnight start [source]
+//   /silentnight stop [source]
+//
+// The command is registered when the server starts (in
+// guild_id's server if set, which takes effect at once; global
+// commands can take a while to show up). Discord delivers it to
+//   POST /discord/interactions
+// which must be reachable from the internet over HTTPS; set it
+// as the application's Interactions Endpoint URL. Requests are
+// checked against the application's Ed25519 public key, so the
+// endpoint skips the web UI login. Who may use the command is
+// up to the Discord server's Integrations settings.
+//
+// Messages never ping anyone, whatever the transcript says.
+// Settings are restart-only; a failed post is logged and
+// dropped.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use anyhow::{Context, Result};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::config::{DiscordSettings, DEFAULT_SOURCE};
+use crate::error::ApiError;
+use crate::lifecycle::RecordingState;
+use crate::{audit, digest, hex, pipeline, privacy, sessions, timezone, AppState};
+
+const COMMAND: &str = "silentnight";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest message Discord accepts
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+// Interaction and response types from Discord's API
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE: u8 = 4;
+// Only the person who ran the command sees the reply
+const EPHEMERAL: u32 = 1 << 6;
+
+/////////////////////////////////////////////////////////////
+// Discord
+/////////////////////////////////////////////////////////////
+pub struct Discord {
+    client: reqwest::Client,
+}
+
+impl Default for Discord {
+    fn default() -> Self {
+        Discord { client: privacy::client() }
+    }
+}
+
+impl Discord {
+    async fn post_message(&self, settings: &DiscordSettings, content: &str) -> Result<()> {
+        let url = format!("{}/channels/{}/messages", settings.api_url.trim_end_matches('/'), settings.channel_id);
+        let body = json!({
+            "content": truncate(content, MAX_MESSAGE_CHARS),
+            "allowed_mentions": { "parse": [] },
+        });
+        let resp = self
+            .client
+            .post(url)
+            .timeout(REQUEST_TIMEOUT)
+            .header("Authorization", format!("Bot {}", settings.bot_token))
+            .json(&body)
+            .send()
+            .await
+            .context("Discord unreachable")?;
+        check_status(resp).await
+    }
+
+    // Overwrites the application's commands with /silentnight
+    async fn register_command(&self, settings: &DiscordSettings) -> Result<()> {
+        let api = settings.api_url.trim_end_matches('/');
+        let url = if settings.guild_id.is_empty() {
+            format!("{api}/applications/{}/commands", settings.application_id)
+        } else {
+            format!("{api}/applications/{}/guilds/{}/commands", settings.application_id, settings.guild_id)
+        };
+        let source = json!({
+            "type": 3,
+            "name": "source",
+            "description": "Audio source (default: \"default\")",
+            "required": false,
+        });
+        let commands = json!([{
+            "name": COMMAND,
+            "description": "Control the SilentNight recorder",
+            "options": [
+                { "type": 1, "name": "start", "description": "Start recording", "options": [source] },
+                { "type": 1, "name": "stop", "description": "Stop recording", "options": [source] },
+            ],
+        }]);
+        let resp = self
+            .client
+            .put(url)
+            .timeout(REQUEST_TIMEOUT)
+            .header("Authorization", format!("Bot {}", settings.bot_token))
+            .json(&commands)
+            .send()
+            .await
+            .context("Discord unreachable")?;
+        check_status(resp).await
+    }
+}
+
+async fn check_status(resp: reqwest::Response) -> Result<()> {
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Discord replied {status}: {body}");
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Registers the slash command in the background, so a Discord
+// outage doesn't hold up startup.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: DiscordSettings) {
+    tracing::info!(channel_id = %settings.channel_id, "Discord bot enabled");
+    let registering = app_data.clone();
+    let register = async move {
+        match registering.discord.register_command(&settings).await {
+            Ok(()) => tracing::info!("registered the /{COMMAND} command"),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "couldn't register the /{COMMAND} command"),
+        }
+    };
+    app_data.tasks.spawn("discord", register.instrument(tracing::info_span!("discord")));
+}
+
+/////////////////////////////////////////////////////////////
+// response
+//
+// A new GPT response; posted in the background if
+// post_responses is on.
+/////////////////////////////////////////////////////////////
+pub async fn response(app_data: &web::Data<AppState>, audio_source: &str, text: &str) {
+    let settings = app_data.config.read().await.discord.clone();
+    if !settings.enabled || !settings.post_responses || pipeline::is_listening(text) {
+        return;
+    }
+    let content = format!("**{}**: {}", markdown_escape(audio_source), markdown_escape(text));
+    post_in_background(app_data, settings, content, "response");
+}
+
+/////////////////////////////////////////////////////////////
+// session_stopped
+//
+// Posts the summary of a session that just ended, in the
+// background.
+/////////////////////////////////////////////////////////////
+pub async fn session_stopped(app_data: &web::Data<AppState>, audio_source: &str, session_id: &str) {
+    let settings = app_data.config.read().await.discord.clone();
+    if !settings.enabled {
+        return;
+    }
+    let span = tracing::info_span!(parent: None, "discord", source = %audio_source, session_id = %session_id);
+    let (data, audio_source, session_id) = (app_data.clone(), audio_source.to_string(), session_id.to_string());
+    let post = async move {
+        let content = match session_summary(&data, &audio_source, &session_id).await {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!(error = %format!("{e:#}"), "couldn't summarize the session");
+                return;
+            }
+        };
+        match data.discord.post_message(&settings, &content).await {
+            Ok(()) => tracing::debug!("posted the session summary to Discord"),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "Discord post failed"),
+        }
+    };
+    app_data.tasks.spawn("discord", post.instrument(span));
+}
+
+// Err only if the log can't be read
+async fn session_summary(app_data: &AppState, audio_source: &str, session_id: &str) -> Result<String> {
+    let session = digest::session(app_data, session_id).await?;
+    let mut heading = format!("**Session {session_id}** on {}", markdown_escape(audio_source));
+    if let Some((first, last)) = session.span {
+        heading.push_str(&format!(
+            ", {}, {} min, {} chunks",
+            timezone::local(first).format("%Y-%m-%d %H:%M (UTC%:z)"),
+            (last - first).num_minutes(),
+            session.chunks
+        ));
+    }
+    Ok(format!("{heading}\n{}", session.summary))
+}
+
+fn post_in_background(app_data: &web::Data<AppState>, settings: DiscordSettings, content: String, what: &'static str) {
+    let discord = app_data.clone();
+    let post = async move {
+        match discord.discord.post_message(&settings, &content).await {
+            Ok(()) => tracing::debug!(what, "posted to Discord"),
+            Err(e) => tracing::warn!(what, error = %format!("{e:#}"), "Discord post failed"),
+        }
+    };
+    app_data.tasks.spawn("discord", post.instrument(tracing::info_span!(parent: None, "discord")));
+}
+
+/////////////////////////////////////////////////////////////
+// POST /discord/interactions
+//
+// Discord's PING check and the /silentnight command. Anything
+// without a valid signature gets a 401, which Discord also
+// tests for before it accepts the endpoint.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(default)]
+    data: Option<CommandData>,
+    #[serde(default)]
+    guild_id: Option<String>,
+    // Set in servers; `user` in DMs
+    #[serde(default)]
+    member: Option<Value>,
+    #[serde(default)]
+    user: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Deserialize)]
+struct CommandOption {
+    name: String,
+    #[serde(default)]
+    value: Option<Value>,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[utoipa::path(
+    tag = "integrations",
+    params(
+        ("X-Signature-Ed25519" = String, Header, description = "Discord's signature of timestamp + body"),
+        ("X-Signature-Timestamp" = String, Header, description = "Signed along with the body"),
+    ),
+    request_body(content = String, description = "A Discord interaction", content_type = "application/json"),
+    responses(
+        (status = 200, description = "The interaction response"),
+        (status = 401, description = "Missing or bad signature", body = ErrorBody),
+        (status = 404, description = "The Discord bot is off (code discord_disabled)", body = ErrorBody),
+    )
+)]
+#[post("/discord/interactions")]
+async fn interactions(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_data: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = app_data.config.read().await.discord.clone();
+    if !settings.enabled {
+        return Err(ApiError::not_found("discord_disabled", "The Discord bot is not enabled"));
+    }
+    if !signature_ok(&req, &body, &settings.public_key) {
+        tracing::info!("rejecting Discord interaction with a bad signature");
+        return Err(ApiError::unauthorized("Invalid request signature"));
+    }
+    let interaction: Interaction = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request("invalid_interaction", "Not a Discord interaction").with_detail(e))?;
+
+    match interaction.kind {
+        PING => Ok(HttpResponse::Ok().json(json!({ "type": PONG }))),
+        APPLICATION_COMMAND => {
+            let reply = command(&app_data, &settings, &interaction).await;
+            Ok(HttpResponse::Ok().json(json!({
+                "type": CHANNEL_MESSAGE,
+                "data": { "content": reply, "flags": EPHEMERAL, "allowed_mentions": { "parse": [] } },
+            })))
+        }
+        other => Err(ApiError::bad_request(
+            "unsupported_interaction",
+            format!("Interaction type {other} isn't handled"),
+        )),
+    }
+}
+
+// The reply to show whoever ran the command
+async fn command(app_data: &web::Data<AppState>, settings: &DiscordSettings, interaction: &Interaction) -> String {
+    if !settings.guild_id.is_empty() && interaction.guild_id.as_deref() != Some(settings.guild_id.as_str()) {
+        return "This bot only takes commands in its own server.".to_string();
+    }
+    let Some(data) = interaction.data.as_ref().filter(|d| d.name == COMMAND) else {
+        return "Unknown command.".to_string();
+    };
+    let Some(sub) = data.options.first() else {
+        return format!("Use /{COMMAND} start or /{COMMAND} stop.");
+    };
+    let name = sub
+        .options
+        .iter()
+        .find(|o| o.name == "source")
+        .and_then(|o| o.value.as_ref()?.as_str())
+        .unwrap_or(DEFAULT_SOURCE);
+    let user = interaction
+        .member
+        .as_ref()
+        .and_then(|m| m.get("user"))
+        .or(interaction.user.as_ref())
+        .and_then(|u| u["username"].as_str())
+        .unwrap_or("someone");
+
+    let Some(source) = app_data.sources.get(app_data, name).await else {
+        return format!("There's no audio source named {}.", markdown_escape(name));
+    };
+    let shown = markdown_escape(name);
+    match sub.name.as_str() {
+        "start" => {
+            tracing::info!(source = name, user, "start requested on Discord");
+            let started = sessions::start_source(app_data, source.clone()).await;
+            audit::record(app_data, "discord", &format!("discord:{user}"), &format!("start {name}"), started.is_ok()).await;
+            match started {
+                Ok(()) => {
+                    let session_id = source.session_id.borrow().clone().unwrap_or_default();
+                    format!("Recording {shown} (session {session_id}).")
+                }
+                Err(e) => format!("Couldn't start {shown}: {e}"),
+            }
+        }
+        "stop" => {
+            tracing::info!(source = name, user, "stop requested on Discord");
+            if matches!(*source.state.borrow(), RecordingState::Idle | RecordingState::Error { .. }) {
+                return format!("{shown} isn't recording.");
+            }
+            sessions::stop_source(app_data, &source).await;
+            audit::record(app_data, "discord", &format!("discord:{user}"), &format!("stop {name}"), true).await;
+            format!("Stopping {shown}; the summary follows once the last chunk is done.")
+        }
+        other => format!("Unknown subcommand {}.", markdown_escape(other)),
+    }
+}
+
+fn signature_ok(req: &HttpRequest, body: &[u8], public_key_hex: &str) -> bool {
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+    let (Some(signature), Some(timestamp)) = (header("X-Signature-Ed25519"), header("X-Signature-Timestamp")) else {
+        return false;
+    };
+    let (Some(signature), Some(key)) = (hex::decode(signature), public_key(public_key_hex)) else {
+        return false;
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    UnparsedPublicKey::new(&ED25519, key).verify(&message, &signature).is_ok()
+}
+
+// The application's public key, if `key` is one (32 bytes)
+pub fn public_key(key: &str) -> Option<Vec<u8>> {
+    hex::decode(key).filter(|key| key.len() == 32)
+}
+
+// Transcripts shouldn't turn into formatting
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '_' | '~' | '`' | '|' | '>' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// At most `max_chars`, with "…" if it was cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars - 1).collect();
+    format!("{kept}…")
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(interactions);
+}
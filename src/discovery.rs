@@ -0,0 +1,235 @@
+/////////////////////////////////////////////////////////////
+// src/discovery.rs
+//
+// mDNS / zeroconf: advertises this server on the LAN as
+// _silentnight._tcp so companion apps can find the Pi without
+// hardcoding its IP, and browses for other instances.
+//
+//   GET /discover  - other SilentNight servers seen on the LAN
+//
+// TXT records: version, tls ("true"/"false"), path ("/").
+//
+// Settings ([discovery] or env):
+//   enabled       (DISCOVERY_ENABLED)  default true
+//   instance_name                      default: the hostname
+// Both need a restart to change. mDNS itself is behind the
+// "mdns" cargo feature (on by default); without it /discover
+// just reports discovery as disabled.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse, Responder};
+#[cfg(feature = "mdns")]
+use anyhow::{Context, Result};
+#[cfg(feature = "mdns")]
+use chrono::Utc;
+#[cfg(feature = "mdns")]
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+use utoipa::ToSchema;
+
+use crate::config::DiscoverySettings;
+use crate::tasks::TaskManager;
+use crate::AppState;
+
+#[cfg(feature = "mdns")]
+const SERVICE_TYPE: &str = "_silentnight._tcp.local.";
+
+/////////////////////////////////////////////////////////////
+// Peer
+//
+// Another instance found via mDNS.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema, Clone)]
+pub(crate) struct Peer {
+    name: String,
+    host: String,
+    addresses: Vec<String>,
+    port: u16,
+    tls: bool,
+    version: Option<String>,
+    // Ready-to-open URL using the first address
+    url: Option<String>,
+    // RFC 3339
+    last_seen: String,
+}
+
+/////////////////////////////////////////////////////////////
+// Discovery
+/////////////////////////////////////////////////////////////
+pub struct Discovery {
+    // Kept alive for as long as we advertise; None = disabled
+    #[cfg(feature = "mdns")]
+    daemon: Option<ServiceDaemon>,
+    instance_name: String,
+    // Keyed by mDNS full name
+    peers: Arc<AsyncMutex<HashMap<String, Peer>>>,
+}
+
+impl Discovery {
+    /////////////////////////////////////////////////////////
+    // start
+    //
+    // Registers our service and starts browsing. mDNS is a
+    // nice-to-have, so failures are logged and discovery is
+    // just switched off.
+    /////////////////////////////////////////////////////////
+    #[cfg_attr(not(feature = "mdns"), allow(unused_variables))]
+    pub fn start(settings: &DiscoverySettings, port: u16, tls: bool, tasks: &TaskManager) -> Discovery {
+        let instance_name = if settings.instance_name.is_empty() {
+            hostname()
+        } else {
+            settings.instance_name.clone()
+        };
+        #[allow(unused_mut)]
+        let mut discovery = Discovery {
+            #[cfg(feature = "mdns")]
+            daemon: None,
+            instance_name,
+            peers: Arc::new(AsyncMutex::new(HashMap::new())),
+        };
+        if !settings.enabled {
+            return discovery;
+        }
+
+        #[cfg(not(feature = "mdns"))]
+        tracing::info!("mDNS discovery not built in (cargo feature \"mdns\")");
+        #[cfg(feature = "mdns")]
+        match discovery.advertise_and_browse(port, tls, tasks) {
+            Ok(daemon) => {
+                tracing::info!(name = %discovery.instance_name, service = SERVICE_TYPE, "advertising via mDNS");
+                discovery.daemon = Some(daemon);
+            }
+            Err(e) => {
+                tracing::warn!(error = %format!("{e:#}"), "mDNS discovery disabled");
+            }
+        }
+        discovery
+    }
+
+    #[cfg(feature = "mdns")]
+    fn advertise_and_browse(&self, port: u16, tls: bool, tasks: &TaskManager) -> Result<ServiceDaemon> {
+        let daemon = ServiceDaemon::new().context("Failed to start the mDNS daemon")?;
+
+        let host_name = format!("{}.local.", hostname());
+        let properties = [
+            ("version", env!("CARGO_PKG_VERSION")),
+            ("tls", if tls { "true" } else { "false" }),
+            ("path", "/"),
+        ];
+        let service = ServiceInfo::new(SERVICE_TYPE, &self.instance_name, &host_name, "", port, &properties[..])
+            .context("Invalid mDNS service info")?
+            .enable_addr_auto();
+        let own_fullname = service.get_fullname().to_string();
+        daemon.register(service).context("Failed to register the mDNS service")?;
+
+        let events = daemon.browse(SERVICE_TYPE).context("Failed to browse for mDNS peers")?;
+        let peers = self.peers.clone();
+        let shutdown = tasks.token();
+        tasks.spawn("discovery", async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv_async() => match event {
+                        Ok(event) => event,
+                        Err(_) => break,
+                    },
+                    _ = shutdown.cancelled() => break,
+                };
+                match event {
+                    ServiceEvent::ServiceResolved(info) if info.get_fullname() != own_fullname => {
+                        let peer = peer_from_info(&info);
+                        tracing::debug!(name = %peer.name, url = ?peer.url, "found mDNS peer");
+                        peers.lock().await.insert(info.get_fullname().to_string(), peer);
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        let removed = peers.lock().await.remove(&fullname);
+                        if removed.is_some() {
+                            tracing::debug!(fullname = %fullname, "mDNS peer went away");
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(daemon)
+    }
+
+    fn advertising(&self) -> bool {
+        #[cfg(feature = "mdns")]
+        return self.daemon.is_some();
+        #[cfg(not(feature = "mdns"))]
+        false
+    }
+}
+
+#[cfg(feature = "mdns")]
+fn peer_from_info(info: &ServiceInfo) -> Peer {
+    let tls = info.get_property_val_str("tls") == Some("true");
+    let mut addresses: Vec<String> = info.get_addresses().iter().map(|a| a.to_string()).collect();
+    // IPv4 first; they're what most people will want to click
+    addresses.sort_by_key(|a| (a.contains(':'), a.clone()));
+
+    let scheme = if tls { "https" } else { "http" };
+    let url = addresses.first().map(|addr| {
+        if addr.contains(':') {
+            format!("{scheme}://[{addr}]:{}/", info.get_port())
+        } else {
+            format!("{scheme}://{addr}:{}/", info.get_port())
+        }
+    });
+
+    let fullname = info.get_fullname();
+    Peer {
+        name: fullname.strip_suffix(&format!(".{SERVICE_TYPE}")).unwrap_or(fullname).to_string(),
+        host: info.get_hostname().trim_end_matches('.').to_string(),
+        addresses,
+        port: info.get_port(),
+        tls,
+        version: info.get_property_val_str("version").map(str::to_string),
+        url,
+        last_seen: Utc::now().to_rfc3339(),
+    }
+}
+
+// This machine's hostname, for the default instance name
+pub(crate) fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "silentnight".to_string())
+}
+
+/////////////////////////////////////////////////////////////
+// GET /discover
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DiscoverResponse {
+    // False if discovery is disabled or mDNS couldn't start
+    enabled: bool,
+    // How we advertise ourselves
+    instance_name: String,
+    peers: Vec<Peer>,
+}
+
+#[utoipa::path(tag = "monitoring", responses((status = 200, description = "Other SilentNight instances on the LAN", body = DiscoverResponse)))]
+#[get("/discover")]
+async fn discover(app_data: web::Data<AppState>) -> impl Responder {
+    let discovery = &app_data.discovery;
+    let mut peers: Vec<Peer> = discovery.peers.lock().await.values().cloned().collect();
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    HttpResponse::Ok().json(DiscoverResponse {
+        enabled: discovery.advertising(),
+        instance_name: discovery.instance_name.clone(),
+        peers,
+    })
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(discover);
+}
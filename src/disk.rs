@@ -0,0 +1,216 @@
+/////////////////////////////////////////////////////////////
+// src/disk.rs
+//
+// Free space where the server writes. Every disk.check_secs (30)
+// the volume of the working directory (the log and the other
+// .json files, "data") and that of audio.save_dir ("audio") are
+// measured, and each is:
+//   ok       - at least disk.low_mb (1024) free
+//   low      - less: a warning is logged
+//   critical - less than disk.critical_mb (256): for "audio",
+//              chunks stop being saved to save_dir. They're
+//              still transcribed, answered and logged, and saving
+//              picks up again once there's room.
+// A chunk that doesn't fit on the disk pauses saving too, until
+// the next check finds room, rather than failing the chunk.
+// When a volume's level changes, /live_log gets an SSE event
+// named "disk_space":
+//
+//   {"volume":"audio","path":"recordings","free_bytes":...,
+//    "total_bytes":...,"level":"critical","archiving_paused":true}
+//
+// GET /status shows the volumes under `disk`, and /metrics has
+// them too. Only Unix systems are measured.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::DiskSettings;
+use crate::AppState;
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct Volume {
+    // "data" (the working directory) or "audio" (audio.save_dir)
+    pub volume: String,
+    pub path: String,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+    // "ok", "low" or "critical"
+    pub level: String,
+}
+
+#[derive(Default)]
+pub struct Disk {
+    // As last measured
+    volumes: Mutex<Vec<Volume>>,
+    // Chunks aren't being saved to audio.save_dir
+    archiving_paused: AtomicBool,
+    // Chunks not saved while it was
+    skipped: AtomicU64,
+}
+
+impl Disk {
+    pub fn archiving_paused(&self) -> bool {
+        self.archiving_paused.load(Ordering::Relaxed)
+    }
+
+    // A chunk wasn't saved
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A save ran out of space; paused until the next check finds room
+    pub fn out_of_space(&self) {
+        self.skipped();
+        if !self.archiving_paused.swap(true, Ordering::Relaxed) {
+            tracing::warn!("disk full, audio archiving paused");
+        }
+    }
+
+    pub(crate) fn volumes(&self) -> Vec<Volume> {
+        self.volumes.lock().unwrap().clone()
+    }
+
+    pub fn skipped_total(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+// Whether a failed write means the disk (or quota) is full
+pub fn is_full(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DiskStatus {
+    volumes: Vec<Volume>,
+    archiving_paused: bool,
+    // Chunks not saved to audio.save_dir for lack of space
+    archive_skipped: u64,
+}
+
+pub(crate) fn status(app_data: &AppState) -> DiskStatus {
+    DiskStatus {
+        volumes: app_data.disk.volumes(),
+        archiving_paused: app_data.disk.archiving_paused(),
+        archive_skipped: app_data.disk.skipped_total(),
+    }
+}
+
+// Free and total bytes of the volume `path` is on
+#[cfg(unix)]
+fn space(path: &Path) -> io::Result<(u64, u64)> {
+    let stats = rustix::fs::statvfs(path)?;
+    Ok((stats.f_bavail * stats.f_frsize, stats.f_blocks * stats.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn space(_path: &Path) -> io::Result<(u64, u64)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space is only measured on Unix"))
+}
+
+fn level(free_bytes: u64, settings: &DiskSettings) -> &'static str {
+    if free_bytes < settings.critical_mb.saturating_mul(MB) {
+        "critical"
+    } else if free_bytes < settings.low_mb.saturating_mul(MB) {
+        "low"
+    } else {
+        "ok"
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// measure
+//
+// The volumes the server writes to, as they are now.
+/////////////////////////////////////////////////////////////
+async fn measure(app_data: &AppState) -> Vec<Volume> {
+    let (settings, save_dir) = {
+        let config = app_data.config.read().await;
+        (config.disk.clone(), config.audio.save_dir.clone())
+    };
+    let mut paths = vec![("data", ".".to_string())];
+    paths.extend(save_dir.map(|dir| ("audio", dir)));
+    let mut volumes = Vec::new();
+    for (volume, path) in paths {
+        // save_dir may not be made yet: it'll be on its parent's volume
+        let existing = Path::new(&path).ancestors().find(|p| p.exists()).filter(|p| !p.as_os_str().is_empty());
+        match space(existing.unwrap_or(Path::new("."))) {
+            Ok((free_bytes, total_bytes)) => volumes.push(Volume {
+                volume: volume.to_string(),
+                path,
+                free_bytes,
+                total_bytes,
+                level: level(free_bytes, &settings).to_string(),
+            }),
+            Err(e) => tracing::debug!(path = %path, error = %e, "couldn't measure free space"),
+        }
+    }
+    volumes
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Measures the volumes every disk.check_secs, pausing or
+// resuming audio archiving and announcing level changes.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "disk");
+    app_data.tasks.spawn("disk", watch(app_data.clone()).instrument(span));
+}
+
+async fn watch(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    loop {
+        check(&app_data).await;
+        let secs = app_data.config.read().await.disk.check_secs;
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(secs)) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+async fn check(app_data: &AppState) {
+    let volumes = measure(app_data).await;
+    let critical = volumes.iter().any(|v| v.volume == "audio" && v.level == "critical");
+    if app_data.disk.archiving_paused.swap(critical, Ordering::Relaxed) != critical {
+        if critical {
+            tracing::warn!("disk space critically low, audio archiving paused");
+        } else {
+            tracing::info!("disk space recovered, audio archiving resumed");
+        }
+    }
+    let previous = std::mem::replace(&mut *app_data.disk.volumes.lock().unwrap(), volumes.clone());
+    for volume in &volumes {
+        let was = previous.iter().find(|v| v.volume == volume.volume).map_or("ok", |v| v.level.as_str());
+        if was != volume.level {
+            let free_mb = volume.free_bytes / MB;
+            if volume.level == "ok" {
+                tracing::info!(volume = %volume.volume, path = %volume.path, free_mb, "disk space ok again");
+            } else {
+                tracing::warn!(volume = %volume.volume, path = %volume.path, free_mb, level = %volume.level, "disk space low");
+            }
+            let event = serde_json::json!({
+                "volume": volume.volume,
+                "path": volume.path,
+                "free_bytes": volume.free_bytes,
+                "total_bytes": volume.total_bytes,
+                "level": volume.level,
+                "archiving_paused": critical,
+            });
+            app_data.events.publish_notice("disk_space", event.to_string());
+        }
+    }
+}
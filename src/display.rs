@@ -0,0 +1,187 @@
+/////////////////////////////////////////////////////////////
+// src/display.rs
+//
+// How each wall display or monitor shows the conversation, so
+// rooms can differ without editing static/index.html:
+//   GET /display/profile?display=kitchen  - its profile
+//   PUT /display/profile?display=kitchen  - replace it (same
+//                                           access as /admin)
+// A profile has the font size, colors, layout
+// ("transcript_and_response" or "response_only") and how it
+// refreshes ("live" over /live_log, or "poll" /transcript every
+// poll_secs). Profiles come from [displays.<name>]; a display
+// without one gets [displays.default], or the built-in green on
+// black. Leaving out ?display means "default".
+//
+// The web UI loads the profile named in its own ?display=, and a
+// PUT is published to it as a "display" event on /live_log so it
+// restyles at once. Other screens (an e-ink panel, a Pi driving
+// an LCD) can read the same profile. Like /admin/settings, a PUT
+// lasts until the next restart or reload.
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{get, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::{Config, DisplayProfile};
+use crate::error::ApiError;
+use crate::{admin, AppState};
+
+// The profile used when ?display is left out
+pub const DEFAULT_DISPLAY: &str = "default";
+
+// The profile `display` gets
+pub fn profile(config: &Config, display: &str) -> DisplayProfile {
+    config
+        .displays
+        .get(display)
+        .or_else(|| config.displays.get(DEFAULT_DISPLAY))
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct DisplayQuery {
+    // Which display; default "default"
+    display: Option<String>,
+}
+
+impl DisplayQuery {
+    fn name(&self) -> String {
+        self.display.clone().filter(|d| !d.is_empty()).unwrap_or_else(|| DEFAULT_DISPLAY.to_string())
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// ProfileView / ProfileBody
+//
+// A profile as GET shows it, and what a PUT replaces it with;
+// fields left out of a PUT get their defaults, unknown ones are
+// rejected.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ProfileView {
+    display: String,
+    font_size_px: u32,
+    // CSS colors, "#rgb" or "#rrggbb"
+    foreground: String,
+    background: String,
+    // "transcript_and_response" or "response_only"
+    layout: String,
+    // "live" or "poll"
+    refresh: String,
+    poll_secs: u64,
+}
+
+impl ProfileView {
+    fn new(display: String, profile: DisplayProfile) -> ProfileView {
+        ProfileView {
+            display,
+            font_size_px: profile.font_size_px,
+            foreground: profile.foreground,
+            background: profile.background,
+            layout: profile.layout,
+            refresh: profile.refresh,
+            poll_secs: profile.poll_secs,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ProfileBody {
+    font_size_px: u32,
+    foreground: String,
+    background: String,
+    layout: String,
+    refresh: String,
+    poll_secs: u64,
+}
+
+impl Default for ProfileBody {
+    fn default() -> Self {
+        let profile = DisplayProfile::default();
+        ProfileBody {
+            font_size_px: profile.font_size_px,
+            foreground: profile.foreground,
+            background: profile.background,
+            layout: profile.layout,
+            refresh: profile.refresh,
+            poll_secs: profile.poll_secs,
+        }
+    }
+}
+
+impl From<ProfileBody> for DisplayProfile {
+    fn from(body: ProfileBody) -> Self {
+        DisplayProfile {
+            font_size_px: body.font_size_px,
+            foreground: body.foreground,
+            background: body.background,
+            layout: body.layout,
+            refresh: body.refresh,
+            poll_secs: body.poll_secs,
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /display/profile, PUT /display/profile
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "ui",
+    path = "/display/profile",
+    params(DisplayQuery),
+    responses((status = 200, description = "The display's profile", body = ProfileView)),
+)]
+#[get("/display/profile")]
+async fn get_profile(app_data: web::Data<AppState>, query: web::Query<DisplayQuery>) -> HttpResponse {
+    let name = query.name();
+    let profile = profile(&*app_data.config.read().await, &name);
+    HttpResponse::Ok().json(ProfileView::new(name, profile))
+}
+
+#[utoipa::path(
+    tag = "ui",
+    path = "/display/profile",
+    params(DisplayQuery),
+    request_body = ProfileBody,
+    responses(
+        (status = 200, description = "The display's new profile", body = ProfileView),
+        (status = 400, description = "Unknown field or invalid value (code invalid_json / invalid_settings)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[put("/profile")]
+async fn put_profile(
+    app_data: web::Data<AppState>,
+    query: web::Query<DisplayQuery>,
+    body: web::Json<ProfileBody>,
+) -> Result<HttpResponse, ApiError> {
+    let name = query.name();
+    let profile = DisplayProfile::from(body.into_inner());
+    {
+        let mut live = app_data.config.write().await;
+        let mut updated = live.clone();
+        updated.displays.insert(name.clone(), profile.clone());
+        updated.validate().map_err(|e| {
+            ApiError::bad_request("invalid_settings", "Invalid display profile").with_detail(format!("{e:#}"))
+        })?;
+        *live = updated;
+    }
+
+    tracing::info!(display = %name, layout = %profile.layout, refresh = %profile.refresh, "display profile updated");
+    let view = ProfileView::new(name, profile);
+    let event = serde_json::to_string(&view).expect("a profile serializes");
+    app_data.events.publish_notice("display", event);
+    Ok(HttpResponse::Ok().json(view))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_profile)
+        .service(web::scope("/display").wrap(from_fn(admin::require_admin)).service(put_profile));
+}
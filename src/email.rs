@@ -0,0 +1,154 @@
+/////////////////////////////////////////////////////////////
+// src/email.rs
+//
+// Email over SMTP ([email] enabled, "email" cargo feature), for
+// people who never open the web UI:
+//   - the digest (see digest.rs), daily at email.digest_time or
+//     weekly on email.digest_weekday, covering the day or week
+//     before it
+//   - with session_summaries, each session's summary when it
+//     stops
+// Plain text, to every address in email.to.
+//
+// The connection is opened per message, so a mail server that
+// restarts doesn't matter. Settings are restart-only; a failed
+// send is logged and dropped.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Utc, Weekday};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::config::EmailSettings;
+use crate::{digest, privacy, timezone, AppState};
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Starts the digest schedule, unless email.digest is "off".
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: EmailSettings) {
+    let weekly = match settings.digest.as_str() {
+        "daily" => false,
+        "weekly" => true,
+        _ => return,
+    };
+    // Both checked by Config::validate
+    let at = NaiveTime::parse_from_str(&settings.digest_time, "%H:%M").unwrap_or_default();
+    let weekday = weekly.then(|| settings.digest_weekday.parse().unwrap_or(Weekday::Mon));
+    tracing::info!(recipients = settings.to.len(), digest = %settings.digest, "emailing digests");
+    let span = tracing::info_span!(parent: None, "email");
+    app_data.tasks.spawn("email", digests(app_data.clone(), settings, at, weekday).instrument(span));
+}
+
+async fn digests(app_data: web::Data<AppState>, settings: EmailSettings, at: NaiveTime, weekday: Option<Weekday>) {
+    let shutdown = app_data.tasks.token();
+    let (period, days) = if weekday.is_some() { ("weekly", 7) } else { ("daily", 1) };
+    loop {
+        let now = Local::now().naive_local();
+        let wait = (next_digest(now, at, weekday) - now).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        let text = match digest::build(&app_data, Utc::now() - chrono::Duration::days(days)).await {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::warn!(error = %format!("{e:#}"), "couldn't build the {period} digest");
+                continue;
+            }
+        };
+        let subject = format!("SilentNight {period} digest, {}", timezone::now().format("%Y-%m-%d"));
+        match send(&settings, &subject, text).await {
+            Ok(()) => tracing::info!("emailed the {period} digest"),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "digest email failed"),
+        }
+    }
+}
+
+// The first `at` after `now`, on `weekday` if there is one
+fn next_digest(now: NaiveDateTime, at: NaiveTime, weekday: Option<Weekday>) -> NaiveDateTime {
+    let mut next = now.date().and_time(at);
+    while next <= now || weekday.is_some_and(|day| next.weekday() != day) {
+        next += chrono::Duration::days(1);
+    }
+    next
+}
+
+/////////////////////////////////////////////////////////////
+// session_stopped
+//
+// Mails the summary of a session that just ended, in the
+// background, when email.session_summaries is on.
+/////////////////////////////////////////////////////////////
+pub async fn session_stopped(app_data: &web::Data<AppState>, audio_source: &str, session_id: &str) {
+    let settings = app_data.config.read().await.email.clone();
+    if !settings.enabled || !settings.session_summaries {
+        return;
+    }
+    let span = tracing::info_span!(parent: None, "email", source = %audio_source, session_id = %session_id);
+    let (data, audio_source, session_id) = (app_data.clone(), audio_source.to_string(), session_id.to_string());
+    let mail = async move {
+        let session = match digest::session(&data, &session_id).await {
+            Ok(session) => session,
+            Err(e) => {
+                tracing::warn!(error = %format!("{e:#}"), "couldn't summarize the session");
+                return;
+            }
+        };
+        let mut text = format!("Session {session_id} on {audio_source}");
+        if let Some((first, last)) = session.span {
+            text.push_str(&format!(
+                ", {}, {} min, {} chunks",
+                timezone::local(first).format("%Y-%m-%d %H:%M"),
+                (last - first).num_minutes(),
+                session.chunks
+            ));
+        }
+        text.push_str(&format!("\n\n{}", session.summary));
+        let subject = format!("SilentNight session {session_id} on {audio_source}");
+        match send(&settings, &subject, text).await {
+            Ok(()) => tracing::debug!("emailed the session summary"),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "session summary email failed"),
+        }
+    };
+    app_data.tasks.spawn("email", mail.instrument(span));
+}
+
+// One plain-text message to every recipient
+async fn send(settings: &EmailSettings, subject: &str, text: String) -> Result<()> {
+    let mut message = Message::builder()
+        .from(settings.from.parse().with_context(|| format!("email.from {:?} is not valid", settings.from))?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN);
+    for to in &settings.to {
+        message = message.to(to.parse().with_context(|| format!("email address {to:?} is not valid"))?);
+    }
+    let message = message.body(text).context("Failed to build the email")?;
+
+    let host = settings.smtp_host.as_str();
+    privacy::check_host(host, settings.smtp_port).await?;
+    let builder = match settings.security.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        _ => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+    };
+    let mut builder = builder.port(settings.smtp_port).timeout(Some(SMTP_TIMEOUT));
+    if !settings.username.is_empty() {
+        builder = builder.credentials(Credentials::new(settings.username.clone(), settings.password.clone()));
+    }
+    builder
+        .build()
+        .send(message)
+        .await
+        .with_context(|| format!("SMTP server {host}:{} refused the email", settings.smtp_port))?;
+    Ok(())
+}
@@ -0,0 +1,397 @@
+/////////////////////////////////////////////////////////////
+// src/error.rs
+//
+// ApiError: the one error type handlers return. It renders as
+//   {"code": "already_recording", "message": "...", "detail": ...}
+// with a matching HTTP status, so scripts can branch on `code`
+// instead of parsing text.
+//
+// Extractor failures (bad query strings, forms, JSON, path params,
+// bodies over server.max_body_kb) are mapped to the same shape by
+// `configure`.
+//
+// The recording pipeline has its own error types (AudioError,
+// SttError, LlmError, StorageError, wrapped in PipelineError) so
+// the loop can tell a flaky network from a missing API key, and
+// handlers get the right status instead of a blanket 500.
+/////////////////////////////////////////////////////////////
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::error::Error as _;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+use utoipa::ToSchema;
+
+use crate::wav::WavError;
+
+/////////////////////////////////////////////////////////////
+// ErrorBody
+//
+// What every error response looks like on the wire.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorBody {
+    // Stable, machine-readable, snake_case
+    code: String,
+    // Human-readable summary
+    message: String,
+    // Underlying cause, when there is one
+    detail: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    detail: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl fmt::Display) -> ApiError {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::UNAUTHORIZED, "login_required", message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::CONFLICT, code, message)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", message)
+    }
+
+    pub fn internal(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, code, message)
+    }
+
+    pub fn bad_gateway(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_GATEWAY, code, message)
+    }
+
+    pub fn unavailable(code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::SERVICE_UNAVAILABLE, code, message)
+    }
+
+    pub fn payload_too_large(limit: usize) -> ApiError {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            format!("Request body is larger than the {} KiB limit", limit / 1024),
+        )
+    }
+
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.message, detail),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            code: self.code.to_string(),
+            message: self.message.clone(),
+            detail: self.detail.clone(),
+        })
+    }
+}
+
+// gRPC calls carry the REST code as a prefix of the status message
+#[cfg(feature = "grpc")]
+impl From<ApiError> for tonic::Status {
+    fn from(e: ApiError) -> tonic::Status {
+        let code = match e.status {
+            StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE => tonic::Code::InvalidArgument,
+            StatusCode::UNAUTHORIZED => tonic::Code::Unauthenticated,
+            StatusCode::FORBIDDEN => tonic::Code::PermissionDenied,
+            StatusCode::NOT_FOUND => tonic::Code::NotFound,
+            StatusCode::CONFLICT => tonic::Code::FailedPrecondition,
+            StatusCode::TOO_MANY_REQUESTS => tonic::Code::ResourceExhausted,
+            StatusCode::SERVICE_UNAVAILABLE => tonic::Code::Unavailable,
+            _ => tonic::Code::Internal,
+        };
+        tonic::Status::new(code, format!("{}: {e}", e.code))
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// configure
+//
+// Registers extractor error handlers so malformed input also
+// gets an ErrorBody instead of actix's plain-text default, and
+// caps request bodies at `body_limit` bytes.
+/////////////////////////////////////////////////////////////
+pub fn configure(cfg: &mut web::ServiceConfig, body_limit: usize) {
+    cfg.app_data(web::QueryConfig::default().error_handler(|err, _| {
+        ApiError::bad_request("invalid_query", "Invalid query string")
+            .with_detail(err)
+            .into()
+    }))
+    .app_data(web::FormConfig::default().limit(body_limit).error_handler(move |err, _| {
+        if err.status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+            return ApiError::payload_too_large(body_limit).into();
+        }
+        ApiError::bad_request("invalid_form", "Invalid form body")
+            .with_detail(err)
+            .into()
+    }))
+    .app_data(web::JsonConfig::default().limit(body_limit).error_handler(move |err, _| {
+        if err.status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+            return ApiError::payload_too_large(body_limit).into();
+        }
+        ApiError::bad_request("invalid_json", "Invalid JSON body")
+            .with_detail(err)
+            .into()
+    }))
+    .app_data(web::PayloadConfig::new(body_limit))
+    .app_data(web::PathConfig::default().error_handler(|err, _| {
+        ApiError::bad_request("invalid_path", "Invalid path parameter")
+            .with_detail(err)
+            .into()
+    }));
+}
+
+/////////////////////////////////////////////////////////////
+// Pipeline errors
+//
+// One enum per stage of a chunk: capture, Whisper, GPT, and the
+// conversation log. Whisper and GPT share OpenAiError for the
+// HTTP side. PipelineError::is_retryable decides whether the
+// recording loop skips the chunk or stops.
+/////////////////////////////////////////////////////////////
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("Audio source {0:?} was removed from the config")]
+    SourceRemoved(String),
+    #[error("Failed to spawn mic command {program:?}")]
+    Spawn {
+        program: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Reading from mic stdout failed")]
+    Read(#[source] io::Error),
+    #[error("Mic command exited with non-zero status: {0}")]
+    Exited(ExitStatus),
+    #[error("Mic command stopped while listening for speech")]
+    Stopped,
+    #[error("Can't use WAV fixture {path}: {reason}")]
+    Fixture { path: String, reason: String },
+    #[error("Mic command output isn't usable WAV audio")]
+    InvalidWav(#[source] WavError),
+    #[error("Node {name:?} sent no audio for {secs}s")]
+    NodeSilent { name: String, secs: u64 },
+    #[error("Chunk discarded: {speaker} opted out of being recorded")]
+    OptedOut { speaker: String },
+    #[error("Couldn't check the chunk for opted-out speakers")]
+    OptOutUnavailable(#[source] anyhow::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenAiError {
+    #[error("Must set OPENAI_API_KEY (or openai.api_key in the config file)")]
+    NotConfigured,
+    #[error("Too many OpenAI requests waiting ({waiting}); raise openai.max_queued or record fewer sources")]
+    Busy { waiting: usize },
+    #[error("Couldn't reach the OpenAI API")]
+    Unreachable(#[source] reqwest::Error),
+    #[error("OpenAI didn't answer within {secs}s; raise openai.timeout_secs if this keeps happening")]
+    Timeout { secs: u64 },
+    #[error("OpenAI API returned {status}: {body}")]
+    Status { status: u16, body: String },
+    #[error("Couldn't parse the OpenAI response")]
+    InvalidResponse(#[source] reqwest::Error),
+    #[error("privacy.mode is local and {host} isn't on the local network; point openai.base_url at a local server")]
+    NotLocal { host: String },
+    #[error(transparent)]
+    NotRouted(#[from] crate::routing::RouteError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SttError {
+    #[error("Failed to build the Whisper upload")]
+    Upload(#[source] reqwest::Error),
+    #[error("Whisper request failed")]
+    OpenAi(#[from] OpenAiError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LlmError {
+    #[error("ChatCompletion request failed")]
+    OpenAi(#[from] OpenAiError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("Failed to serialize JSON record")]
+    Serialize(#[source] serde_json::Error),
+    #[error("Failed to open or create {path}")]
+    Open {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to write JSON record")]
+    Write(#[source] io::Error),
+    #[error("Failed to save audio to {path}")]
+    SaveAudio {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("The backlog in {dir} is full ({max} chunks); run POST /process_backlog or raise backlog.max_chunks")]
+    BacklogFull { dir: String, max: usize },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error(transparent)]
+    Audio(#[from] AudioError),
+    #[error(transparent)]
+    Stt(#[from] SttError),
+    #[error(transparent)]
+    Llm(#[from] LlmError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl OpenAiError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            OpenAiError::NotConfigured | OpenAiError::NotLocal { .. } | OpenAiError::NotRouted(_) => false,
+            OpenAiError::Busy { .. }
+            | OpenAiError::Unreachable(_)
+            | OpenAiError::Timeout { .. }
+            | OpenAiError::InvalidResponse(_) => true,
+            // Timeouts, rate limits and OpenAI's own outages pass; a bad
+            // key or request won't get better by asking again
+            OpenAiError::Status { status, .. } => matches!(status, 408 | 409 | 429) || *status >= 500,
+        }
+    }
+
+    fn api_error(&self) -> ApiError {
+        match self {
+            OpenAiError::NotConfigured => ApiError::unavailable("openai_not_configured", self.to_string()),
+            OpenAiError::Busy { .. } => ApiError::unavailable("openai_busy", self.to_string()),
+            OpenAiError::NotLocal { .. } => ApiError::unavailable("privacy_local_only", self.to_string()),
+            OpenAiError::NotRouted(_) => ApiError::unavailable("not_routed", self.to_string()),
+            OpenAiError::Status { status: 429, .. } => {
+                ApiError::unavailable("openai_rate_limited", "OpenAI is rate limiting requests")
+            }
+            OpenAiError::Unreachable(_) => ApiError::bad_gateway("openai_unreachable", self.to_string()),
+            OpenAiError::Timeout { .. } => ApiError::bad_gateway("openai_timeout", self.to_string()),
+            OpenAiError::Status { .. } | OpenAiError::InvalidResponse(_) => {
+                ApiError::bad_gateway("openai_error", "The OpenAI API request failed")
+            }
+        }
+    }
+}
+
+impl PipelineError {
+    // Worth trying again with the next chunk
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            PipelineError::Audio(AudioError::SourceRemoved(_) | AudioError::Spawn { .. } | AudioError::Fixture { .. }) => {
+                false
+            }
+            // A glitch in the mic command, the next chunk may be fine
+            PipelineError::Audio(
+                AudioError::Read(_) | AudioError::Exited(_) | AudioError::Stopped | AudioError::InvalidWav(_),
+            ) => true,
+            // It may be back by the next one (see hub.rs)
+            PipelineError::Audio(AudioError::NodeSilent { .. }) => true,
+            // Not a failure; the recording loop moves on (see optout.rs)
+            PipelineError::Audio(AudioError::OptedOut { .. }) => false,
+            PipelineError::Audio(AudioError::OptOutUnavailable(_)) => true,
+            PipelineError::Stt(SttError::Upload(_)) => false,
+            PipelineError::Stt(SttError::OpenAi(e)) | PipelineError::Llm(LlmError::OpenAi(e)) => e.is_retryable(),
+            // A full disk or unwritable log won't fix itself
+            PipelineError::Storage(_) => false,
+        }
+    }
+
+    // Whisper or GPT couldn't be used at all (no key, no network),
+    // so the chunk can wait in the backlog instead of being dropped
+    pub fn is_offline(&self) -> bool {
+        matches!(
+            self,
+            PipelineError::Stt(SttError::OpenAi(OpenAiError::NotConfigured | OpenAiError::Unreachable(_)))
+                | PipelineError::Llm(LlmError::OpenAi(OpenAiError::NotConfigured | OpenAiError::Unreachable(_)))
+        )
+    }
+
+    // The message with every cause, like anyhow's "{:#}"
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut cause = self.source();
+        while let Some(e) = cause {
+            report.push_str(": ");
+            report.push_str(&e.to_string());
+            cause = e.source();
+        }
+        report
+    }
+}
+
+impl From<PipelineError> for ApiError {
+    fn from(e: PipelineError) -> ApiError {
+        let api_error = match &e {
+            PipelineError::Audio(AudioError::SourceRemoved(_)) => ApiError::not_found("unknown_source", e.to_string()),
+            PipelineError::Audio(AudioError::Spawn { .. }) => {
+                ApiError::unavailable("mic_unavailable", "The mic command couldn't be started")
+            }
+            PipelineError::Audio(AudioError::OptedOut { .. }) => ApiError::conflict("speaker_opted_out", e.to_string()),
+            PipelineError::Audio(AudioError::OptOutUnavailable(_)) => {
+                ApiError::bad_gateway("opt_out_unavailable", "Couldn't check the chunk for opted-out speakers")
+            }
+            PipelineError::Audio(_) => ApiError::internal("audio_failed", "Recording audio failed"),
+            PipelineError::Stt(SttError::Upload(_)) => ApiError::internal("stt_failed", "Transcription failed"),
+            PipelineError::Stt(SttError::OpenAi(openai)) | PipelineError::Llm(LlmError::OpenAi(openai)) => {
+                openai.api_error()
+            }
+            PipelineError::Storage(StorageError::SaveAudio { .. } | StorageError::BacklogFull { .. }) => {
+                ApiError::internal("storage_failed", "Saving the audio chunk failed")
+            }
+            PipelineError::Storage(_) => ApiError::internal("storage_failed", "Writing the conversation log failed"),
+        };
+        api_error.with_detail(e.report())
+    }
+}
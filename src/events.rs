@@ -0,0 +1,546 @@
+/////////////////////////////////////////////////////////////
+// src/events.rs
+//
+// SSE plumbing shared by /live_log (every source) and
+// /sources/{name}/live_log (one source), plus long polling for
+// /poll_log.
+//
+// An EventChannel is a broadcast channel plus:
+// - a numeric event ID on every record, so the browser tracks
+//   its position
+// - a small replay buffer, so a reconnecting EventSource that
+//   sends Last-Event-ID receives what it missed
+// - ": keepalive" comments so idle connections aren't dropped
+//   by proxies
+// - optional filtering per subscriber (?source=...&session=...),
+//   so e.g. a wall display only gets GPT responses
+// - lag tracking: a subscriber that falls more than
+//   server.sse_capacity records behind loses the oldest ones. It
+//   gets an SSE event named "lagged" ({"missed": n}) in their
+//   place, and GET /status shows how many each subscriber missed.
+// - notices sent to SSE clients as named events: recording state
+//   changes ("state", see lifecycle.rs), failed background
+//   tasks ("task_failed", see supervisor.rs), bookmarks
+//   ("bookmark", see bookmarks.rs) and responses to transcripts
+//   sent ahead of them ("entry_updated", see pipeline.rs). They
+//   have no ID, aren't replayed or filtered, and don't reach
+//   /poll_log or gRPC.
+// - ?schema=2: instead of the bare log records and named
+//   notices, every record and notice is an unnamed event in one
+//   shape (see Event), so a display can render it without
+//   knowing each notice or fetching the log. /poll_log returns
+//   records in it too. Without it (or with schema=1) streams are
+//   as they always were.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use futures_util::{future, stream, Stream, StreamExt};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Mutex as AsyncMutex};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::timezone;
+
+// How many events we keep around for reconnecting SSE clients
+const SSE_REPLAY_CAPACITY: usize = 200;
+// How often a live log stream sends a ": keepalive" comment
+pub const SSE_KEEPALIVE_SECS: u64 = 15;
+// The Event shape's version, for ?schema=
+pub const EVENT_SCHEMA: u32 = 2;
+
+/////////////////////////////////////////////////////////////
+// LogEvent
+//
+// One appended JSON line plus the SSE event ID it was sent with.
+// The record's source label and session are kept alongside so
+// filters don't have to parse the JSON, and so is the record as
+// an Event, for ?schema=2.
+/////////////////////////////////////////////////////////////
+#[derive(Clone)]
+pub struct LogEvent {
+    pub id: u64,
+    pub source: String,
+    pub session_id: Option<String>,
+    pub data: String,
+    pub event: String,
+}
+
+/////////////////////////////////////////////////////////////
+// Event
+//
+// What ?schema=2 streams send for every record and notice.
+// Every field is always there, null when it doesn't apply:
+//   kind        - "transcript", "response", or the notice's name
+//                 ("state", "telemetry", "bookmark", "lagged", ...)
+//   local_time  - timestamp in ui.timezone (see timezone.rs)
+//   entry_id    - the record's id in the log (records only)
+//   speaker     - who spoke first in the chunk, speakers all of
+//                 them (see speakers.rs)
+//   language, confidence - as Whisper reported them, if it did
+//   data        - the notice itself, as the named event has it
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Event {
+    schema: u32,
+    kind: String,
+    timestamp: String,
+    local_time: String,
+    audio_source: Option<String>,
+    session_id: Option<String>,
+    chunk_id: Option<String>,
+    entry_id: Option<String>,
+    text: Option<String>,
+    speaker: Option<String>,
+    speakers: Vec<String>,
+    language: Option<String>,
+    confidence: Option<f64>,
+    #[schema(value_type = Option<Object>)]
+    data: Option<Value>,
+}
+
+impl Event {
+    fn new(kind: &str) -> Event {
+        let now = Utc::now();
+        Event {
+            schema: EVENT_SCHEMA,
+            kind: kind.to_string(),
+            timestamp: now.to_rfc3339(),
+            local_time: timezone::local(now).to_rfc3339(),
+            audio_source: None,
+            session_id: None,
+            chunk_id: None,
+            entry_id: None,
+            text: None,
+            speaker: None,
+            speakers: Vec::new(),
+            language: None,
+            confidence: None,
+            data: None,
+        }
+    }
+
+    // A conversation_log.json record
+    fn record(record: &Value) -> Event {
+        let string = |key: &str| record[key].as_str().map(str::to_string);
+        let kind = if record["source"] == "Microphone" { "transcript" } else { "response" };
+        let speakers: Vec<String> = record["speakers"]
+            .as_array()
+            .map(|s| s.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        let timestamp = string("timestamp").unwrap_or_else(|| Utc::now().to_rfc3339());
+        Event {
+            local_time: timezone::rfc3339(&timestamp).unwrap_or_else(|| timezone::now().to_rfc3339()),
+            timestamp,
+            audio_source: string("audio_source"),
+            session_id: string("session_id"),
+            chunk_id: string("chunk_id"),
+            entry_id: string("entry_id"),
+            text: string("text"),
+            speaker: speakers.first().cloned(),
+            speakers,
+            language: string("language"),
+            confidence: record["confidence"].as_f64(),
+            ..Event::new(kind)
+        }
+    }
+
+    // A notice; its source and session, when it names them
+    fn notice(name: &str, data: &str) -> Event {
+        let data: Value = serde_json::from_str(data).unwrap_or_else(|_| Value::String(data.to_string()));
+        Event {
+            audio_source: data["audio_source"].as_str().map(str::to_string),
+            session_id: data["session_id"].as_str().map(str::to_string),
+            data: Some(data),
+            ..Event::new(name)
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+// A log record as ?schema=2 streams send it
+pub fn record_event(record: &Value) -> String {
+    Event::record(record).to_json()
+}
+
+/////////////////////////////////////////////////////////////
+// LogFilter
+//
+// Query parameters accepted by the live log endpoints, e.g.
+//   /live_log?source=OPENAI%20RESPONSE&session=current
+// Without any, a client receives every record.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams, Default)]
+pub struct LogFilter {
+    // Only records with one of these sources, comma-separated
+    // ("Microphone", "OPENAI RESPONSE"), case-insensitive
+    source: Option<String>,
+    // "current" for the recording session(s) in progress, or a
+    // session ID
+    session: Option<String>,
+    // 2 for every record and notice as an Event; default 1, the
+    // bare records and named notices
+    schema: Option<u32>,
+}
+
+impl LogFilter {
+    // For callers that don't come through a query string
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn new(source: Option<String>, session: Option<String>) -> LogFilter {
+        LogFilter { source, session, schema: None }
+    }
+
+    // Whether events go out as Events
+    pub fn events(&self) -> bool {
+        self.schema == Some(EVENT_SCHEMA)
+    }
+
+    // `current` is the sessions in progress at subscribe time. A
+    // live record always belongs to one, so pass None for those;
+    // it only matters for what gets replayed.
+    fn matches(&self, ev: &LogEvent, current: Option<&[String]>) -> bool {
+        if let Some(sources) = &self.source {
+            let wanted = sources
+                .split(',')
+                .map(str::trim)
+                .any(|s| s.eq_ignore_ascii_case(&ev.source));
+            if !wanted {
+                return false;
+            }
+        }
+        match self.session.as_deref() {
+            None | Some("") => true,
+            Some("current") => match current {
+                Some(current) => ev.session_id.as_ref().is_some_and(|id| current.contains(id)),
+                None => true,
+            },
+            Some(id) => ev.session_id.as_deref() == Some(id),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// EventChannel
+/////////////////////////////////////////////////////////////
+pub struct EventChannel {
+    // No receiver is kept here, or /status would count it as a subscriber
+    sender: broadcast::Sender<LogEvent>,
+    // (event name, JSON data)
+    notices: broadcast::Sender<(&'static str, String)>,
+    // Next SSE event ID, plus the most recent events for Last-Event-ID replay
+    next_id: AtomicU64,
+    recent: AsyncMutex<VecDeque<LogEvent>>,
+    lag: Arc<LagStats>,
+}
+
+/////////////////////////////////////////////////////////////
+// Lag tracking
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+struct LagStats {
+    next_subscriber: AtomicU64,
+    // Subscriber ID => records it missed, while it's connected
+    subscribers: Mutex<BTreeMap<u64, u64>>,
+    // Every subscriber since startup, long polls included
+    missed_total: AtomicU64,
+}
+
+// Held by a subscriber's stream; unregisters it when dropped
+struct LagTracker {
+    id: u64,
+    stats: Arc<LagStats>,
+}
+
+impl LagTracker {
+    fn missed(&self, n: u64) {
+        self.stats.missed_total.fetch_add(n, Ordering::Relaxed);
+        if let Some(missed) = self.stats.subscribers.lock().unwrap().get_mut(&self.id) {
+            *missed += n;
+        }
+    }
+}
+
+impl Drop for LagTracker {
+    fn drop(&mut self) {
+        self.stats.subscribers.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SubscriberLag {
+    id: u64,
+    missed: u64,
+}
+
+impl EventChannel {
+    // `capacity` is how many records a subscriber may fall behind
+    pub fn new(capacity: usize) -> EventChannel {
+        let (sender, _) = broadcast::channel(capacity);
+        let (notices, _) = broadcast::channel(16);
+        EventChannel {
+            sender,
+            notices,
+            next_id: AtomicU64::new(1),
+            recent: AsyncMutex::new(VecDeque::with_capacity(SSE_REPLAY_CAPACITY)),
+            lag: Arc::default(),
+        }
+    }
+
+    fn track_lag(&self) -> LagTracker {
+        let id = self.lag.next_subscriber.fetch_add(1, Ordering::Relaxed);
+        self.lag.subscribers.lock().unwrap().insert(id, 0);
+        LagTracker { id, stats: self.lag.clone() }
+    }
+
+    // Records missed by falling behind, by every subscriber since startup
+    pub fn missed_total(&self) -> u64 {
+        self.lag.missed_total.load(Ordering::Relaxed)
+    }
+
+    // Connected subscribers that have missed records
+    pub(crate) fn lagging(&self) -> Vec<SubscriberLag> {
+        let subscribers = self.lag.subscribers.lock().unwrap();
+        subscribers
+            .iter()
+            .filter(|(_, missed)| **missed > 0)
+            .map(|(id, missed)| SubscriberLag { id: *id, missed: *missed })
+            .collect()
+    }
+
+    // Broadcasts one record, remembering it for replay. The ID is
+    // taken and the record sent under the replay lock, so records
+    // from concurrent sources are buffered and broadcast in ID
+    // order, and a resumed subscriber never skips one
+    pub async fn publish(&self, source: &str, session_id: Option<String>, data: String) {
+        let record: Value = serde_json::from_str(&data).unwrap_or_default();
+        let event_name = record_event(&record);
+        let mut recent = self.recent.lock().await;
+        let event = LogEvent {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            source: source.to_string(),
+            session_id,
+            event: event_name,
+            data,
+        };
+        if recent.len() == SSE_REPLAY_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+        let _ = self.sender.send(event);
+    }
+
+    // Drops these chunks' records from the replay buffer (see
+    // forget.rs)
+    pub async fn forget(&self, chunk_ids: &HashSet<String>) {
+        if chunk_ids.is_empty() {
+            return;
+        }
+        self.recent.lock().await.retain(|event| {
+            let record: serde_json::Value = serde_json::from_str(&event.data).unwrap_or_default();
+            !record["chunk_id"].as_str().is_some_and(|id| chunk_ids.contains(id))
+        });
+    }
+
+    // A named SSE event, e.g. ("state", a lifecycle::StateChange as JSON)
+    pub fn publish_notice(&self, event: &'static str, data: String) {
+        let _ = self.notices.send((event, data));
+    }
+
+    // Records buffered in the channel, not yet read by every subscriber
+    pub fn pending(&self) -> usize {
+        self.sender.len()
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub async fn replay_len(&self) -> usize {
+        self.recent.lock().await.len()
+    }
+
+    // ID of the most recent record, 0 if there hasn't been one
+    pub fn last_id(&self) -> u64 {
+        self.next_id.load(Ordering::SeqCst) - 1
+    }
+
+    /////////////////////////////////////////////////////////
+    // poll
+    //
+    // Long polling: returns the buffered records newer than
+    // `since` if there are any, otherwise waits up to `timeout`
+    // for the next matching one. Empty on timeout.
+    /////////////////////////////////////////////////////////
+    pub async fn poll(
+        &self,
+        since: Option<u64>,
+        filter: &LogFilter,
+        current_sessions: &[String],
+        timeout: Duration,
+    ) -> Vec<LogEvent> {
+        // Subscribe before checking the buffer so nothing slips between
+        let mut rx = self.sender.subscribe();
+
+        if let Some(since) = since {
+            let recent = self.recent.lock().await;
+            // Same as SSE: an ID we never issued means we restarted
+            let restarted = since >= self.next_id.load(Ordering::SeqCst);
+            let missed: Vec<LogEvent> = recent
+                .iter()
+                .filter(|ev| restarted || ev.id > since)
+                .filter(|ev| filter.matches(ev, Some(current_sessions)))
+                .cloned()
+                .collect();
+            if !missed.is_empty() {
+                return missed;
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(ev)) if filter.matches(&ev, None) => return vec![ev],
+                Ok(Ok(_)) => continue,
+                Ok(Err(RecvError::Lagged(n))) => {
+                    self.lag.missed_total.fetch_add(n, Ordering::Relaxed);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) | Err(_) => return Vec::new(),
+            }
+        }
+    }
+
+    /////////////////////////////////////////////////////////
+    // subscribe
+    //
+    // Buffered records newer than `last_event_id` (if given),
+    // followed by live ones, all passed through `filter`;
+    // `current_sessions` resolves session=current. An Err item
+    // means this subscriber fell behind and missed that many
+    // records; it's counted in the lag stats.
+    /////////////////////////////////////////////////////////
+    pub async fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+        filter: LogFilter,
+        current_sessions: Vec<String>,
+    ) -> impl Stream<Item = Result<LogEvent, BroadcastStreamRecvError>> + Send + 'static {
+        // Subscribe before snapshotting the buffer so nothing slips between
+        let rx = self.sender.subscribe();
+
+        let missed: Vec<LogEvent> = match last_event_id {
+            Some(last_id) => {
+                let recent = self.recent.lock().await;
+                // An ID we never issued means the server restarted; replay everything
+                let restarted = last_id >= self.next_id.load(Ordering::SeqCst);
+                recent
+                    .iter()
+                    .filter(|ev| restarted || ev.id > last_id)
+                    .filter(|ev| filter.matches(ev, Some(&current_sessions)))
+                    .cloned()
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let replayed_up_to = missed.last().map(|ev| ev.id).unwrap_or(0);
+        if let Some(last_id) = last_event_id {
+            tracing::info!(last_event_id = last_id, replayed = missed.len(), "resuming live log");
+        }
+
+        let tracker = self.track_lag();
+        let live_stream = BroadcastStream::new(rx).filter_map(move |res| {
+            if let Err(BroadcastStreamRecvError::Lagged(n)) = &res {
+                tracker.missed(*n);
+            }
+            future::ready(match res {
+                // Skip anything we already replayed from the buffer
+                Ok(ev) if ev.id <= replayed_up_to => None,
+                Ok(ev) if !filter.matches(&ev, None) => None,
+                other => Some(other),
+            })
+        });
+        stream::iter(missed).map(Ok).chain(live_stream)
+    }
+
+    /////////////////////////////////////////////////////////
+    // sse_response
+    //
+    // Streams this channel as text/event-stream, resuming after
+    // the request's Last-Event-ID if it has one.
+    /////////////////////////////////////////////////////////
+    pub async fn sse_response(
+        &self,
+        req: &HttpRequest,
+        filter: LogFilter,
+        current_sessions: Vec<String>,
+    ) -> HttpResponse {
+        let last_event_id: Option<u64> = req
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok());
+
+        // Subscribe before the replay so no change slips between
+        let as_events = filter.events();
+        let notices = BroadcastStream::new(self.notices.subscribe())
+            .filter_map(|res| future::ready(res.ok()))
+            .map(move |(event, data)| {
+                let frame = if as_events {
+                    format!("data: {}\n\n", Event::notice(event, &data).to_json())
+                } else {
+                    format!("event: {event}\ndata: {data}\n\n")
+                };
+                Ok::<Bytes, std::io::Error>(Bytes::from(frame))
+            });
+        let events = self
+            .subscribe(last_event_id, filter, current_sessions)
+            .await
+            .map(move |res| match res {
+                Ok(ev) => Ok::<Bytes, std::io::Error>(format_sse_event(&ev, as_events)),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    tracing::warn!(missed = n, "live log subscriber fell behind");
+                    let data = format!("{{\"missed\":{n}}}");
+                    let frame = if as_events {
+                        format!("data: {}\n\n", Event::notice("lagged", &data).to_json())
+                    } else {
+                        format!("event: lagged\ndata: {data}\n\n")
+                    };
+                    Ok(Bytes::from(frame))
+                }
+            });
+
+        let mut keepalive = tokio::time::interval(Duration::from_secs(SSE_KEEPALIVE_SECS));
+        keepalive.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let keepalive_stream = IntervalStream::new(keepalive)
+            .skip(1) // the first tick fires immediately
+            .map(|_| Ok::<Bytes, std::io::Error>(Bytes::from_static(b": keepalive\n\n")));
+
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(stream::select(stream::select(events, notices), keepalive_stream))
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// format_sse_event
+//
+// Renders one LogEvent as an SSE frame with its event ID, the
+// record itself or as an Event
+/////////////////////////////////////////////////////////////
+fn format_sse_event(ev: &LogEvent, as_event: bool) -> Bytes {
+    let data = if as_event { &ev.event } else { &ev.data };
+    Bytes::from(format!("id: {}\ndata: {data}\n\n", ev.id))
+}
@@ -0,0 +1,474 @@
+/////////////////////////////////////////////////////////////
+// src/export.rs
+//
+// Session notes for people whose meeting notes live in Obsidian
+// or Notion rather than the web UI:
+//   obsidian - a Markdown file in export.obsidian_dir (a vault
+//              folder), with YAML frontmatter; re-exporting a
+//              session overwrites its file
+//   notion   - a page in export.notion_database_id, titled in
+//              its notion_title_property
+// Each destination is on when it's configured. Exports happen
+//   - when a session stops, with export.on_session_close
+//   - on request: POST /sessions/{id}/export, optionally
+//     ?to=obsidian or ?to=notion, and ?lang=es for the session's
+//     Spanish translation (see translate.rs) instead of what was
+//     said, and ?workspace= for another workspace's session (see
+//     workspaces.rs)
+//
+// A note is the session's title (or id), GPT's summary (see
+// digest.rs), a meeting's decisions and action items from its
+// minutes (see meetings.rs) and the transcript with GPT's
+// responses, e.g.
+//   ---
+//   session_id: 20261015-140200
+//   audio_source: default
+//   title: "Weekly sync"
+//   attendees: ["Ada Lovelace <ada@example.com>"]
+//   date: 2026-10-15T14:02:00+02:00
+//   duration_min: 33
+//   chunks: 40
+//   tags: [silentnight]
+//   ---
+//   # Weekly sync
+//   ## Summary
+//   ...
+//   ## Decisions
+//   - Ship on Friday
+//   ## Action items
+//   - [ ] Write the release notes (Ada Lovelace <ada@example.com>)
+//   ## Transcript
+//   - **14:02:05** let's start the weekly sync
+//     > Noted.
+//
+// Settings are read at each export, so a reload applies to the
+// next one. Exports on close run in the background; a failure is
+// logged and dropped.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{middleware, post, web, HttpRequest, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::ExportSettings;
+use crate::error::ApiError;
+use crate::meetings::{self, Minutes};
+use crate::translate::Translation;
+use crate::{digest, privacy, rate_limit, timezone, translate, workspaces, AppState};
+
+const NOTION_VERSION: &str = "2022-06-28";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Notion's limits: blocks per request, characters per text
+const NOTION_MAX_BLOCKS: usize = 100;
+const NOTION_MAX_CHARS: usize = 2000;
+
+/////////////////////////////////////////////////////////////
+// Note
+//
+// A session, ready to render.
+/////////////////////////////////////////////////////////////
+struct Note {
+    session_id: String,
+    audio_source: String,
+    title: Option<String>,
+    attendees: Vec<String>,
+    span: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    chunks: usize,
+    summary: String,
+    // If it was a meeting and they were written
+    minutes: Option<Minutes>,
+    // The language of the transcript, if it's a translation
+    lang: Option<String>,
+    // (time, transcript, GPT's response if it had something to say)
+    lines: Vec<(DateTime<Utc>, String, Option<String>)>,
+}
+
+impl Note {
+    // None if nothing of the session was logged
+    async fn load(app_data: &AppState, session_id: &str, translation: Option<&Translation>) -> Result<Option<Note>> {
+        let records = digest::session_records(session_id).await?;
+        let Some(first) = records.first() else {
+            return Ok(None);
+        };
+        let audio_source = first["audio_source"].as_str().unwrap_or_default().to_string();
+        let title = records.iter().find_map(|r| r["session_title"].as_str()).map(str::to_string);
+        let attendees = records
+            .iter()
+            .find_map(|r| r["attendees"].as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+
+        let mut lines: Vec<(DateTime<Utc>, String, Option<String>)> = Vec::new();
+        for record in &records {
+            let Some(at) = record["timestamp"].as_str().and_then(|t| DateTime::parse_from_rfc3339(t).ok()) else {
+                continue;
+            };
+            let text = record["text"].as_str().unwrap_or_default().trim().to_string();
+            if record["source"] == "Microphone" {
+                if !text.is_empty() {
+                    let translated = translation.and_then(|t| t.text(record["chunk_id"].as_str().unwrap_or_default()));
+                    lines.push((at.with_timezone(&Utc), translated.map_or(text, str::to_string), None));
+                }
+            } else if !crate::pipeline::is_listening(&text) {
+                // Responses follow their transcript
+                if let Some(last) = lines.last_mut().filter(|l| l.2.is_none()) {
+                    last.2 = Some(text);
+                }
+            }
+        }
+
+        let summary = digest::session(app_data, session_id).await?;
+        let minutes = meetings::load(session_id).await.unwrap_or_else(|e| {
+            tracing::warn!(error = %format!("{e:#}"), "exporting without the minutes");
+            None
+        });
+        Ok(Some(Note {
+            session_id: session_id.to_string(),
+            audio_source,
+            title,
+            attendees,
+            span: summary.span,
+            chunks: summary.chunks,
+            summary: summary.summary,
+            minutes,
+            lang: translation.map(|t| t.lang.clone()),
+            lines,
+        }))
+    }
+
+    fn heading(&self) -> String {
+        self.title.clone().unwrap_or_else(|| format!("Session {}", self.session_id))
+    }
+
+    fn markdown(&self) -> String {
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+        let mut text = String::from("---\n");
+        text.push_str(&format!("session_id: {}\n", quote(&self.session_id)));
+        text.push_str(&format!("audio_source: {}\n", quote(&self.audio_source)));
+        if let Some(title) = &self.title {
+            text.push_str(&format!("title: {}\n", quote(title)));
+        }
+        if !self.attendees.is_empty() {
+            let attendees: Vec<String> = self.attendees.iter().map(|a| quote(a)).collect();
+            text.push_str(&format!("attendees: [{}]\n", attendees.join(", ")));
+        }
+        if let Some((first, last)) = self.span {
+            text.push_str(&format!("date: {}\n", timezone::local(first).to_rfc3339()));
+            text.push_str(&format!("duration_min: {}\n", (last - first).num_minutes()));
+        }
+        if let Some(lang) = &self.lang {
+            text.push_str(&format!("language: {}\n", quote(lang)));
+        }
+        text.push_str(&format!("chunks: {}\ntags: [silentnight]\n---\n\n", self.chunks));
+
+        text.push_str(&format!("# {}\n\n## Summary\n\n{}\n\n", self.heading(), self.summary));
+        if let Some(minutes) = &self.minutes {
+            if !minutes.decisions.is_empty() {
+                text.push_str("## Decisions\n\n");
+                for decision in &minutes.decisions {
+                    text.push_str(&format!("- {decision}\n"));
+                }
+                text.push('\n');
+            }
+            if !minutes.action_items.is_empty() {
+                text.push_str("## Action items\n\n");
+                for item in &minutes.action_items {
+                    let owner = item.owner.as_ref().map(|owner| format!(" ({owner})"));
+                    text.push_str(&format!("- [ ] {}{}\n", item.task, owner.unwrap_or_default()));
+                }
+                text.push('\n');
+            }
+        }
+        text.push_str("## Transcript\n\n");
+        for (at, transcript, response) in &self.lines {
+            text.push_str(&format!("- **{}** {transcript}\n", timezone::local(*at).format("%H:%M:%S")));
+            if let Some(response) = response {
+                text.push_str(&format!("  > {}\n", response.replace('\n', " ")));
+            }
+        }
+        text
+    }
+
+    // "2026-10-15 Weekly sync.md" ("... (es).md" translated),
+    // without characters vaults or file systems choke on
+    fn file_name(&self) -> String {
+        let date = self.span.map(|(first, _)| timezone::local(first).format("%Y-%m-%d ").to_string());
+        let lang = self.lang.as_ref().map(|lang| format!(" ({lang})"));
+        let name: String = format!("{}{}{}", date.unwrap_or_default(), self.heading(), lang.unwrap_or_default())
+            .chars()
+            .map(|c| if "\\/:*?\"<>|#^[]".contains(c) || c.is_control() { '-' } else { c })
+            .collect();
+        format!("{}.md", name.trim())
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// export
+//
+// Sends one session to each configured destination (or just
+// `only`), in its translation into `lang` if given.
+// UnknownSession if it has no records, UnknownTranslation if
+// it wasn't translated into `lang`.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, Default, ToSchema)]
+pub(crate) struct ExportResponse {
+    // The Markdown file written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    obsidian: Option<String>,
+    // The Notion page created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notion: Option<String>,
+}
+
+enum ExportError {
+    UnknownSession,
+    UnknownTranslation,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ExportError {
+    fn from(e: anyhow::Error) -> Self {
+        ExportError::Failed(e)
+    }
+}
+
+async fn export(
+    app_data: &AppState,
+    settings: &ExportSettings,
+    session_id: &str,
+    only: Option<&str>,
+    lang: Option<&str>,
+) -> Result<ExportResponse, ExportError> {
+    let translation = match lang {
+        Some(lang) => Some(translate::load(session_id, lang).await?.ok_or(ExportError::UnknownTranslation)?),
+        None => None,
+    };
+    let note = Note::load(app_data, session_id, translation.as_ref()).await?.ok_or(ExportError::UnknownSession)?;
+    let wanted = |to: &str| only.is_none_or(|only| only == to);
+    let mut done = ExportResponse::default();
+    if wanted("obsidian") && !settings.obsidian_dir.is_empty() {
+        done.obsidian = Some(to_obsidian(settings, &note).await?);
+    }
+    if wanted("notion") && !settings.notion_token.is_empty() {
+        done.notion = Some(to_notion(settings, &note).await?);
+    }
+    Ok(done)
+}
+
+async fn to_obsidian(settings: &ExportSettings, note: &Note) -> Result<String> {
+    let dir = PathBuf::from(&settings.obsidian_dir);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(note.file_name());
+    tokio::fs::write(&path, note.markdown())
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    tracing::info!(session_id = %note.session_id, file = %path.display(), "exported the session to Obsidian");
+    Ok(path.display().to_string())
+}
+
+// The page's URL
+async fn to_notion(settings: &ExportSettings, note: &Note) -> Result<String> {
+    let client = privacy::client();
+    let api = settings.notion_api_url.trim_end_matches('/');
+    let blocks = notion_blocks(note);
+    let mut batches = blocks.chunks(NOTION_MAX_BLOCKS);
+    let mut properties = serde_json::Map::new();
+    properties.insert(settings.notion_title_property.clone(), json!({ "title": rich_text(&note.heading()) }));
+    let page = json!({
+        "parent": { "database_id": settings.notion_database_id },
+        "properties": properties,
+        "children": batches.next().unwrap_or_default(),
+    });
+    let page = notion_call(client.post(format!("{api}/v1/pages")), settings, page).await?;
+    let page_id = page["id"].as_str().context("Notion didn't return the page's id")?;
+    // Long transcripts go in after the page is made
+    for batch in batches {
+        let url = format!("{api}/v1/blocks/{page_id}/children");
+        notion_call(client.patch(url), settings, json!({ "children": batch })).await?;
+    }
+    let url = page["url"].as_str().unwrap_or(page_id).to_string();
+    tracing::info!(session_id = %note.session_id, page = %url, "exported the session to Notion");
+    Ok(url)
+}
+
+async fn notion_call(request: reqwest::RequestBuilder, settings: &ExportSettings, body: Value) -> Result<Value> {
+    let resp = request
+        .bearer_auth(&settings.notion_token)
+        .header("Notion-Version", NOTION_VERSION)
+        .timeout(REQUEST_TIMEOUT)
+        .json(&body)
+        .send()
+        .await
+        .context("Notion unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body: Value = resp.json().await.unwrap_or_default();
+        anyhow::bail!("Notion replied {status}: {}", body["message"].as_str().unwrap_or("no reason given"));
+    }
+    resp.json().await.with_context(|| format!("Notion replied {status} without JSON"))
+}
+
+fn notion_blocks(note: &Note) -> Vec<Value> {
+    let block = |kind: &str, text: &str| json!({ "object": "block", "type": kind, kind: { "rich_text": rich_text(text) } });
+    let mut blocks = Vec::new();
+    let mut details = vec![format!("Session {} on {}", note.session_id, note.audio_source)];
+    if let Some((first, last)) = note.span {
+        details.push(format!(
+            "{}, {} min, {} chunks",
+            timezone::local(first).format("%Y-%m-%d %H:%M"),
+            (last - first).num_minutes(),
+            note.chunks
+        ));
+    }
+    if !note.attendees.is_empty() {
+        details.push(format!("Attendees: {}", note.attendees.join(", ")));
+    }
+    blocks.push(block("paragraph", &details.join("\n")));
+    blocks.push(block("heading_2", "Summary"));
+    blocks.push(block("paragraph", &note.summary));
+    if let Some(minutes) = &note.minutes {
+        if !minutes.decisions.is_empty() {
+            blocks.push(block("heading_2", "Decisions"));
+            blocks.extend(minutes.decisions.iter().map(|decision| block("bulleted_list_item", decision)));
+        }
+        if !minutes.action_items.is_empty() {
+            blocks.push(block("heading_2", "Action items"));
+            for item in &minutes.action_items {
+                let owner = item.owner.as_ref().map(|owner| format!(" ({owner})"));
+                blocks.push(json!({
+                    "object": "block",
+                    "type": "to_do",
+                    "to_do": { "rich_text": rich_text(&format!("{}{}", item.task, owner.unwrap_or_default())), "checked": false },
+                }));
+            }
+        }
+    }
+    blocks.push(block("heading_2", "Transcript"));
+    for (at, transcript, response) in &note.lines {
+        let mut text = format!("{} {transcript}", timezone::local(*at).format("%H:%M:%S"));
+        if let Some(response) = response {
+            text.push_str(&format!("\n→ {response}"));
+        }
+        blocks.push(block("bulleted_list_item", &text));
+    }
+    blocks
+}
+
+// Notion text, split where it's longer than Notion takes
+fn rich_text(text: &str) -> Value {
+    let chars: Vec<char> = text.chars().collect();
+    let parts: Vec<Value> = chars
+        .chunks(NOTION_MAX_CHARS)
+        .map(|part| json!({ "type": "text", "text": { "content": part.iter().collect::<String>() } }))
+        .collect();
+    Value::Array(parts)
+}
+
+/////////////////////////////////////////////////////////////
+// session_stopped
+//
+// Exports a session that just ended, in the background, when
+// export.on_session_close is on.
+/////////////////////////////////////////////////////////////
+pub async fn session_stopped(app_data: &web::Data<AppState>, session_id: &str) {
+    let settings = app_data.config.read().await.export.clone();
+    if !settings.on_session_close || (settings.obsidian_dir.is_empty() && settings.notion_token.is_empty()) {
+        return;
+    }
+    let span = tracing::info_span!(parent: None, "export", session_id = %session_id);
+    let (data, session_id) = (app_data.clone(), session_id.to_string());
+    let run = async move {
+        match export(&data, &settings, &session_id, None, None).await {
+            Ok(_) => {}
+            Err(ExportError::UnknownSession | ExportError::UnknownTranslation) => {
+                tracing::debug!("nothing logged, not exporting")
+            }
+            Err(ExportError::Failed(e)) => tracing::warn!(error = %format!("{e:#}"), "session export failed"),
+        }
+    };
+    app_data.tasks.spawn("export", run.instrument(span));
+}
+
+/////////////////////////////////////////////////////////////
+// POST /sessions/{id}/export
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct ExportQuery {
+    // "obsidian" or "notion"; default both
+    to: Option<String>,
+    // Export this translation of the session (see translate.rs)
+    lang: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(("id" = String, Path, description = "Session id"), ExportQuery, workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "Where the session went", body = ExportResponse),
+        (status = 400, description = "Unknown destination (code unknown_destination)", body = ErrorBody),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "Nothing logged for that session in the workspace (code unknown_session), no such translation (code unknown_translation) or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 409, description = "No destination configured (code export_disabled)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 502, description = "The export failed (code export_failed)", body = ErrorBody),
+    ),
+)]
+#[post("/sessions/{id}/export", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn export_session(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let only = query.to.as_deref();
+    if only.is_some_and(|to| to != "obsidian" && to != "notion") {
+        return Err(ApiError::bad_request("unknown_destination", "to must be \"obsidian\" or \"notion\""));
+    }
+    // Only a session of the workspace asked for (see workspaces.rs)
+    let records = digest::read_log(&workspaces::requested(&req, &app_data).await?)
+        .await
+        .map_err(|e| ApiError::internal("log_unreadable", "Failed to read the conversation log").with_detail(format!("{e:#}")))?;
+    if !records.iter().any(|r| r["session_id"] == id.as_str()) {
+        return Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")));
+    }
+    let settings = app_data.config.read().await.export.clone();
+    let configured = match only {
+        Some("obsidian") => !settings.obsidian_dir.is_empty(),
+        Some(_) => !settings.notion_token.is_empty(),
+        None => !settings.obsidian_dir.is_empty() || !settings.notion_token.is_empty(),
+    };
+    if !configured {
+        return Err(ApiError::conflict(
+            "export_disabled",
+            "Set export.obsidian_dir (OBSIDIAN_DIR) or export.notion_token/notion_database_id to export sessions",
+        ));
+    }
+
+    let lang = query.lang.as_deref();
+    match export(&app_data, &settings, &id, only, lang).await {
+        Ok(done) => Ok(HttpResponse::Ok().json(done)),
+        Err(ExportError::UnknownSession) => {
+            Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")))
+        }
+        Err(ExportError::UnknownTranslation) => Err(ApiError::not_found(
+            "unknown_translation",
+            format!("Session {id} hasn't been translated to {}", lang.unwrap_or_default()),
+        )),
+        Err(ExportError::Failed(e)) => {
+            tracing::warn!(session_id = %id, error = %format!("{e:#}"), "session export failed");
+            Err(ApiError::bad_gateway("export_failed", "Export failed").with_detail(format!("{e:#}")))
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(export_session);
+}
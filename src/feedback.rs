@@ -0,0 +1,230 @@
+/////////////////////////////////////////////////////////////
+// src/feedback.rs
+//
+// Telling the assistant how it's doing. Any of GPT's logged
+// responses can be rated:
+//   POST /entries/{id}/feedback  {"rating": "down",
+//                                 "comment": "Don't chime in on small talk"}
+//   GET  /feedback/stats         - ups, downs and usefulness (the
+//                                  share rated up) per persona
+// A response has one rating; rating it again replaces it. Ratings
+// are kept in feedback.json by entry_id, with the persona and
+// text of the response, and GET /entries/{id} shows a response's
+// under "feedback". Responses are logged with the "persona" that
+// wrote them when it isn't the default system_prompt.
+//
+// Ratings stay in the workspace of the response (see
+// workspaces.rs): stats and what GPT is shown are per workspace.
+//
+// With feedback.prompt_items above 0, that many of the latest
+// ratings of the current persona's responses go to GPT after the
+// system prompt, so it does more of what was liked and less of
+// what wasn't. A reload applies to the next chunk.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::{digest, workspaces, AppState, CONVERSATION_LOG};
+
+const FEEDBACK_FILE: &str = "feedback.json";
+// Longest comment taken
+const MAX_COMMENT_CHARS: usize = 1000;
+// Of a response quoted to GPT
+const QUOTED_CHARS: usize = 200;
+const PROMPT_INTRO: &str = "People rated some of your recent responses. Do more of what they liked and less of what they didn't:";
+
+// Writes to FEEDBACK_FILE
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FeedbackRequest {
+    // "up" or "down"
+    rating: String,
+    comment: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub(crate) struct Feedback {
+    entry_id: String,
+    rating: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    // RFC 3339
+    at: String,
+    // The response's; "" = system_prompt
+    persona: String,
+    response: String,
+    // Its workspace's name; "" = the default (see workspaces.rs)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    workspace: String,
+}
+
+fn load() -> BTreeMap<String, Feedback> {
+    std::fs::read_to_string(FEEDBACK_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save(feedback: Feedback) -> Result<()> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let mut all = load();
+    all.insert(feedback.entry_id.clone(), feedback);
+    let contents = serde_json::to_string_pretty(&all)?;
+    let partial = format!("{FEEDBACK_FILE}.partial");
+    std::fs::write(&partial, contents).with_context(|| format!("Failed to write {partial}"))?;
+    std::fs::rename(&partial, FEEDBACK_FILE).with_context(|| format!("Failed to replace {FEEDBACK_FILE}"))
+}
+
+// Adds a logged response's rating to it, for GET /entries/{id}
+pub fn attach(record: &mut Value) {
+    let rated = record["entry_id"].as_str().and_then(|id| load().remove(id));
+    if let (Some(record), Some(feedback)) = (record.as_object_mut(), rated) {
+        record.insert("feedback".to_string(), serde_json::to_value(feedback).unwrap_or_default());
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// prompt
+//
+// The system message with the latest ratings of `persona`'s
+// responses in `workspace`; None when feedback.prompt_items is 0
+// or there are none.
+/////////////////////////////////////////////////////////////
+pub async fn prompt(app_data: &AppState, workspace: &str, persona: &str) -> Option<String> {
+    let items = app_data.config.read().await.feedback.prompt_items;
+    if items == 0 {
+        return None;
+    }
+    let mut rated: Vec<Feedback> = load().into_values().filter(|f| f.workspace == workspace && f.persona == persona).collect();
+    if rated.is_empty() {
+        return None;
+    }
+    rated.sort_by(|a, b| b.at.cmp(&a.at));
+    let mut message = PROMPT_INTRO.to_string();
+    for feedback in rated.iter().take(items).rev() {
+        let verdict = if feedback.rating == "up" { "Liked" } else { "Disliked" };
+        let quoted: String = feedback.response.chars().take(QUOTED_CHARS).collect();
+        let _ = write!(message, "\n- {verdict}: \"{quoted}\"");
+        if let Some(comment) = &feedback.comment {
+            let _ = write!(message, " ({comment})");
+        }
+    }
+    Some(message)
+}
+
+/////////////////////////////////////////////////////////////
+// POST /entries/{id}/feedback
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    path = "/entries/{id}/feedback",
+    params(("id" = String, Path, description = "The response's entry_id"), workspaces::WorkspaceQuery),
+    request_body = FeedbackRequest,
+    responses(
+        (status = 200, description = "The rating, as kept", body = Feedback),
+        (status = 400, description = "Bad rating or comment (code invalid_feedback), or the entry isn't a response (code not_a_response)", body = ErrorBody),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No record has that entry_id in the workspace (code unknown_entry), or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 500, description = "The log couldn't be read or the rating kept (code log_unreadable, feedback_unsaved)", body = ErrorBody),
+    ),
+)]
+#[post("/entries/{id}/feedback")]
+async fn rate(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    body: web::Json<FeedbackRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let FeedbackRequest { rating, comment } = body.into_inner();
+    if !matches!(rating.as_str(), "up" | "down") {
+        return Err(ApiError::bad_request("invalid_feedback", format!("rating must be \"up\" or \"down\", got {rating:?}")));
+    }
+    let comment = comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+    if comment.as_ref().is_some_and(|c| c.chars().count() > MAX_COMMENT_CHARS) {
+        return Err(ApiError::bad_request("invalid_feedback", format!("comment must be at most {MAX_COMMENT_CHARS} characters")));
+    }
+    let workspace = workspaces::requested(&req, &app_data).await?;
+    let records = digest::read_log(&workspace)
+        .await
+        .map_err(|e| ApiError::internal("log_unreadable", format!("Failed to read {CONVERSATION_LOG}")).with_detail(format!("{e:#}")))?;
+    let Some(record) = records.into_iter().find(|r| r["entry_id"] == id.as_str()) else {
+        return Err(ApiError::not_found("unknown_entry", format!("No entry {id}")));
+    };
+    if record["source"] != "OPENAI RESPONSE" {
+        return Err(ApiError::bad_request("not_a_response", format!("Entry {id} isn't one of GPT's responses")));
+    }
+    let feedback = Feedback {
+        entry_id: id.into_inner(),
+        rating,
+        comment,
+        at: Utc::now().to_rfc3339(),
+        persona: record["persona"].as_str().unwrap_or_default().to_string(),
+        response: record["text"].as_str().unwrap_or_default().to_string(),
+        workspace,
+    };
+    tracing::info!(entry_id = %feedback.entry_id, rating = %feedback.rating, "response rated");
+    save(feedback.clone())
+        .map_err(|e| ApiError::internal("feedback_unsaved", "Failed to keep the rating").with_detail(format!("{e:#}")))?;
+    Ok(HttpResponse::Ok().json(feedback))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /feedback/stats
+/////////////////////////////////////////////////////////////
+#[derive(Default, Serialize, ToSchema)]
+pub(crate) struct PersonaStats {
+    // "" = system_prompt
+    persona: String,
+    up: u64,
+    down: u64,
+    // The share rated up, 0-1
+    usefulness: f64,
+    comments: u64,
+}
+
+#[utoipa::path(
+    tag = "log",
+    path = "/feedback/stats",
+    params(workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "Ratings per persona", body = [PersonaStats]),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No such workspace (code unknown_workspace)", body = ErrorBody),
+    ),
+)]
+#[get("/feedback/stats")]
+async fn stats(req: HttpRequest, app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let workspace = workspaces::requested(&req, &app_data).await?;
+    let mut personas: BTreeMap<String, PersonaStats> = BTreeMap::new();
+    for feedback in load().into_values().filter(|f| f.workspace == workspace) {
+        let stats = personas
+            .entry(feedback.persona.clone())
+            .or_insert_with(|| PersonaStats { persona: feedback.persona.clone(), ..PersonaStats::default() });
+        if feedback.rating == "up" {
+            stats.up += 1;
+        } else {
+            stats.down += 1;
+        }
+        stats.comments += u64::from(feedback.comment.is_some());
+    }
+    let stats: Vec<PersonaStats> = personas
+        .into_values()
+        .map(|stats| PersonaStats { usefulness: stats.up as f64 / (stats.up + stats.down) as f64, ..stats })
+        .collect();
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(rate).service(stats);
+}
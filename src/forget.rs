@@ -0,0 +1,197 @@
+/////////////////////////////////////////////////////////////
+// src/forget.rs
+//
+// "Silent night, forget the last five minutes": the
+// forget:<minutes> command (see control.rs, voice.rs) deletes
+// what a source recorded in those minutes from everywhere this
+// instance keeps it:
+//   - its records in conversation_log.json (or its workspace's,
+//     see workspaces.rs)
+//   - its audio in audio.save_dir and in the backlog
+//   - its bookmarks
+//   - GPT's conversation history (and its summary, if that
+//     reaches back into them), the latest transcript and
+//     response, and the SSE replay buffer
+// Chunks from before the command that are still on their way
+// through the pipeline are dropped too. Like every command it
+// goes in the audit log; SSE clients get a "forgotten" event
+// ({"audio_source", "since", "records", "audio_files",
+// "bookmarks"}) and the confirmation is spoken through
+// rules.tts_command.
+//
+// Out of reach: copies sync peers have already pulled (see
+// sync.rs), exports and translations, and what webhooks, Slack
+// or Discord were sent.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+use tracing::Instrument;
+
+use crate::sessions::SourceSession;
+use crate::{backlog, bookmarks, memory, rules, workspaces, AppState, TranscriptResponse, CONVERSATION_LOG_LOCK};
+
+// A day
+pub const MAX_MINUTES: u32 = 24 * 60;
+
+/////////////////////////////////////////////////////////////
+// Forgotten
+//
+// What was deleted.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Forgotten {
+    records: usize,
+    audio_files: usize,
+    bookmarks: usize,
+}
+
+impl fmt::Display for Forgotten {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} log records, {} audio files, {} bookmarks",
+            self.records, self.audio_files, self.bookmarks
+        )
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// last
+//
+// Forgets the last `minutes` of `source`.
+/////////////////////////////////////////////////////////////
+pub async fn last(app_data: &AppState, source: &SourceSession, minutes: u32) -> Result<Forgotten> {
+    let since = Utc::now() - chrono::Duration::minutes(minutes as i64);
+    // Before anything else, so nothing in flight lands afterwards
+    source.forgotten_until.send_replace(Some(Instant::now()));
+
+    let (save_dir, backlog_dir, tts_command, log) = {
+        let config = app_data.config.read().await;
+        let log = workspaces::log_path(&workspaces::of_source(&config, &source.name));
+        (config.audio.save_dir.clone(), config.backlog.dir.clone(), config.rules.tts_command.clone(), log)
+    };
+    let name = source.name.clone();
+    let removed = tokio::task::spawn_blocking(move || {
+        remove_records(&log, |record| record["audio_source"] == name.as_str() && recorded_since(record, since))
+    })
+        .await
+        .context("Forgetting log records panicked")??;
+
+    let mut forgotten = Forgotten { records: removed.len(), ..Forgotten::default() };
+    if let Some(dir) = save_dir {
+        forgotten.audio_files += remove_files_since(&Path::new(&dir).join(&source.name), since).await;
+    }
+    forgotten.audio_files += backlog::forget(Path::new(&backlog_dir), &source.name, since).await;
+    forgotten.bookmarks = bookmarks::forget(&source.name, since)?;
+
+    // GPT's history gains a user/assistant pair per logged chunk
+    let transcripts = removed.iter().filter(|r| r["source"] == "Microphone").count();
+    if transcripts > 0 {
+        let mut history = source.conversation_history.lock().await;
+        // Some were folded into the summary already
+        if history.len() < transcripts * 2 {
+            memory::clear(source).await;
+        }
+        let keep = history.len().saturating_sub(transcripts * 2);
+        history.truncate(keep);
+        source.latest.send_replace(TranscriptResponse::default());
+    }
+    let chunk_ids: HashSet<String> =
+        removed.iter().filter_map(|r| r["chunk_id"].as_str()).map(str::to_string).collect();
+    app_data.events.forget(&chunk_ids).await;
+    source.events.forget(&chunk_ids).await;
+
+    tracing::warn!(source = %source.name, minutes, %forgotten, "forgot recent recordings");
+    let event = json!({
+        "audio_source": source.name,
+        "since": since.to_rfc3339(),
+        "records": forgotten.records,
+        "audio_files": forgotten.audio_files,
+        "bookmarks": forgotten.bookmarks,
+    })
+    .to_string();
+    app_data.events.publish_notice("forgotten", event.clone());
+    source.events.publish_notice("forgotten", event);
+
+    let confirmation = match minutes {
+        1 => "Okay, I've forgotten the last minute.".to_string(),
+        60 => "Okay, I've forgotten the last hour.".to_string(),
+        _ => format!("Okay, I've forgotten the last {minutes} minutes."),
+    };
+    let speak = async move {
+        if let Err(e) = rules::speak(&tts_command, &confirmation).await {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't speak the confirmation");
+        }
+    };
+    app_data.tasks.spawn("forget", speak.in_current_span());
+    Ok(forgotten)
+}
+
+// A chunk whose capture began before the last forget
+pub fn dropped(source: &SourceSession, started: Instant) -> bool {
+    source.forgotten_until.borrow().is_some_and(|until| started < until)
+}
+
+// Rewrites the log at `path` (see workspaces.rs) without the
+// records `remove` picks; returns them
+pub(crate) fn remove_records(path: &str, remove: impl Fn(&Value) -> bool) -> Result<Vec<Value>> {
+    let _guard = CONVERSATION_LOG_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {path}")),
+    };
+    let mut kept = String::with_capacity(contents.len());
+    let mut removed = Vec::new();
+    for line in contents.lines() {
+        match serde_json::from_str::<Value>(line) {
+            Ok(record) if remove(&record) => removed.push(record),
+            _ => {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+    }
+    if removed.is_empty() {
+        return Ok(removed);
+    }
+    // Through a temporary file, so a crash never leaves half a log
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, kept).with_context(|| format!("Failed to write {tmp}"))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {path}"))?;
+    Ok(removed)
+}
+
+pub(crate) fn recorded_since(record: &Value, since: DateTime<Utc>) -> bool {
+    record["timestamp"]
+        .as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t >= since)
+}
+
+// Files directly in `dir` written since `since`; returns how many
+// were removed
+async fn remove_files_since(dir: &Path, since: DateTime<Utc>) -> usize {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return 0;
+    };
+    let since = SystemTime::from(since);
+    let mut removed = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let recent = entry.metadata().await.and_then(|m| m.modified()).is_ok_and(|modified| modified >= since);
+        if !recent {
+            continue;
+        }
+        match tokio::fs::remove_file(entry.path()).await {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!(path = %entry.path().display(), error = %e, "couldn't remove saved audio"),
+        }
+    }
+    removed
+}
@@ -0,0 +1,385 @@
+/////////////////////////////////////////////////////////////
+// src/graphql.rs
+//
+// Read-only GraphQL endpoint over the conversation log, so a
+// dashboard can fetch exactly the shape it needs in one request:
+//   POST /graphql          - {"query": "...", "variables": {...}}
+//   GET  /graphql?query=   - same, for quick curl checks
+//
+// Query fields:
+//   entries(...)   - conversation_log.json records, filtered
+//   sessions(...)  - recording sessions built from those records
+//   session(id)    - one session, with its entries
+//   stats          - counts over the log plus live counters
+// Entries pulled from other instances (see sync.rs) are in there
+// too, with their "instance".
+//
+// Example:
+//   { sessions(limit: 5) { id startedAt transcripts
+//       entries(source: "OPENAI RESPONSE") { timestamp text } }
+//     stats { entries chunksProcessed } }
+//
+// The log file is read per request; there is no separate store.
+// ?workspace= reads another workspace's (see workspaces.rs).
+// Errors carry a "code" extension like the REST ErrorBody.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, post, web, HttpRequest};
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, ErrorExtensions, Object, Schema, SimpleObject,
+};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+
+use crate::error::ApiError;
+use crate::{workspaces, AppState};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+// Keep a single request from walking the whole log many times over
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 500;
+
+pub type SilentNightSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/////////////////////////////////////////////////////////////
+// Entry
+//
+// One conversation_log.json record.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, SimpleObject, Clone)]
+pub struct Entry {
+    // RFC 3339
+    timestamp: String,
+    // "Microphone" or "OPENAI RESPONSE"
+    source: String,
+    text: String,
+    #[serde(default)]
+    audio_source: Option<String>,
+    #[serde(default)]
+    session_id: Option<String>,
+    #[serde(default)]
+    chunk_id: Option<String>,
+    // Set for titled sessions, e.g. calendar meetings
+    #[serde(default)]
+    session_title: Option<String>,
+    #[serde(default)]
+    attendees: Option<Vec<String>>,
+    // The instance that logged it, for synced entries; null here
+    #[serde(default)]
+    instance: Option<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// Session
+//
+// Every entry sharing a session_id, i.e. one start..stop of an
+// audio source. Entries from before session IDs existed don't
+// belong to any session.
+/////////////////////////////////////////////////////////////
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Session {
+    id: String,
+    audio_source: Option<String>,
+    // The calendar meeting it recorded, if any
+    title: Option<String>,
+    attendees: Vec<String>,
+    // Timestamps of the first and latest entry
+    started_at: String,
+    last_activity_at: String,
+    transcripts: usize,
+    responses: usize,
+    // Still being recorded
+    active: bool,
+    #[graphql(skip)]
+    records: Vec<Entry>,
+}
+
+#[ComplexObject]
+impl Session {
+    async fn entries(
+        &self,
+        source: Option<String>,
+        contains: Option<String>,
+        limit: Option<usize>,
+        #[graphql(default)] offset: usize,
+    ) -> async_graphql::Result<Vec<Entry>> {
+        let filter = EntryFilter {
+            source,
+            contains,
+            ..EntryFilter::default()
+        };
+        page(self.records.iter().filter(|e| filter.matches(e)).cloned().collect(), limit, offset)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Stats
+/////////////////////////////////////////////////////////////
+#[derive(SimpleObject)]
+pub struct Stats {
+    // From the log file
+    entries: usize,
+    transcripts: usize,
+    responses: usize,
+    sessions: usize,
+    first_entry_at: Option<String>,
+    last_entry_at: Option<String>,
+    // Live counters since startup
+    chunks_processed: u64,
+    recording_sources: Vec<String>,
+    uptime_secs: i64,
+}
+
+/////////////////////////////////////////////////////////////
+// QueryRoot
+/////////////////////////////////////////////////////////////
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Oldest first, unless newestFirst is set
+    #[allow(clippy::too_many_arguments)]
+    async fn entries(
+        &self,
+        ctx: &Context<'_>,
+        source: Option<String>,
+        audio_source: Option<String>,
+        session: Option<String>,
+        // RFC 3339, inclusive
+        since: Option<String>,
+        until: Option<String>,
+        // Case-insensitive substring of the text
+        contains: Option<String>,
+        #[graphql(default)] newest_first: bool,
+        limit: Option<usize>,
+        #[graphql(default)] offset: usize,
+    ) -> async_graphql::Result<Vec<Entry>> {
+        let filter = EntryFilter {
+            source,
+            audio_source,
+            session,
+            since: since.as_deref().map(|t| parse_time("since", t)).transpose()?,
+            until: until.as_deref().map(|t| parse_time("until", t)).transpose()?,
+            contains,
+        };
+        let mut entries: Vec<Entry> = read_log(ctx).await?.into_iter().filter(|e| filter.matches(e)).collect();
+        if newest_first {
+            entries.reverse();
+        }
+        page(entries, limit, offset)
+    }
+
+    // Most recently active first
+    async fn sessions(
+        &self,
+        ctx: &Context<'_>,
+        audio_source: Option<String>,
+        limit: Option<usize>,
+        #[graphql(default)] offset: usize,
+    ) -> async_graphql::Result<Vec<Session>> {
+        let current = current_sessions(ctx).await?;
+        let mut sessions: Vec<Session> = group_sessions(read_log(ctx).await?, &current)
+            .into_iter()
+            .filter(|s| audio_source.is_none() || s.audio_source == audio_source)
+            .collect();
+        sessions.sort_by(|a, b| b.last_activity_at.cmp(&a.last_activity_at));
+        page(sessions, limit, offset)
+    }
+
+    async fn session(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Option<Session>> {
+        let current = current_sessions(ctx).await?;
+        Ok(group_sessions(read_log(ctx).await?, &current).into_iter().find(|s| s.id == id))
+    }
+
+    async fn stats(&self, ctx: &Context<'_>) -> async_graphql::Result<Stats> {
+        let app_data = ctx.data::<web::Data<AppState>>()?;
+        let entries = read_log(ctx).await?;
+
+        let mut recording_sources = Vec::new();
+        for source in app_data.sources.all(app_data).await {
+            if source.state.borrow().is_recording() {
+                recording_sources.push(source.name.clone());
+            }
+        }
+        let mut sessions: Vec<&str> = entries.iter().filter_map(|e| e.session_id.as_deref()).collect();
+        sessions.sort_unstable();
+        sessions.dedup();
+
+        Ok(Stats {
+            transcripts: entries.iter().filter(|e| e.source == "Microphone").count(),
+            responses: entries.iter().filter(|e| e.source == "OPENAI RESPONSE").count(),
+            sessions: sessions.len(),
+            first_entry_at: entries.first().map(|e| e.timestamp.clone()),
+            last_entry_at: entries.last().map(|e| e.timestamp.clone()),
+            entries: entries.len(),
+            chunks_processed: app_data.chunks_processed.load(Ordering::Relaxed),
+            recording_sources,
+            uptime_secs: (Utc::now() - app_data.started_at).num_seconds(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct EntryFilter {
+    source: Option<String>,
+    audio_source: Option<String>,
+    session: Option<String>,
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+    contains: Option<String>,
+}
+
+impl EntryFilter {
+    fn matches(&self, entry: &Entry) -> bool {
+        let time = || DateTime::parse_from_rfc3339(&entry.timestamp).ok();
+        self.source.as_ref().is_none_or(|s| entry.source.eq_ignore_ascii_case(s))
+            && self.audio_source.as_ref().is_none_or(|s| entry.audio_source.as_ref() == Some(s))
+            && self.session.as_ref().is_none_or(|s| entry.session_id.as_ref() == Some(s))
+            && self.since.is_none_or(|since| time().is_some_and(|t| t >= since))
+            && self.until.is_none_or(|until| time().is_some_and(|t| t <= until))
+            && self
+                .contains
+                .as_ref()
+                .is_none_or(|needle| entry.text.to_lowercase().contains(&needle.to_lowercase()))
+    }
+}
+
+fn group_sessions(entries: Vec<Entry>, current: &[String]) -> Vec<Session> {
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for entry in entries {
+        let Some(id) = entry.session_id.clone() else {
+            continue;
+        };
+        let i = *index.entry(id.clone()).or_insert_with(|| {
+            sessions.push(Session {
+                active: current.contains(&id),
+                id,
+                audio_source: entry.audio_source.clone(),
+                title: entry.session_title.clone(),
+                attendees: entry.attendees.clone().unwrap_or_default(),
+                started_at: entry.timestamp.clone(),
+                last_activity_at: String::new(),
+                transcripts: 0,
+                responses: 0,
+                records: Vec::new(),
+            });
+            sessions.len() - 1
+        });
+        let session = &mut sessions[i];
+        match entry.source.as_str() {
+            "Microphone" => session.transcripts += 1,
+            "OPENAI RESPONSE" => session.responses += 1,
+            _ => {}
+        }
+        session.last_activity_at = entry.timestamp.clone();
+        session.records.push(entry);
+    }
+    sessions
+}
+
+async fn current_sessions(ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+    let app_data = ctx.data::<web::Data<AppState>>()?;
+    Ok(app_data.sources.current_session_ids(app_data).await)
+}
+
+// The workspace a request reads (see workspaces.rs)
+struct Workspace(String);
+
+// This instance's records, then synced ones (the default
+// workspace only), by time. Lines that don't parse (e.g. a
+// half-written last line) are skipped.
+async fn read_log(ctx: &Context<'_>) -> async_graphql::Result<Vec<Entry>> {
+    let app_data = ctx.data::<web::Data<AppState>>()?;
+    let Workspace(workspace) = ctx.data::<Workspace>()?;
+    let path = workspaces::log_path(workspace);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            tracing::warn!(error = %e, "GraphQL couldn't read {path}");
+            return Err(error("log_unreadable", format!("Failed to read {path}")));
+        }
+    };
+    let mut entries: Vec<Entry> = contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    let synced = if workspace.is_empty() { app_data.synced.entries().await } else { Vec::new() };
+    if !synced.is_empty() {
+        entries.extend(synced.into_iter().filter_map(|record| serde_json::from_value(record).ok()));
+        entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    }
+    Ok(entries)
+}
+
+fn page<T>(items: Vec<T>, limit: Option<usize>, offset: usize) -> async_graphql::Result<Vec<T>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(error("invalid_limit", format!("limit must be between 1 and {MAX_LIMIT}")));
+    }
+    Ok(items.into_iter().skip(offset).take(limit).collect())
+}
+
+fn parse_time(name: &str, value: &str) -> async_graphql::Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|_| error("invalid_timestamp", format!("{name} must be an RFC 3339 timestamp, got {value:?}")))
+}
+
+fn error(code: &'static str, message: String) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code))
+}
+
+/////////////////////////////////////////////////////////////
+// POST /graphql, GET /graphql
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    params(workspaces::WorkspaceQuery),
+    request_body(content = String, content_type = "application/json", description = "GraphQL request: {\"query\", \"variables\", \"operationName\"}"),
+    responses(
+        (status = 200, description = "GraphQL response: {\"data\", \"errors\"}"),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No such workspace (code unknown_workspace)", body = ErrorBody),
+    ),
+)]
+#[post("/graphql")]
+async fn graphql(
+    http: HttpRequest,
+    app_data: web::Data<AppState>,
+    schema: web::Data<SilentNightSchema>,
+    req: GraphQLRequest,
+) -> Result<GraphQLResponse, ApiError> {
+    let workspace = Workspace(workspaces::requested(&http, &app_data).await?);
+    Ok(schema.execute(req.into_inner().data(app_data).data(workspace)).await.into())
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(("query" = String, Query, description = "GraphQL query"), workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "GraphQL response: {\"data\", \"errors\"}"),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No such workspace (code unknown_workspace)", body = ErrorBody),
+    ),
+)]
+#[get("/graphql")]
+async fn graphql_get(
+    http: HttpRequest,
+    app_data: web::Data<AppState>,
+    schema: web::Data<SilentNightSchema>,
+    req: GraphQLRequest,
+) -> Result<GraphQLResponse, ApiError> {
+    let workspace = Workspace(workspaces::requested(&http, &app_data).await?);
+    Ok(schema.execute(req.into_inner().data(app_data).data(workspace)).await.into())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish();
+    cfg.app_data(web::Data::new(schema)).service(graphql).service(graphql_get);
+}
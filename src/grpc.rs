@@ -0,0 +1,433 @@
+/////////////////////////////////////////////////////////////
+// src/grpc.rs
+//
+// Optional gRPC API (cargo feature "grpc"), for robot and
+// embedded clients that speak gRPC rather than REST. The
+// contract is proto/silentnight.proto: the recording and log
+// endpoints, plus Converse, a bidirectional stream where the
+// client sends WAV chunks it captured itself and gets back each
+// chunk's transcript and GPT response.
+//
+// Settings ([grpc] or env), restart to change:
+//   enabled (GRPC_ENABLED)  default false
+//   port    (GRPC_PORT)     default 50051, on server.bind_addr
+//
+// Served without TLS. When admin.token is set, calls need
+// "authorization: Bearer <token>" metadata (required if web UI
+// login is on, see config validation).
+//
+// The messages and the service dispatch below are what
+// tonic-build would generate, written out by hand so that
+// building doesn't need protoc.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::Result;
+use futures_util::{Stream, StreamExt};
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::server::{Grpc, NamedService};
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status, Streaming};
+use tower::service_fn;
+use tracing::Instrument;
+
+use crate::auth::constant_time_eq;
+use crate::config::DEFAULT_SOURCE;
+use crate::events::LogFilter;
+use crate::sessions::{self, SourceSession};
+use crate::pipeline::ChunkAudio;
+use crate::{audit, listen, logging, single_chunk, supervisor, wav, AppState};
+
+/////////////////////////////////////////////////////////////
+// Messages (see proto/silentnight.proto)
+/////////////////////////////////////////////////////////////
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSourcesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListSourcesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub sources: Vec<SourceStatus>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SourceRequest {
+    #[prost(string, tag = "1")]
+    pub source: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SourceStatus {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(bool, tag = "2")]
+    pub recording: bool,
+    #[prost(string, tag = "3")]
+    pub session_id: String,
+    #[prost(uint64, tag = "4")]
+    pub chunks_processed: u64,
+    #[prost(string, tag = "5")]
+    pub last_error: String,
+    #[prost(string, tag = "6")]
+    pub state: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Transcript {
+    #[prost(string, tag = "1")]
+    pub transcript: String,
+    #[prost(string, tag = "2")]
+    pub gpt_response: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RecordOnceRequest {
+    #[prost(uint32, tag = "1")]
+    pub duration: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchLogRequest {
+    #[prost(string, tag = "1")]
+    pub audio_source: String,
+    #[prost(string, repeated, tag = "2")]
+    pub sources: Vec<String>,
+    #[prost(string, tag = "3")]
+    pub session: String,
+    #[prost(uint64, tag = "4")]
+    pub since: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogRecord {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(string, tag = "2")]
+    pub timestamp: String,
+    #[prost(string, tag = "3")]
+    pub source: String,
+    #[prost(string, tag = "4")]
+    pub text: String,
+    #[prost(string, tag = "5")]
+    pub audio_source: String,
+    #[prost(string, tag = "6")]
+    pub session_id: String,
+    #[prost(string, tag = "7")]
+    pub chunk_id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AudioChunk {
+    #[prost(string, tag = "1")]
+    pub source: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub wav: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ChunkResult {
+    #[prost(string, tag = "1")]
+    pub transcript: String,
+    #[prost(string, tag = "2")]
+    pub gpt_response: String,
+    #[prost(string, tag = "3")]
+    pub error_code: String,
+    #[prost(string, tag = "4")]
+    pub error: String,
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Binds the gRPC port and serves it in a supervised background
+// task; if serving fails, the port is bound again and served
+// after a backoff (see supervisor.rs).
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, bind_addr: &str, port: u16) -> Result<()> {
+    let listener = bind(bind_addr, port)?;
+    tracing::info!(addr = %listener.local_addr()?, "serving gRPC");
+
+    // The first run uses the port bound above, so a startup error is reported
+    let mut first = Some(listener);
+    let bind_addr = bind_addr.to_string();
+    let service_data = app_data.clone();
+    supervisor::spawn_worker(&app_data, "grpc", move || {
+        let listener = first.take();
+        let bind_addr = bind_addr.clone();
+        let app_data = service_data.clone();
+        let shutdown = app_data.tasks.token();
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => bind(&bind_addr, port).map_err(|e| format!("{e:#}"))?,
+            };
+            tonic::transport::Server::builder()
+                .add_service(SilentNightServer { app_data })
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown.cancelled_owned())
+                .await
+                .map_err(|e| format!("gRPC server stopped: {e}"))
+        }
+    });
+    Ok(())
+}
+
+fn bind(bind_addr: &str, port: u16) -> Result<tokio::net::TcpListener> {
+    let listener = listen::tcp_listener(bind_addr, port)?;
+    listener.set_nonblocking(true)?;
+    Ok(tokio::net::TcpListener::from_std(listener)?)
+}
+
+/////////////////////////////////////////////////////////////
+// SilentNightServer
+/////////////////////////////////////////////////////////////
+#[derive(Clone)]
+struct SilentNightServer {
+    app_data: web::Data<AppState>,
+}
+
+impl SilentNightServer {
+    async fn authorize(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        let token = self.app_data.config.read().await.admin.token.clone();
+        if token.is_empty() {
+            return Ok(());
+        }
+        let authorized = metadata
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()));
+        if !authorized {
+            return Err(Status::unauthenticated("admin_token_required: Admin token required"));
+        }
+        Ok(())
+    }
+
+    async fn source(&self, name: &str) -> Result<Arc<SourceSession>, Status> {
+        let name = if name.is_empty() { DEFAULT_SOURCE } else { name };
+        match self.app_data.sources.get(&self.app_data, name).await {
+            Some(source) => Ok(source),
+            None => Err(sessions::unknown_source(name).into()),
+        }
+    }
+
+    async fn list_sources(&self, request: Request<ListSourcesRequest>) -> Result<Response<ListSourcesResponse>, Status> {
+        self.authorize(request.metadata()).await?;
+        let mut sources = Vec::new();
+        for source in self.app_data.sources.all(&self.app_data).await {
+            sources.push(source_status(&source).await);
+        }
+        Ok(Response::new(ListSourcesResponse { sources }))
+    }
+
+    async fn start_recording(&self, request: Request<SourceRequest>) -> Result<Response<SourceStatus>, Status> {
+        self.authorize(request.metadata()).await?;
+        let source = self.source(&request.get_ref().source).await?;
+        let started = sessions::start_source(&self.app_data, source.clone()).await;
+        audit::record(&self.app_data, "grpc", "grpc", &format!("start {}", source.name), started.is_ok()).await;
+        started?;
+        Ok(Response::new(source_status(&source).await))
+    }
+
+    async fn stop_recording(&self, request: Request<SourceRequest>) -> Result<Response<SourceStatus>, Status> {
+        self.authorize(request.metadata()).await?;
+        let source = self.source(&request.get_ref().source).await?;
+        sessions::stop_source(&self.app_data, &source).await;
+        audit::record(&self.app_data, "grpc", "grpc", &format!("stop {}", source.name), true).await;
+        Ok(Response::new(source_status(&source).await))
+    }
+
+    async fn get_transcript(&self, request: Request<SourceRequest>) -> Result<Response<Transcript>, Status> {
+        self.authorize(request.metadata()).await?;
+        let source = self.source(&request.get_ref().source).await?;
+        let latest = source.latest.borrow().clone();
+        let transcript = Transcript {
+            transcript: latest.transcript,
+            gpt_response: latest.gpt_response,
+        };
+        Ok(Response::new(transcript))
+    }
+
+    async fn record_once(&self, request: Request<RecordOnceRequest>) -> Result<Response<Transcript>, Status> {
+        self.authorize(request.metadata()).await?;
+        let duration = match request.get_ref().duration {
+            0 => self.app_data.config.read().await.audio.chunk_secs,
+            secs => secs,
+        };
+        if !(1..=60).contains(&duration) {
+            return Err(Status::invalid_argument(
+                "invalid_duration: duration must be between 1 and 60 seconds",
+            ));
+        }
+        let source = self.source(DEFAULT_SOURCE).await?;
+        let chunk = single_chunk(&self.app_data, &source, ChunkAudio::Record(duration)).await?;
+        Ok(Response::new(Transcript {
+            transcript: chunk.transcript,
+            gpt_response: chunk.gpt_response,
+        }))
+    }
+
+    async fn watch_log(&self, request: Request<WatchLogRequest>) -> Result<Response<ResponseStream<LogRecord>>, Status> {
+        self.authorize(request.metadata()).await?;
+        let watch = request.into_inner();
+        let filter = LogFilter::new(
+            (!watch.sources.is_empty()).then(|| watch.sources.join(",")),
+            (!watch.session.is_empty()).then_some(watch.session),
+        );
+        let since = (watch.since > 0).then_some(watch.since);
+
+        let events = if watch.audio_source.is_empty() {
+            let current = self.app_data.sources.current_session_ids(&self.app_data).await;
+            self.app_data.events.subscribe(since, filter, current).await.boxed()
+        } else {
+            let source = self.source(&watch.audio_source).await?;
+            let current = source.session_id.borrow().iter().cloned().collect();
+            source.events.subscribe(since, filter, current).await.boxed()
+        };
+
+        let records = events.filter_map(|res| async move {
+            match res {
+                Ok(ev) => Some(Ok(log_record(ev.id, &ev.data))),
+                Err(e) => {
+                    tracing::warn!(error = %e, "gRPC log watcher fell behind");
+                    None
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(records)))
+    }
+
+    async fn converse(&self, request: Request<Streaming<AudioChunk>>) -> Result<Response<ResponseStream<ChunkResult>>, Status> {
+        self.authorize(request.metadata()).await?;
+        let mut inbound = request.into_inner();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let server = self.clone();
+
+        // One chunk at a time, in order, like the recording loop
+        tokio::spawn(
+            async move {
+                while let Some(message) = inbound.next().await {
+                    let chunk = match message {
+                        Ok(chunk) => chunk,
+                        Err(status) => {
+                            tracing::debug!(error = %status, "Converse stream closed by client");
+                            break;
+                        }
+                    };
+                    let result = server.converse_chunk(chunk).await;
+                    if tx.send(Ok(result)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn converse_chunk(&self, chunk: AudioChunk) -> ChunkResult {
+        let failed = |code: &str, error: String| ChunkResult {
+            error_code: code.to_string(),
+            error,
+            ..ChunkResult::default()
+        };
+        if let Err(e) = wav::parse(&chunk.wav) {
+            return failed("invalid_audio", format!("AudioChunk.wav: {e}"));
+        }
+        let source = match self.source(&chunk.source).await {
+            Ok(source) => source,
+            Err(status) => return failed("unknown_source", status.message().to_string()),
+        };
+
+        match single_chunk(&self.app_data, &source, ChunkAudio::Provided(chunk.wav.into())).await {
+            Ok(result) => ChunkResult {
+                transcript: result.transcript,
+                gpt_response: result.gpt_response,
+                ..ChunkResult::default()
+            },
+            Err(e) => failed(e.code(), e.to_string()),
+        }
+    }
+}
+
+async fn source_status(source: &SourceSession) -> SourceStatus {
+    let state = source.state.borrow().clone();
+    let session_id = source.session_id.borrow().clone();
+    SourceStatus {
+        name: source.name.clone(),
+        recording: state.is_recording(),
+        state: state.name().to_string(),
+        session_id: session_id.unwrap_or_default(),
+        chunks_processed: source.chunks_processed.load(std::sync::atomic::Ordering::Relaxed),
+        last_error: source.last_error.lock().await.clone().unwrap_or_default(),
+    }
+}
+
+// A published record is the conversation_log.json line
+fn log_record(id: u64, data: &str) -> LogRecord {
+    let record: serde_json::Value = serde_json::from_str(data).unwrap_or_default();
+    let field = |name: &str| record[name].as_str().unwrap_or_default().to_string();
+    LogRecord {
+        id,
+        timestamp: field("timestamp"),
+        source: field("source"),
+        text: field("text"),
+        audio_source: field("audio_source"),
+        session_id: field("session_id"),
+        chunk_id: field("chunk_id"),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Dispatch
+//
+// Routes "/silentnight.v1.SilentNight/<Method>" to the methods
+// above, with a tracing span per call.
+/////////////////////////////////////////////////////////////
+impl NamedService for SilentNightServer {
+    const NAME: &'static str = "silentnight.v1.SilentNight";
+}
+
+impl tonic::codegen::Service<tonic::codegen::http::Request<BoxBody>> for SilentNightServer {
+    type Response = tonic::codegen::http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: tonic::codegen::http::Request<BoxBody>) -> Self::Future {
+        let server = self.clone();
+        let method = req.uri().path().rsplit('/').next().unwrap_or_default().to_string();
+        let span = tracing::info_span!("grpc", request_id = %logging::new_id(), method = %method);
+
+        Box::pin(
+            async move {
+                let s = &server;
+                let response = match method.as_str() {
+                    "ListSources" => Grpc::new(ProstCodec::default()).unary(service_fn(|r| s.list_sources(r)), req).await,
+                    "StartRecording" => Grpc::new(ProstCodec::default()).unary(service_fn(|r| s.start_recording(r)), req).await,
+                    "StopRecording" => Grpc::new(ProstCodec::default()).unary(service_fn(|r| s.stop_recording(r)), req).await,
+                    "GetTranscript" => Grpc::new(ProstCodec::default()).unary(service_fn(|r| s.get_transcript(r)), req).await,
+                    "RecordOnce" => Grpc::new(ProstCodec::default()).unary(service_fn(|r| s.record_once(r)), req).await,
+                    "WatchLog" => {
+                        Grpc::new(ProstCodec::default()).server_streaming(service_fn(|r| s.watch_log(r)), req).await
+                    }
+                    "Converse" => Grpc::new(ProstCodec::default()).streaming(service_fn(|r| s.converse(r)), req).await,
+                    _ => Status::unimplemented(format!("Unknown method {method}")).into_http(),
+                };
+                tracing::info!(grpc_status = ?response.headers().get("grpc-status"), "gRPC call finished");
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,22 @@
+/////////////////////////////////////////////////////////////
+// src/hex.rs
+//
+// Lowercase hex for bytes that live in text: Discord's public
+// key and signatures, HomeKit's keys and pairings in its state
+// file, and webhook signatures.
+/////////////////////////////////////////////////////////////
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// None unless every two characters are a hex byte
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,1141 @@
+/////////////////////////////////////////////////////////////
+// src/homekit.rs
+//
+// HomeKit accessory (cargo feature "homekit", [homekit]
+// enabled): recording on homekit.source as a switch, so the Pi
+// shows up in the Home app next to everything else in the room
+// and "Hey Siri, turn off SilentNight" works. Speaks HAP over IP
+// itself, on its own port (homekit.port):
+//   - advertised over mDNS as _hap._tcp, named homekit.name
+//   - pair setup with homekit.setup_code (SRP-6a, 3072-bit
+//     group); empty = one is generated, kept in the state file
+//     and logged at startup while nobody is paired
+//   - pair verify, then ChaCha20-Poly1305 framed sessions
+//   - GET /accessories, GET/PUT /characteristics (with events,
+//     so the Home app follows recording started from elsewhere)
+//     and POST /pairings for sharing with other people's iPhones
+// The accessory ID, its long-term key and the pairings are kept
+// in homekit.state_file; delete it to start over (remove the
+// accessory from the Home app too). Settings are restart-only.
+//
+// Not certified, so the Home app asks to confirm adding an
+// "Uncertified Accessory", like with any DIY one.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use num_bigint::BigUint;
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, X25519};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use ring::{digest, hkdf};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::Instrument;
+
+use crate::config::HomeKitSettings;
+use crate::control::{self, Command};
+use crate::sessions::SourceSession;
+use crate::{hex, listen, AppState};
+
+const SERVICE_TYPE: &str = "_hap._tcp.local.";
+// "Switch", for the icon while adding it
+const CATEGORY: &str = "8";
+// The one accessory, and the instance IDs of what it has
+const AID: u64 = 1;
+const IID_IDENTIFY: u64 = 2;
+const IID_ON: u64 = 9;
+// Longest plaintext in one encrypted frame
+const FRAME_LEN: usize = 1024;
+const TAG_LEN: usize = 16;
+// Failed pair setups before it refuses more, as HAP asks
+const MAX_TRIES: u32 = 100;
+// Longest request we'll buffer
+const MAX_REQUEST: usize = 64 * 1024;
+
+// TLV8 types and errors used in pairing
+const TLV_METHOD: u8 = 0;
+const TLV_IDENTIFIER: u8 = 1;
+const TLV_SALT: u8 = 2;
+const TLV_PUBLIC_KEY: u8 = 3;
+const TLV_PROOF: u8 = 4;
+const TLV_ENCRYPTED_DATA: u8 = 5;
+const TLV_STATE: u8 = 6;
+const TLV_ERROR: u8 = 7;
+const TLV_SIGNATURE: u8 = 10;
+const TLV_PERMISSIONS: u8 = 11;
+const TLV_SEPARATOR: u8 = 0xff;
+const ERROR_UNKNOWN: u8 = 1;
+const ERROR_AUTHENTICATION: u8 = 2;
+const ERROR_MAX_TRIES: u8 = 5;
+const ERROR_UNAVAILABLE: u8 = 6;
+const METHOD_ADD_PAIRING: u8 = 3;
+const METHOD_REMOVE_PAIRING: u8 = 4;
+const METHOD_LIST_PAIRINGS: u8 = 5;
+
+// HAP status codes, in /characteristics replies
+const STATUS_PRIVILEGES: i32 = -70401;
+const STATUS_COMMUNICATION: i32 = -70402;
+const STATUS_READ_ONLY: i32 = -70404;
+const STATUS_NO_NOTIFICATION: i32 = -70406;
+const STATUS_NOT_FOUND: i32 = -70409;
+const STATUS_INVALID_VALUE: i32 = -70410;
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Loads (or creates) the accessory's identity, binds its port
+// and starts serving and advertising it.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: HomeKitSettings, bind_addr: &str) -> Result<()> {
+    let path = PathBuf::from(&settings.state_file);
+    let state = State::load_or_create(&path)?;
+    let key_pair = hex::decode(&state.secret_key)
+        .and_then(|pkcs8| Ed25519KeyPair::from_pkcs8(&pkcs8).ok())
+        .with_context(|| format!("{} has an invalid secret_key; delete it to start over", path.display()))?;
+    let listener = listen::tcp_listener(bind_addr, settings.port)?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    tracing::info!(addr = %listener.local_addr()?, name = %settings.name, source = %settings.source, "serving HomeKit");
+
+    let setup_code = if settings.setup_code.is_empty() { state.setup_code.clone() } else { settings.setup_code.clone() };
+    let paired = !state.pairings.is_empty();
+    if !paired {
+        tracing::info!(setup_code = %setup_code, "not paired yet; add \"{}\" in the Home app with this setup code", settings.name);
+    }
+    let accessory = Arc::new(Accessory {
+        app_data: app_data.clone(),
+        advert: Advert::start(&settings, &state.device_id),
+        settings,
+        setup_code,
+        key_pair,
+        state: AsyncMutex::new(state),
+        path,
+        failed_setups: AtomicU32::new(0),
+    });
+    accessory.advertise(paired);
+
+    let shutdown = app_data.tasks.token();
+    let span = tracing::info_span!(parent: None, "homekit");
+    let serve = async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "couldn't accept a HomeKit connection");
+                        continue;
+                    }
+                },
+                _ = shutdown.cancelled() => break,
+            };
+            let accessory = accessory.clone();
+            let span = tracing::info_span!("controller", peer = %peer);
+            tokio::spawn(
+                async move {
+                    tracing::debug!("connected");
+                    match accessory.serve(stream).await {
+                        Ok(()) => tracing::debug!("disconnected"),
+                        Err(e) => tracing::debug!(error = %format!("{e:#}"), "connection dropped"),
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    };
+    app_data.tasks.spawn("homekit", serve.instrument(span));
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// State
+//
+// homekit.state_file: who we are, and who may control us.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, Deserialize)]
+struct State {
+    // Looks like a MAC address; controllers know us by it
+    device_id: String,
+    // Long-term Ed25519 key, PKCS#8 in hex
+    secret_key: String,
+    // Used when homekit.setup_code is empty
+    setup_code: String,
+    // By controller pairing ID
+    #[serde(default)]
+    pairings: BTreeMap<String, Pairing>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Pairing {
+    // Ed25519, hex
+    public_key: String,
+    admin: bool,
+}
+
+impl State {
+    fn load_or_create(path: &Path) -> Result<State> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                    .map_err(|_| anyhow::anyhow!("Failed to generate the accessory's key"))?;
+                let mut rng = rand::thread_rng();
+                let device_id: Vec<String> = (0..6).map(|_| format!("{:02X}", rng.gen::<u8>())).collect();
+                let state = State {
+                    device_id: device_id.join(":"),
+                    secret_key: hex::encode(pkcs8.as_ref()),
+                    setup_code: new_setup_code(),
+                    pairings: BTreeMap::new(),
+                };
+                state.save_sync(path)?;
+                tracing::info!(path = %path.display(), device_id = %state.device_id, "created a new HomeKit identity");
+                Ok(state)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    // Through a temporary file, so a crash never loses the pairings
+    fn save_sync(&self, path: &Path) -> Result<()> {
+        let tmp = tmp_path(path);
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// "XXX-XX-XXX", avoiding the ones HAP forbids
+fn new_setup_code() -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let digits: String = (0..8).map(|_| char::from(b'0' + rng.gen_range(0..10))).collect();
+        let code = format!("{}-{}-{}", &digits[..3], &digits[3..5], &digits[5..]);
+        if crate::config::valid_setup_code(&code) {
+            return code;
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Advert
+//
+// The _hap._tcp service. Its "sf" flag tells the Home app
+// whether we can still be added, so it's registered again when
+// that changes.
+/////////////////////////////////////////////////////////////
+struct Advert {
+    daemon: ServiceDaemon,
+    host_name: String,
+    device_id: String,
+}
+
+impl Advert {
+    fn start(settings: &HomeKitSettings, device_id: &str) -> Option<Advert> {
+        match ServiceDaemon::new() {
+            Ok(daemon) => Some(Advert {
+                daemon,
+                host_name: format!("{}.local.", settings.name.replace(|c: char| !c.is_ascii_alphanumeric(), "-")),
+                device_id: device_id.to_string(),
+            }),
+            Err(e) => {
+                tracing::warn!(error = %e, "mDNS unavailable; the Home app won't find the accessory");
+                None
+            }
+        }
+    }
+
+    fn register(&self, name: &str, port: u16, paired: bool) -> Result<()> {
+        let properties = [
+            ("c#", "1"),
+            ("ff", "0"),
+            ("id", self.device_id.as_str()),
+            ("md", name),
+            ("pv", "1.1"),
+            ("s#", "1"),
+            ("sf", if paired { "0" } else { "1" }),
+            ("ci", CATEGORY),
+        ];
+        let service = ServiceInfo::new(SERVICE_TYPE, name, &self.host_name, "", port, &properties[..])
+            .context("Invalid mDNS service info")?
+            .enable_addr_auto();
+        self.daemon.register(service).context("Failed to register the mDNS service")
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Accessory
+/////////////////////////////////////////////////////////////
+struct Accessory {
+    app_data: web::Data<AppState>,
+    settings: HomeKitSettings,
+    advert: Option<Advert>,
+    setup_code: String,
+    key_pair: Ed25519KeyPair,
+    state: AsyncMutex<State>,
+    path: PathBuf,
+    failed_setups: AtomicU32,
+}
+
+// One controller's connection
+#[derive(Default)]
+struct Connection {
+    // Received, not yet decrypted (part of a frame)
+    raw: Vec<u8>,
+    // Received and decrypted, not yet a whole request
+    plain: Vec<u8>,
+    setup: PairSetup,
+    verify: Option<PairVerify>,
+    session: Option<Session>,
+    // Installed once the reply to pair verify has gone out in clear
+    next_session: Option<Session>,
+    // Wants events for the switch
+    events: bool,
+    // What it was last told the switch is
+    reported: Option<bool>,
+    close: bool,
+}
+
+#[derive(Default)]
+enum PairSetup {
+    #[default]
+    Idle,
+    // M2 sent
+    Started(Srp),
+    // M4 sent; the SRP session key
+    Proven(Vec<u8>),
+}
+
+struct PairVerify {
+    shared: Vec<u8>,
+    accessory_key: Vec<u8>,
+    controller_key: Vec<u8>,
+}
+
+struct Session {
+    controller: String,
+    read_key: LessSafeKey,
+    write_key: LessSafeKey,
+    read_count: u64,
+    write_count: u64,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn tlv(items: &[(u8, &[u8])]) -> Response {
+        Response { status: "200 OK", content_type: "application/pairing+tlv8", body: tlv_encode(items) }
+    }
+
+    fn json(status: &'static str, body: Value) -> Response {
+        Response { status, content_type: "application/hap+json", body: body.to_string().into_bytes() }
+    }
+
+    fn no_content() -> Response {
+        Response { status: "204 No Content", content_type: "", body: Vec::new() }
+    }
+}
+
+impl Accessory {
+    fn advertise(&self, paired: bool) {
+        let Some(advert) = &self.advert else { return };
+        if let Err(e) = advert.register(&self.settings.name, self.settings.port, paired) {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't advertise the accessory");
+        }
+    }
+
+    async fn source(&self) -> Option<Arc<SourceSession>> {
+        self.app_data.sources.get(&self.app_data, &self.settings.source).await
+    }
+
+    async fn serve(&self, mut stream: TcpStream) -> Result<()> {
+        let source = self.source().await.with_context(|| format!("there's no audio source named {}", self.settings.source))?;
+        let mut states = source.state.subscribe();
+        let shutdown = self.app_data.tasks.token();
+        let mut conn = Connection::default();
+        let mut chunk = [0u8; 4096];
+        loop {
+            tokio::select! {
+                read = stream.read(&mut chunk) => {
+                    let n = read.context("read failed")?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    conn.received(&chunk[..n])?;
+                    while let Some(request) = parse_request(&mut conn.plain)? {
+                        let response = self.handle(&mut conn, request).await;
+                        let bytes = response_bytes("HTTP/1.1", &response);
+                        stream.write_all(&conn.seal(bytes)).await.context("write failed")?;
+                        if let Some(session) = conn.next_session.take() {
+                            conn.session = Some(session);
+                        }
+                        if conn.close {
+                            return Ok(());
+                        }
+                    }
+                    if conn.raw.len() + conn.plain.len() > MAX_REQUEST {
+                        anyhow::bail!("request too long");
+                    }
+                }
+                changed = states.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    let on = states.borrow_and_update().is_recording();
+                    if conn.events && conn.session.is_some() && conn.reported != Some(on) {
+                        conn.reported = Some(on);
+                        let event = Response::json("200 OK", json!({ "characteristics": [{ "aid": AID, "iid": IID_ON, "value": on }] }));
+                        stream.write_all(&conn.seal(response_bytes("EVENT/1.0", &event))).await.context("write failed")?;
+                    }
+                }
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    async fn handle(&self, conn: &mut Connection, request: Request) -> Response {
+        tracing::debug!(method = %request.method, path = %request.path, "request");
+        if let Some(session) = &conn.session {
+            // Removed while connected (from another controller)
+            if !self.state.lock().await.pairings.contains_key(&session.controller) {
+                conn.close = true;
+                return Response::json("470 Connection Authorization Required", json!({ "status": STATUS_PRIVILEGES }));
+            }
+        }
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/pair-setup") => self.pair_setup(conn, &request.body).await,
+            ("POST", "/pair-verify") => self.pair_verify(conn, &request.body).await,
+            ("POST", "/identify") => {
+                if !self.state.lock().await.pairings.is_empty() {
+                    return Response::json("400 Bad Request", json!({ "status": STATUS_PRIVILEGES }));
+                }
+                tracing::info!("identify requested");
+                Response::no_content()
+            }
+            _ if conn.session.is_none() => {
+                Response::json("470 Connection Authorization Required", json!({ "status": STATUS_PRIVILEGES }))
+            }
+            ("GET", "/accessories") => Response::json("200 OK", self.accessories(conn).await),
+            ("GET", "/characteristics") => self.read(conn, &request.query).await,
+            ("PUT", "/characteristics") => self.write(conn, &request.body).await,
+            ("POST", "/pairings") => self.pairings(conn, &request.body).await,
+            _ => Response::json("404 Not Found", json!({ "status": STATUS_NOT_FOUND })),
+        }
+    }
+
+    /////////////////////////////////////////////////////////
+    // Pair setup (M1-M6): SRP with the setup code, then an
+    // exchange of long-term keys.
+    /////////////////////////////////////////////////////////
+    async fn pair_setup(&self, conn: &mut Connection, body: &[u8]) -> Response {
+        let Some(items) = tlv_decode(body) else {
+            return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+        };
+        match tlv_get(&items, TLV_STATE).and_then(|s| s.first().copied()) {
+            Some(1) => {
+                conn.setup = PairSetup::Idle;
+                if !self.state.lock().await.pairings.is_empty() {
+                    return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNAVAILABLE])]);
+                }
+                if self.failed_setups.load(Ordering::SeqCst) >= MAX_TRIES {
+                    return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_MAX_TRIES])]);
+                }
+                let srp = Srp::new(&self.setup_code);
+                let response = Response::tlv(&[(TLV_STATE, &[2]), (TLV_PUBLIC_KEY, &srp.b_pub), (TLV_SALT, &srp.salt)]);
+                conn.setup = PairSetup::Started(srp);
+                response
+            }
+            Some(3) => {
+                let PairSetup::Started(srp) = std::mem::take(&mut conn.setup) else {
+                    return Response::tlv(&[(TLV_STATE, &[4]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                };
+                let (Some(a_pub), Some(proof)) = (tlv_get(&items, TLV_PUBLIC_KEY), tlv_get(&items, TLV_PROOF)) else {
+                    return Response::tlv(&[(TLV_STATE, &[4]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                };
+                match srp.verify(a_pub, proof) {
+                    Some((key, accessory_proof)) => {
+                        conn.setup = PairSetup::Proven(key);
+                        Response::tlv(&[(TLV_STATE, &[4]), (TLV_PROOF, &accessory_proof)])
+                    }
+                    None => {
+                        self.failed_setups.fetch_add(1, Ordering::SeqCst);
+                        tracing::warn!("pairing failed: wrong setup code");
+                        Response::tlv(&[(TLV_STATE, &[4]), (TLV_ERROR, &[ERROR_AUTHENTICATION])])
+                    }
+                }
+            }
+            Some(5) => {
+                let PairSetup::Proven(key) = std::mem::take(&mut conn.setup) else {
+                    return Response::tlv(&[(TLV_STATE, &[6]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                };
+                match self.exchange_keys(&key, &items).await {
+                    Ok(Some(sealed)) => Response::tlv(&[(TLV_STATE, &[6]), (TLV_ENCRYPTED_DATA, &sealed)]),
+                    Ok(None) => {
+                        tracing::warn!("pairing failed: another controller paired first");
+                        Response::tlv(&[(TLV_STATE, &[6]), (TLV_ERROR, &[ERROR_UNAVAILABLE])])
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %format!("{e:#}"), "pairing failed");
+                        Response::tlv(&[(TLV_STATE, &[6]), (TLV_ERROR, &[ERROR_AUTHENTICATION])])
+                    }
+                }
+            }
+            _ => Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]),
+        }
+    }
+
+    // M5 -> M6: checks and keeps the controller's key, and
+    // returns ours, sealed; None if someone else paired first
+    async fn exchange_keys(&self, key: &[u8], items: &[(u8, Vec<u8>)]) -> Result<Option<Vec<u8>>> {
+        let session_key = hkdf_sha512(key, "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+        let sealed = tlv_get(items, TLV_ENCRYPTED_DATA).context("no encrypted data")?;
+        let sub = open(&session_key, *b"PS-Msg05", &[], sealed).context("couldn't decrypt M5")?;
+        let sub = tlv_decode(&sub).context("malformed M5")?;
+        let (Some(id), Some(public_key), Some(signature)) =
+            (tlv_get(&sub, TLV_IDENTIFIER), tlv_get(&sub, TLV_PUBLIC_KEY), tlv_get(&sub, TLV_SIGNATURE))
+        else {
+            anyhow::bail!("M5 is missing the identifier, key or signature");
+        };
+        let controller_x = hkdf_sha512(key, "Pair-Setup-Controller-Sign-Salt", "Pair-Setup-Controller-Sign-Info");
+        let signed = [&controller_x[..], id, public_key].concat();
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&signed, signature)
+            .map_err(|_| anyhow::anyhow!("the controller's signature doesn't match"))?;
+        let id = String::from_utf8_lossy(id).to_string();
+        // Checked again at M1's "nobody paired yet": another
+        // controller may have finished in between
+        if !self.add_pairing(&id, public_key, true, BTreeMap::is_empty).await? {
+            return Ok(None);
+        }
+        tracing::info!(controller = %id, "paired");
+
+        let device_id = self.state.lock().await.device_id.clone();
+        let accessory_x = hkdf_sha512(key, "Pair-Setup-Accessory-Sign-Salt", "Pair-Setup-Accessory-Sign-Info");
+        let own_key = self.key_pair.public_key().as_ref();
+        let signature = self.key_pair.sign(&[&accessory_x[..], device_id.as_bytes(), own_key].concat());
+        let sub = tlv_encode(&[
+            (TLV_IDENTIFIER, device_id.as_bytes()),
+            (TLV_PUBLIC_KEY, own_key),
+            (TLV_SIGNATURE, signature.as_ref()),
+        ]);
+        Ok(Some(seal(&session_key, *b"PS-Msg06", &[], &sub)))
+    }
+
+    /////////////////////////////////////////////////////////
+    // Pair verify (M1-M4): an X25519 exchange signed with both
+    // long-term keys; the shared secret keys the session.
+    /////////////////////////////////////////////////////////
+    async fn pair_verify(&self, conn: &mut Connection, body: &[u8]) -> Response {
+        let Some(items) = tlv_decode(body) else {
+            return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+        };
+        match tlv_get(&items, TLV_STATE).and_then(|s| s.first().copied()) {
+            Some(1) => match self.start_verify(&items).await {
+                Ok((verify, response)) => {
+                    conn.verify = Some(verify);
+                    response
+                }
+                Err(e) => {
+                    tracing::debug!(error = %format!("{e:#}"), "pair verify failed");
+                    Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])])
+                }
+            },
+            Some(3) => {
+                let Some(verify) = conn.verify.take() else {
+                    return Response::tlv(&[(TLV_STATE, &[4]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                };
+                match self.finish_verify(&verify, &items).await {
+                    Ok(session) => {
+                        tracing::debug!(controller = %session.controller, "verified");
+                        conn.next_session = Some(session);
+                        Response::tlv(&[(TLV_STATE, &[4])])
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %format!("{e:#}"), "pair verify failed");
+                        Response::tlv(&[(TLV_STATE, &[4]), (TLV_ERROR, &[ERROR_AUTHENTICATION])])
+                    }
+                }
+            }
+            _ => Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]),
+        }
+    }
+
+    async fn start_verify(&self, items: &[(u8, Vec<u8>)]) -> Result<(PairVerify, Response)> {
+        let controller_key = tlv_get(items, TLV_PUBLIC_KEY).context("no public key")?.to_vec();
+        let rng = SystemRandom::new();
+        let private = EphemeralPrivateKey::generate(&X25519, &rng).map_err(|_| anyhow::anyhow!("key generation failed"))?;
+        let accessory_key = private.compute_public_key().map_err(|_| anyhow::anyhow!("key generation failed"))?.as_ref().to_vec();
+        let shared = agreement::agree_ephemeral(private, &agreement::UnparsedPublicKey::new(&X25519, &controller_key), |k| k.to_vec())
+            .map_err(|_| anyhow::anyhow!("invalid public key"))?;
+
+        let device_id = self.state.lock().await.device_id.clone();
+        let signature = self.key_pair.sign(&[&accessory_key[..], device_id.as_bytes(), &controller_key].concat());
+        let sub = tlv_encode(&[(TLV_IDENTIFIER, device_id.as_bytes()), (TLV_SIGNATURE, signature.as_ref())]);
+        let session_key = hkdf_sha512(&shared, "Pair-Verify-Encrypt-Salt", "Pair-Verify-Encrypt-Info");
+        let sealed = seal(&session_key, *b"PV-Msg02", &[], &sub);
+        let response = Response::tlv(&[(TLV_STATE, &[2]), (TLV_PUBLIC_KEY, &accessory_key), (TLV_ENCRYPTED_DATA, &sealed)]);
+        Ok((PairVerify { shared, accessory_key, controller_key }, response))
+    }
+
+    async fn finish_verify(&self, verify: &PairVerify, items: &[(u8, Vec<u8>)]) -> Result<Session> {
+        let session_key = hkdf_sha512(&verify.shared, "Pair-Verify-Encrypt-Salt", "Pair-Verify-Encrypt-Info");
+        let sealed = tlv_get(items, TLV_ENCRYPTED_DATA).context("no encrypted data")?;
+        let sub = open(&session_key, *b"PV-Msg03", &[], sealed).context("couldn't decrypt M3")?;
+        let sub = tlv_decode(&sub).context("malformed M3")?;
+        let (Some(id), Some(signature)) = (tlv_get(&sub, TLV_IDENTIFIER), tlv_get(&sub, TLV_SIGNATURE)) else {
+            anyhow::bail!("M3 is missing the identifier or signature");
+        };
+        let controller = String::from_utf8_lossy(id).to_string();
+        let public_key = self
+            .state
+            .lock()
+            .await
+            .pairings
+            .get(&controller)
+            .and_then(|p| hex::decode(&p.public_key))
+            .with_context(|| format!("{controller} isn't paired"))?;
+        let signed = [&verify.controller_key[..], id, &verify.accessory_key].concat();
+        UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(&signed, signature)
+            .map_err(|_| anyhow::anyhow!("{controller}'s signature doesn't match"))?;
+        Ok(Session {
+            controller,
+            read_key: aead_key(&hkdf_sha512(&verify.shared, "Control-Salt", "Control-Write-Encryption-Key")),
+            write_key: aead_key(&hkdf_sha512(&verify.shared, "Control-Salt", "Control-Read-Encryption-Key")),
+            read_count: 0,
+            write_count: 0,
+        })
+    }
+
+    /////////////////////////////////////////////////////////
+    // POST /pairings: add, remove and list, for admins
+    /////////////////////////////////////////////////////////
+    async fn pairings(&self, conn: &mut Connection, body: &[u8]) -> Response {
+        let Some(items) = tlv_decode(body) else {
+            return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+        };
+        let controller = conn.session.as_ref().map(|s| s.controller.clone()).unwrap_or_default();
+        let is_admin = self.state.lock().await.pairings.get(&controller).is_some_and(|p| p.admin);
+        if !is_admin {
+            return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_AUTHENTICATION])]);
+        }
+        let id = tlv_get(&items, TLV_IDENTIFIER).map(|id| String::from_utf8_lossy(id).to_string());
+        match (tlv_get(&items, TLV_METHOD).and_then(|m| m.first().copied()), id) {
+            (Some(METHOD_ADD_PAIRING), Some(id)) => {
+                let Some(public_key) = tlv_get(&items, TLV_PUBLIC_KEY) else {
+                    return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                };
+                let admin = tlv_get(&items, TLV_PERMISSIONS).is_some_and(|p| p.first() == Some(&1));
+                // An ID already paired keeps its key
+                let key = hex::encode(public_key);
+                let same_key = |pairings: &BTreeMap<String, Pairing>| pairings.get(&id).is_none_or(|p| p.public_key == key);
+                match self.add_pairing(&id, public_key, admin, same_key).await {
+                    Ok(true) => {}
+                    Ok(false) => return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]),
+                    Err(e) => {
+                        tracing::error!(error = %format!("{e:#}"), "couldn't save the pairing");
+                        return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                    }
+                }
+                tracing::info!(controller = %id, admin, "pairing added");
+                Response::tlv(&[(TLV_STATE, &[2])])
+            }
+            (Some(METHOD_REMOVE_PAIRING), Some(id)) => {
+                if let Err(e) = self.remove_pairing(&id).await {
+                    tracing::error!(error = %format!("{e:#}"), "couldn't save the pairings");
+                    return Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]);
+                }
+                tracing::info!(controller = %id, "pairing removed");
+                conn.close = id == controller;
+                Response::tlv(&[(TLV_STATE, &[2])])
+            }
+            (Some(METHOD_LIST_PAIRINGS), _) => {
+                let state = self.state.lock().await;
+                let mut items: Vec<(u8, Vec<u8>)> = vec![(TLV_STATE, vec![2])];
+                for (i, (id, pairing)) in state.pairings.iter().enumerate() {
+                    if i > 0 {
+                        items.push((TLV_SEPARATOR, Vec::new()));
+                    }
+                    items.push((TLV_IDENTIFIER, id.as_bytes().to_vec()));
+                    items.push((TLV_PUBLIC_KEY, hex::decode(&pairing.public_key).unwrap_or_default()));
+                    items.push((TLV_PERMISSIONS, vec![u8::from(pairing.admin)]));
+                }
+                let items: Vec<(u8, &[u8])> = items.iter().map(|(t, v)| (*t, v.as_slice())).collect();
+                Response::tlv(&items)
+            }
+            _ => Response::tlv(&[(TLV_STATE, &[2]), (TLV_ERROR, &[ERROR_UNKNOWN])]),
+        }
+    }
+
+    // False, and nothing kept, unless `allowed` says yes to the
+    // pairings as they are; it's asked under the same lock as the
+    // insert, so two controllers can't both pass it
+    async fn add_pairing(
+        &self,
+        id: &str,
+        public_key: &[u8],
+        admin: bool,
+        allowed: impl FnOnce(&BTreeMap<String, Pairing>) -> bool,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().await;
+        if !allowed(&state.pairings) {
+            return Ok(false);
+        }
+        state.pairings.insert(id.to_string(), Pairing { public_key: hex::encode(public_key), admin });
+        state.save_sync(&self.path)?;
+        drop(state);
+        self.advertise(true);
+        Ok(true)
+    }
+
+    // Without an admin left, nobody could manage the rest, so
+    // they go too
+    async fn remove_pairing(&self, id: &str) -> Result<()> {
+        let mut state = self.state.lock().await;
+        state.pairings.remove(id);
+        if !state.pairings.values().any(|p| p.admin) {
+            state.pairings.clear();
+        }
+        state.save_sync(&self.path)?;
+        let paired = !state.pairings.is_empty();
+        drop(state);
+        if !paired {
+            tracing::info!(setup_code = %self.setup_code, "no longer paired; add it again with this setup code");
+        }
+        self.advertise(paired);
+        Ok(())
+    }
+
+    /////////////////////////////////////////////////////////
+    // The accessory database and its characteristics
+    /////////////////////////////////////////////////////////
+    async fn accessories(&self, conn: &mut Connection) -> Value {
+        let string = |iid: u64, kind: &str, value: Value| json!({ "iid": iid, "type": kind, "perms": ["pr"], "format": "string", "value": value });
+        let on = self.is_on().await;
+        conn.reported = Some(on);
+        json!({ "accessories": [{
+            "aid": AID,
+            "services": [
+                { "iid": 1, "type": "3E", "characteristics": [
+                    { "iid": IID_IDENTIFY, "type": "14", "perms": ["pw"], "format": "bool" },
+                    string(3, "20", self.value(3).await.unwrap_or_default()),
+                    string(4, "21", self.value(4).await.unwrap_or_default()),
+                    string(5, "23", self.value(5).await.unwrap_or_default()),
+                    string(6, "30", self.value(6).await.unwrap_or_default()),
+                    string(7, "52", self.value(7).await.unwrap_or_default()),
+                ]},
+                { "iid": 8, "type": "49", "primary": true, "characteristics": [
+                    { "iid": IID_ON, "type": "25", "perms": ["pr", "pw", "ev"], "format": "bool", "value": on },
+                    string(10, "23", self.value(10).await.unwrap_or_default()),
+                ]},
+                { "iid": 11, "type": "A2", "characteristics": [
+                    string(12, "37", self.value(12).await.unwrap_or_default()),
+                ]},
+            ],
+        }]})
+    }
+
+    // Readable characteristics by instance ID
+    async fn value(&self, iid: u64) -> Option<Value> {
+        Some(match iid {
+            3 | 4 => json!("SilentNight"),
+            5 | 10 => json!(self.settings.name),
+            6 => json!(self.state.lock().await.device_id),
+            7 => json!(env!("CARGO_PKG_VERSION")),
+            IID_ON => json!(self.is_on().await),
+            12 => json!("1.1.0"),
+            _ => return None,
+        })
+    }
+
+    async fn is_on(&self) -> bool {
+        match self.source().await {
+            Some(source) => source.state.borrow().is_recording(),
+            None => false,
+        }
+    }
+
+    // GET /characteristics?id=1.9,1.3
+    async fn read(&self, conn: &mut Connection, query: &str) -> Response {
+        let ids = query.split('&').find_map(|p| p.strip_prefix("id=")).unwrap_or_default();
+        let mut results = Vec::new();
+        let mut failed = false;
+        for id in ids.split(',').filter(|id| !id.is_empty()) {
+            let (aid, iid) = id.split_once('.').unwrap_or((id, ""));
+            let (aid, iid) = (aid.parse::<u64>().unwrap_or(0), iid.parse::<u64>().unwrap_or(0));
+            match self.value(iid).await.filter(|_| aid == AID) {
+                Some(value) => {
+                    if iid == IID_ON {
+                        conn.reported = value.as_bool();
+                    }
+                    results.push(json!({ "aid": aid, "iid": iid, "value": value }));
+                }
+                None => {
+                    failed = true;
+                    results.push(json!({ "aid": aid, "iid": iid, "status": STATUS_NOT_FOUND }));
+                }
+            }
+        }
+        if failed {
+            for result in results.iter_mut().filter(|r| r.get("status").is_none()) {
+                result["status"] = json!(0);
+            }
+            return Response::json("207 Multi-Status", json!({ "characteristics": results }));
+        }
+        Response::json("200 OK", json!({ "characteristics": results }))
+    }
+
+    // PUT /characteristics: values and event subscriptions
+    async fn write(&self, conn: &mut Connection, body: &[u8]) -> Response {
+        let Ok(body) = serde_json::from_slice::<Value>(body) else {
+            return Response::json("400 Bad Request", json!({ "status": STATUS_INVALID_VALUE }));
+        };
+        let mut statuses = Vec::new();
+        for write in body["characteristics"].as_array().into_iter().flatten() {
+            let (aid, iid) = (write["aid"].as_u64().unwrap_or(0), write["iid"].as_u64().unwrap_or(0));
+            let mut status = 0;
+            if aid != AID {
+                status = STATUS_NOT_FOUND;
+            }
+            if let (0, Some(ev)) = (status, write.get("ev")) {
+                match (iid, ev.as_bool()) {
+                    (IID_ON, Some(ev)) => {
+                        conn.events = ev;
+                        conn.reported = Some(self.is_on().await);
+                    }
+                    (IID_ON, None) => status = STATUS_INVALID_VALUE,
+                    _ => status = STATUS_NO_NOTIFICATION,
+                }
+            }
+            if let (0, Some(value)) = (status, write.get("value")) {
+                status = match iid {
+                    IID_ON => match value.as_bool().or_else(|| value.as_u64().map(|v| v != 0)) {
+                        Some(on) => self.switch(conn, on).await,
+                        None => STATUS_INVALID_VALUE,
+                    },
+                    IID_IDENTIFY => {
+                        tracing::info!("identify requested");
+                        0
+                    }
+                    _ if self.value(iid).await.is_some() => STATUS_READ_ONLY,
+                    _ => STATUS_NOT_FOUND,
+                };
+            }
+            statuses.push(json!({ "aid": aid, "iid": iid, "status": status }));
+        }
+        if statuses.iter().all(|s| s["status"] == 0) {
+            return Response::no_content();
+        }
+        Response::json("207 Multi-Status", json!({ "characteristics": statuses }))
+    }
+
+    async fn switch(&self, conn: &mut Connection, on: bool) -> i32 {
+        let command = if on { Command::Start } else { Command::Stop };
+        match control::run(&self.app_data, "homekit", &self.settings.source, &command).await {
+            Ok(done) => {
+                tracing::info!(command = %command, "{done}");
+                // It asked for it, so it needn't hear it back
+                conn.reported = Some(on);
+                0
+            }
+            Err(e) => {
+                tracing::warn!(command = %command, error = %format!("{e:#}"), "HomeKit command failed");
+                STATUS_COMMUNICATION
+            }
+        }
+    }
+}
+
+impl Connection {
+    // Decrypts whole frames once a session is up
+    fn received(&mut self, bytes: &[u8]) -> Result<()> {
+        let Some(session) = &mut self.session else {
+            self.plain.extend_from_slice(bytes);
+            return Ok(());
+        };
+        self.raw.extend_from_slice(bytes);
+        while self.raw.len() >= 2 {
+            let len = u16::from_le_bytes([self.raw[0], self.raw[1]]) as usize;
+            if len > FRAME_LEN {
+                anyhow::bail!("frame too long");
+            }
+            if self.raw.len() < 2 + len + TAG_LEN {
+                break;
+            }
+            let frame: Vec<u8> = self.raw.drain(..2 + len + TAG_LEN).collect();
+            let plain = open_with(&session.read_key, counter_nonce(session.read_count), &frame[..2], &frame[2..])
+                .context("couldn't decrypt a frame")?;
+            session.read_count += 1;
+            self.plain.extend_from_slice(&plain);
+        }
+        Ok(())
+    }
+
+    // What to send for `bytes`: framed and encrypted once a
+    // session is up
+    fn seal(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        let Some(session) = &mut self.session else { return bytes };
+        let mut out = Vec::with_capacity(bytes.len() + (bytes.len() / FRAME_LEN + 1) * (2 + TAG_LEN));
+        for chunk in bytes.chunks(FRAME_LEN) {
+            let len = (chunk.len() as u16).to_le_bytes();
+            let mut sealed = chunk.to_vec();
+            session
+                .write_key
+                .seal_in_place_append_tag(counter_nonce(session.write_count), Aad::from(len), &mut sealed)
+                .expect("frames are far below ChaCha20-Poly1305's limit");
+            session.write_count += 1;
+            out.extend_from_slice(&len);
+            out.extend_from_slice(&sealed);
+        }
+        out
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// HTTP, just what HAP controllers send
+/////////////////////////////////////////////////////////////
+fn parse_request(buf: &mut Vec<u8>) -> Result<Option<Request>> {
+    let Some(head_len) = buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4) else {
+        return Ok(None);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        anyhow::bail!("malformed request line");
+    };
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .context("invalid Content-Length")?
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST {
+        anyhow::bail!("request too long");
+    }
+    if buf.len() < head_len + content_length {
+        return Ok(None);
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body: buf[head_len..head_len + content_length].to_vec(),
+    };
+    buf.drain(..head_len + content_length);
+    Ok(Some(request))
+}
+
+// `protocol` is HTTP/1.1, or EVENT/1.0 for notifications
+fn response_bytes(protocol: &str, response: &Response) -> Vec<u8> {
+    let mut head = format!("{protocol} {}\r\n", response.status);
+    if !response.content_type.is_empty() {
+        head.push_str(&format!("Content-Type: {}\r\n", response.content_type));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+    [head.as_bytes(), &response.body].concat()
+}
+
+/////////////////////////////////////////////////////////////
+// TLV8: type, length, value; values over 255 bytes are split
+// across consecutive items of the same type
+/////////////////////////////////////////////////////////////
+fn tlv_encode(items: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (kind, value) in items {
+        if value.is_empty() {
+            out.extend_from_slice(&[*kind, 0]);
+        }
+        for chunk in value.chunks(255) {
+            out.push(*kind);
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+    out
+}
+
+fn tlv_decode(mut data: &[u8]) -> Option<Vec<(u8, Vec<u8>)>> {
+    let mut items: Vec<(u8, Vec<u8>)> = Vec::new();
+    let mut continues = false;
+    while let [kind, len, rest @ ..] = data {
+        let value = rest.get(..*len as usize)?;
+        match items.last_mut() {
+            Some((last, joined)) if continues && last == kind => joined.extend_from_slice(value),
+            _ => items.push((*kind, value.to_vec())),
+        }
+        continues = *len == 255;
+        data = &rest[*len as usize..];
+    }
+    data.is_empty().then_some(items)
+}
+
+fn tlv_get(items: &[(u8, Vec<u8>)], kind: u8) -> Option<&[u8]> {
+    items.iter().find(|(k, _)| *k == kind).map(|(_, v)| v.as_slice())
+}
+
+/////////////////////////////////////////////////////////////
+// Srp
+//
+// The accessory's side of SRP-6a as HAP uses it: SHA-512, the
+// 3072-bit group from RFC 5054, user "Pair-Setup", the setup
+// code as password; A, B and S padded to the group's length.
+/////////////////////////////////////////////////////////////
+const N_HEX: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+    "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+    "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+    "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+    "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+);
+const G: u8 = 5;
+const N_BYTES: usize = 384;
+
+struct Srp {
+    salt: [u8; 16],
+    verifier: BigUint,
+    b: BigUint,
+    b_pub: Vec<u8>,
+}
+
+impl Srp {
+    fn new(setup_code: &str) -> Srp {
+        let (n, g) = (group(), BigUint::from(G));
+        let salt: [u8; 16] = rand::random();
+        let x = BigUint::from_bytes_be(&sha512(&[&salt, &sha512(&[b"Pair-Setup:", setup_code.as_bytes()])]));
+        let verifier = g.modpow(&x, n);
+        let b = BigUint::from_bytes_be(&rand::random::<[u8; 32]>());
+        let k = BigUint::from_bytes_be(&sha512(&[&n.to_bytes_be(), &pad(&g)]));
+        let b_pub = pad(&((k * &verifier + g.modpow(&b, n)) % n));
+        Srp { salt, verifier, b, b_pub }
+    }
+
+    // The session key and our proof, if the controller's proof
+    // shows it knows the setup code
+    fn verify(&self, a_pub: &[u8], proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let n = group();
+        let a = BigUint::from_bytes_be(a_pub);
+        if (&a % n).bits() == 0 {
+            return None;
+        }
+        let a_pub = pad(&a);
+        let u = BigUint::from_bytes_be(&sha512(&[&a_pub, &self.b_pub]));
+        let s = (a * self.verifier.modpow(&u, n)).modpow(&self.b, n);
+        let key = sha512(&[&pad(&s)]);
+        let hash_n = sha512(&[&n.to_bytes_be()]);
+        let hash_g = sha512(&[&[G]]);
+        let n_xor_g: Vec<u8> = hash_n.iter().zip(&hash_g).map(|(n, g)| n ^ g).collect();
+        let expected = sha512(&[&n_xor_g, &sha512(&[b"Pair-Setup"]), &self.salt, &a_pub, &self.b_pub, &key]);
+        if expected != proof {
+            return None;
+        }
+        let accessory_proof = sha512(&[&a_pub, proof, &key]);
+        Some((key, accessory_proof))
+    }
+}
+
+fn group() -> &'static BigUint {
+    static N: OnceLock<BigUint> = OnceLock::new();
+    N.get_or_init(|| BigUint::parse_bytes(N_HEX.as_bytes(), 16).expect("N_HEX is hex"))
+}
+
+fn pad(n: &BigUint) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    [vec![0; N_BYTES.saturating_sub(bytes.len())], bytes].concat()
+}
+
+/////////////////////////////////////////////////////////////
+// Crypto helpers
+/////////////////////////////////////////////////////////////
+fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+    let mut context = digest::Context::new(&digest::SHA512);
+    for part in parts {
+        context.update(part);
+    }
+    context.finish().as_ref().to_vec()
+}
+
+fn hkdf_sha512(secret: &[u8], salt: &str, info: &str) -> [u8; 32] {
+    struct Len32;
+    impl hkdf::KeyType for Len32 {
+        fn len(&self) -> usize {
+            32
+        }
+    }
+    let mut out = [0u8; 32];
+    hkdf::Salt::new(hkdf::HKDF_SHA512, salt.as_bytes())
+        .extract(secret)
+        .expand(&[info.as_bytes()], Len32)
+        .and_then(|okm| okm.fill(&mut out))
+        .expect("32 bytes is a valid HKDF-SHA512 length");
+    out
+}
+
+fn aead_key(key: &[u8; 32]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("32-byte key"))
+}
+
+// Pairing messages use a fixed label as the nonce, sessions a
+// counter; both padded with four zero bytes
+fn label_nonce(label: [u8; 8]) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&label);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn counter_nonce(count: u64) -> Nonce {
+    label_nonce(count.to_le_bytes())
+}
+
+fn seal(key: &[u8; 32], label: [u8; 8], aad: &[u8], plain: &[u8]) -> Vec<u8> {
+    let mut sealed = plain.to_vec();
+    aead_key(key)
+        .seal_in_place_append_tag(label_nonce(label), Aad::from(aad), &mut sealed)
+        .expect("pairing messages are far below ChaCha20-Poly1305's limit");
+    sealed
+}
+
+fn open(key: &[u8; 32], label: [u8; 8], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    open_with(&aead_key(key), label_nonce(label), aad, sealed)
+}
+
+fn open_with(key: &LessSafeKey, nonce: Nonce, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = sealed.to_vec();
+    let plain = key.open_in_place(nonce, Aad::from(aad), &mut buf).ok()?;
+    Some(plain.to_vec())
+}
@@ -0,0 +1,356 @@
+/////////////////////////////////////////////////////////////
+// src/hub.rs
+//
+// The hub side of hub-and-node deployments: small "node"
+// instances (a Pi Zero in the kitchen, see node.rs) only capture
+// audio and send it here, and this instance does Whisper, GPT,
+// the log and everything after. Each node is an audio source
+// with mic_backend = "node":
+//   [[audio.sources]]
+//   name = "kitchen"
+//   mic_backend = "node"
+// and is started, stopped and shown like any other source; its
+// capture stage waits for the node's next chunk instead of a mic.
+//
+//   PUT  /nodes/{name}         - a node registering, and then
+//                                checking in every few seconds
+//                                ({"label", "version"})
+//   POST /nodes/{name}/chunks  - a chunk of WAV audio from it
+//   GET  /nodes                - every node seen, with its label,
+//                                address, last check-in, whether
+//                                it's healthy and chunk counts
+// The first two need hub.token as a bearer token (and skip the
+// web UI login); without one set, nodes are turned away. Both
+// answer {"capture", "chunk_secs"}: capture is true while the
+// source records and isn't muted or in quiet hours, which is how
+// a node knows to. A node is healthy while it has checked in within
+// hub.node_timeout_secs; GET /health/ready fails while one isn't,
+// and a recording node that sends nothing for chunk_secs plus
+// that long fails its chunk.
+/////////////////////////////////////////////////////////////
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use utoipa::ToSchema;
+
+use crate::auth::constant_time_eq;
+use crate::error::{ApiError, AudioError};
+use crate::{quiet, wav, AppState};
+
+// Chunks a node may be ahead of the pipeline
+const CHUNK_QUEUE: usize = 2;
+// Whisper's own limit on an upload
+const MAX_CHUNK_BYTES: usize = 25 * 1024 * 1024;
+
+/////////////////////////////////////////////////////////////
+// Nodes
+//
+// Every node that has checked in, and the chunks it has sent
+// that its source hasn't taken yet.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Nodes {
+    nodes: AsyncMutex<HashMap<String, Node>>,
+}
+
+struct Node {
+    info: NodeInfo,
+    last_seen: Option<DateTime<Utc>>,
+    chunks: mpsc::Sender<web::Bytes>,
+    // Taken by the source's capture stage
+    received: Arc<AsyncMutex<mpsc::Receiver<web::Bytes>>>,
+}
+
+impl Node {
+    fn new(name: &str) -> Node {
+        let (chunks, received) = mpsc::channel(CHUNK_QUEUE);
+        Node {
+            info: NodeInfo {
+                name: name.to_string(),
+                ..NodeInfo::default()
+            },
+            last_seen: None,
+            chunks,
+            received: Arc::new(AsyncMutex::new(received)),
+        }
+    }
+
+    fn healthy(&self, timeout: Duration) -> bool {
+        self.last_seen
+            .is_some_and(|seen| (Utc::now() - seen).to_std().unwrap_or_default() < timeout)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// NodeInfo
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema, Clone, Default)]
+pub(crate) struct NodeInfo {
+    // Its audio source
+    name: String,
+    // What the node calls itself, e.g. "Kitchen"
+    label: String,
+    // The node's SilentNight version
+    version: String,
+    // Where it last checked in from
+    address: String,
+    // RFC 3339; empty until it has
+    registered_at: String,
+    last_seen: String,
+    // Checked in within hub.node_timeout_secs
+    healthy: bool,
+    // Whether its source is recording here (and not muted)
+    recording: bool,
+    chunks_received: u64,
+    // Turned away because the pipeline was behind
+    chunks_dropped: u64,
+}
+
+impl Nodes {
+    async fn with_node<T>(&self, name: &str, f: impl FnOnce(&mut Node) -> T) -> T {
+        let mut nodes = self.nodes.lock().await;
+        f(nodes.entry(name.to_string()).or_insert_with(|| Node::new(name)))
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// next_chunk
+//
+// The capture stage of a node source: the node's next chunk.
+/////////////////////////////////////////////////////////////
+pub async fn next_chunk(app_data: &AppState, name: &str, chunk_secs: u32) -> Result<web::Bytes, AudioError> {
+    let timeout = Duration::from_secs(u64::from(chunk_secs) + app_data.config.read().await.hub.node_timeout_secs);
+    let received = app_data.nodes.with_node(name, |node| node.received.clone()).await;
+    let mut received = received.lock().await;
+    match tokio::time::timeout(timeout, received.recv()).await {
+        Ok(Some(chunk)) => Ok(chunk),
+        // The sender lives as long as the node's entry, i.e. forever
+        Ok(None) | Err(_) => Err(AudioError::NodeSilent {
+            name: name.to_string(),
+            secs: timeout.as_secs(),
+        }),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// problem
+//
+// For GET /health/ready: why node `name` isn't usable, if it
+// isn't.
+/////////////////////////////////////////////////////////////
+pub async fn problem(app_data: &AppState, name: &str) -> Option<String> {
+    let timeout = Duration::from_secs(app_data.config.read().await.hub.node_timeout_secs);
+    let nodes = app_data.nodes.nodes.lock().await;
+    match nodes.get(name) {
+        Some(node) if node.healthy(timeout) => None,
+        Some(node) if node.last_seen.is_some() => {
+            Some(format!("node {name} last checked in at {}", node.info.last_seen))
+        }
+        _ => Some(format!("node {name} hasn't checked in")),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// /nodes
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct NodeHello {
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    version: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct NodeReply {
+    // Capture and send chunks while this is true
+    pub capture: bool,
+    pub chunk_secs: u32,
+}
+
+// Node requests are checked here rather than by the web UI login
+// (see auth::require_login), with hub.token
+pub fn is_node_request(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/nodes/")
+}
+
+async fn authorize(req: &HttpRequest, app_data: &AppState, name: &str) -> Result<(), ApiError> {
+    let token = app_data.config.read().await.hub.token.clone();
+    if token.is_empty() {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "hub_disabled", "Set hub.token (HUB_TOKEN) to accept nodes"));
+    }
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(given.trim().as_bytes(), token.as_bytes()) {
+        tracing::warn!(node = name, "node rejected: wrong hub token");
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "hub_token_required", "Hub token required"));
+    }
+    let is_node = app_data
+        .config
+        .read()
+        .await
+        .audio
+        .input(name)
+        .is_some_and(|input| input.backend == "node");
+    if !is_node {
+        return Err(ApiError::not_found(
+            "unknown_node",
+            format!("No audio source named {name} with mic_backend = \"node\" on this hub"),
+        ));
+    }
+    Ok(())
+}
+
+async fn reply(app_data: &web::Data<AppState>, name: &str) -> NodeReply {
+    let source = app_data.sources.get(app_data, name).await;
+    let capture = match &source {
+        Some(source) => source.state.borrow().is_recording() && !*source.muted.borrow(),
+        None => false,
+    } && quiet::in_force(app_data).await.is_none();
+    let overrides = source.map(|source| source.overrides()).unwrap_or_default();
+    NodeReply {
+        capture,
+        chunk_secs: overrides.chunk_secs(&app_data.config.read().await.audio),
+    }
+}
+
+#[utoipa::path(
+    tag = "nodes",
+    path = "/nodes/{name}",
+    params(("name" = String, Path, description = "The node's audio source")),
+    request_body = NodeHello,
+    responses(
+        (status = 200, description = "Checked in; whether to capture", body = NodeReply),
+        (status = 401, description = "Missing or wrong hub token (code hub_token_required)", body = ErrorBody),
+        (status = 403, description = "No hub.token set (code hub_disabled)", body = ErrorBody),
+        (status = 404, description = "No node source by that name (code unknown_node)", body = ErrorBody),
+    ),
+)]
+#[put("/nodes/{name}")]
+async fn check_in(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+    hello: web::Json<NodeHello>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &app_data, &name).await?;
+    let timeout = Duration::from_secs(app_data.config.read().await.hub.node_timeout_secs);
+    let address = req.peer_addr().map(|a| a.ip().to_canonical().to_string()).unwrap_or_default();
+    let now = Utc::now();
+    app_data
+        .nodes
+        .with_node(&name, |node| {
+            if node.last_seen.is_none() {
+                node.info.registered_at = now.to_rfc3339();
+                tracing::info!(node = %name, label = %hello.label, address = %address, "node registered");
+            } else if !node.healthy(timeout) {
+                tracing::info!(node = %name, "node is back");
+            }
+            node.info.label = hello.label.clone();
+            node.info.version = hello.version.clone();
+            node.info.address = address;
+            node.info.last_seen = now.to_rfc3339();
+            node.last_seen = Some(now);
+        })
+        .await;
+    Ok(HttpResponse::Ok().json(reply(&app_data, &name).await))
+}
+
+#[utoipa::path(
+    post,
+    tag = "nodes",
+    path = "/nodes/{name}/chunks",
+    params(("name" = String, Path, description = "The node's audio source")),
+    request_body(content = Vec<u8>, content_type = "audio/wav", description = "One chunk of WAV audio"),
+    responses(
+        (status = 200, description = "Queued for the pipeline; whether to keep capturing", body = NodeReply),
+        (status = 400, description = "Not WAV audio (code invalid_audio)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong hub token (code hub_token_required)", body = ErrorBody),
+        (status = 404, description = "No node source by that name (code unknown_node)", body = ErrorBody),
+        (status = 409, description = "The source isn't recording, or is muted; the chunk is dropped (code not_recording)", body = ErrorBody),
+        (status = 413, description = "Bigger than Whisper takes (code payload_too_large)", body = ErrorBody),
+        (status = 503, description = "The pipeline is behind; the chunk is dropped (code node_backlogged)", body = ErrorBody),
+    ),
+)]
+#[post("/nodes/{name}/chunks")]
+async fn receive_chunk(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+    payload: web::Payload,
+) -> Result<HttpResponse, ApiError> {
+    // The token first, so nothing unauthenticated gets buffered
+    authorize(&req, &app_data, &name).await?;
+    let body = match payload.to_bytes_limited(MAX_CHUNK_BYTES).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return Err(ApiError::bad_request("invalid_audio", "Couldn't read the chunk").with_detail(e)),
+        Err(_) => return Err(ApiError::payload_too_large(MAX_CHUNK_BYTES)),
+    };
+    if let Err(e) = wav::parse(&body) {
+        return Err(ApiError::bad_request("invalid_audio", "The chunk isn't WAV audio").with_detail(e));
+    }
+    let reply = reply(&app_data, &name).await;
+    if !reply.capture {
+        return Err(ApiError::conflict("not_recording", format!("{name} isn't recording")));
+    }
+    let now = Utc::now();
+    let queued = app_data
+        .nodes
+        .with_node(&name, |node| {
+            node.last_seen = Some(now);
+            node.info.last_seen = now.to_rfc3339();
+            let queued = node.chunks.try_send(body.clone()).is_ok();
+            if queued {
+                node.info.chunks_received += 1;
+            } else {
+                node.info.chunks_dropped += 1;
+            }
+            queued
+        })
+        .await;
+    if !queued {
+        tracing::warn!(node = %name, "pipeline is behind, dropped a chunk from the node");
+        return Err(ApiError::unavailable("node_backlogged", "The hub is still busy with earlier chunks"));
+    }
+    Ok(HttpResponse::Ok().json(reply))
+}
+
+#[utoipa::path(
+    tag = "nodes",
+    responses((status = 200, description = "Every node that has checked in, by name", body = [NodeInfo])),
+)]
+#[get("/nodes")]
+async fn list_nodes(app_data: web::Data<AppState>) -> HttpResponse {
+    let timeout = Duration::from_secs(app_data.config.read().await.hub.node_timeout_secs);
+    let snapshot: Vec<(NodeInfo, bool)> = {
+        let nodes = app_data.nodes.nodes.lock().await;
+        nodes
+            .values()
+            .filter(|node| node.last_seen.is_some())
+            .map(|node| (node.info.clone(), node.healthy(timeout)))
+            .collect()
+    };
+    let mut list = Vec::new();
+    for (mut info, healthy) in snapshot {
+        info.healthy = healthy;
+        info.recording = reply(&app_data, &info.name).await.capture;
+        list.push(info);
+    }
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    HttpResponse::Ok().json(list)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_nodes).service(check_in).service(receive_chunk);
+}
@@ -0,0 +1,461 @@
+/////////////////////////////////////////////////////////////
+// src/i18n.rs
+//
+// The words the device shows people, in their language: the
+// server-rendered pages (see browse.rs), and the status lines,
+// banners and buttons of the web UI and login page, which ask
+//   GET /i18n  - {"language": "de", "strings": {...}}
+// for theirs. English, German, Spanish and French are built in.
+//
+// ui.language (UI_LANGUAGE) picks one for every browser, e.g. for
+// a wall display in a German household; empty means each browser
+// gets the best match for its Accept-Language, or English. Every
+// language has every string, so a page never mixes two.
+//
+// Placeholders like {time} are filled in by whoever shows the
+// string. /i18n needs no login, so the login page can use it.
+// What GPT says is left alone (see openai.system_prompt for that).
+/////////////////////////////////////////////////////////////
+
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+// The languages there are strings for
+pub const LANGUAGES: [&str; 4] = ["en", "de", "es", "fr"];
+
+/////////////////////////////////////////////////////////////
+// Strings
+//
+// One language's strings, by what they're for
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub struct Strings {
+    // Web UI buttons
+    pub start_recording: &'static str,
+    pub stop_recording: &'static str,
+    pub last_transcript: &'static str,
+    pub full_log: &'static str,
+    pub past_sessions: &'static str,
+    pub log_out: &'static str,
+    pub restart_server: &'static str,
+    pub shut_down_server: &'static str,
+    // Web UI status lines and banners
+    pub press_start: &'static str,
+    pub recording_started: &'static str,
+    pub recording_stopped: &'static str,
+    pub listening: &'static str,
+    // {state}
+    pub state: &'static str,
+    // {reason}
+    pub recording_failed: &'static str,
+    // {attempt}, {reason}
+    pub restarting: &'static str,
+    // {time}
+    pub muted_until: &'static str,
+    pub local_only: &'static str,
+    pub transcript: &'static str,
+    pub response: &'static str,
+    pub fetching_log: &'static str,
+    pub log_fetched: &'static str,
+    pub log_unavailable: &'static str,
+    // {reason}
+    pub failed: &'static str,
+    pub confirm_restart: &'static str,
+    pub confirm_shutdown: &'static str,
+    pub server_restarting: &'static str,
+    pub server_shutting_down: &'static str,
+    // Login page
+    pub username: &'static str,
+    pub password: &'static str,
+    pub log_in: &'static str,
+    pub wrong_login: &'static str,
+    // Server-rendered pages (browse.rs)
+    pub live: &'static str,
+    pub sessions: &'static str,
+    pub session: &'static str,
+    pub source: &'static str,
+    pub started: &'static str,
+    pub length: &'static str,
+    pub chunks: &'static str,
+    // {minutes}
+    pub minutes: &'static str,
+    pub no_sessions: &'static str,
+    // {source}, {started}, {chunks}
+    pub session_summary: &'static str,
+    // Links to a session's translations (see translate.rs)
+    pub translations: &'static str,
+    pub original: &'static str,
+    // GET /dashboard (dashboard.rs)
+    pub dashboard: &'static str,
+    pub audio_sources: &'static str,
+    pub state_column: &'static str,
+    pub queued: &'static str,
+    pub failed_chunks: &'static str,
+    pub latency: &'static str,
+    pub last_error: &'static str,
+    pub no_error: &'static str,
+    pub muted: &'static str,
+    // {running}, {waiting}, {rejected}
+    pub requests: &'static str,
+    // {avg}, {max}
+    pub latency_summary: &'static str,
+    pub server: &'static str,
+    pub backlog: &'static str,
+    // {queued}, {failed}
+    pub backlog_summary: &'static str,
+    pub live_clients: &'static str,
+    pub task_restarts: &'static str,
+    pub uptime: &'static str,
+}
+
+const EN: Strings = Strings {
+    start_recording: "Start Recording",
+    stop_recording: "Stop Recording",
+    last_transcript: "Get Last Transcript/Response",
+    full_log: "View Full Log",
+    past_sessions: "Past Sessions",
+    log_out: "Log Out",
+    restart_server: "Restart Server",
+    shut_down_server: "Shut Down Server",
+    press_start: "Press \"Start\" to record 5s of audio in memory",
+    recording_started: "Recording started...",
+    recording_stopped: "Stopped recording.",
+    listening: "Listening…",
+    state: "State: {state}",
+    recording_failed: "Recording stopped: {reason}",
+    restarting: "Restarting (attempt {attempt}): {reason}",
+    muted_until: "Muted: quiet hours until {time}",
+    local_only: "Local-only mode: nothing leaves this network",
+    transcript: "TRANSCRIPT",
+    response: "GPT RESPONSE",
+    fetching_log: "Fetching full conversation log...",
+    log_fetched: "Full log fetched.",
+    log_unavailable: "Failed to fetch conversation_log",
+    failed: "Failed: {reason}",
+    confirm_restart: "Really restart the server?",
+    confirm_shutdown: "Really shut down the server?",
+    server_restarting: "Restarting... reload the page in a few seconds.",
+    server_shutting_down: "Server is shutting down.",
+    username: "Username",
+    password: "Password",
+    log_in: "Log In",
+    wrong_login: "Wrong username or password",
+    live: "Live",
+    sessions: "Sessions",
+    session: "Session",
+    source: "Source",
+    started: "Started",
+    length: "Length",
+    chunks: "Chunks",
+    minutes: "{minutes} min",
+    no_sessions: "No sessions yet.",
+    session_summary: "{source}, {started}, {chunks} chunks",
+    translations: "Translations",
+    original: "Original",
+    dashboard: "Dashboard",
+    audio_sources: "Sources",
+    state_column: "State",
+    queued: "Queued",
+    failed_chunks: "Failed",
+    latency: "Latency",
+    last_error: "Last error",
+    no_error: "none",
+    muted: "muted",
+    requests: "{running} running, {waiting} waiting, {rejected} rejected",
+    latency_summary: "avg {avg} ms, max {max} ms",
+    server: "Server",
+    backlog: "Backlog",
+    backlog_summary: "{queued} waiting, {failed} failed",
+    live_clients: "Live clients",
+    task_restarts: "Task restarts",
+    uptime: "Uptime",
+};
+
+const DE: Strings = Strings {
+    start_recording: "Aufnahme starten",
+    stop_recording: "Aufnahme beenden",
+    last_transcript: "Letztes Transkript/Antwort",
+    full_log: "Ganzes Protokoll",
+    past_sessions: "Frühere Sitzungen",
+    log_out: "Abmelden",
+    restart_server: "Server neu starten",
+    shut_down_server: "Server herunterfahren",
+    press_start: "„Start“ drücken, um 5 s Audio im Speicher aufzunehmen",
+    recording_started: "Aufnahme läuft...",
+    recording_stopped: "Aufnahme beendet.",
+    listening: "Hört zu…",
+    state: "Status: {state}",
+    recording_failed: "Aufnahme gestoppt: {reason}",
+    restarting: "Neustart (Versuch {attempt}): {reason}",
+    muted_until: "Stumm: Ruhezeit bis {time}",
+    local_only: "Nur lokal: nichts verlässt dieses Netzwerk",
+    transcript: "TRANSKRIPT",
+    response: "GPT-ANTWORT",
+    fetching_log: "Protokoll wird geladen...",
+    log_fetched: "Protokoll geladen.",
+    log_unavailable: "Protokoll konnte nicht geladen werden",
+    failed: "Fehlgeschlagen: {reason}",
+    confirm_restart: "Server wirklich neu starten?",
+    confirm_shutdown: "Server wirklich herunterfahren?",
+    server_restarting: "Neustart... Seite in ein paar Sekunden neu laden.",
+    server_shutting_down: "Server fährt herunter.",
+    username: "Benutzername",
+    password: "Passwort",
+    log_in: "Anmelden",
+    wrong_login: "Falscher Benutzername oder falsches Passwort",
+    live: "Live",
+    sessions: "Sitzungen",
+    session: "Sitzung",
+    source: "Quelle",
+    started: "Beginn",
+    length: "Dauer",
+    chunks: "Abschnitte",
+    minutes: "{minutes} Min.",
+    no_sessions: "Noch keine Sitzungen.",
+    session_summary: "{source}, {started}, {chunks} Abschnitte",
+    translations: "Übersetzungen",
+    original: "Original",
+    dashboard: "Übersicht",
+    audio_sources: "Quellen",
+    state_column: "Zustand",
+    queued: "Wartend",
+    failed_chunks: "Fehlgeschlagen",
+    latency: "Latenz",
+    last_error: "Letzter Fehler",
+    no_error: "keiner",
+    muted: "stumm",
+    requests: "{running} laufend, {waiting} wartend, {rejected} abgelehnt",
+    latency_summary: "Ø {avg} ms, max. {max} ms",
+    server: "Server",
+    backlog: "Rückstand",
+    backlog_summary: "{queued} wartend, {failed} fehlgeschlagen",
+    live_clients: "Live-Clients",
+    task_restarts: "Neustarts von Aufgaben",
+    uptime: "Laufzeit",
+};
+
+const ES: Strings = Strings {
+    start_recording: "Empezar a grabar",
+    stop_recording: "Dejar de grabar",
+    last_transcript: "Última transcripción/respuesta",
+    full_log: "Ver registro completo",
+    past_sessions: "Sesiones anteriores",
+    log_out: "Cerrar sesión",
+    restart_server: "Reiniciar servidor",
+    shut_down_server: "Apagar servidor",
+    press_start: "Pulsa «Empezar» para grabar 5 s de audio en memoria",
+    recording_started: "Grabando...",
+    recording_stopped: "Grabación detenida.",
+    listening: "Escuchando…",
+    state: "Estado: {state}",
+    recording_failed: "Grabación detenida: {reason}",
+    restarting: "Reiniciando (intento {attempt}): {reason}",
+    muted_until: "Silenciado: horas de silencio hasta las {time}",
+    local_only: "Modo solo local: nada sale de esta red",
+    transcript: "TRANSCRIPCIÓN",
+    response: "RESPUESTA DE GPT",
+    fetching_log: "Cargando el registro completo...",
+    log_fetched: "Registro cargado.",
+    log_unavailable: "No se pudo cargar el registro",
+    failed: "Error: {reason}",
+    confirm_restart: "¿Seguro que quieres reiniciar el servidor?",
+    confirm_shutdown: "¿Seguro que quieres apagar el servidor?",
+    server_restarting: "Reiniciando... recarga la página en unos segundos.",
+    server_shutting_down: "El servidor se está apagando.",
+    username: "Usuario",
+    password: "Contraseña",
+    log_in: "Entrar",
+    wrong_login: "Usuario o contraseña incorrectos",
+    live: "En directo",
+    sessions: "Sesiones",
+    session: "Sesión",
+    source: "Fuente",
+    started: "Inicio",
+    length: "Duración",
+    chunks: "Fragmentos",
+    minutes: "{minutes} min",
+    no_sessions: "Todavía no hay sesiones.",
+    session_summary: "{source}, {started}, {chunks} fragmentos",
+    translations: "Traducciones",
+    original: "Original",
+    dashboard: "Panel",
+    audio_sources: "Fuentes",
+    state_column: "Estado",
+    queued: "En cola",
+    failed_chunks: "Fallidos",
+    latency: "Latencia",
+    last_error: "Último error",
+    no_error: "ninguno",
+    muted: "silenciado",
+    requests: "{running} en curso, {waiting} en espera, {rejected} rechazadas",
+    latency_summary: "media {avg} ms, máx. {max} ms",
+    server: "Servidor",
+    backlog: "Pendientes",
+    backlog_summary: "{queued} en espera, {failed} fallidos",
+    live_clients: "Clientes en directo",
+    task_restarts: "Reinicios de tareas",
+    uptime: "Tiempo activo",
+};
+
+const FR: Strings = Strings {
+    start_recording: "Démarrer l'enregistrement",
+    stop_recording: "Arrêter l'enregistrement",
+    last_transcript: "Dernière transcription/réponse",
+    full_log: "Voir tout le journal",
+    past_sessions: "Sessions passées",
+    log_out: "Se déconnecter",
+    restart_server: "Redémarrer le serveur",
+    shut_down_server: "Éteindre le serveur",
+    press_start: "Appuyez sur « Démarrer » pour enregistrer 5 s d'audio en mémoire",
+    recording_started: "Enregistrement en cours...",
+    recording_stopped: "Enregistrement arrêté.",
+    listening: "À l'écoute…",
+    state: "État : {state}",
+    recording_failed: "Enregistrement arrêté : {reason}",
+    restarting: "Redémarrage (essai {attempt}) : {reason}",
+    muted_until: "Muet : heures calmes jusqu'à {time}",
+    local_only: "Mode local : rien ne quitte ce réseau",
+    transcript: "TRANSCRIPTION",
+    response: "RÉPONSE DE GPT",
+    fetching_log: "Chargement du journal complet...",
+    log_fetched: "Journal chargé.",
+    log_unavailable: "Impossible de charger le journal",
+    failed: "Échec : {reason}",
+    confirm_restart: "Vraiment redémarrer le serveur ?",
+    confirm_shutdown: "Vraiment éteindre le serveur ?",
+    server_restarting: "Redémarrage... rechargez la page dans quelques secondes.",
+    server_shutting_down: "Le serveur s'éteint.",
+    username: "Nom d'utilisateur",
+    password: "Mot de passe",
+    log_in: "Se connecter",
+    wrong_login: "Nom d'utilisateur ou mot de passe incorrect",
+    live: "En direct",
+    sessions: "Sessions",
+    session: "Session",
+    source: "Source",
+    started: "Début",
+    length: "Durée",
+    chunks: "Extraits",
+    minutes: "{minutes} min",
+    no_sessions: "Aucune session pour l'instant.",
+    session_summary: "{source}, {started}, {chunks} extraits",
+    translations: "Traductions",
+    original: "Original",
+    dashboard: "Tableau de bord",
+    audio_sources: "Sources",
+    state_column: "État",
+    queued: "En attente",
+    failed_chunks: "Échoués",
+    latency: "Latence",
+    last_error: "Dernière erreur",
+    no_error: "aucune",
+    muted: "muet",
+    requests: "{running} en cours, {waiting} en attente, {rejected} refusées",
+    latency_summary: "moy. {avg} ms, max. {max} ms",
+    server: "Serveur",
+    backlog: "File d'attente",
+    backlog_summary: "{queued} en attente, {failed} échoués",
+    live_clients: "Clients en direct",
+    task_restarts: "Redémarrages de tâches",
+    uptime: "Disponibilité",
+};
+
+// The strings for `language` (one of LANGUAGES); English for
+// anything else
+pub fn strings(language: &str) -> &'static Strings {
+    match language {
+        "de" => &DE,
+        "es" => &ES,
+        "fr" => &FR,
+        _ => &EN,
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// language
+//
+// The language a request gets: ui.language if set, otherwise the
+// first of its Accept-Language ranges (by q) that we have, by
+// primary tag ("de-AT" gets "de"); "en" if none match.
+/////////////////////////////////////////////////////////////
+pub async fn language(app_data: &AppState, req: &HttpRequest) -> &'static str {
+    let configured = app_data.config.read().await.ui.language.clone();
+    if let Some(language) = LANGUAGES.into_iter().find(|l| *l == configured) {
+        return language;
+    }
+    let accept = req.headers().get(ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    negotiate(accept)
+}
+
+pub fn negotiate(accept_language: &str) -> &'static str {
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+    // Stable, so equal q keeps the browser's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges
+        .into_iter()
+        .find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or_default().to_ascii_lowercase();
+            LANGUAGES.into_iter().find(|l| *l == primary)
+        })
+        .unwrap_or("en")
+}
+
+/////////////////////////////////////////////////////////////
+// GET /i18n
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct I18nResponse {
+    // "en", "de", "es" or "fr"
+    language: &'static str,
+    strings: &'static Strings,
+}
+
+#[utoipa::path(
+    tag = "ui",
+    path = "/i18n",
+    responses((status = 200, description = "The UI strings in ui.language, or the browser's language", body = I18nResponse)),
+)]
+#[get("/i18n")]
+async fn get_strings(req: HttpRequest, app_data: web::Data<AppState>) -> HttpResponse {
+    let language = language(&app_data, &req).await;
+    HttpResponse::Ok()
+        .insert_header(("Content-Language", language))
+        .insert_header(("Vary", "Accept-Language"))
+        .json(I18nResponse { language, strings: strings(language) })
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_strings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_most_preferred_language_we_have() {
+        assert_eq!(negotiate("de-AT,de;q=0.9,en;q=0.8"), "de");
+        assert_eq!(negotiate("nl-NL,nl;q=0.9,fr;q=0.7,en;q=0.5"), "fr");
+        assert_eq!(negotiate("en;q=0.3, es"), "es");
+        assert_eq!(negotiate("ES-mx"), "es");
+        assert_eq!(negotiate("fr;q=0, de;q=0.1"), "de");
+        assert_eq!(negotiate("ja"), "en");
+        assert_eq!(negotiate("*"), "en");
+        assert_eq!(negotiate(""), "en");
+        assert_eq!(negotiate("de;q=abc"), "en");
+    }
+}
@@ -0,0 +1,92 @@
+/////////////////////////////////////////////////////////////
+// src/lifecycle.rs
+//
+// Where each audio source is in its recording lifecycle:
+//
+//   idle ──start──> starting ──> recording ──stop──> stopping
+//    ^  \                                               │
+//    │   record_once / gRPC audio                       v
+//    │    └────────────────────────> processing <───────┘
+//    └──────────────── done ────────────┘
+//
+// When the pipeline fails in a way worth retrying, the source
+// is `restarting` (with the reason and attempt number) until the
+// supervisor starts it again (see supervisor.rs). Any busy state
+// can end in `error` (with the reason) when the pipeline fails
+// for good; the next start leaves it. A source is busy (start
+// and record_once answer 409) in every state but idle and error.
+//
+// The state is shown on GET /status, GET /sources and gRPC, and
+// every change is sent on the live logs as an SSE event named
+// "state", so clients can tell a stopped source from a crashed
+// one.
+/////////////////////////////////////////////////////////////
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub(crate) enum RecordingState {
+    #[default]
+    Idle,
+    // Loop spawned, capture not running yet
+    Starting,
+    Recording,
+    // Finishing chunks after capture ended, or a one-off chunk
+    Processing,
+    // Stop requested, capture winding down
+    Stopping,
+    // Waiting to run the pipeline again after a failure
+    Restarting { reason: String, attempt: u32 },
+    Error { reason: String },
+}
+
+impl RecordingState {
+    pub fn can_become(&self, next: &RecordingState) -> bool {
+        use RecordingState::*;
+        matches!(
+            (self, next),
+            (Idle | Error { .. }, Starting | Processing)
+                | (Starting, Recording)
+                | (Starting | Recording | Restarting { .. }, Stopping)
+                | (Starting | Recording, Restarting { .. })
+                | (Restarting { .. }, Starting)
+                | (Stopping, Processing)
+                | (Starting | Recording | Stopping | Processing | Restarting { .. }, Idle | Error { .. })
+        )
+    }
+
+    // The mic loop is running (or about to run again) and hasn't
+    // been asked to stop
+    pub fn is_recording(&self) -> bool {
+        matches!(
+            self,
+            RecordingState::Starting | RecordingState::Recording | RecordingState::Restarting { .. }
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordingState::Idle => "idle",
+            RecordingState::Starting => "starting",
+            RecordingState::Recording => "recording",
+            RecordingState::Processing => "processing",
+            RecordingState::Stopping => "stopping",
+            RecordingState::Restarting { .. } => "restarting",
+            RecordingState::Error { .. } => "error",
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// StateChange
+//
+// Data of a "state" SSE event.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize)]
+pub(crate) struct StateChange<'a> {
+    pub audio_source: &'a str,
+    pub session_id: Option<String>,
+    pub state: &'a RecordingState,
+}
@@ -0,0 +1,446 @@
+/////////////////////////////////////////////////////////////
+// src/lights.rs
+//
+// A light cue ([lights] enabled): whenever GPT has something to
+// say (any response but "Listening..."), a light pulses so people
+// glance at the wall display.
+//   hue  - one "breathe" of a Philips Hue light (alert "select")
+//          through the bridge's local API
+//   wled - a WLED strip lit at lights.brightness for
+//          lights.pulse_ms, then put back as it was
+// A pulse that's still running swallows the next one.
+//
+// Setup, with the same access as /admin (see admin.rs):
+//   GET   /lights/settings  - the light settings
+//   PATCH /lights/settings  - change some, e.g. {"light": "3"}
+//   GET   /lights/discover  - Hue bridges (via
+//                             lights.hue_discovery_url), WLED
+//                             devices (mDNS, when [discovery] is
+//                             on) and the configured bridge's
+//                             lights
+//   POST  /lights/pair      - press the bridge's link button, then
+//                             call this for a Hue app key
+//   POST  /lights/test      - pulse now
+// Like /admin/settings, changes last until the next restart or
+// reload. Settings are read at each response, so a reload
+// applies to the next one. A failed pulse is logged and dropped.
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{get, patch, post, web, HttpResponse};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::LightSettings;
+use crate::error::ApiError;
+use crate::{admin, pipeline, privacy, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// How long /lights/discover listens for WLED devices
+#[cfg(feature = "mdns")]
+const MDNS_BROWSE: Duration = Duration::from_secs(3);
+#[cfg(feature = "mdns")]
+const WLED_SERVICE: &str = "_wled._tcp.local.";
+
+/////////////////////////////////////////////////////////////
+// Lights
+/////////////////////////////////////////////////////////////
+pub struct Lights {
+    client: reqwest::Client,
+    // A pulse is running
+    busy: AtomicBool,
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Lights {
+            client: privacy::client(),
+            busy: AtomicBool::default(),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// check
+//
+// Called with each logged record; pulses in the background for
+// a response worth looking at.
+/////////////////////////////////////////////////////////////
+pub async fn check(app_data: &web::Data<AppState>, event: &str, text: &str) {
+    if event != "response" || pipeline::is_listening(text) {
+        return;
+    }
+    let settings = app_data.config.read().await.lights.clone();
+    if !settings.enabled || app_data.lights.busy.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let span = tracing::info_span!(parent: None, "lights", kind = %settings.kind);
+    let data = app_data.clone();
+    let run = async move {
+        match pulse(&data.lights.client, &settings).await {
+            Ok(()) => tracing::debug!("light pulsed"),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "light pulse failed"),
+        }
+        data.lights.busy.store(false, Ordering::SeqCst);
+    };
+    app_data.tasks.spawn("lights", run.instrument(span));
+}
+
+async fn pulse(client: &reqwest::Client, settings: &LightSettings) -> Result<()> {
+    let base = format!("http://{}", settings.host);
+    if settings.kind == "wled" {
+        let state: Value = send(client.get(format!("{base}/json/state"))).await?;
+        let lit = json!({ "on": true, "bri": settings.brightness, "transition": 0 });
+        send(client.post(format!("{base}/json/state")).json(&lit)).await?;
+        tokio::time::sleep(Duration::from_millis(settings.pulse_ms)).await;
+        let before = json!({ "on": state["on"], "bri": state["bri"], "transition": 0 });
+        send(client.post(format!("{base}/json/state")).json(&before)).await?;
+    } else {
+        let url = format!("{base}/api/{}/lights/{}/state", settings.username, settings.light);
+        let reply = send(client.put(url).json(&json!({ "alert": "select" }))).await?;
+        hue_result(&reply)?;
+    }
+    Ok(())
+}
+
+// The JSON reply to a request on the LAN
+async fn send(request: reqwest::RequestBuilder) -> Result<Value> {
+    let resp = request.timeout(REQUEST_TIMEOUT).send().await.context("light unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        anyhow::bail!("light replied {status}");
+    }
+    resp.json().await.with_context(|| format!("light replied {status} without JSON"))
+}
+
+// The bridge answers 200 with [{"error": {...}}] when it refuses
+fn hue_result(reply: &Value) -> Result<&Value> {
+    let first = reply.get(0).unwrap_or(reply);
+    if let Some(error) = first.get("error") {
+        anyhow::bail!("Hue bridge refused: {}", error["description"].as_str().unwrap_or("no reason given"));
+    }
+    Ok(first)
+}
+
+/////////////////////////////////////////////////////////////
+// LightsView / LightsPatch
+//
+// The settings as /lights/settings shows them (app key hidden)
+// and the change a PATCH makes; unknown fields are rejected.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct LightsView {
+    enabled: bool,
+    kind: String,
+    host: String,
+    // Whether a Hue app key is set; it's never shown
+    paired: bool,
+    light: String,
+    pulse_ms: u64,
+    brightness: u8,
+}
+
+impl LightsView {
+    fn from_settings(settings: &LightSettings) -> LightsView {
+        LightsView {
+            enabled: settings.enabled,
+            kind: settings.kind.clone(),
+            host: settings.host.clone(),
+            paired: !settings.username.is_empty(),
+            light: settings.light.clone(),
+            pulse_ms: settings.pulse_ms,
+            brightness: settings.brightness,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LightsPatch {
+    enabled: Option<bool>,
+    kind: Option<String>,
+    host: Option<String>,
+    username: Option<String>,
+    light: Option<String>,
+    pulse_ms: Option<u64>,
+    brightness: Option<u8>,
+}
+
+impl LightsPatch {
+    fn apply(self, settings: &mut LightSettings) {
+        set(&mut settings.enabled, self.enabled);
+        set(&mut settings.kind, self.kind);
+        set(&mut settings.host, self.host);
+        set(&mut settings.username, self.username);
+        set(&mut settings.light, self.light);
+        set(&mut settings.pulse_ms, self.pulse_ms);
+        set(&mut settings.brightness, self.brightness);
+    }
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /lights/settings, PATCH /lights/settings
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "lights",
+    path = "/lights/settings",
+    responses(
+        (status = 200, description = "Light cue settings", body = LightsView),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("/settings")]
+async fn get_settings(app_data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(LightsView::from_settings(&app_data.config.read().await.lights))
+}
+
+#[utoipa::path(
+    tag = "lights",
+    path = "/lights/settings",
+    request_body = LightsPatch,
+    responses(
+        (status = 200, description = "Updated light cue settings", body = LightsView),
+        (status = 400, description = "Unknown field or invalid value (code invalid_json / invalid_settings)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[patch("/settings")]
+async fn patch_settings(
+    app_data: web::Data<AppState>,
+    patch: web::Json<LightsPatch>,
+) -> Result<HttpResponse, ApiError> {
+    let mut live = app_data.config.write().await;
+    let mut updated = live.clone();
+    patch.into_inner().apply(&mut updated.lights);
+    updated.validate().map_err(|e| {
+        ApiError::bad_request("invalid_settings", "Invalid settings").with_detail(format!("{e:#}"))
+    })?;
+    *live = updated;
+
+    tracing::info!(enabled = live.lights.enabled, kind = %live.lights.kind, "light settings updated");
+    Ok(HttpResponse::Ok().json(LightsView::from_settings(&live.lights)))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /lights/discover
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema, Default)]
+pub(crate) struct DiscoveredLights {
+    hue_bridges: Vec<FoundDevice>,
+    wled: Vec<FoundDevice>,
+    // On the configured Hue bridge, once paired
+    hue_lights: Vec<HueLight>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct FoundDevice {
+    name: String,
+    // For lights.host
+    host: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct HueLight {
+    // For lights.light
+    id: String,
+    name: String,
+}
+
+#[utoipa::path(
+    tag = "lights",
+    path = "/lights/discover",
+    responses(
+        (status = 200, description = "Lights and bridges found; a lookup that fails is just empty", body = DiscoveredLights),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("/discover")]
+async fn discover(app_data: web::Data<AppState>) -> HttpResponse {
+    let (settings, mdns) = {
+        let config = app_data.config.read().await;
+        (config.lights.clone(), config.discovery.enabled)
+    };
+    let client = &app_data.lights.client;
+    let mut found = DiscoveredLights::default();
+
+    match hue_bridges(client, &settings.hue_discovery_url).await {
+        Ok(bridges) => found.hue_bridges = bridges,
+        Err(e) => tracing::warn!(error = %format!("{e:#}"), "couldn't look up Hue bridges"),
+    }
+    if settings.kind == "hue" && !settings.host.is_empty() && !settings.username.is_empty() {
+        match hue_lights(client, &settings).await {
+            Ok(lights) => found.hue_lights = lights,
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "couldn't list the Hue bridge's lights"),
+        }
+    }
+    if mdns {
+        found.wled = wled_devices().await;
+    }
+    HttpResponse::Ok().json(found)
+}
+
+async fn hue_bridges(client: &reqwest::Client, url: &str) -> Result<Vec<FoundDevice>> {
+    if url.is_empty() {
+        return Ok(Vec::new());
+    }
+    let bridges: Value = send(client.get(url)).await?;
+    Ok(bridges
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|bridge| {
+            Some(FoundDevice {
+                name: bridge["id"].as_str().unwrap_or("Hue bridge").to_string(),
+                host: bridge["internalipaddress"].as_str()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+async fn hue_lights(client: &reqwest::Client, settings: &LightSettings) -> Result<Vec<HueLight>> {
+    let url = format!("http://{}/api/{}/lights", settings.host, settings.username);
+    let reply = send(client.get(url)).await?;
+    hue_result(&reply)?;
+    let mut lights: Vec<HueLight> = reply
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(id, light)| HueLight {
+            id: id.clone(),
+            name: light["name"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect();
+    lights.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(lights)
+}
+
+#[cfg(feature = "mdns")]
+async fn wled_devices() -> Vec<FoundDevice> {
+    use mdns_sd::{ServiceDaemon, ServiceEvent};
+
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            tracing::warn!(error = %e, "couldn't start mDNS to look for WLED");
+            return Vec::new();
+        }
+    };
+    let mut devices = Vec::new();
+    if let Ok(events) = daemon.browse(WLED_SERVICE) {
+        let deadline = tokio::time::Instant::now() + MDNS_BROWSE;
+        while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, events.recv_async()).await {
+            let ServiceEvent::ServiceResolved(info) = event else { continue };
+            let Some(address) = info.get_addresses().iter().find(|a| a.is_ipv4()) else { continue };
+            let fullname = info.get_fullname();
+            devices.push(FoundDevice {
+                name: fullname.strip_suffix(&format!(".{WLED_SERVICE}")).unwrap_or(fullname).to_string(),
+                host: format!("{address}:{}", info.get_port()),
+            });
+        }
+    }
+    let _ = daemon.shutdown();
+    devices
+}
+
+#[cfg(not(feature = "mdns"))]
+async fn wled_devices() -> Vec<FoundDevice> {
+    Vec::new()
+}
+
+/////////////////////////////////////////////////////////////
+// POST /lights/pair
+//
+// Asks the bridge at lights.host for an app key and keeps it
+// (until restart; copy it into the config file).
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PairResponse {
+    // For lights.username (HUE_USERNAME)
+    username: String,
+}
+
+#[utoipa::path(
+    tag = "lights",
+    path = "/lights/pair",
+    responses(
+        (status = 200, description = "Paired; the app key to put in the config", body = PairResponse),
+        (status = 400, description = "lights.host isn't set (code no_bridge)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+        (status = 502, description = "The bridge refused, e.g. its link button wasn't pressed (code pairing_failed)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("/pair")]
+async fn pair(app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let host = app_data.config.read().await.lights.host.clone();
+    if host.is_empty() {
+        return Err(ApiError::bad_request("no_bridge", "Set lights.host to the Hue bridge's address first"));
+    }
+    let body = json!({ "devicetype": "silentnight#server" });
+    let username = async {
+        let reply = send(app_data.lights.client.post(format!("http://{host}/api")).json(&body)).await?;
+        let success = hue_result(&reply)?;
+        success["success"]["username"].as_str().map(str::to_string).context("the bridge sent no app key")
+    }
+    .await
+    .map_err(|e| ApiError::bad_gateway("pairing_failed", "Pairing with the Hue bridge failed").with_detail(format!("{e:#}")))?;
+
+    let mut config = app_data.config.write().await;
+    config.lights.kind = "hue".to_string();
+    config.lights.username = username.clone();
+    tracing::info!(bridge = %host, "paired with the Hue bridge");
+    Ok(HttpResponse::Ok().json(PairResponse { username }))
+}
+
+/////////////////////////////////////////////////////////////
+// POST /lights/test
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "lights",
+    path = "/lights/test",
+    responses(
+        (status = 204, description = "The light pulsed"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+        (status = 502, description = "The light couldn't be reached or refused (code light_failed)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("/test")]
+async fn test(app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let settings = app_data.config.read().await.lights.clone();
+    pulse(&app_data.lights.client, &settings)
+        .await
+        .map_err(|e| ApiError::bad_gateway("light_failed", "The light didn't pulse").with_detail(format!("{e:#}")))?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/lights")
+            .wrap(from_fn(admin::require_admin))
+            .service(get_settings)
+            .service(patch_settings)
+            .service(discover)
+            .service(pair)
+            .service(test),
+    );
+}
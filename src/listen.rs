@@ -0,0 +1,111 @@
+/////////////////////////////////////////////////////////////
+// src/listen.rs
+//
+// Where the HTTP server listens.
+//
+// Settings ([server] or env):
+//   bind_addr    (BIND_ADDR)    IP to listen on. The default "::"
+//                is dual-stack: every IPv6 and IPv4 address (falls
+//                back to 0.0.0.0 if the kernel has no IPv6).
+//                "0.0.0.0" for IPv4 only, or a single address
+//                such as "127.0.0.1" or "fd00::5".
+//   port         (PORT)         TCP port, default 8080
+//   listen_tcp   (LISTEN_TCP)   set false to skip TCP entirely
+//   unix_socket  (UNIX_SOCKET)  also serve plain HTTP on this
+//                Unix socket, for an nginx on the same Pi that
+//                terminates TLS (proxy_pass http://unix:<path>:)
+//
+// The socket file is created world-writable so the proxy user
+// can connect; restrict access with the permissions of the
+// directory it lives in. It is removed again on shutdown.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use socket2::{Domain, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+
+// Same backlog actix uses for its own bind()
+const BACKLOG: i32 = 1024;
+
+#[cfg(unix)]
+pub use unix::{remove_unix_socket, unix_listener};
+
+// Accepts "::1" as well as "[::1]"
+pub fn parse_bind_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim();
+    let addr = addr.strip_prefix('[').and_then(|a| a.strip_suffix(']')).unwrap_or(addr);
+    addr.parse().ok()
+}
+
+/////////////////////////////////////////////////////////////
+// tcp_listener
+//
+// Binds bind_addr:port. For "::" we clear IPV6_V6ONLY so IPv4
+// clients get in too, whatever the system default is.
+/////////////////////////////////////////////////////////////
+pub fn tcp_listener(bind_addr: &str, port: u16) -> Result<TcpListener> {
+    let ip = parse_bind_addr(bind_addr).with_context(|| format!("Invalid bind address {bind_addr:?}"))?;
+
+    if ip.is_ipv6() && ip.is_unspecified() {
+        if let Err(e) = Socket::new(Domain::IPV6, Type::STREAM, None) {
+            tracing::warn!(error = %e, "IPv6 unavailable, listening on IPv4 only");
+            return bind_tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+        }
+    }
+    bind_tcp(SocketAddr::new(ip, port))
+}
+
+fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)
+        .with_context(|| format!("Failed to create a socket for {addr}"))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false).context("Failed to enable dual-stack IPv6")?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into()).with_context(|| format!("Failed to bind {addr}"))?;
+    socket.listen(BACKLOG).with_context(|| format!("Failed to listen on {addr}"))?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{Context, Result};
+    use std::fs;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    /////////////////////////////////////////////////////////
+    // unix_listener
+    //
+    // Binds the socket, replacing one left behind by a previous
+    // run. Refuses if another server is still answering on it or
+    // the path is something other than a socket.
+    /////////////////////////////////////////////////////////
+    pub fn unix_listener(path: &str) -> Result<UnixListener> {
+        let socket = Path::new(path);
+        if let Ok(meta) = fs::symlink_metadata(socket) {
+            if !meta.file_type().is_socket() {
+                anyhow::bail!("{path} exists and is not a socket");
+            }
+            if UnixStream::connect(socket).is_ok() {
+                anyhow::bail!("{path} is in use by another running server");
+            }
+            fs::remove_file(socket).with_context(|| format!("Failed to remove stale socket {path}"))?;
+        }
+        if let Some(dir) = socket.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let listener = UnixListener::bind(socket).with_context(|| format!("Failed to bind {path}"))?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o666))
+            .with_context(|| format!("Failed to set permissions on {path}"))?;
+        Ok(listener)
+    }
+
+    pub fn remove_unix_socket(path: &str) {
+        if let Err(e) = fs::remove_file(path) {
+            tracing::debug!(path, error = %e, "couldn't remove Unix socket");
+        }
+    }
+}
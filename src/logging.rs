@@ -0,0 +1,176 @@
+/////////////////////////////////////////////////////////////
+// src/logging.rs
+//
+// Structured logging with `tracing`.
+//
+// Settings ([logging] or env):
+//   level  (LOG_LEVEL, --log-level)  "info" by default; any
+//          tracing filter works, e.g. "info,my_project=debug".
+//          RUST_LOG, if set, wins over all of these.
+//   format (LOG_FORMAT)  "text" for humans, "json" for one JSON
+//          object per line (log shippers), "journald" to send
+//          structured entries straight to the systemd journal
+//          (the generated unit file uses this).
+// Text and JSON lines have secrets masked on their way out (see
+// secrets.rs).
+//
+// Every HTTP request runs in a "request" span carrying a
+// request ID (taken from an incoming X-Request-Id header or
+// generated, and echoed back in the response). Every recorded
+// chunk runs in a "chunk" span whose chunk_id also goes into
+// conversation_log.json, so a chunk can be followed from
+// capture through Whisper and GPT to the log file.
+/////////////////////////////////////////////////////////////
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::LoggingSettings;
+use crate::secrets;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Lets a config reload change the level without a restart
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/////////////////////////////////////////////////////////////
+// init
+//
+// Installs the global subscriber. Call once, right after the
+// config is loaded.
+/////////////////////////////////////////////////////////////
+pub fn init(settings: &LoggingSettings) -> Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(directives),
+        _ => EnvFilter::try_new(&settings.level),
+    }
+    .context("Invalid log level")?;
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let fmt = tracing_subscriber::fmt::layer().with_target(true).with_writer(Redacted);
+    let registry = tracing_subscriber::registry().with(filter);
+    match settings.format.as_str() {
+        "json" => registry.with(fmt.json().flatten_event(true)).try_init(),
+        "journald" => registry.with(journald_layer()?).try_init(),
+        _ => registry.with(fmt).try_init(),
+    }
+    .context("Failed to install the tracing subscriber")?;
+
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn journald_layer() -> Result<tracing_journald::Layer> {
+    Ok(tracing_journald::layer()
+        .context("Couldn't connect to journald")?
+        .with_syslog_identifier("silentnight".to_string()))
+}
+
+#[cfg(not(unix))]
+fn journald_layer() -> Result<tracing_subscriber::layer::Identity> {
+    anyhow::bail!("logging.format = \"journald\" needs a Unix system with systemd")
+}
+
+/////////////////////////////////////////////////////////////
+// Redacted
+//
+// Stdout with secrets masked. fmt hands over each line in one
+// write, so a secret is never split between two.
+/////////////////////////////////////////////////////////////
+struct Redacted;
+
+struct RedactedStdout(io::Stdout);
+
+impl<'a> MakeWriter<'a> for Redacted {
+    type Writer = RedactedStdout;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactedStdout(io::stdout())
+    }
+}
+
+impl Write for RedactedStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.0.write_all(secrets::redact(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// Applies a new logging.level (RUST_LOG, if set, still wins)
+pub fn set_level(level: &str) -> Result<()> {
+    if std::env::var("RUST_LOG").is_ok_and(|v| !v.is_empty()) {
+        return Ok(());
+    }
+    let filter = EnvFilter::try_new(level).context("Invalid log level")?;
+    if let Some(handle) = FILTER.get() {
+        handle.reload(filter).context("Failed to change the log level")?;
+    }
+    Ok(())
+}
+
+// Short random ID for requests and chunks
+pub fn new_id() -> String {
+    format!("{:08x}", rand::thread_rng().gen::<u32>())
+}
+
+/////////////////////////////////////////////////////////////
+// trace_requests (middleware)
+//
+// Runs the rest of the request inside a "request" span and
+// logs one line when it completes.
+/////////////////////////////////////////////////////////////
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(new_id);
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let started = Instant::now();
+
+    async move {
+        let mut res = next.call(req).await?;
+        tracing::info!(
+            status = res.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "request finished"
+        );
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            res.headers_mut()
+                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(res)
+    }
+    .instrument(span)
+    .await
+}
@@ -3,7 +3,9 @@
 //
 // A single Actix Web server that records 5s of audio
 // in memory. It can switch between macOS "rec" (SoX)
-// and Linux "arecord" based on MIC_BACKEND env var.
+// and Linux "arecord" based on the mic_backend setting.
+// Settings come from silentnight.toml, env vars and CLI
+// flags (see config.rs).
 //
 // Then sends the captured WAV data to OpenAI Whisper & GPT.
 // Logging has been expanded so you can confirm local calls.
@@ -24,85 +26,557 @@
 // ADDITION:
 // - We now keep up to the last 20 messages in conversation_history 
 //   to provide context to GPT each time we process a new chunk.
+//
+// SSE RESUME:
+// - Every broadcast record gets a numeric event ID and is kept in
+//   a small replay buffer, so a reconnecting EventSource that sends
+//   Last-Event-ID receives what it missed.
+// - /live_log emits ": keepalive" comments so idle connections
+//   aren't dropped by proxies.
+// - GET /poll_log?since=<id> long-polls for the same records, for
+//   clients that can't hold an SSE connection.
+// - ?schema=2 sends records and notices alike in one versioned
+//   shape with their kind, speaker, language and confidence
+//   (see events.rs).
+// - GET /entries/{id} returns one record, with the Whisper
+//   segments a transcript was made from.
+//
+// LOGIN:
+// - Optional username/password login for the web UI (see auth.rs).
+// - [tokens.<name>] hand devices a display, control or admin
+//   scope instead of the login (see tokens.rs).
+//
+// HTTPS:
+// - Optional rustls termination, configured via TLS_* env vars
+//   (see tls.rs).
+//
+// UNIX SOCKET:
+// - Optionally serve on a Unix socket, alongside or instead of
+//   TCP, for a reverse proxy on the same machine (see listen.rs).
+//
+// RATE LIMITING:
+// - /start_recording and /conversation_log are rate limited per
+//   client (see rate_limit.rs).
+//
+// LOGGING:
+// - Logs go through `tracing` (see logging.rs): a span per HTTP
+//   request and per recorded chunk, configurable level, text or
+//   JSON output.
+//
+// SYSTEMD:
+// - sd_notify readiness/reload/watchdog, journald logging and
+//   `--install-service` to write a unit file (see systemd.rs).
+//
+// ADMIN:
+// - GET/PATCH /admin/settings to tune a live installation
+//   (see admin.rs).
+//
+// WEBHOOKS:
+// - Signed JSON POSTs to [[webhooks]] targets for new transcripts,
+//   responses and session start/stop, with retries; GET /webhooks
+//   shows delivery status (see webhooks.rs).
+//
+// ALERTS:
+// - Slack messages when alerts.keywords turn up in a transcript,
+//   with the context and a link to the session; senders share
+//   the Notifier trait (see notify.rs).
+//
+// DISCORD:
+// - Session summaries and (optionally) GPT responses posted to a
+//   channel, and /silentnight start|stop as a slash command
+//   (see discord.rs).
+//
+// TELEGRAM:
+// - /start_recording, /stop, /status and /last from authorized
+//   chats, plus a daily digest and keyword alerts
+//   (see telegram.rs, digest.rs).
+//
+// EMAIL:
+// - The daily or weekly digest, and optionally each session's
+//   summary, mailed over SMTP, behind the "email" cargo feature
+//   and [email] enabled (see email.rs).
+//
+// CALENDAR:
+// - Starts a session titled after each meeting on a CalDAV
+//   calendar or iCalendar feed, with its attendees, and stops it
+//   when the meeting ends (see calendar.rs).
+//
+// RULES:
+// - Conditions on transcripts/responses (keywords, speaker,
+//   sentiment, time of day) that trigger webhooks, MQTT messages,
+//   GPIO pins or spoken announcements, managed through /rules
+//   (see rules.rs).
+//
+// REMINDERS:
+// - Timers and reminders GPT sets through function calling,
+//   announced over SSE, webhooks and TTS when due and kept
+//   across restarts (see tools.rs, reminders.rs).
+//
+// WEATHER:
+// - Open-Meteo forecasts and the local date/time, for GPT to
+//   look up through function calling (see weather.rs).
+//
+// HUB AND NODES:
+// - Small boxes (say a Pi Zero per room) run as nodes that only
+//   capture and send chunks to one hub, which does Whisper, GPT
+//   and the log; each node is one of the hub's audio sources
+//   (see hub.rs, node.rs).
+//
+// SYNC:
+// - Full instances pull each other's log entries, so a session
+//   recorded on one is searchable on all of them; the later
+//   write of an entry wins (see sync.rs).
+//
+// TRANSCRIPT BROWSER:
+// - GET /sessions lists past sessions and /sessions/{id} shows
+//   one's transcript, as pages rendered on the server (see
+//   browse.rs).
+// - GET /sessions/{id}/replay streams a past session over SSE in
+//   its original timing, for display clients (see replay.rs).
+//
+// DISPLAY PROFILES:
+// - GET/PUT /display/profile sets each display's font size,
+//   colors, layout and refresh; the web UI follows the one named
+//   in its ?display= (see display.rs).
+//
+// LANGUAGE:
+// - Pages, buttons and status lines in English, German, Spanish
+//   or French: ui.language, or each browser's Accept-Language.
+//   The web UI gets its strings from GET /i18n (see i18n.rs).
+//
+// PAIRING:
+// - GET /qr.png is a QR code phones scan to open the UI, with a
+//   single-use login token when login is on (see pairing.rs).
+//
+// EXPORT:
+// - Session notes as Markdown in an Obsidian vault or as Notion
+//   pages, when a session stops or on POST /sessions/{id}/export
+//   (see export.rs).
+//
+// TRANSLATION:
+// - POST /sessions/{id}/translate?lang=es has GPT translate a
+//   session's transcript and keeps it for the transcript
+//   browser and exports (see translate.rs).
+//
+// MEETINGS:
+// - POST /meetings records a meeting with a title and attendees;
+//   when it stops GPT writes its minutes (summary, decisions,
+//   action items with owners), kept for GET /meetings/{id}/minutes,
+//   webhooks and exports (see meetings.rs).
+//
+// LIGHT CUE:
+// - A Hue or WLED light pulses whenever GPT has something to
+//   say, set up through /lights (see lights.rs).
+//
+// REMOTE:
+// - IR remote (LIRC) and media-key (evdev) presses start/stop
+//   recording, bookmark, mute or switch persona (see remote.rs,
+//   control.rs, bookmarks.rs).
+//
+// VOICE:
+// - "silent night, stop listening" and other spoken commands in
+//   a recording source's transcripts are run, and kept from GPT
+//   and the log (see voice.rs).
+//
+// HOMEKIT:
+// - Recording as a switch in the Home app (and for Siri), paired
+//   with a setup code, behind the "homekit" cargo feature and
+//   [homekit] enabled (see homekit.rs).
+//
+// QUIET HOURS:
+// - Windows of the day when nothing is recorded and running
+//   sources pause, overridable only by an admin (see quiet.rs).
+//
+// OPT-OUT:
+// - Voice profiles of people who don't want to be recorded;
+//   chunks they dominate are discarded and their speech silenced
+//   in the rest, before anything else hears it (see optout.rs).
+//
+// SPEAKERS:
+// - speakers.mode = "anonymous" labels who spoke in each
+//   transcript only as "Speaker A", "Speaker B", ..., with no
+//   voice kept past the session (see speakers.rs).
+//
+// OUTPUTS:
+// - [outputs] routes transcripts and responses to any of the
+//   log, SSE, webhooks, MQTT, TTS and an e-ink command (see
+//   outputs.rs).
+//
+// SESSION SETTINGS:
+// - POST /start_recording takes optional per-session overrides
+//   (persona, chunk length, STT model, language), and each
+//   session's effective settings are kept for
+//   GET /sessions/{id}/settings (see session_settings.rs).
+//
+// DEDUP:
+// - dedup.enabled drops a transcript that repeats the one
+//   before it and cuts words repeated across the chunk boundary
+//   (see dedup.rs).
+//
+// SIMULATION:
+// - --simulate <PATH> feeds every source from WAV fixtures or a
+//   .txt script at the pace they were spoken, through the same
+//   Whisper, GPT, log and live logs as the mic (see recorder.rs).
+//
+// FEEDBACK:
+// - POST /entries/{id}/feedback rates a response up or down,
+//   GET /feedback/stats sums the ratings per persona, and
+//   feedback.prompt_items shows GPT the latest (see feedback.rs).
+//
+// WORKSPACES:
+// - [workspaces.<name>] give teams sharing the hub their own log,
+//   persona and retention, and tokens confined to them (see
+//   workspaces.rs).
+//
+// CAPTIONS:
+// - GET /captions streams each transcript word by word, at the
+//   pace it was spoken, for karaoke-style live captions, with
+//   captions.enabled (see captions.rs).
+//
+// TIME ZONE:
+// - Times are kept in UTC and shown in ui.timezone, e.g.
+//   "Europe/Berlin": session pages, exports, digests and emails
+//   use it, and records sent out carry "local_time" next to
+//   "timestamp" (see timezone.rs).
+//
+// ROUTING:
+// - [routing] says which providers audio, transcripts and
+//   summaries may each be sent to, e.g. audio to a local
+//   whisper.cpp only and summaries to an EU Azure endpoint
+//   (see routing.rs).
+//
+// FORGET:
+// - "Silent night, forget the last five minutes" deletes what
+//   the source recorded since then, log records, audio and
+//   bookmarks alike, and says so (see forget.rs).
+//
+// AUDIT:
+// - Every control action (start/stop, settings, deletions,
+//   exports, logins) with who, when and from where, in an
+//   append-only file behind GET /audit (see audit.rs).
+//
+// REDACTION:
+// - Phone numbers, emails, card numbers and addresses masked in
+//   each transcript before it's logged, shown or sent to GPT,
+//   by regex and optionally a NER model (see redact.rs).
+//
+// SECRETS:
+// - Keys can come from a private file, the OS keyring or a
+//   systemd credential instead of plain settings, are masked in
+//   logs and /status, and rotate at runtime through
+//   POST /admin/keys (see secrets.rs).
+//
+// PRIVACY:
+// - PRIVACY_MODE=local refuses every connection outside the local
+//   network where HTTP clients, MQTT and SMTP connect, whatever
+//   else is configured (see privacy.rs).
+//
+// ERROR REPORTS:
+// - Panics and pipeline failures go to Sentry or GlitchTip, with
+//   the backend, chunk length and last OpenAI status, and without
+//   transcripts (see sentry.rs).
+//
+// CLIENT:
+// - `silentnight status|start|stop|tail|export` talk to a running
+//   server over HTTP; no subcommand (or `serve`) runs it
+//   (see client.rs).
+//
+// GRAPHQL:
+// - Read-only POST /graphql over log entries, sessions and stats
+//   for dashboards, behind the "graphql" cargo feature (on by
+//   default; see graphql.rs).
+//
+// GRPC:
+// - Optional gRPC API with log and audio streaming, behind the
+//   "grpc" cargo feature and [grpc] enabled (see grpc.rs).
+//
+// HOME ASSISTANT:
+// - Each source as a recording switch and state/last-response
+//   sensors via MQTT discovery, behind the "mqtt" cargo feature
+//   and [mqtt] enabled (see mqtt.rs).
+//
+// DISCOVERY:
+// - Advertised on the LAN as _silentnight._tcp via mDNS, and
+//   GET /discover lists other instances; mDNS itself is behind
+//   the "mdns" cargo feature (on by default; see discovery.rs).
+//
+// ONE-SHOT:
+// - POST /record_once?duration=N records a single chunk and
+//   returns its transcript and GPT response directly.
+//
+// MONITORING:
+// - GET /health, GET /health/ready and GET /status (see
+//   status.rs). Each recording run gets a session ID, and we
+//   count processed chunks and remember the last pipeline error.
+// - Free disk space is watched; when it runs low, a "disk_space"
+//   SSE event warns and chunks stop being saved to
+//   audio.save_dir, while transcripts carry on (see disk.rs).
+//
+// API DOCS:
+// - GET /openapi.json and a Swagger UI page at GET /docs
+//   (see openapi.rs).
+//
+// HOT RELOAD:
+// - POST /reload or SIGHUP re-reads the config and applies what
+//   can change live (see reload.rs). Code that needs settings
+//   takes a snapshot from app_data.config per use.
+//
+// AUDIO SOURCES:
+// - Each configured source ("default" plus [[audio.sources]])
+//   records independently with its own history, transcript and
+//   live log (see sessions.rs). The original endpoints act on
+//   "default"; /live_log streams every source, and each record
+//   names its audio_source and session_id.
+//
+// PIPELINE:
+// - Capture, Whisper, GPT and logging run as stages connected by
+//   channels, each retrying its own failures (see pipeline.rs).
+//   Errors are typed (see error.rs) so retryable ones don't stop
+//   a recording and handlers return the right status. With
+//   audio.save_dir set, captured chunks are also written to disk
+//   (this replaces the old standalone server.rs). Captured WAV
+//   is validated before upload (see wav.rs). With
+//   audio.deadline_secs set, a transcript GPT is slow to answer
+//   goes out ahead of its response.
+//
+// TASKS:
+// - Recordings, webhook deliveries, discovery, gRPC and the SIGHUP
+//   listener are spawned through a TaskManager and cancelled via
+//   tokens on stop, reload and shutdown (see tasks.rs). A failed
+//   or panicking recording, gRPC server or SIGHUP listener is
+//   restarted with backoff (see supervisor.rs).
+//
+// LIFECYCLE:
+// - Each source is idle, starting, recording, processing,
+//   stopping or in error; shown on /status and sent as "state"
+//   SSE events (see lifecycle.rs).
+//
+// BACKLOG:
+// - Without an OpenAI key, or while OpenAI is unreachable, sources
+//   keep recording and chunks (or transcripts GPT couldn't
+//   answer) wait on disk in backlog.dir. They're worked through
+//   in order once OpenAI is back, checked every
+//   backlog.retry_secs, or on POST /process_backlog, with
+//   "backlog_progress" SSE events (see backlog.rs).
+//
+// TELEMETRY:
+// - Per-stage, end-to-end and OpenAI request latency on /status,
+//   GET /metrics for Prometheus, and periodic "telemetry" SSE
+//   events with throughput (see telemetry.rs).
+// - GET /dashboard shows it for people: each source's state,
+//   queues and last error, and OpenAI latency sparklines,
+//   reloading itself (see dashboard.rs).
 /////////////////////////////////////////////////////////////
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
-use std::env;
+mod admin;
+mod assets;
+mod audit;
+mod auth;
+mod backlog;
+mod bookmarks;
+mod browse;
+mod calendar;
+mod captions;
+mod client;
+mod config;
+mod control;
+mod dashboard;
+mod dedup;
+mod digest;
+mod discord;
+mod disk;
+mod discovery;
+mod display;
+#[cfg(feature = "email")]
+mod email;
+mod error;
+mod events;
+mod export;
+mod feedback;
+mod forget;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod hex;
+#[cfg(feature = "homekit")]
+mod homekit;
+mod lifecycle;
+mod lights;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hub;
+mod i18n;
+mod listen;
+mod logging;
+mod meetings;
+mod memory;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod node;
+mod notify;
+mod openai_limit;
+mod openapi;
+mod optout;
+mod outputs;
+mod pairing;
+mod pipeline;
+mod privacy;
+mod qr;
+mod quiet;
+mod rate_limit;
+mod recorder;
+mod redact;
+mod reload;
+mod reminders;
+mod remote;
+mod replay;
+mod routing;
+mod rules;
+mod segmenter;
+mod sentry;
+mod session_settings;
+mod sessions;
+mod status;
+mod secrets;
+mod speakers;
+mod supervisor;
+mod sync;
+mod systemd;
+mod tasks;
+mod telegram;
+mod telemetry;
+mod timezone;
+mod tls;
+mod tokens;
+mod tools;
+mod translate;
+mod voice;
+mod wav;
+mod weather;
+mod webhooks;
+mod workspaces;
+
+use actix_web::http::header::{self, ContentType};
+use actix_web::{get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use std::sync::Arc;
-use std::fs;
-
-use tokio::sync::{Mutex as AsyncMutex, broadcast};
-use tokio::io::AsyncReadExt;
-use tokio::process::Command;
-use anyhow::{Context, Result};
-use serde::Serialize;
-use std::process::Stdio;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+
+use tokio::sync::{Mutex as AsyncMutex, RwLock as AsyncRwLock};
+use error::ApiError;
+use lifecycle::RecordingState;
+use pipeline::ChunkAudio;
+use session_settings::Overrides;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use tracing::Instrument;
 
 // ADDED: for timestamps
 use chrono::Utc;
 
-// For streaming lines as SSE
-use futures_util::StreamExt;
-use tokio_stream::wrappers::BroadcastStream;
-use actix_web::web::{Data, Bytes};
+// Static web UI files
+use actix_files::NamedFile;
 
-/////////////////////////////////////////////////////////////
-// For HTTP calls to OpenAI
-/////////////////////////////////////////////////////////////
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+// Every transcript and response, one JSON record per line
+const CONVERSATION_LOG: &str = "conversation_log.json";
+// Held while it's appended to or rewritten (see forget.rs)
+static CONVERSATION_LOG_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
 /////////////////////////////////////////////////////////////
 // Shared state (in an Actix Web Data wrapper).
 /////////////////////////////////////////////////////////////
 struct AppState {
-    // If we're currently recording
-    is_recording: Arc<AsyncMutex<bool>>,
-    // Last transcription from Whisper
-    last_transcript: Arc<AsyncMutex<String>>,
-    // Last GPT response to that transcription
-    last_gpt_response: Arc<AsyncMutex<String>>,
-
-    // SSE broadcast
-    log_sender: broadcast::Sender<String>,
-
-    // NEW: store up to last 20 conversation messages
-    // Each tuple is (role, content), role is "user" or "assistant"
-    conversation_history: Arc<AsyncMutex<Vec<(String, String)>>>,
-}
-
-/////////////////////////////////////////////////////////////
-// GET /  => Serve static/index.html
-/////////////////////////////////////////////////////////////
-#[get("/")]
-async fn index() -> impl Responder {
-    println!("▶ GET / - Serving static/index.html...");
-
-    match fs::read_to_string("static/index.html") {
-        Ok(html) => HttpResponse::Ok().content_type("text/html").body(html),
-        Err(_) => HttpResponse::NotFound().body("<h1>index.html not found</h1>"),
-    }
+    // Effective settings (file + env + CLI), swapped on reload
+    config: AsyncRwLock<config::Config>,
+    // Kept so a reload layers env/CLI over the file the same way
+    cli: config::Cli,
+
+    // Per-source recording state, history and live log
+    sources: sessions::Sources,
+
+    // SSE broadcast of every source's records
+    events: events::EventChannel,
+    // Transcripts word by word (see captions.rs)
+    captions: captions::Captions,
+
+    // Web UI login (None = login disabled) and active sessions
+    login_config: AsyncRwLock<Option<auth::LoginConfig>>,
+    login_sessions: auth::LoginSessions,
+    // Single-use login tokens handed out in QR codes
+    pairing: pairing::PairingTokens,
+
+    // Per-client limits on the expensive endpoints
+    rate_limiter: rate_limit::RateLimiter,
+
+    // Caps concurrent Whisper/GPT requests
+    openai_limiter: openai_limit::OpenAiLimiter,
+    // Pooled connections for every Whisper/GPT request
+    openai_client: reqwest::Client,
+
+    // For /status, totals across every source
+    chunks_processed: AtomicU64,
+    last_error: Arc<AsyncMutex<Option<String>>>,
+    started_at: chrono::DateTime<Utc>,
+    tls_enabled: bool,
+
+    // mDNS advertisement and the peers we've seen
+    discovery: discovery::Discovery,
+
+    // Background tasks and their cancellation tokens
+    tasks: tasks::TaskManager,
+
+    // Outbound webhook deliveries
+    webhooks: webhooks::Webhooks,
+    // Keyword alerts (Slack, ...)
+    notifiers: notify::Notifiers,
+    // Discord bot posts
+    discord: discord::Discord,
+    // Automation rules (see rules.rs)
+    rules: rules::Rules,
+    // Pending timers and reminders (see reminders.rs)
+    reminders: reminders::Reminders,
+    // Audit log of control actions (see audit.rs)
+    audit: audit::Audit,
+    // An admin's override of quiet hours (see quiet.rs)
+    quiet_hours: quiet::QuietHours,
+    // Voices that mustn't be recorded (see optout.rs)
+    opt_out: optout::Profiles,
+    // Log entries pulled from peers (see sync.rs)
+    synced: sync::Synced,
+    // Nodes sending this hub audio (see hub.rs)
+    nodes: hub::Nodes,
+    // The light cue's client and whether it's pulsing
+    lights: lights::Lights,
+    // Publishing to the MQTT broker
+    #[cfg(feature = "mqtt")]
+    mqtt: mqtt::Mqtt,
+
+    // Chunks recorded while OpenAI was unavailable
+    backlog: backlog::Backlog,
+    // Free space and whether audio is being archived (see disk.rs)
+    disk: disk::Disk,
+
+    // Set by POST /admin/shutdown and /admin/restart
+    shutdown: admin::Shutdown,
 }
 
 /////////////////////////////////////////////////////////////
 // GET /transcript
 //
 // Returns JSON with the last transcript and GPT response
+// from the default source
 /////////////////////////////////////////////////////////////
-#[derive(Serialize)]
-struct TranscriptResponse {
-    transcript: String,
-    gpt_response: String,
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub(crate) struct TranscriptResponse {
+    pub transcript: String,
+    pub gpt_response: String,
 }
 
+#[utoipa::path(tag = "log", responses((status = 200, description = "Latest transcript and GPT response", body = TranscriptResponse)))]
 #[get("/transcript")]
 async fn get_transcript(app_data: web::Data<AppState>) -> impl Responder {
-    let transcript = app_data.last_transcript.lock().await.clone();
-    let gpt_resp = app_data.last_gpt_response.lock().await.clone();
-
-    HttpResponse::Ok().json(TranscriptResponse {
-        transcript,
-        gpt_response: gpt_resp,
-    })
+    let source = app_data.sources.default_source(&app_data).await;
+    let latest = source.latest.borrow().clone();
+    HttpResponse::Ok().json(latest)
 }
 
 /////////////////////////////////////////////////////////////
@@ -114,466 +588,641 @@ async fn get_transcript(app_data: web::Data<AppState>) -> impl Responder {
 //   3) Summarize via GPT
 //   4) Append each chunk+response to a local JSON file
 //   5) Update the shared transcript/gpt fields
-// until user calls /stop_recording
+// until user calls /stop_recording. Uses the default source;
+// see sessions.rs for the others. An optional JSON body sets
+// the persona, chunk length, STT model or language for this
+// session only (see session_settings.rs).
 /////////////////////////////////////////////////////////////
-#[post("/start_recording")]
-async fn start_recording(app_data: web::Data<AppState>) -> impl Responder {
-    println!("▶ POST /start_recording - Checking if we're already recording...");
-
-    let mut recording_flag = app_data.is_recording.lock().await;
-    if *recording_flag {
-        println!("   Already recording!");
-        return HttpResponse::Ok().body("Already recording");
-    }
-
-    // Mark ourselves as recording
-    *recording_flag = true;
-    println!("   Setting is_recording = true, spawning background task...");
-
-    let shared_state = app_data.clone();
-    tokio::spawn(async move {
-        if let Err(e) = record_and_process_audio(shared_state).await {
-            println!("   ERROR: record_and_process_audio => {:?}", e);
-        }
-    });
-
-    HttpResponse::Ok().body("Recording started in memory for 5s blocks...")
+#[utoipa::path(tag = "recording", request_body(content = Option<Overrides>, description = "Optional; settings for this session only"), responses(
+    (status = 200, description = "Recording started", body = String),
+    (status = 400, description = "Overrides the config can't take (code invalid_overrides)", body = ErrorBody),
+    (status = 409, description = "Already recording (code already_recording)", body = ErrorBody),
+    (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+    (status = 503, description = "No OpenAI API key configured (code openai_not_configured)", body = ErrorBody),
+))]
+#[post("/start_recording", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn start_recording(
+    app_data: web::Data<AppState>,
+    body: Option<web::Json<Overrides>>,
+) -> Result<HttpResponse, ApiError> {
+    let source = app_data.sources.default_source(&app_data).await;
+    let overrides = body.map(web::Json::into_inner).unwrap_or_default();
+    let chunk_secs = overrides.chunk_secs(&app_data.config.read().await.audio);
+    sessions::start_session(&app_data, source, sessions::SessionDetails { overrides, ..Default::default() }).await?;
+
+    Ok(HttpResponse::Ok().body(format!("Recording started in memory for {}s blocks...", chunk_secs)))
 }
 
 /////////////////////////////////////////////////////////////
 // POST /stop_recording
 //
-// Sets is_recording = false. We do NOT forcibly kill the
-// mic process if it's mid-block (the chunk will wrap up
-// once the 5s finishes).
+// Moves the default source to "stopping": the mic command is
+// killed mid-chunk, chunks already captured still go through
+// Whisper/GPT, then it's "idle".
 /////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "recording", responses((status = 200, description = "Recording is stopping; chunks already captured still finish", body = String)))]
 #[post("/stop_recording")]
 async fn stop_recording(app_data: web::Data<AppState>) -> impl Responder {
-    println!("▶ POST /stop_recording - Setting is_recording = false...");
-    let mut recording_flag = app_data.is_recording.lock().await;
-    *recording_flag = false;
+    let source = app_data.sources.default_source(&app_data).await;
+    sessions::stop_source(&app_data, &source).await;
 
     HttpResponse::Ok().body("Recording stopped")
 }
 
 /////////////////////////////////////////////////////////////
-// MAIN - start Actix web server on port from $PORT or 8080
+// POST /record_once?duration=10
+//
+// Records a single chunk from the default source (duration in
+// seconds, audio.chunk_secs if omitted), runs it through
+// Whisper + GPT right away and returns both in the response.
+// The chunk is logged and joins the conversation history like
+// any other. Refused while the recording loop is running,
+// since both would need the mic.
 /////////////////////////////////////////////////////////////
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Choose port from $PORT or default 8080
-    let port: u16 = env::var("PORT")
-        .ok()
-        .and_then(|val| val.parse().ok())
-        .unwrap_or(8080);
+#[derive(Deserialize, IntoParams)]
+struct RecordOnceQuery {
+    // Seconds to record, 1-60
+    duration: Option<u32>,
+}
 
-    println!("===============================================");
-    println!("🚀 Starting in-memory Audio -> Whisper -> GPT!");
-    println!("   Listening on port {}", port);
-    println!("===============================================");
+#[utoipa::path(
+    tag = "recording",
+    params(RecordOnceQuery),
+    responses(
+        (status = 200, description = "Transcript and GPT response for the recorded chunk", body = TranscriptResponse),
+        (status = 400, description = "Invalid duration (code invalid_duration / invalid_query)", body = ErrorBody),
+        (status = 409, description = "The default source is already recording (code already_recording)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 404, description = "The default source was removed from the config (code unknown_source)", body = ErrorBody),
+        (status = 500, description = "Recording or logging failed (code audio_failed / storage_failed)", body = ErrorBody),
+        (status = 502, description = "The OpenAI API failed or couldn't be reached (code openai_error / openai_unreachable)", body = ErrorBody),
+        (status = 503, description = "No OpenAI API key, mic command missing, or OpenAI busy (code openai_not_configured / mic_unavailable / openai_busy / openai_rate_limited)", body = ErrorBody),
+    ),
+)]
+#[post("/record_once", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn record_once(
+    app_data: web::Data<AppState>,
+    query: web::Query<RecordOnceQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let duration = match query.duration {
+        Some(secs) => secs,
+        None => app_data.config.read().await.audio.chunk_secs,
+    };
+    tracing::info!(duration, "recording a single chunk");
+    if !(1..=60).contains(&duration) {
+        return Err(ApiError::bad_request(
+            "invalid_duration",
+            "duration must be between 1 and 60 seconds",
+        ));
+    }
 
-    // ADDED: Create a broadcast channel for real-time SSE lines
-    let (log_sender, _rx) = broadcast::channel(100);
+    let source = app_data.sources.default_source(&app_data).await;
+    let chunk = single_chunk(&app_data, &source, ChunkAudio::Record(duration)).await?;
+    Ok(HttpResponse::Ok().json(chunk))
+}
 
-    // NEW: Initialize conversation_history
-    let conversation_history = Arc::new(AsyncMutex::new(Vec::new()));
+/////////////////////////////////////////////////////////////
+// single_chunk
+//
+// Runs one chunk outside the recording loop (record_once, and
+// audio sent over gRPC). The source is "processing" for the
+// duration, so this can't overlap its loop.
+/////////////////////////////////////////////////////////////
+pub(crate) async fn single_chunk(
+    app_data: &web::Data<AppState>,
+    source: &sessions::SourceSession,
+    audio: ChunkAudio,
+) -> Result<TranscriptResponse, ApiError> {
+    sessions::require_openai(app_data).await?;
+    quiet::refuse(app_data).await?;
+    if let Err(state) = source.transition(app_data, RecordingState::Processing).await {
+        return Err(sessions::already_recording(source, &state));
+    }
 
-    // Initialize shared state
-    let app_state = web::Data::new(AppState {
-        is_recording: Arc::new(AsyncMutex::new(false)),
-        last_transcript: Arc::new(AsyncMutex::new(String::new())),
-        last_gpt_response: Arc::new(AsyncMutex::new(String::new())),
-        log_sender,
-        conversation_history,
-    });
+    let chunk_id = logging::new_id();
+    let span = tracing::info_span!("chunk", source = %source.name, chunk_id = %chunk_id);
+    let result = pipeline::process_chunk(app_data, source, audio, &chunk_id)
+        .instrument(span)
+        .await;
+    let _ = source.transition(app_data, RecordingState::Idle).await;
 
-    // Launch Actix Web
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .service(index)
-            .service(get_transcript)
-            .service(start_recording)
-            .service(stop_recording)
-            .service(conversation_log) // ADDED
-            .service(live_log_sse)     // ADDED SSE route
-    })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+    match result {
+        Ok(chunk) => Ok(chunk),
+        Err(e) => {
+            let message = e.report();
+            tracing::error!(error = %message, retryable = e.is_retryable(), "single chunk failed");
+            sentry::capture_failure(&*app_data.config.read().await, "record_once", Some(&source.name), &message);
+            *app_data.last_error.lock().await = Some(format!("[{}] {}", source.name, message));
+            Err(e.into())
+        }
+    }
 }
 
 /////////////////////////////////////////////////////////////
-// record_and_process_audio
-//
-// ADDED: Now runs in a loop, capturing 5s chunks while 
-// is_recording = true. For each chunk, we do:
-// 1) record_audio_in_memory(5)
-// 2) transcribe with Whisper
-// 3) build a chat prompt with last 20 messages + new transcript
-// 4) Summarize with GPT
-// 5) append both to a JSON file with timestamps
-// 6) update shared state
+// MAIN - load config, then start Actix web server on the
+// configured port (default 8080)
 /////////////////////////////////////////////////////////////
-async fn record_and_process_audio(app_data: web::Data<AppState>) -> Result<()> {
-    // We loop until is_recording = false
-    loop {
-        {
-            let flag = app_data.is_recording.lock().await;
-            if !*flag {
-                println!("   >>> Recording loop ended (user clicked Stop).");
-                break;
-            }
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = <config::Cli as clap::Parser>::parse();
+    let config = match config::Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {e:#}");
+            std::process::exit(2);
         }
+    };
 
-        println!("   >>> Starting 5s in-memory recording chunk...");
-        let audio_data = record_audio_in_memory(5).await?;
-        println!("   >>> Chunk captured, {} bytes.", audio_data.len());
+    timezone::set(&config.ui.timezone);
 
-        // Transcribe
-        println!("   >>> Sending chunk to Whisper...");
-        let transcript = transcribe_audio_with_whisper(&audio_data).await?;
-        println!("   >>> Transcript: {}", transcript);
+    if cli.print_config {
+        let masked = toml::to_string_pretty(&config.masked()).map_err(std::io::Error::other)?;
+        println!("{masked}");
+        return Ok(());
+    }
 
-        // We add this new user message to conversation history
-        {
-            let mut hist = app_data.conversation_history.lock().await;
-            hist.push(("user".to_string(), transcript.clone()));
+    if let Some(path) = &cli.install_service {
+        if let Err(e) = systemd::install_service(&cli, path) {
+            eprintln!("❌ {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-            // Keep only last 20 messages (40 entries, since each user+assistant is 2)
-            let length = hist.len();
-            if length > 40 {
-                hist.drain(0..(length - 40));
-            }
+    // Client subcommands talk to a running server and exit
+    if let Some(command) = cli.command.clone().filter(|c| !matches!(c, config::Command::Serve)) {
+        if let Err(e) = client::run(&cli, &config, &command).await {
+            eprintln!("❌ {e:#}");
+            std::process::exit(1);
         }
+        return Ok(());
+    }
 
-        // Summarize with GPT using last 20 messages
-        println!("   >>> Summarizing chunk with GPT...");
-        let gpt_response = summarize_with_gpt(&app_data, &transcript).await?;
-        println!("   >>> GPT response: {}", gpt_response);
+    if let Err(e) = logging::init(&config.logging) {
+        eprintln!("❌ {e:#}");
+        std::process::exit(2);
+    }
+    // Before any HTTP client is built
+    privacy::init(&config.privacy);
+
+    let port = config.server.port;
+    let bind_addr = config.server.bind_addr.clone();
+    let listen_tcp = config.server.listen_tcp;
+    #[cfg_attr(not(unix), allow(unused_variables))] // validate() rejects it there
+    let unix_socket = config.server.unix_socket.clone();
+
+    tracing::info!(port, "🚀 Starting in-memory Audio -> Whisper -> GPT!");
+    if let Some(path) = config::config_file_path(&cli) {
+        tracing::info!(path = %path.display(), "loaded config file");
+    }
+    for warning in config.warnings() {
+        tracing::warn!("{warning}");
+    }
 
-        // Add the assistant's response to conversation history
-        {
-            let mut hist = app_data.conversation_history.lock().await;
-            hist.push(("assistant".to_string(), gpt_response.clone()));
+    // HTTPS if tls.cert_path/tls.key_path (or tls.self_signed) are set
+    let tls_config = tls::load_tls_config(&config.tls)
+        .map_err(|e| std::io::Error::other(format!("TLS setup failed: {e:#}")))?;
 
-            let length = hist.len();
-            if length > 40 {
-                hist.drain(0..(length - 40));
-            }
-        }
+    // Web UI login is only enabled when a username/password are configured
+    let login_config = auth::LoginConfig::from_settings(&config.login);
+    if login_config.is_some() {
+        tracing::info!("web UI login enabled");
+    }
 
-        // Append to JSON file for logging
-        append_to_json_log("Microphone", &transcript, &app_data)?;
-        append_to_json_log("OPENAI RESPONSE", &gpt_response, &app_data)?;
+    // Nothing to advertise if we're only reachable through a local socket
+    let mut discovery_settings = config.discovery.clone();
+    discovery_settings.enabled &= listen_tcp;
+    let tasks = tasks::TaskManager::default();
+    sentry::init(&config.sentry, &tasks);
+    let discovery = discovery::Discovery::start(&discovery_settings, port, tls_config.is_some(), &tasks);
+
+    let openai_client = pipeline::openai_client()
+        .map_err(|e| std::io::Error::other(format!("HTTP client setup failed: {e}")))?;
+    let rules = rules::Rules::load(&config.rules.file)
+        .map_err(|e| std::io::Error::other(format!("Rules setup failed: {e:#}")))?;
+    let reminders = reminders::Reminders::load(&config.reminders.file)
+        .map_err(|e| std::io::Error::other(format!("Reminders setup failed: {e:#}")))?;
+    let synced = sync::Synced::load(&config.sync.file)
+        .map_err(|e| std::io::Error::other(format!("Sync setup failed: {e:#}")))?;
+    let opt_out = optout::Profiles::load(&config.opt_out.file)
+        .map_err(|e| std::io::Error::other(format!("Voice profiles setup failed: {e:#}")))?;
 
-        // Update shared state so /transcript endpoint shows the latest
-        {
-            let mut t = app_data.last_transcript.lock().await;
-            *t = transcript;
-        }
-        {
-            let mut g = app_data.last_gpt_response.lock().await;
-            *g = gpt_response;
-        }
+    // Initialize shared state
+    let app_state = web::Data::new(AppState {
+        sources: sessions::Sources::default(),
+        events: events::EventChannel::new(config.server.sse_capacity),
+        captions: captions::Captions::default(),
+        login_config: AsyncRwLock::new(login_config),
+        login_sessions: auth::LoginSessions::default(),
+        pairing: pairing::PairingTokens::default(),
+        rate_limiter: rate_limit::RateLimiter::new(&config.rate_limit),
+        openai_limiter: openai_limit::OpenAiLimiter::new(&config.openai),
+        openai_client,
+        chunks_processed: AtomicU64::new(0),
+        last_error: Arc::new(AsyncMutex::new(None)),
+        started_at: Utc::now(),
+        tls_enabled: tls_config.is_some(),
+        discovery,
+        tasks,
+        webhooks: webhooks::Webhooks::default(),
+        notifiers: notify::Notifiers::default(),
+        discord: discord::Discord::default(),
+        rules,
+        reminders,
+        audit: audit::Audit::new(&config.audit.file),
+        quiet_hours: quiet::QuietHours::default(),
+        opt_out,
+        synced,
+        nodes: hub::Nodes::default(),
+        lights: lights::Lights::default(),
+        #[cfg(feature = "mqtt")]
+        mqtt: mqtt::Mqtt::default(),
+        backlog: backlog::Backlog::default(),
+        disk: disk::Disk::default(),
+        shutdown: admin::Shutdown::default(),
+        config: AsyncRwLock::new(config),
+        cli,
+    });
 
-        {
-            let flag = app_data.is_recording.lock().await;
-            if !*flag {
-                println!("   >>> Recording loop ended after chunk.");
-                break;
-            }
+    // SIGHUP => reload config
+    reload::spawn_sighup_listener(app_state.clone());
+    telemetry::spawn(&app_state);
+    reminders::spawn(&app_state);
+    sync::spawn(&app_state);
+    quiet::spawn(&app_state);
+    disk::spawn(&app_state);
+    workspaces::spawn(&app_state);
+    backlog::spawn(&app_state);
+
+    #[cfg(feature = "grpc")]
+    let grpc_settings = app_state.config.read().await.grpc.clone();
+    #[cfg(feature = "grpc")]
+    if grpc_settings.enabled {
+        grpc::spawn(app_state.clone(), &bind_addr, grpc_settings.port)
+            .map_err(|e| std::io::Error::other(format!("gRPC setup failed: {e:#}")))?;
+    }
+
+    #[cfg(feature = "mqtt")]
+    let mqtt_settings = app_state.config.read().await.mqtt.clone();
+    #[cfg(feature = "mqtt")]
+    if mqtt_settings.enabled {
+        match privacy::check_host(&mqtt_settings.host, mqtt_settings.port).await {
+            Ok(()) => mqtt::spawn(app_state.clone(), &mqtt_settings),
+            Err(e) => tracing::error!(error = %e, "not connecting to the MQTT broker"),
         }
     }
 
-    println!("   >>> Done with continuous chunk loop. is_recording = false.");
-    Ok(())
-}
+    let discord_settings = app_state.config.read().await.discord.clone();
+    if discord_settings.enabled {
+        discord::spawn(app_state.clone(), discord_settings);
+    }
+    let telegram_settings = app_state.config.read().await.telegram.clone();
+    if telegram_settings.enabled {
+        telegram::spawn(app_state.clone(), telegram_settings);
+    }
+    let calendar_settings = app_state.config.read().await.calendar.clone();
+    if calendar_settings.enabled {
+        calendar::spawn(app_state.clone(), calendar_settings);
+    }
+    let node_settings = app_state.config.read().await.node.clone();
+    if node_settings.enabled {
+        node::spawn(app_state.clone(), node_settings);
+    }
+    let remote_settings = app_state.config.read().await.remote.clone();
+    if remote_settings.enabled {
+        remote::spawn(app_state.clone(), remote_settings);
+    }
+    #[cfg(feature = "homekit")]
+    let homekit_settings = app_state.config.read().await.homekit.clone();
+    #[cfg(feature = "homekit")]
+    if homekit_settings.enabled {
+        homekit::spawn(app_state.clone(), homekit_settings, &bind_addr)
+            .map_err(|e| std::io::Error::other(format!("HomeKit setup failed: {e:#}")))?;
+    }
+    #[cfg(feature = "email")]
+    let email_settings = app_state.config.read().await.email.clone();
+    #[cfg(feature = "email")]
+    if email_settings.enabled {
+        email::spawn(app_state.clone(), email_settings);
+    }
 
-/////////////////////////////////////////////////////////////
-// record_audio_in_memory
-//
-// Switches between "arecord" (Linux) and "rec" (SoX on mac)
-// based on MIC_BACKEND env var. Captures the WAV data to a
-// Vec<u8> in memory. (No changes here.)
-/////////////////////////////////////////////////////////////
-async fn record_audio_in_memory(duration_sec: u32) -> Result<Vec<u8>> {
-    let mic_cmd = get_mic_command(duration_sec)?;
-    println!("   [DEBUG] Using mic command: {:?}", mic_cmd);
-
-    // Spawn the chosen command via tokio::process::Command
-    let mut child = Command::new(&mic_cmd[0])
-        .args(&mic_cmd[1..])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("Failed to spawn mic command")?;
-
-    let mut output = Vec::new();
-
-    // Read child's stdout asynchronously into output
-    if let Some(mut stdout) = child.stdout.take() {
-        stdout.read_to_end(&mut output).await
-            .context("Reading from mic stdout failed")?;
+    let static_dir = app_state.config.read().await.server.static_dir.clone();
+    let body_limit = app_state.config.read().await.server.max_body_kb * 1024;
+    match &static_dir {
+        Some(dir) => tracing::info!(static_dir = %dir, "serving web UI from disk"),
+        None => tracing::info!("serving built-in web UI"),
     }
 
-    // Wait for the process to finish
-    let status = child.wait().await
-        .context("Failed to .wait() on mic process")?;
+    let shutdown_data = app_state.clone();
+
+    // Launch Actix Web
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .wrap(middleware::from_fn(auth::require_login))
+            // Outside the login check, so refused requests are audited
+            .wrap(middleware::from_fn(audit::record_requests))
+            // Outermost, so everything above runs inside the request span
+            .wrap(middleware::from_fn(logging::trace_requests))
+            .configure(|cfg| error::configure(cfg, body_limit))
+            .configure(auth::configure)
+            .configure(status::configure)
+            .configure(openapi::configure)
+            .configure(reload::configure)
+            .configure(sessions::configure)
+            .configure(captions::configure)
+            .configure(discovery::configure)
+            .configure(admin::configure)
+            .configure(webhooks::configure)
+            .configure(discord::configure)
+            .configure(rules::configure)
+            .configure(reminders::configure)
+            .configure(hub::configure)
+            .configure(sync::configure)
+            .configure(audit::configure)
+            .configure(quiet::configure)
+            .configure(optout::configure)
+            .configure(export::configure)
+            .configure(translate::configure)
+            .configure(meetings::configure)
+            .configure(replay::configure)
+            .configure(browse::configure)
+            .configure(session_settings::configure)
+            .configure(feedback::configure)
+            .configure(pairing::configure)
+            .configure(display::configure)
+            .configure(i18n::configure)
+            .configure(dashboard::configure)
+            .configure(lights::configure)
+            .configure(bookmarks::configure)
+            .configure(backlog::configure)
+            .configure(telemetry::configure)
+            .configure(|_cfg| {
+                #[cfg(feature = "graphql")]
+                graphql::configure(_cfg);
+            })
+            .service(get_transcript)
+            .service(start_recording)
+            .service(stop_recording)
+            .service(record_once)
+            .service(conversation_log) // ADDED
+            .service(live_log_sse)     // ADDED SSE route
+            .service(poll_log)
+            .service(entry)
+            // Everything else: the web UI (index.html at "/"). Last,
+            // since it matches every path.
+            .configure(|cfg| assets::configure(cfg, static_dir.as_deref()))
+    });
 
-    if !status.success() {
-        anyhow::bail!("Mic command exited with non-zero status: {:?}", status);
+    let mut server = server;
+    let mut listening = Vec::new();
+    if listen_tcp {
+        let listener = listen::tcp_listener(&bind_addr, port).map_err(|e| std::io::Error::other(format!("{e:#}")))?;
+        let local_addr = listener.local_addr()?;
+        server = match tls_config {
+            Some(config) => {
+                tracing::info!(addr = %local_addr, "serving HTTPS");
+                server.listen_rustls_0_21(listener, config)?
+            }
+            None => {
+                tracing::info!(addr = %local_addr, "serving HTTP");
+                server.listen(listener)?
+            }
+        };
+        listening.push(local_addr.to_string());
+    }
+    #[cfg(unix)]
+    if let Some(path) = &unix_socket {
+        let listener = listen::unix_listener(path).map_err(|e| std::io::Error::other(format!("{e:#}")))?;
+        server = server.listen_uds(listener)?;
+        tracing::info!(path = %path, "serving HTTP on Unix socket");
+        listening.push(path.clone());
     }
 
-    Ok(output)
-}
+    // Everything is bound: tell systemd we're up, then keep the watchdog fed
+    systemd::ready(&format!("Listening on {}", listening.join(" and ")));
+    systemd::spawn_watchdog();
 
-/////////////////////////////////////////////////////////////
-// get_mic_command
-//
-// Returns the appropriate mic command + args for either
-// "mac" (SoX) or "linux" (arecord), based on `MIC_BACKEND`.
-/////////////////////////////////////////////////////////////
-fn get_mic_command(duration_sec: u32) -> Result<Vec<String>> {
-    let backend = env::var("MIC_BACKEND").unwrap_or_else(|_| "linux".to_string());
-
-    if backend == "mac" {
-        let cmd = vec![
-            "rec".to_string(),
-            "-q".to_string(),
-            "-c".to_string(), "1".to_string(),
-            "-r".to_string(), "16000".to_string(),
-            "-b".to_string(), "16".to_string(),
-            "-e".to_string(), "signed-integer".to_string(),
-            "-t".to_string(), "wav".to_string(),
-            "-".to_string(),
-            "trim".to_string(), "0".to_string(), duration_sec.to_string(),
-        ];
-        Ok(cmd)
-    } else {
-        // Linux default: arecord -d <sec> -f cd -t wav -
-        Ok(vec![
-            "arecord".to_string(),
-            "-d".to_string(), duration_sec.to_string(),
-            "-f".to_string(), "cd".to_string(),
-            "-t".to_string(), "wav".to_string(),
-            "-".to_string(),
-        ])
+    let server = server.run();
+
+    // POST /admin/shutdown or /admin/restart
+    let handle = server.handle();
+    let requested = shutdown_data.clone();
+    tokio::spawn(async move {
+        requested.shutdown.requested().await;
+        admin::prepare_shutdown(&requested).await;
+        handle.stop(true).await;
+    });
+
+    let result = server.await;
+    // Recordings finish the chunks they've captured, webhooks stop retrying
+    shutdown_data.tasks.shutdown(tasks::SHUTDOWN_GRACE).await;
+    #[cfg(unix)]
+    if let Some(path) = &unix_socket {
+        listen::remove_unix_socket(path);
+    }
+    systemd::status("Stopped");
+    if result.is_ok() && shutdown_data.shutdown.restart_requested() {
+        tracing::info!(code = systemd::RESTART_EXIT_CODE, "exiting for systemd to restart us");
+        std::process::exit(systemd::RESTART_EXIT_CODE);
     }
+    result
 }
 
 /////////////////////////////////////////////////////////////
-// transcribe_audio_with_whisper
+// conversation_log
 //
-// Sends the captured audio bytes to OpenAI Whisper API
+// Returns the entire 'conversation_log.json' as text (or a
+// workspace's, see workspaces.rs).
+// Responses carry ETag and Last-Modified, so a polling client
+// sending If-None-Match / If-Modified-Since gets a bodyless
+// 304 until something new is logged.
 /////////////////////////////////////////////////////////////
-async fn transcribe_audio_with_whisper(audio_data: &[u8]) -> Result<String> {
-    let api_key = env::var("OPENAI_API_KEY")
-        .context("Must set OPENAI_API_KEY")?;
-    println!("   [DEBUG] Sending {} bytes to Whisper API...", audio_data.len());
-
-    let client = reqwest::Client::new();
-    let form = reqwest::multipart::Form::new()
-        .part("file",
-              reqwest::multipart::Part::bytes(audio_data.to_vec())
-                  .file_name("audio.wav")
-                  .mime_str("audio/wav")?)
-        .text("model", "whisper-1");
-
-    let resp = client
-        .post("https://api.openai.com/v1/audio/transcriptions")
-        .header(AUTHORIZATION, format!("Bearer {}", api_key))
-        .multipart(form)
-        .send()
-        .await
-        .context("Failed to call Whisper API")?;
-
-    if !resp.status().is_success() {
-        let text = resp.text().await.unwrap_or_default();
-        anyhow::bail!("Whisper API error: {}", text);
-    }
-
-    let json_resp: serde_json::Value = resp.json().await
-        .context("Failed to parse Whisper JSON")?;
-    println!("   [DEBUG] Whisper API raw JSON: {:?}", json_resp);
-
-    let transcript = json_resp["text"]
-        .as_str()
-        .unwrap_or("")
-        .to_string();
-
-    Ok(transcript)
+#[utoipa::path(
+    tag = "log",
+    params(
+        workspaces::WorkspaceQuery,
+        ("If-None-Match" = Option<String>, Header, description = "ETag from an earlier response"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified from an earlier response"),
+    ),
+    responses(
+        (status = 200, description = "conversation_log.json, one JSON record per line", content_type = "text/plain", body = String),
+        (status = 304, description = "Unchanged since the given ETag / date"),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No log file yet (code log_not_found), or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 500, description = "Log file couldn't be read (code log_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/conversation_log", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn conversation_log(req: HttpRequest, app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let path = workspaces::log_path(&workspaces::requested(&req, &app_data).await?);
+
+    // NamedFile does the ETag / Last-Modified / 304 handling
+    let file = match NamedFile::open_async(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ApiError::not_found("log_not_found", "Nothing has been logged yet"));
+        }
+        Err(e) => {
+            return Err(ApiError::internal("log_unreadable", format!("Failed to read {path}")).with_detail(e));
+        }
+    };
+
+    let mut response = file
+        .set_content_type(ContentType::plaintext().0)
+        .disable_content_disposition()
+        .into_response(&req);
+    // Always revalidate; the file changes with every chunk
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    Ok(response)
 }
 
 /////////////////////////////////////////////////////////////
-// summarize_with_gpt
+// live_log_sse
 //
-// We now build a "chat" array with:
-// - system message
-// - up to 20 user/assistant messages from conversation_history
-// - the new user chunk
+// SSE endpoint that streams appended lines from every source
+// in real-time (see events.rs for IDs, replay and keepalives).
+// /sources/{name}/live_log streams a single source.
 //
-// Then call GPT with "gpt-4o" per your code.
+// ?source=OPENAI%20RESPONSE&session=current narrows the stream,
+// e.g. for a wall display that only shows GPT's responses.
 /////////////////////////////////////////////////////////////
-async fn summarize_with_gpt(
-    app_data: &web::Data<AppState>,
-    latest_chunk: &str
-) -> Result<String> {
-    let api_key = env::var("OPENAI_API_KEY")
-        .context("Must set OPENAI_API_KEY")?;
-    println!("   [DEBUG] Sending transcript to GPT: {}", latest_chunk);
-
-    let system_prompt = "You are listening in on a conversation. You will display your response on a monitor mounted on the wall, so the goal should be 50 words or less so they are not too small. If there is something said that you could provide some interesting information about, return a response. If there is nothing interesting to share, just return Listening...";
-
-    // Gather last 20 messages
-    let mut history = app_data.conversation_history.lock().await.clone();
-
-    // We'll build a messages array for ChatCompletion
-    let mut messages = Vec::new();
-    // Start with system
-    messages.push(serde_json::json!({
-        "role": "system",
-        "content": system_prompt
-    }));
-
-    // Add up to last 20 from conversation_history
-    // Each item is ("user"|"assistant", content)
-    // We’ll skip if empty. We'll do the last 20 items or fewer.
-    let start_idx = if history.len() > 40 { history.len() - 40 } else { 0 };
-    for (role, content) in &history[start_idx..] {
-        let r = if role == "assistant" { "assistant" } else { "user" };
-        messages.push(serde_json::json!({
-            "role": r,
-            "content": content
-        }));
-    }
-
-    // Finally add the new chunk as a user message
-    messages.push(serde_json::json!({
-        "role": "user",
-        "content": latest_chunk
-    }));
-
-    // Build request body
-    let req_body = serde_json::json!({
-        "model": "gpt-4o", // same as your code
-        "messages": messages,
-        "max_tokens": 100,
-        "temperature": 0.7
-    });
-
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header(AUTHORIZATION, format!("Bearer {}", api_key))
-        .header(CONTENT_TYPE, "application/json")
-        .json(&req_body)
-        .send()
-        .await
-        .context("Failed to call ChatCompletion API")?;
-
-    if !resp.status().is_success() {
-        let text = resp.text().await.unwrap_or_default();
-        anyhow::bail!("ChatCompletion error: {}", text);
-    }
-
-    let json_resp: serde_json::Value = resp.json().await
-        .context("Failed to parse GPT JSON")?;
-    println!("   [DEBUG] GPT response raw JSON: {:?}", json_resp);
-
-    let content = json_resp["choices"][0]["message"]["content"]
-        .as_str()
-        .unwrap_or("")
-        .trim()
-        .to_string();
-
-    Ok(content)
+#[utoipa::path(
+    tag = "log",
+    params(
+        events::LogFilter,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Replay records newer than this event ID"),
+    ),
+    responses(
+        (status = 200, description = "SSE stream of log records", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid query string (code invalid_query)", body = ErrorBody),
+    ),
+)]
+#[get("/live_log")]
+async fn live_log_sse(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    filter: web::Query<events::LogFilter>,
+) -> HttpResponse {
+    let current = app_data.sources.current_session_ids(&app_data).await;
+    app_data.events.sse_response(&req, filter.into_inner(), current).await
 }
 
 /////////////////////////////////////////////////////////////
-// append_to_json_log
+// poll_log
 //
-// Called after we get the new user chunk + GPT response
-// Also broadcasts over SSE
+// Long-polling version of /live_log for clients (e.g. ESP32
+// displays) that can't keep an SSE connection up. Returns the
+// records after `since` right away if there are any, otherwise
+// waits up to `timeout` seconds for the next one. Pass the
+// returned last_id as `since` on the next call. Takes the same
+// source/session filters as /live_log.
 /////////////////////////////////////////////////////////////
-fn append_to_json_log(
-    source: &str,
-    text: &str,
-    app_data: &web::Data<AppState>,
-) -> Result<()> {
-    let timestamp = Utc::now().to_rfc3339();
-    let record = serde_json::json!({
-        "timestamp": timestamp,
-        "source": source,
-        "text": text
-    });
-
-    let record_string = serde_json::to_string(&record)
-        .context("Failed to serialize JSON record")?;
-
-    // Append each JSON entry on its own line for simplicity
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("conversation_log.json")
-        .context("Failed to open or create conversation_log.json")?;
-
-    use std::io::Write;
-    writeln!(file, "{}", record_string)
-        .context("Failed to write JSON record")?;
-
-    println!("   [DEBUG] Appended record to conversation_log.json: {}", record_string);
+const POLL_TIMEOUT_SECS: u64 = 30;
+const POLL_TIMEOUT_MAX_SECS: u64 = 60;
+
+#[derive(Deserialize, IntoParams)]
+struct PollQuery {
+    // Last event ID already seen; omit to just wait for the next record
+    since: Option<u64>,
+    // Seconds to wait for a record, 1-60 (default 30)
+    timeout: Option<u64>,
+}
 
-    // Also broadcast over SSE for real-time display
-    let _ = app_data.log_sender.send(record_string.clone());
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PollResponse {
+    // Send as ?since= on the next poll
+    last_id: u64,
+    // Empty if the timeout passed with nothing new
+    records: Vec<PolledRecord>,
+}
 
-    Ok(())
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PolledRecord {
+    id: u64,
+    // The conversation_log.json record; with ?schema=2, the
+    // record as an events::Event
+    #[schema(value_type = Object)]
+    record: serde_json::Value,
 }
 
-/////////////////////////////////////////////////////////////
-// conversation_log
-//
-// Returns the entire 'conversation_log.json' as text
-/////////////////////////////////////////////////////////////
-#[get("/conversation_log")]
-async fn conversation_log() -> impl Responder {
-    let path = "conversation_log.json";
-
-    match std::fs::read_to_string(path) {
-        Ok(contents) => {
-            HttpResponse::Ok()
-                .content_type("text/plain; charset=utf-8")
-                .body(contents)
-        }
-        Err(e) => {
-            HttpResponse::NotFound()
-                .body(format!("Failed to read {path}: {e}"))
-        }
+#[utoipa::path(
+    tag = "log",
+    params(PollQuery, events::LogFilter),
+    responses(
+        (status = 200, description = "New records, or none after the timeout", body = PollResponse),
+        (status = 400, description = "Invalid timeout (code invalid_timeout / invalid_query)", body = ErrorBody),
+    ),
+)]
+#[get("/poll_log")]
+async fn poll_log(
+    app_data: web::Data<AppState>,
+    query: web::Query<PollQuery>,
+    filter: web::Query<events::LogFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let timeout = query.timeout.unwrap_or(POLL_TIMEOUT_SECS);
+    if !(1..=POLL_TIMEOUT_MAX_SECS).contains(&timeout) {
+        return Err(ApiError::bad_request(
+            "invalid_timeout",
+            format!("timeout must be between 1 and {POLL_TIMEOUT_MAX_SECS} seconds, got {timeout}"),
+        ));
     }
+
+    let current = app_data.sources.current_session_ids(&app_data).await;
+    let events = app_data
+        .events
+        .poll(query.since, &filter, &current, Duration::from_secs(timeout))
+        .await;
+
+    let last_id = match events.last() {
+        Some(ev) => ev.id,
+        // Clamped, so a client still holding IDs from before a restart resyncs
+        None => {
+            let latest = app_data.events.last_id();
+            query.since.map_or(latest, |since| since.min(latest))
+        }
+    };
+    let records = events
+        .into_iter()
+        .map(|ev| {
+            let data = if filter.events() { ev.event } else { ev.data };
+            PolledRecord { id: ev.id, record: serde_json::from_str(&data).unwrap_or(serde_json::Value::String(data)) }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(PollResponse { last_id, records }))
 }
 
 /////////////////////////////////////////////////////////////
-// live_log_sse
+// entry
 //
-// SSE endpoint that streams appended lines in real-time
+// One conversation_log.json record by its entry_id. A
+// transcript's has Whisper's "segments", "language" and
+// "confidence" when Whisper sent them (see pipeline::Segment); a
+// rated response's has its "feedback" (see feedback.rs).
 /////////////////////////////////////////////////////////////
-#[get("/live_log")]
-async fn live_log_sse(app_data: web::Data<AppState>) -> HttpResponse {
-    let rx = app_data.log_sender.subscribe();
-
-    let sse_stream = BroadcastStream::new(rx).map(|res| {
-        match res {
-            Ok(line) => {
-                let msg = format!("data: {}\n\n", line);
-                Ok::<Bytes, std::io::Error>(Bytes::from(msg))
-            }
-            Err(_) => {
-                Ok::<Bytes, std::io::Error>(Bytes::from("data:\n\n"))
-            }
+#[utoipa::path(
+    tag = "log",
+    params(("id" = String, Path, description = "The record's entry_id"), workspaces::WorkspaceQuery),
+    responses(
+        (status = 200, description = "The record, as logged", body = Object),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "No record has that entry_id in the workspace (code unknown_entry), or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 500, description = "Log file couldn't be read (code log_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/entries/{id}")]
+async fn entry(req: HttpRequest, app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let records = digest::read_log(&workspaces::requested(&req, &app_data).await?)
+        .await
+        .map_err(|e| ApiError::internal("log_unreadable", format!("Failed to read {CONVERSATION_LOG}")).with_detail(format!("{e:#}")))?;
+    match records.into_iter().find(|r| r["entry_id"] == id.as_str()) {
+        Some(mut record) => {
+            feedback::attach(&mut record);
+            Ok(HttpResponse::Ok().json(record))
         }
-    });
-
-    HttpResponse::Ok()
-        .content_type("text/event-stream")
-        .streaming(sse_stream)
+        None => Err(ApiError::not_found("unknown_entry", format!("No entry {id}"))),
+    }
 }
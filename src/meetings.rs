@@ -0,0 +1,359 @@
+/////////////////////////////////////////////////////////////
+// src/meetings.rs
+//
+// Meetings: sessions with a title and attendees, and minutes
+// when they end.
+//   POST /meetings                 - starts recording a meeting on
+//                                    a source ({"title": ...,
+//                                    "attendees": [...]})
+//   GET  /meetings/{id}/minutes    - the minutes of a meeting
+//   POST /meetings/{id}/minutes    - writes them again
+// A meeting is a session with SessionDetails, like a calendar
+// meeting (see calendar.rs): its records carry session_title
+// and attendees. Its attendees also bound its speaker labels
+// (see speakers.rs): there are never more voices than people.
+//
+// When any titled session stops, GPT writes its minutes from the
+// transcript: a summary, the decisions made and the action
+// items, each with its owner when the transcript names one of
+// the attendees. They're kept in minutes/<session>.json, sent
+// as a "meeting.minutes" webhook and added to the session's
+// export (see export.rs), which waits for them.
+//
+// Minutes go to GPT routed like summaries (see routing.rs); only
+// the newest MAX_TRANSCRIPT_CHARS of a long meeting are read.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, middleware, post, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::DEFAULT_SOURCE;
+use crate::error::ApiError;
+use crate::routing::Category;
+use crate::sessions::{self, SessionDetails};
+use crate::{digest, export, pipeline, rate_limit, timezone, translate, webhooks, AppState};
+
+const MINUTES_DIR: &str = "minutes";
+const MAX_TRANSCRIPT_CHARS: usize = 24_000;
+const MINUTES_MAX_TOKENS: u32 = 1500;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_ATTENDEES: usize = 50;
+const MINUTES_PROMPT: &str = "You take the minutes of a meeting. You are given its title, its attendees and its transcript, in order; lines may start with who spoke as \"Speaker A\", \"Speaker B\", ..., which are not the attendees' names. Reply with only a JSON object: {\"summary\": \"under 150 words\", \"decisions\": [\"each decision made\"], \"action_items\": [{\"task\": \"what is to be done\", \"owner\": \"the attendee who took it on, as listed, or null\"}]}. Leave out anything that wasn't said.";
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct Minutes {
+    pub session_id: String,
+    pub title: String,
+    pub attendees: Vec<String>,
+    pub generated_at: String,
+    pub summary: String,
+    pub decisions: Vec<String>,
+    pub action_items: Vec<ActionItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub(crate) struct ActionItem {
+    pub task: String,
+    // One of the attendees, if someone took it on
+    pub owner: Option<String>,
+}
+
+// GPT's part of the minutes
+#[derive(Deserialize)]
+struct Written {
+    summary: String,
+    #[serde(default)]
+    decisions: Vec<String>,
+    #[serde(default)]
+    action_items: Vec<ActionItem>,
+}
+
+fn path(session_id: &str) -> PathBuf {
+    Path::new(MINUTES_DIR).join(format!("{}.json", translate::file_stem(session_id)))
+}
+
+/////////////////////////////////////////////////////////////
+// load
+//
+// The meeting's minutes, if they were written.
+/////////////////////////////////////////////////////////////
+pub async fn load(session_id: &str) -> Result<Option<Minutes>> {
+    let path = path(session_id);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    serde_json::from_str(&contents).with_context(|| format!("{} is corrupt", path.display())).map(Some)
+}
+
+/////////////////////////////////////////////////////////////
+// write
+//
+// Has GPT write a session's minutes and keeps them.
+// UnknownSession if it has no records. The title and
+// attendees come from its records when not given.
+/////////////////////////////////////////////////////////////
+enum MinutesError {
+    UnknownSession,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MinutesError {
+    fn from(e: anyhow::Error) -> Self {
+        MinutesError::Failed(e)
+    }
+}
+
+async fn write(app_data: &AppState, session_id: &str, details: Option<&SessionDetails>) -> Result<Minutes, MinutesError> {
+    let records = digest::session_records(session_id).await?;
+    if records.is_empty() {
+        return Err(MinutesError::UnknownSession);
+    }
+    let title = details
+        .and_then(|d| d.title.clone())
+        .or_else(|| records.iter().find_map(|r| r["session_title"].as_str()).map(str::to_string))
+        .unwrap_or_else(|| format!("Session {session_id}"));
+    let attendees: Vec<String> = match details {
+        Some(details) => details.attendees.clone(),
+        None => records
+            .iter()
+            .find_map(|r| r["attendees"].as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+            .unwrap_or_default(),
+    };
+
+    let mut lines: Vec<String> = Vec::new();
+    for record in records.iter().filter(|r| r["source"] == "Microphone") {
+        let text = record["text"].as_str().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+        let at = record["timestamp"]
+            .as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|at| timezone::local(at.with_timezone(&Utc)).format("[%H:%M:%S] ").to_string())
+            .unwrap_or_default();
+        let speakers: Vec<&str> =
+            record["speakers"].as_array().into_iter().flatten().filter_map(|s| s.as_str()).collect();
+        let speakers = if speakers.is_empty() { String::new() } else { format!("{}: ", speakers.join(", ")) };
+        lines.push(format!("{at}{speakers}{text}"));
+    }
+    // The newest lines that fit
+    let mut budget = MAX_TRANSCRIPT_CHARS;
+    let mut kept = Vec::new();
+    for line in lines.iter().rev() {
+        let len = line.chars().count() + 1;
+        if len > budget {
+            break;
+        }
+        budget -= len;
+        kept.push(line.as_str());
+    }
+    kept.reverse();
+
+    let written = if kept.is_empty() {
+        Written { summary: "Nothing was transcribed.".to_string(), decisions: Vec::new(), action_items: Vec::new() }
+    } else {
+        let content = format!(
+            "Title: {title}\nAttendees: {}\n\nTranscript:\n{}",
+            if attendees.is_empty() { "not known".to_string() } else { attendees.join("; ") },
+            kept.join("\n")
+        );
+        let messages = vec![
+            json!({ "role": "system", "content": MINUTES_PROMPT }),
+            json!({ "role": "user", "content": content }),
+        ];
+        let openai = app_data.config.read().await.openai.clone();
+        let reply = pipeline::chat_completion(app_data, &openai, Category::Summaries, messages, MINUTES_MAX_TOKENS)
+            .await
+            .context("GPT couldn't write the minutes")?;
+        // Sometimes in a Markdown code block
+        let reply = reply.trim().trim_start_matches("```json").trim_matches('`').trim();
+        let mut written: Written = serde_json::from_str(reply).context("GPT's minutes weren't the JSON asked for")?;
+        // An owner who isn't an attendee is GPT's guess
+        for item in &mut written.action_items {
+            if item.owner.as_ref().is_some_and(|owner| !attendees.contains(owner)) {
+                item.owner = None;
+            }
+        }
+        written
+    };
+
+    let minutes = Minutes {
+        session_id: session_id.to_string(),
+        title,
+        attendees,
+        generated_at: Utc::now().to_rfc3339(),
+        summary: written.summary,
+        decisions: written.decisions,
+        action_items: written.action_items,
+    };
+    let path = path(session_id);
+    tokio::fs::create_dir_all(MINUTES_DIR)
+        .await
+        .with_context(|| format!("Failed to create {MINUTES_DIR}"))?;
+    let contents = serde_json::to_string_pretty(&minutes).context("Failed to serialize the minutes")?;
+    tokio::fs::write(&path, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    tracing::info!(
+        session_id,
+        decisions = minutes.decisions.len(),
+        action_items = minutes.action_items.len(),
+        file = %path.display(),
+        "wrote the meeting's minutes"
+    );
+    Ok(minutes)
+}
+
+/////////////////////////////////////////////////////////////
+// session_stopped
+//
+// Writes the minutes of a titled session that just ended, in
+// the background, then hands it to export::session_stopped so
+// the note has them. Other sessions go straight to export.
+/////////////////////////////////////////////////////////////
+pub async fn session_stopped(app_data: &web::Data<AppState>, session_id: &str, details: SessionDetails) {
+    if details.title.is_none() {
+        export::session_stopped(app_data, session_id).await;
+        return;
+    }
+    let span = tracing::info_span!(parent: None, "minutes", session_id = %session_id);
+    let (data, session_id) = (app_data.clone(), session_id.to_string());
+    let run = async move {
+        match write(&data, &session_id, Some(&details)).await {
+            Ok(minutes) => webhooks::send(&data, "meeting.minutes", json!(minutes)).await,
+            Err(MinutesError::UnknownSession) => tracing::debug!("nothing logged, no minutes"),
+            Err(MinutesError::Failed(e)) => tracing::warn!(error = %format!("{e:#}"), "the minutes failed"),
+        }
+        export::session_stopped(&data, &session_id).await;
+    };
+    app_data.tasks.spawn("minutes", run.instrument(span));
+}
+
+/////////////////////////////////////////////////////////////
+// POST /meetings
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct MeetingRequest {
+    title: String,
+    // Who's in the meeting, e.g. "Ada Lovelace"; owners of action
+    // items are named from these
+    #[serde(default)]
+    attendees: Vec<String>,
+    // The audio source to record; default "default"
+    source: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct MeetingStarted {
+    session_id: String,
+    audio_source: String,
+    title: String,
+    attendees: Vec<String>,
+}
+
+#[utoipa::path(
+    tag = "recording",
+    request_body = MeetingRequest,
+    responses(
+        (status = 200, description = "Recording the meeting", body = MeetingStarted),
+        (status = 400, description = "No title, or too long, or too many attendees (code invalid_meeting)", body = ErrorBody),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+        (status = 409, description = "Already recording (code already_recording)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 503, description = "No OpenAI API key configured (code openai_not_configured)", body = ErrorBody),
+    ),
+)]
+#[post("/meetings", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn start_meeting(
+    app_data: web::Data<AppState>,
+    body: web::Json<MeetingRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let title = body.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return Err(ApiError::bad_request(
+            "invalid_meeting",
+            format!("title must be 1 to {MAX_TITLE_CHARS} characters"),
+        ));
+    }
+    let mut attendees: Vec<String> = Vec::new();
+    for attendee in body.attendees.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        if !attendees.iter().any(|a| a == attendee) {
+            attendees.push(attendee.to_string());
+        }
+    }
+    if attendees.len() > MAX_ATTENDEES {
+        return Err(ApiError::bad_request("invalid_meeting", format!("at most {MAX_ATTENDEES} attendees")));
+    }
+    let name = body.source.unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+    let Some(source) = app_data.sources.get(&app_data, &name).await else {
+        return Err(sessions::unknown_source(&name));
+    };
+
+    let details = SessionDetails { title: Some(title.clone()), attendees: attendees.clone(), ..SessionDetails::default() };
+    sessions::start_session(&app_data, source.clone(), details).await?;
+    let session_id = source.session_id.borrow().clone().unwrap_or_default();
+    tracing::info!(source = %name, session_id = %session_id, attendees = attendees.len(), "meeting started");
+    Ok(HttpResponse::Ok().json(MeetingStarted { session_id, audio_source: name, title, attendees }))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /meetings/{id}/minutes
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    params(("id" = String, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "The meeting's minutes", body = Minutes),
+        (status = 404, description = "No minutes for that session (code unknown_minutes)", body = ErrorBody),
+        (status = 500, description = "The minutes can't be read (code minutes_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/meetings/{id}/minutes")]
+async fn get_minutes(id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match load(&id).await {
+        Ok(Some(minutes)) => Ok(HttpResponse::Ok().json(minutes)),
+        Ok(None) => Err(ApiError::not_found("unknown_minutes", format!("No minutes for session {id}"))),
+        Err(e) => Err(ApiError::internal("minutes_unreadable", "Couldn't read the minutes").with_detail(format!("{e:#}"))),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// POST /meetings/{id}/minutes
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    params(("id" = String, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "The minutes, as kept", body = Minutes),
+        (status = 404, description = "Nothing logged for that session (code unknown_session)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 502, description = "GPT couldn't write them (code minutes_failed)", body = ErrorBody),
+    ),
+)]
+#[post("/meetings/{id}/minutes", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn write_minutes(app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match write(&app_data, &id, None).await {
+        Ok(minutes) => Ok(HttpResponse::Ok().json(minutes)),
+        Err(MinutesError::UnknownSession) => {
+            Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")))
+        }
+        Err(MinutesError::Failed(e)) => {
+            tracing::warn!(session_id = %id, error = %format!("{e:#}"), "the minutes failed");
+            Err(ApiError::bad_gateway("minutes_failed", "The minutes failed").with_detail(format!("{e:#}")))
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(start_meeting).service(get_minutes).service(write_minutes);
+}
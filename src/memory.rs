@@ -0,0 +1,139 @@
+/////////////////////////////////////////////////////////////
+// src/memory.rs
+//
+// How much of the conversation GPT is reminded of. Each
+// source's conversation_history keeps the latest turns, up to
+// openai.history_tokens (roughly: 4 characters to a token) and
+// openai.history_messages. Past either limit, the oldest turns
+// (down to three quarters of it) are folded into the source's
+// rolling summary by GPT rather than dropped, and the summary
+// goes to GPT ahead of the history, so a long session fades
+// instead of forgetting all at once. Summaries are kept in history_summaries.json, one per
+// source, so they survive a restart. "Forget the last ..."
+// clears a summary whose turns it reaches (see forget.rs).
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use std::sync::Mutex;
+
+use crate::routing::Category;
+use crate::sessions::SourceSession;
+use crate::{pipeline, AppState};
+
+const SUMMARIES_FILE: &str = "history_summaries.json";
+const SUMMARY_MAX_TOKENS: u32 = 300;
+const SUMMARY_PROMPT: &str = "You keep the memory of a conversation you are listening in on. You are given what you remembered so far, if anything, and the turns that followed it. Write the new memory: who is talking, what about, and anything decided or still open, oldest first. Keep it under 150 words.";
+// Per message, on top of its text
+const MESSAGE_TOKENS: usize = 4;
+
+// Writes to SUMMARIES_FILE, from any source
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+// A rough count of the tokens `text` takes as a chat message
+pub fn tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4) + MESSAGE_TOKENS
+}
+
+// The summary `source` had when the server last stopped
+pub fn load(source: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(SUMMARIES_FILE).ok()?;
+    match serde_json::from_str::<Map<String, Value>>(&contents) {
+        Ok(summaries) => summaries.get(source)?.as_str().map(str::to_string),
+        Err(e) => {
+            tracing::warn!(error = %e, "{SUMMARIES_FILE} is unreadable; starting without summaries");
+            None
+        }
+    }
+}
+
+fn save(source: &str, summary: Option<&str>) -> Result<()> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let mut summaries: Map<String, Value> = std::fs::read_to_string(SUMMARIES_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    match summary {
+        Some(summary) => summaries.insert(source.to_string(), summary.into()),
+        None => summaries.remove(source),
+    };
+    let contents = serde_json::to_string_pretty(&summaries)?;
+    let partial = format!("{SUMMARIES_FILE}.partial");
+    std::fs::write(&partial, contents).with_context(|| format!("Failed to write {partial}"))?;
+    std::fs::rename(&partial, SUMMARIES_FILE).with_context(|| format!("Failed to replace {SUMMARIES_FILE}"))
+}
+
+/////////////////////////////////////////////////////////////
+// remember
+//
+// Adds an exchange to `source`'s history and folds whatever no
+// longer fits into its summary.
+/////////////////////////////////////////////////////////////
+pub async fn remember(app_data: &AppState, source: &SourceSession, transcript: &str, response: &str) {
+    let (max_messages, max_tokens) = {
+        let config = app_data.config.read().await;
+        (config.openai.history_messages, config.openai.history_tokens)
+    };
+    let folded: Vec<(String, String)> = {
+        let mut history = source.conversation_history.lock().await;
+        history.push(("user".to_string(), transcript.to_string()));
+        history.push(("assistant".to_string(), response.to_string()));
+        let mut total: usize = history.iter().map(|(_, text)| tokens(text)).sum();
+        let mut end = 0;
+        if history.len() > max_messages || (max_tokens > 0 && total > max_tokens) {
+            // Down to three quarters, so it's not another fold per exchange
+            let (keep_messages, keep_tokens) = (max_messages * 3 / 4, max_tokens * 3 / 4);
+            while end < history.len() && (history.len() - end > keep_messages || (max_tokens > 0 && total > keep_tokens)) {
+                total -= tokens(&history[end].1);
+                end += 1;
+            }
+        }
+        history.drain(..end).collect()
+    };
+    if !folded.is_empty() {
+        fold(app_data, source, &folded).await;
+    }
+}
+
+// Rewrites the summary to take in `turns`. Without GPT they're
+// lost, as before summaries
+async fn fold(app_data: &AppState, source: &SourceSession, turns: &[(String, String)]) {
+    // Held throughout, so folds happen one at a time and in order
+    let mut summary = source.history_summary.lock().await;
+    let mut content = match summary.as_deref() {
+        Some(summary) => format!("What you remembered:\n{summary}\n\nWhat followed:\n"),
+        None => "What was said:\n".to_string(),
+    };
+    for (role, text) in turns {
+        let speaker = if role == "assistant" { "You" } else { "They" };
+        content.push_str(&format!("{speaker}: {text}\n"));
+    }
+    let messages = vec![
+        json!({ "role": "system", "content": SUMMARY_PROMPT }),
+        json!({ "role": "user", "content": content }),
+    ];
+
+    let openai = app_data.config.read().await.openai.clone();
+    match pipeline::chat_completion(app_data, &openai, Category::Summaries, messages, SUMMARY_MAX_TOKENS).await {
+        Ok(folded) => {
+            tracing::debug!(source = %source.name, turns = turns.len(), summary = %folded, "folded old turns into the summary");
+            if let Err(e) = save(&source.name, Some(&folded)) {
+                tracing::warn!(error = %format!("{e:#}"), "couldn't save the conversation summary");
+            }
+            *summary = Some(folded);
+        }
+        Err(e) => {
+            tracing::warn!(source = %source.name, turns = turns.len(), error = %e, "GPT couldn't summarize the oldest turns; they're dropped");
+        }
+    }
+}
+
+// Forgets `source`'s summary, here and on disk
+pub async fn clear(source: &SourceSession) {
+    let mut summary = source.history_summary.lock().await;
+    if summary.take().is_some() {
+        if let Err(e) = save(&source.name, None) {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't remove the conversation summary");
+        }
+    }
+}
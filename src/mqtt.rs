@@ -0,0 +1,421 @@
+/////////////////////////////////////////////////////////////
+// src/mqtt.rs
+//
+// Home Assistant integration (cargo feature "mqtt"). Through MQTT
+// discovery every audio source shows up on one "SilentNight"
+// device as:
+//   switch "<source> recording"      - on starts it, off stops it
+//   sensor "<source> state"          - idle, recording, error, ...
+//                                      (reason/attempt as attributes)
+//   sensor "<source> last response"  - GPT's latest response, with
+//                                      the transcript as an attribute
+// so automations like "stop recording when we say goodnight" need
+// no glue.
+//
+// Topics, all retained (base = mqtt.base_topic):
+//   <base>/status                  online/offline (last will)
+//   <base>/<source>/recording      ON/OFF
+//   <base>/<source>/recording/set  ON/OFF commands from Home Assistant
+//   <base>/<source>/state          the state, as on GET /sources
+//   <base>/<source>/latest         {"transcript", "response"}
+// and the discovery configs under
+//   <discovery_prefix>/<switch|sensor>/<base>/<source>_<entity>/config
+// Everything is published again when we (re)connect and when Home
+// Assistant comes back online; sources a reload removes have their
+// entities deleted.
+//
+// Settings ([mqtt] or env), restart to change:
+//   enabled  (MQTT_ENABLED)  default false
+//   host     (MQTT_HOST)     default localhost
+//   port     (MQTT_PORT)     default 1883
+//   username (MQTT_USERNAME), password (MQTT_PASSWORD)
+//   discovery_prefix         default homeassistant
+//   base_topic               default silentnight; give each
+//                            instance on a broker its own
+// Plain TCP; the broker is expected on the LAN. Rules can
+// publish through the same connection (see Mqtt, rules.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use futures_util::future::{self, BoxFuture, FutureExt};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+use crate::config::MqttSettings;
+use crate::lifecycle::RecordingState;
+use crate::{audit, sessions, AppState, TranscriptResponse};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Messages held for the broker while it's unreachable
+const QUEUE: usize = 64;
+// How often to look for sources a reload added or removed
+const RESCAN: Duration = Duration::from_secs(30);
+// Longest Home Assistant keeps as a sensor's state
+const MAX_STATE_LEN: usize = 255;
+
+// (component, entity) of every source's entities
+const ENTITIES: [(&str, &str); 3] = [("switch", "recording"), ("sensor", "state"), ("sensor", "last_response")];
+
+/////////////////////////////////////////////////////////////
+// Topics
+/////////////////////////////////////////////////////////////
+struct Topics {
+    discovery_prefix: String,
+    base: String,
+    // base_topic as a discovery node/device ID
+    node_id: String,
+}
+
+impl Topics {
+    fn new(settings: &MqttSettings) -> Topics {
+        let node_id = settings
+            .base_topic
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        Topics {
+            discovery_prefix: settings.discovery_prefix.clone(),
+            base: settings.base_topic.clone(),
+            node_id,
+        }
+    }
+
+    fn availability(&self) -> String {
+        format!("{}/status", self.base)
+    }
+
+    fn source(&self, source: &str, leaf: &str) -> String {
+        format!("{}/{source}/{leaf}", self.base)
+    }
+
+    // Every source's recording/set
+    fn commands(&self) -> String {
+        format!("{}/+/recording/set", self.base)
+    }
+
+    // The source a recording/set topic is for
+    fn command_source<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic
+            .strip_prefix(self.base.as_str())?
+            .strip_prefix('/')?
+            .strip_suffix("/recording/set")
+            .filter(|source| !source.contains('/'))
+    }
+
+    // Home Assistant's birth and last will
+    fn home_assistant(&self) -> String {
+        format!("{}/status", self.discovery_prefix)
+    }
+
+    fn discovery(&self, component: &str, source: &str, entity: &str) -> String {
+        format!("{}/{component}/{}/{source}_{entity}/config", self.discovery_prefix, self.node_id)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Mqtt
+//
+// The broker connection, for publishing from elsewhere; unset
+// until spawn.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Mqtt {
+    client: OnceLock<AsyncClient>,
+}
+
+impl Mqtt {
+    // Queued for the broker; Err if MQTT is off or the queue is full
+    pub fn publish(&self, topic: &str, payload: String, retain: bool) -> anyhow::Result<()> {
+        let Some(client) = self.client.get() else {
+            anyhow::bail!("MQTT isn't enabled (mqtt.enabled)");
+        };
+        client.try_publish(topic, QoS::AtLeastOnce, retain, payload)?;
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Starts the broker connection, which also carries out the
+// switch commands, and the task that publishes each source's
+// entities and keeps them up to date.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: &MqttSettings) {
+    let topics = Arc::new(Topics::new(settings));
+    let mut options = MqttOptions::new(topics.node_id.clone(), &settings.host, settings.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(topics.availability(), "offline", QoS::AtLeastOnce, true));
+    if !settings.username.is_empty() {
+        options.set_credentials(&settings.username, &settings.password);
+    }
+    let (client, eventloop) = AsyncClient::new(options, QUEUE);
+    let _ = app_data.mqtt.client.set(client.clone());
+    tracing::info!(broker = %format!("{}:{}", settings.host, settings.port), base_topic = %topics.base, "publishing Home Assistant entities over MQTT");
+
+    let republish = Arc::new(Notify::new());
+    let connection = connection(app_data.clone(), client.clone(), eventloop, topics.clone(), republish.clone());
+    app_data.tasks.spawn("mqtt", connection);
+    app_data.tasks.spawn("mqtt", publisher(app_data.clone(), client, topics, republish));
+}
+
+// Polling the event loop is what (re)connects, so it runs until
+// shutdown whatever the broker does
+async fn connection(
+    app_data: web::Data<AppState>,
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    topics: Arc<Topics>,
+    republish: Arc<Notify>,
+) {
+    let shutdown = app_data.tasks.token();
+    let mut failing = false;
+    loop {
+        let event = tokio::select! {
+            event = eventloop.poll() => event,
+            _ = shutdown.cancelled() => break,
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!("connected to the MQTT broker");
+                failing = false;
+                for topic in [topics.commands(), topics.home_assistant()] {
+                    if let Err(e) = client.try_subscribe(topic, QoS::AtLeastOnce) {
+                        tracing::warn!(error = %e, "MQTT subscribe failed");
+                    }
+                }
+                publish(&client, topics.availability(), "online");
+                republish.notify_one();
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                let payload = String::from_utf8_lossy(&message.payload);
+                if message.topic == topics.home_assistant() {
+                    // Home Assistant restarted and lost what it knew
+                    if payload == "online" {
+                        republish.notify_one();
+                    }
+                } else if let Some(source) = topics.command_source(&message.topic) {
+                    command(&app_data, source, &payload).await;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // Once per outage; rumqttc retries on the next poll
+                if !failing {
+                    tracing::warn!(error = %e, "MQTT broker unreachable, retrying every {}s", RECONNECT_DELAY.as_secs());
+                }
+                failing = true;
+                tokio::select! {
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        }
+    }
+
+    // The last will only covers going away without saying so
+    if !failing {
+        publish(&client, topics.availability(), "offline");
+        let _ = client.try_disconnect();
+        let flushed = async {
+            while let Ok(event) = eventloop.poll().await {
+                if matches!(event, Event::Outgoing(Outgoing::Disconnect)) {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(Duration::from_secs(1), flushed).await;
+    }
+}
+
+// "ON"/"OFF" from a recording switch
+async fn command(app_data: &web::Data<AppState>, name: &str, payload: &str) {
+    let Some(source) = app_data.sources.get(app_data, name).await else {
+        tracing::warn!(source = name, "MQTT command for an unknown audio source");
+        return;
+    };
+    match payload {
+        "ON" => {
+            tracing::info!(source = name, "start requested over MQTT");
+            let started = sessions::start_source(app_data, source).await;
+            audit::record(app_data, "mqtt", "mqtt", &format!("start {name}"), started.is_ok()).await;
+            if let Err(e) = started {
+                tracing::warn!(source = name, error = %e, "couldn't start recording");
+            }
+        }
+        "OFF" => {
+            tracing::info!(source = name, "stop requested over MQTT");
+            sessions::stop_source(app_data, &source).await;
+            audit::record(app_data, "mqtt", "mqtt", &format!("stop {name}"), true).await;
+        }
+        other => tracing::warn!(source = name, payload = other, "ignoring MQTT command, expected ON or OFF"),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// publisher
+//
+// Waits on every source's state and latest transcript watch
+// channels and publishes what changed.
+/////////////////////////////////////////////////////////////
+struct Watched {
+    state: watch::Receiver<RecordingState>,
+    latest: watch::Receiver<TranscriptResponse>,
+}
+
+enum Wake {
+    // The source whose state (true) or latest transcript (false) changed
+    Changed(String, bool),
+    Rescan,
+    Republish,
+}
+
+async fn publisher(app_data: web::Data<AppState>, client: AsyncClient, topics: Arc<Topics>, republish: Arc<Notify>) {
+    let shutdown = app_data.tasks.token();
+    let mut watched: HashMap<String, Watched> = HashMap::new();
+    let mut rescan = tokio::time::interval(RESCAN);
+    loop {
+        // One change per pass; any other is still unseen, so its
+        // changed() is ready straight away on the next
+        let wake = {
+            let mut changes: Vec<BoxFuture<'_, (String, bool)>> = Vec::new();
+            for (name, Watched { state, latest }) in &mut watched {
+                let (state_of, latest_of) = (name.clone(), name.clone());
+                changes.push(state.changed().map(move |_| (state_of, true)).boxed());
+                changes.push(latest.changed().map(move |_| (latest_of, false)).boxed());
+            }
+            // select_all needs at least one
+            changes.push(future::pending().boxed());
+            tokio::select! {
+                ((name, is_state), _, _) = future::select_all(changes) => Wake::Changed(name, is_state),
+                _ = rescan.tick() => Wake::Rescan,
+                _ = republish.notified() => Wake::Republish,
+                _ = shutdown.cancelled() => return,
+            }
+        };
+
+        match wake {
+            Wake::Changed(name, is_state) => {
+                let Some(source) = watched.get_mut(&name) else { continue };
+                if is_state {
+                    publish_state(&client, &topics, &name, source);
+                } else {
+                    publish_latest(&client, &topics, &name, source);
+                }
+            }
+            Wake::Rescan => {
+                let sources = app_data.sources.all(&app_data).await;
+                watched.retain(|name, _| {
+                    let keep = sources.iter().any(|s| &s.name == name);
+                    if !keep {
+                        tracing::info!(source = %name, "removing Home Assistant entities");
+                        remove_entities(&client, &topics, name);
+                    }
+                    keep
+                });
+                for source in sources {
+                    if watched.contains_key(&source.name) {
+                        continue;
+                    }
+                    let mut added = Watched {
+                        state: source.state.subscribe(),
+                        latest: source.latest.subscribe(),
+                    };
+                    publish_entities(&client, &topics, &source.name);
+                    publish_state(&client, &topics, &source.name, &mut added);
+                    publish_latest(&client, &topics, &source.name, &mut added);
+                    watched.insert(source.name.clone(), added);
+                }
+            }
+            Wake::Republish => {
+                for (name, source) in &mut watched {
+                    publish_entities(&client, &topics, name);
+                    publish_state(&client, &topics, name, source);
+                    publish_latest(&client, &topics, name, source);
+                }
+            }
+        }
+    }
+}
+
+fn publish_state(client: &AsyncClient, topics: &Topics, name: &str, source: &mut Watched) {
+    let state = source.state.borrow_and_update().clone();
+    let switch = if state.is_recording() { "ON" } else { "OFF" };
+    publish(client, topics.source(name, "recording"), switch);
+    if let Ok(state) = serde_json::to_string(&state) {
+        publish(client, topics.source(name, "state"), state);
+    }
+}
+
+fn publish_latest(client: &AsyncClient, topics: &Topics, name: &str, source: &mut Watched) {
+    let latest = source.latest.borrow_and_update().clone();
+    // Keep the retained one from before a restart until there's news
+    if latest.transcript.is_empty() && latest.gpt_response.is_empty() {
+        return;
+    }
+    let payload = json!({ "transcript": latest.transcript, "response": latest.gpt_response });
+    publish(client, topics.source(name, "latest"), payload.to_string());
+}
+
+/////////////////////////////////////////////////////////////
+// Discovery configs
+/////////////////////////////////////////////////////////////
+fn publish_entities(client: &AsyncClient, topics: &Topics, name: &str) {
+    let device = json!({
+        "identifiers": [topics.node_id],
+        "name": "SilentNight",
+        "model": "SilentNight",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    for (component, entity) in ENTITIES {
+        let mut config = match entity {
+            "recording" => json!({
+                "name": format!("{name} recording"),
+                "icon": "mdi:microphone",
+                "state_topic": topics.source(name, "recording"),
+                "command_topic": topics.source(name, "recording/set"),
+                "payload_on": "ON",
+                "payload_off": "OFF",
+            }),
+            "state" => json!({
+                "name": format!("{name} state"),
+                "icon": "mdi:state-machine",
+                "state_topic": topics.source(name, "state"),
+                "value_template": "{{ value_json.name }}",
+                "json_attributes_topic": topics.source(name, "state"),
+            }),
+            _ => json!({
+                "name": format!("{name} last response"),
+                "icon": "mdi:message-text",
+                "state_topic": topics.source(name, "latest"),
+                "value_template": format!("{{{{ value_json.response[:{MAX_STATE_LEN}] }}}}"),
+                "json_attributes_topic": topics.source(name, "latest"),
+            }),
+        };
+        config["unique_id"] = json!(format!("{}_{name}_{entity}", topics.node_id));
+        config["availability_topic"] = json!(topics.availability());
+        config["device"] = device.clone();
+        publish(client, topics.discovery(component, name, entity), config.to_string());
+    }
+}
+
+// An empty retained config deletes the entity
+fn remove_entities(client: &AsyncClient, topics: &Topics, name: &str) {
+    for (component, entity) in ENTITIES {
+        publish(client, topics.discovery(component, name, entity), "");
+    }
+}
+
+// Retained, so Home Assistant gets the latest after a restart.
+// Never waits: if the queue is full the broker has been away a
+// while, and everything is published again on reconnect
+fn publish(client: &AsyncClient, topic: String, payload: impl Into<Vec<u8>>) {
+    if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload) {
+        tracing::debug!(error = %e, "MQTT message dropped");
+    }
+}
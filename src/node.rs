@@ -0,0 +1,140 @@
+/////////////////////////////////////////////////////////////
+// src/node.rs
+//
+// Node mode ([node] enabled): this instance only captures audio
+// and sends it to a hub (see hub.rs), which does Whisper, GPT,
+// the log and the display. For a Pi Zero in the kitchen, with
+// the hub on something bigger.
+//
+// The node checks in with the hub every node.heartbeat_secs
+// (PUT /nodes/{node.name}, with node.label), and while the hub
+// says to capture, records node.source's mic chunk after chunk
+// (the hub's chunk_secs) and POSTs each to
+// /nodes/{node.name}/chunks, with node.token as a bearer token.
+// Recording is started and stopped on the hub, like any of its
+// sources; the node needs no OpenAI key. A hub that can't be
+// reached is retried every heartbeat; chunks recorded meanwhile
+// are lost. Settings are restart-only.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::config::NodeSettings;
+use crate::hub::NodeReply;
+use crate::{privacy, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/////////////////////////////////////////////////////////////
+// spawn
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: NodeSettings) {
+    tracing::info!(hub = %settings.hub_url, node = %settings.name, source = %settings.source, "running as a node");
+    let span = tracing::info_span!(parent: None, "node", node = %settings.name);
+    let data = app_data.clone();
+    app_data.tasks.spawn("node", run(data, settings).instrument(span));
+}
+
+async fn run(app_data: web::Data<AppState>, settings: NodeSettings) {
+    let shutdown = app_data.tasks.token();
+    let client = privacy::client();
+    let heartbeat = Duration::from_secs(settings.heartbeat_secs);
+    let mut reachable = true;
+    loop {
+        let result = tokio::select! {
+            result = check_in_and_capture(&app_data, &client, &settings) => result,
+            _ = shutdown.cancelled() => return,
+        };
+        match result {
+            // Straight on to the next chunk
+            Ok(true) => {
+                reachable = true;
+                continue;
+            }
+            Ok(false) => reachable = true,
+            // Once per outage
+            Err(e) if reachable => {
+                tracing::warn!(error = %format!("{e:#}"), "hub unavailable, retrying every {}s", heartbeat.as_secs());
+                reachable = false;
+            }
+            Err(_) => {}
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(heartbeat) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+// Checks in, then sends one chunk if the hub wants it; true if
+// it did, and wants the next one
+async fn check_in_and_capture(app_data: &web::Data<AppState>, client: &reqwest::Client, settings: &NodeSettings) -> Result<bool> {
+    let hub = settings.hub_url.trim_end_matches('/');
+    let hello = json!({ "label": settings.label, "version": env!("CARGO_PKG_VERSION") });
+    let reply = call(client.put(format!("{hub}/nodes/{}", settings.name)).json(&hello), settings).await?;
+    if !reply.capture {
+        return Ok(false);
+    }
+
+    let input = app_data
+        .config
+        .read()
+        .await
+        .audio
+        .input(&settings.source)
+        .with_context(|| format!("there's no audio source named {}", settings.source))?;
+    let Some(source) = app_data.sources.get(app_data, &settings.source).await else {
+        anyhow::bail!("there's no audio source named {}", settings.source);
+    };
+    let audio = source
+        .recorder(&input)
+        .await
+        .record(reply.chunk_secs)
+        .await
+        .context("Failed to record a chunk")?;
+    tracing::debug!(bytes = audio.len(), "sending a chunk to the hub");
+    let request = client
+        .post(format!("{hub}/nodes/{}/chunks", settings.name))
+        .header("Content-Type", "audio/wav")
+        .body(audio);
+    match call(request, settings).await {
+        Ok(reply) => Ok(reply.capture),
+        // Stopped, muted or busy while this one was being recorded
+        Err(e) if e.downcast_ref::<Refused>().is_some() => {
+            tracing::debug!(reason = %e, "hub didn't take the chunk");
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// The hub answered, but not with 2xx, to a chunk it can't use
+#[derive(Debug, thiserror::Error)]
+#[error("hub returned {status}: {body}")]
+struct Refused {
+    status: u16,
+    body: String,
+}
+
+async fn call(request: reqwest::RequestBuilder, settings: &NodeSettings) -> Result<NodeReply> {
+    let resp = request
+        .bearer_auth(&settings.token)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("hub unreachable")?;
+    let status = resp.status();
+    if matches!(status.as_u16(), 409 | 503) {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(Refused { status: status.as_u16(), body }.into());
+    }
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("hub returned {status}: {body}");
+    }
+    resp.json().await.context("hub sent an unexpected reply")
+}
@@ -0,0 +1,293 @@
+/////////////////////////////////////////////////////////////
+// src/notify.rs
+//
+// Keyword alerts: when a new transcript contains one of
+// alerts.keywords ("invoice", "server down", a child's name)
+// every configured notifier gets an Alert with
+//   - the keywords that matched
+//   - the audio source and session
+//   - the transcript around the first match (the source's
+//     previous transcript too, so a sentence split across two
+//     chunks still reads)
+//   - a link to the session in the web UI, when
+//     alerts.public_url says where the server can be reached
+//
+// Keywords match whole words, ignoring case, so "Sam" doesn't
+// fire on "same". Notifiers implement the Notifier trait:
+//   slack    - POST to a Slack incoming webhook
+//              (alerts.slack_webhook_url)
+//   telegram - a message to each of telegram.chat_ids, when the
+//              bot is on and telegram.alerts is set (see
+//              telegram.rs)
+//
+// Settings are read at each transcript, so a reload applies to
+// the next one. Sending happens in background tasks; a failure
+// is logged and not retried.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::ops::Range;
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::config::{AlertSettings, Config};
+use crate::{privacy, telegram, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/////////////////////////////////////////////////////////////
+// Alert
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+pub struct Alert {
+    // In the order they're configured
+    pub keywords: Vec<String>,
+    pub audio_source: String,
+    pub session_id: Option<String>,
+    // Transcript around the first match, "…" where it was cut
+    pub context: String,
+    // The session in the web UI
+    pub link: Option<String>,
+}
+
+impl Alert {
+    // For notifiers without markup
+    pub fn plain_text(&self) -> String {
+        let keywords = self.keywords.iter().map(|k| format!("\"{k}\"")).collect::<Vec<_>>().join(", ");
+        let mut text = format!("Heard {keywords} on {}\n{}", self.audio_source, self.context);
+        match (&self.link, &self.session_id) {
+            (Some(link), _) => text.push_str(&format!("\n{link}")),
+            (None, Some(id)) => text.push_str(&format!("\nSession {id}")),
+            (None, None) => {}
+        }
+        text
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    // For logs
+    fn name(&self) -> &'static str;
+
+    async fn notify(&self, alert: &Alert) -> Result<()>;
+}
+
+/////////////////////////////////////////////////////////////
+// Notifiers
+//
+// Shared by every notifier that talks HTTP.
+/////////////////////////////////////////////////////////////
+pub struct Notifiers {
+    client: reqwest::Client,
+}
+
+impl Default for Notifiers {
+    fn default() -> Self {
+        Notifiers { client: privacy::client() }
+    }
+}
+
+impl Notifiers {
+    // The ones switched on in `config`
+    fn configured(&self, config: &Config) -> Vec<Box<dyn Notifier>> {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if !config.alerts.slack_webhook_url.is_empty() {
+            notifiers.push(Box::new(SlackNotifier {
+                client: self.client.clone(),
+                webhook_url: config.alerts.slack_webhook_url.clone(),
+            }));
+        }
+        if config.telegram.enabled && config.telegram.alerts {
+            notifiers.push(Box::new(telegram::TelegramNotifier::new(self.client.clone(), &config.telegram)));
+        }
+        notifiers
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// check_transcript
+//
+// Called with each logged transcript; `previous` is the
+// source's transcript before it. Returns right away, alerts
+// are sent in background tasks.
+/////////////////////////////////////////////////////////////
+pub async fn check_transcript(
+    app_data: &AppState,
+    audio_source: &str,
+    session_id: Option<&str>,
+    previous: &str,
+    transcript: &str,
+) {
+    let (settings, notifiers) = {
+        let config = app_data.config.read().await;
+        (config.alerts.clone(), app_data.notifiers.configured(&config))
+    };
+    if notifiers.is_empty() {
+        return;
+    }
+    let Some(alert) = match_keywords(&settings, audio_source, session_id, previous, transcript) else {
+        return;
+    };
+    tracing::info!(source = audio_source, keywords = ?alert.keywords, "keyword heard, sending alerts");
+
+    for notifier in notifiers {
+        let alert = alert.clone();
+        let span = tracing::info_span!(parent: None, "alert", notifier = notifier.name(), source = audio_source);
+        let send = async move {
+            match notifier.notify(&alert).await {
+                Ok(()) => tracing::debug!("alert sent"),
+                Err(e) => tracing::warn!(error = %format!("{e:#}"), "alert failed"),
+            }
+        };
+        app_data.tasks.spawn("alert", send.instrument(span));
+    }
+}
+
+// None if no keyword is in `transcript`. Only the new transcript
+// can trigger an alert; `previous` is just context
+fn match_keywords(
+    settings: &AlertSettings,
+    audio_source: &str,
+    session_id: Option<&str>,
+    previous: &str,
+    transcript: &str,
+) -> Option<Alert> {
+    let mut first: Option<Range<usize>> = None;
+    let mut keywords = Vec::new();
+    for keyword in &settings.keywords {
+        let Some(found) = find_word(transcript, keyword) else { continue };
+        keywords.push(keyword.clone());
+        if first.as_ref().is_none_or(|f| found.start < f.start) {
+            first = Some(found);
+        }
+    }
+    let first = first?;
+
+    let previous = previous.trim();
+    let (text, offset) = if previous.is_empty() {
+        (transcript.to_string(), 0)
+    } else {
+        (format!("{previous} {transcript}"), previous.len() + 1)
+    };
+    let context = excerpt(&text, first.start + offset..first.end + offset, settings.context_chars);
+
+    let public_url = settings.public_url.trim_end_matches('/');
+    let link = match session_id {
+        Some(id) if !public_url.is_empty() => Some(format!("{public_url}/?session={id}")),
+        _ => None,
+    };
+    Some(Alert {
+        keywords,
+        audio_source: audio_source.to_string(),
+        session_id: session_id.map(str::to_string),
+        context,
+        link,
+    })
+}
+
+// Byte range of the first whole-word, case-insensitive match
+pub(crate) fn find_word(text: &str, keyword: &str) -> Option<Range<usize>> {
+    let keyword = keyword.trim();
+    if keyword.is_empty() {
+        return None;
+    }
+    text.char_indices().find_map(|(start, _)| {
+        let mut rest = text[start..].chars();
+        let mut end = start;
+        for wanted in keyword.chars() {
+            let c = rest.next()?;
+            if !c.to_lowercase().eq(wanted.to_lowercase()) {
+                return None;
+            }
+            end += c.len_utf8();
+        }
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+        (!is_word(before) && !is_word(after)).then_some(start..end)
+    })
+}
+
+// `text` from up to `chars` characters before `found` to as many
+// after it, widened to whole words
+fn excerpt(text: &str, found: Range<usize>, chars: usize) -> String {
+    let mut start = text[..found.start].char_indices().rev().nth(chars.saturating_sub(1)).map_or(0, |(i, _)| i);
+    let mut end = text[found.end..].char_indices().nth(chars).map_or(text.len(), |(i, _)| found.end + i);
+    if start > 0 {
+        start = text[..start]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+    }
+    if end < text.len() {
+        end = text[end..].find(char::is_whitespace).map_or(text.len(), |i| end + i);
+    }
+
+    let mut context = String::new();
+    if start > 0 {
+        context.push('…');
+    }
+    context.push_str(text[start..end].trim());
+    if end < text.len() {
+        context.push('…');
+    }
+    context
+}
+
+/////////////////////////////////////////////////////////////
+// SlackNotifier
+//
+// Posts to a Slack incoming webhook as mrkdwn.
+/////////////////////////////////////////////////////////////
+struct SlackNotifier {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let keywords = alert
+            .keywords
+            .iter()
+            .map(|k| format!("\"{}\"", slack_escape(k)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut text = format!(
+            "*Heard {keywords}* on {}\n> {}",
+            slack_escape(&alert.audio_source),
+            slack_escape(&alert.context)
+        );
+        match (&alert.link, &alert.session_id) {
+            (Some(link), _) => text.push_str(&format!("\n<{link}|Open the session>")),
+            (None, Some(id)) => text.push_str(&format!("\nSession {id}")),
+            (None, None) => {}
+        }
+
+        let resp = self
+            .client
+            .post(&self.webhook_url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&serde_json::json!({ "text": text }))
+            .send()
+            .await
+            .context("Slack unreachable")?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("Slack replied {status}: {body}");
+        }
+        Ok(())
+    }
+}
+
+// Slack treats these three as markup in message text
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
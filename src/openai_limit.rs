@@ -0,0 +1,181 @@
+/////////////////////////////////////////////////////////////
+// src/openai_limit.rs
+//
+// Caps how many Whisper/GPT requests are in flight at once, so
+// several sources recording together don't trip OpenAI's rate
+// limits, and bounds how many chunks wait for a slot, so a
+// backlog can't eat the Pi's memory (each waiting chunk holds
+// its audio).
+//
+// Settings ([openai] or env), applied on reload:
+//   max_concurrent (OPENAI_MAX_CONCURRENT)  default 2
+//   max_queued     (OPENAI_MAX_QUEUED)      default 16; a call
+//                  that would wait beyond this fails instead
+//
+// GET /status shows the current and total counts, and how long
+// Whisper and GPT requests take once they have a slot.
+/////////////////////////////////////////////////////////////
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use utoipa::ToSchema;
+
+use crate::config::OpenAiConfig;
+use crate::error::OpenAiError;
+use crate::telemetry::{Latency, LatencyStats};
+
+/////////////////////////////////////////////////////////////
+// OpenAiLimiter
+/////////////////////////////////////////////////////////////
+pub struct OpenAiLimiter {
+    slots: Arc<Semaphore>,
+    // Permits the semaphore was sized for; changes on reload
+    max_concurrent: AtomicUsize,
+    max_queued: AtomicUsize,
+    in_flight: AtomicUsize,
+    queued: AtomicUsize,
+    calls: AtomicU64,
+    rejected: AtomicU64,
+    wait_ms: AtomicU64,
+    whisper: Latency,
+    gpt: Latency,
+}
+
+// Held for the duration of one OpenAI request, which it times
+pub struct Slot<'a> {
+    limiter: &'a OpenAiLimiter,
+    api: &'static str,
+    started: Instant,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let latency = if self.api == "whisper" { &self.limiter.whisper } else { &self.limiter.gpt };
+        latency.record(self.started.elapsed());
+    }
+}
+
+impl OpenAiLimiter {
+    pub fn new(settings: &OpenAiConfig) -> OpenAiLimiter {
+        OpenAiLimiter {
+            slots: Arc::new(Semaphore::new(settings.max_concurrent)),
+            max_concurrent: AtomicUsize::new(settings.max_concurrent),
+            max_queued: AtomicUsize::new(settings.max_queued),
+            in_flight: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            calls: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            wait_ms: AtomicU64::new(0),
+            whisper: Latency::default(),
+            gpt: Latency::default(),
+        }
+    }
+
+    // Resizes in place; calls already running keep their slot
+    pub fn update(&self, settings: &OpenAiConfig) {
+        self.max_queued.store(settings.max_queued, Ordering::Relaxed);
+        let old = self.max_concurrent.swap(settings.max_concurrent, Ordering::Relaxed);
+        if settings.max_concurrent > old {
+            self.slots.add_permits(settings.max_concurrent - old);
+        } else if settings.max_concurrent < old {
+            // Retire the extra permits as running calls hand them back
+            let surplus = (old - settings.max_concurrent) as u32;
+            let slots = self.slots.clone();
+            tokio::spawn(async move {
+                if let Ok(permits) = slots.acquire_many_owned(surplus).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+
+    /////////////////////////////////////////////////////////
+    // acquire
+    //
+    // Waits for a free slot, or fails right away if max_queued
+    // calls are already waiting. `api` ("whisper" / "gpt") is
+    // for the logs.
+    /////////////////////////////////////////////////////////
+    pub async fn acquire(&self, api: &'static str) -> Result<Slot<'_>, OpenAiError> {
+        let started = Instant::now();
+        let permit = match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let waiting = self.queued.fetch_add(1, Ordering::Relaxed);
+                if waiting >= self.max_queued.load(Ordering::Relaxed) {
+                    self.queued.fetch_sub(1, Ordering::Relaxed);
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(api, waiting, "OpenAI queue full, dropping request");
+                    return Err(OpenAiError::Busy { waiting });
+                }
+                tracing::debug!(api, waiting = waiting + 1, "waiting for an OpenAI slot");
+                let permit = self.slots.clone().acquire_owned().await;
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                permit.expect("OpenAI limiter semaphore is never closed")
+            }
+        };
+
+        let waited = started.elapsed().as_millis() as u64;
+        self.wait_ms.fetch_add(waited, Ordering::Relaxed);
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        if waited > 0 {
+            tracing::debug!(api, waited_ms = waited, "got an OpenAI slot");
+        }
+        Ok(Slot {
+            limiter: self,
+            api,
+            started: Instant::now(),
+            _permit: permit,
+        })
+    }
+
+    // The latest request times, oldest first, for "whisper" or "gpt"
+    pub fn recent_ms(&self, api: &str) -> Vec<u64> {
+        let latency = if api == "whisper" { &self.whisper } else { &self.gpt };
+        latency.recent()
+    }
+
+    pub fn usage(&self) -> OpenAiUsage {
+        let calls = self.calls.load(Ordering::Relaxed);
+        OpenAiUsage {
+            max_concurrent: self.max_concurrent.load(Ordering::Relaxed),
+            max_queued: self.max_queued.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            calls,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            avg_wait_ms: self.wait_ms.load(Ordering::Relaxed).checked_div(calls).unwrap_or(0),
+            whisper: self.whisper.stats(),
+            gpt: self.gpt.stats(),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// OpenAiUsage
+//
+// For GET /status.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OpenAiUsage {
+    max_concurrent: usize,
+    max_queued: usize,
+    // Requests to Whisper/GPT right now
+    pub in_flight: usize,
+    // Requests waiting for a slot
+    pub queued: usize,
+    // Totals since startup
+    calls: u64,
+    pub rejected: u64,
+    // Average time a call waited for a slot
+    avg_wait_ms: u64,
+    // Request time once a slot was free, failed requests included
+    pub whisper: LatencyStats,
+    pub gpt: LatencyStats,
+}
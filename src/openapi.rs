@@ -0,0 +1,228 @@
+/////////////////////////////////////////////////////////////
+// src/openapi.rs
+//
+// OpenAPI spec for the HTTP API, generated from the
+// #[utoipa::path] annotations on each handler.
+//   GET /openapi.json  - the spec
+//   GET /docs          - Swagger UI (static/swagger.html)
+//
+// When adding a handler, annotate it and list it in ApiDoc (or,
+// behind a cargo feature, in that feature's doc, see spec()).
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::assets::static_file;
+use crate::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Silent Night", description = "Audio -> Whisper -> GPT conversation listener"),
+    paths(
+        crate::get_transcript,
+        crate::start_recording,
+        crate::stop_recording,
+        crate::record_once,
+        crate::conversation_log,
+        crate::live_log_sse,
+        crate::poll_log,
+        crate::entry,
+        crate::auth::login_page,
+        crate::auth::login,
+        crate::auth::logout,
+        crate::pairing::qr_png,
+        crate::pairing::pair,
+        crate::display::get_profile,
+        crate::display::put_profile,
+        crate::i18n::get_strings,
+        crate::status::health,
+        crate::status::ready,
+        crate::status::status,
+        crate::dashboard::dashboard,
+        crate::reload::reload,
+        crate::sessions::list_sources,
+        crate::sessions::start_named,
+        crate::sessions::stop_named,
+        crate::sessions::transcript_named,
+        crate::sessions::live_log_named,
+        crate::discovery::discover,
+        crate::webhooks::webhooks_status,
+        crate::discord::interactions,
+        crate::backlog::process_backlog,
+        crate::telemetry::metrics,
+        crate::admin::get_settings,
+        crate::admin::patch_settings,
+        crate::admin::shutdown,
+        crate::admin::restart,
+        crate::secrets::rotate_key,
+        crate::rules::list_rules,
+        crate::rules::create_rule,
+        crate::rules::get_rule,
+        crate::rules::update_rule,
+        crate::rules::delete_rule,
+        crate::reminders::list_reminders,
+        crate::reminders::delete_reminder,
+        crate::hub::list_nodes,
+        crate::hub::check_in,
+        crate::hub::receive_chunk,
+        crate::sync::sync_status,
+        crate::sync::list_entries,
+        crate::audit::list_audit,
+        crate::quiet::get_quiet_hours,
+        crate::quiet::override_quiet_hours,
+        crate::quiet::end_override,
+        crate::optout::list_profiles,
+        crate::optout::enroll,
+        crate::optout::delete_profile,
+        crate::export::export_session,
+        crate::translate::translate_session,
+        crate::meetings::start_meeting,
+        crate::meetings::get_minutes,
+        crate::meetings::write_minutes,
+        crate::browse::list_sessions,
+        crate::browse::show_session,
+        crate::replay::replay_session,
+        crate::session_settings::session_settings,
+        crate::feedback::rate,
+        crate::feedback::stats,
+        crate::captions::captions,
+        crate::lights::get_settings,
+        crate::lights::patch_settings,
+        crate::lights::discover,
+        crate::lights::pair,
+        crate::lights::test,
+        crate::bookmarks::list_bookmarks,
+        openapi_json,
+        docs,
+    ),
+    components(schemas(
+        crate::TranscriptResponse,
+        crate::PollResponse,
+        crate::PolledRecord,
+        crate::auth::LoginForm,
+        crate::error::ErrorBody,
+        crate::status::StatusResponse,
+        crate::status::ReadinessResponse,
+        crate::status::ReadinessCheck,
+        crate::status::QueueDepths,
+        crate::openai_limit::OpenAiUsage,
+        crate::pipeline::PipelineStatus,
+        crate::pipeline::StageStatus,
+        crate::lifecycle::RecordingState,
+        crate::events::SubscriberLag,
+        crate::events::Event,
+        crate::captions::Caption,
+        crate::pipeline::Segment,
+        crate::session_settings::Overrides,
+        crate::session_settings::Settings,
+        crate::session_settings::Snapshot,
+        crate::session_settings::SessionSettings,
+        crate::feedback::FeedbackRequest,
+        crate::feedback::Feedback,
+        crate::feedback::PersonaStats,
+        crate::disk::DiskStatus,
+        crate::disk::Volume,
+        crate::status::Backends,
+        crate::reload::ReloadReport,
+        crate::sessions::SourceStatus,
+        crate::discovery::DiscoverResponse,
+        crate::discovery::Peer,
+        crate::webhooks::WebhooksResponse,
+        crate::webhooks::TargetInfo,
+        crate::webhooks::Delivery,
+        crate::backlog::BacklogStatus,
+        crate::privacy::PrivacyStatus,
+        crate::routing::RoutingStatus,
+        crate::telemetry::LatencyStats,
+        crate::admin::AdminSettings,
+        crate::admin::SettingsPatch,
+        crate::secrets::RotateKey,
+        crate::secrets::RotatedKey,
+        crate::rules::Rule,
+        crate::rules::Conditions,
+        crate::rules::Action,
+        crate::reminders::Reminder,
+        crate::hub::NodeInfo,
+        crate::hub::NodeHello,
+        crate::hub::NodeReply,
+        crate::sync::SyncStatus,
+        crate::sync::PeerStatus,
+        crate::sync::EntriesPage,
+        crate::audit::AuditEntry,
+        crate::quiet::QuietHoursStatus,
+        crate::quiet::OverrideRequest,
+        crate::optout::ProfileInfo,
+        crate::export::ExportResponse,
+        crate::translate::Translation,
+        crate::translate::TranslatedLine,
+        crate::meetings::MeetingRequest,
+        crate::meetings::MeetingStarted,
+        crate::meetings::Minutes,
+        crate::meetings::ActionItem,
+        crate::display::ProfileView,
+        crate::display::ProfileBody,
+        crate::i18n::I18nResponse,
+        crate::i18n::Strings,
+        crate::lights::LightsView,
+        crate::lights::LightsPatch,
+        crate::lights::DiscoveredLights,
+        crate::lights::FoundDevice,
+        crate::lights::HueLight,
+        crate::lights::PairResponse,
+    )),
+    modifiers(&SecuritySchemes)
+)]
+struct ApiDoc;
+
+#[cfg(feature = "graphql")]
+#[derive(OpenApi)]
+#[openapi(paths(crate::graphql::graphql, crate::graphql::graphql_get))]
+struct GraphqlDoc;
+
+// ApiDoc plus the endpoints of the features built in
+fn spec() -> utoipa::openapi::OpenApi {
+    #[allow(unused_mut)]
+    let mut spec = ApiDoc::openapi();
+    #[cfg(feature = "graphql")]
+    spec.merge(GraphqlDoc::openapi());
+    spec
+}
+
+// "Authorization: Bearer <admin.token>" for the /admin, /rules
+// and /lights endpoints
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /openapi.json
+/////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "docs", responses((status = 200, description = "This OpenAPI document")))]
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(spec())
+}
+
+/////////////////////////////////////////////////////////////
+// GET /docs  => Serve static/swagger.html
+/////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "docs", responses((status = 200, description = "Swagger UI", content_type = "text/html")))]
+#[get("/docs")]
+async fn docs(req: HttpRequest, app_data: web::Data<AppState>) -> actix_web::Result<HttpResponse> {
+    static_file(&req, &app_data, "swagger.html").await
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(openapi_json).service(docs);
+}
@@ -0,0 +1,392 @@
+/////////////////////////////////////////////////////////////
+// src/optout.rs
+//
+// Opting out by voice: someone who doesn't want to be recorded
+// enrolls a sample of their voice as a "do not record" profile,
+// and every chunk is checked for it as soon as it's captured,
+// before it's saved, transcribed or sent anywhere:
+//   - a chunk where opted-out voices make up at least
+//     opt_out.dominance of the speech is discarded
+//   - in any other chunk, their speech is silenced
+//     (opt_out.redact_segments) before Whisper hears it
+// record_once answers 409 (code speaker_opted_out) for a
+// discarded chunk; a recording source just moves on.
+//
+// Voices are compared by a speaker-embedding service at
+// opt_out.embedding_url, which gets the WAV audio (POST,
+// audio/wav) and answers
+//   {"segments": [{"start": 0.0, "end": 2.4, "embedding": [...]}]}
+// with times in seconds: a diarization model (pyannote, ...)
+// one segment per speaker turn, a plain embedding model
+// (SpeechBrain ECAPA, Resemblyzer, ...) one for the whole clip.
+// A segment whose embedding is within opt_out.threshold (cosine
+// similarity) of a profile's is that person. While it can't be
+// reached, chunks are dropped rather than let through.
+//
+//   GET    /opt_out/profiles             - who has opted out
+//   POST   /opt_out/profiles?name=Grandma - enroll a WAV sample
+//                                         of only their voice
+//   DELETE /opt_out/profiles/{id}
+// Same access as /admin (see admin.rs). Profiles are kept in
+// opt_out.file, rewritten on every change; they hold the
+// embedding, not the sample.
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{delete, get, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::{ApiError, AudioError};
+use crate::{admin, logging, privacy, wav, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+// An enrollment sample, like a node's chunk
+const MAX_SAMPLE_BYTES: usize = 16 * 1024 * 1024;
+
+/////////////////////////////////////////////////////////////
+// Profile
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone)]
+struct Profile {
+    id: String,
+    name: String,
+    // RFC 3339
+    enrolled_at: String,
+    embedding: Vec<f32>,
+}
+
+// A profile as /opt_out/profiles shows it
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ProfileInfo {
+    id: String,
+    name: String,
+    // RFC 3339
+    enrolled_at: String,
+}
+
+impl From<&Profile> for ProfileInfo {
+    fn from(profile: &Profile) -> ProfileInfo {
+        ProfileInfo {
+            id: profile.id.clone(),
+            name: profile.name.clone(),
+            enrolled_at: profile.enrolled_at.clone(),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Profiles
+//
+// The enrolled voices, and the file they're kept in.
+/////////////////////////////////////////////////////////////
+pub struct Profiles {
+    profiles: RwLock<Vec<Profile>>,
+    path: PathBuf,
+}
+
+impl Profiles {
+    // No file yet is nobody opted out
+    pub fn load(path: &str) -> Result<Profiles> {
+        let path = PathBuf::from(path);
+        let profiles: Vec<Profile> = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                serde_json::from_str(&text).with_context(|| format!("{} isn't a list of voice profiles", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        tracing::debug!(profiles = profiles.len(), file = %path.display(), "loaded voice profiles");
+        Ok(Profiles {
+            profiles: RwLock::new(profiles),
+            path,
+        })
+    }
+
+    // Through a temporary file, so a crash never leaves half a list
+    async fn save(&self, profiles: &[Profile]) -> Result<()> {
+        let text = serde_json::to_string_pretty(profiles)?;
+        let tmp = tmp_path(&self.path);
+        tokio::fs::write(&tmp, text)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// Whether screen may change a chunk: opt-out is on and someone
+// has opted out
+pub async fn active(app_data: &AppState) -> bool {
+    app_data.config.read().await.opt_out.enabled && !app_data.opt_out.profiles.read().await.is_empty()
+}
+
+/////////////////////////////////////////////////////////////
+// screen
+//
+// A captured chunk with opted-out speech silenced, or
+// AudioError::OptedOut if there's too much of it to keep.
+/////////////////////////////////////////////////////////////
+pub async fn screen(app_data: &AppState, audio_data: web::Bytes) -> Result<web::Bytes, AudioError> {
+    let settings = app_data.config.read().await.opt_out.clone();
+    if !settings.enabled {
+        return Ok(audio_data);
+    }
+    let profiles = app_data.opt_out.profiles.read().await.clone();
+    if profiles.is_empty() {
+        return Ok(audio_data);
+    }
+    let info = wav::parse(&audio_data).map_err(AudioError::InvalidWav)?;
+    let segments = segments(&settings.embedding_url, &audio_data).await.map_err(AudioError::OptOutUnavailable)?;
+
+    let clip = info.duration().as_secs_f64();
+    let mut speech = 0.0;
+    let mut opted_out = Vec::new();
+    for segment in &segments {
+        // No times: the whole clip
+        let (start, end) = if segment.end > segment.start { (segment.start, segment.end.min(clip)) } else { (0.0, clip) };
+        let length = (end - start).max(0.0);
+        speech += length;
+        let matched = profiles
+            .iter()
+            .map(|p| (p, similarity(&p.embedding, &segment.embedding)))
+            .filter(|(_, score)| *score >= settings.threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((profile, _)) = matched {
+            opted_out.push((profile.name.clone(), start, end, length));
+        }
+    }
+    if opted_out.is_empty() {
+        return Ok(audio_data);
+    }
+
+    let opted_out_speech: f64 = opted_out.iter().map(|(_, _, _, length)| length).sum();
+    if speech > 0.0 && opted_out_speech / speech >= settings.dominance {
+        let speaker = opted_out[0].0.clone();
+        tracing::info!(speaker = %speaker, "chunk discarded, an opted-out speaker dominates it");
+        return Err(AudioError::OptedOut { speaker });
+    }
+    if settings.redact_segments {
+        // The only copy of a chunk the pipeline makes
+        let mut silenced = audio_data.to_vec();
+        for (_, start, end, _) in &opted_out {
+            wav::silence(&mut silenced, &info, Duration::from_secs_f64(*start), Duration::from_secs_f64(*end));
+        }
+        tracing::info!(segments = opted_out.len(), "silenced opted-out speech");
+        return Ok(silenced.into());
+    }
+    Ok(audio_data)
+}
+
+pub(crate) fn similarity(a: &[f32], b: &[f32]) -> f64 {
+    // From a different model
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| *x as f64 * *y as f64).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// segments
+//
+// Asks the embedding service at `url` who speaks when. Also
+// used for anonymous speaker labels (see speakers.rs).
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize)]
+struct Embedded {
+    segments: Vec<Segment>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Segment {
+    // Seconds; both 0 (or missing) for the whole clip
+    #[serde(default)]
+    pub start: f64,
+    #[serde(default)]
+    end: f64,
+    pub embedding: Vec<f32>,
+}
+
+pub(crate) async fn segments(url: &str, audio_data: &web::Bytes) -> Result<Vec<Segment>> {
+    let resp = privacy::client()
+        .post(url)
+        .header("Content-Type", "audio/wav")
+        .body(audio_data.clone())
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("Speaker-embedding service unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Speaker-embedding service returned {status}: {body}");
+    }
+    let embedded: Embedded = resp.json().await.context("Speaker-embedding service sent an unexpected reply")?;
+    Ok(embedded.segments)
+}
+
+/////////////////////////////////////////////////////////////
+// /opt_out/profiles
+/////////////////////////////////////////////////////////////
+fn unknown(id: &str) -> ApiError {
+    ApiError::not_found("unknown_profile", format!("No voice profile with id {id}"))
+}
+
+fn save_failed(e: anyhow::Error) -> ApiError {
+    tracing::error!(error = %format!("{e:#}"), "couldn't save the voice profiles");
+    ApiError::internal("profiles_not_saved", "Couldn't save the voice profiles").with_detail(format!("{e:#}"))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/opt_out/profiles",
+    responses(
+        (status = 200, description = "Everyone who opted out", body = [ProfileInfo]),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("")]
+async fn list_profiles(app_data: web::Data<AppState>) -> HttpResponse {
+    let profiles = app_data.opt_out.profiles.read().await;
+    HttpResponse::Ok().json(profiles.iter().map(ProfileInfo::from).collect::<Vec<_>>())
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct EnrollQuery {
+    // Who it is, e.g. "Grandma"
+    name: String,
+}
+
+#[utoipa::path(
+    post,
+    tag = "admin",
+    path = "/opt_out/profiles",
+    params(EnrollQuery),
+    request_body(content = Vec<u8>, content_type = "audio/wav", description = "A few seconds of only their voice"),
+    responses(
+        (status = 201, description = "Enrolled; chunks are checked for them from now on", body = ProfileInfo),
+        (status = 400, description = "Not WAV audio, or no voice in it (code invalid_audio)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 409, description = "opt_out isn't enabled (code opt_out_disabled)", body = ErrorBody),
+        (status = 502, description = "The embedding service failed (code opt_out_unavailable)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+// Registered by hand in configure(), for its own body limit
+async fn enroll(
+    app_data: web::Data<AppState>,
+    query: web::Query<EnrollQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let settings = app_data.config.read().await.opt_out.clone();
+    if !settings.enabled {
+        return Err(ApiError::conflict("opt_out_disabled", "Voice opt-out isn't enabled (opt_out.enabled)"));
+    }
+    let name = query.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("invalid_query", "name can't be empty"));
+    }
+    if let Err(e) = wav::parse(&body) {
+        return Err(ApiError::bad_request("invalid_audio", "The sample isn't WAV audio").with_detail(e));
+    }
+    let segments = segments(&settings.embedding_url, &body).await.map_err(|e| {
+        ApiError::bad_gateway("opt_out_unavailable", "The speaker-embedding service failed").with_detail(format!("{e:#}"))
+    })?;
+    let Some(embedding) = voiceprint(&segments) else {
+        return Err(ApiError::bad_request("invalid_audio", "No voice found in the sample"));
+    };
+
+    let profile = Profile {
+        id: logging::new_id(),
+        name: name.to_string(),
+        enrolled_at: Utc::now().to_rfc3339(),
+        embedding,
+    };
+    let mut profiles = app_data.opt_out.profiles.write().await;
+    let mut updated = profiles.clone();
+    updated.push(profile.clone());
+    app_data.opt_out.save(&updated).await.map_err(save_failed)?;
+    *profiles = updated;
+    tracing::info!(name = %profile.name, id = %profile.id, "voice profile enrolled");
+    Ok(HttpResponse::Created().json(ProfileInfo::from(&profile)))
+}
+
+// The sample's segments averaged, longer ones counting for more
+fn voiceprint(segments: &[Segment]) -> Option<Vec<f32>> {
+    let dims = segments.first()?.embedding.len();
+    if dims == 0 || segments.iter().any(|s| s.embedding.len() != dims) {
+        return None;
+    }
+    let mut sum = vec![0.0f64; dims];
+    for segment in segments {
+        let weight = if segment.end > segment.start { segment.end - segment.start } else { 1.0 };
+        for (total, x) in sum.iter_mut().zip(&segment.embedding) {
+            *total += *x as f64 * weight;
+        }
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return None;
+    }
+    Some(sum.into_iter().map(|x| (x / norm) as f32).collect())
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/opt_out/profiles/{id}",
+    params(("id" = String, Path, description = "Profile id")),
+    responses(
+        (status = 204, description = "Deleted; they're recorded like anyone else again"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 404, description = "No such profile (code unknown_profile)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[delete("/{id}")]
+async fn delete_profile(app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let mut profiles = app_data.opt_out.profiles.write().await;
+    let mut updated = profiles.clone();
+    let before = updated.len();
+    updated.retain(|p| p.id != *id);
+    if updated.len() == before {
+        return Err(unknown(&id));
+    }
+    app_data.opt_out.save(&updated).await.map_err(save_failed)?;
+    *profiles = updated;
+    tracing::info!(id = %id, "voice profile deleted");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/opt_out/profiles")
+            .wrap(from_fn(admin::require_admin))
+            .service(list_profiles)
+            .service(delete_profile)
+            .service(
+                web::resource("")
+                    .app_data(web::PayloadConfig::new(MAX_SAMPLE_BYTES))
+                    .route(web::post().to(enroll)),
+            ),
+    );
+}
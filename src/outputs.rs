@@ -0,0 +1,211 @@
+/////////////////////////////////////////////////////////////
+// src/outputs.rs
+//
+// Where each logged record goes. [outputs] lists, per kind, the
+// sinks it's sent to:
+//   [outputs]
+//   transcript = ["log", "sse", "webhook"]
+//   response   = ["log", "sse", "webhook", "mqtt", "tts"]
+// Sinks:
+//   log     - conversation_log.json; a kind without it isn't in
+//             the history (sessions, digests, replays, exports)
+//   sse     - /live_log, the source's own stream, /poll_log and
+//             gRPC subscribers
+//   webhook - [[webhooks]] targets subscribed to the kind
+//   mqtt    - <mqtt.base_topic>/<source>/<kind>, the record as
+//             JSON, not retained (needs mqtt.enabled)
+//   tts     - the text, spoken with rules.tts_command
+//   eink    - the text on outputs.eink_command's stdin (like
+//             tts, no shell), e.g. a script that draws it on an
+//             e-ink display
+// The default is log, sse and webhook for both, as before there
+// was a choice. "log" is written first and its failure fails
+// the chunk, as before; the rest are sent in order, and one
+// that fails is logged and skipped. Rules and lights see every
+// record whatever its sinks. A reload applies to the next
+// record.
+//
+// A new sink implements Sink and is added to SINKS and `sink`.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use tracing::Instrument;
+
+use crate::sessions::SourceSession;
+use crate::{rules, webhooks, AppState};
+
+pub const SINKS: [&str; 6] = ["log", "sse", "webhook", "mqtt", "tts", "eink"];
+// Record kinds that can be routed
+pub const KINDS: [&str; 2] = ["transcript", "response"];
+
+/////////////////////////////////////////////////////////////
+// Output
+//
+// A record on its way out, with the time as it's shown added
+// (see timezone.rs).
+/////////////////////////////////////////////////////////////
+pub struct Output<'a> {
+    // "transcript" or "response"
+    pub kind: &'a str,
+    // As the log has it: "Microphone" or "OPENAI RESPONSE"
+    pub source: &'a str,
+    pub audio_source: &'a SourceSession,
+    pub session_id: Option<String>,
+    pub record: &'a Value,
+    pub record_string: &'a str,
+}
+
+impl Output<'_> {
+    fn text(&self) -> &str {
+        self.record["text"].as_str().unwrap_or_default()
+    }
+}
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    // As [outputs] names it
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()>;
+}
+
+// The sink called `name`; None for "log", which the pipeline
+// writes itself, and names that aren't sinks
+fn sink(name: &str) -> Option<Box<dyn Sink>> {
+    match name {
+        "sse" => Some(Box::new(SseSink)),
+        "webhook" => Some(Box::new(WebhookSink)),
+        "mqtt" => Some(Box::new(MqttSink)),
+        "tts" => Some(Box::new(TtsSink)),
+        "eink" => Some(Box::new(EinkSink)),
+        _ => None,
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// sinks
+//
+// The names of the sinks records of `kind` go to.
+/////////////////////////////////////////////////////////////
+pub async fn sinks(app_data: &AppState, kind: &str) -> Vec<String> {
+    let config = app_data.config.read().await;
+    match kind {
+        "transcript" => config.outputs.transcript.clone(),
+        _ => config.outputs.response.clone(),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// send
+//
+// Hands a logged (or log-less) record to each of `names` but
+// "log", in order.
+/////////////////////////////////////////////////////////////
+pub async fn send(app_data: &web::Data<AppState>, names: &[String], output: &Output<'_>) {
+    for sink in names.iter().filter_map(|name| sink(name)) {
+        if let Err(e) = sink.send(app_data, output).await {
+            tracing::warn!(sink = sink.name(), kind = output.kind, error = %format!("{e:#}"), "output failed");
+        }
+    }
+}
+
+struct SseSink;
+
+#[async_trait]
+impl Sink for SseSink {
+    fn name(&self) -> &'static str {
+        "sse"
+    }
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()> {
+        let data = output.record_string.to_string();
+        app_data.events.publish(output.source, output.session_id.clone(), data.clone()).await;
+        output.audio_source.events.publish(output.source, output.session_id.clone(), data).await;
+        Ok(())
+    }
+}
+
+struct WebhookSink;
+
+#[async_trait]
+impl Sink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()> {
+        webhooks::send(app_data, output.kind, output.record.clone()).await;
+        Ok(())
+    }
+}
+
+struct MqttSink;
+
+#[async_trait]
+impl Sink for MqttSink {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()> {
+        let base = app_data.config.read().await.mqtt.base_topic.clone();
+        let topic = format!("{base}/{}/{}", output.audio_source.name, output.kind);
+        rules::publish(app_data, &topic, output.record_string.to_string(), false)
+    }
+}
+
+// Spoken in the background, so a long text doesn't hold up the
+// chunks after it
+struct TtsSink;
+
+#[async_trait]
+impl Sink for TtsSink {
+    fn name(&self) -> &'static str {
+        "tts"
+    }
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()> {
+        let command = app_data.config.read().await.rules.tts_command.clone();
+        let text = output.text().to_string();
+        let span = tracing::info_span!(parent: None, "output", sink = "tts", kind = output.kind);
+        app_data.tasks.spawn(
+            "output",
+            async move {
+                if let Err(e) = rules::speak(&command, &text).await {
+                    tracing::warn!(error = %format!("{e:#}"), "output failed");
+                }
+            }
+            .instrument(span),
+        );
+        Ok(())
+    }
+}
+
+// Also in the background; a display takes its time to redraw
+struct EinkSink;
+
+#[async_trait]
+impl Sink for EinkSink {
+    fn name(&self) -> &'static str {
+        "eink"
+    }
+
+    async fn send(&self, app_data: &web::Data<AppState>, output: &Output<'_>) -> Result<()> {
+        let command = app_data.config.read().await.outputs.eink_command.clone();
+        let text = output.text().to_string();
+        let span = tracing::info_span!(parent: None, "output", sink = "eink", kind = output.kind);
+        app_data.tasks.spawn(
+            "output",
+            async move {
+                if let Err(e) = rules::speak(&command, &text).await {
+                    tracing::warn!(error = %format!("{e:#}"), "output failed");
+                }
+            }
+            .instrument(span),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+/////////////////////////////////////////////////////////////
+// src/pairing.rs
+//
+// Joining from a phone by scanning the wall display instead of
+// typing an IP:
+//   GET /qr.png  - a QR code of the URL phones should open
+//                  (also in the X-Pairing-Url header); put it on
+//                  the display with <img src="/qr.png">
+//   GET /pair    - where the code leads when login is on
+// The URL is pairing.url, or this machine's LAN address and
+// server.port (https with TLS on).
+//
+// With login on (see auth.rs), /qr.png is behind it like every
+// page, and the code carries a login token:
+//   http://192.168.1.20:8080/pair?token=...
+// Opening it logs the phone in, once, within
+// pairing.token_ttl_secs (600), with whatever fetched the image
+// could do: a display-scoped tablet's code gives a display
+// session for its token, the login's an admin one (see
+// tokens.rs). It goes in the audit log as a login by "qr pairing". Every request for the image makes a new
+// token, so refresh it now and then. Tokens live in memory: a
+// restart voids them.
+/////////////////////////////////////////////////////////////
+
+use actix_web::http::header::{CACHE_CONTROL, LOCATION};
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+use utoipa::IntoParams;
+
+use crate::error::ApiError;
+use crate::qr::QrCode;
+use crate::auth::Caller;
+use crate::{audit, auth, AppState};
+
+// Pixels per module: about 300px square for a typical URL
+const SCALE: usize = 8;
+
+/////////////////////////////////////////////////////////////
+// PairingTokens
+//
+// Tokens handed out in codes, when they expire, and who for.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct PairingTokens {
+    tokens: AsyncMutex<HashMap<String, (Instant, Caller)>>,
+}
+
+impl PairingTokens {
+    async fn issue(&self, ttl: Duration, caller: Caller) -> String {
+        let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+        let mut tokens = self.tokens.lock().await;
+        // Drop expired ones while we're here
+        let now = Instant::now();
+        tokens.retain(|_, (expires, _)| *expires > now);
+        tokens.insert(token.clone(), (now + ttl, caller));
+        token
+    }
+
+    // Who `token` was issued to, if it hasn't expired; it can't
+    // be used again either way
+    async fn redeem(&self, token: &str) -> Option<Caller> {
+        let issued = self.tokens.lock().await.remove(token);
+        issued.filter(|(expires, _)| *expires > Instant::now()).map(|(_, caller)| caller)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /qr.png
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "ui",
+    path = "/qr.png",
+    responses(
+        (status = 200, description = "QR code of the URL to open, also in X-Pairing-Url", content_type = "image/png"),
+        (status = 500, description = "pairing.url is too long for a QR code (code pairing_url_too_long)", body = ErrorBody),
+    ),
+)]
+#[get("/qr.png")]
+async fn qr_png(req: HttpRequest, app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let (settings, server) = {
+        let config = app_data.config.read().await;
+        (config.pairing.clone(), config.server.clone())
+    };
+    let base = if settings.url.is_empty() {
+        let scheme = if app_data.tls_enabled { "https" } else { "http" };
+        let port = server.port;
+        match lan_address(&server.bind_addr) {
+            IpAddr::V4(ip) => format!("{scheme}://{ip}:{port}"),
+            IpAddr::V6(ip) => format!("{scheme}://[{ip}]:{port}"),
+        }
+    } else {
+        settings.url.trim_end_matches('/').to_string()
+    };
+    let url = if app_data.login_config.read().await.is_some() {
+        let caller = req.extensions().get::<Caller>().cloned().ok_or_else(|| ApiError::unauthorized("Login required"))?;
+        let token = app_data.pairing.issue(Duration::from_secs(settings.token_ttl_secs), caller).await;
+        format!("{base}/pair?token={token}")
+    } else {
+        format!("{base}/")
+    };
+
+    let qr = QrCode::encode(url.as_bytes())
+        .map_err(|e| ApiError::internal("pairing_url_too_long", "pairing.url doesn't fit in a QR code").with_detail(e))?;
+    tracing::debug!(version = qr.version(), "QR code for pairing");
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .insert_header(("X-Pairing-Url", url))
+        .body(qr.png(SCALE)))
+}
+
+// The address other machines on the LAN reach this one at: the
+// bind address if it's a specific one, otherwise the one the OS
+// would send from (connecting a UDP socket sends nothing)
+fn lan_address(bind_addr: &str) -> IpAddr {
+    if let Ok(ip) = bind_addr.parse::<IpAddr>() {
+        if !ip.is_unspecified() && !ip.is_loopback() {
+            return ip;
+        }
+    }
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            // TEST-NET-1, never actually contacted
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9))?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /pair
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct PairQuery {
+    // From the QR code
+    token: String,
+}
+
+#[utoipa::path(
+    tag = "auth",
+    path = "/pair",
+    params(PairQuery),
+    responses((status = 303, description = "Redirect to / with a session cookie, or to /login?failed=1 if the token is unknown, used or expired")),
+)]
+#[get("/pair")]
+async fn pair(req: HttpRequest, app_data: web::Data<AppState>, query: web::Query<PairQuery>) -> HttpResponse {
+    // Nothing to log into
+    if app_data.login_config.read().await.is_none() {
+        return HttpResponse::SeeOther().insert_header((LOCATION, "/")).finish();
+    }
+    let caller = app_data.pairing.redeem(&query.token).await;
+    audit::record_login(&app_data, &req, "qr pairing", caller.is_some()).await;
+    let Some(caller) = caller else {
+        tracing::warn!("unknown, used or expired pairing token");
+        return HttpResponse::SeeOther().insert_header((LOCATION, "/login?failed=1")).finish();
+    };
+    tracing::info!(scope = %caller.scope, "phone paired, session created");
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/"))
+        .cookie(auth::new_scoped_session(&app_data, caller.scope, caller.token).await)
+        .finish()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(qr_png).service(pair);
+}
@@ -0,0 +1,1707 @@
+/////////////////////////////////////////////////////////////
+// src/pipeline.rs
+//
+// What happens to each chunk of audio, in five stages:
+//   capture    - record chunk_secs from the source's mic, or
+//                with audio.chunking = "speech" up to the next
+//                pause (see segmenter.rs)
+//   screen     - check it for opted-out voices (see optout.rs),
+//                and keep a copy on disk if audio.save_dir is set
+//                and there's room (see disk.rs)
+//   transcribe - Whisper, redaction (see redact.rs), spoken
+//                commands (see voice.rs), then repeats of the
+//                chunk before (see dedup.rs); adjacent chunks
+//                with little speech may go to Whisper together
+//                (see Batch)
+//   respond    - GPT, with the source's conversation history
+//                and the enabled tools (see tools.rs)
+//   persist    - conversation_log.json (or the source's
+//                workspace's, see workspaces.rs), SSE, webhooks,
+//                keyword alerts, Discord, counters
+//
+// With audio.deadline_secs set, a chunk whose response isn't in
+// that long after it was recorded has its transcript logged and
+// sent ahead, so a slow GPT doesn't hold up the display (see
+// deadline_stage).
+//
+// While a source records, each stage runs as its own loop,
+// handing chunks to the next through a small bounded channel,
+// so the mic keeps recording while earlier chunks are still at
+// Whisper or GPT. Capture does nothing but record, starting the
+// next chunk as soon as one is handed on; the time the mic sits
+// idle in between is `capture_gap`, which grows if the later
+// stages fall behind by more than their queues hold. If the
+// first audio provider takes streamed uploads (openai.stream_audio,
+// or stream_audio on a routing provider), capture also sends
+// the audio to Whisper as it's recorded, so the transcript is
+// there about when the chunk ends rather than an upload later;
+// not while anyone has opted out, since screening may still
+// change the chunk. If that upload fails, transcribe sends the
+// chunk the usual way. A retryable failure is retried by the stage
+// that hit it (STAGE_ATTEMPTS times) before the chunk is
+// dropped; a fatal one, or MAX_CONSECUTIVE_FAILURES dropped
+// chunks in a row, stops the whole pipeline. A chunk Whisper
+// can't take at all (no API key, OpenAI unreachable) goes to the
+// backlog instead, and so does a transcript GPT can't answer for
+// the same reasons (see backlog.rs). GET /status shows per-stage
+// counts and timings under each source's `pipeline` (see
+// telemetry.rs).
+//
+// One-off chunks (record_once, gRPC audio) go through the same
+// stages in sequence, without retries.
+//
+// A chunk's audio is one shared Bytes buffer from capture on:
+// handing it to the next stage, retrying, the Whisper upload and
+// the speaker-embedding service all share it rather than copy
+// it, so several sources recording on a small Pi don't hold each
+// chunk in memory several times over.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use chrono::Utc;
+use futures_util::StreamExt;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex as AsyncMutex};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::error::{AudioError, LlmError, OpenAiError, PipelineError, StorageError, SttError};
+use crate::config::MicInput;
+use crate::lifecycle::RecordingState;
+use crate::recorder::{self, AudioStream};
+use crate::routing::{self, Category, Provider};
+use crate::segmenter::{self, Listening};
+use crate::session_settings::{self, Overrides};
+use crate::sessions::SourceSession;
+use crate::telemetry::{Latency, LatencyStats};
+use crate::captions::{self, Word};
+use crate::{backlog, config, dedup, discord, disk, feedback, forget, hub, lights, logging, memory, notify, openai_limit, optout, outputs, privacy, quiet, redact, rules, sentry, speakers, timezone, tools, voice, wav, workspaces, AppState, TranscriptResponse, CONVERSATION_LOG_LOCK};
+
+// Chunks that may wait between two stages
+const STAGE_QUEUE: usize = 2;
+const STAGE_ATTEMPTS: u32 = 2;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+// Times GPT may call tools before it has to answer
+const MAX_TOOL_ROUNDS: usize = 3;
+
+// Shared OpenAI client (see openai_client)
+const OPENAI_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const OPENAI_KEEP_ALIVE: Duration = Duration::from_secs(30);
+const OPENAI_POOL_IDLE: Duration = Duration::from_secs(90);
+
+/////////////////////////////////////////////////////////////
+// PipelineMetrics
+//
+// Per source, for the recording loop.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct PipelineMetrics {
+    capture: StageMetrics,
+    screen: StageMetrics,
+    transcribe: StageMetrics,
+    respond: StageMetrics,
+    persist: StageMetrics,
+    bytes_captured: AtomicU64,
+    // Chunks sent to Whisper together with others
+    batched: AtomicU64,
+    // Chunks dropped as repeats of the one before (see dedup.rs)
+    duplicates: AtomicU64,
+    // Transcripts sent ahead of a late response
+    late_responses: AtomicU64,
+    // From the start of a chunk's capture until it's logged
+    end_to_end: Latency,
+    // From the end of one recording to the start of the next,
+    // while not muted or in quiet hours
+    capture_gap: Latency,
+}
+
+#[derive(Default)]
+struct StageMetrics {
+    processed: AtomicU64,
+    // Chunks dropped after their last attempt
+    failed: AtomicU64,
+    retries: AtomicU64,
+    // Chunks put in the backlog instead
+    deferred: AtomicU64,
+    // Time per chunk, retries included
+    latency: Latency,
+    // Waiting in the channel in front of this stage
+    queued: AtomicUsize,
+}
+
+impl PipelineMetrics {
+    pub fn status(&self) -> PipelineStatus {
+        PipelineStatus {
+            capture: self.capture.status(),
+            screen: self.screen.status(),
+            transcribe: self.transcribe.status(),
+            respond: self.respond.status(),
+            persist: self.persist.status(),
+            bytes_captured: self.bytes_captured.load(Ordering::Relaxed),
+            batched: self.batched.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            late_responses: self.late_responses.load(Ordering::Relaxed),
+            end_to_end: self.end_to_end.stats(),
+            capture_gap: self.capture_gap.stats(),
+        }
+    }
+
+    // The latest end-to-end times, oldest first
+    pub fn recent_end_to_end_ms(&self) -> Vec<u64> {
+        self.end_to_end.recent()
+    }
+}
+
+impl StageMetrics {
+    fn status(&self) -> StageStatus {
+        let latency = self.latency.stats();
+        StageStatus {
+            processed: self.processed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            deferred: self.deferred.load(Ordering::Relaxed),
+            avg_ms: latency.avg_ms,
+            last_ms: latency.last_ms,
+            max_ms: latency.max_ms,
+            latency,
+            queued: self.queued.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PipelineStatus {
+    capture: StageStatus,
+    screen: StageStatus,
+    transcribe: StageStatus,
+    respond: StageStatus,
+    persist: StageStatus,
+    pub bytes_captured: u64,
+    // Chunks sent to Whisper together with others
+    pub batched: u64,
+    // Chunks dropped as repeats of the one before
+    pub duplicates: u64,
+    // Transcripts sent ahead of a late response
+    pub late_responses: u64,
+    pub end_to_end: LatencyStats,
+    pub capture_gap: LatencyStats,
+}
+
+impl PipelineStatus {
+    pub fn stages(&self) -> [(&'static str, &StageStatus); 5] {
+        [
+            ("capture", &self.capture),
+            ("screen", &self.screen),
+            ("transcribe", &self.transcribe),
+            ("respond", &self.respond),
+            ("persist", &self.persist),
+        ]
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct StageStatus {
+    pub processed: u64,
+    pub failed: u64,
+    pub retries: u64,
+    pub deferred: u64,
+    // Time per chunk, retries included
+    avg_ms: u64,
+    last_ms: u64,
+    max_ms: u64,
+    #[serde(skip)]
+    pub latency: LatencyStats,
+    pub queued: usize,
+}
+
+/////////////////////////////////////////////////////////////
+// record_and_process_audio
+//
+// Runs the four stages until `cancel` fires (stop, or shutdown).
+// Capture stops at once, dropping a partly recorded chunk; the
+// later stages finish the chunks already handed to them and end
+// in turn. If a stage fails for good, the stages before it notice
+// the closed channel and stop too, and the error is returned.
+//
+// One pipeline runs per recording source, using that source's
+// history and state.
+/////////////////////////////////////////////////////////////
+// Whisper's answer for a chunk uploaded while it was recorded
+type Upload = oneshot::Receiver<Result<Transcription, SttError>>;
+
+// What Whisper heard, with each word's time when captions asked
+// for them (see captions.rs)
+struct Transcription {
+    text: String,
+    words: Vec<Word>,
+    language: Option<String>,
+    segments: Vec<Segment>,
+}
+
+/////////////////////////////////////////////////////////////
+// Segment
+//
+// A stretch of a chunk as Whisper's verbose_json describes it.
+// A transcript's log record keeps them all as "segments", with
+// "language" and "confidence" (the segments' mean
+// exp(avg_logprob)), for subtitles, hallucination filtering and
+// lining speakers up with the words. Their text is redacted
+// like the transcript's, but keeps a spoken command the
+// transcript had cut out. Providers that don't send them leave
+// the record without.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub(crate) struct Segment {
+    pub id: u32,
+    // Seconds into the chunk
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    // The temperature Whisper settled on after any fallbacks
+    pub temperature: Option<f64>,
+    pub avg_logprob: Option<f64>,
+    pub compression_ratio: Option<f64>,
+    // How likely it is the segment was silence
+    pub no_speech_prob: Option<f64>,
+}
+
+// What a transcript's log record has besides its text
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub(crate) struct Details {
+    // Who said it, if speakers are labeled
+    speakers: Vec<String>,
+    // As Whisper reported them
+    language: Option<String>,
+    segments: Vec<Segment>,
+    // Logged ahead of its response (see deadline_stage)
+    response_pending: bool,
+}
+
+// `started` is when the chunk's capture began
+struct Captured {
+    chunk_id: String,
+    started: Instant,
+    audio: web::Bytes,
+    upload: Option<Upload>,
+}
+
+/////////////////////////////////////////////////////////////
+// Batch
+//
+// Adjacent chunks with less than audio.batch_speech_ms of speech
+// (see wav::speech), held back so a word here and a few there go
+// to Whisper as one request of up to audio.batch_max_secs: fewer
+// requests, and a transcript with some context rather than one
+// per fragment. A chunk with more speech, one that would make
+// the batch too long, or nothing arriving for two chunk lengths
+// (a pause, or the end of recording) sends what's held first.
+// The batch goes under its first chunk's id and start; streamed
+// uploads of its chunks aren't used.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+struct Batch {
+    chunks: Vec<Captured>,
+    duration: Duration,
+}
+
+impl Batch {
+    // What's ready for Whisper once `chunk` is added, in order
+    fn add(&mut self, chunk: Captured, max: Duration, short_speech: Duration, batched: &AtomicU64) -> Vec<Captured> {
+        let info = wav::parse(&chunk.audio).ok();
+        let duration = info.as_ref().map(|info| info.duration());
+        let short = info.as_ref().is_some_and(|info| wav::speech(&chunk.audio, info) < short_speech);
+        let mut ready = Vec::new();
+        match duration {
+            Some(duration) if short && duration < max => {
+                if self.duration + duration > max {
+                    ready.extend(self.take(batched));
+                }
+                self.duration += duration;
+                self.chunks.push(chunk);
+            }
+            _ => {
+                ready.extend(self.take(batched));
+                ready.push(chunk);
+            }
+        }
+        ready
+    }
+
+    // What's held, joined into one chunk if there's more than one
+    fn take(&mut self, batched: &AtomicU64) -> Vec<Captured> {
+        self.duration = Duration::ZERO;
+        let chunks = std::mem::take(&mut self.chunks);
+        if chunks.len() < 2 {
+            return chunks;
+        }
+        let clips: Vec<&[u8]> = chunks.iter().map(|chunk| &chunk.audio[..]).collect();
+        let Some(joined) = wav::join(&clips) else {
+            // Formats differ, e.g. the device changed in between
+            return chunks;
+        };
+        let ids: Vec<&str> = chunks.iter().map(|chunk| chunk.chunk_id.as_str()).collect();
+        tracing::info!(chunks = ?ids, "sending short chunks to Whisper together");
+        batched.fetch_add(chunks.len() as u64, Ordering::Relaxed);
+        let first = chunks.into_iter().next().expect("at least two chunks");
+        vec![Captured {
+            chunk_id: first.chunk_id,
+            started: first.started,
+            audio: joined.into(),
+            upload: None,
+        }]
+    }
+}
+
+struct Transcribed {
+    chunk_id: String,
+    started: Instant,
+    transcript: String,
+    details: Details,
+    early: Early,
+}
+
+struct Answered {
+    chunk_id: String,
+    started: Instant,
+    transcript: String,
+    details: Details,
+    gpt_response: String,
+    early: Early,
+}
+
+/////////////////////////////////////////////////////////////
+// Early
+//
+// Whether a chunk's transcript was logged ahead of its response
+// (see deadline_stage). The deadline and persist stages take
+// turns under `logged`, so it's logged once; dropping the chunk,
+// persisted or not, tells the deadline stage to stop waiting.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+struct Early {
+    // The transcript's entry_id, once it's logged
+    logged: Arc<AsyncMutex<Option<String>>>,
+    settled: CancellationToken,
+}
+
+impl Drop for Early {
+    fn drop(&mut self) {
+        self.settled.cancel();
+    }
+}
+
+// A transcript waiting on its response, for deadline_stage
+struct Pending {
+    chunk_id: String,
+    started: Instant,
+    deadline: Instant,
+    transcript: String,
+    details: Details,
+    logged: Arc<AsyncMutex<Option<String>>>,
+    settled: CancellationToken,
+}
+
+pub async fn record_and_process_audio(
+    app_data: web::Data<AppState>,
+    source: Arc<SourceSession>,
+    cancel: CancellationToken,
+) -> Result<(), PipelineError> {
+    let (captured_tx, captured_rx) = mpsc::channel(STAGE_QUEUE);
+    let (screened_tx, screened_rx) = mpsc::channel(STAGE_QUEUE);
+    let (transcribed_tx, transcribed_rx) = mpsc::channel(STAGE_QUEUE);
+    let (answered_tx, answered_rx) = mpsc::channel(STAGE_QUEUE);
+    // Never full, so a late GPT can't hold up Whisper
+    let (pending_tx, pending_rx) = mpsc::unbounded_channel();
+
+    let results = tokio::join!(
+        capture_stage(&app_data, &source, &cancel, captured_tx),
+        screen_stage(&app_data, &source, captured_rx, screened_tx),
+        transcribe_stage(&app_data, &source, screened_rx, transcribed_tx, pending_tx),
+        respond_stage(&app_data, &source, transcribed_rx, answered_tx),
+        persist_stage(&app_data, &source, answered_rx),
+        deadline_stage(&app_data, &source, pending_rx),
+    );
+
+    tracing::info!("done with continuous chunk loop");
+    results.0?;
+    results.1?;
+    results.2?;
+    results.3?;
+    results.4
+}
+
+async fn capture_stage(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    cancel: &CancellationToken,
+    next: mpsc::Sender<Captured>,
+) -> Result<(), PipelineError> {
+    let metrics = &source.pipeline.capture;
+    let mut failures = 0;
+    // When the last recording ended; None after a pause
+    let mut mic_idle_since: Option<Instant> = None;
+    // The mic that runs on while chunks are cut at pauses
+    let listening = AsyncMutex::new(None);
+    // Refused if a stop already came in
+    let _ = source.transition(app_data, RecordingState::Recording).await;
+    while !cancel.is_cancelled() {
+        // Muted: nothing is recorded until it's unmuted
+        let muted = *source.muted.borrow();
+        if muted {
+            tracing::info!("muted, capture paused");
+            // Not even kept running
+            *listening.lock().await = None;
+            let mut unmuted = source.muted.subscribe();
+            tokio::select! {
+                _ = unmuted.wait_for(|muted| !muted) => tracing::info!("unmuted"),
+                _ = cancel.cancelled() => break,
+            }
+            mic_idle_since = None;
+            continue;
+        }
+        // Quiet hours: the same, until they end or an admin overrides them
+        if let Some(until) = quiet::in_force(app_data).await {
+            tracing::info!(until = %until.format("%H:%M"), "quiet hours, capture paused");
+            *listening.lock().await = None;
+            tokio::select! {
+                _ = quiet::pause(app_data, until) => {}
+                _ = cancel.cancelled() => break,
+            }
+            mic_idle_since = None;
+            continue;
+        }
+        let (chunk_secs, speech) = {
+            let audio = &app_data.config.read().await.audio;
+            (source.overrides().chunk_secs(audio), audio.chunking == "speech")
+        };
+        session_settings::note(app_data, source).await;
+        if !speech {
+            *listening.lock().await = None;
+        }
+        let chunk_id = logging::new_id();
+        let started = Instant::now();
+        if let Some(idle_since) = mic_idle_since {
+            source.pipeline.capture_gap.record(started - idle_since);
+        }
+        let span = tracing::info_span!("chunk", chunk_id = %chunk_id, stage = "capture");
+        let listening = &listening;
+        let capturing = attempt(metrics, || async move {
+            if speech {
+                segment(app_data, source, listening).await
+            } else {
+                capture(app_data, source, ChunkAudio::Record(chunk_secs)).await
+            }
+        })
+        .instrument(span);
+        // Dropping the capture kills the mic command
+        let result = tokio::select! {
+            result = capturing => result,
+            _ = cancel.cancelled() => break,
+        };
+        // A mic that never stops is never idle
+        mic_idle_since = (!speech).then(Instant::now);
+        match result {
+            Ok((audio, upload)) => {
+                failures = 0;
+                source.pipeline.bytes_captured.fetch_add(audio.len() as u64, Ordering::Relaxed);
+                if !hand_off(&next, Captured { chunk_id, started, audio, upload }, &source.pipeline.screen).await {
+                    break;
+                }
+            }
+            Err(e) => skip(source, &mut failures, e).await?,
+        }
+    }
+    tracing::info!("capture stopped");
+    // Only after a stop; on failure the source goes straight to error
+    let _ = source.transition(app_data, RecordingState::Processing).await;
+    Ok(())
+}
+
+async fn screen_stage(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    mut chunks: mpsc::Receiver<Captured>,
+    next: mpsc::Sender<Captured>,
+) -> Result<(), PipelineError> {
+    let metrics = &source.pipeline.screen;
+    let mut failures = 0;
+    while let Some(Captured { chunk_id, started, audio, upload }) = take(&mut chunks, metrics).await {
+        let span = tracing::info_span!("chunk", chunk_id = %chunk_id, stage = "screen");
+        // Opt-out may silence parts of it, so each attempt starts from the recording
+        match attempt(metrics, || screen(app_data, source, &chunk_id, audio.clone())).instrument(span).await {
+            Ok(audio) => {
+                failures = 0;
+                if !hand_off(&next, Captured { chunk_id, started, audio, upload }, &source.pipeline.transcribe).await {
+                    break;
+                }
+            }
+            // Discarded on purpose, not dropped
+            Err(PipelineError::Audio(AudioError::OptedOut { .. })) => {
+                metrics.failed.fetch_sub(1, Ordering::Relaxed);
+                failures = 0;
+            }
+            Err(e) => skip(source, &mut failures, e).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn transcribe_stage(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    mut chunks: mpsc::Receiver<Captured>,
+    next: mpsc::Sender<Transcribed>,
+    pending: mpsc::UnboundedSender<Pending>,
+) -> Result<(), PipelineError> {
+    let metrics = &source.pipeline.transcribe;
+    let mut failures = 0;
+    let mut batch = Batch::default();
+    loop {
+        let (chunk_secs, max, short_speech) = {
+            let audio = &app_data.config.read().await.audio;
+            // A scripted chunk's transcript is in its upload, which a batch drops
+            let scripted = audio.input(&source.name).is_some_and(|input| recorder::is_script(&input));
+            let max = if scripted { Duration::ZERO } else { Duration::from_secs(audio.batch_max_secs.into()) };
+            (source.overrides().chunk_secs(audio), max, Duration::from_millis(audio.batch_speech_ms.into()))
+        };
+        let ready = if batch.chunks.is_empty() {
+            match take(&mut chunks, metrics).await {
+                Some(chunk) => batch.add(chunk, max, short_speech, &source.pipeline.batched),
+                None => break,
+            }
+        } else {
+            let wait = Duration::from_secs(chunk_secs.into()) * 2;
+            match tokio::time::timeout(wait, take(&mut chunks, metrics)).await {
+                Ok(Some(chunk)) => batch.add(chunk, max, short_speech, &source.pipeline.batched),
+                Ok(None) => break,
+                Err(_) => batch.take(&source.pipeline.batched),
+            }
+        };
+        for chunk in ready {
+            if !transcribe_chunk(app_data, source, &mut failures, chunk, &next, &pending).await? {
+                return Ok(());
+            }
+        }
+    }
+    for chunk in batch.take(&source.pipeline.batched) {
+        if !transcribe_chunk(app_data, source, &mut failures, chunk, &next, &pending).await? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// False once the next stage has stopped
+async fn transcribe_chunk(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    failures: &mut u32,
+    chunk: Captured,
+    next: &mpsc::Sender<Transcribed>,
+    pending: &mpsc::UnboundedSender<Pending>,
+) -> Result<bool, PipelineError> {
+    let metrics = &source.pipeline.transcribe;
+    let Captured { chunk_id, started, audio, mut upload } = chunk;
+    let span = tracing::info_span!("chunk", chunk_id = %chunk_id, stage = "transcribe");
+    // A retry sends the chunk the usual way
+    let overrides = source.overrides();
+    match attempt(metrics, || transcribe(app_data, &overrides, &audio, upload.take())).instrument(span).await {
+        Ok(Transcription { text: transcript, words, language, segments }) => {
+            *failures = 0;
+            // Spoken commands stop here
+            let Some(transcript) = voice::intercept(app_data, source, transcript).await else {
+                return Ok(true);
+            };
+            let Some(transcript) = dedup::filter(app_data, source, transcript).await else {
+                source.pipeline.duplicates.fetch_add(1, Ordering::Relaxed);
+                return Ok(true);
+            };
+            let duration = wav::parse(&audio).map(|info| info.duration().as_secs_f64()).unwrap_or_default();
+            captions::publish(app_data, source, &chunk_id, &transcript, &words, duration).await;
+            let speakers = speakers::label(app_data, source, &audio).await;
+            let details = Details { speakers, language, segments, response_pending: false };
+            let early = Early::default();
+            let deadline_secs = app_data.config.read().await.audio.deadline_secs;
+            if deadline_secs > 0 {
+                // Counted from when the chunk was recorded
+                let recorded = started + Duration::from_secs_f64(duration);
+                let _ = pending.send(Pending {
+                    chunk_id: chunk_id.clone(),
+                    started,
+                    deadline: recorded + Duration::from_secs(deadline_secs.into()),
+                    transcript: transcript.clone(),
+                    details: Details { response_pending: true, ..details.clone() },
+                    logged: early.logged.clone(),
+                    settled: early.settled.clone(),
+                });
+            }
+            let transcribed = Transcribed { chunk_id, started, transcript, details, early };
+            return Ok(hand_off(next, transcribed, &source.pipeline.respond).await);
+        }
+        Err(e) if e.is_offline() && app_data.config.read().await.backlog.enabled => {
+            tracing::warn!(error = %e.report(), chunk_id = %chunk_id, "OpenAI unavailable, keeping chunk for later");
+            backlog::defer(app_data, source, &chunk_id, recorded_at(started), &audio).await?;
+            metrics.failed.fetch_sub(1, Ordering::Relaxed);
+            metrics.deferred.fetch_add(1, Ordering::Relaxed);
+            *failures = 0;
+        }
+        Err(e) => skip(source, failures, e).await?,
+    }
+    Ok(true)
+}
+
+async fn respond_stage(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    mut chunks: mpsc::Receiver<Transcribed>,
+    next: mpsc::Sender<Answered>,
+) -> Result<(), PipelineError> {
+    let metrics = &source.pipeline.respond;
+    let mut failures = 0;
+    while let Some(Transcribed { chunk_id, started, transcript, details, early }) = take(&mut chunks, metrics).await {
+        if forget::dropped(source, started) {
+            tracing::info!(chunk_id = %chunk_id, "dropped a forgotten chunk");
+            continue;
+        }
+        let span = tracing::info_span!("chunk", chunk_id = %chunk_id, stage = "respond");
+        match attempt(metrics, || respond(app_data, source, &transcript)).instrument(span).await {
+            Ok(gpt_response) => {
+                failures = 0;
+                let answered = Answered {
+                    chunk_id,
+                    started,
+                    transcript,
+                    details,
+                    gpt_response,
+                    early,
+                };
+                if !hand_off(&next, answered, &source.pipeline.persist).await {
+                    break;
+                }
+            }
+            Err(e) if e.is_offline() && app_data.config.read().await.backlog.enabled => {
+                tracing::warn!(error = %e.report(), chunk_id = %chunk_id, "GPT unavailable, keeping the transcript for later");
+                // The deadline stage gives up once it gets the lock
+                let logged = early.logged.lock().await.clone();
+                early.settled.cancel();
+                let unanswered = backlog::Unanswered { text: transcript, details, logged };
+                backlog::defer_transcript(app_data, source, &chunk_id, recorded_at(started), unanswered).await?;
+                metrics.failed.fetch_sub(1, Ordering::Relaxed);
+                metrics.deferred.fetch_add(1, Ordering::Relaxed);
+                failures = 0;
+            }
+            Err(e) => skip(source, &mut failures, e).await?,
+        }
+    }
+    Ok(())
+}
+
+// When the chunk that began at `started` was recorded
+fn recorded_at(started: Instant) -> chrono::DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(started.elapsed()).unwrap_or_default()
+}
+
+async fn persist_stage(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    mut chunks: mpsc::Receiver<Answered>,
+) -> Result<(), PipelineError> {
+    let metrics = &source.pipeline.persist;
+    let mut failures = 0;
+    while let Some(chunk) = take(&mut chunks, metrics).await {
+        if forget::dropped(source, chunk.started) {
+            tracing::info!(chunk_id = %chunk.chunk_id, "dropped a forgotten chunk");
+            continue;
+        }
+        let span = tracing::info_span!("chunk", chunk_id = %chunk.chunk_id, stage = "persist");
+        let session_id = source.session_id.borrow().clone();
+        // The deadline stage gives up once it gets the lock
+        let logged = chunk.early.logged.lock().await;
+        chunk.early.settled.cancel();
+        let result = attempt(metrics, || {
+            let (transcript, details) = (&chunk.transcript, &chunk.details);
+            persist(app_data, source, &chunk.chunk_id, session_id.as_deref(), transcript, details, &chunk.gpt_response, logged.as_deref())
+        })
+        .instrument(span)
+        .await;
+        match result {
+            Ok(()) => {
+                failures = 0;
+                source.pipeline.end_to_end.record(chunk.started.elapsed());
+            }
+            Err(e) => skip(source, &mut failures, e).await?,
+        }
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// deadline_stage
+//
+// Logs and sends out, with "response_pending": true, each
+// transcript whose response isn't persisted by its deadline.
+// The response follows as usual when GPT answers, and persist
+// sends an "entry_updated" notice naming the transcript's entry
+// with it. Chunks arrive in the order they were recorded, so
+// their deadlines do too. A transcript that can't be logged here
+// is left to persist.
+/////////////////////////////////////////////////////////////
+async fn deadline_stage(app_data: &web::Data<AppState>, source: &SourceSession, mut chunks: mpsc::UnboundedReceiver<Pending>) {
+    while let Some(chunk) = chunks.recv().await {
+        tokio::select! {
+            _ = tokio::time::sleep_until(chunk.deadline.into()) => {}
+            _ = chunk.settled.cancelled() => continue,
+        }
+        let mut logged = chunk.logged.lock().await;
+        if chunk.settled.is_cancelled() || forget::dropped(source, chunk.started) {
+            continue;
+        }
+        let span = tracing::info_span!("chunk", chunk_id = %chunk.chunk_id, stage = "deadline");
+        let session_id = source.session_id.borrow().clone();
+        let result = append_to_json_log("Microphone", &chunk.transcript, Some(&chunk.details), app_data, source, &chunk.chunk_id, session_id.as_deref())
+            .instrument(span)
+            .await;
+        match result {
+            Ok(entry_id) => {
+                tracing::info!(chunk_id = %chunk.chunk_id, "GPT is late, sent the transcript ahead");
+                source.pipeline.late_responses.fetch_add(1, Ordering::Relaxed);
+                *logged = Some(entry_id);
+            }
+            Err(e) => tracing::warn!(chunk_id = %chunk.chunk_id, error = %format!("{e:#}"), "couldn't send the transcript ahead"),
+        }
+    }
+}
+
+// One stage's work on one chunk, retrying retryable failures
+async fn attempt<T, F, Fut>(metrics: &StageMetrics, mut work: F) -> Result<T, PipelineError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, PipelineError>>,
+{
+    let started = Instant::now();
+    let mut attempts = 1;
+    let result = loop {
+        match work().await {
+            Err(e) if e.is_retryable() && attempts < STAGE_ATTEMPTS => {
+                tracing::warn!(error = %e.report(), attempts, "stage failed, retrying");
+                metrics.retries.fetch_add(1, Ordering::Relaxed);
+                attempts += 1;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            result => break result,
+        }
+    };
+    metrics.latency.record(started.elapsed());
+    let counter = if result.is_ok() { &metrics.processed } else { &metrics.failed };
+    counter.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+// Drops a chunk that failed with a retryable error; anything
+// else, or too many in a row, ends the stage
+async fn skip(source: &SourceSession, failures: &mut u32, e: PipelineError) -> Result<(), PipelineError> {
+    *failures += 1;
+    if !e.is_retryable() || *failures >= MAX_CONSECUTIVE_FAILURES {
+        return Err(e);
+    }
+    let message = e.report();
+    tracing::warn!(error = %message, failures = *failures, "dropped chunk");
+    *source.last_error.lock().await = Some(message);
+    Ok(())
+}
+
+// False once the next stage has stopped
+async fn hand_off<T>(next: &mpsc::Sender<T>, item: T, next_metrics: &StageMetrics) -> bool {
+    next_metrics.queued.fetch_add(1, Ordering::Relaxed);
+    let sent = next.send(item).await.is_ok();
+    if !sent {
+        next_metrics.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+    sent
+}
+
+async fn take<T>(chunks: &mut mpsc::Receiver<T>, metrics: &StageMetrics) -> Option<T> {
+    let item = chunks.recv().await;
+    if item.is_some() {
+        metrics.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+    item
+}
+
+/////////////////////////////////////////////////////////////
+// process_chunk
+//
+// One chunk through every stage in turn, for record_once and
+// audio a gRPC client sent. chunk_id tags the log records so
+// the chunk can be traced.
+/////////////////////////////////////////////////////////////
+pub(crate) enum ChunkAudio {
+    // Capture this many seconds from the source's mic
+    Record(u32),
+    // A WAV file received from a client
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    Provided(web::Bytes),
+}
+
+pub(crate) async fn process_chunk(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    audio: ChunkAudio,
+    chunk_id: &str,
+) -> Result<TranscriptResponse, PipelineError> {
+    let (audio_data, upload) = capture(app_data, source, audio).await?;
+    let audio_data = screen(app_data, source, chunk_id, audio_data).await?;
+    let Transcription { text: transcript, language, segments, .. } = transcribe(app_data, &source.overrides(), &audio_data, upload).await?;
+    let speakers = speakers::label(app_data, source, &audio_data).await;
+    let details = Details { speakers, language, segments, response_pending: false };
+    let gpt_response = respond(app_data, source, &transcript).await?;
+    let session_id = source.session_id.borrow().clone();
+    persist(app_data, source, chunk_id, session_id.as_deref(), &transcript, &details, &gpt_response, None).await?;
+    Ok(TranscriptResponse {
+        transcript,
+        gpt_response,
+    })
+}
+
+// A chunk from the backlog, logged under the session it was
+// recorded in
+pub(crate) async fn process_deferred(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    chunk_id: &str,
+    session_id: Option<&str>,
+    audio_data: &web::Bytes,
+) -> Result<(), PipelineError> {
+    let Transcription { text: transcript, language, segments, .. } = transcribe(app_data, &source.overrides(), audio_data, None).await?;
+    let speakers = speakers::label(app_data, source, audio_data).await;
+    let details = Details { speakers, language, segments, response_pending: false };
+    answer_deferred(app_data, source, chunk_id, session_id, &transcript, &details, None).await
+}
+
+// A transcript from the backlog; `logged` as for persist
+pub(crate) async fn answer_deferred(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    chunk_id: &str,
+    session_id: Option<&str>,
+    transcript: &str,
+    details: &Details,
+    logged: Option<&str>,
+) -> Result<(), PipelineError> {
+    let gpt_response = respond(app_data, source, transcript).await?;
+    persist(app_data, source, chunk_id, session_id, transcript, details, &gpt_response, logged).await
+}
+
+/////////////////////////////////////////////////////////////
+// Stages
+//
+// Each takes a fresh config snapshot so reloaded settings apply
+// from the next chunk.
+/////////////////////////////////////////////////////////////
+async fn capture(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    audio: ChunkAudio,
+) -> Result<(web::Bytes, Option<Upload>), PipelineError> {
+    let captured = match audio {
+        ChunkAudio::Record(chunk_secs) => {
+            let (input, openai, provider, words) = {
+                let config = app_data.config.read().await;
+                let input =
+                    config.audio.input(&source.name).ok_or_else(|| AudioError::SourceRemoved(source.name.clone()))?;
+                let overrides = source.overrides();
+                // Only the first: the others are for when it fails
+                let provider = routing::providers(&config, Category::Audio)
+                    .ok()
+                    .and_then(|providers| providers.into_iter().next())
+                    .filter(|provider| provider.stream_audio)
+                    .map(|provider| overrides.audio(provider));
+                (input, overrides.openai(&config.openai), provider, config.captions.enabled)
+            };
+            tracing::info!(chunk_secs, "capture started");
+            let (audio_data, upload) = match input.backend.as_str() {
+                // Sent by the node (see hub.rs)
+                "node" => (hub::next_chunk(app_data, &source.name, chunk_secs).await?, None),
+                _ => {
+                    let mic = source.recorder(&input).await;
+                    match mic.scripted().await {
+                        // A script's line is the transcript; Whisper is skipped
+                        Some(line) => {
+                            let (audio_data, transcript) = line?;
+                            let (answer, upload) = oneshot::channel();
+                            let _ = answer.send(Ok(Transcription {
+                                text: transcript,
+                                words: Vec::new(),
+                                language: None,
+                                segments: Vec::new(),
+                            }));
+                            (audio_data, Some(upload))
+                        }
+                        None => {
+                            let audio = mic.record_stream(chunk_secs).await?;
+                            match provider {
+                                Some(provider) if !optout::active(app_data).await => {
+                                    let (audio_data, upload) =
+                                        record_and_upload(app_data, audio, openai, provider, chunk_secs, words).await?;
+                                    (audio_data, Some(upload))
+                                }
+                                _ => (recorder::collect(audio).await?, None),
+                            }
+                        }
+                    }
+                }
+            };
+            // Checked here rather than left to Whisper's vaguer 400
+            let info = wav::parse(&audio_data).map_err(AudioError::InvalidWav)?;
+            tracing::info!(
+                bytes = audio_data.len(),
+                duration_ms = info.duration().as_millis() as u64,
+                sample_rate = info.sample_rate,
+                channels = info.channels,
+                "capture finished"
+            );
+            (audio_data, upload)
+        }
+        ChunkAudio::Provided(audio_data) => {
+            tracing::info!(bytes = audio_data.len(), "received audio");
+            (audio_data, None)
+        }
+    };
+    Ok(captured)
+}
+
+/////////////////////////////////////////////////////////////
+// segment
+//
+// The capture stage's chunk with audio.chunking = "speech": the
+// next stretch of speech from the source's running mic, started
+// on first use, after a failure, and when the source's input
+// changes (see segmenter.rs). Nodes send fixed chunks anyway,
+// and scripts are cut per line.
+/////////////////////////////////////////////////////////////
+async fn segment(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    listening: &AsyncMutex<Option<(MicInput, Listening)>>,
+) -> Result<(web::Bytes, Option<Upload>), PipelineError> {
+    let (input, settings, chunk_secs) = {
+        let config = app_data.config.read().await;
+        let input = config.audio.input(&source.name).ok_or_else(|| AudioError::SourceRemoved(source.name.clone()))?;
+        (input, segmenter::Settings::new(&config.audio), source.overrides().chunk_secs(&config.audio))
+    };
+    if input.backend == "node" || recorder::is_script(&input) {
+        return capture(app_data, source, ChunkAudio::Record(chunk_secs)).await;
+    }
+    let mut listening = listening.lock().await;
+    if listening.as_ref().is_none_or(|(listening_to, _)| *listening_to != input) {
+        tracing::info!(pause_ms = settings.pause.as_millis() as u64, "listening for speech");
+        let audio = source.recorder(&input).await.listen().await?;
+        *listening = Some((input, Listening::new(audio)));
+    }
+    let (_, running) = listening.as_mut().expect("started above");
+    let audio_data = match running.next_chunk(settings).await {
+        Ok(audio_data) => audio_data,
+        Err(e) => {
+            *listening = None;
+            return Err(e.into());
+        }
+    };
+    let info = wav::parse(&audio_data).map_err(AudioError::InvalidWav)?;
+    tracing::info!(
+        bytes = audio_data.len(),
+        duration_ms = info.duration().as_millis() as u64,
+        "capture finished"
+    );
+    Ok((audio_data, None))
+}
+
+/////////////////////////////////////////////////////////////
+// record_and_upload
+//
+// Records the chunk while a background task streams each piece
+// on to Whisper. The upload is given up if its answer isn't
+// wanted any more (the chunk was dropped, or recording stopped).
+/////////////////////////////////////////////////////////////
+async fn record_and_upload(
+    app_data: &web::Data<AppState>,
+    mut audio: AudioStream,
+    openai: config::OpenAiConfig,
+    provider: Provider,
+    chunk_secs: u32,
+    // Word timestamps, for captions
+    words: bool,
+) -> Result<(web::Bytes, Upload), AudioError> {
+    let (body, pieces) = mpsc::unbounded_channel();
+    let (answer, upload) = oneshot::channel();
+    let uploader = app_data.clone();
+    app_data.tasks.spawn("upload", async move {
+        let mut answer = answer;
+        let client = &uploader.openai_client;
+        let uploading =
+            stream_to_whisper(pieces, chunk_secs, words, &openai, &provider, client, &uploader.openai_limiter);
+        let result = tokio::select! {
+            result = uploading => Some(result),
+            _ = answer.closed() => None,
+        };
+        if let Some(result) = result {
+            let _ = answer.send(result);
+        }
+    });
+
+    let mut audio_data = Vec::new();
+    while let Some(bytes) = audio.next().await {
+        match bytes {
+            Ok(bytes) => {
+                audio_data.extend_from_slice(&bytes);
+                // The upload may have failed already; the recording goes on
+                let _ = body.send(Ok(bytes));
+            }
+            Err(e) => {
+                // Not a whole chunk, so Whisper mustn't get it as one
+                let _ = body.send(Err(std::io::Error::other("recording failed")));
+                return Err(e);
+            }
+        }
+    }
+    Ok((audio_data.into(), upload))
+}
+
+async fn screen(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    chunk_id: &str,
+    audio_data: web::Bytes,
+) -> Result<web::Bytes, PipelineError> {
+    // Before it's kept anywhere
+    let audio_data = optout::screen(app_data, audio_data).await?;
+
+    let save_dir = app_data.config.read().await.audio.save_dir.clone();
+    if let Some(dir) = save_dir {
+        // Short of space the chunk goes on, unsaved (see disk.rs)
+        if app_data.disk.archiving_paused() {
+            tracing::debug!(chunk_id, "disk space low, chunk not saved");
+            app_data.disk.skipped();
+        } else {
+            match save_audio(&dir, source, chunk_id, &audio_data).await {
+                Err(StorageError::SaveAudio { source: e, .. }) if disk::is_full(&e) => app_data.disk.out_of_space(),
+                result => result?,
+            }
+        }
+    }
+    Ok(audio_data)
+}
+
+/////////////////////////////////////////////////////////////
+// save_audio
+//
+// The "save to disk" mode: <save_dir>/<source>/<session or
+// "once">-<chunk_id>.wav, so a chunk's file can be found from
+// its conversation log record.
+/////////////////////////////////////////////////////////////
+async fn save_audio(dir: &str, source: &SourceSession, chunk_id: &str, audio_data: &[u8]) -> Result<(), StorageError> {
+    let session = source.session_id.borrow().clone().unwrap_or_else(|| "once".to_string());
+    let dir = std::path::Path::new(dir).join(&source.name);
+    let path = dir.join(format!("{session}-{chunk_id}.wav"));
+    let failed = |e| StorageError::SaveAudio { path: path.display().to_string(), source: e };
+
+    tokio::fs::create_dir_all(&dir).await.map_err(failed)?;
+    tokio::fs::write(&path, audio_data).await.map_err(failed)?;
+    tracing::debug!(path = %path.display(), "saved audio chunk");
+    Ok(())
+}
+
+async fn transcribe(
+    app_data: &web::Data<AppState>,
+    // The session's (see session_settings.rs)
+    overrides: &Overrides,
+    audio_data: &web::Bytes,
+    // What Whisper made of the chunk uploaded as it was recorded
+    upload: Option<Upload>,
+) -> Result<Transcription, PipelineError> {
+    let (openai, redaction, providers, word_times) = {
+        let config = app_data.config.read().await;
+        let providers = routing::providers(&config, Category::Audio).map_err(|e| SttError::OpenAi(e.into()))?;
+        let providers: Vec<Provider> = providers.into_iter().map(|provider| overrides.audio(provider)).collect();
+        (overrides.openai(&config.openai), config.redaction.clone(), providers, config.captions.enabled)
+    };
+    let Transcription { text: transcript, words, language, mut segments } = match uploaded(upload).await {
+        Some(transcript) => transcript,
+        None => {
+            let mut providers = providers.iter().peekable();
+            loop {
+                let provider = providers.next().expect("routing::providers is never empty");
+                let client = &app_data.openai_client;
+                match transcribe_audio_with_whisper(audio_data, word_times, &openai, provider, client, &app_data.openai_limiter).await {
+                    Err(SttError::OpenAi(e)) if providers.peek().is_some() && try_next_provider(&e) => {
+                        tracing::warn!(provider = %provider.name, error = %e, "trying the next provider for audio");
+                    }
+                    result => break result?,
+                }
+            }
+        }
+    };
+    // Before anything else sees it
+    let transcript = redact::transcript(&redaction, transcript).await;
+    for segment in &mut segments {
+        segment.text = redact::transcript(&redaction, std::mem::take(&mut segment.text)).await;
+    }
+    tracing::info!(transcript = %transcript, "transcribed");
+    Ok(Transcription { text: transcript, words, language, segments })
+}
+
+// The streamed upload's transcript; None if there was none, or
+// it failed and the chunk has to be sent again
+async fn uploaded(upload: Option<Upload>) -> Option<Transcription> {
+    match upload?.await {
+        Ok(Ok(transcript)) => Some(transcript),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %PipelineError::from(e).report(), "streamed upload failed, sending the chunk again");
+            None
+        }
+        // The upload task ended without an answer
+        Err(_) => None,
+    }
+}
+
+// The exchange joins the history only once GPT has answered, so
+// a retried or dropped chunk leaves no half of it behind
+async fn respond(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    transcript: &str,
+) -> Result<String, PipelineError> {
+    let gpt_response = summarize_with_gpt(app_data, source, transcript).await?;
+    tracing::info!(response = %gpt_response, "GPT responded");
+
+    memory::remember(app_data, source, transcript, &gpt_response).await;
+    Ok(gpt_response)
+}
+
+// `logged` is the transcript's entry_id if it went ahead of the
+// response (see deadline_stage)
+#[allow(clippy::too_many_arguments)]
+async fn persist(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    chunk_id: &str,
+    session_id: Option<&str>,
+    transcript: &str,
+    details: &Details,
+    gpt_response: &str,
+    logged: Option<&str>,
+) -> Result<(), PipelineError> {
+    if logged.is_none() {
+        append_to_json_log("Microphone", transcript, Some(details), app_data, source, chunk_id, session_id).await?;
+    }
+    let response_id = append_to_json_log("OPENAI RESPONSE", gpt_response, None, app_data, source, chunk_id, session_id).await?;
+    if let Some(entry_id) = logged {
+        // For displays showing the transcript as pending
+        if outputs::sinks(app_data, "response").await.iter().any(|sink| sink == "sse") {
+            let update = serde_json::json!({
+                "entry_id": entry_id,
+                "audio_source": source.name,
+                "session_id": session_id,
+                "chunk_id": chunk_id,
+                "response_pending": false,
+                "response": {"entry_id": response_id, "text": gpt_response},
+            })
+            .to_string();
+            app_data.events.publish_notice("entry_updated", update.clone());
+            source.events.publish_notice("entry_updated", update);
+        }
+    }
+
+    // Before `latest` moves on, so the alert can quote what came before
+    let previous = source.latest.borrow().transcript.clone();
+    notify::check_transcript(app_data, &source.name, session_id, &previous, transcript).await;
+    discord::response(app_data, &source.name, gpt_response).await;
+
+    source.chunks_processed.fetch_add(1, Ordering::Relaxed);
+    app_data.chunks_processed.fetch_add(1, Ordering::Relaxed);
+
+    // Update shared state so /transcript endpoint shows the latest
+    source.latest.send_replace(TranscriptResponse {
+        transcript: transcript.to_string(),
+        gpt_response: gpt_response.to_string(),
+    });
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// transcribe_audio_with_whisper
+//
+// Sends the captured audio bytes to a Whisper API (OpenAI's, or
+// the provider routing.audio picked)
+/////////////////////////////////////////////////////////////
+async fn transcribe_audio_with_whisper(
+    audio_data: &web::Bytes,
+    // Word timestamps, for captions
+    words: bool,
+    openai: &config::OpenAiConfig,
+    provider: &Provider,
+    client: &reqwest::Client,
+    limiter: &openai_limit::OpenAiLimiter,
+) -> Result<Transcription, SttError> {
+    tracing::debug!(bytes = audio_data.len(), provider = %provider.name, model = %provider.model, "sending audio to Whisper");
+    // Shares the buffer rather than copying it
+    let length = audio_data.len() as u64;
+    let file = reqwest::multipart::Part::stream_with_length(audio_data.clone(), length);
+    send_to_whisper(file, Duration::ZERO, words, openai, provider, client, limiter).await
+}
+
+// The same, with the audio sent as it's recorded, in a chunked
+// body; the request may take chunk_secs longer
+async fn stream_to_whisper(
+    pieces: mpsc::UnboundedReceiver<std::io::Result<web::Bytes>>,
+    chunk_secs: u32,
+    words: bool,
+    openai: &config::OpenAiConfig,
+    provider: &Provider,
+    client: &reqwest::Client,
+    limiter: &openai_limit::OpenAiLimiter,
+) -> Result<Transcription, SttError> {
+    tracing::debug!(provider = %provider.name, model = %provider.model, "streaming audio to Whisper");
+    let body = reqwest::Body::wrap_stream(UnboundedReceiverStream::new(pieces));
+    let file = reqwest::multipart::Part::stream(body);
+    send_to_whisper(file, Duration::from_secs(chunk_secs.into()), words, openai, provider, client, limiter).await
+}
+
+async fn send_to_whisper(
+    file: reqwest::multipart::Part,
+    // On top of openai.timeout_secs
+    extra_time: Duration,
+    words: bool,
+    openai: &config::OpenAiConfig,
+    provider: &Provider,
+    client: &reqwest::Client,
+    limiter: &openai_limit::OpenAiLimiter,
+) -> Result<Transcription, SttError> {
+    if openai.api_key.is_empty() && provider.is_built_in() {
+        return Err(OpenAiError::NotConfigured.into());
+    }
+    let mut form = reqwest::multipart::Form::new()
+        .part("file",
+              file.file_name("audio.wav")
+                  .mime_str("audio/wav")
+                  .map_err(SttError::Upload)?)
+        .text("model", provider.model.clone());
+    // For the segments (see Segment)
+    form = form.text("response_format", "verbose_json");
+    if !openai.language.is_empty() {
+        form = form.text("language", openai.language.clone());
+    }
+    if words {
+        // Either alone leaves the other out
+        form = form
+            .text("timestamp_granularities[]", "segment")
+            .text("timestamp_granularities[]", "word");
+    }
+
+    let _slot = limiter.acquire("whisper").await?;
+    let resp = provider
+        .authorize(client.post(provider.url("audio/transcriptions")))
+        .multipart(form)
+        .timeout(Duration::from_secs(openai.timeout_secs) + extra_time)
+        .send()
+        .await
+        .map_err(|e| openai_failure(openai, e, OpenAiError::Unreachable))?;
+    let resp = check_openai_status(resp).await?;
+
+    let json_resp: serde_json::Value = resp.json().await
+        .map_err(|e| openai_failure(openai, e, OpenAiError::InvalidResponse))?;
+    let transcript = json_resp["text"]
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+    // Not the text itself: it's logged once redacted (see transcribe)
+    tracing::debug!(chars = transcript.chars().count(), "Whisper API response");
+    sentry::note_transcript(&transcript);
+    // None unless asked for, and some providers never send them
+    let words = serde_json::from_value(json_resp["words"].clone()).unwrap_or_default();
+    let language = json_resp["language"].as_str().map(str::to_string);
+    let segments = serde_json::from_value(json_resp["segments"].clone()).unwrap_or_default();
+
+    Ok(Transcription { text: transcript, words, language, segments })
+}
+
+/////////////////////////////////////////////////////////////
+// summarize_with_gpt
+//
+// We now build a "chat" array with:
+// - system message
+// - the summary of older turns, if any (see memory.rs)
+// - up to history_messages user/assistant messages from the
+//   source's conversation_history
+// - the new user chunk
+//
+// Then call GPT with the configured chat model ("gpt-4o"
+// by default).
+/////////////////////////////////////////////////////////////
+async fn summarize_with_gpt(
+    app_data: &web::Data<AppState>,
+    source: &SourceSession,
+    latest_chunk: &str
+) -> Result<String, LlmError> {
+    let (openai, workspace) = {
+        let config = app_data.config.read().await;
+        (workspaces::overrides(&config, source).openai(&config.openai), workspaces::of_source(&config, &source.name))
+    };
+
+    // The persona's prompt, if one is switched on
+    let system_prompt = openai.personas.get(&openai.persona).unwrap_or(&openai.system_prompt);
+
+    // Gather the recent messages
+    let history = source.conversation_history.lock().await.clone();
+
+    // We'll build a messages array for ChatCompletion
+    let mut messages = Vec::new();
+    // Start with system
+    messages.push(serde_json::json!({
+        "role": "system",
+        "content": system_prompt
+    }));
+    if let Some(summary) = source.history_summary.lock().await.as_deref() {
+        messages.push(serde_json::json!({
+            "role": "system",
+            "content": format!("Earlier in this conversation: {summary}")
+        }));
+    }
+    if let Some(feedback) = feedback::prompt(app_data, &workspace, &openai.persona).await {
+        messages.push(serde_json::json!({
+            "role": "system",
+            "content": feedback
+        }));
+    }
+
+    // Add the last history_messages from conversation_history
+    // Each item is ("user"|"assistant", content)
+    let start_idx = history.len().saturating_sub(openai.history_messages);
+    for (role, content) in &history[start_idx..] {
+        let r = if role == "assistant" { "assistant" } else { "user" };
+        messages.push(serde_json::json!({
+            "role": r,
+            "content": content
+        }));
+    }
+
+    // Finally add the new chunk as a user message
+    messages.push(serde_json::json!({
+        "role": "user",
+        "content": latest_chunk
+    }));
+
+    chat_with_tools(app_data, &openai, source, messages).await
+}
+
+// GPT's way of saying it has nothing to add
+pub(crate) fn is_listening(text: &str) -> bool {
+    let text = text.trim().trim_end_matches(['.', '…']);
+    text.is_empty() || text.eq_ignore_ascii_case("listening")
+}
+
+/////////////////////////////////////////////////////////////
+// chat_completion
+//
+// One ChatCompletion request with the configured chat model, to
+// a provider `category` may go to, through the OpenAI limiter.
+// Returns the reply's text.
+/////////////////////////////////////////////////////////////
+pub(crate) async fn chat_completion(
+    app_data: &AppState,
+    openai: &config::OpenAiConfig,
+    category: Category,
+    messages: Vec<serde_json::Value>,
+    max_tokens: u32,
+) -> Result<String, LlmError> {
+    let message = chat_request(app_data, openai, category, &messages, max_tokens, &[]).await?;
+    Ok(reply_text(&message))
+}
+
+/////////////////////////////////////////////////////////////
+// chat_with_tools
+//
+// Like chat_completion, offering GPT the enabled tools (see
+// tools.rs). While it calls them, their results go back to it,
+// for up to MAX_TOOL_ROUNDS rounds; the last round offers none,
+// so it has to answer.
+/////////////////////////////////////////////////////////////
+async fn chat_with_tools(
+    app_data: &web::Data<AppState>,
+    openai: &config::OpenAiConfig,
+    source: &SourceSession,
+    mut messages: Vec<serde_json::Value>,
+) -> Result<String, LlmError> {
+    let tools = tools::definitions(app_data).await;
+    for round in 0..=MAX_TOOL_ROUNDS {
+        let offered = if round < MAX_TOOL_ROUNDS { tools.as_slice() } else { &[] };
+        let message = chat_request(app_data, openai, Category::Transcripts, &messages, openai.max_tokens, offered).await?;
+        let calls = message["tool_calls"].as_array().cloned().unwrap_or_default();
+        if calls.is_empty() {
+            return Ok(reply_text(&message));
+        }
+        messages.push(message);
+        for call in calls {
+            let function = &call["function"];
+            let name = function["name"].as_str().unwrap_or_default();
+            let result = tools::call(app_data, source, name, function["arguments"].as_str().unwrap_or("{}")).await;
+            messages.push(serde_json::json!({
+                "role": "tool",
+                "tool_call_id": call["id"],
+                "content": result
+            }));
+        }
+    }
+    unreachable!("the last round offers no tools")
+}
+
+// The reply message, {"role": "assistant", "content", ...}, from
+// the first of `category`'s providers that can be reached
+async fn chat_request(
+    app_data: &AppState,
+    openai: &config::OpenAiConfig,
+    category: Category,
+    messages: &[serde_json::Value],
+    max_tokens: u32,
+    tools: &[serde_json::Value],
+) -> Result<serde_json::Value, LlmError> {
+    let providers = routing::providers(&*app_data.config.read().await, category).map_err(OpenAiError::from)?;
+    let mut providers = providers.iter().peekable();
+    loop {
+        let provider = providers.next().expect("routing::providers is never empty");
+        match chat_request_to(app_data, openai, provider, messages, max_tokens, tools).await {
+            Err(e) if providers.peek().is_some() && try_next_provider(&e) => {
+                tracing::warn!(provider = %provider.name, error = %e, "trying the next provider for {category}");
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+async fn chat_request_to(
+    app_data: &AppState,
+    openai: &config::OpenAiConfig,
+    provider: &Provider,
+    messages: &[serde_json::Value],
+    max_tokens: u32,
+    tools: &[serde_json::Value],
+) -> Result<serde_json::Value, OpenAiError> {
+    if openai.api_key.is_empty() && provider.is_built_in() {
+        return Err(OpenAiError::NotConfigured);
+    }
+    tracing::debug!(provider = %provider.name, model = %provider.model, "sending messages to GPT");
+
+    // Build request body
+    let mut req_body = serde_json::json!({
+        "model": provider.model,
+        "messages": messages,
+        "max_tokens": max_tokens,
+        "temperature": openai.temperature
+    });
+    // OpenAI rejects an empty list
+    if !tools.is_empty() {
+        req_body["tools"] = serde_json::json!(tools);
+    }
+
+    let _slot = app_data.openai_limiter.acquire("gpt").await?;
+    let resp = provider
+        .authorize(app_data.openai_client.post(provider.url("chat/completions")))
+        .header(CONTENT_TYPE, "application/json")
+        .json(&req_body)
+        .timeout(Duration::from_secs(openai.timeout_secs))
+        .send()
+        .await
+        .map_err(|e| openai_failure(openai, e, OpenAiError::Unreachable))?;
+    let resp = check_openai_status(resp).await?;
+
+    let mut json_resp: serde_json::Value = resp.json().await
+        .map_err(|e| openai_failure(openai, e, OpenAiError::InvalidResponse))?;
+    tracing::debug!(raw = %json_resp, "GPT API response");
+
+    Ok(json_resp["choices"][0]["message"].take())
+}
+
+fn reply_text(message: &serde_json::Value) -> String {
+    message["content"].as_str().unwrap_or("").trim().to_string()
+}
+
+/////////////////////////////////////////////////////////////
+// openai_client
+//
+// Built once at startup and kept in AppState, so Whisper and GPT
+// calls reuse pooled connections (HTTP/2 when the server offers
+// it) instead of a new TLS handshake for every chunk. The request
+// timeout, openai.timeout_secs, is set per request so it follows
+// config reloads.
+/////////////////////////////////////////////////////////////
+pub fn openai_client() -> reqwest::Result<reqwest::Client> {
+    privacy::client_builder()
+        .connect_timeout(OPENAI_CONNECT_TIMEOUT)
+        .pool_idle_timeout(OPENAI_POOL_IDLE)
+        .tcp_keepalive(OPENAI_KEEP_ALIVE)
+        .http2_keep_alive_interval(OPENAI_KEEP_ALIVE)
+        .http2_keep_alive_while_idle(true)
+        .build()
+}
+
+// A slow answer is worth retrying but isn't a sign we're offline,
+// so it doesn't send chunks to the backlog; nor is local-only
+// mode, which won't let them go later either
+fn openai_failure(
+    openai: &config::OpenAiConfig,
+    e: reqwest::Error,
+    otherwise: fn(reqwest::Error) -> OpenAiError,
+) -> OpenAiError {
+    if let Some(refused) = privacy::refused_in(&e) {
+        OpenAiError::NotLocal { host: refused.host.clone() }
+    } else if e.is_timeout() {
+        OpenAiError::Timeout { secs: openai.timeout_secs }
+    } else {
+        otherwise(e)
+    }
+}
+
+// Another provider may be up, or allowed by local-only mode
+fn try_next_provider(e: &OpenAiError) -> bool {
+    matches!(e, OpenAiError::Unreachable(_) | OpenAiError::NotLocal { .. })
+}
+
+// Non-2xx replies become OpenAiError::Status with OpenAI's error body
+async fn check_openai_status(resp: reqwest::Response) -> Result<reqwest::Response, OpenAiError> {
+    let status = resp.status();
+    sentry::note_api_status(status.as_u16());
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(OpenAiError::Status { status: status.as_u16(), body })
+}
+
+/////////////////////////////////////////////////////////////
+// append_to_json_log
+//
+// Called after we get the new user chunk + GPT response
+// Then hands the record to the sinks [outputs] routes it to:
+// by default SSE (with an event ID for resume), on both the
+// combined /live_log and the audio source's own stream, and
+// webhooks (see outputs.rs)
+/////////////////////////////////////////////////////////////
+async fn append_to_json_log(
+    source: &str,
+    text: &str,
+    // A transcript's; None for a response
+    details: Option<&Details>,
+    app_data: &web::Data<AppState>,
+    audio_source: &SourceSession,
+    chunk_id: &str,
+    session_id: Option<&str>,
+) -> Result<String, StorageError> {
+    let timestamp = Utc::now().to_rfc3339();
+    let session_id = session_id.map(str::to_string);
+    let entry_id = logging::new_id();
+    let mut record = serde_json::json!({
+        "timestamp": timestamp,
+        "source": source,
+        "text": text,
+        "audio_source": audio_source.name,
+        "session_id": session_id,
+        "chunk_id": chunk_id,
+        "entry_id": entry_id
+    });
+    if let Some(details) = details {
+        // Pseudonyms only (see speakers.rs)
+        if !details.speakers.is_empty() {
+            record["speakers"] = details.speakers.clone().into();
+        }
+        if let Some(language) = &details.language {
+            record["language"] = language.clone().into();
+        }
+        let logprobs: Vec<f64> = details.segments.iter().filter_map(|s| s.avg_logprob).collect();
+        if !logprobs.is_empty() {
+            record["confidence"] = (logprobs.iter().map(|p| p.exp()).sum::<f64>() / logprobs.len() as f64).into();
+        }
+        if !details.segments.is_empty() {
+            record["segments"] = serde_json::to_value(&details.segments).map_err(StorageError::Serialize)?;
+        }
+        if details.response_pending {
+            record["response_pending"] = true.into();
+        }
+    } else {
+        // Which persona answered, for its ratings (see feedback.rs)
+        let persona = {
+            let config = app_data.config.read().await;
+            workspaces::overrides(&config, audio_source).persona.unwrap_or_else(|| config.openai.persona.clone())
+        };
+        if !persona.is_empty() {
+            record["persona"] = persona.into();
+        }
+    }
+    // Its own log, if its source is a workspace's (see workspaces.rs)
+    let workspace = workspaces::of_source(&*app_data.config.read().await, &audio_source.name);
+    if !workspace.is_empty() {
+        record["workspace"] = workspace.clone().into();
+    }
+    // A titled session (e.g. a calendar meeting) is named in each
+    // record; a backlog chunk from an earlier session isn't
+    if session_id.is_some() && *audio_source.session_id.borrow() == session_id {
+        let details = audio_source.details.borrow().clone();
+        if let Some(title) = details.title {
+            record["session_title"] = title.into();
+            record["attendees"] = details.attendees.into();
+        }
+    }
+
+    let record_string = serde_json::to_string(&record)
+        .map_err(StorageError::Serialize)?;
+
+    let event = if source == "Microphone" { "transcript" } else { "response" };
+    let sinks = outputs::sinks(app_data, event).await;
+    if sinks.iter().any(|sink| sink == "log") {
+        // Append each JSON entry on its own line for simplicity
+        let path = workspaces::log_path(&workspace);
+        if let Some(dir) = Path::new(&path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|source| StorageError::Open { path: path.clone(), source })?;
+        }
+        let guard = CONVERSATION_LOG_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|source| StorageError::Open { path: path.clone(), source })?;
+
+        use std::io::Write;
+        writeln!(file, "{}", record_string)
+            .map_err(StorageError::Write)?;
+        drop(guard);
+
+        tracing::debug!(record = %record_string, path = %path, "appended record to the log");
+    }
+
+    // Sent out with the time as it's shown, too (see timezone.rs)
+    timezone::annotate(&mut record);
+    let record_string = serde_json::to_string(&record).map_err(StorageError::Serialize)?;
+
+    // SSE for real-time display, webhooks, ... as [outputs] says
+    let output = outputs::Output {
+        kind: event,
+        source,
+        audio_source,
+        session_id,
+        record: &record,
+        record_string: &record_string,
+    };
+    outputs::send(app_data, &sinks, &output).await;
+
+    rules::check(app_data, event, &record).await;
+    lights::check(app_data, event, text).await;
+
+    Ok(entry_id)
+}
@@ -0,0 +1,185 @@
+/////////////////////////////////////////////////////////////
+// src/privacy.rs
+//
+// Local-only mode (privacy.mode = "local", PRIVACY_MODE=local):
+// nothing leaves the local network, whatever else is configured.
+// It's enforced where connections are made rather than by
+// switching features off:
+//   - every HTTP client the server builds comes from client() or
+//     client_builder(), whose DNS resolver only hands out
+//     loopback, private and link-local addresses, and which sends
+//     URLs with a public IP address to a proxy the resolver
+//     refuses; system proxies (HTTP_PROXY, ...) are ignored
+//   - MQTT and SMTP hosts are checked with check_host() before
+//     connecting
+// So Whisper and GPT only work when openai.base_url points at a
+// local server (whisper.cpp, Ollama, ...); otherwise chunks fail
+// with OpenAiError::NotLocal and recording stops. Slack, Discord,
+// Telegram, Notion, Sentry, weather and the rest fail the same
+// way and are logged like any other outage.
+//
+// GET /status shows the mode and how many connections were
+// refused, and the web UI says so. Restart-only: clients built
+// before a change would keep the old behavior.
+/////////////////////////////////////////////////////////////
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::config::PrivacySettings;
+
+// Where URLs with a public IP address are sent; the resolver
+// refuses it without looking it up
+const REFUSED_PROXY_HOST: &str = "public-address.invalid";
+
+static LOCAL_ONLY: AtomicBool = AtomicBool::new(false);
+static REFUSED: AtomicU64 = AtomicU64::new(0);
+
+/////////////////////////////////////////////////////////////
+// Refused
+//
+// A connection local-only mode didn't allow. Found in an error's
+// source chain with refused_in().
+/////////////////////////////////////////////////////////////
+#[derive(Debug, thiserror::Error)]
+#[error("privacy.mode is local, so {host} can't be reached: it isn't on the local network")]
+pub struct Refused {
+    pub host: String,
+}
+
+impl Refused {
+    fn new(host: &str) -> Refused {
+        REFUSED.fetch_add(1, Ordering::Relaxed);
+        let host = if host == REFUSED_PROXY_HOST { "a public address" } else { host };
+        tracing::debug!(host, "refused a connection outside the local network");
+        Refused { host: host.to_string() }
+    }
+}
+
+// Set once at startup, before any client is built
+pub fn init(settings: &PrivacySettings) {
+    let local = settings.mode == "local";
+    LOCAL_ONLY.store(local, Ordering::Relaxed);
+    if local {
+        tracing::info!("privacy mode is local: connections outside the local network are refused");
+    }
+}
+
+pub fn is_local_only() -> bool {
+    LOCAL_ONLY.load(Ordering::Relaxed)
+}
+
+/////////////////////////////////////////////////////////////
+// client / client_builder
+//
+// Use these instead of reqwest::Client::new()/builder().
+/////////////////////////////////////////////////////////////
+pub fn client() -> reqwest::Client {
+    client_builder().build().expect("HTTP client setup")
+}
+
+pub fn client_builder() -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    if !is_local_only() {
+        return builder;
+    }
+    // A proxy replaces any from the environment
+    let refuse_public = reqwest::Proxy::custom(|url| {
+        let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+        let ip: IpAddr = host.parse().ok()?;
+        (!is_local(ip)).then(|| format!("http://{REFUSED_PROXY_HOST}"))
+    });
+    builder.dns_resolver(Arc::new(LocalResolver)).proxy(refuse_public)
+}
+
+struct LocalResolver;
+
+impl Resolve for LocalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let refused = || Box::new(Refused::new(&host)) as Box<dyn std::error::Error + Send + Sync>;
+            if host == REFUSED_PROXY_HOST {
+                return Err(refused());
+            }
+            let found = tokio::net::lookup_host((host.as_str(), 0)).await?;
+            let local: Vec<SocketAddr> = found.filter(|addr| is_local(addr.ip())).collect();
+            if local.is_empty() {
+                return Err(refused());
+            }
+            Ok(Box::new(local.into_iter()) as Addrs)
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// check_host
+//
+// For connections that don't go through an HTTP client: Ok if
+// local-only mode is off, or every address `host` resolves to is
+// local.
+/////////////////////////////////////////////////////////////
+pub async fn check_host(host: &str, port: u16) -> Result<(), Refused> {
+    if !is_local_only() {
+        return Ok(());
+    }
+    let local = match tokio::net::lookup_host((host, port)).await {
+        Ok(mut found) => found.all(|addr| is_local(addr.ip())),
+        // It won't connect either
+        Err(_) => true,
+    };
+    if local {
+        Ok(())
+    } else {
+        Err(Refused::new(host))
+    }
+}
+
+// The Refused somewhere in an error's causes, if that's why it failed
+pub fn refused_in<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a Refused> {
+    let mut cause = Some(error);
+    while let Some(e) = cause {
+        if let Some(refused) = e.downcast_ref::<Refused>() {
+            return Some(refused);
+        }
+        cause = e.source();
+    }
+    None
+}
+
+// Loopback, private (RFC 1918, unique local) or link-local
+pub fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_local(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// For GET /status
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PrivacyStatus {
+    // "off" or "local"
+    mode: String,
+    // Connections refused since startup
+    refused: u64,
+}
+
+pub(crate) fn status() -> PrivacyStatus {
+    PrivacyStatus {
+        mode: if is_local_only() { "local" } else { "off" }.to_string(),
+        refused: REFUSED.load(Ordering::Relaxed),
+    }
+}
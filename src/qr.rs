@@ -0,0 +1,611 @@
+/////////////////////////////////////////////////////////////
+// src/qr.rs
+//
+// QR codes as PNG images, for GET /qr.png (see pairing.rs).
+// Only what a URL needs:
+//   - byte mode, error correction level M (15% of the symbol
+//     can be lost), the smallest version (1-40) that fits
+//   - the mask with the lowest penalty, as in ISO/IEC 18004
+//   - a 1-bit grayscale PNG with a quiet zone around it
+// The encoder follows Project Nayuki's reference implementation
+// (MIT), cut down to that.
+/////////////////////////////////////////////////////////////
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+// Level M, per version (index 0 unused)
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+const ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31,
+    33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+// Level M's two format bits
+const FORMAT_BITS_M: u32 = 0;
+// Modules of light border the spec asks for
+pub const QUIET_ZONE: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0} bytes don't fit in a QR code")]
+pub struct TooLong(usize);
+
+/////////////////////////////////////////////////////////////
+// QrCode
+//
+// A square of dark and light modules.
+/////////////////////////////////////////////////////////////
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,
+    // Finder, timing, alignment, format and version modules,
+    // which masks leave alone
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<QrCode, TooLong> {
+        let version = (1..=40)
+            .find(|&version| 4 + count_bits(version) + data.len() * 8 <= data_codewords(version) * 8)
+            .ok_or(TooLong(data.len()))?;
+        let capacity = data_codewords(version) * 8;
+
+        let mut bits = Bits::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, count_bits(version));
+        for &byte in data {
+            bits.push(byte.into(), 8);
+        }
+        // Terminator, then up to a byte boundary, then pad bytes
+        bits.push(0, (capacity - bits.len()).min(4));
+        bits.push(0, (8 - bits.len() % 8) % 8);
+        for pad in [0xEC, 0x11].into_iter().cycle() {
+            if bits.len() >= capacity {
+                break;
+            }
+            bits.push(pad, 8);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            version,
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+        qr.draw_function_patterns();
+        qr.draw_codewords(&add_ecc_and_interleave(version, &bits.bytes()));
+
+        let mut best = (0, i32::MAX);
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+            let penalty = qr.penalty();
+            if penalty < best.1 {
+                best = (mask, penalty);
+            }
+            // Masks are their own inverse
+            qr.apply_mask(mask);
+        }
+        qr.apply_mask(best.0);
+        qr.draw_format_bits(best.0);
+        Ok(qr)
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    // Column x, row y; false outside the symbol
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /////////////////////////////////////////////////////////////
+    // png
+    //
+    // The code with its quiet zone, each module `scale` pixels
+    // square.
+    /////////////////////////////////////////////////////////////
+    pub fn png(&self, scale: usize) -> Vec<u8> {
+        let side = (self.size + 2 * QUIET_ZONE) * scale;
+        let row_bytes = side.div_ceil(8);
+        let mut pixels = Vec::with_capacity((row_bytes + 1) * side);
+        for py in 0..side {
+            // Filter type: none
+            pixels.push(0);
+            let y = (py / scale).wrapping_sub(QUIET_ZONE);
+            let mut row = vec![0u8; row_bytes];
+            for px in 0..side {
+                let x = (px / scale).wrapping_sub(QUIET_ZONE);
+                // 1 is white
+                if !self.is_dark(x, y) {
+                    row[px / 8] |= 0x80 >> (px % 8);
+                }
+            }
+            pixels.extend_from_slice(&row);
+        }
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&pixels).expect("writing to a Vec can't fail");
+        let idat = zlib.finish().expect("writing to a Vec can't fail");
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(side as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(side as u32).to_be_bytes());
+        // Bit depth 1, grayscale, deflate, no filter, no interlace
+        ihdr.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &ihdr);
+        png_chunk(&mut png, b"IDAT", &idat);
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Not over the finders
+                let over_finder = [(0, 0), (0, last), (last, 0)].contains(&(i, j));
+                if !over_finder {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+        // Reserved now, drawn for real once the mask is picked
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set_function((x as i32 + dx) as usize, (y as i32 + dy) as usize, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let data = (FORMAT_BITS_M << 3) | mask;
+        let bits = bch(data, 10, 0x537) ^ 0x5412;
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(bits, i));
+        }
+        self.set_function(8, 7, bit(bits, 6));
+        self.set_function(8, 8, bit(bits, 7));
+        self.set_function(7, 8, bit(bits, 8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(bits, i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(bits, i));
+        }
+        // Always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let bits = bch(self.version as u32, 12, 0x1F25);
+        for i in 0..18 {
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, bit(bits, i));
+            self.set_function(b, a, bit(bits, i));
+        }
+    }
+
+    // In the zigzag order, two columns at a time from the right
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // Around the vertical timing pattern
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+                    if !self.is_function[y * size + x] && i < data.len() * 8 {
+                        self.modules[y * size + x] = (data[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                self.modules[i] ^= invert && !self.is_function[i];
+            }
+        }
+    }
+
+    fn penalty(&self) -> i32 {
+        const N1: i32 = 3;
+        const N2: i32 = 3;
+        const N3: i32 = 40;
+        const N4: i32 = 10;
+        let size = self.size;
+        let mut result = 0;
+
+        // Runs of one color and finder-like patterns, in rows then columns
+        for transposed in [false, true] {
+            for a in 0..size {
+                let mut run_color = false;
+                let mut run = 0;
+                let mut history = RunHistory::new(size);
+                for b in 0..size {
+                    let dark = if transposed { self.is_dark(a, b) } else { self.is_dark(b, a) };
+                    if dark == run_color {
+                        run += 1;
+                        if run == 5 {
+                            result += N1;
+                        } else if run > 5 {
+                            result += 1;
+                        }
+                    } else {
+                        history.add(run);
+                        if !run_color {
+                            result += history.finder_patterns() * N3;
+                        }
+                        run_color = dark;
+                        run = 1;
+                    }
+                }
+                result += history.terminate(run_color, run) * N3;
+            }
+        }
+
+        // 2x2 blocks of one color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y) && dark == self.is_dark(x, y + 1) && dark == self.is_dark(x + 1, y + 1) {
+                    result += N2;
+                }
+            }
+        }
+
+        // Far from half dark
+        let dark = self.modules.iter().filter(|&&m| m).count() as i32;
+        let total = (size * size) as i32;
+        let k = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
+        result + k * N4
+    }
+}
+
+// The last seven run lengths, for spotting 1:1:3:1:1 finder lookalikes
+struct RunHistory {
+    size: i32,
+    runs: [i32; 7],
+}
+
+impl RunHistory {
+    fn new(size: usize) -> RunHistory {
+        RunHistory { size: size as i32, runs: [0; 7] }
+    }
+
+    fn add(&mut self, mut run: i32) {
+        // The light border before the first run
+        if self.runs[0] == 0 {
+            run += self.size;
+        }
+        self.runs.copy_within(0..6, 1);
+        self.runs[0] = run;
+    }
+
+    fn finder_patterns(&self) -> i32 {
+        let r = &self.runs;
+        let n = r[1];
+        let core = n > 0 && r[2] == n && r[3] == n * 3 && r[4] == n && r[5] == n;
+        i32::from(core && r[0] >= n * 4 && r[6] >= n) + i32::from(core && r[6] >= n * 4 && r[0] >= n)
+    }
+
+    fn terminate(&mut self, run_color: bool, mut run: i32) -> i32 {
+        if run_color {
+            self.add(run);
+            run = 0;
+        }
+        // The light border after the last run
+        self.add(run + self.size);
+        self.finder_patterns()
+    }
+}
+
+#[derive(Default)]
+struct Bits(Vec<bool>);
+
+impl Bits {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            self.0.push((value >> i) & 1 == 1);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.0.chunks(8).map(|byte| byte.iter().fold(0u8, |acc, &b| (acc << 1) | u8::from(b))).collect()
+    }
+}
+
+// Bits of the byte-mode character count
+fn count_bits(version: usize) -> usize {
+    if version <= 9 {
+        8
+    } else {
+        16
+    }
+}
+
+// Modules left for data and error correction once the function
+// patterns are drawn
+fn raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        result -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let size = version * 4 + 17;
+    let count = version / 7 + 2;
+    let step = if version == 32 { 26 } else { (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2 };
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+// Splits the data into blocks, adds each one's error correction
+// and interleaves them
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw % blocks;
+    let short_len = raw / blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut all: Vec<Vec<u8>> = Vec::with_capacity(blocks);
+    let mut k = 0;
+    for i in 0..blocks {
+        let len = short_len - ecc_len + usize::from(i >= short_blocks);
+        let mut block = data[k..k + len].to_vec();
+        k += len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        // Keeps the columns lined up; skipped when interleaving
+        if i < short_blocks {
+            block.push(0);
+        }
+        block.extend_from_slice(&ecc);
+        all.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw);
+    for i in 0..all[0].len() {
+        for (j, block) in all.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree - 1];
+    result.push(1);
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+// In GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z = 0u8;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1D);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+// `data` followed by its BCH remainder of `bits` bits
+fn bch(data: u32, bits: u32, generator: u32) -> u32 {
+    let mut remainder = data;
+    for _ in 0..bits {
+        remainder = (remainder << 1) ^ ((remainder >> (bits - 1)) * generator);
+    }
+    (data << bits) | remainder
+}
+
+fn bit(value: u32, i: usize) -> bool {
+    (value >> i) & 1 == 1
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reed_solomon_matches_the_worked_example() {
+        // "HELLO WORLD" as 1-M, from Thonky's QR code tutorial
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        let ecc = reed_solomon_remainder(&data, &reed_solomon_divisor(10));
+        assert_eq!(ecc, [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn format_and_version_bits_match_the_spec_tables() {
+        // Level M, mask 0 and 5, before and after masking
+        assert_eq!(bch(0, 10, 0x537) ^ 0x5412, 0b101010000010010);
+        assert_eq!(bch(5, 10, 0x537) ^ 0x5412, 0b100000011001110);
+        assert_eq!(bch(7, 12, 0x1F25), 0x07C94);
+        assert_eq!(bch(40, 12, 0x1F25), 0x28C69);
+    }
+
+    #[test]
+    fn capacities_match_the_spec_tables() {
+        // Level M data codewords for a few versions
+        for (version, codewords) in [(1, 16), (2, 28), (5, 86), (7, 124), (10, 216), (40, 2334)] {
+            assert_eq!(data_codewords(version), codewords, "version {version}");
+        }
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_positions(32), [6, 34, 60, 86, 112, 138]);
+    }
+
+    #[test]
+    fn picks_the_smallest_version_that_fits() {
+        // 14 bytes fill 1-M; one more needs version 2
+        assert_eq!(QrCode::encode(&[b'a'; 14]).unwrap().version(), 1);
+        assert_eq!(QrCode::encode(&[b'a'; 15]).unwrap().version(), 2);
+        let url = QrCode::encode(b"http://192.168.1.20:8080/pair?token=abcdefghijklmnopqrstuvwxyz012345").unwrap();
+        assert_eq!((url.version(), url.size), (5, 37));
+        assert!(QrCode::encode(&[0; 3000]).is_err());
+    }
+
+    #[test]
+    fn draws_finders_timing_and_the_dark_module() {
+        let qr = QrCode::encode(b"https://example.com/").unwrap();
+        let size = qr.size;
+        for (x, y) in [(0, 0), (size - 7, 0), (0, size - 7)] {
+            // Dark ring, light ring, dark 3x3 center
+            assert!(qr.is_dark(x, y) && qr.is_dark(x + 6, y + 6));
+            assert!(!qr.is_dark(x + 1, y + 1) && !qr.is_dark(x + 5, y + 1));
+            assert!(qr.is_dark(x + 2, y + 2) && qr.is_dark(x + 4, y + 4));
+        }
+        for i in 8..size - 8 {
+            assert_eq!(qr.is_dark(i, 6), i % 2 == 0);
+            assert_eq!(qr.is_dark(6, i), i % 2 == 0);
+        }
+        assert!(qr.is_dark(8, size - 8));
+    }
+
+    #[test]
+    fn png_is_well_formed() {
+        let qr = QrCode::encode(b"http://silentnight.local:8080/").unwrap();
+        let png = qr.png(4);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let side = ((qr.size + 2 * QUIET_ZONE) * 4) as u32;
+        assert_eq!(&png[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), side);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), side);
+        // Every chunk's CRC checks out, and IEND comes last
+        let mut at = 8;
+        let mut kinds = Vec::new();
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let body = &png[at + 4..at + 8 + len];
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc32fast::hash(body), crc);
+            kinds.push(String::from_utf8_lossy(&body[..4]).to_string());
+            at += 12 + len;
+        }
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+    }
+}
@@ -0,0 +1,312 @@
+/////////////////////////////////////////////////////////////
+// src/quiet.rs
+//
+// Quiet hours: windows of local time (quiet_hours.windows, e.g.
+// ["22:00-07:00"]) when nothing is recorded, whoever asks:
+//   - starting a source, record_once and gRPC audio answer 409
+//     (code quiet_hours), from the web UI, the remote, voice,
+//     MQTT, Discord, Telegram, HomeKit or the calendar alike
+//   - a source that's already recording pauses capture at the
+//     next chunk, like a mute, and carries on when they end
+//   - nodes are told not to capture
+// The web UI shows "muted" while they last; a "quiet_hours"
+// event on the live log says when they begin and end.
+//
+// Only an admin can override them (same access as /admin):
+//   GET    /quiet_hours           - windows, and whether they're on now
+//   POST   /quiet_hours/override  - record anyway until {"until"}, or
+//                                   until the current (or next) quiet
+//                                   hours end
+//   DELETE /quiet_hours/override  - quiet hours apply again
+// Windows can change on a reload; an override lasts until it
+// runs out or the server restarts.
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::{admin, AppState};
+
+// How often paused capture and the event task look again, so
+// reloaded windows take effect
+const RECHECK: Duration = Duration::from_secs(30);
+
+/////////////////////////////////////////////////////////////
+// Window
+//
+// "HH:MM-HH:MM"; one that ends before it starts runs past
+// midnight.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug)]
+pub struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    pub fn parse(window: &str) -> Result<Window, String> {
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("must look like \"22:00-07:00\", got {window:?}"))?;
+        let time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| format!("{t:?} in {window:?} isn't an HH:MM time"))
+        };
+        let parsed = Window { start: time(start)?, end: time(end)? };
+        if parsed.start == parsed.end {
+            return Err(format!("{window:?} starts and ends at the same time"));
+        }
+        Ok(parsed)
+    }
+
+    fn contains(&self, t: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+// Config::validate has checked them
+fn windows(settings: &[String]) -> Vec<Window> {
+    settings.iter().filter_map(|w| Window::parse(w).ok()).collect()
+}
+
+// The first `t` after `now`, today or on a following day
+fn next_at(now: DateTime<Local>, t: NaiveTime) -> DateTime<Local> {
+    (0..3)
+        .filter_map(|days| {
+            let day = now.date_naive() + chrono::Days::new(days);
+            day.and_time(t).and_local_timezone(Local).earliest()
+        })
+        .find(|at| *at > now)
+        .unwrap_or(now + chrono::Duration::days(1))
+}
+
+// When the quiet hours in force at `at` end, running on through
+// any window that starts where the last one ends; None if `at`
+// isn't in quiet hours
+fn quiet_until(windows: &[Window], at: DateTime<Local>) -> Option<DateTime<Local>> {
+    let mut until = None;
+    let mut at = at;
+    for _ in 0..=windows.len() {
+        let Some(window) = windows.iter().find(|w| w.contains(at.time())) else { break };
+        at = next_at(at, window.end);
+        until = Some(at);
+    }
+    until
+}
+
+fn next_start(windows: &[Window], now: DateTime<Local>) -> Option<DateTime<Local>> {
+    windows.iter().map(|w| next_at(now, w.start)).min()
+}
+
+/////////////////////////////////////////////////////////////
+// QuietHours
+//
+// The admin's override, if any.
+/////////////////////////////////////////////////////////////
+pub struct QuietHours {
+    override_until: watch::Sender<Option<DateTime<Utc>>>,
+}
+
+impl Default for QuietHours {
+    fn default() -> Self {
+        QuietHours { override_until: watch::Sender::new(None) }
+    }
+}
+
+impl QuietHours {
+    fn overridden(&self) -> Option<DateTime<Utc>> {
+        (*self.override_until.borrow()).filter(|until| *until > Utc::now())
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// in_force
+//
+// When the quiet hours going on now end, unless there are none
+// or an admin has overridden them.
+/////////////////////////////////////////////////////////////
+pub async fn in_force(app_data: &AppState) -> Option<DateTime<Local>> {
+    let windows = windows(&app_data.config.read().await.quiet_hours.windows);
+    let until = quiet_until(&windows, Local::now())?;
+    app_data.quiet_hours.overridden().is_none().then_some(until)
+}
+
+// For anything that would start recording
+pub async fn refuse(app_data: &AppState) -> Result<(), ApiError> {
+    match in_force(app_data).await {
+        Some(until) => Err(ApiError::conflict(
+            "quiet_hours",
+            format!(
+                "Quiet hours until {}; only an admin can override them (POST /quiet_hours/override)",
+                until.format("%H:%M")
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Paused capture waits on this: until the quiet hours end, an
+// override, or RECHECK
+pub async fn pause(app_data: &AppState, until: DateTime<Local>) {
+    let wait = (until - Local::now()).to_std().unwrap_or_default().min(RECHECK);
+    let mut overridden = app_data.quiet_hours.override_until.subscribe();
+    tokio::select! {
+        _ = tokio::time::sleep(wait) => {}
+        _ = overridden.changed() => {}
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Publishes a "quiet_hours" event, {"active", "until"}, when
+// quiet hours begin or end.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "quiet_hours");
+    app_data.tasks.spawn("quiet_hours", announce(app_data.clone()).instrument(span));
+}
+
+async fn announce(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    let mut overridden = app_data.quiet_hours.override_until.subscribe();
+    let mut was_active = false;
+    loop {
+        let until = in_force(&app_data).await;
+        if until.is_some() != was_active {
+            was_active = until.is_some();
+            match until {
+                Some(until) => tracing::info!(until = %until.format("%H:%M"), "quiet hours began"),
+                None => tracing::info!("quiet hours ended"),
+            }
+            let event = json!({ "active": was_active, "until": until.map(|u| u.to_rfc3339()) });
+            app_data.events.publish_notice("quiet_hours", event.to_string());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECHECK) => {}
+            _ = overridden.changed() => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /quiet_hours
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct QuietHoursStatus {
+    // "22:00-07:00", ... in the server's local time
+    windows: Vec<String>,
+    // Nothing may be recorded right now
+    active: bool,
+    // RFC 3339; when the quiet hours going on now end
+    until: Option<String>,
+    // RFC 3339; an admin's override runs until then
+    override_until: Option<String>,
+}
+
+async fn quiet_hours_status(app_data: &AppState) -> QuietHoursStatus {
+    let configured = app_data.config.read().await.quiet_hours.windows.clone();
+    let until = quiet_until(&windows(&configured), Local::now());
+    let override_until = app_data.quiet_hours.overridden();
+    QuietHoursStatus {
+        windows: configured,
+        active: until.is_some() && override_until.is_none(),
+        until: until.map(|u| u.to_rfc3339()),
+        override_until: override_until.map(|u| u.to_rfc3339()),
+    }
+}
+
+#[utoipa::path(
+    tag = "recording",
+    responses((status = 200, description = "Quiet hours and whether they're on", body = QuietHoursStatus)),
+)]
+#[get("/quiet_hours")]
+async fn get_quiet_hours(app_data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(quiet_hours_status(&app_data).await)
+}
+
+/////////////////////////////////////////////////////////////
+// POST/DELETE /quiet_hours/override
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct OverrideRequest {
+    // RFC 3339; default: when the current (or next) quiet hours end
+    until: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/quiet_hours/override",
+    request_body(content = Option<OverrideRequest>, description = "Optional; {} or no body for the default"),
+    responses(
+        (status = 200, description = "Overridden", body = QuietHoursStatus),
+        (status = 400, description = "Bad or past until (code invalid_query)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 409, description = "No quiet hours configured (code no_quiet_hours)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("")]
+async fn override_quiet_hours(
+    app_data: web::Data<AppState>,
+    body: Option<web::Json<OverrideRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let windows = windows(&app_data.config.read().await.quiet_hours.windows);
+    let until = match body.and_then(|b| b.into_inner().until) {
+        Some(until) => DateTime::parse_from_rfc3339(&until)
+            .map_err(|_| ApiError::bad_request("invalid_query", format!("until must be an RFC 3339 timestamp, got {until:?}")))?
+            .with_timezone(&Utc),
+        None => {
+            let now = Local::now();
+            let current_or_next = quiet_until(&windows, now).map(|_| now).or_else(|| next_start(&windows, now));
+            let Some(end) = current_or_next.and_then(|at| quiet_until(&windows, at)) else {
+                return Err(ApiError::conflict("no_quiet_hours", "No quiet hours configured (quiet_hours.windows)"));
+            };
+            end.with_timezone(&Utc)
+        }
+    };
+    if until <= Utc::now() {
+        return Err(ApiError::bad_request("invalid_query", "until is in the past"));
+    }
+    tracing::warn!(until = %until.to_rfc3339(), "quiet hours overridden");
+    app_data.quiet_hours.override_until.send_replace(Some(until));
+    Ok(HttpResponse::Ok().json(quiet_hours_status(&app_data).await))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/quiet_hours/override",
+    responses(
+        (status = 200, description = "Quiet hours apply again", body = QuietHoursStatus),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[delete("")]
+async fn end_override(app_data: web::Data<AppState>) -> HttpResponse {
+    if app_data.quiet_hours.override_until.send_replace(None).is_some() {
+        tracing::info!("quiet hours override ended");
+    }
+    HttpResponse::Ok().json(quiet_hours_status(&app_data).await)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_quiet_hours).service(
+        web::scope("/quiet_hours/override")
+            .wrap(from_fn(admin::require_admin))
+            .service(override_quiet_hours)
+            .service(end_override),
+    );
+}
@@ -0,0 +1,195 @@
+/////////////////////////////////////////////////////////////
+// src/rate_limit.rs
+//
+// Token-bucket rate limiting for the expensive endpoints
+// (starting recording loops, reading the whole log file).
+//
+// Clients are identified by what auth.rs verified: the admin
+// token, a scoped token's name or the login session's user;
+// anything else (including a Bearer value that isn't a token)
+// by peer IP, or for IPv6 its /64, which one host usually has
+// all of. Each (route, client) pair gets its own bucket; idle
+// ones are forgotten every PRUNE_SECS, and there are at most
+// MAX_BUCKETS, the stalest dropped first.
+//
+// Settings ([rate_limit] or env):
+//   per_minute (RATE_LIMIT_PER_MINUTE)  refill rate (default 10, 0 = off)
+//   burst      (RATE_LIMIT_BURST)       bucket size (default 5)
+/////////////////////////////////////////////////////////////
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+use actix_web::{web, Error, ResponseError};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::config::RateLimitSettings;
+use crate::error::ApiError;
+use crate::{admin, auth, tokens, AppState};
+
+// Buckets idle longer than this are forgotten
+const IDLE_BUCKET_SECS: u64 = 600;
+// How often they're looked for
+const PRUNE_SECS: u64 = 60;
+// However many clients turn up
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+struct Buckets {
+    by_client: HashMap<(String, String), Bucket>,
+    last_pruned: Instant,
+}
+
+/////////////////////////////////////////////////////////////
+// RateLimiter
+/////////////////////////////////////////////////////////////
+pub struct RateLimiter {
+    // Atomics so a config reload can change them in place
+    per_minute: AtomicU32,
+    burst: AtomicU32,
+    buckets: AsyncMutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> RateLimiter {
+        let limiter = RateLimiter {
+            per_minute: AtomicU32::new(0),
+            burst: AtomicU32::new(1),
+            buckets: AsyncMutex::new(Buckets { by_client: HashMap::new(), last_pruned: Instant::now() }),
+        };
+        limiter.update(settings);
+        limiter
+    }
+
+    pub fn update(&self, settings: &RateLimitSettings) {
+        self.per_minute.store(settings.per_minute, Ordering::Relaxed);
+        self.burst.store(settings.burst.max(1), Ordering::Relaxed);
+    }
+
+    // Takes one token; on failure returns how long until one is available
+    async fn check(&self, route: &str, client: &str) -> Result<(), Duration> {
+        let per_minute = self.per_minute.load(Ordering::Relaxed);
+        let burst = self.burst.load(Ordering::Relaxed) as f64;
+        if per_minute == 0 {
+            return Ok(());
+        }
+
+        let per_sec = per_minute as f64 / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+
+        if now.duration_since(buckets.last_pruned).as_secs() >= PRUNE_SECS {
+            buckets.by_client.retain(|_, b| now.duration_since(b.last_refill).as_secs() < IDLE_BUCKET_SECS);
+            buckets.last_pruned = now;
+        }
+        let key = (route.to_string(), client.to_string());
+        if buckets.by_client.len() >= MAX_BUCKETS && !buckets.by_client.contains_key(&key) {
+            let stalest = buckets.by_client.iter().min_by_key(|(_, b)| b.last_refill).map(|(key, _)| key.clone());
+            if let Some(stalest) = stalest {
+                buckets.by_client.remove(&stalest);
+            }
+        }
+
+        let bucket = buckets
+            .by_client
+            .entry(key)
+            .or_insert(Bucket {
+                tokens: burst,
+                last_refill: now,
+            });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// limit (middleware)
+//
+// Attach to a route with
+//   #[post("/path", wrap = "middleware::from_fn(rate_limit::limit)")]
+// Over-limit requests get a 429 ErrorBody (code rate_limited)
+// with a Retry-After header.
+/////////////////////////////////////////////////////////////
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let app_data = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .expect("AppState not registered");
+
+    let client = client_key(&req, &app_data).await;
+    let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+
+    if let Err(wait) = app_data.rate_limiter.check(&route, &client).await {
+        let retry_after = wait.as_secs().max(1);
+        tracing::warn!(client = %client, route = %route, retry_after, "rate limited");
+        let mut resp = ApiError::too_many_requests(format!("Too many requests, retry in {retry_after}s"))
+            .error_response();
+        resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        return Ok(req.into_response(resp));
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+// Who auth.rs let in, otherwise the peer IP
+async fn client_key(req: &ServiceRequest, app_data: &AppState) -> String {
+    let admin_token = app_data.config.read().await.admin.token.clone();
+    if admin::has_admin_token(req, &admin_token) {
+        return "admin".to_string();
+    }
+    if let Some(granted) = tokens::granted(req, app_data).await {
+        return format!("token:{}", granted.name);
+    }
+    if let Some(user) = auth::session_user(req, app_data).await {
+        return format!("session:{user}");
+    }
+    req.peer_addr().map_or_else(|| "ip:".to_string(), |a| ip_key(a.ip()))
+}
+
+// Canonical, so IPv4 clients on the dual-stack listener aren't
+// keyed as ::ffff:a.b.c.d; IPv6 by its /64
+fn ip_key(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            format!("ip:{}/64", Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+        ip => format!("ip:{ip}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv6_clients_are_keyed_by_their_64() {
+        let key = |ip: &str| ip_key(ip.parse().unwrap());
+        assert_eq!(key("2001:db8:1:2::1"), "ip:2001:db8:1:2::/64");
+        assert_eq!(key("2001:db8:1:2:aaaa:bbbb:cccc:dddd"), key("2001:db8:1:2::1"));
+        assert_ne!(key("2001:db8:1:3::1"), key("2001:db8:1:2::1"));
+        assert_eq!(key("::ffff:192.168.1.20"), "ip:192.168.1.20");
+        assert_eq!(key("192.168.1.20"), "ip:192.168.1.20");
+    }
+}
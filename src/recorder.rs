@@ -0,0 +1,391 @@
+/////////////////////////////////////////////////////////////
+// src/recorder.rs
+//
+// Audio capture behind the Recorder trait, picked per source by
+// its mic_backend:
+//   "linux" - arecord [-D device]
+//   "mac"   - SoX rec (device via AUDIODEV)
+//   "file"  - no microphone: each chunk is a WAV fixture read
+//             from disk. `device` is a .wav file, or a directory
+//             whose .wav files are used in name order, wrapping
+//             around. Chunks come back right away (chunk_secs is
+//             ignored), so the pipeline can be driven
+//             deterministically in tests and demos. Listening
+//             plays the fixtures one after another, as if the
+//             mic never stopped. With audio.realtime (or
+//             --simulate) each fixture takes as long as it
+//             plays, so demos and bug reports run at the pace
+//             they did in the room.
+//             `device` can also be a .txt script: one utterance
+//             per line (blank lines and # comments skipped),
+//             each a chunk in turn, wrapping around. A line
+//             skips Whisper and goes on as the chunk's
+//             transcript (redaction, commands, GPT, the log and
+//             the live logs all see it), with silence as its
+//             audio, about as long as saying it takes.
+// record_stream hands out the audio as the mic command writes
+// it, so it can go to Whisper while the chunk is still being
+// recorded (see pipeline.rs); backends without a stream of
+// their own give the whole chunk as one piece. listen is one
+// recording that runs until it's dropped, for cutting chunks at
+// pauses (see segmenter.rs).
+/////////////////////////////////////////////////////////////
+
+use async_trait::async_trait;
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::time::Instant;
+
+use crate::config::MicInput;
+use crate::error::AudioError;
+use crate::wav;
+
+// Bytes read from the mic command's stdout at a time
+const READ_SIZE: usize = 8192;
+
+// One chunk of WAV data, piece by piece; ends with an error if
+// the recording fails partway
+pub type AudioStream = Pin<Box<dyn Stream<Item = Result<Bytes, AudioError>> + Send>>;
+
+#[async_trait]
+pub trait Recorder: Send + Sync {
+    // One chunk of WAV data
+    async fn record(&self, duration_sec: u32) -> Result<Bytes, AudioError>;
+
+    // The same chunk, as it's recorded
+    async fn record_stream(&self, duration_sec: u32) -> Result<AudioStream, AudioError> {
+        let audio_data = self.record(duration_sec).await?;
+        Ok(Box::pin(stream::once(async move { Ok(audio_data) })))
+    }
+
+    // WAV data from now until the stream is dropped
+    async fn listen(&self) -> Result<AudioStream, AudioError>;
+
+    // Why recording can't work right now, for GET /health/ready
+    fn problem(&self) -> Option<String>;
+
+    // A chunk whose transcript is already known, with its stand-in
+    // audio: the next line of a script. None for real audio.
+    async fn scripted(&self) -> Option<Result<(Bytes, String), AudioError>> {
+        None
+    }
+}
+
+pub fn for_input(input: &MicInput) -> Arc<dyn Recorder> {
+    // validate() requires a device for the file backend
+    let path = PathBuf::from(input.device.clone().unwrap_or_default());
+    match input.backend.as_str() {
+        "file" if is_script(input) => Arc::new(ScriptRecorder { path, next: AtomicUsize::new(0), realtime: input.realtime }),
+        "file" => Arc::new(FileRecorder { path, next: AtomicUsize::new(0), realtime: input.realtime }),
+        _ => Arc::new(CommandRecorder { input: input.clone() }),
+    }
+}
+
+// Whether the input plays a text script rather than audio
+pub fn is_script(input: &MicInput) -> bool {
+    input.backend == "file"
+        && input
+            .device
+            .as_deref()
+            .is_some_and(|device| Path::new(device).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt")))
+}
+
+// The whole chunk of a stream
+pub async fn collect(mut audio: AudioStream) -> Result<Bytes, AudioError> {
+    let mut audio_data = Vec::new();
+    while let Some(bytes) = audio.next().await {
+        audio_data.extend_from_slice(&bytes?);
+    }
+    Ok(audio_data.into())
+}
+
+/////////////////////////////////////////////////////////////
+// CommandRecorder
+//
+// Switches between "arecord" (Linux) and "rec" (SoX on mac)
+// based on the source's mic backend. Streams the WAV data from
+// its stdout, in memory.
+/////////////////////////////////////////////////////////////
+struct CommandRecorder {
+    input: MicInput,
+}
+
+#[async_trait]
+impl Recorder for CommandRecorder {
+    async fn record(&self, duration_sec: u32) -> Result<Bytes, AudioError> {
+        collect(self.record_stream(duration_sec).await?).await
+    }
+
+    async fn record_stream(&self, duration_sec: u32) -> Result<AudioStream, AudioError> {
+        let input = &self.input;
+        let mic_cmd = get_mic_command(duration_sec, &input.backend, input.device.as_deref());
+        tracing::debug!(?mic_cmd, "using mic command");
+
+        // Spawn the chosen command via tokio::process::Command
+        let mut command = Command::new(&mic_cmd[0]);
+        command.args(&mic_cmd[1..]);
+        // SoX picks its input device from AUDIODEV
+        if let (Some(device), "mac") = (&input.device, input.backend.as_str()) {
+            command.env("AUDIODEV", device);
+        }
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            // A stopped recording drops this future mid-chunk
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|source| AudioError::Spawn { program: mic_cmd[0].clone(), source })?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        // Read child's stdout as it comes; at its end, wait for the
+        // process to finish. The child goes with the stream, so
+        // dropping the stream still kills it.
+        let reads = stream::unfold(Some((child, stdout)), |state| async move {
+            let (mut child, mut stdout) = state?;
+            let mut buf = vec![0; READ_SIZE];
+            match stdout.read(&mut buf).await {
+                Ok(0) => match child.wait().await {
+                    Ok(status) if status.success() => None,
+                    Ok(status) => Some((Err(AudioError::Exited(status)), None)),
+                    Err(e) => Some((Err(AudioError::Read(e)), None)),
+                },
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(Bytes::from(buf)), Some((child, stdout))))
+                }
+                Err(e) => Some((Err(AudioError::Read(e)), None)),
+            }
+        });
+        Ok(Box::pin(reads))
+    }
+
+    async fn listen(&self) -> Result<AudioStream, AudioError> {
+        self.record_stream(0).await
+    }
+
+    fn problem(&self) -> Option<String> {
+        let program = if self.input.backend == "mac" { "rec" } else { "arecord" };
+        (!on_path(program)).then(|| format!("{program} not found on PATH"))
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// get_mic_command
+//
+// Returns the appropriate mic command + args for either
+// "mac" (SoX) or "linux" (arecord), based on `mic_backend`.
+// A device, if set, is passed to arecord with -D. A duration of
+// 0 records until the command is killed.
+/////////////////////////////////////////////////////////////
+fn get_mic_command(duration_sec: u32, backend: &str, device: Option<&str>) -> Vec<String> {
+    if backend == "mac" {
+        let mut cmd = vec![
+            "rec".to_string(),
+            "-q".to_string(),
+            "-c".to_string(), "1".to_string(),
+            "-r".to_string(), "16000".to_string(),
+            "-b".to_string(), "16".to_string(),
+            "-e".to_string(), "signed-integer".to_string(),
+            "-t".to_string(), "wav".to_string(),
+            "-".to_string(),
+        ];
+        if duration_sec > 0 {
+            cmd.extend(["trim".to_string(), "0".to_string(), duration_sec.to_string()]);
+        }
+        cmd
+    } else {
+        // Linux default: arecord [-D <device>] -d <sec> -f cd -t wav -
+        // (-d 0 is arecord's "no limit")
+        let mut cmd = vec!["arecord".to_string()];
+        if let Some(device) = device {
+            cmd.push("-D".to_string());
+            cmd.push(device.to_string());
+        }
+        cmd.extend([
+            "-d".to_string(), duration_sec.to_string(),
+            "-f".to_string(), "cd".to_string(),
+            "-t".to_string(), "wav".to_string(),
+            "-".to_string(),
+        ]);
+        cmd
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/////////////////////////////////////////////////////////////
+// FileRecorder
+//
+// The "file" backend. The directory is listed on every chunk, so
+// fixtures can be added while it runs.
+/////////////////////////////////////////////////////////////
+struct FileRecorder {
+    path: PathBuf,
+    // Index of the next fixture when path is a directory
+    next: AtomicUsize,
+    realtime: bool,
+}
+
+impl FileRecorder {
+    fn fixtures(path: &Path) -> Result<Vec<PathBuf>, String> {
+        if !path.is_dir() {
+            return Ok(vec![path.to_path_buf()]);
+        }
+        let entries = std::fs::read_dir(path).map_err(|e| e.to_string())?;
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")))
+            .collect();
+        if files.is_empty() {
+            return Err("no .wav files in the directory".to_string());
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn fixture_error(path: &Path, reason: impl ToString) -> AudioError {
+        AudioError::Fixture {
+            path: path.display().to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    // The fixture after `index`, listed again each time
+    async fn read(path: &Path, index: usize) -> Result<(Vec<u8>, wav::WavInfo), AudioError> {
+        let files = FileRecorder::fixtures(path).map_err(|reason| FileRecorder::fixture_error(path, reason))?;
+        let file = &files[index % files.len()];
+        tracing::debug!(fixture = %file.display(), "reading WAV fixture");
+
+        let data = tokio::fs::read(file)
+            .await
+            .map_err(|e| FileRecorder::fixture_error(file, e))?;
+        // A bad fixture won't get better, unlike a mic glitch
+        let info = wav::parse(&data).map_err(|e| FileRecorder::fixture_error(file, e))?;
+        Ok((data, info))
+    }
+}
+
+#[async_trait]
+impl Recorder for FileRecorder {
+    async fn record(&self, _duration_sec: u32) -> Result<Bytes, AudioError> {
+        let started = Instant::now();
+        let (data, info) = FileRecorder::read(&self.path, self.next.fetch_add(1, Ordering::Relaxed)).await?;
+        if self.realtime {
+            tokio::time::sleep_until(started + info.duration()).await;
+        }
+        Ok(data.into())
+    }
+
+    // The first fixture whole, then only the audio of the ones
+    // after it, so they play as one long recording
+    async fn listen(&self) -> Result<AudioStream, AudioError> {
+        let start = self.next.load(Ordering::Relaxed);
+        let realtime = self.realtime;
+        let reads = stream::unfold((self.path.clone(), start), move |(path, index)| async move {
+            let started = Instant::now();
+            let piece = match FileRecorder::read(&path, index).await {
+                Ok((data, info)) => {
+                    // Heard only once it's been said
+                    if realtime {
+                        tokio::time::sleep_until(started + info.duration()).await;
+                    }
+                    let data = Bytes::from(data);
+                    if index == start {
+                        Ok(data)
+                    } else {
+                        Ok(data.slice(info.data_offset..info.data_offset + info.data_len))
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            Some((piece, (path, index + 1)))
+        });
+        Ok(Box::pin(reads))
+    }
+
+    fn problem(&self) -> Option<String> {
+        match FileRecorder::fixtures(&self.path) {
+            Ok(files) => files
+                .iter()
+                .find(|f| !f.is_file())
+                .map(|f| format!("{} not found", f.display())),
+            Err(reason) => Some(format!("{}: {reason}", self.path.display())),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// ScriptRecorder
+//
+// The "file" backend with a .txt device. The script is read
+// again for every line, so it can be edited while it runs.
+/////////////////////////////////////////////////////////////
+struct ScriptRecorder {
+    path: PathBuf,
+    // Index of the next line, counting only utterances
+    next: AtomicUsize,
+    realtime: bool,
+}
+
+// How long a line takes to say, roughly
+const WORD_TIME: Duration = Duration::from_millis(400);
+const MIN_LINE_TIME: Duration = Duration::from_secs(1);
+
+impl ScriptRecorder {
+    fn lines(path: &Path) -> Result<Vec<String>, String> {
+        let script = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let lines: Vec<String> = script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        if lines.is_empty() {
+            return Err("no lines in the script".to_string());
+        }
+        Ok(lines)
+    }
+}
+
+#[async_trait]
+impl Recorder for ScriptRecorder {
+    async fn record(&self, _duration_sec: u32) -> Result<Bytes, AudioError> {
+        self.scripted().await.expect("always scripted").map(|(audio, _)| audio)
+    }
+
+    // Scripts are cut per line, not at pauses (see pipeline.rs)
+    async fn listen(&self) -> Result<AudioStream, AudioError> {
+        Err(FileRecorder::fixture_error(&self.path, "a script is read a line at a time, not listened to"))
+    }
+
+    fn problem(&self) -> Option<String> {
+        ScriptRecorder::lines(&self.path).err().map(|reason| format!("{}: {reason}", self.path.display()))
+    }
+
+    async fn scripted(&self) -> Option<Result<(Bytes, String), AudioError>> {
+        let started = Instant::now();
+        let lines = match ScriptRecorder::lines(&self.path) {
+            Ok(lines) => lines,
+            Err(reason) => return Some(Err(FileRecorder::fixture_error(&self.path, reason))),
+        };
+        let line = lines[self.next.fetch_add(1, Ordering::Relaxed) % lines.len()].clone();
+        tracing::debug!(script = %self.path.display(), %line, "reading scripted line");
+        let duration = (WORD_TIME * line.split_whitespace().count() as u32).max(MIN_LINE_TIME);
+        if self.realtime {
+            tokio::time::sleep_until(started + duration).await;
+        }
+        Some(Ok((wav::silent(duration).into(), line)))
+    }
+}
@@ -0,0 +1,235 @@
+/////////////////////////////////////////////////////////////
+// src/redact.rs
+//
+// PII redaction ([redaction] enabled): each transcript is masked
+// as soon as Whisper returns it, so the log, the display, SSE,
+// webhooks, alerts and GPT only ever see the masked text. Per
+// category, each with its own toggle:
+//   phones     - "+44 20 7946 0958", "(555) 123-4567", "555-0199"
+//   emails     - "ann@example.com", "ann at example dot com"
+//   cards      - 13 to 19 digits that pass the Luhn check
+//   addresses  - "221 Baker Street", "PO Box 12"
+// become "[phone]", "[email]", "[card]" and "[address]".
+//
+// The regexes only know the usual shapes. redaction.ner_url can
+// add a named-entity model: a Presidio analyzer
+// (POST {"text", "language"} -> [{"entity_type", "start", "end",
+// "score"}]) or anything that answers the same way. Its entities
+// at or above ner_min_score are masked too, by the same toggles
+// (PHONE_NUMBER, EMAIL_ADDRESS, CREDIT_CARD, and LOCATION or
+// ADDRESS for addresses). If it can't be reached the regexes
+// still run.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::config::RedactionSettings;
+use crate::privacy;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?xi)
+        \b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b
+        # As Whisper writes it when it's read out
+        | \b[a-z0-9._+-]+\s+at\s+[a-z0-9-]+(?:\s+dot\s+[a-z0-9-]+)*\s+dot\s+(?:com|org|net|edu|gov|io|co|uk|de|fr|ca|au)\b",
+    )
+    .expect("email regex")
+});
+// Digits with optional single spaces or dashes between them
+static CARD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("card regex"));
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        # North American, with or without +1
+        (?:\+\d{1,3}[\s.-]?)?(?:\(\d{3}\)\s?|\b\d{3}[\s.-]?)\d{3}[\s.-]?\d{4}\b
+        # International
+        | \+\d{1,3}(?:[\s.-]?\d{2,4}){3,5}\b
+        # Local
+        | \b\d{3}-\d{4}\b",
+    )
+    .expect("phone regex")
+});
+// A house number, one to four capitalized words and a street
+// type; or a PO box
+static ADDRESS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        \b\d{1,6}[A-Za-z]?\s+(?:[A-Z][\w'-]*\s+){1,4}
+        (?i:street|st|avenue|ave|road|rd|boulevard|blvd|lane|ln|drive|dr|court|ct|way|place|pl|terrace|circle|highway|hwy|parkway|square|sq)\b\.?
+        (?:,?\s+(?i:apt|apartment|suite|unit|flat)\.?\s*\#?\w+)?
+        | \b(?i:p\.?\s?o\.?\s+box)\s+\d+\b",
+    )
+    .expect("address regex")
+});
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Category {
+    Phone,
+    Email,
+    Card,
+    Address,
+}
+
+impl Category {
+    fn mask(self) -> &'static str {
+        match self {
+            Category::Phone => "[phone]",
+            Category::Email => "[email]",
+            Category::Card => "[card]",
+            Category::Address => "[address]",
+        }
+    }
+
+    fn enabled(self, settings: &RedactionSettings) -> bool {
+        match self {
+            Category::Phone => settings.phones,
+            Category::Email => settings.emails,
+            Category::Card => settings.cards,
+            Category::Address => settings.addresses,
+        }
+    }
+
+    fn from_entity(entity_type: &str) -> Option<Category> {
+        match entity_type {
+            "PHONE_NUMBER" => Some(Category::Phone),
+            "EMAIL_ADDRESS" => Some(Category::Email),
+            "CREDIT_CARD" => Some(Category::Card),
+            "LOCATION" | "ADDRESS" | "STREET_ADDRESS" => Some(Category::Address),
+            _ => None,
+        }
+    }
+}
+
+// A byte range of the transcript to mask
+struct Span {
+    start: usize,
+    end: usize,
+    category: Category,
+}
+
+/////////////////////////////////////////////////////////////
+// transcript
+//
+// `text` with every enabled category masked; unchanged if
+// redaction is off.
+/////////////////////////////////////////////////////////////
+pub async fn transcript(settings: &RedactionSettings, text: String) -> String {
+    if !settings.enabled || text.is_empty() {
+        return text;
+    }
+    let mut spans = Vec::new();
+    if !settings.ner_url.is_empty() {
+        match entities(settings, &text).await {
+            Ok(found) => spans.extend(found),
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "NER model unavailable, redacting with regexes only"),
+        }
+    }
+    // Cards before phones, so a card number isn't half a phone number
+    if settings.cards {
+        spans.extend(
+            CARD.find_iter(&text)
+                .filter(|m| luhn(m.as_str()))
+                .map(|m| Span { start: m.start(), end: m.end(), category: Category::Card }),
+        );
+    }
+    let patterns = [
+        (Category::Email, &*EMAIL),
+        (Category::Phone, &*PHONE),
+        (Category::Address, &*ADDRESS),
+    ];
+    for (category, pattern) in patterns {
+        if category.enabled(settings) {
+            spans.extend(pattern.find_iter(&text).map(|m| Span { start: m.start(), end: m.end(), category }));
+        }
+    }
+    mask(&text, spans)
+}
+
+// Replaces the spans, the first one found winning where two
+// overlap
+fn mask(text: &str, spans: Vec<Span>) -> String {
+    let mut kept: Vec<Span> = Vec::new();
+    for span in spans {
+        if !kept.iter().any(|k| span.start < k.end && k.start < span.end) {
+            kept.push(span);
+        }
+    }
+    if kept.is_empty() {
+        return text.to_string();
+    }
+    kept.sort_by_key(|s| s.start);
+    tracing::debug!(masked = kept.len(), "redacted transcript");
+    let mut masked = String::with_capacity(text.len());
+    let mut at = 0;
+    for span in kept {
+        masked.push_str(&text[at..span.start]);
+        masked.push_str(span.category.mask());
+        at = span.end;
+    }
+    masked.push_str(&text[at..]);
+    masked
+}
+
+fn luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/////////////////////////////////////////////////////////////
+// entities
+//
+// Asks the NER model at ner_url what it finds.
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize)]
+struct Entity {
+    entity_type: String,
+    // In characters, as Python counts them
+    start: usize,
+    end: usize,
+    #[serde(default = "full_score")]
+    score: f64,
+}
+
+fn full_score() -> f64 {
+    1.0
+}
+
+async fn entities(settings: &RedactionSettings, text: &str) -> Result<Vec<Span>> {
+    let resp = privacy::client()
+        .post(&settings.ner_url)
+        .json(&json!({ "text": text, "language": settings.ner_language }))
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("NER model unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("NER model returned {status}: {body}");
+    }
+    let found: Vec<Entity> = resp.json().await.context("NER model sent an unexpected reply")?;
+
+    // Character offsets to byte offsets
+    let bytes: Vec<usize> = text.char_indices().map(|(i, _)| i).chain([text.len()]).collect();
+    Ok(found
+        .into_iter()
+        .filter(|e| e.score >= settings.ner_min_score && e.start < e.end && e.end < bytes.len())
+        .filter_map(|e| {
+            let category = Category::from_entity(&e.entity_type).filter(|c| c.enabled(settings))?;
+            Some(Span { start: bytes[e.start], end: bytes[e.end], category })
+        })
+        .collect())
+}
@@ -0,0 +1,237 @@
+/////////////////////////////////////////////////////////////
+// src/reload.rs
+//
+// Hot configuration reload, triggered by POST /reload or by
+// sending the process SIGHUP (`systemctl reload`, `kill -HUP`).
+//
+// We rebuild the config exactly like at startup (file + env +
+// CLI), validate it, then apply whatever can change live:
+//   - audio.*       (picked up at the next chunk)
+//   - openai.*      (prompt, models, max_tokens, temperature,
+//                    history depth)
+//   - login.*
+//   - rate_limit.*
+//   - logging.level
+//   - alerts.*      (at the next transcript)
+//   - voice.*       (at the next transcript)
+//   - reminders.*   (at the next chunk), except reminders.file
+//   - weather.*     (at the next chunk)
+//   - hub.*         (at the next check-in or chunk)
+//   - sync.*        (at the next round), except sync.file
+//   - audit.enabled
+//   - redaction.*   (at the next transcript)
+//   - quiet_hours.* (within 30s)
+//   - opt_out.*     (at the next chunk), except opt_out.file
+//   - speakers.*    (at the next chunk)
+//   - providers.*, routing.* (at the next request)
+//   - pairing.*     (at the next /qr.png)
+//   - displays.*    (when a display next loads its profile)
+//   - ui.language   (at the next page load)
+//   - ui.timezone   (at once)
+// A source that is no longer configured stops recording.
+// Changes to server.*, tls.*, discovery.*, grpc.*, mqtt.*,
+// discord.*, telegram.*, email.*, calendar.*, remote.*,
+// homekit.*, node.*, sentry.*, privacy.*, logging.format,
+// rules.file, reminders.file, sync.file, audit.file and
+// opt_out.file need a restart; they are kept at their running values and reported
+// back so the operator knows.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{post, web, HttpResponse};
+use anyhow::Result;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::LoginConfig;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::{logging, sessions, supervisor, systemd, timezone, AppState};
+
+// Settings that can't change without a restart, whole sections
+// or single settings, each with how to keep it at its running value
+type KeepRunning = fn(&mut Config, &Config);
+const RESTART_ONLY: [(&str, KeepRunning); 20] = [
+    ("server", |new, live| new.server = live.server.clone()),
+    ("tls", |new, live| new.tls = live.tls.clone()),
+    ("discovery", |new, live| new.discovery = live.discovery.clone()),
+    ("grpc", |new, live| new.grpc = live.grpc.clone()),
+    ("mqtt", |new, live| new.mqtt = live.mqtt.clone()),
+    ("discord", |new, live| new.discord = live.discord.clone()),
+    ("telegram", |new, live| new.telegram = live.telegram.clone()),
+    ("email", |new, live| new.email = live.email.clone()),
+    ("calendar", |new, live| new.calendar = live.calendar.clone()),
+    ("remote", |new, live| new.remote = live.remote.clone()),
+    ("homekit", |new, live| new.homekit = live.homekit.clone()),
+    ("node", |new, live| new.node = live.node.clone()),
+    ("sentry", |new, live| new.sentry = live.sentry.clone()),
+    ("privacy", |new, live| new.privacy = live.privacy.clone()),
+    ("logging.format", |new, live| new.logging.format = live.logging.format.clone()),
+    ("rules.file", |new, live| new.rules.file = live.rules.file.clone()),
+    ("reminders.file", |new, live| new.reminders.file = live.reminders.file.clone()),
+    ("sync.file", |new, live| new.sync.file = live.sync.file.clone()),
+    ("audit.file", |new, live| new.audit.file = live.audit.file.clone()),
+    ("opt_out.file", |new, live| new.opt_out.file = live.opt_out.file.clone()),
+];
+
+fn restart_only(key: &str) -> bool {
+    let section = key.split('.').next().unwrap_or_default();
+    RESTART_ONLY.iter().any(|(name, _)| *name == key || *name == section)
+}
+
+/////////////////////////////////////////////////////////////
+// ReloadReport
+//
+// Dotted setting names ("openai.chat_model") that changed.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema, Default)]
+pub(crate) struct ReloadReport {
+    applied: Vec<String>,
+    requires_restart: Vec<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// reload_config
+/////////////////////////////////////////////////////////////
+pub async fn reload_config(app_data: &AppState) -> Result<ReloadReport> {
+    systemd::reloading();
+    let result = apply_new_config(app_data).await;
+    // Back to READY=1 either way; a rejected reload keeps the old config
+    systemd::ready("Running");
+    result
+}
+
+async fn apply_new_config(app_data: &AppState) -> Result<ReloadReport> {
+    let mut new_config = Config::load(&app_data.cli)?;
+    let mut live = app_data.config.write().await;
+
+    let new_sources = new_config.audio.source_names();
+    let removed: Vec<String> = live
+        .audio
+        .source_names()
+        .into_iter()
+        .filter(|name| !new_sources.contains(name))
+        .collect();
+
+    let mut report = ReloadReport::default();
+    for key in changed_keys(&live, &new_config) {
+        if restart_only(&key) {
+            report.requires_restart.push(key);
+        } else {
+            report.applied.push(key);
+        }
+    }
+
+    // Keep restart-only settings at what the server is actually using
+    for (_, keep_running) in RESTART_ONLY {
+        keep_running(&mut new_config, &live);
+    }
+
+    logging::set_level(&new_config.logging.level)?;
+    timezone::set(&new_config.ui.timezone);
+    *app_data.login_config.write().await = LoginConfig::from_settings(&new_config.login);
+    app_data.rate_limiter.update(&new_config.rate_limit);
+    app_data.openai_limiter.update(&new_config.openai);
+    for warning in new_config.warnings() {
+        tracing::warn!("{warning}");
+    }
+    *live = new_config;
+    drop(live);
+
+    for name in removed {
+        if let Some(source) = app_data.sources.removed(app_data, &name).await {
+            if source.state.borrow().is_recording() {
+                tracing::info!(source = %name, "source removed from the config");
+                sessions::stop_source(app_data, &source).await;
+            }
+        }
+    }
+
+    tracing::info!(
+        applied = ?report.applied,
+        requires_restart = ?report.requires_restart,
+        "config reloaded"
+    );
+    Ok(report)
+}
+
+/////////////////////////////////////////////////////////////
+// POST /reload
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Config reloaded; lists applied and restart-only changes", body = ReloadReport),
+        (status = 400, description = "New config is invalid; nothing was changed (code invalid_config)", body = ErrorBody),
+    ),
+)]
+#[post("/reload")]
+async fn reload(app_data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    tracing::info!("re-reading configuration");
+
+    match reload_config(&app_data).await {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => {
+            tracing::warn!(error = %format!("{e:#}"), "config reload rejected");
+            Err(ApiError::bad_request("invalid_config", "New config is invalid; nothing was changed")
+                .with_detail(format!("{e:#}")))
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(reload);
+}
+
+/////////////////////////////////////////////////////////////
+// spawn_sighup_listener
+//
+// Reloads on every SIGHUP for the life of the process.
+/////////////////////////////////////////////////////////////
+#[cfg(unix)]
+pub fn spawn_sighup_listener(app_data: web::Data<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let listener_data = app_data.clone();
+    supervisor::spawn_worker(&app_data, "sighup", move || {
+        let app_data = listener_data.clone();
+        let shutdown = app_data.tasks.token();
+        async move {
+            let mut hangups = signal(SignalKind::hangup())
+                .map_err(|e| format!("could not listen for SIGHUP: {e}"))?;
+
+            loop {
+                tokio::select! {
+                    hangup = hangups.recv() => if hangup.is_none() { break },
+                    _ = shutdown.cancelled() => break,
+                }
+                tracing::info!("SIGHUP received, re-reading configuration");
+                if let Err(e) = reload_config(&app_data).await {
+                    tracing::warn!(error = %format!("{e:#}"), "config reload rejected");
+                }
+            }
+            Ok(())
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_sighup_listener(_app_data: web::Data<AppState>) {}
+
+// Flattens both configs to "section.key" => value and lists differences
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+
+    let mut changed = Vec::new();
+    if let (Some(old_sections), Some(new_sections)) = (old.as_object(), new.as_object()) {
+        for (section, new_values) in new_sections {
+            let Some(new_values) = new_values.as_object() else { continue };
+            for (key, value) in new_values {
+                if old_sections.get(section).and_then(|s| s.get(key)) != Some(value) {
+                    changed.push(format!("{section}.{key}"));
+                }
+            }
+        }
+    }
+    changed
+}
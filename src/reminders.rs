@@ -0,0 +1,389 @@
+/////////////////////////////////////////////////////////////
+// src/reminders.rs
+//
+// Timers and reminders ([reminders] enabled): GPT gets tools
+// (see tools.rs) to set, list and cancel them, so "remind us in
+// 20 minutes to check the oven" becomes a reminder on the server
+// instead of a promise it can't keep. When one is due it's
+//   - sent to SSE clients as a "reminder" event, on /live_log
+//     and the source's own stream (the web UI shows it)
+//   - sent to webhooks subscribed to "reminder"
+//   - spoken through rules.tts_command, if reminders.speak
+// A reminder is
+//   {"id", "text", "due", "audio_source", "session_id", "created"}
+// Pending ones are kept in reminders.file (rewritten on every
+// change), so they survive a restart; ones that fell due while
+// the server was down fire as soon as it's back, with
+// "late": true.
+//
+//   GET    /reminders       - pending, soonest first
+//   DELETE /reminders/{id}
+// Same access as /admin (see admin.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{delete, get, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{Notify, RwLock};
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::sessions::SourceSession;
+use crate::{admin, logging, rules, webhooks, AppState};
+
+// How long the scheduler sleeps with nothing pending; a new
+// reminder wakes it sooner
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+// Reminders GPT may set at most this far ahead
+const MAX_AHEAD: TimeDelta = TimeDelta::days(31);
+// A reminder with the same text due this close to a pending one
+// is taken to be that one again (a retried chunk, say)
+const SAME_REMINDER: TimeDelta = TimeDelta::minutes(1);
+
+/////////////////////////////////////////////////////////////
+// Reminder
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct Reminder {
+    id: String,
+    // What to remind them of
+    text: String,
+    #[schema(value_type = String)]
+    due: DateTime<Utc>,
+    // The source that heard it asked for
+    audio_source: String,
+    session_id: Option<String>,
+    #[schema(value_type = String)]
+    created: DateTime<Utc>,
+}
+
+/////////////////////////////////////////////////////////////
+// Reminders
+//
+// The pending reminders and the file they're kept in.
+/////////////////////////////////////////////////////////////
+pub struct Reminders {
+    reminders: RwLock<Vec<Reminder>>,
+    path: PathBuf,
+    // Wakes the scheduler when the list changes
+    changed: Notify,
+}
+
+impl Reminders {
+    // No file yet is no reminders
+    pub fn load(path: &str) -> Result<Reminders> {
+        let path = PathBuf::from(path);
+        let reminders = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("{} isn't a list of reminders", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Reminders {
+            reminders: RwLock::new(reminders),
+            path,
+            changed: Notify::new(),
+        })
+    }
+
+    // Through a temporary file, so a crash never leaves half a list
+    async fn save(&self, reminders: &[Reminder]) -> Result<()> {
+        let text = serde_json::to_string_pretty(reminders)?;
+        let tmp = tmp_path(&self.path);
+        tokio::fs::write(&tmp, text)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    // Runs `change` on a copy of the list and keeps it once saved
+    async fn update<T>(&self, change: impl FnOnce(&mut Vec<Reminder>) -> T) -> Result<T> {
+        let mut reminders = self.reminders.write().await;
+        let mut updated = reminders.clone();
+        let result = change(&mut updated);
+        self.save(&updated).await?;
+        *reminders = updated;
+        self.changed.notify_one();
+        Ok(result)
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// The scheduler: fires each reminder when it's due.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "reminders");
+    app_data.tasks.spawn("reminders", run(app_data.clone()).instrument(span));
+}
+
+async fn run(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    loop {
+        let next = app_data.reminders.reminders.read().await.iter().map(|r| r.due).min();
+        let wait = match next {
+            Some(due) => (due - Utc::now()).to_std().unwrap_or_default(),
+            None => IDLE_WAIT,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => fire_due(&app_data).await,
+            _ = app_data.reminders.changed.notified() => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+async fn fire_due(app_data: &web::Data<AppState>) {
+    let now = Utc::now();
+    let due = app_data.reminders.update(|reminders| {
+        let (due, pending) = reminders.drain(..).partition(|r| r.due <= now);
+        *reminders = pending;
+        due
+    });
+    let due: Vec<Reminder> = match due.await {
+        Ok(due) => due,
+        Err(e) => {
+            // The loop comes straight back here; not in a spin
+            tracing::error!(error = %format!("{e:#}"), "couldn't save the reminders");
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            return;
+        }
+    };
+    let settings = app_data.config.read().await.clone();
+    for reminder in due {
+        // Due while the server was down
+        let late = reminder.due < app_data.started_at;
+        tracing::info!(id = %reminder.id, source = %reminder.audio_source, text = %reminder.text, late, "reminder due");
+        let mut event = json!(reminder);
+        event["late"] = json!(late);
+        let line = event.to_string();
+        app_data.events.publish_notice("reminder", line.clone());
+        if let Some(source) = app_data.sources.get(app_data, &reminder.audio_source).await {
+            source.events.publish_notice("reminder", line);
+        }
+        webhooks::send(app_data, "reminder", event).await;
+        if settings.reminders.speak {
+            let command = settings.rules.tts_command.clone();
+            let speak = async move {
+                if let Err(e) = rules::speak(&command, &reminder.text).await {
+                    tracing::warn!(error = %format!("{e:#}"), "couldn't speak the reminder");
+                }
+            };
+            app_data.tasks.spawn("reminder", speak.in_current_span());
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Tools
+//
+// For tools.rs: what GPT is offered, and running a call.
+/////////////////////////////////////////////////////////////
+pub fn tools() -> Vec<Value> {
+    vec![
+        json!({
+            "type": "function",
+            "function": {
+                "name": "set_reminder",
+                "description": "Set a timer or reminder that is announced out loud when it's due. Use it whenever someone asks to be reminded of something or wants a timer.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "text": { "type": "string", "description": "What to say when it's due, e.g. \"Check the oven\"" },
+                        "in_minutes": { "type": "number", "description": "Due this many minutes from now" },
+                        "at": { "type": "string", "description": "Or due at this local time, HH:MM (the next time it comes round)" }
+                    },
+                    "required": ["text"]
+                }
+            }
+        }),
+        json!({
+            "type": "function",
+            "function": {
+                "name": "list_reminders",
+                "description": "List the pending timers and reminders, soonest first.",
+                "parameters": { "type": "object", "properties": {} }
+            }
+        }),
+        json!({
+            "type": "function",
+            "function": {
+                "name": "cancel_reminder",
+                "description": "Cancel a pending timer or reminder.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "string", "description": "Its id, from list_reminders" }
+                    },
+                    "required": ["id"]
+                }
+            }
+        }),
+    ]
+}
+
+// None if `name` isn't one of ours
+pub async fn call(app_data: &web::Data<AppState>, source: &SourceSession, name: &str, args: &Value) -> Option<Result<Value>> {
+    Some(match name {
+        "set_reminder" => set_reminder(app_data, source, args).await,
+        "list_reminders" => Ok(json!(pending(app_data).await.iter().map(described).collect::<Vec<_>>())),
+        "cancel_reminder" => {
+            let id = args["id"].as_str().unwrap_or_default();
+            match cancel(app_data, id).await {
+                Ok(Some(reminder)) => Ok(json!({ "cancelled": described(&reminder) })),
+                Ok(None) => Err(anyhow::anyhow!("there's no pending reminder with id {id:?}")),
+                Err(e) => Err(e),
+            }
+        }
+        _ => return None,
+    })
+}
+
+async fn set_reminder(app_data: &web::Data<AppState>, source: &SourceSession, args: &Value) -> Result<Value> {
+    let text = args["text"].as_str().map(str::trim).unwrap_or_default();
+    if text.is_empty() {
+        anyhow::bail!("text is required");
+    }
+    let now = Utc::now();
+    let due = if let Some(minutes) = args["in_minutes"].as_f64() {
+        if !(0.0..=MAX_AHEAD.num_minutes() as f64).contains(&minutes) {
+            anyhow::bail!("in_minutes must be between 0 and {}", MAX_AHEAD.num_minutes());
+        }
+        now + TimeDelta::milliseconds((minutes * 60_000.0) as i64)
+    } else if let Some(at) = args["at"].as_str() {
+        let time = NaiveTime::parse_from_str(at.trim(), "%H:%M").with_context(|| format!("at must be HH:MM, got {at:?}"))?;
+        next_local(time)?
+    } else {
+        anyhow::bail!("give in_minutes or at");
+    };
+
+    let reminder = Reminder {
+        id: logging::new_id(),
+        text: text.to_string(),
+        due,
+        audio_source: source.name.clone(),
+        session_id: source.session_id.borrow().clone(),
+        created: now,
+    };
+    let added = app_data
+        .reminders
+        .update(|reminders| {
+            if let Some(same) = reminders.iter().find(|r| r.text == reminder.text && (r.due - reminder.due).abs() < SAME_REMINDER) {
+                return same.clone();
+            }
+            reminders.push(reminder.clone());
+            reminder
+        })
+        .await?;
+    tracing::info!(id = %added.id, due = %added.due, text = %added.text, "reminder set");
+    Ok(described(&added))
+}
+
+// Today at `time`, or tomorrow if that's passed
+fn next_local(time: NaiveTime) -> Result<DateTime<Utc>> {
+    let now = Local::now();
+    for day in [now.date_naive(), now.date_naive() + TimeDelta::days(1)] {
+        let due = day
+            .and_time(time)
+            .and_local_timezone(Local)
+            .earliest()
+            .context("that time doesn't exist today (a clock change)")?;
+        if due > now {
+            return Ok(due.with_timezone(&Utc));
+        }
+    }
+    anyhow::bail!("couldn't work out when {time} is next")
+}
+
+// For GPT: with the due time in local time, which is what it'll say
+fn described(reminder: &Reminder) -> Value {
+    json!({
+        "id": reminder.id,
+        "text": reminder.text,
+        "due": reminder.due.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+    })
+}
+
+async fn pending(app_data: &AppState) -> Vec<Reminder> {
+    let mut reminders = app_data.reminders.reminders.read().await.clone();
+    reminders.sort_by_key(|r| r.due);
+    reminders
+}
+
+async fn cancel(app_data: &AppState, id: &str) -> Result<Option<Reminder>> {
+    let cancelled = app_data
+        .reminders
+        .update(|reminders| {
+            let i = reminders.iter().position(|r| r.id == id)?;
+            Some(reminders.remove(i))
+        })
+        .await?;
+    if let Some(reminder) = &cancelled {
+        tracing::info!(id = %reminder.id, "reminder cancelled");
+    }
+    Ok(cancelled)
+}
+
+/////////////////////////////////////////////////////////////
+// /reminders
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "reminders",
+    path = "/reminders",
+    responses(
+        (status = 200, description = "Pending reminders, soonest first", body = [Reminder]),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("")]
+async fn list_reminders(app_data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(pending(&app_data).await)
+}
+
+#[utoipa::path(
+    tag = "reminders",
+    path = "/reminders/{id}",
+    params(("id" = String, Path, description = "Reminder id")),
+    responses(
+        (status = 204, description = "Cancelled"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 404, description = "No such reminder, or it has already fired (code unknown_reminder)", body = ErrorBody),
+        (status = 500, description = "The reminders couldn't be saved (code reminders_not_saved)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[delete("/{id}")]
+async fn delete_reminder(app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match cancel(&app_data, &id).await {
+        Ok(Some(_)) => Ok(HttpResponse::NoContent().finish()),
+        Ok(None) => Err(ApiError::not_found("unknown_reminder", format!("No pending reminder with id {id}"))),
+        Err(e) => {
+            tracing::error!(error = %format!("{e:#}"), "couldn't save the reminders");
+            Err(ApiError::internal("reminders_not_saved", "Couldn't save the reminders").with_detail(format!("{e:#}")))
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/reminders")
+            .wrap(from_fn(admin::require_admin))
+            .service(list_reminders)
+            .service(delete_reminder),
+    );
+}
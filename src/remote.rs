@@ -0,0 +1,195 @@
+/////////////////////////////////////////////////////////////
+// src/remote.rs
+//
+// IR remote / media keys ([remote] enabled): key presses run
+// commands (see control.rs) on remote.source, so a cheap remote
+// on the coffee table can start/stop, bookmark, mute or switch
+// persona. remote.keys maps key names to commands:
+//   [remote.keys]
+//   KEY_PLAYPAUSE = "toggle"
+//   KEY_RECORD = "bookmark"
+//   KEY_RED = "persona:meeting"
+// Keys come from either or both of
+//   lirc  - lircd's socket (remote.lirc_socket), for IR
+//           receivers; the button names are those in the
+//           remote's lircd.conf. Held buttons repeat; only the
+//           first press counts
+//   evdev - a keyboard device (remote.evdev_device, e.g.
+//           /dev/input/by-id/usb-...-event-kbd), for USB media
+//           keyboards and remotes that show up as one. Keys are
+//           named as in linux/input-event-codes.h (KEY_MUTE);
+//           ones not listed in KEY_NAMES are KEY_<code>. The
+//           device isn't grabbed, so keys still reach the
+//           console too
+// Unmapped keys are ignored. Settings are restart-only; a
+// socket or device that goes away is retried every RETRY_DELAY.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tracing::Instrument;
+
+use crate::config::RemoteSettings;
+use crate::control::{self, Command};
+use crate::AppState;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// struct input_event: a timeval, then type (u16), code (u16) and
+// value (i32)
+const TIMEVAL_BYTES: usize = 2 * std::mem::size_of::<std::ffi::c_long>();
+const EVENT_BYTES: usize = TIMEVAL_BYTES + 8;
+const EV_KEY: u16 = 1;
+const KEY_PRESSED: i32 = 1;
+
+// Keys a remote or media keyboard is likely to have
+const KEY_NAMES: &[(u16, &str)] = &[
+    (1, "KEY_ESC"),
+    (28, "KEY_ENTER"),
+    (57, "KEY_SPACE"),
+    (103, "KEY_UP"),
+    (105, "KEY_LEFT"),
+    (106, "KEY_RIGHT"),
+    (108, "KEY_DOWN"),
+    (113, "KEY_MUTE"),
+    (114, "KEY_VOLUMEDOWN"),
+    (115, "KEY_VOLUMEUP"),
+    (116, "KEY_POWER"),
+    (119, "KEY_PAUSE"),
+    (128, "KEY_STOP"),
+    (139, "KEY_MENU"),
+    (158, "KEY_BACK"),
+    (163, "KEY_NEXTSONG"),
+    (164, "KEY_PLAYPAUSE"),
+    (165, "KEY_PREVIOUSSONG"),
+    (166, "KEY_STOPCD"),
+    (167, "KEY_RECORD"),
+    (168, "KEY_REWIND"),
+    (200, "KEY_PLAYCD"),
+    (201, "KEY_PAUSECD"),
+    (207, "KEY_PLAY"),
+    (208, "KEY_FASTFORWARD"),
+    (352, "KEY_OK"),
+    (398, "KEY_RED"),
+    (399, "KEY_GREEN"),
+    (400, "KEY_YELLOW"),
+    (401, "KEY_BLUE"),
+];
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Starts a listener for each configured input.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: RemoteSettings) {
+    if settings.lirc_socket.is_empty() && settings.evdev_device.is_empty() {
+        tracing::warn!("remote enabled, but neither remote.lirc_socket nor remote.evdev_device is set");
+        return;
+    }
+    tracing::info!(source = %settings.source, keys = settings.keys.len(), "remote control enabled");
+    if !settings.lirc_socket.is_empty() {
+        let (data, settings, path) = (app_data.clone(), settings.clone(), settings.lirc_socket.clone());
+        let listen = keep_reading(app_data.clone(), "LIRC", path.clone(), move || lirc(data.clone(), settings.clone(), path.clone()));
+        let span = tracing::info_span!(parent: None, "remote", input = "lirc");
+        app_data.tasks.spawn("remote", listen.instrument(span));
+    }
+    if !settings.evdev_device.is_empty() {
+        let (data, settings, path) = (app_data.clone(), settings.clone(), settings.evdev_device.clone());
+        let listen = keep_reading(app_data.clone(), "evdev", path.clone(), move || evdev(data.clone(), settings.clone(), path.clone()));
+        let span = tracing::info_span!(parent: None, "remote", input = "evdev");
+        app_data.tasks.spawn("remote", listen.instrument(span));
+    }
+}
+
+// Runs `read` until shutdown, starting it again when it ends
+async fn keep_reading<F, Fut>(app_data: web::Data<AppState>, input: &str, path: String, read: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let shutdown = app_data.tasks.token();
+    let mut failing = false;
+    loop {
+        let result = tokio::select! {
+            result = read() => result,
+            _ = shutdown.cancelled() => return,
+        };
+        let failed = result.is_err();
+        match result {
+            Ok(()) => tracing::warn!(path = %path, "{input} input closed, retrying every {}s", RETRY_DELAY.as_secs()),
+            // Once per outage
+            Err(e) if !failing => {
+                tracing::warn!(path = %path, error = %format!("{e:#}"), "{input} input unavailable, retrying every {}s", RETRY_DELAY.as_secs())
+            }
+            Err(_) => {}
+        }
+        failing = failed;
+        tokio::select! {
+            _ = tokio::time::sleep(RETRY_DELAY) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+// Lines of "<code> <repeat> <button> <remote>", repeat in hex
+async fn lirc(app_data: web::Data<AppState>, settings: RemoteSettings, path: String) -> Result<()> {
+    let stream = tokio::net::UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("Failed to connect to lircd at {path}"))?;
+    tracing::info!(path = %path, "listening to lircd");
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await.context("Failed to read from lircd")? {
+        let mut fields = line.split_whitespace();
+        let (Some(_code), Some(repeat), Some(button)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if u32::from_str_radix(repeat, 16).is_ok_and(|r| r == 0) {
+            pressed(&app_data, &settings, button).await;
+        }
+    }
+    Ok(())
+}
+
+async fn evdev(app_data: web::Data<AppState>, settings: RemoteSettings, path: String) -> Result<()> {
+    let mut device = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("Failed to open {path}"))?;
+    tracing::info!(path = %path, "listening to the keyboard");
+    let mut event = [0u8; EVENT_BYTES];
+    loop {
+        match device.read_exact(&mut event).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {path}")),
+        }
+        let kind = u16::from_ne_bytes([event[TIMEVAL_BYTES], event[TIMEVAL_BYTES + 1]]);
+        let code = u16::from_ne_bytes([event[TIMEVAL_BYTES + 2], event[TIMEVAL_BYTES + 3]]);
+        let value = i32::from_ne_bytes(event[TIMEVAL_BYTES + 4..].try_into().expect("4 bytes"));
+        if kind == EV_KEY && value == KEY_PRESSED {
+            pressed(&app_data, &settings, &key_name(code)).await;
+        }
+    }
+}
+
+fn key_name(code: u16) -> String {
+    KEY_NAMES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map_or_else(|| format!("KEY_{code}"), |(_, name)| name.to_string())
+}
+
+async fn pressed(app_data: &web::Data<AppState>, settings: &RemoteSettings, key: &str) {
+    let Some(command) = settings.keys.get(key) else {
+        tracing::debug!(key, "key not mapped, ignoring");
+        return;
+    };
+    // Checked by Config::validate
+    let Ok(command) = command.parse::<Command>() else { return };
+    match control::run(app_data, "remote", &settings.source, &command).await {
+        Ok(done) => tracing::info!(key, command = %command, "{done}"),
+        Err(e) => tracing::warn!(key, command = %command, error = %format!("{e:#}"), "remote command failed"),
+    }
+}
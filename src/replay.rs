@@ -0,0 +1,132 @@
+/////////////////////////////////////////////////////////////
+// src/replay.rs
+//
+// Watching a past session unfold:
+//   GET /sessions/{id}/replay?speed=2  - its records over SSE, as
+//                                        far apart as they were
+//                                        logged, divided by speed
+// The frames are the ones /live_log sends (?schema=2 too, see
+// events.rs) with IDs counting from 1, so a display client
+// pointed here shows the conversation as it happened. After the
+// last record comes an event named "replay_end"
+// ({"records": n}) and the stream ends. Long gaps get keepalive
+// comments like the live log. An EventSource reconnecting with
+// Last-Event-ID carries on after that record; once there's
+// nothing left the answer is 204, which tells it to stop.
+// Behind the login like the rest of the UI.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, FixedOffset};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use std::time::Duration;
+use utoipa::IntoParams;
+
+use crate::{digest, timezone, workspaces, AppState};
+use crate::error::ApiError;
+use crate::events::{self, EVENT_SCHEMA, SSE_KEEPALIVE_SECS};
+
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 100.0;
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct ReplayQuery {
+    // How many times faster than it happened, 0.1-100 (default 1)
+    speed: Option<f64>,
+    // 2 for records as /live_log?schema=2 sends them
+    schema: Option<u32>,
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(
+        ("id" = String, Path, description = "Session id"),
+        ReplayQuery,
+        workspaces::WorkspaceQuery,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Carry on after this record"),
+    ),
+    responses(
+        (status = 200, description = "SSE stream of the session's records, in their original timing", content_type = "text/event-stream"),
+        (status = 204, description = "Nothing left after Last-Event-ID"),
+        (status = 400, description = "Speed out of range (code invalid_speed)", body = ErrorBody),
+        (status = 403, description = "Another workspace than the token's (code outside_workspace)", body = ErrorBody),
+        (status = 404, description = "Nothing logged for that session in the workspace (code unknown_session), or no such workspace (code unknown_workspace)", body = ErrorBody),
+        (status = 500, description = "The log couldn't be read (code log_unreadable)", body = ErrorBody),
+    ),
+)]
+#[get("/sessions/{id}/replay")]
+async fn replay_session(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<ReplayQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let speed = query.speed.unwrap_or(1.0);
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(ApiError::bad_request(
+            "invalid_speed",
+            format!("speed must be between {MIN_SPEED} and {MAX_SPEED}, got {speed}"),
+        ));
+    }
+    let records: Vec<serde_json::Value> = digest::read_log(&workspaces::requested(&req, &app_data).await?)
+        .await
+        .map_err(|e| ApiError::internal("log_unreadable", "Failed to read the conversation log").with_detail(format!("{e:#}")))?
+        .into_iter()
+        .filter(|r| r["session_id"] == id.as_str())
+        // As the live log sends them
+        .map(|mut r| {
+            timezone::annotate(&mut r);
+            r
+        })
+        .collect();
+    if records.is_empty() {
+        return Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")));
+    }
+    let resume_after: usize = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0);
+    if resume_after >= records.len() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    // (wait, then send)
+    let keepalive = Duration::from_secs(SSE_KEEPALIVE_SECS);
+    let as_events = query.schema == Some(EVENT_SCHEMA);
+    let mut frames: Vec<(Duration, Bytes)> = Vec::new();
+    let mut previous: Option<DateTime<FixedOffset>> = None;
+    for (i, record) in records.iter().enumerate().skip(resume_after) {
+        let at = record["timestamp"].as_str().and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+        let mut wait = match (previous, at) {
+            (Some(previous), Some(at)) => (at - previous).to_std().unwrap_or_default().div_f64(speed),
+            _ => Duration::ZERO,
+        };
+        previous = at.or(previous);
+        while wait > keepalive {
+            frames.push((keepalive, Bytes::from_static(b": keepalive\n\n")));
+            wait -= keepalive;
+        }
+        let data = if as_events { events::record_event(record) } else { record.to_string() };
+        frames.push((wait, Bytes::from(format!("id: {}\ndata: {data}\n\n", i + 1))));
+    }
+    let end = format!("event: replay_end\ndata: {{\"records\":{}}}\n\n", records.len());
+    frames.push((Duration::ZERO, Bytes::from(end)));
+    tracing::info!(session_id = %id, speed, from = resume_after + 1, "replaying the session");
+
+    let stream = stream::iter(frames).then(|(wait, frame)| async move {
+        tokio::time::sleep(wait).await;
+        Ok::<Bytes, std::io::Error>(frame)
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(replay_session);
+}
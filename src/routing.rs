@@ -0,0 +1,224 @@
+/////////////////////////////////////////////////////////////
+// src/routing.rs
+//
+// Where each kind of data may be sent. [routing] lists, per
+// category, the providers it may go to in order of preference:
+//   audio       - recorded chunks, for transcription
+//   transcripts - transcripts and history, for live responses
+//   summaries   - digests and session summaries
+// A provider is "openai" ([openai] itself) or one declared under
+// [providers.<name>]: any OpenAI-compatible endpoint, e.g.
+//   [providers.local_whisper]
+//   base_url = "http://127.0.0.1:8081/v1"
+//   region = "local"
+//   [providers.azure_eu]
+//   base_url = "https://example.openai.azure.com/openai/deployments/gpt-4o"
+//   auth = "api-key"
+//   api_version = "2024-06-01"
+//   region = "eu"
+//   [routing]
+//   audio = ["local_whisper"]
+//   transcripts = ["azure_eu"]
+//   summaries = ["azure_eu"]
+//   regions = ["local", "eu"]
+// An empty list means ["openai"], which is how it worked before.
+//
+// It's enforced here, where the pipeline picks a provider, not
+// only when the config is loaded: a category is never sent to a
+// provider its list doesn't name, and with routing.regions set,
+// never to one outside them ([openai] has no region, so it's
+// out). A chunk with nowhere to go fails with
+// OpenAiError::NotRouted. If a provider can't be reached the next
+// one in the list is tried.
+//
+// Webhooks, Slack, Discord, exports and the other integrations
+// aren't covered; they send what they're configured to.
+// GET /status shows the routes. Live: see reload.rs.
+/////////////////////////////////////////////////////////////
+
+use reqwest::header::AUTHORIZATION;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::config::{Config, ProviderSettings, RoutingSettings};
+
+// The provider [openai] stands for
+pub const BUILT_IN: &str = "openai";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Audio,
+    Transcripts,
+    Summaries,
+}
+
+impl Category {
+    pub const ALL: [Category; 3] = [Category::Audio, Category::Transcripts, Category::Summaries];
+
+    fn allowed(self, routing: &RoutingSettings) -> &[String] {
+        match self {
+            Category::Audio => &routing.audio,
+            Category::Transcripts => &routing.transcripts,
+            Category::Summaries => &routing.summaries,
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Category::Audio => "audio",
+            Category::Transcripts => "transcripts",
+            Category::Summaries => "summaries",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RouteError {
+    #[error("routing.{category} names {name:?}, which isn't \"openai\" or one of [providers]")]
+    Unknown { category: Category, name: String },
+    #[error("routing.{category} allows {name:?}, whose region {region:?} isn't one of routing.regions")]
+    Region { category: Category, name: String, region: String },
+}
+
+/////////////////////////////////////////////////////////////
+// Provider
+//
+// Where one request goes, and how it authenticates.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub name: String,
+    base_url: String,
+    api_key: String,
+    auth: String,
+    api_version: String,
+    // The model for the category it was picked for
+    pub model: String,
+    // Takes audio as it's recorded (see pipeline.rs)
+    pub stream_audio: bool,
+}
+
+impl Provider {
+    pub fn is_built_in(&self) -> bool {
+        self.name == BUILT_IN
+    }
+
+    pub fn url(&self, endpoint: &str) -> String {
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), endpoint);
+        if self.api_version.is_empty() {
+            url
+        } else {
+            format!("{url}?api-version={}", self.api_version)
+        }
+    }
+
+    // Without a key (a local server) nothing is added
+    pub fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (self.api_key.is_empty(), self.auth.as_str()) {
+            (true, _) => req,
+            (false, "api-key") => req.header("api-key", &self.api_key),
+            (false, _) => req.header(AUTHORIZATION, format!("Bearer {}", self.api_key)),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// providers
+//
+// Where `category` may go, in order of preference; never empty.
+/////////////////////////////////////////////////////////////
+pub fn providers(config: &Config, category: Category) -> Result<Vec<Provider>, RouteError> {
+    let allowed = category.allowed(&config.routing);
+    let names: Vec<&str> = if allowed.is_empty() {
+        vec![BUILT_IN]
+    } else {
+        allowed.iter().map(String::as_str).collect()
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let (settings, region) = match config.providers.get(name) {
+                Some(settings) => (settings.clone(), settings.region.clone()),
+                None if name == BUILT_IN => (built_in(config), String::new()),
+                None => return Err(RouteError::Unknown { category, name: name.to_string() }),
+            };
+            if !config.routing.regions.is_empty() && !config.routing.regions.contains(&region) {
+                return Err(RouteError::Region { category, name: name.to_string(), region });
+            }
+            let model = match (category, &settings) {
+                (Category::Audio, s) if !s.stt_model.is_empty() => s.stt_model.clone(),
+                (Category::Audio, _) => config.openai.stt_model.clone(),
+                (_, s) if !s.chat_model.is_empty() => s.chat_model.clone(),
+                _ => config.openai.chat_model.clone(),
+            };
+            Ok(Provider {
+                name: name.to_string(),
+                base_url: settings.base_url,
+                api_key: settings.api_key,
+                auth: settings.auth,
+                api_version: settings.api_version,
+                model,
+                stream_audio: settings.stream_audio,
+            })
+        })
+        .collect()
+}
+
+fn built_in(config: &Config) -> ProviderSettings {
+    ProviderSettings {
+        base_url: config.openai.base_url.clone(),
+        api_key: config.openai.api_key.clone(),
+        stream_audio: config.openai.stream_audio,
+        ..ProviderSettings::default()
+    }
+}
+
+// Whether `category` may go to [openai], so its key matters
+pub fn uses_built_in(config: &Config, category: Category) -> bool {
+    let allowed = category.allowed(&config.routing);
+    allowed.is_empty() || allowed.iter().any(|name| name == BUILT_IN)
+}
+
+// The base URLs every category may be sent to, for the local-only
+// readiness check
+pub fn base_urls(config: &Config) -> Vec<String> {
+    let mut urls: Vec<String> = Category::ALL
+        .into_iter()
+        .filter_map(|category| providers(config, category).ok())
+        .flatten()
+        .map(|provider| provider.base_url)
+        .collect();
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/////////////////////////////////////////////////////////////
+// RoutingStatus
+//
+// For GET /status: where each category goes.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub struct RoutingStatus {
+    // Category ("audio", "transcripts", "summaries") -> providers, in order
+    routes: BTreeMap<String, Vec<String>>,
+    // The only regions allowed; empty = any
+    regions: Vec<String>,
+}
+
+pub fn status(config: &Config) -> RoutingStatus {
+    let routes = Category::ALL
+        .into_iter()
+        .map(|category| {
+            let names = providers(config, category)
+                .map(|providers| providers.into_iter().map(|p| p.name).collect())
+                .unwrap_or_default();
+            (category.to_string(), names)
+        })
+        .collect();
+    RoutingStatus { routes, regions: config.routing.regions.clone() }
+}
@@ -0,0 +1,543 @@
+/////////////////////////////////////////////////////////////
+// src/rules.rs
+//
+// Automation rules, IFTTT style: when a logged transcript (or
+// GPT response) matches a rule's conditions, its actions run.
+//   GET    /rules       - every rule
+//   POST   /rules       - add one; the server picks its id
+//   GET    /rules/{id}
+//   PUT    /rules/{id}  - replace one
+//   DELETE /rules/{id}
+// Same access as /admin (see admin.rs). Rules are kept in
+// rules.file (RULES_FILE), rewritten on every change.
+//
+// A rule:
+//   {"name": "doorbell", "on": "transcript",
+//    "when": {"keywords": ["doorbell"], "after": "22:00", "before": "07:00"},
+//    "then": [{"type": "gpio", "pin": 17, "pulse_ms": 500},
+//             {"type": "tts", "text": "Someone's at the door"}]}
+// Conditions, all of which must hold (none = every record):
+//   keywords   - any of them, whole words, ignoring case
+//   speaker    - the audio source's name; there's no speaker
+//                diarization, so a source per room/mic is the
+//                closest thing
+//   sentiment  - positive, negative or neutral, from a small
+//                word list (no GPT call)
+//   after/before - local HH:MM; after > before wraps midnight
+// Actions:
+//   webhook - a "rule" event to url (signed like webhooks.rs),
+//             data {"rule", "record"}
+//   mqtt    - publish payload to topic over the [mqtt]
+//             connection ("mqtt" cargo feature)
+//   gpio    - set a sysfs GPIO pin under rules.gpio_dir,
+//             exporting it first if needed; pulse_ms sets it
+//             back afterwards
+//   tts     - pipe text to rules.tts_command's stdin
+// mqtt payload and tts text may use {text}, {audio_source},
+// {session_id} and {rule}; the default payload is the record.
+//
+// GPT's "Listening..." responses never trigger rules. Actions
+// run in background tasks; a failure is logged and dropped.
+/////////////////////////////////////////////////////////////
+
+use actix_web::middleware::from_fn;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::WebhookTarget;
+use crate::error::ApiError;
+use crate::{admin, logging, notify, pipeline, webhooks, AppState};
+
+const TTS_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Lexicon for the sentiment condition; a score above zero is
+// positive, below is negative
+const POSITIVE_WORDS: &[&str] = &[
+    "good", "great", "love", "happy", "thanks", "thank", "excellent", "awesome", "nice", "glad", "wonderful",
+    "perfect", "amazing", "fantastic", "yes", "fun", "beautiful", "enjoy", "pleased", "brilliant",
+];
+const NEGATIVE_WORDS: &[&str] = &[
+    "bad", "hate", "angry", "sad", "terrible", "awful", "no", "wrong", "problem", "broken", "worst", "upset",
+    "annoying", "horrible", "fail", "failed", "stop", "help", "sorry", "hurt",
+];
+
+/////////////////////////////////////////////////////////////
+// Rule
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    // Set by the server; ignored in requests
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // "transcript" or "response"
+    #[serde(default = "transcript")]
+    pub on: String,
+    #[serde(default)]
+    pub when: Conditions,
+    pub then: Vec<Action>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn transcript() -> String {
+    "transcript".to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conditions {
+    pub keywords: Vec<String>,
+    pub speaker: Option<String>,
+    pub sentiment: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    Webhook {
+        url: String,
+        #[serde(default)]
+        secret: String,
+    },
+    Mqtt {
+        topic: String,
+        #[serde(default)]
+        payload: Option<String>,
+        #[serde(default)]
+        retain: bool,
+    },
+    Gpio {
+        pin: u32,
+        #[serde(default = "enabled")]
+        high: bool,
+        #[serde(default)]
+        pulse_ms: Option<u64>,
+    },
+    Tts {
+        text: String,
+    },
+}
+
+impl Rule {
+    // Everything that's wrong with it, one per line
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push("name can't be empty".to_string());
+        }
+        if !matches!(self.on.as_str(), "transcript" | "response") {
+            problems.push(format!("on must be \"transcript\" or \"response\", not {:?}", self.on));
+        }
+        if let Some(sentiment) = &self.when.sentiment {
+            if !matches!(sentiment.as_str(), "positive" | "negative" | "neutral") {
+                problems.push(format!("when.sentiment must be positive, negative or neutral, not {sentiment:?}"));
+            }
+        }
+        for (field, time) in [("after", &self.when.after), ("before", &self.when.before)] {
+            if time.as_ref().is_some_and(|t| NaiveTime::parse_from_str(t, "%H:%M").is_err()) {
+                problems.push(format!("when.{field} must be HH:MM"));
+            }
+        }
+        if self.then.is_empty() {
+            problems.push("then needs at least one action".to_string());
+        }
+        for action in &self.then {
+            match action {
+                Action::Webhook { url, .. } if !url.starts_with("http://") && !url.starts_with("https://") => {
+                    problems.push(format!("webhook url {url:?} must be http:// or https://"));
+                }
+                Action::Mqtt { topic, .. } if topic.is_empty() => problems.push("mqtt topic can't be empty".to_string()),
+                Action::Tts { text } if text.trim().is_empty() => problems.push("tts text can't be empty".to_string()),
+                _ => {}
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    fn matches(&self, event: &str, audio_source: &str, text: &str, now: NaiveTime) -> bool {
+        let when = &self.when;
+        let time = |t: &Option<String>| t.as_deref().and_then(|t| NaiveTime::parse_from_str(t, "%H:%M").ok());
+        let in_window = match (time(&when.after), time(&when.before)) {
+            (Some(after), Some(before)) if after > before => now >= after || now < before,
+            (after, before) => after.is_none_or(|a| now >= a) && before.is_none_or(|b| now < b),
+        };
+        self.enabled
+            && self.on == event
+            && in_window
+            && when.speaker.as_ref().is_none_or(|s| s == audio_source)
+            && when.sentiment.as_ref().is_none_or(|s| s == sentiment(text))
+            && (when.keywords.is_empty() || when.keywords.iter().any(|k| notify::find_word(text, k).is_some()))
+    }
+}
+
+fn sentiment(text: &str) -> &'static str {
+    let score: i32 = text
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| {
+            let word = word.to_lowercase();
+            if POSITIVE_WORDS.contains(&word.as_str()) {
+                1
+            } else if NEGATIVE_WORDS.contains(&word.as_str()) {
+                -1
+            } else {
+                0
+            }
+        })
+        .sum();
+    match score {
+        s if s > 0 => "positive",
+        s if s < 0 => "negative",
+        _ => "neutral",
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Rules
+//
+// The rules and the file they're kept in.
+/////////////////////////////////////////////////////////////
+pub struct Rules {
+    rules: RwLock<Vec<Rule>>,
+    path: PathBuf,
+}
+
+impl Rules {
+    // No file yet is no rules
+    pub fn load(path: &str) -> Result<Rules> {
+        let path = PathBuf::from(path);
+        let rules = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("{} isn't a list of rules", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        tracing::debug!(rules = rules.len(), file = %path.display(), "loaded rules");
+        Ok(Rules {
+            rules: RwLock::new(rules),
+            path,
+        })
+    }
+
+    // Through a temporary file, so a crash never leaves half a list
+    async fn save(&self, rules: &[Rule]) -> Result<()> {
+        let text = serde_json::to_string_pretty(rules)?;
+        let tmp = tmp_path(&self.path);
+        tokio::fs::write(&tmp, text)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/////////////////////////////////////////////////////////////
+// check
+//
+// Called with each logged record; `event` is "transcript" or
+// "response". Returns right away, actions run in background
+// tasks.
+/////////////////////////////////////////////////////////////
+pub async fn check(app_data: &web::Data<AppState>, event: &str, record: &Value) {
+    let text = record["text"].as_str().unwrap_or_default();
+    if event == "response" && pipeline::is_listening(text) {
+        return;
+    }
+    let audio_source = record["audio_source"].as_str().unwrap_or_default();
+    let now = Local::now().time();
+    let fired: Vec<Rule> = app_data
+        .rules
+        .rules
+        .read()
+        .await
+        .iter()
+        .filter(|rule| rule.matches(event, audio_source, text, now))
+        .cloned()
+        .collect();
+    if fired.is_empty() {
+        return;
+    }
+    let settings = app_data.config.read().await.rules.clone();
+
+    for rule in fired {
+        tracing::info!(rule = %rule.name, source = audio_source, "rule matched");
+        for action in rule.then.clone() {
+            let span = tracing::info_span!(parent: None, "rule", rule = %rule.name, id = %rule.id);
+            let (data, rule, record, settings) = (app_data.clone(), rule.clone(), record.clone(), settings.clone());
+            let run = async move {
+                match run_action(&data, &settings, &rule, &action, &record).await {
+                    Ok(()) => tracing::debug!(action = ?action, "rule action done"),
+                    Err(e) => tracing::warn!(error = %format!("{e:#}"), "rule action failed"),
+                }
+            };
+            app_data.tasks.spawn("rule", run.instrument(span));
+        }
+    }
+}
+
+async fn run_action(
+    app_data: &web::Data<AppState>,
+    settings: &crate::config::RulesSettings,
+    rule: &Rule,
+    action: &Action,
+    record: &Value,
+) -> Result<()> {
+    match action {
+        Action::Webhook { url, secret } => {
+            let target = WebhookTarget {
+                url: url.clone(),
+                secret: secret.clone(),
+                events: Vec::new(),
+            };
+            let data = json!({ "rule": { "id": rule.id, "name": rule.name }, "record": record });
+            webhooks::send_to(app_data, target, "rule", data).await;
+            Ok(())
+        }
+        Action::Mqtt { topic, payload, retain } => {
+            let payload = match payload {
+                Some(template) => fill(template, rule, record),
+                None => record.to_string(),
+            };
+            publish(app_data, topic, payload, *retain)
+        }
+        Action::Gpio { pin, high, pulse_ms } => {
+            set_gpio(&settings.gpio_dir, *pin, *high).await?;
+            if let Some(ms) = pulse_ms {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+                set_gpio(&settings.gpio_dir, *pin, !high).await?;
+            }
+            Ok(())
+        }
+        Action::Tts { text } => speak(&settings.tts_command, &fill(text, rule, record)).await,
+    }
+}
+
+#[cfg(feature = "mqtt")]
+pub(crate) fn publish(app_data: &AppState, topic: &str, payload: String, retain: bool) -> Result<()> {
+    app_data.mqtt.publish(topic, payload, retain)
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub(crate) fn publish(_app_data: &AppState, _topic: &str, _payload: String, _retain: bool) -> Result<()> {
+    anyhow::bail!("built without the \"mqtt\" feature")
+}
+
+// The placeholders in a payload/text template
+fn fill(template: &str, rule: &Rule, record: &Value) -> String {
+    let field = |name: &str| record[name].as_str().unwrap_or_default().to_string();
+    template
+        .replace("{text}", &field("text"))
+        .replace("{audio_source}", &field("audio_source"))
+        .replace("{session_id}", &field("session_id"))
+        .replace("{rule}", &rule.name)
+}
+
+async fn set_gpio(gpio_dir: &str, pin: u32, high: bool) -> Result<()> {
+    let dir = Path::new(gpio_dir);
+    let pin_dir = dir.join(format!("gpio{pin}"));
+    if !tokio::fs::try_exists(&pin_dir).await.unwrap_or(false) {
+        tokio::fs::write(dir.join("export"), pin.to_string())
+            .await
+            .with_context(|| format!("Failed to export GPIO {pin} under {gpio_dir}"))?;
+        tokio::fs::write(pin_dir.join("direction"), "out")
+            .await
+            .with_context(|| format!("Failed to make GPIO {pin} an output"))?;
+    }
+    tokio::fs::write(pin_dir.join("value"), if high { "1" } else { "0" })
+        .await
+        .with_context(|| format!("Failed to set GPIO {pin}"))
+}
+
+pub(crate) async fn speak(command: &str, text: &str) -> Result<()> {
+    let mut words = command.split_whitespace();
+    let program = words.next().context("rules.tts_command is empty")?;
+    let mut child = tokio::process::Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+    let status = tokio::time::timeout(TTS_TIMEOUT, child.wait())
+        .await
+        .with_context(|| format!("{program} took over {}s", TTS_TIMEOUT.as_secs()))??;
+    if !status.success() {
+        anyhow::bail!("{program} exited with {status}");
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// /rules
+/////////////////////////////////////////////////////////////
+fn invalid(detail: String) -> ApiError {
+    ApiError::bad_request("invalid_rule", "Invalid rule").with_detail(detail)
+}
+
+fn unknown(id: &str) -> ApiError {
+    ApiError::not_found("unknown_rule", format!("No rule with id {id}"))
+}
+
+fn save_failed(e: anyhow::Error) -> ApiError {
+    tracing::error!(error = %format!("{e:#}"), "couldn't save the rules");
+    ApiError::internal("rules_not_saved", "Couldn't save the rules").with_detail(format!("{e:#}"))
+}
+
+#[utoipa::path(
+    tag = "rules",
+    path = "/rules",
+    responses(
+        (status = 200, description = "Every rule", body = [Rule]),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("")]
+async fn list_rules(app_data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(&*app_data.rules.rules.read().await)
+}
+
+#[utoipa::path(
+    tag = "rules",
+    path = "/rules",
+    request_body = Rule,
+    responses(
+        (status = 201, description = "The new rule, with its id", body = Rule),
+        (status = 400, description = "Malformed or invalid rule (code invalid_json / invalid_rule)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("")]
+async fn create_rule(app_data: web::Data<AppState>, rule: web::Json<Rule>) -> Result<HttpResponse, ApiError> {
+    let mut rule = rule.into_inner();
+    rule.validate().map_err(invalid)?;
+    rule.id = logging::new_id();
+
+    let mut rules = app_data.rules.rules.write().await;
+    let mut updated = rules.clone();
+    updated.push(rule.clone());
+    app_data.rules.save(&updated).await.map_err(save_failed)?;
+    *rules = updated;
+    tracing::info!(rule = %rule.name, id = %rule.id, "rule added");
+    Ok(HttpResponse::Created().json(rule))
+}
+
+#[utoipa::path(
+    tag = "rules",
+    path = "/rules/{id}",
+    params(("id" = String, Path, description = "Rule id")),
+    responses(
+        (status = 200, description = "The rule", body = Rule),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 404, description = "No such rule (code unknown_rule)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[get("/{id}")]
+async fn get_rule(app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let rules = app_data.rules.rules.read().await;
+    let rule = rules.iter().find(|r| r.id == *id).ok_or_else(|| unknown(&id))?;
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[utoipa::path(
+    tag = "rules",
+    path = "/rules/{id}",
+    params(("id" = String, Path, description = "Rule id")),
+    request_body = Rule,
+    responses(
+        (status = 200, description = "The updated rule", body = Rule),
+        (status = 400, description = "Malformed or invalid rule (code invalid_json / invalid_rule)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 404, description = "No such rule (code unknown_rule)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[put("/{id}")]
+async fn update_rule(
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    rule: web::Json<Rule>,
+) -> Result<HttpResponse, ApiError> {
+    let mut rule = rule.into_inner();
+    rule.validate().map_err(invalid)?;
+    rule.id = id.into_inner();
+
+    let mut rules = app_data.rules.rules.write().await;
+    let mut updated = rules.clone();
+    let slot = updated.iter_mut().find(|r| r.id == rule.id).ok_or_else(|| unknown(&rule.id))?;
+    *slot = rule.clone();
+    app_data.rules.save(&updated).await.map_err(save_failed)?;
+    *rules = updated;
+    tracing::info!(rule = %rule.name, id = %rule.id, "rule updated");
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[utoipa::path(
+    tag = "rules",
+    path = "/rules/{id}",
+    params(("id" = String, Path, description = "Rule id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 404, description = "No such rule (code unknown_rule)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[delete("/{id}")]
+async fn delete_rule(app_data: web::Data<AppState>, id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let mut rules = app_data.rules.rules.write().await;
+    let mut updated = rules.clone();
+    let before = updated.len();
+    updated.retain(|r| r.id != *id);
+    if updated.len() == before {
+        return Err(unknown(&id));
+    }
+    app_data.rules.save(&updated).await.map_err(save_failed)?;
+    *rules = updated;
+    tracing::info!(id = %id, "rule deleted");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/rules")
+            .wrap(from_fn(admin::require_admin))
+            .service(list_rules)
+            .service(create_rule)
+            .service(get_rule)
+            .service(update_rule)
+            .service(delete_rule),
+    );
+}
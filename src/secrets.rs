@@ -0,0 +1,363 @@
+/////////////////////////////////////////////////////////////
+// src/secrets.rs
+//
+// Credentials that live outside the config file and the
+// environment. Any secret setting (openai.api_key, admin.token,
+// the bot tokens: everything Config::masked hides) can hold a
+// reference instead of the value, in the file or its env var:
+//   file:/etc/silentnight/openai.key  - the file's contents; it
+//                                       mustn't be readable by
+//                                       group or others (chmod 600)
+//   keyring:openai                    - the OS keyring: secret-tool
+//                                       (libsecret) on Linux, the
+//                                       login keychain on macOS;
+//                                       service "silentnight"
+//   systemd:openai_api_key            - a systemd credential
+//                                       (LoadCredential=) from
+//                                       $CREDENTIALS_DIRECTORY
+// e.g. OPENAI_API_KEY=file:/run/secrets/openai_api_key. They're
+// read whenever the config is loaded or reloaded; one that can't
+// be read rejects the config.
+//
+// Every secret in use (and every one used since startup) is
+// replaced with ******** in text and JSON log lines and in the
+// errors GET /status shows. journald gets tracing's fields as
+// they are, so it isn't covered.
+//
+//   POST /admin/keys  - {"provider": "openai", "key": "sk-..."}
+// swaps a provider's key without a restart (openai, notion or
+// slack, whose webhook URL is its key). A key read from file: or
+// keyring: is written back there; any other is kept in memory,
+// across reloads, until the server restarts. Same access as
+// /admin (see admin.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::{post, web, HttpResponse};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::AppState;
+
+const MASK: &str = "********";
+// The keyring service everything is stored under
+const KEYRING_SERVICE: &str = "silentnight";
+// Shorter values would mask ordinary words in the logs
+const MIN_SCRUBBED_LEN: usize = 8;
+// Providers whose key can change while running, and the setting
+// holding it
+const PROVIDERS: [(&str, &str); 3] = [
+    ("openai", "openai.api_key"),
+    ("notion", "export.notion_token"),
+    ("slack", "alerts.slack_webhook_url"),
+];
+
+// Values scrubbed from the logs; never shrinks, so old keys stay
+// hidden after a rotation
+static KNOWN: RwLock<Vec<String>> = RwLock::new(Vec::new());
+// Keys rotated in memory, by setting, for reloads to keep
+static ROTATED: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/////////////////////////////////////////////////////////////
+// Reference
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug)]
+enum Reference {
+    File(PathBuf),
+    Keyring(String),
+    Systemd(String),
+}
+
+impl Reference {
+    fn parse(value: &str) -> Option<Reference> {
+        if let Some(path) = value.strip_prefix("file:") {
+            return Some(Reference::File(PathBuf::from(path)));
+        }
+        if let Some(name) = value.strip_prefix("keyring:") {
+            return Some(Reference::Keyring(name.to_string()));
+        }
+        value.strip_prefix("systemd:").map(|name| Reference::Systemd(name.to_string()))
+    }
+
+    // "file", "keyring" or "systemd"
+    fn kind(&self) -> &'static str {
+        match self {
+            Reference::File(_) => "file",
+            Reference::Keyring(_) => "keyring",
+            Reference::Systemd(_) => "systemd",
+        }
+    }
+
+    fn read(&self) -> Result<String> {
+        let secret = match self {
+            Reference::File(path) => read_private_file(path)?,
+            Reference::Keyring(name) => keyring_lookup(name)?,
+            Reference::Systemd(name) => {
+                if name.is_empty() || name.contains('/') {
+                    bail!("systemd credential names can't be empty or contain '/', got {name:?}");
+                }
+                let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") else {
+                    bail!("$CREDENTIALS_DIRECTORY isn't set; add LoadCredential={name}:<file> to the unit");
+                };
+                let path = Path::new(&dir).join(name);
+                std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?
+            }
+        };
+        let secret = secret.trim_end_matches(['\r', '\n']).to_string();
+        if secret.is_empty() {
+            bail!("{} is empty", self.kind());
+        }
+        Ok(secret)
+    }
+
+    // Whether write() can store a new value here
+    fn writable(&self) -> bool {
+        !matches!(self, Reference::Systemd(_))
+    }
+
+    fn write(&self, secret: &str) -> Result<()> {
+        match self {
+            Reference::File(path) => write_private_file(path, secret),
+            Reference::Keyring(name) => keyring_store(name, secret),
+            Reference::Systemd(_) => bail!("systemd credentials are read-only"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_private_file(path: &Path) -> Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        bail!("{} can be read by other users (mode {:o}); chmod 600 it", path.display(), mode & 0o777);
+    }
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn read_private_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+// Through a temporary file created 0600, so the key is never
+// readable by anyone else or half-written
+fn write_private_file(path: &Path, secret: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    writeln!(file, "{secret}").with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_lookup(name: &str) -> Result<String> {
+    run_keyring("security", &["find-generic-password", "-s", KEYRING_SERVICE, "-a", name, "-w"], None)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn keyring_lookup(name: &str) -> Result<String> {
+    run_keyring("secret-tool", &["lookup", "service", KEYRING_SERVICE, "key", name], None)
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_store(name: &str, secret: &str) -> Result<()> {
+    // security only takes the password as an argument
+    run_keyring("security", &["add-generic-password", "-U", "-s", KEYRING_SERVICE, "-a", name, "-w", secret], None)
+        .map(|_| ())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn keyring_store(name: &str, secret: &str) -> Result<()> {
+    let label = format!("SilentNight {name}");
+    let args = ["store", "--label", &label, "service", KEYRING_SERVICE, "key", name];
+    run_keyring("secret-tool", &args, Some(secret)).map(|_| ())
+}
+
+fn run_keyring(program: &str, args: &[&str], stdin: Option<&str>) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Couldn't run {program} to reach the keyring"))?;
+    if let (Some(secret), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(secret.as_bytes()).with_context(|| format!("Failed to pass the key to {program}"))?;
+    }
+    let output = child.wait_with_output().with_context(|| format!("{program} failed"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{program} failed ({}): {}; is it in the keyring?", output.status, stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/////////////////////////////////////////////////////////////
+// resolve
+//
+// Replaces references in `config` with what they point at and
+// applies keys rotated in memory. Config::load calls it before
+// validating.
+/////////////////////////////////////////////////////////////
+pub fn resolve(config: &mut Config) -> Result<()> {
+    let rotated = lock_rotated().clone();
+    let mut references = BTreeMap::new();
+    let mut problems = Vec::new();
+    for (setting, value) in config.secrets_mut() {
+        if let Some(reference) = Reference::parse(value) {
+            match reference.read() {
+                Ok(secret) => {
+                    references.insert(setting.clone(), std::mem::replace(value, secret));
+                }
+                Err(e) => problems.push(format!("{setting} ({}): {e:#}", value)),
+            }
+        }
+        if let Some(key) = rotated.get(&setting) {
+            *value = key.clone();
+        }
+        remember(value);
+    }
+    config.secret_references = references;
+    if !problems.is_empty() {
+        bail!("Couldn't read secrets:\n  - {}", problems.join("\n  - "));
+    }
+    Ok(())
+}
+
+fn remember(secret: &str) {
+    if secret.len() < MIN_SCRUBBED_LEN {
+        return;
+    }
+    let mut known = KNOWN.write().unwrap_or_else(std::sync::PoisonError::into_inner);
+    if !known.iter().any(|k| k == secret) {
+        known.push(secret.to_string());
+        // Longest first, so a key containing another is masked whole
+        known.sort_by_key(|k| std::cmp::Reverse(k.len()));
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// redact
+//
+// `text` with every secret in use masked.
+/////////////////////////////////////////////////////////////
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let known = KNOWN.read().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let mut text = Cow::Borrowed(text);
+    for secret in known.iter() {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), MASK));
+        }
+    }
+    text
+}
+
+pub fn redact_opt(text: Option<String>) -> Option<String> {
+    text.map(|t| redact(&t).into_owned())
+}
+
+/////////////////////////////////////////////////////////////
+// POST /admin/keys
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct RotateKey {
+    // "openai", "notion" or "slack"
+    provider: String,
+    // For slack, the new webhook URL
+    key: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct RotatedKey {
+    provider: String,
+    // The setting it replaced, e.g. "openai.api_key"
+    setting: String,
+    // Where it's kept: "file" or "keyring" (it survives restarts),
+    // or "memory" (until the next restart)
+    stored_in: String,
+}
+
+#[utoipa::path(
+    tag = "admin",
+    path = "/admin/keys",
+    request_body = RotateKey,
+    responses(
+        (status = 200, description = "The provider's key was replaced", body = RotatedKey),
+        (status = 400, description = "Unknown provider or empty key (code invalid_key)", body = ErrorBody),
+        (status = 401, description = "Missing or wrong admin token (code admin_token_required)", body = ErrorBody),
+        (status = 403, description = "No admin token or login configured (code admin_disabled)", body = ErrorBody),
+        (status = 500, description = "Its file or the keyring couldn't be written; nothing changed (code key_not_stored)", body = ErrorBody),
+    ),
+    security(("admin_token" = [])),
+)]
+#[post("/keys")]
+async fn rotate_key(app_data: web::Data<AppState>, body: web::Json<RotateKey>) -> Result<HttpResponse, ApiError> {
+    let RotateKey { provider, key } = body.into_inner();
+    let Some((_, setting)) = PROVIDERS.iter().find(|(name, _)| *name == provider) else {
+        let names: Vec<&str> = PROVIDERS.iter().map(|(name, _)| *name).collect();
+        return Err(ApiError::bad_request(
+            "invalid_key",
+            format!("provider must be one of {}, got {provider:?}", names.join(", ")),
+        ));
+    };
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        return Err(ApiError::bad_request("invalid_key", "key can't be empty"));
+    }
+    if Reference::parse(&key).is_some() {
+        return Err(ApiError::bad_request("invalid_key", "key must be the key itself, not a file:, keyring: or systemd: reference"));
+    }
+    remember(&key);
+
+    let mut live = app_data.config.write().await;
+    let reference = live.secret_references.get(*setting).and_then(|r| Reference::parse(r)).filter(Reference::writable);
+    let stored_in = match reference {
+        Some(reference) => {
+            let stored = reference.clone();
+            let secret = key.clone();
+            web::block(move || stored.write(&secret))
+                .await
+                .map_err(|e| ApiError::internal("key_not_stored", "Couldn't store the key").with_detail(e))?
+                .map_err(|e| {
+                    ApiError::internal("key_not_stored", format!("Couldn't store the key in its {}", reference.kind()))
+                        .with_detail(format!("{e:#}"))
+                })?;
+            lock_rotated().remove(*setting);
+            reference.kind()
+        }
+        None => {
+            lock_rotated().insert(setting.to_string(), key.clone());
+            "memory"
+        }
+    };
+    if let Some((_, value)) = live.secrets_mut().into_iter().find(|(name, _)| name == setting) {
+        *value = key;
+    }
+    drop(live);
+
+    tracing::warn!(provider, stored_in, "key rotated");
+    Ok(HttpResponse::Ok().json(RotatedKey {
+        provider,
+        setting: setting.to_string(),
+        stored_in: stored_in.to_string(),
+    }))
+}
+
+fn lock_rotated() -> std::sync::MutexGuard<'static, BTreeMap<String, String>> {
+    ROTATED.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
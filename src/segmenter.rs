@@ -0,0 +1,177 @@
+/////////////////////////////////////////////////////////////
+// src/segmenter.rs
+//
+// Speech chunking (audio.chunking = "speech"): instead of
+// recording chunk_secs at a time, the mic runs without stopping
+// (see Recorder::listen) and a chunk ends at the first pause of
+// audio.pause_ms once it's audio.min_chunk_secs long, or at
+// audio.max_chunk_secs if nobody pauses. Sentences aren't cut
+// mid-word, and silence isn't sent to Whisper: before anyone
+// speaks only a pause's worth of lead-in is kept, and a chunk
+// ends halfway into the pause that ended it.
+//
+// What's been heard since the last cut is buffered in memory,
+// never more than max_chunk_secs of it. Speech is judged by
+// loudness per 30 ms frame (see wav::is_speech).
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use futures_util::stream::{Fuse, StreamExt};
+use std::time::Duration;
+
+use crate::config::AudioConfig;
+use crate::error::AudioError;
+use crate::recorder::AudioStream;
+use crate::wav::{self, WavInfo, SPEECH_FRAME};
+
+// A header longer than this isn't coming
+const MAX_HEADER: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub min: Duration,
+    pub max: Duration,
+    pub pause: Duration,
+}
+
+impl Settings {
+    pub fn new(audio: &AudioConfig) -> Settings {
+        Settings {
+            min: Duration::from_secs(audio.min_chunk_secs.into()),
+            max: Duration::from_secs(audio.max_chunk_secs.into()),
+            pause: Duration::from_millis(audio.pause_ms.into()),
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Listening
+//
+// A source's running mic and what's been heard since the last
+// chunk. Dropping it stops the mic.
+/////////////////////////////////////////////////////////////
+pub struct Listening {
+    audio: Fuse<AudioStream>,
+    segmenter: Segmenter,
+}
+
+impl Listening {
+    pub fn new(audio: AudioStream) -> Listening {
+        Listening {
+            audio: audio.fuse(),
+            segmenter: Segmenter::default(),
+        }
+    }
+
+    // The next chunk, as a WAV file; an error means the mic has
+    // to be started again
+    pub async fn next_chunk(&mut self, settings: Settings) -> Result<Bytes, AudioError> {
+        loop {
+            if let Some(chunk) = self.segmenter.cut(settings) {
+                return Ok(chunk.into());
+            }
+            match self.audio.next().await {
+                Some(Ok(bytes)) => self.segmenter.push(&bytes)?,
+                Some(Err(e)) => return Err(e),
+                // The mic command ended by itself
+                None => return self.segmenter.finish().map(Bytes::from).ok_or(AudioError::Stopped),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Segmenter {
+    // What's come before the header is complete
+    head: Vec<u8>,
+    // The header's bytes and what they say, once read
+    format: Option<(Vec<u8>, WavInfo)>,
+    // Audio since the last cut
+    buffer: Vec<u8>,
+    // Bytes of buffer judged so far, in whole frames
+    judged: usize,
+    // Of the judged audio: how much is speech, and how long it's
+    // been quiet at the end
+    speech: Duration,
+    quiet: Duration,
+}
+
+impl Segmenter {
+    fn push(&mut self, bytes: &[u8]) -> Result<(), AudioError> {
+        if self.format.is_some() {
+            self.buffer.extend_from_slice(bytes);
+            return Ok(());
+        }
+        self.head.extend_from_slice(bytes);
+        match wav::parse(&self.head) {
+            Ok(info) => {
+                let mut head = std::mem::take(&mut self.head);
+                self.buffer = head.split_off(info.data_offset);
+                self.format = Some((head, info));
+                Ok(())
+            }
+            // Most likely not all there yet
+            Err(_) if self.head.len() < MAX_HEADER => Ok(()),
+            Err(e) => Err(AudioError::InvalidWav(e)),
+        }
+    }
+
+    // A chunk if one has ended
+    fn cut(&mut self, settings: Settings) -> Option<Vec<u8>> {
+        let (header, info) = self.format.as_ref()?;
+        let frame = wav::frame_len(info);
+        let frame_time = |bytes: usize| SPEECH_FRAME * (bytes / frame) as u32;
+        while self.judged + frame <= self.buffer.len() {
+            let loud = wav::is_speech(&self.buffer[self.judged..self.judged + frame], info);
+            self.judged += frame;
+            if loud {
+                self.speech += SPEECH_FRAME;
+                self.quiet = Duration::ZERO;
+            } else {
+                self.quiet += SPEECH_FRAME;
+            }
+
+            if self.speech.is_zero() {
+                // Nobody has said anything yet: keep a pause's worth of lead-in
+                let keep = (settings.pause.as_millis() / SPEECH_FRAME.as_millis()).max(1) as usize * frame;
+                if self.judged > keep {
+                    let dropped = self.judged - keep;
+                    self.buffer.drain(..dropped);
+                    self.judged = keep;
+                    self.quiet = frame_time(keep);
+                }
+                continue;
+            }
+            let length = frame_time(self.judged);
+            let paused = length >= settings.min && self.quiet >= settings.pause;
+            if !paused && length < settings.max {
+                continue;
+            }
+            // Halfway into the pause; the rest is the next chunk's lead-in
+            let trailing = if paused { self.quiet.as_millis() / SPEECH_FRAME.as_millis() / 2 } else { 0 } as usize * frame;
+            let end = self.judged - trailing;
+            let chunk = wav::with_data(header, &[&self.buffer[..end]]);
+            self.buffer.drain(..end);
+            self.judged -= end;
+            self.speech = Duration::ZERO;
+            self.quiet = frame_time(self.judged);
+            return Some(chunk);
+        }
+        None
+    }
+
+    // Whatever's left when the mic stops, if anyone spoke in it
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        let (header, info) = self.format.as_ref()?;
+        if self.speech.is_zero() {
+            return None;
+        }
+        let block = info.channels as usize * (info.bits_per_sample / 8) as usize;
+        let end = self.buffer.len() / block * block;
+        let chunk = wav::with_data(header, &[&self.buffer[..end]]);
+        self.buffer.clear();
+        self.judged = 0;
+        self.speech = Duration::ZERO;
+        Some(chunk)
+    }
+}
@@ -0,0 +1,297 @@
+/////////////////////////////////////////////////////////////
+// src/sentry.rs
+//
+// Error reports to Sentry, or a self-hosted GlitchTip, when
+// sentry.dsn is set. Sent as events over the envelope API:
+//   - panics, anywhere (a panic hook, so a recording loop that
+//     the supervisor restarts is reported too)
+//   - a recording loop or background task that failed
+//   - a POST /record_once chunk that failed
+// Each carries the audio source and its mic backend, the chunk
+// length, the chat and speech models, and the last HTTP status
+// OpenAI answered with, as tags and a "pipeline" context.
+//
+// Transcripts stay out of reports: the RECENT_TEXTS latest
+// transcripts are cut from every message (an OpenAI error body
+// may quote one), and so is anything a panic message quotes in
+// backticks. The same error is sent at most once a minute.
+// Delivery is in the background and best effort; settings need a
+// restart.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::config::{Config, SentrySettings};
+use crate::privacy;
+use crate::tasks::TaskManager;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Transcripts kept to scrub from messages
+const RECENT_TEXTS: usize = 20;
+// Shorter ones ("yes", "ok") would scrub ordinary words
+const MIN_SCRUBBED_LEN: usize = 8;
+const SAME_ERROR: Duration = Duration::from_secs(60);
+const SCRUBBED: &str = "[transcript]";
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+// The last OpenAI reply's HTTP status, whether or not reports are on
+static LAST_API_STATUS: AtomicU16 = AtomicU16::new(0);
+
+struct Reporter {
+    environment: String,
+    events: mpsc::UnboundedSender<Value>,
+    recent: Mutex<VecDeque<String>>,
+    sent: Mutex<HashMap<String, Instant>>,
+}
+
+/////////////////////////////////////////////////////////////
+// Dsn
+//
+// {scheme}://{public_key}@{host}[:port][/path]/{project_id}
+/////////////////////////////////////////////////////////////
+#[derive(Debug, Clone)]
+pub struct Dsn {
+    public_key: String,
+    // Everything before /api/
+    base: String,
+    project_id: String,
+}
+
+impl Dsn {
+    pub fn parse(dsn: &str) -> Result<Dsn, String> {
+        let (scheme, rest) = dsn
+            .split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "http" | "https"))
+            .ok_or_else(|| format!("must start with https:// or http://, got {dsn:?}"))?;
+        let (public_key, rest) = rest
+            .split_once('@')
+            .map(|(key, rest)| (key.split(':').next().unwrap_or_default(), rest))
+            .filter(|(key, _)| !key.is_empty())
+            .ok_or_else(|| "is missing the key before the @".to_string())?;
+        let (host_and_path, project_id) = rest
+            .trim_end_matches('/')
+            .rsplit_once('/')
+            .filter(|(host, id)| !host.is_empty() && !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+            .ok_or_else(|| "must end with the numeric project ID".to_string())?;
+        Ok(Dsn {
+            public_key: public_key.to_string(),
+            base: format!("{scheme}://{host_and_path}"),
+            project_id: project_id.to_string(),
+        })
+    }
+
+    fn envelope_url(&self) -> String {
+        format!("{}/api/{}/envelope/", self.base, self.project_id)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// init
+//
+// Starts reporting if sentry.dsn is set: installs the panic hook
+// and the task that sends events.
+/////////////////////////////////////////////////////////////
+pub fn init(settings: &SentrySettings, tasks: &TaskManager) {
+    if settings.dsn.is_empty() {
+        return;
+    }
+    // validate() has checked it
+    let Ok(dsn) = Dsn::parse(&settings.dsn) else { return };
+    let (events, queue) = mpsc::unbounded_channel();
+    let reporter = Reporter {
+        environment: settings.environment.clone(),
+        events,
+        recent: Mutex::new(VecDeque::new()),
+        sent: Mutex::new(HashMap::new()),
+    };
+    if REPORTER.set(reporter).is_err() {
+        return;
+    }
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".to_string());
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line()));
+        let thread = std::thread::current().name().unwrap_or("unnamed").to_string();
+        let mut tags = Map::new();
+        tags.insert("thread".to_string(), thread.into());
+        if let Some(location) = &location {
+            tags.insert("location".to_string(), location.clone().into());
+        }
+        capture("fatal", "panic", &message, tags, Value::Null);
+        previous(info);
+    }));
+
+    tracing::info!(host = %dsn.base, project = %dsn.project_id, "sending error reports");
+    let span = tracing::info_span!(parent: None, "sentry");
+    tasks.spawn("sentry", deliver(dsn, queue, tasks.token()).instrument(span));
+}
+
+async fn deliver(dsn: Dsn, mut queue: mpsc::UnboundedReceiver<Value>, shutdown: tokio_util::sync::CancellationToken) {
+    let client = privacy::client();
+    loop {
+        let event = tokio::select! {
+            event = queue.recv() => match event {
+                Some(event) => event,
+                None => return,
+            },
+            _ = shutdown.cancelled() => return,
+        };
+        if let Err(e) = send(&client, &dsn, &event).await {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't send an error report");
+        }
+    }
+}
+
+async fn send(client: &reqwest::Client, dsn: &Dsn, event: &Value) -> Result<()> {
+    let header = json!({ "event_id": event["event_id"], "sent_at": Utc::now().to_rfc3339() });
+    let body = format!("{header}\n{}\n{event}\n", json!({ "type": "event" }));
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_client=silentnight/{}, sentry_key={}",
+        env!("CARGO_PKG_VERSION"),
+        dsn.public_key
+    );
+    let resp = client
+        .post(dsn.envelope_url())
+        .header("X-Sentry-Auth", auth)
+        .header("Content-Type", "application/x-sentry-envelope")
+        .body(body)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("Sentry unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Sentry returned {status}: {body}");
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////
+// Pipeline hooks
+/////////////////////////////////////////////////////////////
+
+// Every OpenAI reply's status, for the next report's context
+pub fn note_api_status(status: u16) {
+    LAST_API_STATUS.store(status, Ordering::Relaxed);
+}
+
+// A transcript to keep out of reports
+pub fn note_transcript(text: &str) {
+    let Some(reporter) = REPORTER.get() else { return };
+    let text = text.trim();
+    if text.len() < MIN_SCRUBBED_LEN {
+        return;
+    }
+    let mut recent = reporter.recent.lock().unwrap_or_else(PoisonError::into_inner);
+    recent.push_back(text.to_string());
+    if recent.len() > RECENT_TEXTS {
+        recent.pop_front();
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// capture_failure
+//
+// Reports a failure of `task` ("recording", "record_once", a
+// worker's kind) on `audio_source`, if any.
+/////////////////////////////////////////////////////////////
+pub fn capture_failure(config: &Config, task: &str, audio_source: Option<&str>, message: &str) {
+    if REPORTER.get().is_none() {
+        return;
+    }
+    let mut tags = Map::new();
+    tags.insert("task".to_string(), task.into());
+    let mut pipeline = json!({
+        "chunk_secs": config.audio.chunk_secs,
+        "stt_model": config.openai.stt_model,
+        "chat_model": config.openai.chat_model,
+    });
+    if let Some(name) = audio_source {
+        tags.insert("audio_source".to_string(), name.into());
+        if let Some(input) = config.audio.input(name) {
+            tags.insert("backend".to_string(), input.backend.clone().into());
+            pipeline["backend"] = input.backend.into();
+        }
+    }
+    capture("error", task, message, tags, pipeline);
+}
+
+fn capture(level: &str, kind: &str, message: &str, mut tags: Map<String, Value>, mut pipeline: Value) {
+    let Some(reporter) = REPORTER.get() else { return };
+    let message = reporter.scrub(message);
+    {
+        let mut sent = reporter.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        let fingerprint = format!("{kind}\n{message}");
+        if sent.get(&fingerprint).is_some_and(|at| at.elapsed() < SAME_ERROR) {
+            return;
+        }
+        sent.retain(|_, at| at.elapsed() < SAME_ERROR);
+        sent.insert(fingerprint, Instant::now());
+    }
+
+    let status = LAST_API_STATUS.load(Ordering::Relaxed);
+    if !pipeline.is_object() {
+        pipeline = json!({});
+    }
+    pipeline["last_api_status"] = if status == 0 { Value::Null } else { status.into() };
+    if status != 0 {
+        tags.insert("last_api_status".to_string(), status.to_string().into());
+    }
+    let mut event = json!({
+        "event_id": format!("{:032x}", rand::random::<u128>()),
+        "timestamp": Utc::now().to_rfc3339(),
+        "platform": "other",
+        "level": level,
+        "logger": "silentnight",
+        "release": concat!("silentnight@", env!("CARGO_PKG_VERSION")),
+        "exception": { "values": [{ "type": kind, "value": message }] },
+        "tags": tags,
+        "contexts": { "pipeline": pipeline },
+    });
+    if !reporter.environment.is_empty() {
+        event["environment"] = reporter.environment.clone().into();
+    }
+    let _ = reporter.events.send(event);
+}
+
+impl Reporter {
+    fn scrub(&self, message: &str) -> String {
+        let mut message = message.to_string();
+        for text in self.recent.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            message = message.replace(text.as_str(), SCRUBBED);
+        }
+        // `...` is how panics quote a value, e.g. a string that was
+        // sliced wrong
+        let mut scrubbed = String::with_capacity(message.len());
+        let mut parts = message.split('`');
+        if let Some(first) = parts.next() {
+            scrubbed.push_str(first);
+        }
+        let rest: Vec<&str> = parts.collect();
+        for (i, part) in rest.iter().enumerate() {
+            scrubbed.push('`');
+            // An unclosed quote is left as it is
+            if i % 2 == 0 && i + 1 < rest.len() {
+                scrubbed.push_str(SCRUBBED);
+            } else {
+                scrubbed.push_str(part);
+            }
+        }
+        scrubbed
+    }
+}
@@ -0,0 +1,250 @@
+/////////////////////////////////////////////////////////////
+// src/session_settings.rs
+//
+// What each session ran with. POST /start_recording and
+// POST /sources/{name}/start take optional overrides that hold
+// for that session only, whatever the config says meanwhile:
+//   {"persona": "kids", "chunk_secs": 3, "stt_model": "whisper-1",
+//    "language": "de"}
+//   persona    - one of openai.personas; "" = openai.system_prompt
+//   chunk_secs - 1 to 60
+//   stt_model  - for every audio provider (see routing.rs)
+//   language   - the ISO-639-1 code Whisper is told to expect
+// A voice or remote persona switch doesn't change an overridden
+// persona. Without a body, a session starts as before.
+//
+// The settings a session records with, overrides applied, are
+// kept in session_settings.json under its ID, so its log can be
+// read later with what produced it:
+//   GET /sessions/{id}/settings
+//   {"session_id": ..., "audio_source": ..., "overrides": {...},
+//    "snapshots": [{"from": "<RFC 3339>", "settings": {...}}]}
+// A new snapshot is added when the config changes mid-session (a
+// reload, the admin API, a persona switch), from the next chunk
+// on.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::config::{AudioConfig, Config, OpenAiConfig};
+use crate::error::ApiError;
+use crate::routing::{self, Category, Provider};
+use crate::sessions::SourceSession;
+use crate::{workspaces, AppState};
+
+const SETTINGS_FILE: &str = "session_settings.json";
+
+// Writes to SETTINGS_FILE, from any source
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Overrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_secs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stt_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl Overrides {
+    // Why the config can't take them, if it can't
+    pub fn check(&self, config: &Config) -> Result<(), ApiError> {
+        let invalid = |message: String| Err(ApiError::bad_request("invalid_overrides", message));
+        if let Some(persona) = &self.persona {
+            if !persona.is_empty() && !config.openai.personas.contains_key(persona) {
+                return invalid(format!("No persona named {persona:?} in openai.personas"));
+            }
+        }
+        if let Some(secs) = self.chunk_secs {
+            if !(1..=60).contains(&secs) {
+                return invalid(format!("chunk_secs must be between 1 and 60, got {secs}"));
+            }
+        }
+        if self.stt_model.as_ref().is_some_and(|model| model.trim().is_empty()) {
+            return invalid("stt_model can't be empty".to_string());
+        }
+        if let Some(language) = &self.language {
+            if language.is_empty() || !valid_language(language) {
+                return invalid(format!("language must be an ISO-639-1 code such as \"de\", got {language:?}"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn chunk_secs(&self, audio: &AudioConfig) -> u32 {
+        self.chunk_secs.unwrap_or(audio.chunk_secs)
+    }
+
+    // [openai] as the session sees it
+    pub fn openai(&self, openai: &OpenAiConfig) -> OpenAiConfig {
+        let mut openai = openai.clone();
+        if let Some(persona) = &self.persona {
+            openai.persona = persona.clone();
+        }
+        if let Some(model) = &self.stt_model {
+            openai.stt_model = model.clone();
+        }
+        if let Some(language) = &self.language {
+            openai.language = language.clone();
+        }
+        openai
+    }
+
+    // An audio provider as the session sees it
+    pub fn audio(&self, mut provider: Provider) -> Provider {
+        if let Some(model) = &self.stt_model {
+            provider.model = model.clone();
+        }
+        provider
+    }
+}
+
+// Empty (Whisper detects it) or two or three lowercase letters
+pub fn valid_language(code: &str) -> bool {
+    code.is_empty() || ((2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+/////////////////////////////////////////////////////////////
+// Settings
+//
+// What shapes a session's transcripts and responses.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub(crate) struct Settings {
+    mic_backend: String,
+    chunking: String,
+    chunk_secs: u32,
+    // The first audio provider's
+    stt_model: String,
+    // "" = detected
+    language: String,
+    // The first transcript provider's
+    chat_model: String,
+    persona: String,
+    system_prompt: String,
+    temperature: f32,
+    max_tokens: u32,
+    history_messages: usize,
+    history_tokens: usize,
+}
+
+impl Settings {
+    pub fn new(config: &Config, overrides: &Overrides) -> Settings {
+        let openai = overrides.openai(&config.openai);
+        let first = |category| routing::providers(config, category).ok().and_then(|providers| providers.into_iter().next());
+        Settings {
+            mic_backend: config.audio.mic_backend.clone(),
+            chunking: config.audio.chunking.clone(),
+            chunk_secs: overrides.chunk_secs(&config.audio),
+            stt_model: first(Category::Audio).map_or(openai.stt_model.clone(), |provider| overrides.audio(provider).model),
+            language: openai.language.clone(),
+            chat_model: first(Category::Transcripts).map_or(openai.chat_model.clone(), |provider| provider.model),
+            system_prompt: openai.personas.get(&openai.persona).unwrap_or(&openai.system_prompt).clone(),
+            persona: openai.persona,
+            temperature: openai.temperature,
+            max_tokens: openai.max_tokens,
+            history_messages: openai.history_messages,
+            history_tokens: openai.history_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub(crate) struct Snapshot {
+    // RFC 3339; in effect from then until the next one
+    from: String,
+    settings: Settings,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub(crate) struct SessionSettings {
+    session_id: String,
+    audio_source: String,
+    overrides: Overrides,
+    snapshots: Vec<Snapshot>,
+}
+
+/////////////////////////////////////////////////////////////
+// note
+//
+// Keeps the settings `source`'s session records with, if they
+// changed since the last chunk; returns them.
+/////////////////////////////////////////////////////////////
+pub async fn note(app_data: &AppState, source: &SourceSession) -> Option<Settings> {
+    let session_id = source.session_id.borrow().clone()?;
+    let overrides = source.overrides();
+    let settings = {
+        let config = app_data.config.read().await;
+        // A workspace's persona counts (see workspaces.rs)
+        Settings::new(&config, &workspaces::overrides(&config, source))
+    };
+    let mut last = source.settings.lock().await;
+    if last.as_ref() != Some(&settings) {
+        *last = Some(settings.clone());
+        if let Err(e) = save(&session_id, &source.name, &overrides, &settings) {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't keep the session's settings");
+        }
+    }
+    Some(settings)
+}
+
+fn save(session_id: &str, audio_source: &str, overrides: &Overrides, settings: &Settings) -> Result<()> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let mut sessions: Map<String, Value> = std::fs::read_to_string(SETTINGS_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    let mut session = sessions
+        .remove(session_id)
+        .and_then(|session| serde_json::from_value(session).ok())
+        .unwrap_or_else(|| SessionSettings {
+            session_id: session_id.to_string(),
+            audio_source: audio_source.to_string(),
+            overrides: overrides.clone(),
+            snapshots: Vec::new(),
+        });
+    session.snapshots.push(Snapshot { from: Utc::now().to_rfc3339(), settings: settings.clone() });
+    sessions.insert(session_id.to_string(), serde_json::to_value(session)?);
+    let contents = serde_json::to_string_pretty(&sessions)?;
+    let partial = format!("{SETTINGS_FILE}.partial");
+    std::fs::write(&partial, contents).with_context(|| format!("Failed to write {partial}"))?;
+    std::fs::rename(&partial, SETTINGS_FILE).with_context(|| format!("Failed to replace {SETTINGS_FILE}"))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sessions/{id}/settings
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    path = "/sessions/{id}/settings",
+    params(("id" = String, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "The session's overrides and the settings it recorded with", body = SessionSettings),
+        (status = 404, description = "No settings kept for that session (code unknown_session)", body = ErrorBody),
+    ),
+)]
+#[get("/sessions/{id}/settings")]
+async fn session_settings(id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let sessions: Map<String, Value> = std::fs::read_to_string(SETTINGS_FILE)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    match sessions.get(id.as_str()) {
+        Some(session) => Ok(HttpResponse::Ok().json(session)),
+        None => Err(ApiError::not_found("unknown_session", format!("No settings kept for session {id}"))),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(session_settings);
+}
@@ -0,0 +1,554 @@
+/////////////////////////////////////////////////////////////
+// src/sessions.rs
+//
+// Recording sessions, one per audio source. Every source in
+// the config ("default" plus each [[audio.sources]] entry) can
+// record at the same time as the others, with its own
+// recording state (see lifecycle.rs), conversation history,
+// latest transcript and live log.
+//
+//   GET  /sources                    - every source and its state
+//   POST /sources/{name}/start       - start that source's loop
+//   POST /sources/{name}/stop        - stop it (see stop_source)
+//   GET  /sources/{name}/transcript  - its latest transcript/response
+//   GET  /sources/{name}/live_log    - SSE of its records only
+//                                      (same filters as /live_log)
+//
+// The original /start_recording, /stop_recording and
+// /transcript act on "default"; /live_log streams every source.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, middleware, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{watch, Mutex as AsyncMutex};
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::{MicInput, DEFAULT_SOURCE};
+use crate::error::ApiError;
+use crate::events::{EventChannel, LogFilter};
+use crate::lifecycle::{RecordingState, StateChange};
+use crate::recorder::{self, Recorder};
+use crate::routing::{self, Category};
+use crate::speakers::Voices;
+use crate::pipeline::{PipelineMetrics, PipelineStatus};
+use crate::session_settings::{self, Overrides, Settings};
+use crate::{discord, meetings, memory, quiet, rate_limit, secrets, supervisor, webhooks, AppState, TranscriptResponse};
+
+/////////////////////////////////////////////////////////////
+// SourceSession
+//
+// Everything one recording loop reads and writes.
+/////////////////////////////////////////////////////////////
+pub struct SourceSession {
+    pub name: String,
+    // Watch channels, so readers (status, SSE, gRPC) never wait on
+    // the recording loop and can subscribe() to await a change.
+    // Change the state through transition(), which announces it
+    pub state: watch::Sender<RecordingState>,
+    // Set while the loop runs
+    pub session_id: watch::Sender<Option<String>>,
+    // What the current session is, when something more than an ID
+    // is known (a meeting, see meetings.rs and calendar.rs)
+    pub details: watch::Sender<SessionDetails>,
+    // Capture pauses while set; the session goes on (see
+    // control.rs). Cleared when the session ends
+    pub muted: watch::Sender<bool>,
+    // Last transcription from Whisper and GPT's response to it
+    pub latest: watch::Sender<TranscriptResponse>,
+    // Recent (role, content) messages, role is "user" or "assistant"
+    pub conversation_history: AsyncMutex<Vec<(String, String)>>,
+    // GPT's summary of the turns that history no longer holds (see
+    // memory.rs)
+    pub history_summary: AsyncMutex<Option<String>>,
+    // Chunks whose capture began before this were forgotten (see
+    // forget.rs)
+    pub forgotten_until: watch::Sender<Option<Instant>>,
+    // Who's spoken this session, for anonymous labels (see
+    // speakers.rs); cleared when the session ends
+    pub voices: Voices,
+    // The last transcript, for spotting repeats (see dedup.rs);
+    // cleared when the session ends
+    pub last_transcript: AsyncMutex<Option<String>>,
+    // What the session last recorded with (see
+    // session_settings.rs); cleared when it ends
+    pub settings: AsyncMutex<Option<Settings>>,
+    // This source's records only
+    pub events: EventChannel,
+    pub chunks_processed: AtomicU64,
+    pub last_error: AsyncMutex<Option<String>>,
+    pub pipeline: PipelineMetrics,
+    // Built for the input it was last used with
+    recorder: AsyncMutex<Option<(MicInput, Arc<dyn Recorder>)>>,
+}
+
+/////////////////////////////////////////////////////////////
+// SessionDetails
+//
+// Added to the session's log records and its session.started
+// event when set.
+/////////////////////////////////////////////////////////////
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionDetails {
+    pub title: Option<String>,
+    pub attendees: Vec<String>,
+    // Settings that hold for this session only (see
+    // session_settings.rs)
+    pub overrides: Overrides,
+}
+
+impl SourceSession {
+    fn new(name: &str, sse_capacity: usize) -> SourceSession {
+        SourceSession {
+            name: name.to_string(),
+            state: watch::Sender::new(RecordingState::Idle),
+            session_id: watch::Sender::new(None),
+            details: watch::Sender::new(SessionDetails::default()),
+            muted: watch::Sender::new(false),
+            latest: watch::Sender::new(TranscriptResponse::default()),
+            conversation_history: AsyncMutex::new(Vec::new()),
+            history_summary: AsyncMutex::new(memory::load(name)),
+            forgotten_until: watch::Sender::new(None),
+            voices: Voices::default(),
+            last_transcript: AsyncMutex::new(None),
+            settings: AsyncMutex::new(None),
+            events: EventChannel::new(sse_capacity),
+            chunks_processed: AtomicU64::new(0),
+            last_error: AsyncMutex::new(None),
+            pipeline: PipelineMetrics::default(),
+            recorder: AsyncMutex::new(None),
+        }
+    }
+
+    // The current session's overrides (see session_settings.rs)
+    pub fn overrides(&self) -> Overrides {
+        self.details.borrow().overrides.clone()
+    }
+
+    /////////////////////////////////////////////////////////
+    // transition
+    //
+    // Moves to `next` if the current state allows it and sends
+    // the change on the live logs. Err holds the state it was
+    // refused in.
+    /////////////////////////////////////////////////////////
+    pub async fn transition(&self, app_data: &AppState, next: RecordingState) -> Result<(), RecordingState> {
+        self.change_state(&next, || ())?;
+        self.announce(app_data, &next);
+        Ok(())
+    }
+
+    // Checks and sets the state in one step, running `also` with
+    // it still held when the change is allowed
+    fn change_state(&self, next: &RecordingState, also: impl FnOnce()) -> Result<(), RecordingState> {
+        let mut refused = None;
+        self.state.send_if_modified(|state| {
+            if !state.can_become(next) {
+                refused = Some(state.clone());
+                return false;
+            }
+            *state = next.clone();
+            also();
+            true
+        });
+        refused.map_or(Ok(()), Err)
+    }
+
+    fn announce(&self, app_data: &AppState, state: &RecordingState) {
+        tracing::debug!(source = %self.name, state = state.name(), "recording state changed");
+        let change = StateChange {
+            audio_source: &self.name,
+            session_id: self.session_id.borrow().clone(),
+            state,
+        };
+        if let Ok(data) = serde_json::to_string(&change) {
+            app_data.events.publish_notice("state", data.clone());
+            self.events.publish_notice("state", data);
+        }
+    }
+
+    // Kept between chunks so a recorder's state (e.g. which fixture
+    // comes next) carries over; rebuilt when a reload changes the input
+    pub async fn recorder(&self, input: &MicInput) -> Arc<dyn Recorder> {
+        let mut current = self.recorder.lock().await;
+        match &*current {
+            Some((used_for, recorder)) if used_for == input => recorder.clone(),
+            _ => {
+                let recorder = recorder::for_input(input);
+                *current = Some((input.clone(), recorder.clone()));
+                recorder
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// Sources
+//
+// Sessions are created the first time a source is used, so
+// sources added by a config reload work without a restart.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Sources {
+    sessions: AsyncMutex<HashMap<String, Arc<SourceSession>>>,
+}
+
+impl Sources {
+    // None if no source by that name is configured
+    pub async fn get(&self, app_data: &AppState, name: &str) -> Option<Arc<SourceSession>> {
+        let sse_capacity = {
+            let config = app_data.config.read().await;
+            config.audio.input(name)?;
+            config.server.sse_capacity
+        };
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(SourceSession::new(name, sse_capacity)));
+        Some(session.clone())
+    }
+
+    // The session of a source a reload has removed, if it was used
+    pub async fn removed(&self, app_data: &AppState, name: &str) -> Option<Arc<SourceSession>> {
+        if app_data.config.read().await.audio.input(name).is_some() {
+            return None;
+        }
+        self.sessions.lock().await.get(name).cloned()
+    }
+
+    pub async fn default_source(&self, app_data: &AppState) -> Arc<SourceSession> {
+        self.get(app_data, DEFAULT_SOURCE)
+            .await
+            .expect("the default source is always configured")
+    }
+
+    // Every configured source, in config order
+    pub async fn all(&self, app_data: &AppState) -> Vec<Arc<SourceSession>> {
+        let names = app_data.config.read().await.audio.source_names();
+        let mut all = Vec::with_capacity(names.len());
+        for name in names {
+            if let Some(session) = self.get(app_data, &name).await {
+                all.push(session);
+            }
+        }
+        all
+    }
+
+    // Session IDs of every source that is recording right now
+    pub async fn current_session_ids(&self, app_data: &AppState) -> Vec<String> {
+        let mut ids = Vec::new();
+        for source in self.all(app_data).await {
+            ids.extend(source.session_id.borrow().clone());
+        }
+        ids
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// start_source
+//
+// If the source isn't already recording, spawns its
+// record_and_process_audio loop. start_session also gives the
+// session a title and attendees, or settings of its own.
+/////////////////////////////////////////////////////////////
+pub async fn start_source(
+    app_data: &web::Data<AppState>,
+    source: Arc<SourceSession>,
+) -> Result<(), ApiError> {
+    start_session(app_data, source, SessionDetails::default()).await
+}
+
+pub async fn start_session(
+    app_data: &web::Data<AppState>,
+    source: Arc<SourceSession>,
+    details: SessionDetails,
+) -> Result<(), ApiError> {
+    quiet::refuse(app_data).await?;
+    details.overrides.check(&*app_data.config.read().await)?;
+    if let Err(e) = require_openai(app_data).await {
+        // Record-only: the chunks wait in the backlog (see backlog.rs)
+        if !app_data.config.read().await.backlog.enabled {
+            return Err(e);
+        }
+        tracing::warn!(source = %source.name, "no OpenAI API key, recording to the backlog only");
+    }
+
+    let mut session_id = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    if source.name != DEFAULT_SOURCE {
+        session_id = format!("{}-{}", session_id, source.name);
+    }
+    // The session ID is set along with the state, so the "starting" event carries it
+    let started = source.change_state(&RecordingState::Starting, || {
+        source.session_id.send_replace(Some(session_id.clone()));
+        source.details.send_replace(details.clone());
+    });
+    if let Err(state) = started {
+        tracing::info!(source = %source.name, state = state.name(), "already busy");
+        return Err(already_recording(&source, &state));
+    }
+    source.announce(app_data, &RecordingState::Starting);
+    tracing::info!(source = %source.name, session_id = %session_id, "recording started");
+
+    let mut session_event = serde_json::json!({ "audio_source": source.name, "session_id": session_id });
+    if let Some(title) = &details.title {
+        session_event["title"] = title.clone().into();
+        session_event["attendees"] = details.attendees.clone().into();
+    }
+    if details.overrides != Overrides::default() {
+        session_event["overrides"] = serde_json::to_value(&details.overrides).unwrap_or_default();
+    }
+    if let Some(settings) = session_settings::note(app_data, &source).await {
+        session_event["settings"] = serde_json::to_value(settings).unwrap_or_default();
+    }
+    webhooks::send(app_data, "session.started", session_event).await;
+
+    let (generation, cancel) = app_data.tasks.start_recording(&source.name);
+    let shared_state = app_data.clone();
+    let source = source.clone();
+    let span = tracing::info_span!(parent: None, "recording", source = %source.name, session_id = %session_id);
+    app_data.tasks.spawn("recording", async move {
+        let error = supervisor::supervise_recording(&shared_state, &source, &cancel).await;
+        let end = match &error {
+            Some(reason) => RecordingState::Error { reason: reason.clone() },
+            None => RecordingState::Idle,
+        };
+        let _ = source.transition(&shared_state, end).await;
+        source.session_id.send_replace(None);
+        let details = source.details.send_replace(SessionDetails::default());
+        source.muted.send_replace(false);
+        source.voices.clear().await;
+        *source.last_transcript.lock().await = None;
+        *source.settings.lock().await = None;
+        shared_state.tasks.recording_ended(&source.name, generation);
+
+        let session_event = serde_json::json!({
+            "audio_source": source.name,
+            "session_id": session_id,
+            "error": error,
+        });
+        webhooks::send(&shared_state, "session.stopped", session_event).await;
+        discord::session_stopped(&shared_state, &source.name, &session_id).await;
+        #[cfg(feature = "email")]
+        crate::email::session_stopped(&shared_state, &source.name, &session_id).await;
+        // Exported after its minutes, if it's a meeting
+        meetings::session_stopped(&shared_state, &session_id, details).await;
+    }.instrument(span));
+
+    Ok(())
+}
+
+// Cancels the recording: capture stops right away, and chunks
+// already captured finish the pipeline
+pub async fn stop_source(app_data: &AppState, source: &SourceSession) {
+    if source.transition(app_data, RecordingState::Stopping).await.is_ok() {
+        tracing::info!(source = %source.name, "stopping recording");
+    }
+    app_data.tasks.stop_recording(&source.name);
+}
+
+// Every chunk needs Whisper and GPT, so don't start without a key
+// if either goes to OpenAI (see routing.rs)
+pub async fn require_openai(app_data: &AppState) -> Result<(), ApiError> {
+    let config = app_data.config.read().await;
+    let uses_openai = [Category::Audio, Category::Transcripts]
+        .into_iter()
+        .any(|category| routing::uses_built_in(&config, category));
+    if uses_openai && config.openai.api_key.is_empty() {
+        return Err(ApiError::unavailable(
+            "openai_not_configured",
+            "No OpenAI API key configured (openai.api_key / OPENAI_API_KEY)",
+        ));
+    }
+    Ok(())
+}
+
+pub fn already_recording(source: &SourceSession, state: &RecordingState) -> ApiError {
+    let message = if state.is_recording() {
+        format!("Audio source {:?} is already recording", source.name)
+    } else {
+        format!("Audio source {:?} is busy ({})", source.name, state.name())
+    };
+    ApiError::conflict("already_recording", message)
+}
+
+pub fn unknown_source(name: &str) -> ApiError {
+    ApiError::not_found("unknown_source", format!("No audio source named {name:?}"))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sources
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SourceStatus {
+    name: String,
+    mic: String,
+    device: Option<String>,
+    // Capturing, i.e. state is starting or recording
+    pub recording: bool,
+    pub state: RecordingState,
+    pub session_id: Option<String>,
+    // Capture paused from the remote or by voice
+    muted: bool,
+    chunks_processed: u64,
+    last_error: Option<String>,
+    pub conversation_history: usize,
+    sse_subscribers: usize,
+    // Records its live log subscribers missed by falling behind
+    sse_missed: u64,
+    // Per-stage counts for the recording loop
+    pipeline: PipelineStatus,
+}
+
+pub(crate) async fn source_status(app_data: &AppState, source: &SourceSession) -> SourceStatus {
+    let input = app_data.config.read().await.audio.input(&source.name);
+    // Watch borrows must not be held across an await
+    let state = source.state.borrow().clone();
+    let session_id = source.session_id.borrow().clone();
+    SourceStatus {
+        name: source.name.clone(),
+        mic: input.as_ref().map(|i| i.backend.clone()).unwrap_or_default(),
+        device: input.and_then(|i| i.device),
+        recording: state.is_recording(),
+        state,
+        session_id,
+        muted: *source.muted.borrow(),
+        chunks_processed: source.chunks_processed.load(Ordering::Relaxed),
+        last_error: secrets::redact_opt(source.last_error.lock().await.clone()),
+        conversation_history: source.conversation_history.lock().await.len(),
+        sse_subscribers: source.events.subscribers(),
+        sse_missed: source.events.missed_total(),
+        pipeline: source.pipeline.status(),
+    }
+}
+
+#[utoipa::path(tag = "recording", responses((status = 200, description = "Configured audio sources and their state", body = [SourceStatus])))]
+#[get("/sources")]
+async fn list_sources(app_data: web::Data<AppState>) -> impl Responder {
+    let mut statuses = Vec::new();
+    for source in app_data.sources.all(&app_data).await {
+        statuses.push(source_status(&app_data, &source).await);
+    }
+    HttpResponse::Ok().json(statuses)
+}
+
+/////////////////////////////////////////////////////////////
+// POST /sources/{name}/start
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "recording",
+    params(("name" = String, Path, description = "Audio source name")),
+    request_body(content = Option<Overrides>, description = "Optional; settings for this session only"),
+    responses(
+        (status = 200, description = "Recording started", body = String),
+        (status = 400, description = "Overrides the config can't take (code invalid_overrides)", body = ErrorBody),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+        (status = 409, description = "Already recording (code already_recording)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 503, description = "No OpenAI API key configured (code openai_not_configured)", body = ErrorBody),
+    ),
+)]
+#[post("/sources/{name}/start", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn start_named(
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+    body: Option<web::Json<Overrides>>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(source) = app_data.sources.get(&app_data, &name).await else {
+        return Err(unknown_source(&name));
+    };
+
+    let overrides = body.map(web::Json::into_inner).unwrap_or_default();
+    let chunk_secs = overrides.chunk_secs(&app_data.config.read().await.audio);
+    start_session(&app_data, source, SessionDetails { overrides, ..SessionDetails::default() }).await?;
+    Ok(HttpResponse::Ok().body(format!("Recording {} in {}s blocks...", name, chunk_secs)))
+}
+
+/////////////////////////////////////////////////////////////
+// POST /sources/{name}/stop
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "recording",
+    params(("name" = String, Path, description = "Audio source name")),
+    responses(
+        (status = 200, description = "Recording is stopping; chunks already captured still finish", body = String),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+    ),
+)]
+#[post("/sources/{name}/stop")]
+async fn stop_named(
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(source) = app_data.sources.get(&app_data, &name).await else {
+        return Err(unknown_source(&name));
+    };
+
+    stop_source(&app_data, &source).await;
+    Ok(HttpResponse::Ok().body("Recording stopped"))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sources/{name}/transcript
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    params(("name" = String, Path, description = "Audio source name")),
+    responses(
+        (status = 200, description = "Latest transcript and GPT response for this source", body = TranscriptResponse),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+    ),
+)]
+#[get("/sources/{name}/transcript")]
+async fn transcript_named(
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(source) = app_data.sources.get(&app_data, &name).await else {
+        return Err(unknown_source(&name));
+    };
+
+    let latest = source.latest.borrow().clone();
+    Ok(HttpResponse::Ok().json(latest))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /sources/{name}/live_log
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "log",
+    params(
+        ("name" = String, Path, description = "Audio source name"),
+        LogFilter,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Replay records newer than this event ID"),
+    ),
+    responses(
+        (status = 200, description = "SSE stream of this source's log records", content_type = "text/event-stream"),
+        (status = 404, description = "No such source (code unknown_source)", body = ErrorBody),
+    ),
+)]
+#[get("/sources/{name}/live_log")]
+async fn live_log_named(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    name: web::Path<String>,
+    filter: web::Query<LogFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(source) = app_data.sources.get(&app_data, &name).await else {
+        return Err(unknown_source(&name));
+    };
+    let current: Vec<String> = source.session_id.borrow().iter().cloned().collect();
+    Ok(source.events.sse_response(&req, filter.into_inner(), current).await)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_sources)
+        .service(start_named)
+        .service(stop_named)
+        .service(transcript_named)
+        .service(live_log_named);
+}
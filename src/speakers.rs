@@ -0,0 +1,146 @@
+/////////////////////////////////////////////////////////////
+// src/speakers.rs
+//
+// Anonymous speaker labels (speakers.mode = "anonymous"), for
+// rooms where transcripts mustn't be traceable to a person
+// (classrooms, support rooms): each transcript record says who
+// spoke only as "Speaker A", "Speaker B", ...
+//   {"source": "Microphone", "text": ..., "speakers": ["Speaker A"]}
+// in the order they first spoke in the chunk.
+//
+// Labels come from a diarization service at
+// speakers.embedding_url, which answers like opt_out's (see
+// optout.rs): a segment per speaker turn with its embedding. A
+// segment within speakers.threshold (cosine similarity) of a
+// voice already heard this session gets its label, otherwise the
+// next letter. Labels are stable for a session and start again
+// at "Speaker A" in the next one. A meeting with attendees (see
+// meetings.rs) has no more voices than attendees: past that, a
+// segment is whoever it sounds most like. The labels are still
+// letters, never the attendees' names.
+//
+// No embedding is kept beyond that: the session's voices live in
+// memory only and are dropped when it ends, and nothing about a
+// voice is written anywhere. Only pseudonyms reach the log, the
+// live log, webhooks and exports. (Saved audio, audio.save_dir,
+// is still their voices; Config::warnings says so.)
+//
+// A chunk the service can't label is logged without "speakers".
+/////////////////////////////////////////////////////////////
+
+use actix_web::web::Bytes;
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::sessions::SourceSession;
+use crate::{optout, AppState};
+
+// "Speaker A" to "Speaker Z"; anyone after that is whoever they
+// sound most like
+const MAX_SPEAKERS: usize = 26;
+
+/////////////////////////////////////////////////////////////
+// Voices
+//
+// The voices heard in a source's current session.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Voices {
+    inner: AsyncMutex<SessionVoices>,
+}
+
+#[derive(Default)]
+struct SessionVoices {
+    session_id: Option<String>,
+    voices: Vec<Voice>,
+}
+
+struct Voice {
+    label: String,
+    // Mean of the embeddings matched to it
+    centroid: Vec<f32>,
+    segments: u32,
+}
+
+impl Voices {
+    // Forgets every voice; called when a session ends
+    pub async fn clear(&self) {
+        *self.inner.lock().await = SessionVoices::default();
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// label
+//
+// The pseudonyms of who speaks in `audio_data`; empty when
+// speakers.mode is "off" or the service fails.
+/////////////////////////////////////////////////////////////
+pub async fn label(app_data: &AppState, source: &SourceSession, audio_data: &Bytes) -> Vec<String> {
+    let settings = app_data.config.read().await.speakers.clone();
+    if settings.mode != "anonymous" {
+        return Vec::new();
+    }
+    let mut segments = match optout::segments(&settings.embedding_url, audio_data).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            tracing::warn!(error = %format!("{e:#}"), "couldn't label speakers");
+            return Vec::new();
+        }
+    };
+
+    let session_id = source.session_id.borrow().clone();
+    let attendees = source.details.borrow().attendees.len();
+    let max_voices = if attendees > 0 { attendees.min(MAX_SPEAKERS) } else { MAX_SPEAKERS };
+    let mut session = source.voices.inner.lock().await;
+    // A new session, or a one-off chunk: nobody's been heard yet
+    if session.session_id != session_id || session_id.is_none() {
+        *session = SessionVoices { session_id, voices: Vec::new() };
+    }
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let mut labels: Vec<String> = Vec::new();
+    for segment in segments {
+        let label = session.identify(segment.embedding, settings.threshold, max_voices);
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+    labels
+}
+
+impl SessionVoices {
+    fn identify(&mut self, embedding: Vec<f32>, threshold: f64, max_voices: usize) -> String {
+        let full = self.voices.len() >= max_voices;
+        let closest = self
+            .voices
+            .iter_mut()
+            .map(|voice| {
+                let score = optout::similarity(&voice.centroid, &embedding);
+                (voice, score)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match closest {
+            Some((voice, score)) if score >= threshold || full => {
+                voice.absorb(&embedding);
+                voice.label.clone()
+            }
+            _ => {
+                let label = format!("Speaker {}", char::from(b'A' + self.voices.len() as u8));
+                self.voices.push(Voice { label: label.clone(), centroid: embedding, segments: 1 });
+                label
+            }
+        }
+    }
+}
+
+impl Voice {
+    fn absorb(&mut self, embedding: &[f32]) {
+        // From a different model; keep what we have
+        if embedding.len() != self.centroid.len() {
+            return;
+        }
+        self.segments += 1;
+        let weight = 1.0 / self.segments as f32;
+        for (c, e) in self.centroid.iter_mut().zip(embedding) {
+            *c += (e - *c) * weight;
+        }
+    }
+}
@@ -0,0 +1,280 @@
+/////////////////////////////////////////////////////////////
+// src/status.rs
+//
+// Monitoring endpoints:
+//   GET /health        - liveness, always 200 while the server runs
+//   GET /health/ready  - readiness: 200 if a recording could work
+//                        right now, else 503 listing what's wrong
+//                        (mic command or WAV fixture missing, a
+//                        node not checking in, log not writable,
+//                        no OpenAI API key, or in local-only mode
+//                        an OpenAI server that isn't local)
+//   GET /status  - recording state, session, counters, last
+//                  error, queue depths, backends, privacy mode,
+//                  free disk space (see disk.rs), running
+//                  background tasks, uptime and the state
+//                  of each audio source; errors have secrets
+//                  masked (see secrets.rs)
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use utoipa::ToSchema;
+
+use crate::backlog::{self, BacklogStatus};
+use crate::disk::{self, DiskStatus};
+use crate::openai_limit::OpenAiUsage;
+use crate::privacy::{self, PrivacyStatus};
+use crate::recorder;
+use crate::routing::{self, Category, RoutingStatus};
+use crate::events::SubscriberLag;
+use crate::lifecycle::RecordingState;
+use crate::sessions::{source_status, SourceStatus};
+use crate::{hub, secrets, AppState};
+
+/////////////////////////////////////////////////////////////
+// GET /health
+/////////////////////////////////////////////////////////////
+#[utoipa::path(tag = "monitoring", responses((status = 200, description = "Server is up")))]
+#[get("/health")]
+async fn health() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /health/ready
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ReadinessResponse {
+    // "ready" or "not_ready"
+    status: String,
+    checks: Vec<ReadinessCheck>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ReadinessCheck {
+    // "mic:<backend>", "storage" or "openai_api_key"
+    name: String,
+    ok: bool,
+    // What's wrong, when not ok
+    detail: Option<String>,
+}
+
+impl ReadinessCheck {
+    fn new(name: impl Into<String>, problem: Option<String>) -> ReadinessCheck {
+        ReadinessCheck {
+            name: name.into(),
+            ok: problem.is_none(),
+            detail: problem,
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Ready to record", body = ReadinessResponse),
+        (status = 503, description = "Not ready; the failing checks say why", body = ReadinessResponse),
+    ),
+)]
+#[get("/health/ready")]
+async fn ready(app_data: web::Data<AppState>) -> impl Responder {
+    let config = app_data.config.read().await.clone();
+
+    // One check per backend (and fixture path) in use, across every source
+    let mut checks: Vec<ReadinessCheck> = Vec::new();
+    for name in config.audio.source_names() {
+        let Some(input) = config.audio.input(&name) else { continue };
+        // A node's mic is elsewhere; what matters is that it's checking in
+        let check = match input.backend.as_str() {
+            "node" => ReadinessCheck::new(format!("node:{name}"), hub::problem(&app_data, &name).await),
+            _ => ReadinessCheck::new(format!("mic:{}", input.backend), recorder::for_input(&input).problem()),
+        };
+        if !checks.iter().any(|c| c.name == check.name && c.detail == check.detail) {
+            checks.push(check);
+        }
+    }
+    checks.push(ReadinessCheck::new("storage", storage_problem().await));
+    let uses_openai = Category::ALL.into_iter().any(|category| routing::uses_built_in(&config, category));
+    checks.push(ReadinessCheck::new(
+        "openai_api_key",
+        (uses_openai && config.openai.api_key.is_empty()).then(|| "No OpenAI API key configured".to_string()),
+    ));
+    if privacy::is_local_only() {
+        // The first of them that's refused
+        let mut problem = None;
+        for base_url in routing::base_urls(&config) {
+            problem = openai_local_problem(&base_url).await;
+            if problem.is_some() {
+                break;
+            }
+        }
+        checks.push(ReadinessCheck::new("openai_local", problem));
+    }
+
+    let ready = checks.iter().all(|c| c.ok);
+    if !ready {
+        let failing: Vec<&str> = checks.iter().filter(|c| !c.ok).map(|c| c.name.as_str()).collect();
+        tracing::debug!(?failing, "not ready");
+    }
+    let body = ReadinessResponse {
+        status: if ready { "ready" } else { "not_ready" }.to_string(),
+        checks,
+    };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// Local-only mode refuses anything but local Whisper/GPT servers
+async fn openai_local_problem(base_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(base_url).ok()?;
+    let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(443);
+    privacy::check_host(host, port).await.err().map(|e| e.to_string())
+}
+
+// conversation_log.json is appended to in the working directory.
+// If it doesn't exist yet, a probe file stands in for it so the
+// check doesn't create an empty log.
+async fn storage_problem() -> Option<String> {
+    let result = web::block(|| {
+        let log = std::path::Path::new(crate::CONVERSATION_LOG);
+        if log.exists() {
+            return std::fs::OpenOptions::new().append(true).open(log).map(|_| ());
+        }
+        let probe = format!(".{}.probe", crate::CONVERSATION_LOG);
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(&probe)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("Can't write {}: {e}", crate::CONVERSATION_LOG)),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// GET /status
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct StatusResponse {
+    // Any source recording
+    recording: bool,
+    // The default source's state and session
+    state: RecordingState,
+    session_id: Option<String>,
+    // Totals across every source
+    chunks_processed: u64,
+    last_error: Option<String>,
+    queues: QueueDepths,
+    openai: OpenAiUsage,
+    backends: Backends,
+    sources: Vec<SourceStatus>,
+    backlog: BacklogStatus,
+    privacy: PrivacyStatus,
+    disk: DiskStatus,
+    routing: RoutingStatus,
+    // Background tasks still running, by kind ("recording", "webhook", ...)
+    tasks: BTreeMap<String, usize>,
+    // Restarts after a failure since startup, by kind
+    task_restarts: BTreeMap<String, u64>,
+    started_at: String,
+    uptime_secs: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct QueueDepths {
+    // Records buffered in the broadcast channel, not yet read by every subscriber
+    sse_pending: usize,
+    sse_subscribers: usize,
+    // Records held for Last-Event-ID replay
+    sse_replay_buffer: usize,
+    // Records subscribers missed by falling behind, since startup
+    sse_missed: u64,
+    // Connected subscribers that have missed records
+    sse_lagging: Vec<SubscriberLag>,
+    // Summed over every source
+    conversation_history: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Backends {
+    mic: String,
+    // "<provider>/<model>", of the first provider audio and
+    // transcripts may go to
+    stt: String,
+    llm: String,
+    tls: bool,
+    login: bool,
+}
+
+#[utoipa::path(tag = "monitoring", responses((status = 200, description = "Recording state, counters and backends", body = StatusResponse)))]
+#[get("/status")]
+async fn status(app_data: web::Data<AppState>) -> impl Responder {
+    let mut sources = Vec::new();
+    for source in app_data.sources.all(&app_data).await {
+        sources.push(source_status(&app_data, &source).await);
+    }
+    let recording = sources.iter().any(|s| s.recording);
+    let state = sources.first().map(|s| s.state.clone()).unwrap_or_default();
+    let session_id = sources.first().and_then(|s| s.session_id.clone());
+    let last_error = secrets::redact_opt(app_data.last_error.lock().await.clone());
+
+    let queues = QueueDepths {
+        sse_pending: app_data.events.pending(),
+        sse_subscribers: app_data.events.subscribers(),
+        sse_replay_buffer: app_data.events.replay_len().await,
+        sse_missed: app_data.events.missed_total(),
+        sse_lagging: app_data.events.lagging(),
+        conversation_history: sources.iter().map(|s| s.conversation_history).sum(),
+    };
+
+    let config = app_data.config.read().await;
+    let backends = Backends {
+        mic: config.audio.mic_backend.clone(),
+        stt: backend(&config, Category::Audio),
+        llm: backend(&config, Category::Transcripts),
+        tls: app_data.tls_enabled,
+        login: app_data.login_config.read().await.is_some(),
+    };
+
+    HttpResponse::Ok().json(StatusResponse {
+        recording,
+        state,
+        session_id,
+        chunks_processed: app_data.chunks_processed.load(Ordering::Relaxed),
+        last_error,
+        queues,
+        openai: app_data.openai_limiter.usage(),
+        backends,
+        sources,
+        backlog: backlog::status(&app_data).await,
+        privacy: privacy::status(),
+        disk: disk::status(&app_data),
+        routing: routing::status(&config),
+        tasks: app_data.tasks.running(),
+        task_restarts: app_data.tasks.restarts(),
+        started_at: app_data.started_at.to_rfc3339(),
+        uptime_secs: (chrono::Utc::now() - app_data.started_at).num_seconds(),
+    })
+}
+
+fn backend(config: &crate::config::Config, category: Category) -> String {
+    match routing::providers(config, category) {
+        Ok(providers) => format!("{}/{}", providers[0].name, providers[0].model),
+        Err(_) => "none".to_string(),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// configure
+/////////////////////////////////////////////////////////////
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(health).service(ready).service(status);
+}
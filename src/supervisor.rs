@@ -0,0 +1,229 @@
+/////////////////////////////////////////////////////////////
+// src/supervisor.rs
+//
+// Keeps background work running when it fails. A failure (an
+// error, or a panic, which is caught) is logged, counted under
+// the task's kind in GET /status `task_restarts`, and sent on
+// /live_log as an SSE event named "task_failed":
+//
+//   {"task":"recording","audio_source":"default",
+//    "error":"...","restart_in_ms":2000}
+//
+// (restart_in_ms is null when the task gives up). Restarts wait
+// with exponential backoff, from 1s up to a minute.
+//
+//   recording - a retryable pipeline failure or a panic restarts
+//               the source's pipeline in the same session, via
+//               the "restarting" state. A fatal failure, or
+//               MAX_RESTARTS in a row without a chunk getting
+//               through, leaves the source in "error".
+//   workers   - gRPC and the SIGHUP listener are restarted
+//               until shutdown.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use futures_util::FutureExt;
+use serde::Serialize;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::lifecycle::RecordingState;
+use crate::pipeline::record_and_process_audio;
+use crate::sessions::SourceSession;
+use crate::{sentry, AppState};
+
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+// Pipeline restarts in a row before the source gives up
+const MAX_RESTARTS: u32 = 5;
+// A worker that ran this long before failing starts over at FIRST_DELAY
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/////////////////////////////////////////////////////////////
+// Backoff
+/////////////////////////////////////////////////////////////
+struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff { next: FIRST_DELAY }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_DELAY);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next = FIRST_DELAY;
+    }
+}
+
+// Runs `task`, turning a panic into Err(message)
+async fn catch_panic<T>(task: impl Future<Output = T>) -> Result<T, String> {
+    AssertUnwindSafe(task).catch_unwind().await.map_err(panic_message)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    format!("panicked: {message}")
+}
+
+/////////////////////////////////////////////////////////////
+// TaskFailed
+//
+// Data of a "task_failed" SSE event.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize)]
+struct TaskFailed<'a> {
+    task: &'static str,
+    audio_source: Option<&'a str>,
+    error: &'a str,
+    restart_in_ms: Option<u128>,
+}
+
+fn report(app_data: &AppState, failed: TaskFailed, source: Option<&SourceSession>) {
+    if failed.restart_in_ms.is_some() {
+        app_data.tasks.restarted(failed.task);
+    }
+    if let Ok(data) = serde_json::to_string(&failed) {
+        app_data.events.publish_notice("task_failed", data.clone());
+        if let Some(source) = source {
+            source.events.publish_notice("task_failed", data);
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// supervise_recording
+//
+// Runs the source's pipeline until it is stopped or fails for
+// good. Returns the error it gave up on, if any; the caller moves
+// the source to idle or error.
+/////////////////////////////////////////////////////////////
+pub async fn supervise_recording(
+    app_data: &web::Data<AppState>,
+    source: &Arc<SourceSession>,
+    cancel: &CancellationToken,
+) -> Option<String> {
+    let mut backoff = Backoff::new();
+    let mut restarts = 0;
+    loop {
+        let chunks_before = source.chunks_processed.load(Ordering::Relaxed);
+        let run = record_and_process_audio(app_data.clone(), source.clone(), cancel.clone());
+        let (message, retryable) = match catch_panic(run).await {
+            Ok(Ok(())) => return None,
+            Ok(Err(e)) => {
+                let message = e.report();
+                // Panics were reported by the panic hook
+                sentry::capture_failure(&*app_data.config.read().await, "recording", Some(&source.name), &message);
+                (message, e.is_retryable())
+            }
+            Err(panic) => (panic, true),
+        };
+        tracing::error!(error = %message, retryable, "recording loop failed");
+        *source.last_error.lock().await = Some(message.clone());
+        *app_data.last_error.lock().await = Some(format!("[{}] {}", source.name, message));
+
+        // Chunks got through, so this isn't the same failure repeating
+        if source.chunks_processed.load(Ordering::Relaxed) > chunks_before {
+            restarts = 0;
+            backoff.reset();
+        }
+        let give_up = !retryable || restarts >= MAX_RESTARTS || cancel.is_cancelled();
+        let delay = (!give_up).then(|| backoff.next_delay());
+        report(
+            app_data,
+            TaskFailed {
+                task: "recording",
+                audio_source: Some(&source.name),
+                error: &message,
+                restart_in_ms: delay.map(|d| d.as_millis()),
+            },
+            Some(source),
+        );
+        let Some(delay) = delay else {
+            return Some(message);
+        };
+
+        restarts += 1;
+        let restarting = RecordingState::Restarting { reason: message.clone(), attempt: restarts };
+        // Refused if a stop came in meanwhile
+        if source.transition(app_data, restarting).await.is_err() {
+            return Some(message);
+        }
+        tracing::info!(attempt = restarts, delay_ms = delay.as_millis() as u64, "restarting recording loop");
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            // Stopped while waiting: that's a clean stop
+            _ = cancel.cancelled() => return None,
+        }
+        if source.transition(app_data, RecordingState::Starting).await.is_err() {
+            return None;
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn_worker
+//
+// Spawns `run` as a tracked task of `kind` and starts it again
+// (a fresh future from `run`) whenever it fails, until shutdown.
+// Ok(()) means the worker is done and isn't restarted.
+/////////////////////////////////////////////////////////////
+pub fn spawn_worker<F, Fut>(app_data: &web::Data<AppState>, kind: &'static str, mut run: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let shared_state = app_data.clone();
+    let shutdown = app_data.tasks.token();
+    app_data.tasks.spawn(kind, async move {
+        let mut backoff = Backoff::new();
+        loop {
+            let started = Instant::now();
+            let message = match catch_panic(run()).await {
+                Ok(Ok(())) => return,
+                Ok(Err(message)) => {
+                    sentry::capture_failure(&*shared_state.config.read().await, kind, None, &message);
+                    message
+                }
+                Err(message) => message,
+            };
+            if shutdown.is_cancelled() {
+                return;
+            }
+            if started.elapsed() >= HEALTHY_RUN {
+                backoff.reset();
+            }
+            let delay = backoff.next_delay();
+            tracing::error!(task = kind, error = %message, delay_ms = delay.as_millis() as u64, "background task failed, restarting");
+            report(
+                &shared_state,
+                TaskFailed {
+                    task: kind,
+                    audio_source: None,
+                    error: &message,
+                    restart_in_ms: Some(delay.as_millis()),
+                },
+                None,
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.cancelled() => return,
+            }
+        }
+    });
+}
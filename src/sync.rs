@@ -0,0 +1,401 @@
+/////////////////////////////////////////////////////////////
+// src/sync.rs
+//
+// Sync between full instances ([sync] enabled), e.g. one in the
+// bedroom and one in the living room, so a session recorded on
+// either can be searched on both.
+//
+// Every sync.interval_secs, each of sync.peers is asked for its
+// entries since the last pull (GET /sync/entries, with
+// sync.token as a bearer token), and they're kept in sync.file.
+// A peer also passes on what it pulled from others, so a chain
+// of peers is enough. Each entry is a conversation_log.json
+// record plus "instance", the instance that logged it; the
+// conversation log itself stays this instance's own. GraphQL
+// (see graphql.rs) reads both.
+//
+// An entry is identified by instance, chunk_id and source. Two
+// copies that differ are a conflict: the one with the later
+// "updated_at" (else "timestamp") wins, and it's logged and
+// counted in GET /sync. Peers that are down are retried at the
+// next round.
+//
+//   GET /sync           - each peer's last pull, and counts
+//   GET /sync/entries   - for peers; ?since=<RFC 3339>
+/////////////////////////////////////////////////////////////
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, RwLock};
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::constant_time_eq;
+use crate::config::SyncSettings;
+use crate::error::ApiError;
+use crate::{discovery, privacy, AppState, CONVERSATION_LOG};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/////////////////////////////////////////////////////////////
+// Synced
+//
+// The entries pulled from peers, the file they're kept in, and
+// how each peer's pulls went.
+/////////////////////////////////////////////////////////////
+pub struct Synced {
+    entries: RwLock<Vec<Value>>,
+    path: PathBuf,
+    peers: AsyncMutex<HashMap<String, PeerStatus>>,
+}
+
+#[derive(Serialize, Clone, Default, ToSchema)]
+pub struct PeerStatus {
+    url: String,
+    // The peer's sync.instance, once it has answered
+    instance: Option<String>,
+    last_pull: Option<String>,
+    last_error: Option<String>,
+    // Newest "updated_at" pulled; the next pull asks for newer
+    cursor: Option<String>,
+    entries_pulled: u64,
+    conflicts: u64,
+}
+
+// What one pull changed
+#[derive(Default)]
+struct Merged {
+    added: usize,
+    replaced: usize,
+    conflicts: usize,
+}
+
+impl Synced {
+    // No file yet is nothing synced
+    pub fn load(path: &str) -> Result<Synced> {
+        let path = PathBuf::from(path);
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).with_context(|| format!("{} isn't a list of entries", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Synced {
+            entries: RwLock::new(entries),
+            path,
+            peers: AsyncMutex::new(HashMap::new()),
+        })
+    }
+
+    // Every synced entry, oldest first
+    pub async fn entries(&self) -> Vec<Value> {
+        self.entries.read().await.clone()
+    }
+
+    // Through a temporary file, so a crash never leaves half a list
+    async fn save(&self, entries: &[Value]) -> Result<()> {
+        let text = serde_json::to_string(entries)?;
+        let tmp = tmp_path(&self.path);
+        tokio::fs::write(&tmp, text)
+            .await
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+
+    // Last write wins, per entry; our own entries are skipped
+    async fn merge(&self, ours: &str, incoming: Vec<Value>) -> Result<Merged> {
+        let mut entries = self.entries.write().await;
+        let mut updated = entries.clone();
+        let mut merged = Merged::default();
+        for entry in incoming {
+            let Some(id) = key(&entry) else { continue };
+            if id.0 == ours {
+                continue;
+            }
+            let Some(existing) = updated.iter_mut().find(|e| key(e).as_ref() == Some(&id)) else {
+                updated.push(entry);
+                merged.added += 1;
+                continue;
+            };
+            if *existing == entry {
+                continue;
+            }
+            merged.conflicts += 1;
+            let newer = newer(&entry, existing);
+            tracing::warn!(
+                instance = %id.0,
+                chunk_id = %id.1,
+                source = %id.2,
+                kept = %version(if newer { &entry } else { existing }),
+                dropped = %version(if newer { existing } else { &entry }),
+                "sync conflict, keeping the later write"
+            );
+            if newer {
+                *existing = entry;
+                merged.replaced += 1;
+            }
+        }
+        if merged.added + merged.replaced > 0 {
+            updated.sort_by(|a, b| a["timestamp"].as_str().cmp(&b["timestamp"].as_str()));
+            self.save(&updated).await?;
+            *entries = updated;
+        }
+        Ok(merged)
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// instance, chunk_id (or the timestamp, for records from before
+// chunk IDs), source
+type Key = (String, String, String);
+
+fn key(entry: &Value) -> Option<Key> {
+    let instance = entry["instance"].as_str()?;
+    let chunk = entry["chunk_id"].as_str().or(entry["timestamp"].as_str())?;
+    let source = entry["source"].as_str()?;
+    Some((instance.to_string(), chunk.to_string(), source.to_string()))
+}
+
+fn version(entry: &Value) -> &str {
+    entry["updated_at"].as_str().or(entry["timestamp"].as_str()).unwrap_or_default()
+}
+
+// Whether `a` wins over `b`. Ties go by content, so every
+// instance keeps the same copy.
+fn newer(a: &Value, b: &Value) -> bool {
+    match version(a).cmp(version(b)) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => a.to_string().as_str() > b.to_string().as_str(),
+    }
+}
+
+// sync.instance, or the hostname
+pub fn instance_name(settings: &SyncSettings) -> String {
+    if settings.instance.is_empty() {
+        discovery::hostname()
+    } else {
+        settings.instance.clone()
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Pulls from every peer each sync.interval_secs; settings are
+// read each round, so they reload live.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "sync");
+    app_data.tasks.spawn("sync", run(app_data.clone()).instrument(span));
+}
+
+async fn run(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    let client = privacy::client();
+    loop {
+        let settings = app_data.config.read().await.sync.clone();
+        if settings.enabled {
+            let ours = instance_name(&settings);
+            for url in &settings.peers {
+                tokio::select! {
+                    _ = pull(&app_data, &client, &settings, &ours, url) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(settings.interval_secs.max(1))) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+async fn pull(app_data: &AppState, client: &reqwest::Client, settings: &SyncSettings, ours: &str, url: &str) {
+    let cursor = app_data.synced.peers.lock().await.get(url).and_then(|p| p.cursor.clone());
+    let result = fetch(client, settings, url, cursor.as_deref()).await;
+    let result = match result {
+        Ok(page) => {
+            let cursor = page.entries.iter().map(version).max().map(str::to_string);
+            let count = page.entries.len();
+            app_data.synced.merge(ours, page.entries).await.map(|merged| (page.instance, cursor, count, merged))
+        }
+        Err(e) => Err(e),
+    };
+
+    let mut peers = app_data.synced.peers.lock().await;
+    let peer = peers.entry(url.to_string()).or_insert_with(|| PeerStatus {
+        url: url.to_string(),
+        ..PeerStatus::default()
+    });
+    match result {
+        Ok((instance, cursor, count, merged)) => {
+            if merged.added + merged.replaced > 0 {
+                tracing::info!(peer = %url, added = merged.added, replaced = merged.replaced, "pulled entries from a peer");
+            }
+            if peer.last_error.is_some() {
+                tracing::info!(peer = %url, "peer is back");
+            }
+            peer.instance = Some(instance);
+            peer.last_pull = Some(Utc::now().to_rfc3339());
+            peer.last_error = None;
+            peer.cursor = cursor.or(peer.cursor.take());
+            peer.entries_pulled += count as u64;
+            peer.conflicts += merged.conflicts as u64;
+        }
+        Err(e) => {
+            let error = format!("{e:#}");
+            // Once per outage
+            if peer.last_error.is_none() {
+                tracing::warn!(peer = %url, error = %error, "couldn't pull from a peer, retrying every round");
+            }
+            peer.last_error = Some(error);
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, settings: &SyncSettings, url: &str, since: Option<&str>) -> Result<EntriesPage> {
+    let mut request = client
+        .get(format!("{}/sync/entries", url.trim_end_matches('/')))
+        .bearer_auth(&settings.token)
+        .timeout(REQUEST_TIMEOUT);
+    if let Some(since) = since {
+        request = request.query(&[("since", since)]);
+    }
+    let resp = request.send().await.context("peer unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("peer returned {status}: {body}");
+    }
+    resp.json().await.context("peer sent an unexpected reply")
+}
+
+/////////////////////////////////////////////////////////////
+// /sync
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct EntriesPage {
+    // The answering instance
+    instance: String,
+    // Its own entries and what it has synced, oldest first
+    #[schema(value_type = Vec<Object>)]
+    entries: Vec<Value>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct EntriesQuery {
+    // Only entries written at or after this (RFC 3339)
+    since: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SyncStatus {
+    enabled: bool,
+    instance: String,
+    // Entries pulled from peers, kept in sync.file
+    synced_entries: usize,
+    peers: Vec<PeerStatus>,
+}
+
+// Peers authenticate with sync.token, not a login (see auth.rs)
+pub fn is_peer_request(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/sync/")
+}
+
+#[utoipa::path(
+    tag = "sync",
+    params(EntriesQuery),
+    responses(
+        (status = 200, description = "This instance's entries and those it synced", body = EntriesPage),
+        (status = 401, description = "Missing or wrong sync token (code sync_token_required)", body = ErrorBody),
+        (status = 403, description = "Sync is off (code sync_disabled)", body = ErrorBody),
+    ),
+)]
+#[get("/sync/entries")]
+async fn list_entries(
+    req: HttpRequest,
+    app_data: web::Data<AppState>,
+    query: web::Query<EntriesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = app_data.config.read().await.sync.clone();
+    if !settings.enabled || settings.token.is_empty() {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "sync_disabled", "Set sync.enabled and sync.token to sync"));
+    }
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(given.trim().as_bytes(), settings.token.as_bytes()) {
+        tracing::warn!("sync request rejected: wrong token");
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "sync_token_required", "Sync token required"));
+    }
+
+    let instance = instance_name(&settings);
+    let local = match tokio::fs::read_to_string(CONVERSATION_LOG).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(ApiError::internal("log_unreadable", format!("Failed to read {CONVERSATION_LOG}")).with_detail(e));
+        }
+    };
+    // Lines that don't parse (e.g. a half-written last line) are skipped
+    let mut entries: Vec<Value> = local
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .map(|mut record| {
+            record["instance"] = instance.clone().into();
+            record
+        })
+        .collect();
+    entries.extend(app_data.synced.entries().await);
+    if let Some(since) = &query.since {
+        entries.retain(|e| version(e) >= since.as_str());
+    }
+    entries.sort_by(|a, b| version(a).cmp(version(b)));
+    Ok(HttpResponse::Ok().json(EntriesPage { instance, entries }))
+}
+
+#[utoipa::path(tag = "sync", responses((status = 200, description = "Sync state, with each peer's last pull", body = SyncStatus)))]
+#[get("/sync")]
+async fn sync_status(app_data: web::Data<AppState>) -> HttpResponse {
+    let settings = app_data.config.read().await.sync.clone();
+    let known = app_data.synced.peers.lock().await.clone();
+    let peers = settings
+        .peers
+        .iter()
+        .map(|url| {
+            known.get(url).cloned().unwrap_or_else(|| PeerStatus {
+                url: url.clone(),
+                ..PeerStatus::default()
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(SyncStatus {
+        enabled: settings.enabled,
+        instance: instance_name(&settings),
+        synced_entries: app_data.synced.entries.read().await.len(),
+        peers,
+    })
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(sync_status).service(list_entries);
+}
@@ -0,0 +1,165 @@
+/////////////////////////////////////////////////////////////
+// src/systemd.rs
+//
+// Running under systemd on the Pi:
+// - sd_notify: READY=1 once the port is bound, RELOADING=1 /
+//   READY=1 around a config reload, STOPPING=1 on a requested
+//   shutdown, STATUS= lines for `systemctl status`.
+// - Watchdog: if the unit sets WatchdogSec=, we ping at half
+//   that interval from a runtime task, so a wedged runtime gets
+//   the service restarted.
+// - `--install-service [PATH]` writes a unit file that uses all
+//   of the above (and journald logging, see logging.rs).
+//
+// Outside systemd (no $NOTIFY_SOCKET) the notify calls are
+// no-ops.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{config_file_path, Cli};
+
+pub const DEFAULT_UNIT_PATH: &str = "/etc/systemd/system/silentnight.service";
+
+#[cfg(unix)]
+mod notify {
+    use sd_notify::NotifyState;
+    use std::time::Duration;
+
+    fn send(states: &[NotifyState]) {
+        if let Err(e) = sd_notify::notify(false, states) {
+            tracing::debug!(error = %e, "sd_notify failed");
+        }
+    }
+
+    pub fn ready(status: &str) {
+        send(&[NotifyState::Ready, NotifyState::Status(status)]);
+    }
+
+    pub fn reloading() {
+        send(&[NotifyState::Reloading]);
+    }
+
+    pub fn stopping() {
+        send(&[NotifyState::Stopping]);
+    }
+
+    pub fn status(status: &str) {
+        send(&[NotifyState::Status(status)]);
+    }
+
+    pub fn spawn_watchdog() {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return;
+        }
+        let interval = Duration::from_micros(usec / 2);
+        tracing::info!(interval_ms = interval.as_millis() as u64, "systemd watchdog enabled");
+
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                send(&[NotifyState::Watchdog]);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+mod notify {
+    pub fn ready(_status: &str) {}
+    pub fn reloading() {}
+    pub fn stopping() {}
+    pub fn status(_status: &str) {}
+    pub fn spawn_watchdog() {}
+}
+
+pub use notify::{ready, reloading, spawn_watchdog, status, stopping};
+
+// Exit status after POST /admin/restart; any failure status makes
+// the unit's Restart=on-failure start us again
+pub const RESTART_EXIT_CODE: i32 = 75;
+
+// Started by systemd, so exiting with RESTART_EXIT_CODE restarts us
+pub fn supervised() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some() || env::var_os("INVOCATION_ID").is_some()
+}
+
+/////////////////////////////////////////////////////////////
+// install_service
+//
+// Writes a unit file for the current binary, working
+// directory and config file. PATH "-" prints it instead.
+/////////////////////////////////////////////////////////////
+pub fn install_service(cli: &Cli, path: &Path) -> Result<()> {
+    let unit = unit_file(cli)?;
+
+    if path == Path::new("-") {
+        print!("{unit}");
+        return Ok(());
+    }
+
+    fs::write(path, unit).with_context(|| {
+        format!("Failed to write {} (run with sudo, or pass a different path)", path.display())
+    })?;
+    println!("✅ Wrote {}", path.display());
+    println!("   Put OPENAI_API_KEY=... in /etc/default/silentnight, then:");
+    println!("   sudo systemctl daemon-reload && sudo systemctl enable --now silentnight");
+    Ok(())
+}
+
+fn unit_file(cli: &Cli) -> Result<String> {
+    let exe = env::current_exe().context("Couldn't find the path of this binary")?;
+    let cwd = env::current_dir().context("Couldn't read the working directory")?;
+
+    let mut exec_start = exe.display().to_string();
+    if let Some(config) = config_file_path(cli) {
+        let config = absolute(&cwd, config);
+        exec_start.push_str(&format!(" --config {}", config.display()));
+    }
+
+    // Under sudo, run as the user who invoked it rather than root
+    let user = env::var("SUDO_USER")
+        .or_else(|_| env::var("USER"))
+        .ok()
+        .filter(|u| !u.is_empty() && u != "root");
+    let user_line = user.map(|u| format!("User={u}\n")).unwrap_or_default();
+
+    Ok(format!(
+        "\
+[Unit]
+Description=Silent Night conversation listener
+Wants=network-online.target
+After=network-online.target sound.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec_start}
+ExecReload=/bin/kill -HUP $MAINPID
+WorkingDirectory={cwd}
+{user_line}SupplementaryGroups=audio
+EnvironmentFile=-/etc/default/silentnight
+Environment=LOG_FORMAT=journald
+WatchdogSec=30
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=multi-user.target
+",
+        cwd = cwd.display(),
+    ))
+}
+
+fn absolute(cwd: &Path, path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        path
+    } else {
+        cwd.join(path)
+    }
+}
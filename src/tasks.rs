@@ -0,0 +1,151 @@
+/////////////////////////////////////////////////////////////
+// src/tasks.rs
+//
+// TaskManager: every long-running background task is spawned
+// through it and gets a CancellationToken, so stop, reload and
+// shutdown end them at once instead of waiting for a flag to be
+// polled.
+//
+//   shutdown token  - cancelled once the HTTP server has stopped;
+//                     every other token is a child of it
+//   recording token - one per recording source; cancelled by
+//                     stop (or a reload that removes the source),
+//                     which ends capture mid-chunk. Chunks already
+//                     captured still go through the pipeline.
+//
+// On shutdown, main() waits up to SHUTDOWN_GRACE for the tracked
+// tasks (recordings draining, webhook deliveries, gRPC) before
+// exiting. GET /status lists how many of each kind are running.
+/////////////////////////////////////////////////////////////
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+pub struct TaskManager {
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+    // Source name => (generation, token) of its current recording
+    recordings: Mutex<HashMap<String, (u64, CancellationToken)>>,
+    generation: AtomicU64,
+    // Kind => tasks of that kind still running
+    running: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+    // Kind => times the supervisor restarted one (see supervisor.rs)
+    restarts: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+// Decrements the running count however the task ends
+struct Running {
+    kind: &'static str,
+    running: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut running = self.running.lock().unwrap();
+        if let Some(count) = running.get_mut(self.kind) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(self.kind);
+            }
+        }
+    }
+}
+
+impl TaskManager {
+    // Cancelled at shutdown; hand it (or a child) to the task
+    pub fn token(&self) -> CancellationToken {
+        self.shutdown.child_token()
+    }
+
+    /////////////////////////////////////////////////////////
+    // spawn
+    //
+    // Runs `task` in the background, counted under `kind`
+    // ("recording", "webhook", ...) until it finishes.
+    /////////////////////////////////////////////////////////
+    pub fn spawn<F>(&self, kind: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        *self.running.lock().unwrap().entry(kind).or_default() += 1;
+        let running = Running {
+            kind,
+            running: self.running.clone(),
+        };
+        self.tracker.spawn(async move {
+            let _running = running;
+            task.await;
+        });
+    }
+
+    /////////////////////////////////////////////////////////
+    // Recording tokens
+    //
+    // The generation lets a finished loop clear its own entry
+    // without touching one a newer start has put there.
+    /////////////////////////////////////////////////////////
+    pub fn start_recording(&self, source: &str) -> (u64, CancellationToken) {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        let token = self.token();
+        let previous = self
+            .recordings
+            .lock()
+            .unwrap()
+            .insert(source.to_string(), (generation, token.clone()));
+        if let Some((_, previous)) = previous {
+            previous.cancel();
+        }
+        (generation, token)
+    }
+
+    pub fn stop_recording(&self, source: &str) {
+        if let Some((_, token)) = self.recordings.lock().unwrap().remove(source) {
+            token.cancel();
+        }
+    }
+
+    pub fn recording_ended(&self, source: &str, generation: u64) {
+        let mut recordings = self.recordings.lock().unwrap();
+        if recordings.get(source).is_some_and(|(g, _)| *g == generation) {
+            recordings.remove(source);
+        }
+    }
+
+    pub fn running(&self) -> BTreeMap<String, usize> {
+        let running = self.running.lock().unwrap();
+        running.iter().map(|(kind, count)| (kind.to_string(), *count)).collect()
+    }
+
+    pub fn restarted(&self, kind: &'static str) {
+        *self.restarts.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    pub fn restarts(&self) -> BTreeMap<String, u64> {
+        let restarts = self.restarts.lock().unwrap();
+        restarts.iter().map(|(kind, count)| (kind.to_string(), *count)).collect()
+    }
+
+    /////////////////////////////////////////////////////////
+    // shutdown
+    //
+    // Cancels everything, then waits up to `grace` for the
+    // tasks to finish.
+    /////////////////////////////////////////////////////////
+    pub async fn shutdown(&self, grace: Duration) {
+        self.shutdown.cancel();
+        self.tracker.close();
+        if tokio::time::timeout(grace, self.tracker.wait()).await.is_err() {
+            tracing::warn!(still_running = ?self.running(), "background tasks didn't finish in time");
+        } else {
+            tracing::info!("background tasks finished");
+        }
+    }
+}
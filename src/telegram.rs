@@ -0,0 +1,335 @@
+/////////////////////////////////////////////////////////////
+// src/telegram.rs
+//
+// Telegram bot ([telegram] enabled), for remote control from
+// anywhere: it long-polls Telegram's Bot API, so nothing has to
+// be reachable from the internet. Chats in telegram.chat_ids can
+// send
+//   /start_recording [source]
+//   /stop [source]
+//   /status              every source's state
+//   /last [source]       the latest transcript and response
+//   /digest              the last 24 hours (see digest.rs)
+// and get the daily digest at telegram.digest_time (local time)
+// plus keyword alerts (see notify.rs). Any other chat is told
+// its ID, so it can be added, and nothing else.
+//
+// Settings are restart-only. A failed send is logged and
+// dropped; polling retries every RETRY_DELAY while Telegram is
+// unreachable.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{Local, NaiveTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::Instrument;
+
+use crate::config::{TelegramSettings, DEFAULT_SOURCE};
+use crate::lifecycle::RecordingState;
+use crate::notify::{Alert, Notifier};
+use crate::{audit, digest, privacy, sessions, AppState};
+
+// How long each getUpdates call waits for news
+const POLL_SECS: u64 = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Longest message Telegram accepts
+const MAX_MESSAGE_CHARS: usize = 4096;
+
+const HELP: &str = "/start_recording [source] - start recording\n\
+                    /stop [source] - stop recording\n\
+                    /status - what every source is doing\n\
+                    /last [source] - the latest transcript and response\n\
+                    /digest - the last 24 hours";
+
+/////////////////////////////////////////////////////////////
+// Bot
+//
+// Bot API calls. Errors never include the URL, since it holds
+// the token.
+/////////////////////////////////////////////////////////////
+#[derive(Clone)]
+struct Bot {
+    client: reqwest::Client,
+    settings: TelegramSettings,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    #[serde(default)]
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    #[serde(default)]
+    from: Option<User>,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    #[serde(default)]
+    username: Option<String>,
+}
+
+impl Bot {
+    // `result` of a successful call
+    async fn call(&self, method: &str, body: Value, timeout: Duration) -> Result<Value> {
+        let url = format!("{}/bot{}/{method}", self.settings.api_url.trim_end_matches('/'), self.settings.bot_token);
+        let resp = self
+            .client
+            .post(url)
+            .timeout(timeout)
+            .json(&body)
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)
+            .context("Telegram unreachable")?;
+        let status = resp.status();
+        let reply: Value = resp
+            .json()
+            .await
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("Telegram replied {status} without JSON"))?;
+        if reply["ok"] != true {
+            anyhow::bail!("Telegram refused {method}: {}", reply["description"].as_str().unwrap_or("no reason given"));
+        }
+        Ok(reply["result"].clone())
+    }
+
+    async fn updates(&self, offset: i64) -> Result<Vec<Update>> {
+        let body = json!({ "offset": offset, "timeout": POLL_SECS, "allowed_updates": ["message"] });
+        let result = self.call("getUpdates", body, Duration::from_secs(POLL_SECS) + REQUEST_TIMEOUT).await?;
+        serde_json::from_value(result).context("Telegram sent updates we can't read")
+    }
+
+    async fn send(&self, chat_id: i64, text: &str) -> Result<()> {
+        let body = json!({ "chat_id": chat_id, "text": truncate(text, MAX_MESSAGE_CHARS) });
+        self.call("sendMessage", body, REQUEST_TIMEOUT).await.map(|_| ())
+    }
+
+    // To every authorized chat; Err if any send failed
+    async fn broadcast(&self, text: &str) -> Result<()> {
+        let mut failed = None;
+        for &chat_id in &self.settings.chat_ids {
+            if let Err(e) = self.send(chat_id, text).await {
+                tracing::warn!(chat_id, error = %format!("{e:#}"), "Telegram message failed");
+                failed = Some(e);
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// TelegramNotifier
+//
+// Keyword alerts to every authorized chat.
+/////////////////////////////////////////////////////////////
+pub struct TelegramNotifier {
+    bot: Bot,
+}
+
+impl TelegramNotifier {
+    pub fn new(client: reqwest::Client, settings: &TelegramSettings) -> TelegramNotifier {
+        TelegramNotifier {
+            bot: Bot { client, settings: settings.clone() },
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        self.bot.broadcast(&alert.plain_text()).await
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Starts polling for commands and, with a digest_time, the
+// daily digest.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: web::Data<AppState>, settings: TelegramSettings) {
+    tracing::info!(chats = settings.chat_ids.len(), "Telegram bot enabled");
+    let digest_at = NaiveTime::parse_from_str(&settings.digest_time, "%H:%M").ok();
+    let bot = Bot {
+        client: privacy::client(),
+        settings,
+    };
+    let span = || tracing::info_span!(parent: None, "telegram");
+    app_data.tasks.spawn("telegram", poll(app_data.clone(), bot.clone()).instrument(span()));
+    if let Some(at) = digest_at {
+        app_data.tasks.spawn("telegram", daily_digest(app_data.clone(), bot, at).instrument(span()));
+    }
+}
+
+async fn poll(app_data: web::Data<AppState>, bot: Bot) {
+    let shutdown = app_data.tasks.token();
+    let mut offset = 0;
+    let mut failing = false;
+    loop {
+        let updates = tokio::select! {
+            updates = bot.updates(offset) => updates,
+            _ = shutdown.cancelled() => return,
+        };
+        match updates {
+            Ok(updates) => {
+                if failing {
+                    tracing::info!("reached Telegram again");
+                }
+                failing = false;
+                for update in updates {
+                    offset = update.update_id + 1;
+                    if let Some(message) = update.message {
+                        handle(&app_data, &bot, message).await;
+                    }
+                }
+            }
+            Err(e) => {
+                // Once per outage
+                if !failing {
+                    tracing::warn!(error = %format!("{e:#}"), "Telegram unreachable, retrying every {}s", RETRY_DELAY.as_secs());
+                }
+                failing = true;
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        }
+    }
+}
+
+async fn daily_digest(app_data: web::Data<AppState>, bot: Bot, at: NaiveTime) {
+    let shutdown = app_data.tasks.token();
+    loop {
+        let now = Local::now().naive_local();
+        let mut next = now.date().and_time(at);
+        if next <= now {
+            next += chrono::Duration::days(1);
+        }
+        let wait = (next - now).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.cancelled() => return,
+        }
+
+        match digest::build(&app_data, Utc::now() - chrono::Duration::days(1)).await {
+            Ok(text) => {
+                if bot.broadcast(&text).await.is_ok() {
+                    tracing::info!("sent the daily digest");
+                }
+            }
+            Err(e) => tracing::warn!(error = %format!("{e:#}"), "couldn't build the daily digest"),
+        }
+    }
+}
+
+// Replies to one message
+async fn handle(app_data: &web::Data<AppState>, bot: &Bot, message: Message) {
+    let chat_id = message.chat.id;
+    let user = message.from.and_then(|u| u.username).unwrap_or_default();
+    let reply = if !bot.settings.chat_ids.contains(&chat_id) {
+        tracing::info!(chat_id, user = %user, "ignoring a Telegram chat that isn't authorized");
+        format!("This chat isn't authorized. To allow it, add its ID, {chat_id}, to telegram.chat_ids.")
+    } else {
+        let text = message.text.unwrap_or_default();
+        let mut words = text.split_whitespace();
+        // "/status@SilentNightBot" in groups
+        let command = words.next().unwrap_or_default().split('@').next().unwrap_or_default();
+        let source = words.next().unwrap_or(DEFAULT_SOURCE);
+        tracing::info!(chat_id, user = %user, command, "Telegram command");
+        command_reply(app_data, &user, command, source).await
+    };
+    if let Err(e) = bot.send(chat_id, &reply).await {
+        tracing::warn!(chat_id, error = %format!("{e:#}"), "Telegram reply failed");
+    }
+}
+
+async fn command_reply(app_data: &web::Data<AppState>, user: &str, command: &str, name: &str) -> String {
+    match command {
+        "/status" => {
+            let mut lines = Vec::new();
+            for source in app_data.sources.all(app_data).await {
+                let state = source.state.borrow().clone();
+                let mut line = format!("{}: {}", source.name, state.name());
+                if let RecordingState::Error { reason } = &state {
+                    line.push_str(&format!(" ({reason})"));
+                }
+                if let Some(id) = source.session_id.borrow().as_ref() {
+                    line.push_str(&format!(", session {id}"));
+                }
+                lines.push(line);
+            }
+            lines.join("\n")
+        }
+        "/digest" => match digest::build(app_data, Utc::now() - chrono::Duration::days(1)).await {
+            Ok(text) => text,
+            Err(e) => format!("Couldn't build the digest: {e:#}"),
+        },
+        "/start_recording" | "/stop" | "/last" => {
+            let Some(source) = app_data.sources.get(app_data, name).await else {
+                return format!("There's no audio source named {name}.");
+            };
+            let actor = format!("telegram:{user}");
+            match command {
+                "/start_recording" => match sessions::start_source(app_data, source.clone()).await {
+                    Ok(()) => {
+                        audit::record(app_data, "telegram", &actor, &format!("start {name}"), true).await;
+                        let session_id = source.session_id.borrow().clone().unwrap_or_default();
+                        format!("Recording {name} (session {session_id}).")
+                    }
+                    Err(e) => {
+                        audit::record(app_data, "telegram", &actor, &format!("start {name}"), false).await;
+                        format!("Couldn't start {name}: {e}")
+                    }
+                },
+                "/stop" => {
+                    if matches!(*source.state.borrow(), RecordingState::Idle | RecordingState::Error { .. }) {
+                        return format!("{name} isn't recording.");
+                    }
+                    sessions::stop_source(app_data, &source).await;
+                    audit::record(app_data, "telegram", &actor, &format!("stop {name}"), true).await;
+                    format!("Stopping {name}.")
+                }
+                _ => {
+                    let latest = source.latest.borrow().clone();
+                    if latest.transcript.is_empty() && latest.gpt_response.is_empty() {
+                        format!("Nothing from {name} yet.")
+                    } else {
+                        format!("Heard: {}\nResponse: {}", latest.transcript, latest.gpt_response)
+                    }
+                }
+            }
+        }
+        _ => HELP.to_string(),
+    }
+}
+
+// At most `max_chars`, with "…" if it was cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars - 1).collect();
+    format!("{kept}…")
+}
@@ -0,0 +1,333 @@
+/////////////////////////////////////////////////////////////
+// src/telemetry.rs
+//
+// Latency and throughput, for tuning chunk_secs and
+// openai.max_concurrent:
+//   - per pipeline stage (capture, screen, transcribe, respond,
+//     persist):
+//     avg/last/max time per chunk and queue depth, under each
+//     source's `pipeline` on GET /status
+//   - bytes captured, end-to-end chunk latency (capture start
+//     to logged) and the mic's idle time between chunks
+//     (capture_gap), also under `pipeline`, with the chunks sent
+//     to Whisper in a batch (batched), those dropped as repeats
+//     (duplicates) and those whose transcript went ahead of a
+//     late response (late_responses)
+//   - Whisper and GPT request latency, under `openai`
+//   - free space on the volumes written to, and whether audio
+//     archiving is paused (see disk.rs)
+//
+//   GET /metrics  - all of it in the Prometheus text format
+//
+// The last RECENT_SAMPLES end-to-end and request times are also
+// kept, for the sparklines on GET /dashboard (see dashboard.rs).
+//
+// Every metrics.telemetry_secs (TELEMETRY_SECS, default 10,
+// 0 = off) /live_log also gets an SSE event named "telemetry":
+//
+//   {"interval_secs":10,"sources":[{"audio_source":"default",
+//    "state":"recording","chunks_per_min":11.8,
+//    "bytes_per_sec":32000.0,"pipeline":{...}}],"openai":{...}}
+//
+// with the rates measured over that interval.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::openai_limit::OpenAiUsage;
+use crate::pipeline::PipelineStatus;
+use crate::AppState;
+
+// How often to look again while telemetry events are off
+const OFF_RECHECK: Duration = Duration::from_secs(10);
+// Timings kept one by one, newest last
+const RECENT_SAMPLES: usize = 60;
+
+/////////////////////////////////////////////////////////////
+// Latency
+//
+// Running count, total, max and last of something timed, and
+// the most recent timings.
+/////////////////////////////////////////////////////////////
+#[derive(Default)]
+pub struct Latency {
+    count: AtomicU64,
+    total_ms: AtomicU64,
+    max_ms: AtomicU64,
+    last_ms: AtomicU64,
+    recent_ms: Mutex<VecDeque<u64>>,
+}
+
+impl Latency {
+    pub fn record(&self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ms.fetch_add(ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(ms, Ordering::Relaxed);
+        self.last_ms.store(ms, Ordering::Relaxed);
+        let mut recent = self.recent_ms.lock().unwrap();
+        if recent.len() == RECENT_SAMPLES {
+            recent.pop_front();
+        }
+        recent.push_back(ms);
+    }
+
+    // Up to RECENT_SAMPLES, oldest first
+    pub fn recent(&self) -> Vec<u64> {
+        self.recent_ms.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        let count = self.count.load(Ordering::Relaxed);
+        let total_ms = self.total_ms.load(Ordering::Relaxed);
+        LatencyStats {
+            count,
+            total_ms,
+            avg_ms: total_ms.checked_div(count).unwrap_or(0),
+            max_ms: self.max_ms.load(Ordering::Relaxed),
+            last_ms: self.last_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy)]
+pub(crate) struct LatencyStats {
+    pub count: u64,
+    pub total_ms: u64,
+    pub avg_ms: u64,
+    pub max_ms: u64,
+    pub last_ms: u64,
+}
+
+/////////////////////////////////////////////////////////////
+// Telemetry (the "telemetry" SSE event)
+/////////////////////////////////////////////////////////////
+#[derive(Serialize)]
+struct Telemetry {
+    interval_secs: f64,
+    sources: Vec<SourceTelemetry>,
+    openai: OpenAiUsage,
+}
+
+#[derive(Serialize)]
+struct SourceTelemetry {
+    audio_source: String,
+    state: &'static str,
+    // Chunks logged and audio captured during the interval
+    chunks_per_min: f64,
+    bytes_per_sec: f64,
+    pipeline: PipelineStatus,
+}
+
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let shared_state = app_data.clone();
+    let shutdown = app_data.tasks.token();
+    app_data.tasks.spawn("telemetry", async move {
+        // Per source: chunks logged and bytes captured at the last event
+        let mut previous: HashMap<String, (u64, u64)> = HashMap::new();
+        let mut last_sent = Instant::now();
+        loop {
+            let secs = shared_state.config.read().await.metrics.telemetry_secs;
+            let wait = if secs == 0 { OFF_RECHECK } else { Duration::from_secs(secs) };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            if secs == 0 {
+                continue;
+            }
+
+            let interval = last_sent.elapsed().as_secs_f64();
+            last_sent = Instant::now();
+            let mut sources = Vec::new();
+            for source in shared_state.sources.all(&shared_state).await {
+                let pipeline = source.pipeline.status();
+                let chunks = source.chunks_processed.load(Ordering::Relaxed);
+                let (chunks_before, bytes_before) = previous
+                    .insert(source.name.clone(), (chunks, pipeline.bytes_captured))
+                    .unwrap_or((0, 0));
+                let state = source.state.borrow().name();
+                sources.push(SourceTelemetry {
+                    audio_source: source.name.clone(),
+                    state,
+                    chunks_per_min: chunks.saturating_sub(chunks_before) as f64 * 60.0 / interval,
+                    bytes_per_sec: pipeline.bytes_captured.saturating_sub(bytes_before) as f64 / interval,
+                    pipeline,
+                });
+            }
+            let telemetry = Telemetry {
+                interval_secs: interval,
+                sources,
+                openai: shared_state.openai_limiter.usage(),
+            };
+            if let Ok(data) = serde_json::to_string(&telemetry) {
+                shared_state.events.publish_notice("telemetry", data);
+            }
+        }
+    });
+}
+
+/////////////////////////////////////////////////////////////
+// GET /metrics
+//
+// Durations are in seconds, as Prometheus expects. Stage and
+// request timings are summaries without quantiles (_sum and
+// _count) plus a _max gauge.
+/////////////////////////////////////////////////////////////
+#[utoipa::path(
+    tag = "monitoring",
+    responses((status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain")),
+)]
+#[get("/metrics")]
+async fn metrics(app_data: web::Data<AppState>) -> impl Responder {
+    let mut sources = Vec::new();
+    for source in app_data.sources.all(&app_data).await {
+        let chunks = source.chunks_processed.load(Ordering::Relaxed);
+        sources.push((source.name.clone(), chunks, source.pipeline.status()));
+    }
+    let openai = app_data.openai_limiter.usage();
+    let mut out = Exposition::default();
+
+    out.family("silentnight_uptime_seconds", "gauge", "Seconds since the server started");
+    let uptime = (chrono::Utc::now() - app_data.started_at).num_seconds();
+    out.sample("silentnight_uptime_seconds", &[], uptime);
+
+    out.family("silentnight_chunks_processed_total", "counter", "Chunks transcribed, answered and logged");
+    for (name, chunks, _) in &sources {
+        out.sample("silentnight_chunks_processed_total", &[("source", name)], chunks);
+    }
+    out.family("silentnight_capture_bytes_total", "counter", "Audio bytes captured by the recording loop");
+    for (name, _, pipeline) in &sources {
+        out.sample("silentnight_capture_bytes_total", &[("source", name)], pipeline.bytes_captured);
+    }
+    out.family("silentnight_batched_chunks_total", "counter", "Short chunks sent to Whisper together with others");
+    for (name, _, pipeline) in &sources {
+        out.sample("silentnight_batched_chunks_total", &[("source", name)], pipeline.batched);
+    }
+    out.family("silentnight_duplicate_chunks_total", "counter", "Chunks dropped as repeats of the one before");
+    for (name, _, pipeline) in &sources {
+        out.sample("silentnight_duplicate_chunks_total", &[("source", name)], pipeline.duplicates);
+    }
+    out.family("silentnight_late_responses_total", "counter", "Transcripts sent ahead of a response that missed audio.deadline_secs");
+    for (name, _, pipeline) in &sources {
+        out.sample("silentnight_late_responses_total", &[("source", name)], pipeline.late_responses);
+    }
+
+    out.family("silentnight_stage_chunks_total", "counter", "Chunks through each pipeline stage, by result");
+    for (name, _, pipeline) in &sources {
+        for (stage, status) in pipeline.stages() {
+            for (result, count) in [("processed", status.processed), ("failed", status.failed), ("deferred", status.deferred)] {
+                out.sample("silentnight_stage_chunks_total", &[("source", name), ("stage", stage), ("result", result)], count);
+            }
+        }
+    }
+    out.family("silentnight_stage_retries_total", "counter", "Retried attempts in each pipeline stage");
+    for (name, _, pipeline) in &sources {
+        for (stage, status) in pipeline.stages() {
+            out.sample("silentnight_stage_retries_total", &[("source", name), ("stage", stage)], status.retries);
+        }
+    }
+    out.family("silentnight_stage_queued", "gauge", "Chunks waiting in front of each pipeline stage");
+    for (name, _, pipeline) in &sources {
+        for (stage, status) in pipeline.stages() {
+            out.sample("silentnight_stage_queued", &[("source", name), ("stage", stage)], status.queued);
+        }
+    }
+    let stages: Vec<_> = sources
+        .iter()
+        .flat_map(|(name, _, pipeline)| {
+            pipeline.stages().map(|(stage, status)| (vec![("source", name.as_str()), ("stage", stage)], status.latency))
+        })
+        .collect();
+    out.latency("silentnight_stage_seconds", "Time per chunk in each pipeline stage, retries included", &stages);
+
+    let chunks: Vec<_> = sources
+        .iter()
+        .map(|(name, _, pipeline)| (vec![("source", name.as_str())], pipeline.end_to_end))
+        .collect();
+    out.latency("silentnight_chunk_latency_seconds", "From the start of a chunk's capture until it is logged", &chunks);
+    let gaps: Vec<_> = sources
+        .iter()
+        .map(|(name, _, pipeline)| (vec![("source", name.as_str())], pipeline.capture_gap))
+        .collect();
+    out.latency("silentnight_capture_gap_seconds", "Time the mic was idle between two chunks while recording", &gaps);
+
+    let requests = [(vec![("api", "whisper")], openai.whisper), (vec![("api", "gpt")], openai.gpt)];
+    out.latency("silentnight_openai_request_seconds", "OpenAI request time, not counting the wait for a slot", &requests);
+    out.family("silentnight_openai_in_flight", "gauge", "OpenAI requests running");
+    out.sample("silentnight_openai_in_flight", &[], openai.in_flight);
+    out.family("silentnight_openai_queued", "gauge", "OpenAI requests waiting for a slot");
+    out.sample("silentnight_openai_queued", &[], openai.queued);
+    out.family("silentnight_openai_rejected_total", "counter", "OpenAI requests refused because too many were waiting");
+    out.sample("silentnight_openai_rejected_total", &[], openai.rejected);
+
+    out.family("silentnight_sse_subscribers", "gauge", "Connected /live_log clients");
+    out.sample("silentnight_sse_subscribers", &[], app_data.events.subscribers());
+
+    let volumes = app_data.disk.volumes();
+    out.family("silentnight_disk_free_bytes", "gauge", "Free space on each volume the server writes to");
+    for volume in &volumes {
+        out.sample("silentnight_disk_free_bytes", &[("volume", &volume.volume), ("path", &volume.path)], volume.free_bytes);
+    }
+    out.family("silentnight_disk_total_bytes", "gauge", "Size of each volume the server writes to");
+    for volume in &volumes {
+        out.sample("silentnight_disk_total_bytes", &[("volume", &volume.volume), ("path", &volume.path)], volume.total_bytes);
+    }
+    out.family("silentnight_audio_archiving_paused", "gauge", "1 while chunks aren't saved to audio.save_dir for lack of space");
+    out.sample("silentnight_audio_archiving_paused", &[], u8::from(app_data.disk.archiving_paused()));
+    out.family("silentnight_audio_archive_skipped_total", "counter", "Chunks not saved to audio.save_dir for lack of space");
+    out.sample("silentnight_audio_archive_skipped_total", &[], app_data.disk.skipped_total());
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(out.text)
+}
+
+// Builds the text exposition format
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}\n# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter().map(|(k, v)| format!("{k}=\"{}\"", escape(v))).collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {value}");
+    }
+
+    fn latency(&mut self, name: &str, help: &str, series: &[(Vec<(&str, &str)>, LatencyStats)]) {
+        self.family(name, "summary", help);
+        for (labels, stats) in series {
+            self.sample(&format!("{name}_sum"), labels, stats.total_ms as f64 / 1000.0);
+            self.sample(&format!("{name}_count"), labels, stats.count);
+        }
+        let max = format!("{name}_max");
+        self.family(&max, "gauge", "Longest one so far");
+        for (labels, stats) in series {
+            self.sample(&max, labels, stats.max_ms as f64 / 1000.0);
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics);
+}
@@ -0,0 +1,65 @@
+/////////////////////////////////////////////////////////////
+// src/timezone.rs
+//
+// The time zone times are shown in. Everything is kept in UTC
+// (the log's "timestamp", reminders, bookmarks); ui.timezone, an
+// IANA name such as "Europe/Berlin" (empty = the server's own),
+// is only how people read it:
+//   - session pages, exports, digests, emails and minutes show
+//     their times in it
+//   - log records sent over the live logs, /poll_log, replays
+//     and webhooks carry "local_time" next to "timestamp", and
+//     ?schema=2 events have both too
+// Schedules (quiet hours, digest times, rules, reminders) still
+// follow the server's clock. A reload changes it at once.
+/////////////////////////////////////////////////////////////
+
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::sync::RwLock;
+
+// None = the server's local time
+static ZONE: RwLock<Option<Tz>> = RwLock::new(None);
+
+// Whether `name` is a zone ui.timezone can be set to
+pub fn valid(name: &str) -> bool {
+    name.is_empty() || name.parse::<Tz>().is_ok()
+}
+
+// Switches to ui.timezone; an unknown zone (which validation
+// keeps out) is the server's own
+pub fn set(name: &str) {
+    *ZONE.write().unwrap_or_else(std::sync::PoisonError::into_inner) = name.parse().ok();
+}
+
+// `at` as it's shown
+pub fn local(at: DateTime<Utc>) -> DateTime<FixedOffset> {
+    match *ZONE.read().unwrap_or_else(std::sync::PoisonError::into_inner) {
+        Some(zone) => at.with_timezone(&zone).fixed_offset(),
+        None => at.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+pub fn now() -> DateTime<FixedOffset> {
+    local(Utc::now())
+}
+
+// An RFC 3339 time as it's shown, also in RFC 3339
+pub fn rfc3339(at: &str) -> Option<String> {
+    let at = DateTime::parse_from_rfc3339(at).ok()?;
+    Some(local(at.with_timezone(&Utc)).to_rfc3339())
+}
+
+/////////////////////////////////////////////////////////////
+// annotate
+//
+// Adds "local_time" to a log record that has a "timestamp",
+// for sending it out.
+/////////////////////////////////////////////////////////////
+pub fn annotate(record: &mut Value) {
+    let local_time = record["timestamp"].as_str().and_then(rfc3339);
+    if let (Some(record), Some(local_time)) = (record.as_object_mut(), local_time) {
+        record.insert("local_time".to_string(), local_time.into());
+    }
+}
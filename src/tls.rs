@@ -0,0 +1,144 @@
+/////////////////////////////////////////////////////////////
+// src/tls.rs
+//
+// Optional HTTPS termination using rustls, so the Pi can serve
+// the UI over https:// without a reverse proxy in front.
+//
+// Settings ([tls] or env):
+//   cert_path   (TLS_CERT_PATH)    PEM certificate chain
+//   key_path    (TLS_KEY_PATH)     PEM private key (PKCS#8, RSA or EC)
+//   self_signed (TLS_SELF_SIGNED)  if the files above don't exist
+//               yet, generate a self-signed cert for localhost
+//               and this machine's hostname on first run.
+//               Defaults the paths to tls/cert.pem and
+//               tls/key.pem when they aren't set.
+//
+// With none of these set we keep serving plain HTTP.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::config::TlsSettings;
+
+const DEFAULT_CERT_PATH: &str = "tls/cert.pem";
+const DEFAULT_KEY_PATH: &str = "tls/key.pem";
+
+/////////////////////////////////////////////////////////////
+// load_tls_config
+//
+// Returns Some(ServerConfig) when HTTPS is configured, None
+// for plain HTTP.
+/////////////////////////////////////////////////////////////
+pub fn load_tls_config(settings: &TlsSettings) -> Result<Option<ServerConfig>> {
+    let self_signed = settings.self_signed;
+
+    let (cert_path, key_path) = match (settings.cert_path.clone(), settings.key_path.clone()) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if self_signed => (DEFAULT_CERT_PATH.to_string(), DEFAULT_KEY_PATH.to_string()),
+        (None, None) => return Ok(None),
+        _ => anyhow::bail!("tls.cert_path and tls.key_path must be set together"),
+    };
+
+    if self_signed && !Path::new(&cert_path).exists() && !Path::new(&key_path).exists() {
+        generate_self_signed(&cert_path, &key_path)?;
+    }
+
+    let certs = load_certs(&cert_path)?;
+    let key = load_private_key(&key_path)?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate/key pair")?;
+
+    tracing::info!(cert_path = %cert_path, "loaded TLS certificate");
+    Ok(Some(config))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let file = fs::File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse certificates in {path}"))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {path}");
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey> {
+    let file = fs::File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse private key in {path}"))?;
+
+    for item in items {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    anyhow::bail!("No private key found in {path}")
+}
+
+/////////////////////////////////////////////////////////////
+// generate_self_signed
+//
+// Writes a new self-signed cert + key. Browsers will warn the
+// first time, but the origin is then a secure context.
+/////////////////////////////////////////////////////////////
+fn generate_self_signed(cert_path: &str, key_path: &str) -> Result<()> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if let Ok(hostname) = fs::read_to_string("/etc/hostname") {
+        let hostname = hostname.trim();
+        if !hostname.is_empty() {
+            names.push(hostname.to_string());
+            names.push(format!("{hostname}.local"));
+        }
+    }
+    tracing::info!(?names, "generating self-signed certificate");
+
+    let cert = rcgen::generate_simple_self_signed(names)
+        .context("Failed to generate self-signed certificate")?;
+    let cert_pem = cert.serialize_pem().context("Failed to serialize certificate")?;
+    let key_pem = cert.serialize_private_key_pem();
+
+    for path in [cert_path, key_path] {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+    }
+    fs::write(cert_path, cert_pem).with_context(|| format!("Failed to write {cert_path}"))?;
+    write_private(key_path, key_pem.as_bytes())?;
+
+    tracing::info!(cert_path, key_path, "wrote self-signed certificate and key");
+    Ok(())
+}
+
+// The key file should only be readable by us
+#[cfg(unix)]
+fn write_private(path: &str, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create {path}"))?;
+    file.write_all(contents).with_context(|| format!("Failed to write {path}"))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &str, contents: &[u8]) -> Result<()> {
+    fs::write(path, contents).with_context(|| format!("Failed to write {path}"))
+}
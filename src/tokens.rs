@@ -0,0 +1,154 @@
+/////////////////////////////////////////////////////////////
+// src/tokens.rs
+//
+// Scoped API tokens, for handing a device less than the login:
+//   [tokens.hallway]
+//   token = "..."
+//   scope = "display"
+// A scope allows:
+//   display - reading what a wall display shows: the UI page,
+//             /live_log, /poll_log, /transcript, /captions, the
+//             per-source streams and transcripts, /i18n,
+//             GET /display/profile and the pairing code at
+//             /qr.png (a phone that scans it gets no more than
+//             the display has, see pairing.rs)
+//   control - that, /status and /sources, and starting and
+//             stopping recording (/start_recording,
+//             /stop_recording, /record_once,
+//             /sources/{name}/start and /stop)
+//   admin   - everything, like the login; /admin/* too when
+//             admin.token is set
+// Anything else gets insufficient_scope (403).
+//
+// A token is sent as "Authorization: Bearer <token>" or, for
+// browsers and EventSource, as ?access_token=<token>; the query
+// form also sets a session cookie with the token's scope, so a
+// tablet can open /?access_token=... once. Tokens only matter
+// with login on: without it everything is open anyway. They're
+// read from the live config, so a reload adds or revokes them,
+// and a session keeps at most its token's current scope.
+//
+// A token with a workspace = "<name>" is kept to that workspace
+// as well as its scope (see workspaces.rs).
+/////////////////////////////////////////////////////////////
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{Method, StatusCode};
+use serde::Deserialize;
+use std::fmt;
+
+use crate::auth::constant_time_eq;
+use crate::error::ApiError;
+use crate::AppState;
+
+pub const SCOPES: [&str; 3] = ["display", "control", "admin"];
+
+// Read-only, what a display needs
+const DISPLAY_PATHS: [&str; 9] =
+    ["/", "/index.html", "/i18n", "/live_log", "/poll_log", "/transcript", "/captions", "/display/profile", "/qr.png"];
+const CONTROL_READS: [&str; 2] = ["/status", "/sources"];
+const CONTROL_POSTS: [&str; 3] = ["/start_recording", "/stop_recording", "/record_once"];
+
+// Each allows what the ones before it do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Display,
+    Control,
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(name: &str) -> Option<Scope> {
+        match name {
+            "display" => Some(Scope::Display),
+            "control" => Some(Scope::Control),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Display => "display",
+            Scope::Control => "control",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// required
+//
+// The scope a request needs.
+/////////////////////////////////////////////////////////////
+pub fn required(method: &Method, path: &str) -> Scope {
+    let read = matches!(*method, Method::GET | Method::HEAD);
+    // "live_log" for /sources/kitchen/live_log
+    let per_source = path.strip_prefix("/sources/").and_then(|rest| rest.split_once('/')).map(|(_, what)| what);
+    // Anyone may end their own session
+    if path == "/logout" || (read && (DISPLAY_PATHS.contains(&path) || matches!(per_source, Some("live_log" | "transcript")))) {
+        Scope::Display
+    } else if (read && CONTROL_READS.contains(&path))
+        || (*method == Method::POST && (CONTROL_POSTS.contains(&path) || matches!(per_source, Some("start" | "stop"))))
+    {
+        Scope::Control
+    } else {
+        Scope::Admin
+    }
+}
+
+// A token the request carries, and how
+pub struct Granted {
+    pub name: String,
+    pub scope: Scope,
+    // Sent as ?access_token=, so a browser wants a cookie for it
+    pub from_query: bool,
+    // The workspace it's confined to; "" = none (see workspaces.rs)
+    pub workspace: String,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// granted
+//
+// The configured token the request carries, if any.
+/////////////////////////////////////////////////////////////
+pub async fn granted(req: &ServiceRequest, app_data: &AppState) -> Option<Granted> {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+    let (given, from_query) = match bearer {
+        Some(given) => (given, false),
+        None => {
+            let query = actix_web::web::Query::<TokenQuery>::from_query(req.query_string()).ok()?;
+            (query.into_inner().access_token?, true)
+        }
+    };
+    let config = app_data.config.read().await;
+    config
+        .tokens
+        .iter()
+        .find(|(_, settings)| !settings.token.is_empty() && constant_time_eq(given.as_bytes(), settings.token.as_bytes()))
+        .and_then(|(name, settings)| {
+            let scope = Scope::parse(&settings.scope)?;
+            Some(Granted { name: name.clone(), scope, from_query, workspace: settings.workspace.clone() })
+        })
+}
+
+// The 403 for a scope that's too small
+pub fn insufficient(who: &str, scope: Scope, req: &ServiceRequest) -> ApiError {
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        "insufficient_scope",
+        format!("{who} has the {scope} scope, which doesn't allow {} {}", req.method(), req.path()),
+    )
+}
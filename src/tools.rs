@@ -0,0 +1,70 @@
+/////////////////////////////////////////////////////////////
+// src/tools.rs
+//
+// Functions GPT may call while answering a transcript (OpenAI
+// function calling), so what it says can be backed by something
+// real:
+//   set_reminder, list_reminders, cancel_reminder
+//                 - [reminders] enabled (see reminders.rs)
+//   get_weather, get_local_info
+//                 - [weather] enabled (see weather.rs)
+// A section's tools are offered while it's enabled, read at each
+// chunk. A call's result, or {"error": ...}, goes back to GPT as
+// JSON, and GPT's answer after that is the chunk's response (see
+// pipeline::chat_with_tools).
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+use serde_json::{json, Value};
+
+use crate::sessions::SourceSession;
+use crate::{reminders, weather, AppState};
+
+/////////////////////////////////////////////////////////////
+// definitions
+//
+// The tools to offer GPT now; empty for none.
+/////////////////////////////////////////////////////////////
+pub async fn definitions(app_data: &AppState) -> Vec<Value> {
+    let config = app_data.config.read().await;
+    let mut tools = Vec::new();
+    if config.reminders.enabled {
+        tools.extend(reminders::tools());
+    }
+    if config.weather.enabled {
+        tools.extend(weather::tools());
+    }
+    tools
+}
+
+/////////////////////////////////////////////////////////////
+// call
+//
+// Runs the tool `name` for `source`; `arguments` is the JSON
+// text GPT sent. Returns what to tell GPT.
+/////////////////////////////////////////////////////////////
+pub async fn call(app_data: &web::Data<AppState>, source: &SourceSession, name: &str, arguments: &str) -> String {
+    let args: Value = match serde_json::from_str(arguments) {
+        Ok(args) => args,
+        Err(e) => return json!({ "error": format!("arguments aren't JSON: {e}") }).to_string(),
+    };
+    tracing::info!(tool = name, args = %args, "GPT called a tool");
+    // Only what it's offered; a section may have been turned off since
+    let offered = definitions(app_data).await.iter().any(|tool| tool["function"]["name"] == name);
+    let result = if !offered {
+        None
+    } else {
+        match reminders::call(app_data, source, name, &args).await {
+            Some(result) => Some(result),
+            None => weather::call(app_data, name, &args).await,
+        }
+    };
+    let result = result.unwrap_or_else(|| Err(anyhow::anyhow!("there's no tool named {name}")));
+    match result {
+        Ok(value) => value.to_string(),
+        Err(e) => {
+            tracing::warn!(tool = name, error = %format!("{e:#}"), "tool call failed");
+            json!({ "error": format!("{e:#}") }).to_string()
+        }
+    }
+}
@@ -0,0 +1,233 @@
+/////////////////////////////////////////////////////////////
+// src/translate.rs
+//
+// Translations of past sessions, for sharing a meeting with
+// people who don't speak its language:
+//   POST /sessions/{id}/translate?lang=es  - has GPT translate the
+//                                            session's transcript
+//                                            and keeps the result
+// `lang` is a language tag ("es", "pt-BR"). A translation is
+// kept next to the log rather than in it, in
+// translations/<session>.<lang>.json, and translating again
+// replaces it. /sessions/{id}?lang=es shows it and
+// POST /sessions/{id}/export?lang=es exports it (see browse.rs,
+// export.rs).
+//
+// The transcript goes to GPT BATCH_LINES chunks at a time,
+// routed like summaries (see routing.rs). GPT's responses aren't
+// translated.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{middleware, post, web, HttpResponse};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::ApiError;
+use crate::routing::Category;
+use crate::{digest, pipeline, rate_limit, AppState};
+
+const TRANSLATIONS_DIR: &str = "translations";
+const BATCH_LINES: usize = 40;
+const BATCH_MAX_TOKENS: u32 = 4000;
+const TRANSLATE_PROMPT: &str = "You translate transcripts. You are given a language tag and a JSON array of lines from a transcript. Reply with only a JSON array of the lines translated into that language, as many as you were given and in the same order. Leave names as they are.";
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct Translation {
+    pub session_id: String,
+    pub lang: String,
+    pub translated_at: String,
+    // Each transcribed chunk, in order
+    pub lines: Vec<TranslatedLine>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct TranslatedLine {
+    pub chunk_id: String,
+    pub original: String,
+    pub text: String,
+}
+
+impl Translation {
+    // The translation of a chunk's transcript
+    pub fn text(&self, chunk_id: &str) -> Option<&str> {
+        self.lines.iter().find(|l| l.chunk_id == chunk_id).map(|l| l.text.as_str())
+    }
+}
+
+// A language tag: "es", "pt-BR", "zh-Hant"
+pub fn valid_lang(lang: &str) -> bool {
+    let mut parts = lang.split('-');
+    let primary = parts.next().unwrap_or_default();
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|p| (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+// The session id as it's safe in a file name
+pub(crate) fn file_stem(session_id: &str) -> String {
+    session_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+fn path(session_id: &str, lang: &str) -> PathBuf {
+    Path::new(TRANSLATIONS_DIR).join(format!("{}.{lang}.json", file_stem(session_id)))
+}
+
+/////////////////////////////////////////////////////////////
+// load
+//
+// The session's translation into `lang`, if it was made.
+/////////////////////////////////////////////////////////////
+pub async fn load(session_id: &str, lang: &str) -> Result<Option<Translation>> {
+    if !valid_lang(lang) {
+        return Ok(None);
+    }
+    let path = path(session_id, lang);
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    serde_json::from_str(&contents).with_context(|| format!("{} is corrupt", path.display())).map(Some)
+}
+
+// The languages the session has been translated into
+pub async fn available(session_id: &str) -> Vec<String> {
+    let prefix = format!("{}.", file_stem(session_id));
+    let Ok(mut entries) = tokio::fs::read_dir(TRANSLATIONS_DIR).await else {
+        return Vec::new();
+    };
+    let mut langs = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(lang) = name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(".json")) {
+            if valid_lang(lang) {
+                langs.push(lang.to_string());
+            }
+        }
+    }
+    langs.sort();
+    langs
+}
+
+/////////////////////////////////////////////////////////////
+// translate
+//
+// Translates one session and keeps the result. UnknownSession
+// if it has no records.
+/////////////////////////////////////////////////////////////
+enum TranslateError {
+    UnknownSession,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for TranslateError {
+    fn from(e: anyhow::Error) -> Self {
+        TranslateError::Failed(e)
+    }
+}
+
+async fn translate(app_data: &AppState, session_id: &str, lang: &str) -> Result<Translation, TranslateError> {
+    let records = digest::session_records(session_id).await?;
+    if records.is_empty() {
+        return Err(TranslateError::UnknownSession);
+    }
+    let originals: Vec<(String, String)> = records
+        .iter()
+        .filter(|r| r["source"] == "Microphone")
+        .filter_map(|r| {
+            let text = r["text"].as_str()?.trim();
+            (!text.is_empty()).then(|| (r["chunk_id"].as_str().unwrap_or_default().to_string(), text.to_string()))
+        })
+        .collect();
+
+    let openai = app_data.config.read().await.openai.clone();
+    let mut lines = Vec::with_capacity(originals.len());
+    for batch in originals.chunks(BATCH_LINES) {
+        let texts: Vec<&str> = batch.iter().map(|(_, text)| text.as_str()).collect();
+        let messages = vec![
+            json!({ "role": "system", "content": TRANSLATE_PROMPT }),
+            json!({ "role": "user", "content": format!("Language: {lang}\n{}", json!(texts)) }),
+        ];
+        let reply = pipeline::chat_completion(app_data, &openai, Category::Summaries, messages, BATCH_MAX_TOKENS)
+            .await
+            .context("GPT couldn't translate the transcript")?;
+        // Sometimes in a Markdown code block
+        let reply = reply.trim().trim_start_matches("```json").trim_matches('`').trim();
+        let translated: Vec<String> =
+            serde_json::from_str(reply).context("GPT's translation wasn't a JSON array of lines")?;
+        if translated.len() != batch.len() {
+            return Err(anyhow::anyhow!("GPT translated {} lines instead of {}", translated.len(), batch.len()).into());
+        }
+        lines.extend(batch.iter().zip(translated).map(|((chunk_id, original), text)| TranslatedLine {
+            chunk_id: chunk_id.clone(),
+            original: original.clone(),
+            text,
+        }));
+    }
+
+    let translation = Translation {
+        session_id: session_id.to_string(),
+        lang: lang.to_string(),
+        translated_at: Utc::now().to_rfc3339(),
+        lines,
+    };
+    let path = path(session_id, lang);
+    tokio::fs::create_dir_all(TRANSLATIONS_DIR)
+        .await
+        .with_context(|| format!("Failed to create {TRANSLATIONS_DIR}"))?;
+    let contents = serde_json::to_string_pretty(&translation).context("Failed to serialize the translation")?;
+    tokio::fs::write(&path, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    tracing::info!(session_id, lang, lines = translation.lines.len(), file = %path.display(), "translated the session");
+    Ok(translation)
+}
+
+/////////////////////////////////////////////////////////////
+// POST /sessions/{id}/translate
+/////////////////////////////////////////////////////////////
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct TranslateQuery {
+    // Language tag to translate into, e.g. "es" or "pt-BR"
+    lang: String,
+}
+
+#[utoipa::path(
+    tag = "log",
+    params(("id" = String, Path, description = "Session id"), TranslateQuery),
+    responses(
+        (status = 200, description = "The translation, as kept", body = Translation),
+        (status = 400, description = "Not a language tag (code invalid_language)", body = ErrorBody),
+        (status = 404, description = "Nothing logged for that session (code unknown_session)", body = ErrorBody),
+        (status = 429, description = "Rate limited, see Retry-After", body = ErrorBody),
+        (status = 502, description = "The translation failed (code translation_failed)", body = ErrorBody),
+    ),
+)]
+#[post("/sessions/{id}/translate", wrap = "middleware::from_fn(rate_limit::limit)")]
+async fn translate_session(
+    app_data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<TranslateQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if !valid_lang(&query.lang) {
+        return Err(ApiError::bad_request("invalid_language", "lang must be a language tag such as \"es\" or \"pt-BR\""));
+    }
+    match translate(&app_data, &id, &query.lang).await {
+        Ok(translation) => Ok(HttpResponse::Ok().json(translation)),
+        Err(TranslateError::UnknownSession) => {
+            Err(ApiError::not_found("unknown_session", format!("Nothing logged for session {id}")))
+        }
+        Err(TranslateError::Failed(e)) => {
+            tracing::warn!(session_id = %id, lang = %query.lang, error = %format!("{e:#}"), "session translation failed");
+            Err(ApiError::bad_gateway("translation_failed", "Translation failed").with_detail(format!("{e:#}")))
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(translate_session);
+}
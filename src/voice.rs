@@ -0,0 +1,162 @@
+/////////////////////////////////////////////////////////////
+// src/voice.rs
+//
+// Spoken commands ([voice] enabled): the people being listened
+// to can say "silent night, stop listening" or "silent night,
+// bookmark that" instead of reaching for the web UI. Each
+// transcript of a recording source is checked for
+// voice.wake_phrase followed by one of voice.phrases, before it
+// goes to GPT; a match runs the phrase's command on that source
+// (see control.rs) and is cut out of the transcript, and a
+// chunk with nothing else in it is dropped, so commands never
+// reach GPT or the log.
+//   [voice.phrases]
+//   "stop listening" = "stop"
+//   "bookmark that" = "bookmark"
+//   "switch to {persona} mode" = "persona:{persona}"
+//   "forget the last {minutes} minutes" = "forget:{minutes}"
+// A phrase with {persona} matches each of openai.personas, with
+// "_" and "-" in names spoken as spaces; one with {minutes}
+// matches 2 to 60, in digits or words ("five", "twenty five"). Matching ignores case
+// and punctuation, and "silentnight" counts as "silent night".
+// Settings apply from the next chunk.
+/////////////////////////////////////////////////////////////
+
+use actix_web::web;
+
+use crate::config::VoiceSettings;
+use crate::control::{self, Command};
+use crate::sessions::SourceSession;
+use crate::AppState;
+
+// Commands taken from one transcript, at most
+const MAX_COMMANDS: usize = 4;
+// What {minutes} in a phrase matches
+const SPOKEN_MINUTES: std::ops::RangeInclusive<u32> = 2..=60;
+
+/////////////////////////////////////////////////////////////
+// intercept
+//
+// Runs the commands in `transcript`; returns what's left of it,
+// None if that's nothing.
+/////////////////////////////////////////////////////////////
+pub async fn intercept(app_data: &web::Data<AppState>, source: &SourceSession, transcript: String) -> Option<String> {
+    let (settings, personas) = {
+        let config = app_data.config.read().await;
+        if !config.voice.enabled {
+            return Some(transcript);
+        }
+        (config.voice.clone(), config.openai.personas.keys().cloned().collect::<Vec<_>>())
+    };
+    let phrases = phrases(&settings, &personas);
+    let mut text = transcript;
+    for _ in 0..MAX_COMMANDS {
+        let Some((command, start, end)) = find_command(&text, &settings.wake_phrase, &phrases) else {
+            break;
+        };
+        tracing::info!(command = %command, heard = %&text[start..end], "voice command");
+        match control::run(app_data, "voice", &source.name, &command).await {
+            Ok(done) => tracing::info!(command = %command, "{done}"),
+            Err(e) => tracing::warn!(command = %command, error = %format!("{e:#}"), "voice command failed"),
+        }
+        // With the punctuation that followed it
+        let rest = text[end..].trim_start_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace());
+        text = format!("{} {rest}", text[..start].trim_end()).trim().to_string();
+    }
+    text.chars().any(char::is_alphanumeric).then_some(text)
+}
+
+// (spoken words, command), longest first so "stop listening
+// now" wins over "stop listening"
+fn phrases(settings: &VoiceSettings, personas: &[String]) -> Vec<(Vec<String>, Command)> {
+    let mut phrases = Vec::new();
+    for (phrase, command) in &settings.phrases {
+        let expansions: Vec<(String, String)> = if phrase.contains("{persona}") {
+            personas
+                .iter()
+                .map(|name| (phrase.replace("{persona}", &name.replace(['_', '-'], " ")), command.replace("{persona}", name)))
+                .collect()
+        } else if phrase.contains("{minutes}") {
+            SPOKEN_MINUTES
+                .flat_map(|n| [n.to_string(), number_words(n)].map(|spoken| (n, spoken)))
+                .map(|(n, spoken)| (phrase.replace("{minutes}", &spoken), command.replace("{minutes}", &n.to_string())))
+                .collect()
+        } else {
+            vec![(phrase.clone(), command.clone())]
+        };
+        for (phrase, command) in expansions {
+            // Checked by Config::validate
+            if let Ok(command) = command.parse::<Command>() {
+                phrases.push((words(&phrase).into_iter().map(|w| w.text).collect::<Vec<_>>(), command));
+            }
+        }
+    }
+    phrases.sort_by_key(|(words, _)| std::cmp::Reverse(words.len()));
+    phrases
+}
+
+// The first command and the byte range it was spoken in
+fn find_command(text: &str, wake_phrase: &str, phrases: &[(Vec<String>, Command)]) -> Option<(Command, usize, usize)> {
+    let heard = words(text);
+    let wake: Vec<String> = words(wake_phrase).into_iter().map(|w| w.text).collect();
+    if wake.is_empty() {
+        return None;
+    }
+    let joined = wake.concat();
+    for i in 0..heard.len() {
+        let after_wake = if heard[i..].iter().map(|w| &w.text).take(wake.len()).eq(wake.iter()) {
+            i + wake.len()
+        } else if heard[i].text == joined {
+            i + 1
+        } else {
+            continue;
+        };
+        for (phrase, command) in phrases {
+            if !phrase.is_empty() && heard[after_wake.min(heard.len())..].iter().map(|w| &w.text).take(phrase.len()).eq(phrase.iter()) {
+                let last = &heard[after_wake + phrase.len() - 1];
+                return Some((command.clone(), heard[i].start, last.end));
+            }
+        }
+    }
+    None
+}
+
+// 1 to 99 as Whisper writes them out: "five", "twenty five"
+fn number_words(n: u32) -> String {
+    const ONES: [&str; 20] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven", "twelve",
+        "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+    ];
+    const TENS: [&str; 10] = ["", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+    match n {
+        0..=19 => ONES[n as usize].to_string(),
+        _ if n.is_multiple_of(10) => TENS[(n / 10) as usize].to_string(),
+        _ => format!("{} {}", TENS[(n / 10) as usize], ONES[(n % 10) as usize]),
+    }
+}
+
+struct Word {
+    // Lowercase, without apostrophes
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<Word> = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() || c == '\'' || c == '’' {
+            let word = current.get_or_insert(Word { text: String::new(), start: i, end: i });
+            if c.is_alphanumeric() {
+                word.text.extend(c.to_lowercase());
+            }
+            word.end = i + c.len_utf8();
+        } else if let Some(word) = current.take() {
+            words.push(word);
+        }
+    }
+    words.extend(current);
+    words.retain(|w| !w.text.is_empty());
+    words
+}
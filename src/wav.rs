@@ -0,0 +1,557 @@
+/////////////////////////////////////////////////////////////
+// src/wav.rs
+//
+// Checks a WAV buffer before it's uploaded to Whisper, so a
+// misbehaving arecord/rec (killed early, wrong format, nothing
+// but a header) fails with a clear error instead of an opaque
+// 400 from OpenAI. Accepted:
+//   - RIFF/WAVE with a "fmt " chunk before the "data" chunk;
+//     other chunks (LIST, fact, ...) are skipped
+//   - integer PCM (8/16/24/32 bit) or float (32/64 bit), also
+//     inside WAVE_FORMAT_EXTENSIBLE
+//   - 1-8 channels at 8-192 kHz, with byte rate and block
+//     align matching the rest of the header
+//   - at least MIN_DURATION of audio
+//
+// A header written to a pipe can't know the final length, so the
+// RIFF and data sizes may be placeholders (arecord writes the
+// expected size, SoX 0xFFFFFFFF); the bytes actually present win.
+//
+// Also a rough voice activity check (speech, is_speech) and
+// joining audio of the same format (join, with_data), for
+// batching short utterances into one Whisper request (see
+// pipeline.rs) and cutting chunks at pauses (see segmenter.rs),
+// and plain silence (silent) for simulated chunks.
+/////////////////////////////////////////////////////////////
+
+use std::time::Duration;
+
+// Whisper rejects anything shorter
+pub const MIN_DURATION: Duration = Duration::from_millis(100);
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Voice activity is judged per frame of this length...
+pub const SPEECH_FRAME: Duration = Duration::from_millis(30);
+// ...by its RMS level against full scale (about -34 dBFS)
+const SPEECH_LEVEL: f64 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    Pcm,
+    Float,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WavInfo {
+    pub format: SampleFormat,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    // Where the audio starts, and how many bytes of it there are
+    pub data_offset: usize,
+    pub data_len: usize,
+}
+
+impl WavInfo {
+    pub fn duration(&self) -> Duration {
+        let byte_rate = self.sample_rate as u64 * self.channels as u64 * (self.bits_per_sample / 8) as u64;
+        Duration::from_micros(self.data_len as u64 * 1_000_000 / byte_rate)
+    }
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum WavError {
+    #[error("not a RIFF/WAVE file")]
+    NotWav,
+    #[error("the {0:?} chunk runs past the end of the file")]
+    Truncated(String),
+    #[error("no \"fmt \" chunk before the audio data")]
+    MissingFmt,
+    #[error("no \"data\" chunk")]
+    MissingData,
+    #[error("\"fmt \" chunk is {0} bytes, expected at least 16")]
+    ShortFmt(usize),
+    #[error("unsupported sample format {0:#06x}, expected PCM or float")]
+    UnsupportedFormat(u16),
+    #[error("unsupported {bits}-bit samples")]
+    UnsupportedBits { bits: u16 },
+    #[error("unsupported channel count {0}, expected 1-8")]
+    UnsupportedChannels(u16),
+    #[error("unsupported sample rate {0} Hz, expected 8000-192000")]
+    UnsupportedSampleRate(u32),
+    #[error("inconsistent header: byte rate {byte_rate} and block align {block_align} don't match the format")]
+    Inconsistent { byte_rate: u32, block_align: u16 },
+    #[error("no audio after the header")]
+    EmptyData,
+    #[error("only {ms} ms of audio, need at least {}", MIN_DURATION.as_millis())]
+    TooShort { ms: u128 },
+}
+
+struct Fmt {
+    format_tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    byte_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+}
+
+/////////////////////////////////////////////////////////////
+// parse
+//
+// Walks the chunks up to "data" and validates what it finds.
+/////////////////////////////////////////////////////////////
+pub fn parse(wav: &[u8]) -> Result<WavInfo, WavError> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err(WavError::NotWav);
+    }
+
+    let mut fmt = None;
+    let mut pos = 12;
+    while pos + 8 <= wav.len() {
+        let id = &wav[pos..pos + 4];
+        let declared = u32_at(wav, pos + 4) as usize;
+        let body = pos + 8;
+        if id == b"data" {
+            let fmt = fmt.ok_or(WavError::MissingFmt)?;
+            return validate(fmt, body, declared.min(wav.len() - body));
+        }
+        let end = body
+            .checked_add(declared)
+            .filter(|&end| end <= wav.len())
+            .ok_or_else(|| WavError::Truncated(String::from_utf8_lossy(id).into_owned()))?;
+        if id == b"fmt " {
+            fmt = Some(parse_fmt(&wav[body..end])?);
+        }
+        // Chunks are padded to an even length
+        pos = end + (declared & 1);
+    }
+    Err(if fmt.is_none() { WavError::MissingFmt } else { WavError::MissingData })
+}
+
+fn parse_fmt(chunk: &[u8]) -> Result<Fmt, WavError> {
+    if chunk.len() < 16 {
+        return Err(WavError::ShortFmt(chunk.len()));
+    }
+    let mut format_tag = u16_at(chunk, 0);
+    // The real format is the start of the sub-format GUID
+    if format_tag == FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+        format_tag = u16_at(chunk, 24);
+    }
+    Ok(Fmt {
+        format_tag,
+        channels: u16_at(chunk, 2),
+        sample_rate: u32_at(chunk, 4),
+        byte_rate: u32_at(chunk, 8),
+        block_align: u16_at(chunk, 12),
+        bits_per_sample: u16_at(chunk, 14),
+    })
+}
+
+fn validate(fmt: Fmt, data_offset: usize, data_len: usize) -> Result<WavInfo, WavError> {
+    let bits = fmt.bits_per_sample;
+    let format = match fmt.format_tag {
+        FORMAT_PCM if matches!(bits, 8 | 16 | 24 | 32) => SampleFormat::Pcm,
+        FORMAT_FLOAT if matches!(bits, 32 | 64) => SampleFormat::Float,
+        FORMAT_PCM | FORMAT_FLOAT => return Err(WavError::UnsupportedBits { bits }),
+        other => return Err(WavError::UnsupportedFormat(other)),
+    };
+    if !(1..=8).contains(&fmt.channels) {
+        return Err(WavError::UnsupportedChannels(fmt.channels));
+    }
+    if !(8_000..=192_000).contains(&fmt.sample_rate) {
+        return Err(WavError::UnsupportedSampleRate(fmt.sample_rate));
+    }
+    let block_align = fmt.channels as u32 * (bits / 8) as u32;
+    if fmt.block_align as u32 != block_align || fmt.byte_rate != fmt.sample_rate * block_align {
+        return Err(WavError::Inconsistent {
+            byte_rate: fmt.byte_rate,
+            block_align: fmt.block_align,
+        });
+    }
+    if data_len == 0 {
+        return Err(WavError::EmptyData);
+    }
+
+    let info = WavInfo {
+        format,
+        channels: fmt.channels,
+        sample_rate: fmt.sample_rate,
+        bits_per_sample: bits,
+        data_offset,
+        data_len,
+    };
+    if info.duration() < MIN_DURATION {
+        return Err(WavError::TooShort { ms: info.duration().as_millis() });
+    }
+    Ok(info)
+}
+
+/////////////////////////////////////////////////////////////
+// silence
+//
+// Replaces the audio between `from` and `to` (from the start of
+// the clip) with silence, in place. `info` is what parse()
+// returned for `wav`.
+/////////////////////////////////////////////////////////////
+pub fn silence(wav: &mut [u8], info: &WavInfo, from: Duration, to: Duration) {
+    let block = info.channels as usize * (info.bits_per_sample / 8) as usize;
+    let at = |t: Duration| {
+        let frames = (t.as_secs_f64() * info.sample_rate as f64) as usize;
+        (frames * block).min(info.data_len)
+    };
+    let (start, end) = (at(from), at(to));
+    if start >= end {
+        return;
+    }
+    // 8-bit PCM is unsigned, centered on 128
+    let zero = if info.format == SampleFormat::Pcm && info.bits_per_sample == 8 { 0x80 } else { 0 };
+    wav[info.data_offset + start..info.data_offset + end].fill(zero);
+}
+
+/////////////////////////////////////////////////////////////
+// speech
+//
+// How much of the clip sounds like someone talking: the frames
+// louder than SPEECH_LEVEL. Crude (a slammed door counts), but
+// enough to tell a chunk with a word or two from one with a
+// sentence.
+/////////////////////////////////////////////////////////////
+pub fn speech(wav: &[u8], info: &WavInfo) -> Duration {
+    let data = &wav[info.data_offset..info.data_offset + info.data_len];
+    let loud = data.chunks(frame_len(info)).filter(|frame| is_speech(frame, info)).count();
+    (SPEECH_FRAME * loud as u32).min(info.duration())
+}
+
+// Bytes of audio in a SPEECH_FRAME, in whole frames
+pub fn frame_len(info: &WavInfo) -> usize {
+    let block = info.channels as usize * (info.bits_per_sample / 8) as usize;
+    ((SPEECH_FRAME.as_secs_f64() * info.sample_rate as f64) as usize).max(1) * block
+}
+
+// Whether a stretch of audio data (no header) is loud enough to be speech
+pub fn is_speech(data: &[u8], info: &WavInfo) -> bool {
+    let width = (info.bits_per_sample / 8) as usize;
+    let samples = data.len() / width;
+    if samples == 0 {
+        return false;
+    }
+    let power = data.chunks_exact(width).map(|s| sample(s, info.format).powi(2)).sum::<f64>() / samples as f64;
+    power.sqrt() >= SPEECH_LEVEL
+}
+
+// One sample as a fraction of full scale
+fn sample(bytes: &[u8], format: SampleFormat) -> f64 {
+    match (format, bytes.len()) {
+        // 8-bit PCM is unsigned, centered on 128
+        (SampleFormat::Pcm, 1) => (bytes[0] as f64 - 128.0) / 128.0,
+        (SampleFormat::Pcm, 2) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32_768.0,
+        (SampleFormat::Pcm, 3) => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f64 / 8_388_608.0,
+        (SampleFormat::Pcm, _) => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 2_147_483_648.0,
+        (SampleFormat::Float, 4) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        (SampleFormat::Float, _) => f64::from_le_bytes(bytes[..8].try_into().unwrap_or_default()),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// join
+//
+// The clips one after another, with the first one's header
+// (sizes updated); None unless they're all valid and in the same
+// format.
+/////////////////////////////////////////////////////////////
+pub fn join(clips: &[&[u8]]) -> Option<Vec<u8>> {
+    let infos = clips.iter().map(|clip| parse(clip).ok()).collect::<Option<Vec<WavInfo>>>()?;
+    let first = infos.first()?;
+    let same = |info: &WavInfo| {
+        (info.format, info.channels, info.sample_rate, info.bits_per_sample)
+            == (first.format, first.channels, first.sample_rate, first.bits_per_sample)
+    };
+    if !infos.iter().all(same) {
+        return None;
+    }
+    let data: Vec<&[u8]> = clips.iter().zip(&infos).map(|(clip, info)| &clip[info.data_offset..info.data_offset + info.data_len]).collect();
+    Some(with_data(&clips[0][..first.data_offset], &data))
+}
+
+/////////////////////////////////////////////////////////////
+// with_data
+//
+// A WAV file of `header` (everything before the audio, as parse
+// found it) and `data`, with the RIFF and data sizes set to match.
+/////////////////////////////////////////////////////////////
+pub fn with_data(header: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let data_len: usize = data.iter().map(|d| d.len()).sum();
+    let mut wav = Vec::with_capacity(header.len() + data_len);
+    wav.extend_from_slice(header);
+    for d in data {
+        wav.extend_from_slice(d);
+    }
+    let riff_len = u32::try_from(wav.len() - 8).unwrap_or(u32::MAX);
+    wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    let data_size = u32::try_from(data_len).unwrap_or(u32::MAX);
+    wav[header.len() - 4..header.len()].copy_from_slice(&data_size.to_le_bytes());
+    wav
+}
+
+/////////////////////////////////////////////////////////////
+// silent
+//
+// `duration` of silence as 16 kHz mono 16-bit PCM, the stand-in
+// audio for a scripted line (see recorder.rs).
+/////////////////////////////////////////////////////////////
+pub fn silent(duration: Duration) -> Vec<u8> {
+    const RATE: u32 = 16_000;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+    header.extend(16u32.to_le_bytes());
+    header.extend(FORMAT_PCM.to_le_bytes());
+    header.extend(1u16.to_le_bytes());
+    header.extend(RATE.to_le_bytes());
+    header.extend((RATE * 2).to_le_bytes());
+    header.extend(2u16.to_le_bytes());
+    header.extend(16u16.to_le_bytes());
+    header.extend_from_slice(b"data\0\0\0\0");
+    let frames = (duration.as_secs_f64() * RATE as f64) as usize;
+    with_data(&header, &[&vec![0; frames * 2]])
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Clone, Debug)]
+    struct Header {
+        format_tag: u16,
+        channels: u16,
+        sample_rate: u32,
+        bits: u16,
+    }
+
+    impl Header {
+        fn block_align(&self) -> u16 {
+            self.channels * (self.bits / 8)
+        }
+
+        // fmt chunk body, 16 bytes (or 40 for WAVE_FORMAT_EXTENSIBLE)
+        fn fmt(&self, extensible: bool) -> Vec<u8> {
+            let mut fmt = Vec::new();
+            fmt.extend((if extensible { FORMAT_EXTENSIBLE } else { self.format_tag }).to_le_bytes());
+            fmt.extend(self.channels.to_le_bytes());
+            fmt.extend(self.sample_rate.to_le_bytes());
+            fmt.extend((self.sample_rate * self.block_align() as u32).to_le_bytes());
+            fmt.extend(self.block_align().to_le_bytes());
+            fmt.extend(self.bits.to_le_bytes());
+            if extensible {
+                fmt.extend(22u16.to_le_bytes());
+                fmt.extend(self.bits.to_le_bytes());
+                fmt.extend(0u32.to_le_bytes());
+                fmt.extend(self.format_tag.to_le_bytes());
+                fmt.extend([0; 14]);
+            }
+            fmt
+        }
+    }
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend((body.len() as u32 + 4).to_le_bytes());
+        wav.extend(b"WAVE");
+        wav.extend(body);
+        wav
+    }
+
+    fn valid_header() -> impl Strategy<Value = Header> {
+        let format = prop_oneof![
+            prop::sample::select(vec![8u16, 16, 24, 32]).prop_map(|bits| (FORMAT_PCM, bits)),
+            prop::sample::select(vec![32u16, 64]).prop_map(|bits| (FORMAT_FLOAT, bits)),
+        ];
+        (format, 1u16..=8, 8_000u32..=192_000).prop_map(|((format_tag, bits), channels, sample_rate)| Header {
+            format_tag,
+            channels,
+            sample_rate,
+            bits,
+        })
+    }
+
+    // A header and enough whole frames for at least MIN_DURATION;
+    // an even count, so the data chunk needs no padding byte
+    fn valid_wav() -> impl Strategy<Value = (Header, usize)> {
+        valid_header().prop_flat_map(|header| {
+            let min_frames = (header.sample_rate as usize).div_ceil(10);
+            (Just(header), (min_frames / 2 + 1..min_frames * 3 / 2).prop_map(|pairs| pairs * 2))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn valid_headers_round_trip((header, frames) in valid_wav(), extensible in any::<bool>(), list in any::<bool>()) {
+            let data = vec![0u8; frames * header.block_align() as usize];
+            let mut chunks = vec![chunk(b"fmt ", &header.fmt(extensible))];
+            if list {
+                chunks.push(chunk(b"LIST", b"INFOISFT\x05\x00\x00\x00Lavf\x00"));
+            }
+            chunks.push(chunk(b"data", &data));
+
+            let info = parse(&riff(&chunks)).unwrap();
+            prop_assert_eq!(info.channels, header.channels);
+            prop_assert_eq!(info.sample_rate, header.sample_rate);
+            prop_assert_eq!(info.bits_per_sample, header.bits);
+            prop_assert_eq!(info.data_len, data.len());
+            prop_assert!(info.duration() >= MIN_DURATION);
+        }
+
+        #[test]
+        fn placeholder_sizes_use_the_bytes_present((header, frames) in valid_wav(), placeholder in prop::sample::select(vec![u32::MAX, 0x7FFF_FFFF])) {
+            let data = vec![1u8; frames * header.block_align() as usize];
+            let mut wav = riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &data)]);
+            wav[4..8].copy_from_slice(&placeholder.to_le_bytes());
+            // RIFF header, fmt chunk, "data"
+            let data_size = 12 + 24 + 4;
+            wav[data_size..data_size + 4].copy_from_slice(&placeholder.to_le_bytes());
+
+            prop_assert_eq!(parse(&wav).unwrap().data_len, data.len());
+        }
+
+        #[test]
+        fn inconsistent_byte_rate_is_rejected((header, frames) in valid_wav(), off_by in 1u32..1000) {
+            let mut fmt = header.fmt(false);
+            let byte_rate = header.sample_rate * header.block_align() as u32 + off_by;
+            fmt[8..12].copy_from_slice(&byte_rate.to_le_bytes());
+            let data = vec![0u8; frames * header.block_align() as usize];
+            let wav = riff(&[chunk(b"fmt ", &fmt), chunk(b"data", &data)]);
+
+            let rejected = matches!(parse(&wav), Err(WavError::Inconsistent { .. }));
+            prop_assert!(rejected);
+        }
+
+        #[test]
+        fn less_than_min_duration_is_rejected(header in valid_header(), frames in 1usize..800) {
+            prop_assume!(frames < header.sample_rate as usize / 10);
+            let data = vec![0u8; frames * header.block_align() as usize];
+            let wav = riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &data)]);
+
+            let rejected = matches!(parse(&wav), Err(WavError::TooShort { .. }));
+            prop_assert!(rejected);
+        }
+
+        #[test]
+        fn cut_off_before_the_audio_is_an_error(header in valid_header(), cut in 0usize..44) {
+            let wav = riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &[0; 4])]);
+            prop_assert!(parse(&wav[..cut]).is_err());
+        }
+
+        #[test]
+        fn silence_only_touches_the_range((header, frames) in valid_wav(), from in 0.0f64..0.2, len in 0.0f64..0.2) {
+            let data = vec![0x55u8; frames * header.block_align() as usize];
+            let mut wav = riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &data)]);
+            let info = parse(&wav).unwrap();
+            let (from, to) = (Duration::from_secs_f64(from), Duration::from_secs_f64(from + len));
+            silence(&mut wav, &info, from, to);
+
+            let audio = &wav[info.data_offset..];
+            prop_assert_eq!(audio.len(), data.len());
+            let silent = audio.iter().filter(|&&b| b != 0x55).count();
+            prop_assert_eq!(silent % header.block_align() as usize, 0);
+            let at = |t: Duration| (t.as_secs_f64() * header.sample_rate as f64) as usize * header.block_align() as usize;
+            prop_assert_eq!(silent, at(to).min(data.len()) - at(from).min(data.len()));
+            prop_assert!(audio[..at(from).min(data.len())].iter().all(|&b| b == 0x55));
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..256), riff_header in any::<bool>()) {
+            let mut wav = bytes;
+            if riff_header && wav.len() >= 12 {
+                wav[0..4].copy_from_slice(b"RIFF");
+                wav[8..12].copy_from_slice(b"WAVE");
+            }
+            let _ = parse(&wav);
+        }
+    }
+
+    // 16 kHz mono 16-bit: `quiet` seconds of silence, then `loud` of a square wave
+    fn clip(quiet: f64, loud: f64) -> Vec<u8> {
+        let header = Header { format_tag: FORMAT_PCM, channels: 1, sample_rate: 16_000, bits: 16 };
+        let mut data = vec![0u8; (quiet * 16_000.0) as usize * 2];
+        for i in 0..(loud * 16_000.0) as usize {
+            let level: i16 = if i % 40 < 20 { 8_000 } else { -8_000 };
+            data.extend(level.to_le_bytes());
+        }
+        riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &data)])
+    }
+
+    #[test]
+    fn speech_counts_the_loud_frames() {
+        let silent = clip(1.0, 0.0);
+        assert_eq!(speech(&silent, &parse(&silent).unwrap()), Duration::ZERO);
+        let wav = clip(1.0, 0.6);
+        let found = speech(&wav, &parse(&wav).unwrap()).as_millis();
+        assert!((570..=630).contains(&found), "{found} ms");
+    }
+
+    #[test]
+    fn joined_clips_play_one_after_another() {
+        let (a, b) = (clip(0.2, 0.0), clip(0.0, 0.3));
+        let joined = join(&[&a, &b]).unwrap();
+        let info = parse(&joined).unwrap();
+        assert_eq!(info.duration(), Duration::from_millis(500));
+        assert_eq!(u32_at(&joined, 4) as usize, joined.len() - 8);
+        assert_eq!(u32_at(&joined, info.data_offset - 4) as usize, info.data_len);
+        assert_eq!(&joined[info.data_offset + 6_400..], &b[44..]);
+
+        let other = Header { format_tag: FORMAT_PCM, channels: 2, sample_rate: 16_000, bits: 16 };
+        let stereo = riff(&[chunk(b"fmt ", &other.fmt(false)), chunk(b"data", &[0; 6_400])]);
+        assert_eq!(join(&[&a, &stereo]), None);
+        assert_eq!(join(&[&a, b"RIFF"]), None);
+    }
+
+    #[test]
+    fn silent_lasts_as_long_as_asked() {
+        let wav = silent(Duration::from_millis(1_500));
+        let info = parse(&wav).unwrap();
+        assert_eq!((info.channels, info.sample_rate, info.bits_per_sample), (1, 16_000, 16));
+        assert_eq!(info.duration(), Duration::from_millis(1_500));
+        assert_eq!(speech(&wav, &info), Duration::ZERO);
+    }
+
+    #[test]
+    fn header_only_is_empty() {
+        let header = Header { format_tag: FORMAT_PCM, channels: 1, sample_rate: 16_000, bits: 16 };
+        let wav = riff(&[chunk(b"fmt ", &header.fmt(false)), chunk(b"data", &[])]);
+        assert_eq!(parse(&wav), Err(WavError::EmptyData));
+    }
+
+    #[test]
+    fn unsupported_formats_are_named() {
+        let mut header = Header { format_tag: 2, channels: 1, sample_rate: 16_000, bits: 16 };
+        let wav = |h: &Header| riff(&[chunk(b"fmt ", &h.fmt(false)), chunk(b"data", &[0; 3200])]);
+        assert_eq!(parse(&wav(&header)), Err(WavError::UnsupportedFormat(2)));
+        header.format_tag = FORMAT_PCM;
+        header.sample_rate = 4_000;
+        assert_eq!(parse(&wav(&header)), Err(WavError::UnsupportedSampleRate(4_000)));
+        assert_eq!(parse(b"RIFX\0\0\0\0WAVE"), Err(WavError::NotWav));
+    }
+}
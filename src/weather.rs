@@ -0,0 +1,216 @@
+/////////////////////////////////////////////////////////////
+// src/weather.rs
+//
+// Weather and local info ([weather] enabled): GPT gets tools
+// (see tools.rs) so that "it's going to rain during your hike
+// tomorrow" comes from a forecast rather than a guess.
+//   get_weather     - now, and the next `days` days (1-7), for
+//                     weather.latitude/longitude from Open-Meteo
+//                     (no API key): daily highs, lows, chance of
+//                     rain, sunrise/sunset, and every
+//                     HOURLY_STEP hours
+//   get_local_info  - the place, date, time and UTC offset, from
+//                     the server's clock
+// Times are the place's local time; units follow weather.units.
+/////////////////////////////////////////////////////////////
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::config::WeatherSettings;
+use crate::{privacy, timezone, AppState};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_DAYS: u64 = 7;
+const DEFAULT_DAYS: u64 = 2;
+// Hours between the hourly entries GPT gets; every hour would be
+// a lot of tokens for little
+const HOURLY_STEP: usize = 3;
+
+// WMO weather interpretation codes, as Open-Meteo reports them
+const WEATHER_CODES: &[(u64, &str)] = &[
+    (0, "clear sky"),
+    (1, "mainly clear"),
+    (2, "partly cloudy"),
+    (3, "overcast"),
+    (45, "fog"),
+    (48, "freezing fog"),
+    (51, "light drizzle"),
+    (53, "drizzle"),
+    (55, "heavy drizzle"),
+    (56, "light freezing drizzle"),
+    (57, "freezing drizzle"),
+    (61, "light rain"),
+    (63, "rain"),
+    (65, "heavy rain"),
+    (66, "light freezing rain"),
+    (67, "freezing rain"),
+    (71, "light snow"),
+    (73, "snow"),
+    (75, "heavy snow"),
+    (77, "snow grains"),
+    (80, "light showers"),
+    (81, "showers"),
+    (82, "violent showers"),
+    (85, "light snow showers"),
+    (86, "snow showers"),
+    (95, "thunderstorm"),
+    (96, "thunderstorm with hail"),
+    (99, "thunderstorm with heavy hail"),
+];
+
+/////////////////////////////////////////////////////////////
+// Tools
+//
+// For tools.rs: what GPT is offered, and running a call.
+/////////////////////////////////////////////////////////////
+pub fn tools() -> Vec<Value> {
+    vec![
+        json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "The current weather and the forecast where the listeners are. Use it before saying anything about the weather.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "days": { "type": "integer", "description": format!("Days of forecast, 1-{MAX_DAYS}, today first (default {DEFAULT_DAYS})") }
+                    }
+                }
+            }
+        }),
+        json!({
+            "type": "function",
+            "function": {
+                "name": "get_local_info",
+                "description": "Where the listeners are, and their local date, day of the week and time.",
+                "parameters": { "type": "object", "properties": {} }
+            }
+        }),
+    ]
+}
+
+// None if `name` isn't one of ours
+pub async fn call(app_data: &AppState, name: &str, args: &Value) -> Option<Result<Value>> {
+    let settings = app_data.config.read().await.weather.clone();
+    Some(match name {
+        "get_weather" => {
+            let days = args["days"].as_u64().unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+            forecast(&settings, days).await
+        }
+        "get_local_info" => Ok(local_info(&settings)),
+        _ => return None,
+    })
+}
+
+async fn forecast(settings: &WeatherSettings, days: u64) -> Result<Value> {
+    let imperial = settings.units == "imperial";
+    let mut query = vec![
+        ("latitude", settings.latitude.to_string()),
+        ("longitude", settings.longitude.to_string()),
+        ("timezone", "auto".to_string()),
+        ("forecast_days", days.to_string()),
+        ("current", "temperature_2m,apparent_temperature,weather_code,wind_speed_10m,precipitation".to_string()),
+        ("hourly", "temperature_2m,precipitation_probability,weather_code".to_string()),
+        (
+            "daily",
+            "weather_code,temperature_2m_max,temperature_2m_min,precipitation_probability_max,precipitation_sum,sunrise,sunset"
+                .to_string(),
+        ),
+    ];
+    if imperial {
+        query.extend([
+            ("temperature_unit", "fahrenheit".to_string()),
+            ("wind_speed_unit", "mph".to_string()),
+            ("precipitation_unit", "inch".to_string()),
+        ]);
+    }
+    let url = format!("{}/v1/forecast", settings.api_url.trim_end_matches('/'));
+    let resp = privacy::client()
+        .get(&url)
+        .query(&query)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await
+        .context("Open-Meteo unreachable")?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Open-Meteo returned {status}: {body}");
+    }
+    let raw: Value = resp.json().await.context("Open-Meteo sent something that isn't JSON")?;
+    tracing::debug!(days, "fetched the forecast");
+
+    let current = &raw["current"];
+    let daily = &raw["daily"];
+    let hourly = &raw["hourly"];
+    let days: Vec<Value> = column(daily, "time")
+        .iter()
+        .enumerate()
+        .map(|(i, date)| {
+            json!({
+                "date": date,
+                "weather": described(&daily["weather_code"][i]),
+                "high": daily["temperature_2m_max"][i],
+                "low": daily["temperature_2m_min"][i],
+                "chance_of_rain_percent": daily["precipitation_probability_max"][i],
+                "precipitation": daily["precipitation_sum"][i],
+                "sunrise": daily["sunrise"][i],
+                "sunset": daily["sunset"][i],
+            })
+        })
+        .collect();
+    let hours: Vec<Value> = column(hourly, "time")
+        .iter()
+        .enumerate()
+        .step_by(HOURLY_STEP)
+        .map(|(i, time)| {
+            json!({
+                "time": time,
+                "temperature": hourly["temperature_2m"][i],
+                "chance_of_rain_percent": hourly["precipitation_probability"][i],
+                "weather": described(&hourly["weather_code"][i]),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "place": settings.place,
+        "units": if imperial { "°F, mph, inches" } else { "°C, km/h, mm" },
+        "now": {
+            "time": current["time"],
+            "temperature": current["temperature_2m"],
+            "feels_like": current["apparent_temperature"],
+            "weather": described(&current["weather_code"]),
+            "wind_speed": current["wind_speed_10m"],
+            "precipitation": current["precipitation"],
+        },
+        "days": days,
+        "hourly": hours,
+    }))
+}
+
+fn column<'a>(section: &'a Value, name: &str) -> &'a [Value] {
+    section[name].as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn described(code: &Value) -> Value {
+    let Some(code) = code.as_u64() else { return Value::Null };
+    match WEATHER_CODES.iter().find(|(c, _)| *c == code) {
+        Some((_, text)) => json!(text),
+        None => json!(format!("weather code {code}")),
+    }
+}
+
+fn local_info(settings: &WeatherSettings) -> Value {
+    let now = timezone::now();
+    json!({
+        "place": settings.place,
+        "latitude": settings.latitude,
+        "longitude": settings.longitude,
+        "date": now.format("%Y-%m-%d").to_string(),
+        "weekday": now.format("%A").to_string(),
+        "time": now.format("%H:%M").to_string(),
+        "utc_offset": now.format("%:z").to_string(),
+    })
+}
@@ -0,0 +1,295 @@
+/////////////////////////////////////////////////////////////
+// src/webhooks.rs
+//
+// Outbound webhooks: each [[webhooks]] target gets a JSON POST
+// for the events it subscribes to:
+//   transcript       - a new Whisper transcript was logged
+//   response         - a new GPT response was logged
+//                      (both while [outputs] has them go to
+//                      "webhook", the default; see outputs.rs)
+//   session.started  - a source started recording
+//   session.stopped  - a source's recording loop ended
+//   bookmark         - someone bookmarked the moment (see
+//                      bookmarks.rs)
+//   reminder         - a reminder GPT set is due (see
+//                      reminders.rs)
+// and rules.rs sends "rule" events to its webhook actions.
+//
+// Body: {"id", "event", "timestamp", "data"}, where data is the
+// conversation_log.json record for transcript/response, or
+// {audio_source, session_id, error} for session events, or the
+// bookmark or reminder.
+//
+// Headers: X-SilentNight-Event, X-SilentNight-Delivery (the id),
+// and, when the target has a secret,
+//   X-SilentNight-Signature: sha256=<hex HMAC-SHA256 of the body>
+//
+// Failed deliveries (network errors, 408, 429, 5xx) are retried
+// with backoff. GET /webhooks shows the targets and the most
+// recent deliveries. Targets are read from the config at each
+// event, so a reload applies to the next one.
+/////////////////////////////////////////////////////////////
+
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::Utc;
+use ring::hmac;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::config::WebhookTarget;
+use crate::{hex, logging, privacy, AppState};
+
+pub const EVENT_TYPES: [&str; 6] = ["transcript", "response", "session.started", "session.stopped", "bookmark", "reminder"];
+
+// One try plus this many retries, waiting 2s, 4s, 8s, ...
+const MAX_RETRIES: u32 = 4;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+// How many deliveries GET /webhooks remembers
+const RECENT_DELIVERIES: usize = 50;
+
+/////////////////////////////////////////////////////////////
+// Delivery
+//
+// One event sent to one target.
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema, Clone)]
+pub(crate) struct Delivery {
+    id: String,
+    event: String,
+    url: String,
+    // "pending", "delivered" or "failed"
+    status: String,
+    attempts: u32,
+    // HTTP status of the last attempt, if it got a response
+    response_status: Option<u16>,
+    last_error: Option<String>,
+    // RFC 3339
+    created_at: String,
+    updated_at: String,
+}
+
+/////////////////////////////////////////////////////////////
+// Webhooks
+/////////////////////////////////////////////////////////////
+pub struct Webhooks {
+    client: reqwest::Client,
+    // Newest last
+    recent: AsyncMutex<VecDeque<Delivery>>,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Webhooks {
+            client: privacy::client(),
+            recent: AsyncMutex::default(),
+            delivered: AtomicU64::default(),
+            failed: AtomicU64::default(),
+        }
+    }
+}
+
+impl Webhooks {
+    async fn update(&self, id: &str, url: &str, change: impl FnOnce(&mut Delivery)) {
+        let mut recent = self.recent.lock().await;
+        if let Some(delivery) = recent.iter_mut().find(|d| d.id == id && d.url == url) {
+            change(delivery);
+            delivery.updated_at = Utc::now().to_rfc3339();
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// send
+//
+// Queues `event` for every target subscribed to it and returns
+// right away; delivery happens in background tasks.
+/////////////////////////////////////////////////////////////
+pub async fn send(app_data: &web::Data<AppState>, event: &str, data: serde_json::Value) {
+    let targets: Vec<WebhookTarget> = app_data
+        .config
+        .read()
+        .await
+        .webhooks
+        .iter()
+        .filter(|t| t.events.is_empty() || t.events.iter().any(|e| e == event))
+        .cloned()
+        .collect();
+    queue(app_data, targets, event, data).await;
+}
+
+// Same, to one target that isn't in the config (a rule's
+// webhook action, see rules.rs)
+pub async fn send_to(app_data: &web::Data<AppState>, target: WebhookTarget, event: &str, data: serde_json::Value) {
+    queue(app_data, vec![target], event, data).await;
+}
+
+async fn queue(app_data: &web::Data<AppState>, targets: Vec<WebhookTarget>, event: &str, data: serde_json::Value) {
+    if targets.is_empty() {
+        return;
+    }
+
+    let id = logging::new_id();
+    let now = Utc::now().to_rfc3339();
+    let body = serde_json::json!({
+        "id": id,
+        "event": event,
+        "timestamp": now,
+        "data": data,
+    })
+    .to_string();
+
+    for target in targets {
+        {
+            let mut recent = app_data.webhooks.recent.lock().await;
+            if recent.len() == RECENT_DELIVERIES {
+                recent.pop_front();
+            }
+            recent.push_back(Delivery {
+                id: id.clone(),
+                event: event.to_string(),
+                url: target.url.clone(),
+                status: "pending".to_string(),
+                attempts: 0,
+                response_status: None,
+                last_error: None,
+                created_at: now.clone(),
+                updated_at: now.clone(),
+            });
+        }
+
+        let span = tracing::info_span!(parent: None, "webhook", delivery_id = %id, event, url = %target.url);
+        let delivery = deliver(app_data.clone(), target, event.to_string(), id.clone(), body.clone());
+        app_data.tasks.spawn("webhook", delivery.instrument(span));
+    }
+}
+
+async fn deliver(app_data: web::Data<AppState>, target: WebhookTarget, event: String, id: String, body: String) {
+    let webhooks = &app_data.webhooks;
+    let signature = (!target.secret.is_empty()).then(|| sign(&target.secret, &body));
+    let shutdown = app_data.tasks.token();
+
+    for attempt in 1..=MAX_RETRIES + 1 {
+        let mut request = webhooks
+            .client
+            .post(&target.url)
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .header("Content-Type", "application/json")
+            .header("X-SilentNight-Event", &event)
+            .header("X-SilentNight-Delivery", &id);
+        if let Some(signature) = &signature {
+            request = request.header("X-SilentNight-Signature", signature);
+        }
+
+        let (response_status, error, retryable) = match request.body(body.clone()).send().await {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16()), None, false),
+            Ok(resp) => {
+                let status = resp.status();
+                let retryable = status.is_server_error() || matches!(status.as_u16(), 408 | 429);
+                (Some(status.as_u16()), Some(format!("HTTP {status}")), retryable)
+            }
+            Err(e) => (None, Some(e.to_string()), true),
+        };
+
+        let Some(error) = error else {
+            tracing::debug!(attempt, "webhook delivered");
+            webhooks.delivered.fetch_add(1, Ordering::Relaxed);
+            webhooks
+                .update(&id, &target.url, |d| {
+                    d.status = "delivered".to_string();
+                    d.attempts = attempt;
+                    d.response_status = response_status;
+                    d.last_error = None;
+                })
+                .await;
+            return;
+        };
+
+        let give_up = !retryable || attempt > MAX_RETRIES;
+        tracing::warn!(attempt, error = %error, give_up, "webhook delivery failed");
+        webhooks
+            .update(&id, &target.url, |d| {
+                d.status = if give_up { "failed" } else { "pending" }.to_string();
+                d.attempts = attempt;
+                d.response_status = response_status;
+                d.last_error = Some(error);
+            })
+            .await;
+        if give_up {
+            webhooks.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        // No more retries once the server is shutting down
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1 << attempt)) => {}
+            _ = shutdown.cancelled() => {
+                tracing::info!(attempt, "shutting down, giving up on webhook");
+                return;
+            }
+        }
+    }
+}
+
+// "sha256=<hex HMAC-SHA256 of body>"
+fn sign(secret: &str, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, body.as_bytes());
+    format!("sha256={}", hex::encode(tag.as_ref()))
+}
+
+/////////////////////////////////////////////////////////////
+// GET /webhooks
+/////////////////////////////////////////////////////////////
+#[derive(Serialize, ToSchema)]
+pub(crate) struct WebhooksResponse {
+    targets: Vec<TargetInfo>,
+    // Totals since startup
+    delivered: u64,
+    failed: u64,
+    // Newest first
+    recent: Vec<Delivery>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct TargetInfo {
+    url: String,
+    // Empty = every event
+    events: Vec<String>,
+    signed: bool,
+}
+
+#[utoipa::path(tag = "monitoring", responses((status = 200, description = "Webhook targets and recent deliveries", body = WebhooksResponse)))]
+#[get("/webhooks")]
+async fn webhooks_status(app_data: web::Data<AppState>) -> impl Responder {
+    let targets = app_data
+        .config
+        .read()
+        .await
+        .webhooks
+        .iter()
+        .map(|t| TargetInfo {
+            url: t.url.clone(),
+            events: t.events.clone(),
+            signed: !t.secret.is_empty(),
+        })
+        .collect();
+    let webhooks = &app_data.webhooks;
+    let recent = webhooks.recent.lock().await.iter().rev().cloned().collect();
+
+    HttpResponse::Ok().json(WebhooksResponse {
+        targets,
+        delivered: webhooks.delivered.load(Ordering::Relaxed),
+        failed: webhooks.failed.load(Ordering::Relaxed),
+        recent,
+    })
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(webhooks_status);
+}
@@ -0,0 +1,220 @@
+/////////////////////////////////////////////////////////////
+// src/workspaces.rs
+//
+// One hub shared by teams that mustn't read each other's
+// transcripts:
+//   [workspaces.sales]
+//   sources = ["sales-room"]
+//   persona = "notetaker"
+//   retention_days = 30
+//
+//   [tokens.sales]
+//   token = "..."
+//   scope = "admin"
+//   workspace = "sales"
+// A workspace's sources log to workspaces/<name>/
+// conversation_log.json instead of conversation_log.json, and
+// their records say which "workspace" they're in. Its sessions
+// start with its persona unless they're given another, and the
+// entries it logged more than retention_days ago are deleted
+// (checked hourly). Everything else is the default workspace,
+// which keeps everything.
+//
+// The log endpoints read one workspace's log:
+// /conversation_log, /entries/{id} (and its /feedback),
+// /feedback/stats, /sessions, /sessions/{id}, its /replay and
+// /export, and /graphql. A token with a workspace always gets
+// that one, and may reach nothing else but its own sources
+// (/sources/{name}/...) and /logout; anything else is
+// outside_workspace (403). Its scope still applies, so reading
+// the log takes "admin": admin of the workspace, not the hub. The login and unconfined tokens get
+// the default workspace, or another with ?workspace=<name>.
+//
+// Shared by every workspace: the web UI and /live_log (confined
+// tokens use their sources' streams), outputs such as webhooks
+// and MQTT, the digest (the default workspace's) and sync (ditto).
+/////////////////////////////////////////////////////////////
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::Instrument;
+use utoipa::IntoParams;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::session_settings::Overrides;
+use crate::sessions::SourceSession;
+use crate::{forget, AppState, CONVERSATION_LOG};
+
+const WORKSPACES_DIR: &str = "workspaces";
+const RETENTION_CHECK_SECS: u64 = 60 * 60;
+
+// What a confined token may reach besides its sources; {} is one
+// path segment
+const CONFINED_PATHS: [&str; 10] = [
+    "/logout",
+    "/conversation_log",
+    "/entries/{}",
+    "/entries/{}/feedback",
+    "/feedback/stats",
+    "/sessions",
+    "/sessions/{}",
+    "/sessions/{}/replay",
+    "/sessions/{}/export",
+    "/graphql",
+];
+
+// The workspace a request's token is confined to, put in its
+// extensions by auth.rs
+#[derive(Clone)]
+pub struct Confined(pub String);
+
+// "" for the default workspace
+pub fn of_source(config: &Config, source: &str) -> String {
+    config
+        .workspaces
+        .iter()
+        .find(|(_, workspace)| workspace.sources.iter().any(|s| s == source))
+        .map(|(name, _)| name.clone())
+        .unwrap_or_default()
+}
+
+// `source`'s session overrides, with its workspace's persona
+// unless the session was given one
+pub fn overrides(config: &Config, source: &SourceSession) -> Overrides {
+    let mut overrides = source.overrides();
+    if overrides.persona.is_none() {
+        overrides.persona = config.workspaces.get(&of_source(config, &source.name)).map(|w| w.persona.clone()).filter(|p| !p.is_empty());
+    }
+    overrides
+}
+
+pub fn log_path(workspace: &str) -> String {
+    if workspace.is_empty() {
+        CONVERSATION_LOG.to_string()
+    } else {
+        format!("{WORKSPACES_DIR}/{workspace}/{CONVERSATION_LOG}")
+    }
+}
+
+// Every workspace's log there is, the default one first
+pub fn log_paths() -> Vec<String> {
+    let mut paths = vec![CONVERSATION_LOG.to_string()];
+    let mut named: Vec<String> = std::fs::read_dir(WORKSPACES_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .map(|name| log_path(&name))
+        .filter(|path| std::path::Path::new(path).exists())
+        .collect();
+    named.sort();
+    paths.extend(named);
+    paths
+}
+
+/////////////////////////////////////////////////////////////
+// allows
+//
+// Whether a token confined to `workspace` may make the request.
+/////////////////////////////////////////////////////////////
+pub fn allows(req: &ServiceRequest, config: &Config, workspace: &str) -> bool {
+    if let Some((source, _)) = req.path().strip_prefix("/sources/").and_then(|rest| rest.split_once('/')) {
+        return of_source(config, source) == workspace;
+    }
+    let segments: Vec<&str> = req.path().split('/').collect();
+    CONFINED_PATHS.iter().any(|allowed| {
+        let pattern: Vec<&str> = allowed.split('/').collect();
+        pattern.len() == segments.len()
+            && pattern.iter().zip(&segments).all(|(p, s)| *p == *s || (*p == "{}" && !s.is_empty()))
+    })
+}
+
+pub fn outside(workspace: &str, req: &ServiceRequest) -> ApiError {
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        "outside_workspace",
+        format!("The token is confined to the {workspace} workspace, which doesn't include {} {}", req.method(), req.path()),
+    )
+}
+
+#[derive(Deserialize, IntoParams)]
+pub(crate) struct WorkspaceQuery {
+    // Whose log to read, for the login and unconfined tokens;
+    // default the default workspace
+    workspace: Option<String>,
+}
+
+/////////////////////////////////////////////////////////////
+// requested
+//
+// The workspace whose log a request reads: its token's, or else
+// ?workspace=, or else the default one ("").
+/////////////////////////////////////////////////////////////
+pub async fn requested(req: &HttpRequest, app_data: &AppState) -> Result<String, ApiError> {
+    let asked = web::Query::<WorkspaceQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().workspace)
+        .filter(|name| !name.is_empty());
+    if let Some(Confined(workspace)) = req.extensions().get::<Confined>() {
+        return match asked {
+            Some(asked) if asked != *workspace => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "outside_workspace",
+                format!("The token is confined to the {workspace} workspace"),
+            )),
+            _ => Ok(workspace.clone()),
+        };
+    }
+    match asked {
+        Some(asked) if !app_data.config.read().await.workspaces.contains_key(&asked) => {
+            Err(ApiError::not_found("unknown_workspace", format!("No workspace named {asked:?} in [workspaces]")))
+        }
+        asked => Ok(asked.unwrap_or_default()),
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// spawn
+//
+// Deletes what's past each workspace's retention_days, hourly.
+/////////////////////////////////////////////////////////////
+pub fn spawn(app_data: &web::Data<AppState>) {
+    let span = tracing::info_span!(parent: None, "retention");
+    app_data.tasks.spawn("retention", expire(app_data.clone()).instrument(span));
+}
+
+async fn expire(app_data: web::Data<AppState>) {
+    let shutdown = app_data.tasks.token();
+    loop {
+        let workspaces = app_data.config.read().await.workspaces.clone();
+        for (name, workspace) in workspaces.into_iter().filter(|(_, w)| w.retention_days > 0) {
+            let cutoff = Utc::now() - chrono::Duration::days(workspace.retention_days.into());
+            let path = log_path(&name);
+            let removed = tokio::task::spawn_blocking(move || forget::remove_records(&path, |record| expired(record, cutoff))).await;
+            match removed {
+                Ok(Ok(removed)) if !removed.is_empty() => {
+                    tracing::info!(workspace = %name, records = removed.len(), days = workspace.retention_days, "deleted expired log entries");
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::warn!(workspace = %name, error = %format!("{e:#}"), "couldn't delete expired log entries"),
+                Err(e) => tracing::warn!(workspace = %name, error = %e, "deleting expired log entries panicked"),
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(RETENTION_CHECK_SECS)) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+fn expired(record: &serde_json::Value, cutoff: DateTime<Utc>) -> bool {
+    record["timestamp"]
+        .as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t < cutoff)
+}
@@ -0,0 +1,798 @@
+/////////////////////////////////////////////////////////////
+// tests/common/mod.rs
+//
+// Harness for the end-to-end tests: runs the real binary in a
+// temporary working directory with the "file" mic backend (WAV
+// fixtures from tests/fixtures) and openai.base_url pointed at a
+// wiremock server standing in for Whisper and ChatCompletion
+// (see mock_openai).
+//
+// The server's output goes to server.log in its directory and is
+// printed if the test fails.
+/////////////////////////////////////////////////////////////
+
+#![allow(dead_code)] // each test file uses its own subset
+
+use serde_json::{json, Value};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+pub const API_KEY: &str = "sk-test";
+pub const TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct TestServer {
+    pub url: String,
+    pub dir: TempDir,
+    pub http: reqwest::Client,
+    child: Child,
+}
+
+impl TestServer {
+    // `openai` is where the OpenAI API is, normally the mock's uri()
+    pub async fn start(openai: &str) -> TestServer {
+        TestServer::start_with_env(openai, &[]).await
+    }
+
+    // With extra environment variables, e.g. to override settings
+    pub async fn start_with_env(openai: &str, env: &[(&str, &str)]) -> TestServer {
+        TestServer::start_with_config(openai, "", env).await
+    }
+
+    // With `extra` appended to the config file, for settings that
+    // have no environment variable (tables, maps)
+    pub async fn start_with_config(openai: &str, extra: &str, env: &[(&str, &str)]) -> TestServer {
+        let dir = TempDir::new().expect("create temp dir");
+        let port = free_port();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let config = format!(
+            r#"
+[server]
+port = {port}
+bind_addr = "127.0.0.1"
+
+[audio]
+mic_backend = "file"
+device = {fixtures:?}
+chunk_secs = 1
+
+[openai]
+api_key = "{API_KEY}"
+base_url = "{openai}/v1"
+
+[rate_limit]
+per_minute = 0
+
+[discovery]
+enabled = false
+{extra}
+"#,
+            fixtures = fixtures.display().to_string(),
+        );
+        let config_path = dir.path().join("silentnight.toml");
+        std::fs::write(&config_path, config).expect("write config");
+
+        let log = std::fs::File::create(dir.path().join("server.log")).expect("create server.log");
+        // A clean environment so the developer's own settings
+        // (OPENAI_API_KEY, PORT, ...) don't leak in
+        let child = Command::new(env!("CARGO_BIN_EXE_my-project"))
+            .arg("--config")
+            .arg(&config_path)
+            .current_dir(dir.path())
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("LOG_LEVEL", "info,my_project=debug")
+            .envs(env.iter().copied())
+            .stdout(log.try_clone().expect("clone log handle"))
+            .stderr(log)
+            .spawn()
+            .expect("start server");
+
+        let server = TestServer {
+            url: format!("http://127.0.0.1:{port}"),
+            dir,
+            http: reqwest::Client::new(),
+            child,
+        };
+        server.wait_until(|| async { server.http.get(server.url("/health")).send().await.is_ok() }).await;
+        server
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    pub async fn post(&self, path: &str) -> reqwest::Response {
+        self.http.post(self.url(path)).send().await.expect("POST")
+    }
+
+    pub async fn get_json(&self, path: &str) -> Value {
+        let resp = self.http.get(self.url(path)).send().await.expect("GET");
+        resp.json().await.expect("JSON body")
+    }
+
+    // conversation_log.json, one record per line; empty before the
+    // first record (the endpoint 404s until the file exists)
+    pub async fn log_records(&self) -> Vec<Value> {
+        let resp = self.http.get(self.url("/conversation_log")).send().await.expect("GET /conversation_log");
+        if resp.status() == 404 {
+            return Vec::new();
+        }
+        let text = resp.text().await.expect("log body");
+        text.lines().map(|line| serde_json::from_str(line).expect("log record")).collect()
+    }
+
+    // Polls until `check` passes, panicking after TIMEOUT
+    pub async fn wait_until<F, Fut>(&self, check: F)
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        let started = Instant::now();
+        while !check().await {
+            assert!(started.elapsed() < TIMEOUT, "timed out waiting for the server");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if std::thread::panicking() {
+            let log = std::fs::read_to_string(self.dir.path().join("server.log")).unwrap_or_default();
+            eprintln!("----- server.log -----\n{log}");
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// SseStream
+//
+// Reads a live log response one event at a time.
+/////////////////////////////////////////////////////////////
+pub struct SseStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+pub struct SseEvent {
+    // None for plain records, "state" for state changes
+    pub event: Option<String>,
+    pub data: Value,
+}
+
+impl SseStream {
+    pub async fn open(server: &TestServer, path: &str) -> SseStream {
+        let response = server.http.get(server.url(path)).send().await.expect("GET live log");
+        assert_eq!(response.status(), 200);
+        SseStream { response, buffer: String::new() }
+    }
+
+    pub async fn next(&mut self) -> SseEvent {
+        loop {
+            // Events end with a blank line; comments (keepalives) have no data
+            if let Some(end) = self.buffer.find("\n\n") {
+                let frame: String = self.buffer.drain(..end + 2).collect();
+                let mut event = None;
+                let mut data = None;
+                for line in frame.lines() {
+                    if let Some(name) = line.strip_prefix("event: ") {
+                        event = Some(name.to_string());
+                    } else if let Some(json) = line.strip_prefix("data: ") {
+                        data = Some(serde_json::from_str(json).expect("event data"));
+                    }
+                }
+                if let Some(data) = data {
+                    return SseEvent { event, data };
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(TIMEOUT, self.response.chunk())
+                .await
+                .expect("timed out waiting for an SSE event")
+                .expect("read SSE stream")
+                .expect("SSE stream ended");
+            self.buffer.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    // The next log record, skipping state events
+    pub async fn next_record(&mut self) -> Value {
+        loop {
+            let ev = self.next().await;
+            if ev.event.is_none() {
+                return ev.data;
+            }
+        }
+    }
+
+    // The next state change's name ("recording", ...), skipping records
+    pub async fn next_state(&mut self) -> String {
+        loop {
+            let ev = self.next().await;
+            if ev.event.as_deref() == Some("state") {
+                return ev.data["state"]["name"].as_str().expect("state name").to_string();
+            }
+        }
+    }
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("find a free port")
+        .port()
+}
+
+/////////////////////////////////////////////////////////////
+// OpenAI mocks
+/////////////////////////////////////////////////////////////
+pub fn whisper() -> wiremock::MockBuilder {
+    Mock::given(method("POST"))
+        .and(path("/v1/audio/transcriptions"))
+        .and(header("authorization", format!("Bearer {API_KEY}").as_str()))
+}
+
+pub fn chat() -> wiremock::MockBuilder {
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .and(header("authorization", format!("Bearer {API_KEY}").as_str()))
+}
+
+pub fn transcript(text: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({ "text": text }))
+}
+
+pub fn completion(text: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "choices": [{ "message": { "role": "assistant", "content": text } }]
+    }))
+}
+
+// GPT calling one of the tools it was offered
+pub fn tool_call(name: &str, arguments: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "choices": [{ "message": {
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": name, "arguments": arguments.to_string() },
+            }],
+        } }]
+    }))
+}
+
+// Whisper hears `heard` and GPT always answers `reply`
+pub async fn mock_openai(heard: &str, reply: &str) -> MockServer {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript(heard)).mount(&openai).await;
+    chat().respond_with(completion(reply)).mount(&openai).await;
+    openai
+}
+
+/////////////////////////////////////////////////////////////
+// MqttBroker
+//
+// Just enough of an MQTT broker for one client: acks its
+// connection, subscriptions and QoS 1 publishes, hands over what
+// it publishes, and delivers messages to it whatever it has
+// subscribed to.
+/////////////////////////////////////////////////////////////
+#[cfg(feature = "mqtt")]
+pub struct MqttBroker {
+    pub port: u16,
+    published: tokio::sync::mpsc::UnboundedReceiver<(String, String)>,
+    outgoing: tokio::sync::mpsc::UnboundedSender<(String, String)>,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttBroker {
+    pub async fn start() -> MqttBroker {
+        use bytes::BytesMut;
+        use rumqttc::mqttbytes::{self, v4};
+        use rumqttc::{ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, Publish, QoS, SubAck, SubscribeReasonCode};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind the MQTT port");
+        let port = listener.local_addr().unwrap().port();
+        let (published_tx, published) = tokio::sync::mpsc::unbounded_channel();
+        let (outgoing, mut outgoing_rx) = tokio::sync::mpsc::unbounded_channel::<(String, String)>();
+        tokio::spawn(async move {
+            let Ok((mut stream, _)) = listener.accept().await else { return };
+            let (mut reader, mut writer) = stream.split();
+            let mut incoming = BytesMut::new();
+            loop {
+                let mut reply = BytesMut::new();
+                tokio::select! {
+                    read = reader.read_buf(&mut incoming) => {
+                        if !matches!(read, Ok(n) if n > 0) {
+                            return;
+                        }
+                        loop {
+                            let packet = match v4::read(&mut incoming, 1 << 20) {
+                                Ok(packet) => packet,
+                                Err(mqttbytes::Error::InsufficientBytes(_)) => break,
+                                Err(e) => panic!("bad MQTT packet: {e:?}"),
+                            };
+                            match packet {
+                                Packet::Connect(_) => {
+                                    ConnAck::new(ConnectReturnCode::Success, false).write(&mut reply).unwrap();
+                                }
+                                Packet::Subscribe(subscribe) => {
+                                    let codes = subscribe.filters.iter().map(|_| SubscribeReasonCode::Success(QoS::AtLeastOnce)).collect();
+                                    SubAck::new(subscribe.pkid, codes).write(&mut reply).unwrap();
+                                }
+                                Packet::Publish(publish) => {
+                                    if publish.qos == QoS::AtLeastOnce {
+                                        PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                                    }
+                                    let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                                    let _ = published_tx.send((publish.topic, payload));
+                                }
+                                Packet::PingReq => {
+                                    PingResp.write(&mut reply).unwrap();
+                                }
+                                Packet::Disconnect => return,
+                                _ => {}
+                            }
+                        }
+                    }
+                    Some((topic, payload)) = outgoing_rx.recv() => {
+                        Publish::new(topic, QoS::AtMostOnce, payload).write(&mut reply).unwrap();
+                    }
+                }
+                if !reply.is_empty() && writer.write_all(&reply).await.is_err() {
+                    return;
+                }
+            }
+        });
+        MqttBroker { port, published, outgoing }
+    }
+
+    // The next message the client publishes on `topic`
+    pub async fn next_on(&mut self, topic: &str) -> String {
+        let wait = async {
+            loop {
+                match self.published.recv().await {
+                    Some((t, payload)) if t == topic => return payload,
+                    Some(_) => {}
+                    None => panic!("the MQTT client went away"),
+                }
+            }
+        };
+        tokio::time::timeout(TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("nothing published on {topic} within {TIMEOUT:?}"))
+    }
+
+    // Skips what's published on `topic` until `payload` is
+    pub async fn wait_for(&mut self, topic: &str, payload: &str) {
+        while self.next_on(topic).await != payload {}
+    }
+
+    pub fn send(&self, topic: &str, payload: &str) {
+        self.outgoing.send((topic.to_string(), payload.to_string())).unwrap();
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// SmtpServer
+//
+// Just enough of a plaintext SMTP server: says yes to
+// everything and hands over each message's DATA (headers and
+// body, as sent).
+/////////////////////////////////////////////////////////////
+#[cfg(feature = "email")]
+pub struct SmtpServer {
+    pub port: u16,
+    mail: tokio::sync::mpsc::UnboundedReceiver<String>,
+}
+
+#[cfg(feature = "email")]
+impl SmtpServer {
+    pub async fn start() -> SmtpServer {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind the SMTP port");
+        let port = listener.local_addr().unwrap().port();
+        let (mail_tx, mail) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mail_tx = mail_tx.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_ascii_uppercase();
+                        let reply: &[u8] = if command.starts_with("DATA") {
+                            writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                            let mut data = String::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                data.push_str(&line);
+                                data.push('\n');
+                            }
+                            let _ = mail_tx.send(data);
+                            b"250 queued\r\n"
+                        } else if command.starts_with("QUIT") {
+                            let _ = writer.write_all(b"221 bye\r\n").await;
+                            return;
+                        } else if command.starts_with("EHLO") || command.starts_with("HELO") {
+                            b"250 localhost\r\n"
+                        } else {
+                            b"250 ok\r\n"
+                        };
+                        if writer.write_all(reply).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        SmtpServer { port, mail }
+    }
+
+    // The next message anyone sends
+    pub async fn next_mail(&mut self) -> String {
+        tokio::time::timeout(TIMEOUT, self.mail.recv())
+            .await
+            .unwrap_or_else(|_| panic!("no email within {TIMEOUT:?}"))
+            .expect("the SMTP server stopped")
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// HapController
+//
+// The iPhone's side of HomeKit, enough to pair with the
+// accessory (SRP with the setup code), verify a session and
+// send requests over it.
+/////////////////////////////////////////////////////////////
+#[cfg(feature = "homekit")]
+pub mod hap {
+    use num_bigint::BigUint;
+    use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+    use ring::agreement::{self, EphemeralPrivateKey, X25519};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+    use ring::{digest, hkdf};
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::TIMEOUT;
+
+    // RFC 5054's 3072-bit group
+    const N_HEX: &str = concat!(
+        "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+        "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+        "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+        "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+        "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+        "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+        "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+        "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+        "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+        "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+        "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+        "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+    );
+
+    // A controller's long-term identity
+    pub struct Identity {
+        pub id: String,
+        key_pair: Ed25519KeyPair,
+        // The accessory's, learned while pairing
+        pub accessory_key: Vec<u8>,
+    }
+
+    impl Identity {
+        pub fn new(id: &str) -> Identity {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            Identity {
+                id: id.to_string(),
+                key_pair: Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap(),
+                accessory_key: Vec::new(),
+            }
+        }
+
+        pub fn public_key(&self) -> Vec<u8> {
+            self.key_pair.public_key().as_ref().to_vec()
+        }
+    }
+
+    pub struct Connection {
+        stream: TcpStream,
+        received: Vec<u8>,
+        // (to the accessory, from it, counts)
+        keys: Option<(LessSafeKey, LessSafeKey, u64, u64)>,
+    }
+
+    impl Connection {
+        pub async fn open(port: u16) -> Connection {
+            let stream = TcpStream::connect(("127.0.0.1", port)).await.expect("connect to the accessory");
+            Connection { stream, received: Vec::new(), keys: None }
+        }
+
+        // Pair setup; Err is the accessory's TLV error code
+        pub async fn pair(&mut self, setup_code: &str, identity: &mut Identity) -> Result<(), u8> {
+            let m2 = self.tlv_request("/pair-setup", &[(6, &[1]), (0, &[0])]).await;
+            check(&m2)?;
+            let (n, g) = (BigUint::parse_bytes(N_HEX.as_bytes(), 16).unwrap(), BigUint::from(5u8));
+            let (salt, b_pub) = (get(&m2, 2), BigUint::from_bytes_be(get(&m2, 3)));
+            let a = BigUint::from_bytes_be(&rand::random::<[u8; 32]>());
+            let a_pub = pad(&g.modpow(&a, &n));
+            let k = BigUint::from_bytes_be(&sha512(&[&n.to_bytes_be(), &pad(&g)]));
+            let u = BigUint::from_bytes_be(&sha512(&[&a_pub, &pad(&b_pub)]));
+            let x = BigUint::from_bytes_be(&sha512(&[salt, &sha512(&[b"Pair-Setup:", setup_code.as_bytes()])]));
+            let kv = (k * g.modpow(&x, &n)) % &n;
+            let s = ((&b_pub + &n - kv) % &n).modpow(&(a + u * x), &n);
+            let key = sha512(&[&pad(&s)]);
+            let n_xor_g: Vec<u8> =
+                sha512(&[&n.to_bytes_be()]).iter().zip(sha512(&[&[5]])).map(|(n, g)| n ^ g).collect();
+            let proof = sha512(&[&n_xor_g, &sha512(&[b"Pair-Setup"]), salt, &a_pub, &pad(&b_pub), &key]);
+
+            let m4 = self.tlv_request("/pair-setup", &[(6, &[3]), (3, &a_pub), (4, &proof)]).await;
+            check(&m4)?;
+            assert_eq!(get(&m4, 4), sha512(&[&a_pub, &proof, &key]), "the accessory's SRP proof");
+
+            let session_key = hkdf_sha512(&key, "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+            let controller_x = hkdf_sha512(&key, "Pair-Setup-Controller-Sign-Salt", "Pair-Setup-Controller-Sign-Info");
+            let public_key = identity.public_key();
+            let signature = identity.key_pair.sign(&[&controller_x[..], identity.id.as_bytes(), &public_key].concat());
+            let sub = tlv(&[(1, identity.id.as_bytes()), (3, &public_key), (10, signature.as_ref())]);
+            let sealed = seal(&aead(&session_key), label(*b"PS-Msg05"), &[], &sub);
+            let m6 = self.tlv_request("/pair-setup", &[(6, &[5]), (5, &sealed)]).await;
+            check(&m6)?;
+            let sub = parse_tlv(&open(&aead(&session_key), label(*b"PS-Msg06"), &[], get(&m6, 5)).expect("decrypt M6"));
+            let accessory_x = hkdf_sha512(&key, "Pair-Setup-Accessory-Sign-Salt", "Pair-Setup-Accessory-Sign-Info");
+            let (accessory_id, accessory_key) = (get(&sub, 1), get(&sub, 3));
+            UnparsedPublicKey::new(&ED25519, accessory_key)
+                .verify(&[&accessory_x[..], accessory_id, accessory_key].concat(), get(&sub, 10))
+                .expect("the accessory's signature");
+            identity.accessory_key = accessory_key.to_vec();
+            Ok(())
+        }
+
+        // Pair verify; encrypted from then on
+        pub async fn verify(&mut self, identity: &Identity) -> Result<(), u8> {
+            let rng = SystemRandom::new();
+            let private = EphemeralPrivateKey::generate(&X25519, &rng).unwrap();
+            let public = private.compute_public_key().unwrap().as_ref().to_vec();
+            let m2 = self.tlv_request("/pair-verify", &[(6, &[1]), (3, &public)]).await;
+            check(&m2)?;
+            let accessory_public = get(&m2, 3).to_vec();
+            let shared = agreement::agree_ephemeral(
+                private,
+                &agreement::UnparsedPublicKey::new(&X25519, &accessory_public),
+                |k| k.to_vec(),
+            )
+            .unwrap();
+            let session_key = aead(&hkdf_sha512(&shared, "Pair-Verify-Encrypt-Salt", "Pair-Verify-Encrypt-Info"));
+            let sub = parse_tlv(&open(&session_key, label(*b"PV-Msg02"), &[], get(&m2, 5)).expect("decrypt M2"));
+            UnparsedPublicKey::new(&ED25519, &identity.accessory_key)
+                .verify(&[&accessory_public[..], get(&sub, 1), &public].concat(), get(&sub, 10))
+                .expect("the accessory's signature");
+
+            let signature = identity.key_pair.sign(&[&public[..], identity.id.as_bytes(), &accessory_public].concat());
+            let sub = tlv(&[(1, identity.id.as_bytes()), (10, signature.as_ref())]);
+            let sealed = seal(&session_key, label(*b"PV-Msg03"), &[], &sub);
+            let m4 = self.tlv_request("/pair-verify", &[(6, &[3]), (5, &sealed)]).await;
+            check(&m4)?;
+            self.keys = Some((
+                aead(&hkdf_sha512(&shared, "Control-Salt", "Control-Write-Encryption-Key")),
+                aead(&hkdf_sha512(&shared, "Control-Salt", "Control-Read-Encryption-Key")),
+                0,
+                0,
+            ));
+            Ok(())
+        }
+
+        pub async fn tlv_request(&mut self, path: &str, items: &[(u8, &[u8])]) -> Vec<(u8, Vec<u8>)> {
+            let (status, body) = self.request("POST", path, "application/pairing+tlv8", &tlv(items)).await;
+            assert_eq!(status, 200, "POST {path}");
+            parse_tlv(&body)
+        }
+
+        pub async fn json_request(&mut self, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+            let body = body.map(|b| b.to_string()).unwrap_or_default();
+            let (status, body) = self.request(method, path, "application/hap+json", body.as_bytes()).await;
+            (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+        }
+
+        pub async fn request(&mut self, method: &str, path: &str, content_type: &str, body: &[u8]) -> (u16, Vec<u8>) {
+            let head = format!(
+                "{method} {path} HTTP/1.1\r\nHost: accessory\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            let request = [head.as_bytes(), body].concat();
+            let bytes = match &mut self.keys {
+                Some((write, _, count, _)) => {
+                    let mut out = Vec::new();
+                    for chunk in request.chunks(1024) {
+                        let len = (chunk.len() as u16).to_le_bytes();
+                        out.extend_from_slice(&len);
+                        out.extend(seal(write, counter(*count), &len, chunk));
+                        *count += 1;
+                    }
+                    out
+                }
+                None => request,
+            };
+            self.stream.write_all(&bytes).await.unwrap();
+            let (first_line, body) = self.next_message().await;
+            assert!(first_line.starts_with("HTTP/1.1 "), "expected a response, got {first_line}");
+            (first_line[9..12].parse().unwrap(), body)
+        }
+
+        // The next EVENT/1.0 notification's body
+        pub async fn next_event(&mut self) -> Value {
+            let (first_line, body) = self.next_message().await;
+            assert!(first_line.starts_with("EVENT/1.0 200"), "expected an event, got {first_line}");
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        async fn next_message(&mut self) -> (String, Vec<u8>) {
+            loop {
+                if let Some(message) = self.take_message() {
+                    return message;
+                }
+                let mut chunk = [0u8; 4096];
+                let n = tokio::time::timeout(TIMEOUT, self.stream.read(&mut chunk))
+                    .await
+                    .expect("the accessory answers")
+                    .unwrap();
+                assert!(n > 0, "the accessory closed the connection");
+                self.received.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        // A whole response from what's been received, decrypted
+        fn take_message(&mut self) -> Option<(String, Vec<u8>)> {
+            let plain = match &mut self.keys {
+                Some((_, read, _, count)) => {
+                    let mut plain = Vec::new();
+                    let mut offset = 0;
+                    let mut frames = 0;
+                    while self.received.len() >= offset + 2 {
+                        let len = u16::from_le_bytes([self.received[offset], self.received[offset + 1]]) as usize;
+                        let end = offset + 2 + len + 16;
+                        if self.received.len() < end {
+                            break;
+                        }
+                        let aad = &self.received[offset..offset + 2];
+                        plain.extend(open(read, counter(*count + frames), aad, &self.received[offset + 2..end]).expect("decrypt a frame"));
+                        frames += 1;
+                        offset = end;
+                    }
+                    let (head_len, content_length) = message_len(&plain)?;
+                    if plain.len() < head_len + content_length {
+                        return None;
+                    }
+                    assert_eq!(plain.len(), head_len + content_length, "one message at a time");
+                    *count += frames;
+                    self.received.drain(..offset);
+                    plain
+                }
+                None => {
+                    let (head_len, content_length) = message_len(&self.received)?;
+                    if self.received.len() < head_len + content_length {
+                        return None;
+                    }
+                    self.received.drain(..head_len + content_length).collect()
+                }
+            };
+            let (head_len, _) = message_len(&plain)?;
+            let first_line = String::from_utf8_lossy(&plain[..head_len]).lines().next().unwrap_or_default().to_string();
+            Some((first_line, plain[head_len..].to_vec()))
+        }
+    }
+
+    fn message_len(bytes: &[u8]) -> Option<(usize, usize)> {
+        let head_len = bytes.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+        let head = String::from_utf8_lossy(&bytes[..head_len]).to_ascii_lowercase();
+        let content_length = head
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .map_or(0, |v| v.trim().parse().unwrap());
+        Some((head_len, content_length))
+    }
+
+    fn check(items: &[(u8, Vec<u8>)]) -> Result<(), u8> {
+        match items.iter().find(|(t, _)| *t == 7) {
+            Some((_, error)) => Err(error[0]),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(items: &[(u8, Vec<u8>)], kind: u8) -> &[u8] {
+        items.iter().find(|(t, _)| *t == kind).map(|(_, v)| v.as_slice()).unwrap_or_else(|| panic!("no TLV {kind}"))
+    }
+
+    pub fn tlv(items: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (kind, value) in items {
+            if value.is_empty() {
+                out.extend([*kind, 0]);
+            }
+            for chunk in value.chunks(255) {
+                out.extend([*kind, chunk.len() as u8]);
+                out.extend_from_slice(chunk);
+            }
+        }
+        out
+    }
+
+    pub fn parse_tlv(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut items: Vec<(u8, Vec<u8>)> = Vec::new();
+        let mut fragment = false;
+        while let [kind, len, rest @ ..] = bytes {
+            let value = &rest[..*len as usize];
+            match items.last_mut() {
+                Some((last, joined)) if fragment && last == kind => joined.extend_from_slice(value),
+                _ => items.push((*kind, value.to_vec())),
+            }
+            fragment = *len == 255;
+            bytes = &rest[*len as usize..];
+        }
+        items
+    }
+
+    fn pad(n: &BigUint) -> Vec<u8> {
+        let bytes = n.to_bytes_be();
+        [vec![0; 384 - bytes.len()], bytes].concat()
+    }
+
+    fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+        let mut context = digest::Context::new(&digest::SHA512);
+        parts.iter().for_each(|p| context.update(p));
+        context.finish().as_ref().to_vec()
+    }
+
+    fn hkdf_sha512(secret: &[u8], salt: &str, info: &str) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        hkdf::Salt::new(hkdf::HKDF_SHA512, salt.as_bytes())
+            .extract(secret)
+            .expand(&[info.as_bytes()], hkdf::HKDF_SHA256)
+            .unwrap()
+            .fill(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn aead(key: &[u8; 32]) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).unwrap())
+    }
+
+    fn label(label: [u8; 8]) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&label);
+        nonce
+    }
+
+    fn counter(count: u64) -> [u8; 12] {
+        label(count.to_le_bytes())
+    }
+
+    fn seal(key: &LessSafeKey, nonce: [u8; 12], aad: &[u8], plain: &[u8]) -> Vec<u8> {
+        let mut sealed = plain.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut sealed).unwrap();
+        sealed
+    }
+
+    fn open(key: &LessSafeKey, nonce: [u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let mut buf = sealed.to_vec();
+        key.open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut buf).ok().map(|p| p.to_vec())
+    }
+}
@@ -0,0 +1,2920 @@
+/////////////////////////////////////////////////////////////
+// tests/pipeline.rs
+//
+// End to end through the HTTP API: fixture audio in, mocked
+// Whisper/GPT out, checked via the responses, /status, the
+// conversation log and the live log stream.
+/////////////////////////////////////////////////////////////
+
+mod common;
+
+use common::{chat, completion, free_port, mock_openai, transcript, whisper, SseStream, TestServer};
+use serde_json::Value;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn record_once_transcribes_replies_and_logs() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("is the oven still on")).expect(1).mount(&openai).await;
+    // GPT must be sent what Whisper heard
+    chat()
+        .and(body_string_contains("is the oven still on"))
+        .respond_with(completion("Someone is asking about the oven."))
+        .expect(1)
+        .mount(&openai)
+        .await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 200);
+    let chunk: Value = resp.json().await.unwrap();
+    assert_eq!(chunk["transcript"], "is the oven still on");
+    assert_eq!(chunk["gpt_response"], "Someone is asking about the oven.");
+
+    let records = server.log_records().await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["source"], "Microphone");
+    assert_eq!(records[0]["text"], "is the oven still on");
+    assert_eq!(records[1]["source"], "OPENAI RESPONSE");
+    assert_eq!(records[0]["chunk_id"], records[1]["chunk_id"]);
+
+    let latest = server.get_json("/transcript").await;
+    assert_eq!(latest["transcript"], "is the oven still on");
+}
+
+#[tokio::test]
+async fn recording_loop_runs_until_stopped() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    assert_eq!(server.post("/start_recording").await.status(), 409);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(2) })
+        .await;
+
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["recording"] == false })
+        .await;
+
+    // Every record belongs to the one session
+    let records = server.log_records().await;
+    assert!(records.len() >= 4);
+    let session = records[0]["session_id"].as_str().expect("session_id");
+    assert!(records.iter().all(|r| r["session_id"] == session));
+    let status = server.get_json("/status").await;
+    assert!(status["last_error"].is_null());
+    let pipeline = &status["sources"][0]["pipeline"];
+    for stage in ["capture", "screen", "transcribe", "respond", "persist"] {
+        assert!(pipeline[stage]["processed"].as_u64() >= Some(2), "{stage}: {pipeline}");
+        assert_eq!(pipeline[stage]["failed"], 0);
+    }
+}
+
+#[tokio::test]
+async fn the_mic_keeps_recording_while_whisper_is_busy() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(transcript("hello").set_delay(std::time::Duration::from_millis(500)))
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("A greeting.")).mount(&openai).await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async {
+            server.get_json("/status").await["sources"][0]["pipeline"]["transcribe"]["processed"].as_u64() >= Some(2)
+        })
+        .await;
+    let pipeline = server.get_json("/status").await["sources"][0]["pipeline"].clone();
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    // Chunks were recorded and screened while earlier ones were at Whisper
+    let transcribed = pipeline["transcribe"]["processed"].as_u64().unwrap();
+    assert!(pipeline["capture"]["processed"].as_u64().unwrap() >= transcribed + 2, "{pipeline}");
+    assert!(pipeline["screen"]["processed"].as_u64().unwrap() > transcribed, "{pipeline}");
+    assert!(pipeline["capture_gap"]["count"].as_u64() >= Some(1), "{pipeline}");
+}
+
+#[tokio::test]
+async fn audio_is_streamed_to_whisper_and_resent_if_that_fails() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("OPENAI_STREAM_AUDIO", "true")]).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 200);
+    let chunk: Value = resp.json().await.unwrap();
+    assert_eq!(chunk["transcript"], "hello");
+    // Sent as it was recorded: chunked, with no length up front
+    let uploads = openai.received_requests().await.unwrap();
+    let uploads: Vec<_> = uploads.iter().filter(|r| r.url.path() == "/v1/audio/transcriptions").collect();
+    assert_eq!(uploads.len(), 1);
+    assert!(!uploads[0].headers.contains_key("content-length"), "{:?}", uploads[0].headers);
+
+    // The streamed upload fails, so the chunk is sent again whole
+    let flaky = MockServer::start().await;
+    whisper().respond_with(ResponseTemplate::new(500)).up_to_n_times(1).mount(&flaky).await;
+    whisper().respond_with(transcript("hello again")).mount(&flaky).await;
+    chat().respond_with(completion("Another greeting.")).mount(&flaky).await;
+    let server = TestServer::start_with_env(&flaky.uri(), &[("OPENAI_STREAM_AUDIO", "true")]).await;
+
+    let chunk: Value = server.post("/record_once").await.json().await.unwrap();
+    assert_eq!(chunk["transcript"], "hello again");
+    let uploads = flaky.received_requests().await.unwrap();
+    let uploads: Vec<_> = uploads.iter().filter(|r| r.url.path() == "/v1/audio/transcriptions").collect();
+    assert_eq!(uploads.len(), 2);
+    assert!(uploads[1].headers.contains_key("content-length"));
+}
+
+#[tokio::test]
+async fn short_chunks_go_to_whisper_together() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    // The fixture is 0.1s of silence, so ten fit in a batch
+    let server = TestServer::start_with_env(&openai.uri(), &[("AUDIO_BATCH_MAX_SECS", "1")]).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(2) })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let pipeline = server.get_json("/status").await["sources"][0]["pipeline"].clone();
+    assert!(pipeline["batched"].as_u64() >= Some(20), "{pipeline}");
+    let uploads = openai.received_requests().await.unwrap();
+    let upload = uploads.iter().find(|r| r.url.path() == "/v1/audio/transcriptions").expect("a Whisper request");
+    let fixture = std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/silence.wav")).unwrap();
+    assert!(upload.body.len() > fixture.len() * 9, "{} bytes", upload.body.len());
+    // One entry per batch, under its first chunk's id
+    let records = server.log_records().await;
+    assert!(records.len() >= 4);
+    let transcribed = pipeline["transcribe"]["processed"].as_u64().unwrap();
+    assert!(pipeline["capture"]["processed"].as_u64().unwrap() > transcribed * 2, "{pipeline}");
+}
+
+#[tokio::test]
+async fn chunks_end_at_pauses_in_speech() {
+    // 1s of quiet, 2.5s of "speech" and 1s of quiet, played over and over
+    let fixtures = tempfile::TempDir::new().unwrap();
+    let mut data: Vec<u8> = vec![0; 32_000];
+    for i in 0..40_000 {
+        let level: i16 = if i % 40 < 20 { 8_000 } else { -8_000 };
+        data.extend(level.to_le_bytes());
+    }
+    data.extend([0; 32_000]);
+    let mut wav = b"RIFF".to_vec();
+    wav.extend((36 + data.len() as u32).to_le_bytes());
+    wav.extend(b"WAVEfmt \x10\0\0\0\x01\0\x01\0\x80\x3e\0\0\0\x7d\0\0\x02\0\x10\0data");
+    wav.extend((data.len() as u32).to_le_bytes());
+    wav.extend(data);
+    std::fs::write(fixtures.path().join("talking.wav"), wav).unwrap();
+
+    let openai = mock_openai("hello", "A greeting.").await;
+    let config = format!("[[audio.sources]]\nname = \"talk\"\nmic_backend = \"file\"\ndevice = {:?}\n", fixtures.path().display().to_string());
+    let env = [("AUDIO_CHUNKING", "speech"), ("AUDIO_MIN_CHUNK_SECS", "1"), ("AUDIO_PAUSE_MS", "600")];
+    let server = TestServer::start_with_config(&openai.uri(), &config, &env).await;
+
+    assert_eq!(server.post("/sources/talk/start").await.status(), 200);
+    server
+        .wait_until(|| async { server.log_records().await.iter().filter(|r| r["audio_source"] == "talk").count() >= 6 })
+        .await;
+    assert_eq!(server.post("/sources/talk/stop").await.status(), 200);
+
+    // Each chunk is one utterance: at most 0.6s before it, and half the pause after
+    let status = server.get_json("/status").await;
+    let talk = status["sources"].as_array().unwrap().iter().find(|s| s["name"] == "talk").unwrap().clone();
+    let chunks = talk["pipeline"]["capture"]["processed"].as_u64().unwrap();
+    let secs = talk["pipeline"]["bytes_captured"].as_u64().unwrap() as f64 / chunks as f64 / 32_000.0;
+    assert!((2.5..3.8).contains(&secs), "{secs}s per chunk: {talk}");
+}
+
+#[tokio::test]
+async fn a_script_plays_its_lines_in_real_time() {
+    let script = tempfile::TempDir::new().unwrap();
+    let path = script.path().join("living-room.txt");
+    std::fs::write(&path, "# reported by a user\n\nturn the lights off\nis anyone there\n").unwrap();
+    let openai = MockServer::start().await;
+    chat().respond_with(completion("Okay.")).mount(&openai).await;
+    let config = format!("[[audio.sources]]\nname = \"room\"\ndevice = {:?}\n", path.display().to_string());
+    let server = TestServer::start_with_config(&openai.uri(), &config, &[("AUDIO_REALTIME", "true")]).await;
+
+    let started = std::time::Instant::now();
+    assert_eq!(server.post("/sources/room/start").await.status(), 200);
+    let heard = || async {
+        let records = server.log_records().await;
+        records.into_iter().filter(|r| r["source"] == "Microphone").map(|r| r["text"].clone()).collect::<Vec<_>>()
+    };
+    server.wait_until(|| async { heard().await.len() >= 2 }).await;
+    assert_eq!(server.post("/sources/room/stop").await.status(), 200);
+
+    // The lines, in order, as long as saying them takes (0.4s a word)
+    assert_eq!(heard().await[..2], [serde_json::json!("turn the lights off"), serde_json::json!("is anyone there")]);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(2_800), "{:?}", started.elapsed());
+    // Whisper isn't asked; GPT is
+    let requests = openai.received_requests().await.unwrap();
+    assert!(requests.iter().all(|r| r.url.path() != "/v1/audio/transcriptions"));
+    assert!(requests.iter().any(|r| r.url.path() == "/v1/chat/completions"));
+}
+
+#[tokio::test]
+async fn repeated_transcripts_are_dropped() {
+    // A looping Whisper: the same sentence for every chunk
+    let openai = mock_openai("Thank you for watching.", "You're welcome.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("DEDUP_ENABLED", "true")]).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    let duplicates = || async { server.get_json("/status").await["sources"][0]["pipeline"]["duplicates"].as_u64().unwrap() };
+    server.wait_until(|| async { duplicates().await >= 2 }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    // Logged and answered once
+    let records = server.log_records().await;
+    assert_eq!(records.iter().filter(|r| r["source"] == "Microphone").count(), 1, "{records:?}");
+    let requests = openai.received_requests().await.unwrap();
+    assert_eq!(requests.iter().filter(|r| r.url.path() == "/v1/chat/completions").count(), 1);
+}
+
+#[tokio::test]
+async fn a_session_keeps_its_own_settings() {
+    let openai = mock_openai("hallo zusammen", "Hallo!").await;
+    let personas = "[openai.personas]\nkids = \"You talk to children.\"\n";
+    let server = TestServer::start_with_config(&openai.uri(), personas, &[]).await;
+
+    // Overrides the config can't take
+    let bad = server.http.post(server.url("/start_recording")).json(&serde_json::json!({"persona": "pirate"})).send().await.unwrap();
+    assert_eq!(bad.status(), 400);
+
+    let overrides = serde_json::json!({"persona": "kids", "chunk_secs": 2, "stt_model": "whisper-large", "language": "de"});
+    let started = server.http.post(server.url("/start_recording")).json(&overrides).send().await.unwrap();
+    assert_eq!(started.status(), 200);
+    assert_eq!(started.text().await.unwrap(), "Recording started in memory for 2s blocks...");
+    server.wait_until(|| async { server.log_records().await.len() >= 2 }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    // Whisper and GPT were asked as the session said
+    let requests = openai.received_requests().await.unwrap();
+    let whisper = requests.iter().find(|r| r.url.path() == "/v1/audio/transcriptions").unwrap();
+    let form = String::from_utf8_lossy(&whisper.body);
+    assert!(form.contains("whisper-large") && form.contains("name=\"language\"\r\n\r\nde"), "{form}");
+    let chat = requests.iter().find(|r| r.url.path() == "/v1/chat/completions").unwrap();
+    assert!(String::from_utf8_lossy(&chat.body).contains("You talk to children."));
+
+    // And what it ran with is kept under its ID
+    let session_id = server.log_records().await[0]["session_id"].as_str().unwrap().to_string();
+    let kept = server.get_json(&format!("/sessions/{session_id}/settings")).await;
+    assert_eq!(kept["overrides"], overrides);
+    let settings = &kept["snapshots"][0]["settings"];
+    assert_eq!((settings["chunk_secs"].clone(), settings["persona"].clone()), (serde_json::json!(2), serde_json::json!("kids")));
+    assert_eq!(settings["system_prompt"], "You talk to children.");
+    assert_eq!(kept["snapshots"].as_array().unwrap().len(), 1);
+    let unknown = server.http.get(server.url("/sessions/nope/settings")).send().await.unwrap();
+    assert_eq!(unknown.status(), 404);
+}
+
+#[tokio::test]
+async fn rated_responses_are_kept_and_shown_to_gpt() {
+    let openai = mock_openai("what's the weather like", "Lovely weather for ducks!").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("FEEDBACK_PROMPT_ITEMS", "5")]).await;
+
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let records = server.log_records().await;
+    let entry_id = |source: &str| records.iter().find(|r| r["source"] == source).unwrap()["entry_id"].as_str().unwrap().to_string();
+    let (transcript, response) = (entry_id("Microphone"), entry_id("OPENAI RESPONSE"));
+    let rate = |id: String, body: serde_json::Value| {
+        let request = server.http.post(server.url(&format!("/entries/{id}/feedback"))).json(&body);
+        async move { request.send().await.unwrap().status() }
+    };
+
+    // Only responses, and only up or down
+    assert_eq!(rate(transcript, serde_json::json!({"rating": "up"})).await, 400);
+    assert_eq!(rate(response.clone(), serde_json::json!({"rating": "meh"})).await, 400);
+    assert_eq!(rate("nope".to_string(), serde_json::json!({"rating": "up"})).await, 404);
+    // A second rating replaces the first
+    assert_eq!(rate(response.clone(), serde_json::json!({"rating": "up"})).await, 200);
+    assert_eq!(rate(response.clone(), serde_json::json!({"rating": "down", "comment": "No puns"})).await, 200);
+
+    let entry = server.get_json(&format!("/entries/{response}")).await;
+    assert_eq!((entry["feedback"]["rating"].clone(), entry["feedback"]["comment"].clone()), (serde_json::json!("down"), serde_json::json!("No puns")));
+    let stats = server.get_json("/feedback/stats").await;
+    assert_eq!(stats, serde_json::json!([{"persona": "", "up": 0, "down": 1, "usefulness": 0.0, "comments": 1}]));
+
+    // The next chunk's prompt has it
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let requests = openai.received_requests().await.unwrap();
+    let chat = requests.iter().rfind(|r| r.url.path() == "/v1/chat/completions").unwrap();
+    let body = String::from_utf8_lossy(&chat.body);
+    assert!(body.contains("Disliked: \\\"Lovely weather for ducks!\\\" (No puns)"), "{body}");
+}
+
+#[tokio::test]
+async fn old_turns_are_folded_into_a_summary() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("we should repaint the hallway before the guests arrive")).mount(&openai).await;
+    chat()
+        .and(body_string_contains("You keep the memory"))
+        .respond_with(completion("They plan to repaint the hallway."))
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("Noted.")).mount(&openai).await;
+    // Room for about one exchange
+    let server = TestServer::start_with_env(&openai.uri(), &[("OPENAI_HISTORY_TOKENS", "30")]).await;
+
+    for _ in 0..3 {
+        assert_eq!(server.post("/record_once").await.status(), 200);
+    }
+
+    let requests = openai.received_requests().await.unwrap();
+    let chats: Vec<String> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/v1/chat/completions")
+        .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+        .collect();
+    assert!(chats.iter().any(|body| body.contains("You keep the memory")), "{chats:?}");
+    // The last reply was asked for with the summary, not the oldest turns
+    let last = chats.iter().rev().find(|body| !body.contains("You keep the memory")).unwrap();
+    assert!(last.contains("Earlier in this conversation: They plan to repaint the hallway."), "{last}");
+    assert_eq!(last.matches("repaint the hallway before").count(), 1, "{last}");
+    let saved: Value =
+        serde_json::from_str(&std::fs::read_to_string(server.dir.path().join("history_summaries.json")).unwrap()).unwrap();
+    assert_eq!(saved["default"], "They plan to repaint the hallway.");
+}
+
+#[tokio::test]
+async fn live_log_streams_new_records() {
+    let openai = mock_openai("turn the lights off", "Lights request.").await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    let mut stream = SseStream::open(&server, "/live_log").await;
+    assert_eq!(server.post("/record_once").await.status(), 200);
+
+    let events = [stream.next_record().await, stream.next_record().await];
+    assert_eq!(events[0]["source"], "Microphone");
+    assert_eq!(events[0]["text"], "turn the lights off");
+    assert_eq!(events[1]["source"], "OPENAI RESPONSE");
+    assert_eq!(events[1]["text"], "Lights request.");
+}
+
+#[tokio::test]
+async fn a_subscriber_that_falls_behind_is_told_what_it_missed() {
+    // Big enough that a few fill the connection while nobody reads it
+    let reply = "Noted. ".repeat(40_000);
+    let openai = mock_openai("turn the lights off", &reply).await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("SSE_CAPACITY", "1")]).await;
+
+    let mut stream = SseStream::open(&server, "/live_log").await;
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(40) })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let missed = loop {
+        let event = stream.next().await;
+        if event.event.as_deref() == Some("lagged") {
+            break event.data["missed"].as_u64().unwrap();
+        }
+    };
+    assert!(missed >= 1);
+    assert!(server.get_json("/status").await["queues"]["sse_missed"].as_u64().unwrap() >= missed);
+}
+
+#[tokio::test]
+async fn a_late_response_follows_its_transcript() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("is the oven still on")).mount(&openai).await;
+    chat().respond_with(completion("It's off.").set_delay(std::time::Duration::from_secs(3))).mount(&openai).await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("AUDIO_DEADLINE_SECS", "1")]).await;
+
+    let mut stream = SseStream::open(&server, "/live_log").await;
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    // Out before GPT answers
+    let ahead = stream.next_record().await;
+    assert_eq!((ahead["source"].clone(), ahead["response_pending"].clone()), (serde_json::json!("Microphone"), serde_json::json!(true)));
+    let (mut response, mut update) = (None, None);
+    while response.is_none() || update.is_none() {
+        let event = stream.next().await;
+        match event.event.as_deref() {
+            None if event.data["source"] == "OPENAI RESPONSE" => response = response.or(Some(event.data)),
+            Some("entry_updated") => update = update.or(Some(event.data)),
+            _ => {}
+        }
+    }
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let (response, update) = (response.unwrap(), update.unwrap());
+    assert_eq!(response["chunk_id"], ahead["chunk_id"]);
+    assert_eq!(update["entry_id"], ahead["entry_id"]);
+    assert_eq!(update["response"]["entry_id"], response["entry_id"]);
+    assert_eq!(update["response"]["text"], "It's off.");
+    // Logged once
+    let records = server.log_records().await;
+    assert_eq!(records.iter().filter(|r| r["entry_id"] == ahead["entry_id"]).count(), 1);
+    assert!(server.get_json("/status").await["sources"][0]["pipeline"]["late_responses"].as_u64().unwrap() >= 1);
+}
+
+#[tokio::test]
+async fn live_log_schema_2_sends_everything_in_one_shape() {
+    let openai = mock_openai("turn the lights off", "Lights request.").await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    let mut stream = SseStream::open(&server, "/live_log?schema=2").await;
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    let mut kinds = std::collections::HashMap::new();
+    while !(kinds.contains_key("state") && kinds.contains_key("transcript") && kinds.contains_key("response")) {
+        let event = stream.next().await;
+        // Unnamed: one handler takes them all
+        assert_eq!(event.event, None, "{}", event.data);
+        assert_eq!(event.data["schema"], 2);
+        kinds.entry(event.data["kind"].as_str().unwrap().to_string()).or_insert(event.data);
+    }
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let state = &kinds["state"];
+    assert_eq!(state["audio_source"], "default");
+    assert_eq!(state["data"]["state"]["name"], "starting");
+    assert!(state["entry_id"].is_null() && state["text"].is_null());
+    let transcript = &kinds["transcript"];
+    assert_eq!(transcript["text"], "turn the lights off");
+    assert_eq!(transcript["audio_source"], "default");
+    assert!(transcript["session_id"].is_string() && transcript["chunk_id"].is_string());
+    assert!(transcript["speaker"].is_null() && transcript["speakers"] == serde_json::json!([]));
+    assert!(transcript.as_object().unwrap().contains_key("confidence"));
+    assert!(transcript["data"].is_null());
+    assert_eq!(kinds["response"]["text"], "Lights request.");
+    // The same entry as in the log
+    let records = server.log_records().await;
+    let logged = records.iter().find(|r| r["entry_id"] == transcript["entry_id"]).expect("logged entry");
+    assert_eq!(logged["chunk_id"], transcript["chunk_id"]);
+
+    let polled = server.get_json("/poll_log?since=0&schema=2").await;
+    assert_eq!(polled["records"][0]["record"]["kind"], "transcript");
+    let polled = server.get_json("/poll_log?since=0").await;
+    assert_eq!(polled["records"][0]["record"]["source"], "Microphone");
+}
+
+#[tokio::test]
+async fn times_are_sent_in_the_display_time_zone_too() {
+    let openai = mock_openai("turn the lights off", "Lights request.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("UI_TIMEZONE", "Asia/Tokyo")]).await;
+
+    let mut stream = SseStream::open(&server, "/live_log?schema=2").await;
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let transcript = loop {
+        let event = stream.next().await;
+        if event.data["kind"] == "transcript" {
+            break event.data;
+        }
+    };
+    let utc = chrono::DateTime::parse_from_rfc3339(transcript["timestamp"].as_str().unwrap()).unwrap();
+    let local = chrono::DateTime::parse_from_rfc3339(transcript["local_time"].as_str().unwrap()).unwrap();
+    assert_eq!(utc, local);
+    assert_eq!(local.offset().local_minus_utc(), 9 * 3600);
+
+    let polled = server.get_json("/poll_log?since=0").await;
+    let record = &polled["records"][0]["record"];
+    assert!(record["local_time"].as_str().unwrap().ends_with("+09:00"));
+    // The log itself keeps UTC only
+    let records = server.log_records().await;
+    assert!(records.iter().all(|r| r.get("local_time").is_none()));
+}
+
+#[tokio::test]
+async fn whisper_segments_are_kept_with_the_transcript() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(|request: &wiremock::Request| {
+            let verbose = request.body.windows(b"verbose_json".len()).any(|w| w == b"verbose_json");
+            assert!(verbose, "Whisper wasn't asked for segments");
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "text": "the roof leaks",
+                "language": "english",
+                "segments": [
+                    { "id": 0, "seek": 0, "start": 0.0, "end": 0.9, "text": " the roof leaks", "tokens": [1, 2],
+                      "temperature": 0.2, "avg_logprob": -0.1, "compression_ratio": 1.1, "no_speech_prob": 0.01 },
+                ],
+            }))
+        })
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("Call a roofer.")).mount(&openai).await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let records = server.log_records().await;
+    let transcript = records.iter().find(|r| r["source"] == "Microphone").expect("transcript");
+    let entry = server.get_json(&format!("/entries/{}", transcript["entry_id"].as_str().unwrap())).await;
+    assert_eq!(entry["text"], "the roof leaks");
+    assert_eq!(entry["language"], "english");
+    assert!((entry["confidence"].as_f64().unwrap() - (-0.1f64).exp()).abs() < 1e-9);
+    let segment = &entry["segments"][0];
+    assert_eq!(segment["end"], 0.9);
+    assert_eq!(segment["temperature"], 0.2);
+    assert_eq!(segment["no_speech_prob"], 0.01);
+    assert!(segment.get("tokens").is_none());
+    // Only transcripts have them
+    let response = records.iter().find(|r| r["source"] == "OPENAI RESPONSE").expect("response");
+    assert!(response.get("segments").is_none());
+
+    let missing = server.http.get(server.url("/entries/nope")).send().await.unwrap();
+    assert_eq!(missing.status(), 404);
+    assert_eq!(missing.json::<Value>().await.unwrap()["code"], "unknown_entry");
+}
+
+#[tokio::test]
+async fn outputs_send_each_kind_only_to_its_sinks() {
+    let openai = mock_openai("turn the lights off", "Lights request.").await;
+    let env = [("OUTPUTS_TRANSCRIPT", "log"), ("OUTPUTS_RESPONSE", "sse,eink"), ("OUTPUTS_EINK_COMMAND", "tee eink.txt")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    let mut stream = SseStream::open(&server, "/live_log").await;
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    // The transcript isn't streamed, only the response
+    let streamed = loop {
+        let event = stream.next().await;
+        if event.data["source"].is_string() {
+            break event.data;
+        }
+    };
+    assert_eq!(streamed["source"], "OPENAI RESPONSE");
+    assert_eq!(streamed["text"], "Lights request.");
+    // And only the transcript is logged
+    let records = server.log_records().await;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["source"], "Microphone");
+    // The e-ink command gets the response's text
+    let eink = server.dir.path().join("eink.txt");
+    server
+        .wait_until(|| async { std::fs::read_to_string(&eink).is_ok_and(|text| text == "Lights request.") })
+        .await;
+}
+
+#[tokio::test]
+async fn save_dir_keeps_each_chunk_on_disk() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("SAVE_AUDIO_DIR", "recordings")]).await;
+
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let chunk_id = server.log_records().await[0]["chunk_id"].as_str().expect("chunk_id").to_string();
+    let saved = server.dir.path().join(format!("recordings/default/once-{chunk_id}.wav"));
+    let fixture = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/silence.wav");
+    assert_eq!(std::fs::read(&saved).expect("saved chunk"), std::fs::read(fixture).unwrap());
+}
+
+#[tokio::test]
+async fn a_full_disk_pauses_archiving_but_not_transcripts() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    // No disk has a hundred terabytes free
+    let env = [("SAVE_AUDIO_DIR", "recordings"), ("DISK_LOW_MB", "100000000"), ("DISK_CRITICAL_MB", "100000000")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    server.wait_until(|| async { server.get_json("/status").await["disk"]["archiving_paused"] == true }).await;
+
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    assert_eq!(server.log_records().await.len(), 2);
+    assert!(!server.dir.path().join("recordings/default").exists());
+
+    let disk = &server.get_json("/status").await["disk"];
+    assert_eq!(disk["archive_skipped"], 1);
+    let volumes = disk["volumes"].as_array().unwrap();
+    assert_eq!(volumes.iter().map(|v| v["volume"].as_str().unwrap()).collect::<Vec<_>>(), ["data", "audio"]);
+    assert!(volumes.iter().all(|v| v["level"] == "critical" && v["free_bytes"].as_u64().unwrap() > 0));
+    let metrics = server.http.get(server.url("/metrics")).send().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("silentnight_audio_archiving_paused 1"), "{metrics}");
+    assert!(metrics.contains("silentnight_disk_free_bytes{volume=\"audio\",path=\"recordings\"}"), "{metrics}");
+}
+
+#[tokio::test]
+async fn stop_cancels_the_recording_task() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    let status = server.get_json("/status").await;
+    assert_eq!(status["tasks"]["recording"], 1);
+
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["tasks"]["recording"].is_null() })
+        .await;
+    assert_eq!(server.get_json("/status").await["state"]["name"], "idle");
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+}
+
+#[tokio::test]
+async fn state_changes_are_streamed() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start(&openai.uri()).await;
+    let mut stream = SseStream::open(&server, "/live_log").await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    assert_eq!(stream.next_state().await, "starting");
+    assert_eq!(stream.next_state().await, "recording");
+    assert_eq!(server.get_json("/status").await["state"]["name"], "recording");
+
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    assert_eq!(stream.next_state().await, "stopping");
+    assert_eq!(stream.next_state().await, "processing");
+    assert_eq!(stream.next_state().await, "idle");
+
+    // A one-off chunk goes through processing too
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    assert_eq!(stream.next_state().await, "processing");
+    assert_eq!(stream.next_state().await, "idle");
+}
+
+#[tokio::test]
+async fn rejected_api_key_is_a_bad_gateway_and_stops_the_loop() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+        .mount(&openai)
+        .await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 502);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "openai_error");
+
+    // Not worth retrying, so the loop gives up on the first chunk
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["recording"] == false })
+        .await;
+    let status = server.get_json("/status").await;
+    assert!(status["last_error"].as_str().unwrap().contains("401"));
+    // Crashed, not just stopped
+    assert_eq!(status["state"]["name"], "error");
+    assert!(status["state"]["reason"].as_str().unwrap().contains("401"));
+    assert_eq!(status["chunks_processed"], 0);
+    assert!(server.log_records().await.is_empty());
+}
+
+#[tokio::test]
+async fn fatal_failure_is_reported_without_a_restart() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+        .mount(&openai)
+        .await;
+    let server = TestServer::start(&openai.uri()).await;
+    let mut stream = SseStream::open(&server, "/live_log").await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    let failed = loop {
+        let ev = stream.next().await;
+        if ev.event.as_deref() == Some("task_failed") {
+            break ev.data;
+        }
+    };
+    assert_eq!(failed["task"], "recording");
+    assert_eq!(failed["audio_source"], "default");
+    assert!(failed["error"].as_str().unwrap().contains("401"));
+    assert!(failed["restart_in_ms"].is_null());
+    assert_eq!(stream.next_state().await, "error");
+    assert_eq!(server.get_json("/status").await["task_restarts"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn transient_openai_failure_is_retried() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&openai)
+        .await;
+    whisper().respond_with(transcript("still here")).mount(&openai).await;
+    chat().respond_with(completion("Still here.")).mount(&openai).await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    let status = server.get_json("/status").await;
+    assert_eq!(status["recording"], true);
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+}
+
+#[tokio::test]
+async fn openai_unreachable_maps_to_bad_gateway() {
+    // Nothing listens there
+    let server = TestServer::start(&format!("http://127.0.0.1:{}", free_port())).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 502);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "openai_unreachable");
+}
+
+#[tokio::test]
+async fn slow_openai_times_out() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(transcript("too late").set_delay(std::time::Duration::from_secs(5)))
+        .mount(&openai)
+        .await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("OPENAI_TIMEOUT_SECS", "1")]).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 502);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "openai_timeout");
+    // Slow isn't offline: nothing goes to the backlog
+    assert_eq!(server.get_json("/status").await["backlog"]["queued"], 0);
+}
+
+#[tokio::test]
+async fn unreachable_openai_queues_chunks_for_the_backlog() {
+    let port = free_port();
+    let env = [("BACKLOG_AUTO_DRAIN", "false")];
+    let server = TestServer::start_with_env(&format!("http://127.0.0.1:{port}"), &env).await;
+
+    // Recording carries on without Whisper and the chunks wait on disk
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["backlog"]["queued"].as_u64() >= Some(1) })
+        .await;
+    assert_eq!(server.get_json("/status").await["recording"], true);
+    let session = server.get_json("/status").await["session_id"].clone();
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["state"]["name"] == "idle" })
+        .await;
+    assert!(server.log_records().await.is_empty());
+
+    // OpenAI comes back where the server expects it
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port)).expect("bind the OpenAI port");
+    let openai = MockServer::builder().listener(listener).start().await;
+    whisper().respond_with(transcript("while you were away")).mount(&openai).await;
+    chat().respond_with(completion("Catching up.")).mount(&openai).await;
+
+    let resp = server.post("/process_backlog").await;
+    assert_eq!(resp.status(), 202);
+    server
+        .wait_until(|| async {
+            let backlog = &server.get_json("/status").await["backlog"];
+            backlog["queued"] == 0 && backlog["processing"] == false
+        })
+        .await;
+    let backlog = &server.get_json("/status").await["backlog"];
+    assert_eq!(backlog["failed"], 0);
+    assert!(backlog["processed"].as_u64() >= Some(1));
+
+    let records = server.log_records().await;
+    assert!(!records.is_empty());
+    assert!(records.iter().all(|r| r["session_id"] == session));
+    assert_eq!(records[0]["text"], "while you were away");
+}
+
+#[tokio::test]
+async fn transcripts_gpt_missed_are_answered_once_it_is_back() {
+    // Whisper works, but nothing listens where GPT is
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("the line dropped here")).mount(&openai).await;
+    let gpt_port = free_port();
+    let config = format!(
+        r#"
+[providers.gpt]
+base_url = "http://127.0.0.1:{gpt_port}/v1"
+
+[routing]
+transcripts = ["gpt"]
+"#
+    );
+    let server = TestServer::start_with_config(&openai.uri(), &config, &[("BACKLOG_RETRY_SECS", "1")]).await;
+    let mut events = SseStream::open(&server, "/live_log").await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["backlog"]["queued"].as_u64() >= Some(1) })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    assert!(server.log_records().await.is_empty());
+
+    // GPT comes back and the backlog drains by itself
+    let listener = std::net::TcpListener::bind(("127.0.0.1", gpt_port)).expect("bind the GPT port");
+    let gpt = MockServer::builder().listener(listener).start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/chat/completions"))
+        .respond_with(completion("Back again."))
+        .mount(&gpt)
+        .await;
+    server
+        .wait_until(|| async {
+            let status = server.get_json("/status").await;
+            let backlog = &status["backlog"];
+            status["state"]["name"] == "idle" && backlog["queued"] == 0 && backlog["processing"] == false
+        })
+        .await;
+    assert_eq!(server.get_json("/status").await["backlog"]["failed"], 0);
+    loop {
+        let event = events.next().await;
+        if event.event.as_deref() == Some("backlog_progress") {
+            assert_eq!(event.data["result"], "processed");
+            assert_eq!(event.data["audio_source"], "default");
+            break;
+        }
+    }
+
+    // Each transcript is logged with its response
+    let records = server.log_records().await;
+    let heard: Vec<&Value> = records.iter().filter(|r| r["source"] == "Microphone").map(|r| &r["chunk_id"]).collect();
+    let answered: Vec<&Value> = records.iter().filter(|r| r["source"] == "OPENAI RESPONSE").map(|r| &r["chunk_id"]).collect();
+    assert!(!heard.is_empty());
+    assert_eq!(heard, answered);
+    assert!(records.iter().all(|r| r["text"] == "the line dropped here" || r["text"] == "Back again."));
+}
+
+#[tokio::test]
+async fn restart_only_settings_keep_their_running_values_on_reload() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start_with_config(&openai.uri(), "[homekit]\nname = \"Kitchen\"\n", &[]).await;
+    let config_path = server.dir.path().join("silentnight.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(&config_path, config.replace("name = \"Kitchen\"", "name = \"Hallway\"")).unwrap();
+
+    // Reported each time, since the server keeps running with the old name
+    for _ in 0..2 {
+        let report: Value = server.post("/reload").await.json().await.unwrap();
+        assert_eq!(report["requires_restart"], serde_json::json!(["homekit.name"]));
+        assert_eq!(report["applied"], serde_json::json!([]));
+    }
+}
+
+#[tokio::test]
+async fn latency_is_reported_on_status_metrics_and_telemetry() {
+    let openai = mock_openai("hello", "A greeting.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("TELEMETRY_SECS", "1")]).await;
+
+    let mut stream = SseStream::open(&server, "/live_log").await;
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+
+    let status = server.get_json("/status").await;
+    let pipeline = &status["sources"][0]["pipeline"];
+    assert!(pipeline["bytes_captured"].as_u64() > Some(0), "{pipeline}");
+    assert!(pipeline["end_to_end"]["count"].as_u64() >= Some(1), "{pipeline}");
+    assert!(pipeline["capture"]["max_ms"].as_u64() >= pipeline["capture"]["last_ms"].as_u64());
+    assert!(status["openai"]["whisper"]["count"].as_u64() >= Some(1));
+    assert!(status["openai"]["gpt"]["count"].as_u64() >= Some(1));
+
+    let resp = server.http.get(server.url("/metrics")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let metrics = resp.text().await.unwrap();
+    assert!(metrics.contains("# TYPE silentnight_chunk_latency_seconds summary"), "{metrics}");
+    assert!(metrics.contains("silentnight_capture_gap_seconds_count{source=\"default\"}"), "{metrics}");
+    assert!(metrics.contains(r#"silentnight_stage_seconds_count{source="default",stage="transcribe"}"#));
+    assert!(metrics.contains(r#"silentnight_openai_request_seconds_count{api="whisper"}"#));
+
+    let telemetry = loop {
+        let ev = stream.next().await;
+        if ev.event.as_deref() == Some("telemetry") {
+            break ev.data;
+        }
+    };
+    assert_eq!(telemetry["sources"][0]["audio_source"], "default");
+    assert!(telemetry["sources"][0]["chunks_per_min"].is_number());
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+}
+
+#[cfg(feature = "mqtt")]
+#[tokio::test]
+async fn home_assistant_switch_controls_recording_over_mqtt() {
+    let openai = mock_openai("goodnight everyone", "Sleep well.").await;
+    let mut broker = common::MqttBroker::start().await;
+    let port = broker.port.to_string();
+    let env = [("MQTT_ENABLED", "true"), ("MQTT_HOST", "127.0.0.1"), ("MQTT_PORT", port.as_str())];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    // Discovery config for the default source's switch, then its state
+    let config = broker.next_on("homeassistant/switch/silentnight/default_recording/config").await;
+    let config: Value = serde_json::from_str(&config).unwrap();
+    assert_eq!(config["command_topic"], "silentnight/default/recording/set");
+    assert_eq!(config["availability_topic"], "silentnight/status");
+    assert_eq!(config["device"]["identifiers"][0], "silentnight");
+    assert_eq!(broker.next_on("silentnight/default/recording").await, "OFF");
+
+    broker.send("silentnight/default/recording/set", "ON");
+    broker.wait_for("silentnight/default/recording", "ON").await;
+    let latest: Value = serde_json::from_str(&broker.next_on("silentnight/default/latest").await).unwrap();
+    assert_eq!(latest["transcript"], "goodnight everyone");
+    assert_eq!(latest["response"], "Sleep well.");
+    assert_eq!(server.get_json("/status").await["recording"], true);
+
+    broker.send("silentnight/default/recording/set", "OFF");
+    broker.wait_for("silentnight/default/recording", "OFF").await;
+    server
+        .wait_until(|| async { server.get_json("/status").await["state"]["name"] == "idle" })
+        .await;
+}
+
+#[tokio::test]
+async fn keyword_in_a_transcript_alerts_slack() {
+    let openai = mock_openai("is the Server down again", "Sounds like an outage.").await;
+    let slack = MockServer::start().await;
+    Mock::given(method("POST")).and(path("/hooks/alerts")).respond_with(ResponseTemplate::new(200)).mount(&slack).await;
+    let webhook_url = format!("{}/hooks/alerts", slack.uri());
+    let env = [
+        ("ALERT_KEYWORDS", "invoice,server down,serve"),
+        ("PUBLIC_URL", "http://pi.local:8080/"),
+        ("SLACK_WEBHOOK_URL", webhook_url.as_str()),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { slack.received_requests().await.is_some_and(|r| !r.is_empty()) })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let session = server.log_records().await[0]["session_id"].as_str().expect("session_id").to_string();
+    let alert: Value = slack.received_requests().await.unwrap()[0].body_json().unwrap();
+    let text = alert["text"].as_str().unwrap();
+    // Whole words only, so "serve" doesn't match "Server"
+    assert!(text.starts_with("*Heard \"server down\"* on default"), "{text}");
+    assert!(text.contains("> is the Server down again"), "{text}");
+    assert!(text.contains(&format!("<http://pi.local:8080/?session={session}|Open the session>")), "{text}");
+}
+
+#[tokio::test]
+async fn discord_slash_command_records_and_posts_a_summary() {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    let openai = mock_openai("the budget is due on friday", "Budget due Friday.").await;
+    let discord = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path("/applications/42/guilds/7/commands"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&discord)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/channels/99/messages"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&discord)
+        .await;
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+    let keys = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let public_key: String = keys.public_key().as_ref().iter().map(|b| format!("{b:02x}")).collect();
+    let api_url = discord.uri();
+    let env = [
+        ("DISCORD_ENABLED", "true"),
+        ("DISCORD_BOT_TOKEN", "bot-token"),
+        ("DISCORD_APPLICATION_ID", "42"),
+        ("DISCORD_PUBLIC_KEY", public_key.as_str()),
+        ("DISCORD_GUILD_ID", "7"),
+        ("DISCORD_CHANNEL_ID", "99"),
+        ("DISCORD_API_URL", api_url.as_str()),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    // Signed the way Discord does it: timestamp + body
+    let interaction = |body: Value| {
+        let body = body.to_string();
+        let timestamp = "1700000000";
+        let signature: String = keys
+            .sign(format!("{timestamp}{body}").as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        server
+            .http
+            .post(server.url("/discord/interactions"))
+            .header("X-Signature-Ed25519", signature)
+            .header("X-Signature-Timestamp", timestamp)
+            .header("Content-Type", "application/json")
+            .body(body)
+    };
+    let slash = |sub: &str| {
+        serde_json::json!({
+            "type": 2,
+            "guild_id": "7",
+            "member": { "user": { "username": "ada" } },
+            "data": { "name": "silentnight", "options": [{ "type": 1, "name": sub }] },
+        })
+    };
+
+    let forged = server
+        .http
+        .post(server.url("/discord/interactions"))
+        .header("X-Signature-Ed25519", "00".repeat(64))
+        .header("X-Signature-Timestamp", "1700000000")
+        .body(r#"{"type":1}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forged.status(), 401);
+    let pong: Value = interaction(serde_json::json!({ "type": 1 })).send().await.unwrap().json().await.unwrap();
+    assert_eq!(pong["type"], 1);
+
+    let started: Value = interaction(slash("start")).send().await.unwrap().json().await.unwrap();
+    assert!(started["data"]["content"].as_str().unwrap().starts_with("Recording default"), "{started}");
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    let stopped: Value = interaction(slash("stop")).send().await.unwrap().json().await.unwrap();
+    assert!(stopped["data"]["content"].as_str().unwrap().starts_with("Stopping default"), "{stopped}");
+
+    // The summary is GPT's answer to the session's transcripts
+    let posted = || async {
+        let requests = discord.received_requests().await.unwrap_or_default();
+        requests.iter().filter(|r| r.method.as_str() == "POST").map(|r| r.body_json::<Value>().unwrap()).collect::<Vec<_>>()
+    };
+    server.wait_until(|| async { !posted().await.is_empty() }).await;
+    let summary = &posted().await[0];
+    let content = summary["content"].as_str().unwrap();
+    let session = server.log_records().await[0]["session_id"].as_str().unwrap().to_string();
+    assert!(content.starts_with(&format!("**Session {session}** on default")), "{content}");
+    assert!(content.ends_with("\nBudget due Friday."), "{content}");
+    assert_eq!(summary["allowed_mentions"]["parse"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn telegram_commands_control_recording_and_alerts_arrive() {
+    let openai = mock_openai("the budget is due on friday", "Budget due Friday.").await;
+    let telegram = MockServer::start().await;
+    let updates = |updates: Value| ResponseTemplate::new(200).set_body_json(serde_json::json!({ "ok": true, "result": updates }));
+    let message = |update_id: i64, chat_id: i64, text: &str| {
+        serde_json::json!({ "update_id": update_id, "message": { "chat": { "id": chat_id }, "text": text } })
+    };
+    Mock::given(method("POST"))
+        .and(path("/botbot-token/getUpdates"))
+        .respond_with(updates(serde_json::json!([message(1, 666, "/status"), message(2, 5, "/start_recording")])))
+        .up_to_n_times(1)
+        .mount(&telegram)
+        .await;
+    // Nothing new: a long poll that returns early
+    Mock::given(method("POST"))
+        .and(path("/botbot-token/getUpdates"))
+        .respond_with(updates(serde_json::json!([])).set_delay(std::time::Duration::from_millis(100)))
+        .mount(&telegram)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/botbot-token/sendMessage"))
+        .respond_with(updates(serde_json::json!({})))
+        .mount(&telegram)
+        .await;
+    let api_url = telegram.uri();
+    let env = [
+        ("TELEGRAM_ENABLED", "true"),
+        ("TELEGRAM_BOT_TOKEN", "bot-token"),
+        ("TELEGRAM_CHAT_IDS", "5"),
+        ("TELEGRAM_DIGEST_TIME", ""),
+        ("TELEGRAM_API_URL", api_url.as_str()),
+        ("ALERT_KEYWORDS", "budget"),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    let sent = || async {
+        let requests = telegram.received_requests().await.unwrap_or_default();
+        requests
+            .iter()
+            .filter(|r| r.url.path().ends_with("/sendMessage"))
+            .map(|r| r.body_json::<Value>().unwrap())
+            .map(|body| (body["chat_id"].as_i64().unwrap(), body["text"].as_str().unwrap().to_string()))
+            .collect::<Vec<_>>()
+    };
+    let sent_to = |chat_id: i64, prefix: &'static str| {
+        let sent = &sent;
+        async move { sent().await.iter().any(|(id, text)| *id == chat_id && text.starts_with(prefix)) }
+    };
+
+    // Another chat only learns its ID
+    server.wait_until(|| sent_to(666, "This chat isn't authorized")).await;
+    server.wait_until(|| sent_to(5, "Recording default (session ")).await;
+    server.wait_until(|| sent_to(5, "Heard \"budget\" on default\n")).await;
+
+    // Later commands, once a chunk is through
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/botbot-token/getUpdates"))
+        .and(body_string_contains(r#""offset":3"#))
+        .respond_with(updates(serde_json::json!([message(3, 5, "/last"), message(4, 5, "/stop@SilentNightBot")])))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&telegram)
+        .await;
+    server.wait_until(|| sent_to(5, "Heard: the budget is due on friday\nResponse: Budget due Friday.")).await;
+    server.wait_until(|| sent_to(5, "Stopping default.")).await;
+    server.wait_until(|| async { server.get_json("/status").await["recording"] == false }).await;
+    assert!(sent().await.iter().all(|(id, text)| *id == 5 || !text.contains("budget")));
+}
+
+#[cfg(feature = "email")]
+#[tokio::test]
+async fn stopped_session_summary_is_emailed() {
+    let openai = mock_openai("the budget is due on friday", "Budget due Friday.").await;
+    let mut smtp = common::SmtpServer::start().await;
+    let port = smtp.port.to_string();
+    let env = [
+        ("EMAIL_ENABLED", "true"),
+        ("SMTP_HOST", "127.0.0.1"),
+        ("SMTP_PORT", port.as_str()),
+        ("SMTP_SECURITY", "none"),
+        ("EMAIL_FROM", "SilentNight <silentnight@example.com>"),
+        ("EMAIL_TO", "ada@example.com, bob@example.com"),
+        ("EMAIL_DIGEST", "off"),
+        ("EMAIL_SESSION_SUMMARIES", "true"),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    assert!(server.post("/start_recording").await.status().is_success());
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    assert!(server.post("/stop_recording").await.status().is_success());
+
+    let mail = smtp.next_mail().await;
+    let session = server.log_records().await[0]["session_id"].as_str().unwrap().to_string();
+    assert!(mail.contains(&format!("Subject: SilentNight session {session} on default\n")), "{mail}");
+    assert!(mail.contains("To: ada@example.com, bob@example.com\n"), "{mail}");
+    let body = mail.split_once("\n\n").unwrap().1;
+    assert!(body.starts_with(&format!("Session {session} on default, ")), "{body}");
+    assert!(body.ends_with("\n\nBudget due Friday.\n"), "{body}");
+}
+
+#[tokio::test]
+async fn calendar_meeting_is_recorded_with_its_title_and_attendees() {
+    let openai = mock_openai("let's start the weekly sync", "Noted.").await;
+    let caldav = MockServer::start().await;
+    let at = |t: chrono::DateTime<chrono::Utc>| t.format("%Y%m%dT%H%M%SZ").to_string();
+    let now = chrono::Utc::now();
+    // Folded lines, a VALARM and a declined attendee, escaped as
+    // calendar servers send them
+    let ics = [
+        "BEGIN:VCALENDAR",
+        "BEGIN:VEVENT",
+        "UID:sync-1",
+        &format!("DTSTART:{}", at(now - chrono::Duration::minutes(1))),
+        &format!("DTEND:{}", at(now + chrono::Duration::seconds(8))),
+        "SUMMARY:Weekly sync\\, Q4",
+        "ATTENDEE;CN=\"Ada Lovelace\";PARTSTAT=ACCEPTED:mailto:ada@exam",
+        " ple.com",
+        "ATTENDEE:mailto:bob@example.com",
+        "ATTENDEE;CN=Carol;PARTSTAT=DECLINED:mailto:carol@example.com",
+        "BEGIN:VALARM",
+        "SUMMARY:Reminder",
+        "END:VALARM",
+        "END:VEVENT",
+        "END:VCALENDAR",
+    ]
+    .join("\r\n");
+    let escaped = ics.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;").replace('\r', "&#13;");
+    let multistatus = format!(
+        r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav"><d:response><d:href>/cal/sync-1.ics</d:href><d:propstat><d:prop><cal:calendar-data>{escaped}</cal:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>"#
+    );
+    Mock::given(method("REPORT"))
+        .and(path("/cal/"))
+        .and(wiremock::matchers::header("depth", "1"))
+        .and(body_string_contains("<C:expand"))
+        .respond_with(ResponseTemplate::new(207).set_body_string(multistatus))
+        .mount(&caldav)
+        .await;
+    let url = format!("{}/cal/", caldav.uri());
+    let env = [("CALENDAR_ENABLED", "true"), ("CALENDAR_URL", url.as_str())];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    let record = &server.log_records().await[0];
+    assert_eq!(record["session_title"], "Weekly sync, Q4");
+    assert_eq!(record["attendees"], serde_json::json!(["Ada Lovelace <ada@example.com>", "bob@example.com"]));
+
+    // Stopped when the meeting ends
+    server
+        .wait_until(|| async { server.get_json("/status").await["state"]["name"] == "idle" })
+        .await;
+}
+
+#[tokio::test]
+async fn rules_are_managed_over_http_and_fire_their_actions() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let hooks = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hooks/oven"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&hooks)
+        .await;
+    let env = [("ADMIN_TOKEN", "s3cret"), ("GPIO_DIR", "gpio"), ("TTS_COMMAND", "tee tts.txt")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    // As if pin 17 were already exported
+    std::fs::create_dir_all(server.dir.path().join("gpio/gpio17")).unwrap();
+    let admin = |req: reqwest::RequestBuilder| req.bearer_auth("s3cret");
+
+    let resp = server.http.get(server.url("/rules")).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+    let resp = admin(server.http.post(server.url("/rules")))
+        .json(&serde_json::json!({ "name": "empty", "then": [] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    assert_eq!(resp.json::<Value>().await.unwrap()["code"], "invalid_rule");
+
+    let rule = serde_json::json!({
+        "name": "oven",
+        "when": { "keywords": ["stove"] },
+        "then": [{ "type": "webhook", "url": format!("{}/hooks/oven", hooks.uri()) }],
+    });
+    let resp = admin(server.http.post(server.url("/rules"))).json(&rule).send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    let id = resp.json::<Value>().await.unwrap()["id"].as_str().unwrap().to_string();
+    let rule_url = server.url(&format!("/rules/{id}"));
+
+    let mut rule = admin(server.http.get(&rule_url)).send().await.unwrap().json::<Value>().await.unwrap();
+    assert_eq!(rule["on"], "transcript");
+    rule["when"]["keywords"] = serde_json::json!(["oven"]);
+    rule["then"].as_array_mut().unwrap().extend([
+        serde_json::json!({ "type": "gpio", "pin": 17 }),
+        serde_json::json!({ "type": "tts", "text": "{rule} on {audio_source}: {text}" }),
+    ]);
+    let resp = admin(server.http.put(&rule_url)).json(&rule).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    // Kept across restarts
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(server.dir.path().join("rules.json")).unwrap()).unwrap();
+    assert_eq!(saved[0]["when"]["keywords"], serde_json::json!(["oven"]));
+
+    let throwaway = serde_json::json!({ "name": "later", "then": [{ "type": "tts", "text": "hi" }], "enabled": false });
+    let resp = admin(server.http.post(server.url("/rules"))).json(&throwaway).send().await.unwrap();
+    let throwaway_id = resp.json::<Value>().await.unwrap()["id"].as_str().unwrap().to_string();
+    let resp = admin(server.http.delete(server.url(&format!("/rules/{throwaway_id}")))).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let rules: Value = admin(server.http.get(server.url("/rules"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    let resp = admin(server.http.delete(server.url(&format!("/rules/{throwaway_id}")))).send().await.unwrap();
+    assert_eq!(resp.status(), 404);
+
+    assert_eq!(server.post("/record_once").await.status(), 200);
+
+    let gpio = server.dir.path().join("gpio/gpio17/value");
+    let tts = server.dir.path().join("tts.txt");
+    server
+        .wait_until(|| async {
+            std::fs::read_to_string(&gpio).is_ok_and(|v| v == "1")
+                && std::fs::read_to_string(&tts).is_ok_and(|t| t == "oven on default: is the oven still on")
+        })
+        .await;
+    server
+        .wait_until(|| async { hooks.received_requests().await.is_some_and(|r| !r.is_empty()) })
+        .await;
+    let delivery: Value = hooks.received_requests().await.unwrap()[0].body_json().unwrap();
+    assert_eq!(delivery["event"], "rule");
+    assert_eq!(delivery["data"]["rule"]["name"], "oven");
+    assert_eq!(delivery["data"]["record"]["text"], "is the oven still on");
+}
+
+#[tokio::test]
+async fn sessions_export_to_obsidian_on_close_and_to_notion_on_request() {
+    let openai = mock_openai("the roof needs fixing", "Someone mentions the roof.").await;
+    let notion = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/pages"))
+        .and(wiremock::matchers::header("authorization", "Bearer secret_abc"))
+        .and(wiremock::matchers::header("notion-version", "2022-06-28"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "id": "page-1", "url": "https://notion.so/page-1" })),
+        )
+        .expect(2)
+        .mount(&notion)
+        .await;
+    let env = [
+        ("EXPORT_ON_SESSION_CLOSE", "true"),
+        ("OBSIDIAN_DIR", "vault/Meetings"),
+        ("NOTION_TOKEN", "secret_abc"),
+        ("NOTION_DATABASE_ID", "db-1"),
+        ("NOTION_API_URL", &notion.uri()),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    // Nothing logged yet
+    let resp = server.post("/sessions/nope/export?to=obsidian").await;
+    assert_eq!(resp.status(), 404);
+    assert_eq!(server.post("/sessions/nope/export?to=evernote").await.status(), 400);
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(1) })
+        .await;
+    let session = server.get_json("/status").await["session_id"].as_str().unwrap().to_string();
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    // Closing the session wrote its note and made a Notion page
+    let vault = server.dir.path().join("vault/Meetings");
+    let note = || {
+        std::fs::read_dir(&vault)
+            .ok()
+            .and_then(|mut files| files.next())
+            .and_then(|file| std::fs::read_to_string(file.ok()?.path()).ok())
+    };
+    server.wait_until(|| async { note().is_some() }).await;
+    let note = note().unwrap();
+    assert!(note.starts_with(&format!("---\nsession_id: \"{session}\"\n")), "{note}");
+    assert!(note.contains("tags: [silentnight]"));
+    assert!(note.contains(&format!("# Session {session}")));
+    assert!(note.contains("the roof needs fixing\n  > Someone mentions the roof."), "{note}");
+    server
+        .wait_until(|| async { notion.received_requests().await.is_some_and(|r| !r.is_empty()) })
+        .await;
+    // Again on request, to Notion only
+    let resp = server.post(&format!("/sessions/{session}/export?to=notion")).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body, serde_json::json!({ "notion": "https://notion.so/page-1" }));
+    let page: Value = notion.received_requests().await.unwrap()[1].body_json().unwrap();
+    assert_eq!(page["parent"]["database_id"], "db-1");
+    assert_eq!(page["properties"]["Name"]["title"][0]["text"]["content"], format!("Session {session}"));
+    assert_eq!(page["children"][3]["heading_2"]["rich_text"][0]["text"]["content"], "Transcript");
+}
+
+#[tokio::test]
+async fn sessions_are_translated_for_display_and_export() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("the roof needs fixing")).mount(&openai).await;
+    // Every line of the batch, in Spanish
+    chat()
+        .and(body_string_contains("You translate transcripts"))
+        .respond_with(|request: &wiremock::Request| {
+            let body: Value = request.body_json().unwrap();
+            let content = body["messages"][1]["content"].as_str().unwrap();
+            let (language, lines) = content.split_once('\n').unwrap();
+            assert_eq!(language, "Language: es");
+            let lines: Vec<String> = serde_json::from_str(lines).unwrap();
+            let translated = vec!["hay que arreglar el tejado"; lines.len()];
+            completion(&format!("```json\n{}\n```", serde_json::to_string(&translated).unwrap()))
+        })
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("Someone mentions the roof.")).mount(&openai).await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("OBSIDIAN_DIR", "vault")]).await;
+
+    assert_eq!(server.post("/sessions/nope/translate?lang=es").await.status(), 404);
+    assert_eq!(server.post("/sessions/nope/translate?lang=../x").await.status(), 400);
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64() >= Some(2) })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    let session = server.log_records().await[0]["session_id"].as_str().unwrap().to_string();
+
+    let page = |path: String| {
+        let server = &server;
+        async move {
+            let resp = server.http.get(server.url(&path)).send().await.expect("GET");
+            (resp.status(), resp.text().await.unwrap())
+        }
+    };
+    let (status, _) = page(format!("/sessions/{session}?lang=es")).await;
+    assert_eq!(status, 404);
+
+    let resp = server.post(&format!("/sessions/{session}/translate?lang=es")).await;
+    assert_eq!(resp.status(), 200);
+    let translation: Value = resp.json().await.unwrap();
+    assert_eq!(translation["lang"], "es");
+    let lines = translation["lines"].as_array().unwrap();
+    assert!(lines.len() >= 2, "{translation}");
+    assert_eq!(lines[0]["original"], "the roof needs fixing");
+    assert_eq!(lines[0]["text"], "hay que arreglar el tejado");
+    assert!(server.dir.path().join(format!("translations/{session}.es.json")).exists());
+
+    // Shown with the original under it, and linked from the original
+    let (status, translated) = page(format!("/sessions/{session}?lang=es")).await;
+    assert_eq!(status, 200);
+    assert!(
+        translated.contains("hay que arreglar el tejado<div class=\"original\">the roof needs fixing</div>"),
+        "{translated}"
+    );
+    let (_, original) = page(format!("/sessions/{session}")).await;
+    assert!(original.contains(&format!("<a href=\"/sessions/{session}?lang=es\">es</a>")), "{original}");
+
+    // Exported as its own note
+    let resp = server.post(&format!("/sessions/{session}/export?lang=es")).await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = resp.json().await.unwrap();
+    let note = std::fs::read_to_string(server.dir.path().join(body["obsidian"].as_str().unwrap())).unwrap();
+    assert!(body["obsidian"].as_str().unwrap().ends_with(&format!("Session {session} (es).md")));
+    assert!(note.contains("language: \"es\"\n"), "{note}");
+    assert!(note.contains("hay que arreglar el tejado\n  > Someone mentions the roof."), "{note}");
+    assert_eq!(server.post(&format!("/sessions/{session}/export?lang=fr")).await.status(), 404);
+}
+
+#[tokio::test]
+async fn sessions_replay_in_their_original_timing() {
+    let openai = mock_openai("the roof needs fixing", "Someone mentions the roof.").await;
+    let server = TestServer::start(&openai.uri()).await;
+
+    assert_eq!(server.http.get(server.url("/sessions/nope/replay")).send().await.unwrap().status(), 404);
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server.wait_until(|| async { server.log_records().await.len() >= 4 }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.get_json("/status").await["state"]["name"] == "idle" })
+        .await;
+    let records = server.log_records().await;
+    let session = records[0]["session_id"].as_str().unwrap().to_string();
+    let time = |record: &Value| chrono::DateTime::parse_from_rfc3339(record["timestamp"].as_str().unwrap()).unwrap();
+    let span = (time(&records[records.len() - 1]) - time(&records[0])).to_std().unwrap();
+    let replay = format!("/sessions/{session}/replay?speed=2");
+    let too_slow = format!("/sessions/{session}/replay?speed=0");
+    assert_eq!(server.http.get(server.url(&too_slow)).send().await.unwrap().status(), 400);
+
+    let started = std::time::Instant::now();
+    let mut stream = SseStream::open(&server, &replay).await;
+    for record in &records {
+        let mut event = stream.next().await;
+        assert_eq!(event.event, None);
+        // As logged, plus the time as it's shown
+        assert!(event.data.as_object_mut().unwrap().remove("local_time").is_some());
+        assert_eq!(event.data, *record);
+    }
+    let end = stream.next().await;
+    assert_eq!(end.event.as_deref(), Some("replay_end"));
+    assert_eq!(end.data["records"], records.len());
+    // Twice as fast as it happened
+    let took = started.elapsed();
+    assert!(took >= span / 2 - std::time::Duration::from_millis(50), "{took:?} for {span:?}");
+
+    // Reconnecting carries on after the last record seen, until there's none
+    let last = records.len().to_string();
+    let resp = server.http.get(server.url(&format!("{replay}&schema=2"))).header("Last-Event-ID", "1").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = resp.text().await.unwrap();
+    assert!(body.starts_with("id: 2\ndata: {"), "{body}");
+    assert!(body.contains("\"schema\":2"), "{body}");
+    let resp = server.http.get(server.url(&replay)).header("Last-Event-ID", last).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+}
+
+#[tokio::test]
+async fn light_pulses_for_responses_and_is_set_up_over_http() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let wled = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/json/state"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "on": false, "bri": 40 })))
+        .mount(&wled)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/json/state"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": true })))
+        .mount(&wled)
+        .await;
+    let hue = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/bridges"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!([{ "id": "ecb5fafffe0a1b2c", "internalipaddress": "192.168.1.20" }])),
+        )
+        .mount(&hue)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{ "success": { "username": "key123" } }])))
+        .mount(&hue)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/key123/lights"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "3": { "name": "Hallway" } })))
+        .mount(&hue)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/api/key123/lights/3/state"))
+        .and(body_string_contains("\"alert\":\"select\""))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{ "success": {} }])))
+        .expect(1)
+        .mount(&hue)
+        .await;
+    let wled_host = wled.address().to_string();
+    let discovery_url = format!("{}/bridges", hue.uri());
+    let env = [
+        ("ADMIN_TOKEN", "s3cret"),
+        ("LIGHTS_ENABLED", "true"),
+        ("LIGHTS_KIND", "wled"),
+        ("LIGHTS_HOST", wled_host.as_str()),
+        ("LIGHTS_PULSE_MS", "50"),
+        ("HUE_DISCOVERY_URL", discovery_url.as_str()),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let admin = |req: reqwest::RequestBuilder| req.bearer_auth("s3cret");
+
+    // Lit for the response, then put back
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let posts = || async {
+        let requests = wled.received_requests().await.unwrap_or_default();
+        requests
+            .iter()
+            .filter(|r| r.method == wiremock::http::Method::POST)
+            .map(|r| r.body_json::<Value>().unwrap())
+            .collect::<Vec<_>>()
+    };
+    server.wait_until(|| async { posts().await.len() >= 2 }).await;
+    let posts = posts().await;
+    assert_eq!(posts[0], serde_json::json!({ "on": true, "bri": 255, "transition": 0 }));
+    assert_eq!(posts[1], serde_json::json!({ "on": false, "bri": 40, "transition": 0 }));
+
+    // Over to a Hue bridge: find it, pair, pick a light, test it
+    let resp = server.http.get(server.url("/lights/settings")).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+    let resp = admin(server.http.patch(server.url("/lights/settings")))
+        .json(&serde_json::json!({ "kind": "lamp" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let found: Value = admin(server.http.get(server.url("/lights/discover"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(found["hue_bridges"][0]["host"], "192.168.1.20");
+    let resp = admin(server.http.patch(server.url("/lights/settings")))
+        .json(&serde_json::json!({ "enabled": false, "kind": "hue", "host": hue.address().to_string() }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let paired: Value = admin(server.http.post(server.url("/lights/pair"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(paired["username"], "key123");
+    let found: Value = admin(server.http.get(server.url("/lights/discover"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(found["hue_lights"], serde_json::json!([{ "id": "3", "name": "Hallway" }]));
+    let settings: Value = admin(server.http.patch(server.url("/lights/settings")))
+        .json(&serde_json::json!({ "enabled": true, "light": "3" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(settings["paired"], true);
+    assert!(settings.get("username").is_none());
+    let resp = admin(server.http.post(server.url("/lights/test"))).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+}
+
+#[tokio::test]
+async fn remote_keys_record_bookmark_mute_and_switch_persona() {
+    use tokio::io::AsyncWriteExt;
+
+    let openai = mock_openai("pick up milk on the way home", "A reminder about milk.").await;
+    let lirc_dir = tempfile::TempDir::new().unwrap();
+    let socket = lirc_dir.path().join("lircd");
+    let lircd = tokio::net::UnixListener::bind(&socket).unwrap();
+    let personas = r#"
+[openai.personas]
+shopping = "You keep the shopping list."
+"#;
+    let env = [("REMOTE_ENABLED", "true"), ("LIRC_SOCKET", socket.to_str().unwrap())];
+    let server = TestServer::start_with_config(&openai.uri(), personas, &env).await;
+    let (mut remote, _) = tokio::time::timeout(std::time::Duration::from_secs(10), lircd.accept())
+        .await
+        .expect("server connects to lircd")
+        .unwrap();
+    // What lircd sends for a button press
+    async fn press(remote: &mut tokio::net::UnixStream, button: &str, repeat: u8) {
+        let line = format!("000000037ff07bef {repeat:02x} {button} livingroom\n");
+        remote.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    // The persona applies from the next chunk; the held button's
+    // repeat doesn't toggle recording straight back off
+    press(&mut remote, "KEY_NEXTSONG", 0).await;
+    press(&mut remote, "KEY_PLAYPAUSE", 0).await;
+    press(&mut remote, "KEY_PLAYPAUSE", 1).await;
+    server
+        .wait_until(|| async { server.get_json("/status").await["chunks_processed"].as_u64().unwrap_or(0) >= 1 })
+        .await;
+    let status = server.get_json("/status").await;
+    assert_eq!(status["recording"], true);
+    let session_id = status["sources"][0]["session_id"].as_str().unwrap().to_string();
+    let requests = openai.received_requests().await.unwrap();
+    // Not counting the history being summarized (see memory.rs)
+    let prompts: Vec<_> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/v1/chat/completions")
+        .map(|r| String::from_utf8_lossy(&r.body))
+        .filter(|body| !body.contains("You keep the memory"))
+        .collect();
+    assert!(!prompts.is_empty());
+    assert!(prompts.iter().all(|body| body.contains("You keep the shopping list.")));
+
+    press(&mut remote, "KEY_RECORD", 0).await;
+    let bookmarks_path = format!("/bookmarks?session={session_id}");
+    server
+        .wait_until(|| async { !server.get_json(&bookmarks_path).await.as_array().unwrap().is_empty() })
+        .await;
+    let bookmarks = server.get_json(&bookmarks_path).await;
+    assert_eq!(bookmarks[0]["audio_source"], "default");
+    assert_eq!(bookmarks[0]["transcript"], "pick up milk on the way home");
+
+    press(&mut remote, "KEY_MUTE", 0).await;
+    server.wait_until(|| async { server.get_json("/status").await["sources"][0]["muted"] == true }).await;
+    press(&mut remote, "KEY_MUTE", 0).await;
+    server.wait_until(|| async { server.get_json("/status").await["sources"][0]["muted"] == false }).await;
+
+    press(&mut remote, "KEY_STOP", 0).await;
+    server.wait_until(|| async { server.get_json("/status").await["recording"] == false }).await;
+}
+
+#[cfg(feature = "homekit")]
+#[tokio::test]
+async fn homekit_controller_pairs_and_switches_recording() {
+    use common::hap::{self, Connection, Identity};
+    use serde_json::json;
+
+    let openai = mock_openai("is the baby asleep", "Someone is checking on the baby.").await;
+    let port = free_port();
+    let port_env = port.to_string();
+    let env = [("HOMEKIT_ENABLED", "true"), ("HOMEKIT_PORT", port_env.as_str()), ("HOMEKIT_SETUP_CODE", "031-45-154")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let mut iphone = Identity::new("2B4C1E7A-iPhone");
+
+    // Nothing without a verified session; a wrong code is refused
+    let mut conn = Connection::open(port).await;
+    let (status, _) = conn.json_request("GET", "/accessories", None).await;
+    assert_eq!(status, 470);
+    assert_eq!(conn.pair("111-22-333", &mut iphone).await, Err(2));
+    conn.pair("031-45-154", &mut iphone).await.expect("pair with the right code");
+    let state: Value = serde_json::from_str(&std::fs::read_to_string(server.dir.path().join("homekit.json")).unwrap()).unwrap();
+    assert_eq!(state["pairings"]["2B4C1E7A-iPhone"]["admin"], true);
+    // Only one owner; more people are added through /pairings
+    assert_eq!(conn.pair("031-45-154", &mut Identity::new("someone-else")).await, Err(6));
+
+    let mut conn = Connection::open(port).await;
+    conn.verify(&iphone).await.expect("verify");
+    let (status, accessories) = conn.json_request("GET", "/accessories", None).await;
+    assert_eq!(status, 200);
+    let switch = &accessories["accessories"][0]["services"][1];
+    assert_eq!(switch["type"], "49");
+    assert_eq!(switch["characteristics"][0]["value"], false);
+
+    // On starts recording; the other controller hears about it
+    let mut other = Connection::open(port).await;
+    other.verify(&iphone).await.expect("verify");
+    let subscribe = json!({ "characteristics": [{ "aid": 1, "iid": 9, "ev": true }] });
+    assert_eq!(other.json_request("PUT", "/characteristics", Some(subscribe)).await.0, 204);
+    let turn_on = json!({ "characteristics": [{ "aid": 1, "iid": 9, "value": true }] });
+    assert_eq!(conn.json_request("PUT", "/characteristics", Some(turn_on)).await.0, 204);
+    assert_eq!(server.get_json("/status").await["recording"], true);
+    assert_eq!(other.next_event().await["characteristics"][0]["value"], true);
+    let (_, read) = conn.json_request("GET", "/characteristics?id=1.9", None).await;
+    assert_eq!(read["characteristics"][0]["value"], true);
+
+    // Stopped from the web UI: subscribers are told too
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    assert_eq!(other.next_event().await["characteristics"][0]["value"], false);
+
+    // Sharing with someone else, then removing them
+    let mut guest = Identity::new("guest-ipad");
+    let add = [(6, &[1][..]), (0, &[3][..]), (1, guest.id.as_bytes()), (3, &guest.public_key()), (11, &[0][..])];
+    assert_eq!(hap::get(&conn.tlv_request("/pairings", &add).await, 6), [2]);
+    let list = conn.tlv_request("/pairings", &[(6, &[1]), (0, &[5])]).await;
+    let ids: Vec<&[u8]> = list.iter().filter(|(t, _)| *t == 1).map(|(_, v)| v.as_slice()).collect();
+    assert_eq!(ids, [&b"2B4C1E7A-iPhone"[..], &b"guest-ipad"[..]]);
+    let remove = [(6, &[1][..]), (0, &[4][..]), (1, guest.id.as_bytes())];
+    assert_eq!(hap::get(&conn.tlv_request("/pairings", &remove).await, 6), [2]);
+    guest.accessory_key = iphone.accessory_key.clone();
+    assert_eq!(Connection::open(port).await.verify(&guest).await, Err(2));
+}
+
+#[tokio::test]
+async fn spoken_commands_run_and_never_reach_gpt_or_the_log() {
+    let openai = MockServer::start().await;
+    let heard = [
+        "Silent night, switch to shopping mode.",
+        "Silentnight, bookmark that! We need more eggs.",
+        "OK. Silent Night: stop listening.",
+    ];
+    for (i, text) in heard.iter().enumerate() {
+        let mock = whisper().respond_with(transcript(text)).with_priority(i as u8 + 1);
+        // The last one for every chunk after it, too
+        let mock = if i + 1 < heard.len() { mock.up_to_n_times(1) } else { mock };
+        mock.mount(&openai).await;
+    }
+    chat().respond_with(completion("Eggs are on the list.")).mount(&openai).await;
+    let config = r#"
+[openai.personas]
+shopping = "You keep the shopping list."
+
+[voice]
+enabled = true
+"#;
+    let server = TestServer::start_with_config(&openai.uri(), config, &[]).await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    let session_id = server.get_json("/status").await["sources"][0]["session_id"].as_str().unwrap().to_string();
+    server.wait_until(|| async { server.get_json("/status").await["recording"] == false }).await;
+
+    let records = server.log_records().await;
+    let transcripts: Vec<&str> =
+        records.iter().filter(|r| r["source"] == "Microphone").filter_map(|r| r["text"].as_str()).collect();
+    assert!(transcripts.iter().all(|t| *t == "We need more eggs." || *t == "OK."), "{transcripts:?}");
+    assert!(transcripts.contains(&"We need more eggs."));
+    let requests = openai.received_requests().await.unwrap();
+    let chats: Vec<String> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/v1/chat/completions")
+        .map(|r| String::from_utf8_lossy(&r.body).to_lowercase())
+        .collect();
+    assert!(!chats.is_empty());
+    for body in &chats {
+        assert!(body.contains("you keep the shopping list."));
+        assert!(!["shopping mode", "bookmark that", "stop listening"].iter().any(|c| body.contains(c)), "{body}");
+    }
+    let bookmarks = server.get_json(&format!("/bookmarks?session={session_id}")).await;
+    assert_eq!(bookmarks.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn gpt_sets_a_reminder_that_fires_and_survives_restarts() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("remind us in a few seconds to check the oven")).mount(&openai).await;
+    // Once it has the tool's result, GPT answers
+    chat()
+        .and(body_string_contains(r#""role":"tool""#))
+        .respond_with(completion("I'll remind you about the oven."))
+        .with_priority(1)
+        .mount(&openai)
+        .await;
+    chat()
+        .and(body_string_contains("set_reminder"))
+        .respond_with(common::tool_call("set_reminder", serde_json::json!({ "text": "Check the oven", "in_minutes": 0.05 })))
+        .mount(&openai)
+        .await;
+    let hooks = MockServer::start().await;
+    Mock::given(method("POST")).and(path("/hooks")).respond_with(ResponseTemplate::new(200)).mount(&hooks).await;
+
+    // One that came due while the server was down
+    let state = tempfile::TempDir::new().unwrap();
+    let file = state.path().join("reminders.json");
+    let overdue = serde_json::json!([{
+        "id": "0000beef", "text": "Water the plants", "due": "2020-01-01T08:00:00Z",
+        "audio_source": "default", "session_id": null, "created": "2020-01-01T07:00:00Z",
+    }]);
+    std::fs::write(&file, overdue.to_string()).unwrap();
+    let config = format!("[[webhooks]]\nurl = \"{}/hooks\"\nevents = [\"reminder\"]\n", hooks.uri());
+    let env = [
+        ("ADMIN_TOKEN", "s3cret"),
+        ("REMINDERS_ENABLED", "true"),
+        ("REMINDERS_FILE", file.to_str().unwrap()),
+        ("TTS_COMMAND", "tee -a spoken.txt"),
+    ];
+    let server = TestServer::start_with_config(&openai.uri(), &config, &env).await;
+    let admin = |req: reqwest::RequestBuilder| req.bearer_auth("s3cret");
+    let mut live = SseStream::open(&server, "/live_log").await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.json::<Value>().await.unwrap()["gpt_response"], "I'll remind you about the oven.");
+    let pending: Value = admin(server.http.get(server.url("/reminders"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["text"], "Check the oven");
+    assert_eq!(pending[0]["audio_source"], "default");
+
+    let reminder = loop {
+        let ev = live.next().await;
+        if ev.event.as_deref() == Some("reminder") {
+            break ev.data;
+        }
+    };
+    assert_eq!(reminder["text"], "Check the oven");
+    assert_eq!(reminder["late"], false);
+    let spoken = server.dir.path().join("spoken.txt");
+    server
+        .wait_until(|| async { std::fs::read_to_string(&spoken).is_ok_and(|t| t.contains("Check the oven")) })
+        .await;
+    assert!(std::fs::read_to_string(&spoken).unwrap().contains("Water the plants"));
+    server
+        .wait_until(|| async { hooks.received_requests().await.is_some_and(|r| r.len() == 2) })
+        .await;
+    let deliveries: Vec<Value> = hooks.received_requests().await.unwrap().iter().map(|r| r.body_json().unwrap()).collect();
+    assert!(deliveries.iter().all(|d| d["event"] == "reminder"));
+    assert!(deliveries.iter().any(|d| d["data"]["text"] == "Water the plants" && d["data"]["late"] == true));
+
+    let pending: Value = admin(server.http.get(server.url("/reminders"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(pending, serde_json::json!([]));
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(saved, serde_json::json!([]));
+    let requests = openai.received_requests().await.unwrap();
+    let answer = requests.iter().rfind(|r| r.url.path() == "/v1/chat/completions").unwrap();
+    assert!(String::from_utf8_lossy(&answer.body).contains(r#""tool_call_id":"call_1""#));
+}
+
+#[tokio::test]
+async fn gpt_answers_weather_questions_from_the_forecast() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("I hope it stays dry for the hike tomorrow")).mount(&openai).await;
+    chat()
+        .and(body_string_contains(r#""role":"tool""#))
+        .respond_with(completion("Take a jacket, rain is likely tomorrow afternoon."))
+        .with_priority(1)
+        .mount(&openai)
+        .await;
+    chat()
+        .and(body_string_contains("get_weather"))
+        .respond_with(common::tool_call("get_weather", serde_json::json!({ "days": 2 })))
+        .mount(&openai)
+        .await;
+    let meteo = MockServer::start().await;
+    let forecast = serde_json::json!({
+        "current": { "time": "2024-05-03T09:00", "temperature_2m": 54.1, "apparent_temperature": 51.0,
+                     "weather_code": 2, "wind_speed_10m": 6.0, "precipitation": 0.0 },
+        "hourly": { "time": ["2024-05-04T12:00", "2024-05-04T13:00", "2024-05-04T14:00", "2024-05-04T15:00"],
+                    "temperature_2m": [58.0, 59.0, 57.5, 56.0], "precipitation_probability": [20, 40, 80, 85],
+                    "weather_code": [3, 61, 63, 63] },
+        "daily": { "time": ["2024-05-03", "2024-05-04"], "weather_code": [2, 63],
+                   "temperature_2m_max": [61.0, 59.0], "temperature_2m_min": [45.0, 47.0],
+                   "precipitation_probability_max": [5, 85], "precipitation_sum": [0.0, 0.4],
+                   "sunrise": ["2024-05-03T05:50", "2024-05-04T05:49"], "sunset": ["2024-05-03T20:21", "2024-05-04T20:22"] },
+    });
+    Mock::given(method("GET"))
+        .and(path("/v1/forecast"))
+        .and(wiremock::matchers::query_param("latitude", "45.52"))
+        .and(wiremock::matchers::query_param("forecast_days", "2"))
+        .and(wiremock::matchers::query_param("temperature_unit", "fahrenheit"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(forecast))
+        .expect(1)
+        .mount(&meteo)
+        .await;
+    let meteo_url = meteo.uri();
+    let env = [
+        ("WEATHER_ENABLED", "true"),
+        ("WEATHER_LATITUDE", "45.52"),
+        ("WEATHER_LONGITUDE", "-122.68"),
+        ("WEATHER_PLACE", "Portland, OR"),
+        ("WEATHER_UNITS", "imperial"),
+        ("WEATHER_API_URL", meteo_url.as_str()),
+    ];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.json::<Value>().await.unwrap()["gpt_response"], "Take a jacket, rain is likely tomorrow afternoon.");
+    let requests = openai.received_requests().await.unwrap();
+    let chats: Vec<Value> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/v1/chat/completions")
+        .map(|r| r.body_json().unwrap())
+        .collect();
+    assert_eq!(chats.len(), 2);
+    let offered: Vec<&str> = chats[0]["tools"].as_array().unwrap().iter().filter_map(|t| t["function"]["name"].as_str()).collect();
+    assert_eq!(offered, ["get_weather", "get_local_info"]);
+    // What GPT was told
+    let result = chats[1]["messages"].as_array().unwrap().last().unwrap();
+    assert_eq!(result["role"], "tool");
+    let result: Value = serde_json::from_str(result["content"].as_str().unwrap()).unwrap();
+    assert_eq!(result["place"], "Portland, OR");
+    assert_eq!(result["days"][1]["weather"], "rain");
+    assert_eq!(result["days"][1]["chance_of_rain_percent"], 85);
+    // Every third hour
+    assert_eq!(result["hourly"].as_array().unwrap().len(), 2);
+    assert_eq!(result["hourly"][1]["weather"], "rain");
+}
+
+#[tokio::test]
+async fn a_node_sends_its_mic_to_the_hub_while_the_hub_records() {
+    let openai = mock_openai("the kettle is boiling", "Someone put the kettle on.").await;
+    let hub = TestServer::start_with_config(
+        &openai.uri(),
+        "[[audio.sources]]\nname = \"kitchen\"\nmic_backend = \"node\"\n",
+        &[("HUB_TOKEN", "hub-secret")],
+    )
+    .await;
+    // Only the hub talks to OpenAI
+    let unused = MockServer::start().await;
+    let hub_url = hub.url.clone();
+    let _node = TestServer::start_with_env(
+        &unused.uri(),
+        &[
+            ("NODE_ENABLED", "true"),
+            ("HUB_URL", hub_url.as_str()),
+            ("NODE_NAME", "kitchen"),
+            ("NODE_LABEL", "Kitchen"),
+            ("NODE_TOKEN", "hub-secret"),
+            ("NODE_HEARTBEAT_SECS", "1"),
+        ],
+    )
+    .await;
+
+    hub.wait_until(|| async { hub.get_json("/nodes").await[0]["healthy"] == true }).await;
+    let nodes = hub.get_json("/nodes").await;
+    assert_eq!(nodes[0]["name"], "kitchen");
+    assert_eq!(nodes[0]["label"], "Kitchen");
+    assert_eq!(nodes[0]["recording"], false);
+    let ready = hub.get_json("/health/ready").await;
+    assert!(ready["checks"].as_array().unwrap().iter().any(|c| c["name"] == "node:kitchen" && c["ok"] == true));
+
+    // A wrong token is turned away
+    let resp = hub
+        .http
+        .put(hub.url("/nodes/kitchen"))
+        .bearer_auth("guess")
+        .json(&serde_json::json!({ "label": "Impostor" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+    // And answered before the hub waits on a body it would buffer
+    let mut conn = tokio::net::TcpStream::connect(hub.url.trim_start_matches("http://")).await.unwrap();
+    let head = "POST /nodes/kitchen/chunks HTTP/1.1\r\nHost: hub\r\nAuthorization: Bearer guess\r\nContent-Type: audio/wav\r\nContent-Length: 1000000\r\n\r\nRIFF";
+    tokio::io::AsyncWriteExt::write_all(&mut conn, head.as_bytes()).await.unwrap();
+    let mut answer = [0u8; 12];
+    tokio::time::timeout(std::time::Duration::from_secs(5), tokio::io::AsyncReadExt::read_exact(&mut conn, &mut answer))
+        .await
+        .expect("the hub waited for the body")
+        .unwrap();
+    assert_eq!(&answer, b"HTTP/1.1 401");
+
+    assert_eq!(hub.post("/sources/kitchen/start").await.status(), 200);
+    hub.wait_until(|| async {
+        hub.log_records().await.iter().any(|r| r["audio_source"] == "kitchen" && r["text"] == "Someone put the kettle on.")
+    })
+    .await;
+    assert_eq!(hub.post("/sources/kitchen/stop").await.status(), 200);
+
+    let nodes = hub.get_json("/nodes").await;
+    assert!(nodes[0]["chunks_received"].as_u64().unwrap() >= 1);
+    assert!(unused.received_requests().await.unwrap().is_empty());
+}
+
+#[cfg(feature = "graphql")]
+#[tokio::test]
+async fn instances_sync_entries_and_the_later_write_wins() {
+    // Both already have an entry from a third instance; the
+    // bedroom's copy was corrected later
+    let state = tempfile::TempDir::new().unwrap();
+    let attic = |text: &str, updated_at: Option<&str>| {
+        let mut entry = serde_json::json!({
+            "timestamp": "2024-01-01T10:00:00+00:00", "source": "Microphone", "text": text,
+            "audio_source": "default", "session_id": "attic-1", "chunk_id": "a77c0001", "instance": "attic",
+        });
+        if let Some(updated_at) = updated_at {
+            entry["updated_at"] = updated_at.into();
+        }
+        serde_json::json!([entry]).to_string()
+    };
+    let bedroom_file = state.path().join("bedroom.json");
+    let living_file = state.path().join("living.json");
+    std::fs::write(&bedroom_file, attic("the fuse box is in the garage", Some("2024-01-02T09:00:00+00:00"))).unwrap();
+    std::fs::write(&living_file, attic("the fuse box is in the basement", None)).unwrap();
+
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let bedroom = TestServer::start_with_env(
+        &openai.uri(),
+        &[
+            ("SYNC_ENABLED", "true"),
+            ("SYNC_INSTANCE", "bedroom"),
+            ("SYNC_TOKEN", "sync-secret"),
+            ("SYNC_FILE", bedroom_file.to_str().unwrap()),
+        ],
+    )
+    .await;
+    assert_eq!(bedroom.post("/record_once").await.status(), 200);
+
+    // The wrong token gets nothing
+    let resp = bedroom.http.get(bedroom.url("/sync/entries")).bearer_auth("guess").send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let peers = bedroom.url.clone();
+    let living = TestServer::start_with_env(
+        &openai.uri(),
+        &[
+            ("SYNC_ENABLED", "true"),
+            ("SYNC_INSTANCE", "living-room"),
+            ("SYNC_TOKEN", "sync-secret"),
+            ("SYNC_PEERS", peers.as_str()),
+            ("SYNC_INTERVAL_SECS", "1"),
+            ("SYNC_FILE", living_file.to_str().unwrap()),
+        ],
+    )
+    .await;
+    living.wait_until(|| async { living.get_json("/sync").await["synced_entries"] == 3 }).await;
+    let status = living.get_json("/sync").await;
+    assert_eq!(status["instance"], "living-room");
+    assert_eq!(status["peers"][0]["instance"], "bedroom");
+    assert_eq!(status["peers"][0]["conflicts"], 1);
+    assert!(status["peers"][0]["last_error"].is_null());
+
+    // Searchable like the living room's own entries
+    let query = serde_json::json!({ "query": r#"{ oven: entries(contains: "oven") { instance source text }
+        fuse: entries(contains: "fuse box") { instance text } }"# });
+    let resp: Value = living.http.post(living.url("/graphql")).json(&query).send().await.unwrap().json().await.unwrap();
+    let oven = resp["data"]["oven"].as_array().unwrap();
+    assert_eq!(oven.len(), 2);
+    assert!(oven.iter().all(|e| e["instance"] == "bedroom"));
+    assert_eq!(resp["data"]["fuse"], serde_json::json!([{ "instance": "attic", "text": "the fuse box is in the garage" }]));
+    // Kept across restarts
+    let kept: Value = serde_json::from_str(&std::fs::read_to_string(&living_file).unwrap()).unwrap();
+    assert_eq!(kept.as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn failures_go_to_sentry_without_transcripts() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("my bank pin is 4321")).mount(&openai).await;
+    // An error body that quotes what it was sent
+    chat()
+        .respond_with(ResponseTemplate::new(400).set_body_string(r#"{"error":{"message":"Can't answer: my bank pin is 4321"}}"#))
+        .mount(&openai)
+        .await;
+    let sentry = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/42/envelope/"))
+        .and(wiremock::matchers::header_regex("X-Sentry-Auth", "sentry_key=publickey"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&sentry)
+        .await;
+    let dsn = format!("{}/42", sentry.uri().replace("://", "://publickey@"));
+    let server =
+        TestServer::start_with_env(&openai.uri(), &[("SENTRY_DSN", dsn.as_str()), ("SENTRY_ENVIRONMENT", "test")]).await;
+
+    assert!(!server.post("/record_once").await.status().is_success());
+    server.wait_until(|| async { !sentry.received_requests().await.unwrap().is_empty() }).await;
+    let requests = sentry.received_requests().await.unwrap();
+    let body = String::from_utf8(requests[0].body.clone()).unwrap();
+    assert!(!body.contains("4321"), "{body}");
+    let lines: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines[1]["type"], "event");
+    let event = &lines[2];
+    assert_eq!(event["event_id"], lines[0]["event_id"]);
+    assert_eq!(event["environment"], "test");
+    assert_eq!(event["tags"]["task"], "record_once");
+    assert_eq!(event["tags"]["backend"], "file");
+    assert_eq!(event["contexts"]["pipeline"]["chunk_secs"], 1);
+    assert_eq!(event["contexts"]["pipeline"]["last_api_status"], 400);
+    assert!(event["exception"]["values"][0]["value"].as_str().unwrap().contains("[transcript]"));
+}
+
+#[tokio::test]
+async fn control_actions_are_audited_with_who_and_where() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let env = [("UI_USERNAME", "owner"), ("UI_PASSWORD", "hunter2"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let http = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    let login = |username: &'static str, password: &'static str| {
+        http.post(server.url("/login")).form(&[("username", username), ("password", password)]).send()
+    };
+
+    assert_eq!(login("mallory", "guess").await.unwrap().status(), 303);
+    let resp = login("owner", "hunter2").await.unwrap();
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    // Refused, then done by the logged-in user, then with the admin token
+    assert_eq!(http.post(server.url("/sources/default/start")).send().await.unwrap().status(), 401);
+    let resp = http.post(server.url("/sources/default/start")).header("Cookie", &cookie).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let resp = http.post(server.url("/sources/default/stop")).bearer_auth("s3cret").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    // Reading isn't a control action
+    http.get(server.url("/status")).header("Cookie", &cookie).send().await.unwrap();
+
+    let audit: Value = http.get(server.url("/audit")).bearer_auth("s3cret").send().await.unwrap().json().await.unwrap();
+    let entries: Vec<(&str, &str, bool)> = audit
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["actor"].as_str().unwrap(), e["action"].as_str().unwrap(), e["ok"].as_bool().unwrap()))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("admin token", "POST /sources/default/stop", true),
+            ("owner", "POST /sources/default/start", true),
+            ("anonymous", "POST /sources/default/start", false),
+            ("owner", "login", true),
+            ("mallory", "login", false),
+        ]
+    );
+    assert_eq!(audit[2]["status"], 401);
+    assert_eq!(audit[0]["ip"], "127.0.0.1");
+    assert_eq!(audit[0]["via"], "http");
+
+    let mallory: Value =
+        http.get(server.url("/audit?actor=mallory")).bearer_auth("s3cret").send().await.unwrap().json().await.unwrap();
+    assert_eq!(mallory.as_array().unwrap().len(), 1);
+    // Without the token or a login, no audit log
+    assert_eq!(http.get(server.url("/audit")).send().await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn scoped_tokens_only_reach_what_their_scope_allows() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let config = "[tokens.hallway]\ntoken = \"wall-tablet\"\nscope = \"display\"\n\n\
+                  [tokens.kitchen]\ntoken = \"kitchen-remote\"\nscope = \"control\"\n";
+    let env = [("UI_USERNAME", "owner"), ("UI_PASSWORD", "hunter2"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_config(&openai.uri(), config, &env).await;
+    let http = &server.http;
+    let status = |req: reqwest::RequestBuilder| async move { req.send().await.unwrap().status().as_u16() };
+
+    // The display token reads, and nothing more
+    assert_eq!(status(http.get(server.url("/transcript")).bearer_auth("wall-tablet")).await, 200);
+    assert_eq!(status(http.get(server.url("/i18n")).bearer_auth("wall-tablet")).await, 200);
+    let refused = http.post(server.url("/start_recording")).bearer_auth("wall-tablet").send().await.unwrap();
+    assert_eq!(refused.status(), 403);
+    assert_eq!(refused.json::<Value>().await.unwrap()["code"], "insufficient_scope");
+    assert_eq!(status(http.get(server.url("/status")).bearer_auth("wall-tablet")).await, 403);
+    // A wrong token is no token
+    assert_eq!(status(http.get(server.url("/transcript")).bearer_auth("guess")).await, 401);
+
+    // The control token starts and stops, but can't change settings
+    assert_eq!(status(http.post(server.url("/sources/default/start")).bearer_auth("kitchen-remote")).await, 200);
+    assert_eq!(status(http.post(server.url("/sources/default/stop")).bearer_auth("kitchen-remote")).await, 200);
+    assert_eq!(status(http.get(server.url("/status")).bearer_auth("kitchen-remote")).await, 200);
+    assert_eq!(status(http.get(server.url("/admin/settings")).bearer_auth("kitchen-remote")).await, 403);
+    assert_eq!(status(http.post(server.url("/reload")).bearer_auth("kitchen-remote")).await, 403);
+
+    // A browser opens the page once with the token and keeps a session of the same scope
+    let page = http.get(server.url("/?access_token=wall-tablet")).send().await.unwrap();
+    assert_eq!(page.status(), 200);
+    let cookie = page.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    assert_eq!(status(http.get(server.url("/transcript")).header("Cookie", &cookie)).await, 200);
+    assert_eq!(status(http.post(server.url("/start_recording")).header("Cookie", &cookie)).await, 403);
+    let page = http.get(server.url("/?access_token=kitchen-remote")).send().await.unwrap();
+    let kitchen_cookie = page.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    assert_eq!(status(http.get(server.url("/status")).header("Cookie", &kitchen_cookie)).await, 200);
+
+    let audit: Value = http.get(server.url("/audit")).bearer_auth("s3cret").send().await.unwrap().json().await.unwrap();
+    let actors: Vec<&str> = audit.as_array().unwrap().iter().map(|e| e["actor"].as_str().unwrap()).collect();
+    assert!(actors.contains(&"token:kitchen") && actors.contains(&"token:hallway"), "{actors:?}");
+
+    // A reload that revokes one token and narrows the other reaches their sessions too
+    let config_path = server.dir.path().join("silentnight.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    let config = config
+        .replace("[tokens.hallway]\ntoken = \"wall-tablet\"\nscope = \"display\"\n", "")
+        .replace("scope = \"control\"", "scope = \"display\"");
+    std::fs::write(&config_path, config).unwrap();
+    assert_eq!(status(http.post(server.url("/reload")).bearer_auth("s3cret")).await, 200);
+    assert_eq!(status(http.get(server.url("/transcript")).header("Cookie", &cookie)).await, 401);
+    assert_eq!(status(http.get(server.url("/transcript")).bearer_auth("wall-tablet")).await, 401);
+    assert_eq!(status(http.get(server.url("/transcript")).header("Cookie", &kitchen_cookie)).await, 200);
+    assert_eq!(status(http.get(server.url("/status")).header("Cookie", &kitchen_cookie)).await, 403);
+}
+
+#[tokio::test]
+async fn a_new_bearer_value_on_each_request_doesnt_reset_the_rate_limit() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let env = [("RATE_LIMIT_PER_MINUTE", "1"), ("RATE_LIMIT_BURST", "2")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    // Not tokens, so they're all the one client
+    let mut statuses = Vec::new();
+    for guess in ["guess-1", "guess-2", "guess-3"] {
+        let resp = server.http.get(server.url("/conversation_log")).bearer_auth(guess).send().await.unwrap();
+        statuses.push(resp.status().as_u16());
+    }
+    assert_ne!(statuses[0], 429);
+    assert_ne!(statuses[1], 429);
+    assert_eq!(statuses[2], 429);
+}
+
+#[tokio::test]
+async fn workspaces_keep_their_logs_apart() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let config = "[openai.personas]\nnotetaker = \"You take notes.\"\n\n\
+                  [[audio.sources]]\nname = \"sales\"\n\n\
+                  [workspaces.sales]\nsources = [\"sales\"]\npersona = \"notetaker\"\nretention_days = 30\n\n\
+                  [workspaces.support]\nsources = []\n\n\
+                  [tokens.sales]\ntoken = \"sales-key\"\nscope = \"admin\"\nworkspace = \"sales\"\n";
+    let env = [("UI_USERNAME", "owner"), ("UI_PASSWORD", "hunter2"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_config(&openai.uri(), config, &env).await;
+    let http = &server.http;
+    let get = |path: &str, token: &str| {
+        let request = http.get(server.url(path)).bearer_auth(token);
+        async move { request.send().await.unwrap() }
+    };
+    let log = |path: &str, token: &str| {
+        let request = get(path, token);
+        async move { request.await.text().await.unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect::<Vec<Value>>() }
+    };
+
+    // The default source logs where it always has
+    assert_eq!(http.post(server.url("/record_once")).bearer_auth("s3cret").send().await.unwrap().status(), 200);
+    // The team records into its own workspace
+    assert_eq!(http.post(server.url("/sources/sales/start")).bearer_auth("sales-key").send().await.unwrap().status(), 200);
+    server.wait_until(|| async { log("/conversation_log", "sales-key").await.len() >= 2 }).await;
+    assert_eq!(http.post(server.url("/sources/sales/stop")).bearer_auth("sales-key").send().await.unwrap().status(), 200);
+
+    let sales = log("/conversation_log", "sales-key").await;
+    assert!(sales.iter().all(|r| r["audio_source"] == "sales" && r["workspace"] == "sales"), "{sales:?}");
+    let response = sales.iter().find(|r| r["source"] == "OPENAI RESPONSE").unwrap();
+    assert_eq!(response["persona"], "notetaker");
+    assert!(server.dir.path().join("workspaces/sales/conversation_log.json").exists());
+    let default = log("/conversation_log", "s3cret").await;
+    assert!(!default.is_empty() && default.iter().all(|r| r["audio_source"] == "default"), "{default:?}");
+    // The login may look into a workspace; the team can't look out of theirs
+    let looked_into = log("/conversation_log?workspace=sales", "s3cret").await;
+    assert!(looked_into.len() >= sales.len() && looked_into.iter().all(|r| r["workspace"] == "sales"));
+    assert_eq!(get("/conversation_log?workspace=nope", "s3cret").await.status(), 404);
+    for path in ["/conversation_log?workspace=other", "/status", "/live_log", "/sources/default/transcript"] {
+        let refused = get(path, "sales-key").await;
+        assert_eq!(refused.status(), 403, "{path}");
+        assert_eq!(refused.json::<Value>().await.unwrap()["code"], "outside_workspace", "{path}");
+    }
+    let default_entry = default[0]["entry_id"].as_str().unwrap();
+    assert_eq!(get(&format!("/entries/{default_entry}"), "sales-key").await.status(), 404);
+    assert_eq!(get(&format!("/entries/{}", response["entry_id"].as_str().unwrap()), "sales-key").await.status(), 200);
+
+    // GPT answered the team as their persona
+    let requests = openai.received_requests().await.unwrap();
+    let chats: Vec<String> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/v1/chat/completions")
+        .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+        .collect();
+    assert!(chats.iter().any(|body| body.contains("You take notes.")), "{chats:?}");
+
+    // The team's browser session stays in the workspace, and ends when the token is revoked
+    let page = http.get(server.url("/conversation_log?access_token=sales-key")).send().await.unwrap();
+    let cookie = page.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    let other = || http.get(server.url("/conversation_log?workspace=support")).header("Cookie", &cookie).send();
+    assert_eq!(other().await.unwrap().status(), 403);
+    let config_path = server.dir.path().join("silentnight.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    let config = config.replace("[tokens.sales]\ntoken = \"sales-key\"\nscope = \"admin\"\nworkspace = \"sales\"\n", "");
+    std::fs::write(&config_path, config).unwrap();
+    assert_eq!(http.post(server.url("/reload")).bearer_auth("s3cret").send().await.unwrap().status(), 200);
+    assert_eq!(other().await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn a_phone_pairs_once_by_scanning_the_qr_code() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let env = [("UI_USERNAME", "owner"), ("UI_PASSWORD", "hunter2"), ("ADMIN_TOKEN", "s3cret")];
+    let config = "[tokens.hallway]\ntoken = \"wall-tablet\"\nscope = \"display\"\n";
+    let server = TestServer::start_with_config(&openai.uri(), config, &env).await;
+    let http = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+
+    // Behind the login like the rest of the UI
+    assert_eq!(http.get(server.url("/qr.png")).send().await.unwrap().status(), 401);
+    let resp = http.get(server.url("/qr.png")).bearer_auth("s3cret").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-type"], "image/png");
+    // The LAN address, with the server's port
+    let pairing_url = resp.headers()["x-pairing-url"].to_str().unwrap().to_string();
+    let port = server.url.rsplit(':').next().unwrap();
+    let (base, path) = pairing_url.split_once(&format!(":{port}")).unwrap();
+    assert!(base.starts_with("http://"), "{pairing_url}");
+    assert!(path.starts_with("/pair?token="), "{pairing_url}");
+    let png = resp.bytes().await.unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+    let resp = http.get(server.url(path)).send().await.unwrap();
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers()["location"], "/");
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with("sn_session="), "{cookie}");
+    assert_eq!(http.get(server.url("/status")).header("Cookie", &cookie).send().await.unwrap().status(), 200);
+    // Only once
+    let resp = http.get(server.url(path)).send().await.unwrap();
+    assert_eq!(resp.headers()["location"], "/login?failed=1");
+
+    let audit: Value = http.get(server.url("/audit")).bearer_auth("s3cret").send().await.unwrap().json().await.unwrap();
+    assert_eq!(audit[0]["actor"], "qr pairing");
+    assert_eq!(audit[0]["ok"], false);
+    assert_eq!(audit[1]["actor"], "qr pairing");
+    assert_eq!(audit[1]["ok"], true);
+
+    // A display's code pairs a phone as the display, not as the login
+    let resp = http.get(server.url("/qr.png")).bearer_auth("wall-tablet").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let pairing_url = resp.headers()["x-pairing-url"].to_str().unwrap().to_string();
+    let (_, path) = pairing_url.split_once(&format!(":{port}")).unwrap();
+    let resp = http.get(server.url(path)).send().await.unwrap();
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().split(';').next().unwrap().to_string();
+    assert_eq!(http.get(server.url("/transcript")).header("Cookie", &cookie).send().await.unwrap().status(), 200);
+    assert_eq!(http.get(server.url("/status")).header("Cookie", &cookie).send().await.unwrap().status(), 403);
+
+    // pairing.url wins, and without login the code just opens the UI
+    let server = TestServer::start_with_env(&openai.uri(), &[("PAIRING_URL", "http://silentnight.local:8080/")]).await;
+    let resp = server.http.get(server.url("/qr.png")).send().await.unwrap();
+    assert_eq!(resp.headers()["x-pairing-url"], "http://silentnight.local:8080/");
+}
+
+#[tokio::test]
+async fn each_display_gets_its_own_profile_and_hears_changes() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let config = r##"
+[displays.default]
+font_size_px = 32
+
+[displays.hallway]
+layout = "response_only"
+refresh = "poll"
+poll_secs = 30
+"##;
+    let server = TestServer::start_with_config(&openai.uri(), config, &[("ADMIN_TOKEN", "s3cret")]).await;
+
+    let default = server.get_json("/display/profile").await;
+    assert_eq!(default["display"], "default");
+    assert_eq!(default["font_size_px"], 32);
+    assert_eq!(default["layout"], "transcript_and_response");
+    let hallway = server.get_json("/display/profile?display=hallway").await;
+    assert_eq!(hallway["layout"], "response_only");
+    assert_eq!(hallway["refresh"], "poll");
+    assert_eq!(hallway["poll_secs"], 30);
+    assert_eq!(hallway["font_size_px"], 24);
+    // A display without its own profile gets the default one
+    assert_eq!(server.get_json("/display/profile?display=kitchen").await["font_size_px"], 32);
+
+    let body = serde_json::json!({"font_size_px": 48, "foreground": "#fff", "background": "#102030"});
+    let put = |body: &Value| server.http.put(server.url("/display/profile?display=kitchen")).json(body);
+    assert_eq!(put(&body).send().await.unwrap().status(), 401);
+    let bad = serde_json::json!({"foreground": "green"});
+    let resp = put(&bad).bearer_auth("s3cret").send().await.unwrap();
+    assert_eq!(resp.status(), 400);
+    assert_eq!(resp.json::<Value>().await.unwrap()["code"], "invalid_settings");
+
+    let mut live = SseStream::open(&server, "/live_log").await;
+    let resp = put(&body).bearer_auth("s3cret").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let event = live.next().await;
+    assert_eq!(event.event.as_deref(), Some("display"));
+    assert_eq!(event.data["display"], "kitchen");
+    assert_eq!(event.data["font_size_px"], 48);
+    let kitchen = server.get_json("/display/profile?display=kitchen").await;
+    assert_eq!(kitchen["background"], "#102030");
+    assert_eq!(kitchen["layout"], "transcript_and_response");
+}
+
+#[tokio::test]
+async fn pages_and_ui_strings_follow_the_language() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let env = [("UI_USERNAME", "owner"), ("UI_PASSWORD", "hunter2"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let get = |path: &str, accept_language: &str| {
+        server.http.get(server.url(path)).header("Accept-Language", accept_language).bearer_auth("s3cret")
+    };
+
+    // The login page needs its strings before anyone logs in
+    let resp = server.http.get(server.url("/i18n")).header("Accept-Language", "de-AT,de;q=0.9,en;q=0.8").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-language"], "de");
+    let i18n: Value = resp.json().await.unwrap();
+    assert_eq!(i18n["language"], "de");
+    assert_eq!(i18n["strings"]["wrong_login"], "Falscher Benutzername oder falsches Passwort");
+    assert_eq!(i18n["strings"]["listening"], "Hört zu…");
+    let i18n: Value = get("/i18n", "ja, en;q=0.5").send().await.unwrap().json().await.unwrap();
+    assert_eq!(i18n["language"], "en");
+    assert_eq!(i18n["strings"]["muted_until"], "Muted: quiet hours until {time}");
+
+    let html = get("/sessions", "fr-CA").send().await.unwrap().text().await.unwrap();
+    assert!(html.contains("<html lang=\"fr\">"), "{html}");
+    assert!(html.contains("Aucune session pour l&#39;instant."), "{html}");
+    assert!(html.contains("<a href=\"/\">En direct</a>"), "{html}");
+
+    // ui.language wins over the browser
+    let env = [("UI_LANGUAGE", "es")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let resp = server.http.get(server.url("/sessions")).header("Accept-Language", "de").send().await.unwrap();
+    assert_eq!(resp.headers()["content-language"], "es");
+    assert!(resp.text().await.unwrap().contains("Todavía no hay sesiones."));
+    assert_eq!(server.get_json("/i18n").await["language"], "es");
+}
+
+#[tokio::test]
+async fn the_dashboard_shows_pipeline_health() {
+    let openai = mock_openai("hello", "Someone is greeting.").await;
+    let server = TestServer::start(&openai.uri()).await;
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    assert_eq!(server.post("/record_once").await.status(), 200);
+
+    let resp = server.http.get(server.url("/dashboard")).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    let html = resp.text().await.unwrap();
+    assert!(html.contains("<meta http-equiv=\"refresh\" content=\"5\"/>"), "{html}");
+    assert!(html.contains("<td>default</td><td>idle</td>"), "{html}");
+    assert!(html.contains("capture 0, screen 0, transcribe 0, respond 0, persist 0"), "{html}");
+    assert!(html.contains("0 running, 0 waiting, 0 rejected"), "{html}");
+    // Two Whisper and two GPT requests make a line each; one-off
+    // chunks don't go through the recording loop, so no end-to-end
+    assert_eq!(html.matches("<svg class=\"spark\"").count(), 2, "{html}");
+    assert!(html.contains("<td>2</td><td>capture 0"), "{html}");
+    assert!(html.contains("<th>Last error</th><td>none</td>"), "{html}");
+
+    let html = server.http.get(server.url("/dashboard?refresh=0")).send().await.unwrap().text().await.unwrap();
+    assert!(!html.contains("http-equiv"), "{html}");
+}
+
+#[tokio::test]
+async fn personal_details_are_masked_before_gpt_and_the_log() {
+    let heard = "call 555-123-4567 or ann@example.com, my card is 4111 1111 1111 1111, \
+                 I'm at 221 Baker Street in Springfield, order 1234 5678 9012 3456";
+    let masked = "call [phone] or [email], my card is [card], \
+                  I'm at [address] in [address], order 1234 5678 9012 3456";
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript(heard)).mount(&openai).await;
+    chat()
+        .and(body_string_contains(masked))
+        .respond_with(completion("Someone is sharing their details."))
+        .expect(1)
+        .mount(&openai)
+        .await;
+    // Character offsets, as Presidio gives them
+    let start = heard.find("Springfield").unwrap();
+    let ner = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/analyze"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "entity_type": "LOCATION", "start": start, "end": start + "Springfield".len(), "score": 0.85 },
+            { "entity_type": "PERSON", "start": 0, "end": 4, "score": 0.9 },
+        ])))
+        .expect(1)
+        .mount(&ner)
+        .await;
+    let ner_url = format!("{}/analyze", ner.uri());
+    let env = [("REDACTION_ENABLED", "true"), ("REDACTION_NER_URL", ner_url.as_str())];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    let chunk: Value = server.post("/record_once").await.json().await.unwrap();
+    assert_eq!(chunk["transcript"], masked);
+    let records = server.log_records().await;
+    assert_eq!(records[0]["text"], masked);
+    let log = std::fs::read_to_string(server.dir.path().join("server.log")).unwrap();
+    assert!(!log.contains("555-123-4567"));
+}
+
+#[tokio::test]
+async fn local_privacy_mode_refuses_servers_outside_the_network() {
+    // A local Whisper/GPT server is fine
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("PRIVACY_MODE", "local")]).await;
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    let status = server.get_json("/status").await;
+    assert_eq!(status["privacy"]["mode"], "local");
+    assert_eq!(status["privacy"]["refused"], 0);
+    drop(server);
+
+    // A public one (TEST-NET-3, nothing listens there) is refused
+    // before anything is sent
+    let server = TestServer::start_with_env("http://203.0.113.9", &[("PRIVACY_MODE", "local")]).await;
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 503);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "privacy_local_only");
+    assert!(server.log_records().await.is_empty());
+    let status = server.get_json("/status").await;
+    assert_eq!(status["privacy"]["refused"], 1);
+    let ready: Value = server.http.get(server.url("/health/ready")).send().await.unwrap().json().await.unwrap();
+    let check = ready["checks"].as_array().unwrap().iter().find(|c| c["name"] == "openai_local").unwrap();
+    assert_eq!(check["ok"], false);
+}
+
+#[tokio::test]
+async fn nothing_is_recorded_in_quiet_hours_unless_an_admin_overrides_them() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    // Quiet all day
+    let env = [("QUIET_HOURS", "00:00-12:00, 12:00-00:00"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let admin = |req: reqwest::RequestBuilder| req.bearer_auth("s3cret");
+
+    let quiet = server.get_json("/quiet_hours").await;
+    assert_eq!(quiet["active"], true);
+    assert_eq!(quiet["windows"], serde_json::json!(["00:00-12:00", "12:00-00:00"]));
+    for path in ["/record_once", "/start_recording"] {
+        let resp = server.post(path).await;
+        assert_eq!(resp.status(), 409);
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(body["code"], "quiet_hours");
+    }
+    assert!(server.log_records().await.is_empty());
+
+    // Only an admin can override them
+    assert_eq!(server.post("/quiet_hours/override").await.status(), 401);
+    let resp = admin(server.http.post(server.url("/quiet_hours/override")))
+        .json(&serde_json::json!({ "until": "2001-01-01T00:00:00Z" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let resp = admin(server.http.post(server.url("/quiet_hours/override"))).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let quiet: Value = resp.json().await.unwrap();
+    assert_eq!(quiet["active"], false);
+    assert!(quiet["override_until"].is_string());
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    assert!(!server.log_records().await.is_empty());
+
+    // Until the override is taken back
+    let resp = admin(server.http.delete(server.url("/quiet_hours/override"))).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(server.post("/record_once").await.status(), 409);
+}
+
+#[tokio::test]
+async fn chunks_an_opted_out_speaker_dominates_are_discarded() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let embeddings = MockServer::start().await;
+    let voice = |segments: Value| ResponseTemplate::new(200).set_body_json(serde_json::json!({ "segments": segments }));
+    // The enrollment sample, then a chunk that's all them, then
+    // one where they only say a word
+    Mock::given(method("POST"))
+        .respond_with(voice(serde_json::json!([{ "embedding": [1.0, 0.0, 0.0] }])))
+        .up_to_n_times(1)
+        .mount(&embeddings)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(voice(serde_json::json!([{ "start": 0.0, "end": 0.1, "embedding": [0.9, 0.1, 0.0] }])))
+        .up_to_n_times(1)
+        .mount(&embeddings)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(voice(serde_json::json!([
+            { "start": 0.0, "end": 0.02, "embedding": [0.95, 0.05, 0.0] },
+            { "start": 0.02, "end": 0.1, "embedding": [0.0, 1.0, 0.0] },
+        ])))
+        .mount(&embeddings)
+        .await;
+    let env = [("OPT_OUT_ENABLED", "true"), ("OPT_OUT_EMBEDDING_URL", &embeddings.uri()), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let admin = |req: reqwest::RequestBuilder| req.bearer_auth("s3cret");
+    let sample = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/silence.wav")).unwrap();
+
+    // Only an admin can enroll someone
+    let enroll = || server.http.post(server.url("/opt_out/profiles?name=Grandma")).body(sample.clone());
+    assert_eq!(enroll().send().await.unwrap().status(), 401);
+    let resp = admin(enroll()).header("Content-Type", "audio/wav").send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    let profile: Value = resp.json().await.unwrap();
+    assert_eq!(profile["name"], "Grandma");
+    let profiles: Value = admin(server.http.get(server.url("/opt_out/profiles"))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(profiles.as_array().unwrap().len(), 1);
+    assert!(profiles[0].get("embedding").is_none());
+
+    // All them: nothing reaches Whisper or the log
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 409);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "speaker_opted_out");
+    assert!(openai.received_requests().await.unwrap().is_empty());
+    assert!(server.log_records().await.is_empty());
+
+    // A word from them among someone else's speech goes through
+    assert_eq!(server.post("/record_once").await.status(), 200);
+    assert!(!server.log_records().await.is_empty());
+
+    let id = profile["id"].as_str().unwrap();
+    let resp = admin(server.http.delete(server.url(&format!("/opt_out/profiles/{id}")))).send().await.unwrap();
+    assert_eq!(resp.status(), 204);
+    let saved = std::fs::read_to_string(server.dir.path().join("voice_profiles.json")).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&saved).unwrap(), serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn forget_the_last_five_minutes_deletes_what_was_said() {
+    let openai = MockServer::start().await;
+    let heard = [
+        (2, "My PIN is 4512."),
+        (1, "Silent night, forget the last five minutes."),
+        (1, "Silent night, stop listening."),
+    ];
+    for (i, (times, text)) in heard.iter().enumerate() {
+        let mock = whisper().respond_with(transcript(text)).with_priority(i as u8 + 1);
+        let mock = if i + 1 < heard.len() { mock.up_to_n_times(*times) } else { mock };
+        mock.mount(&openai).await;
+    }
+    chat().respond_with(completion("Noted.")).mount(&openai).await;
+    let env = [("VOICE_COMMANDS", "true"), ("TTS_COMMAND", "tee tts.txt"), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let mut events = SseStream::open(&server, "/live_log").await;
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server.wait_until(|| async { server.get_json("/status").await["recording"] == false }).await;
+
+    assert!(server.log_records().await.iter().all(|r| !r["text"].as_str().unwrap_or_default().contains("4512")));
+    loop {
+        let event = events.next().await;
+        if event.event.as_deref() == Some("forgotten") {
+            assert_eq!(event.data["audio_source"], "default");
+            break;
+        }
+    }
+    let tts = server.dir.path().join("tts.txt");
+    server
+        .wait_until(|| async { std::fs::read_to_string(&tts).is_ok_and(|t| t == "Okay, I've forgotten the last 5 minutes.") })
+        .await;
+    let audit: Value = server
+        .http
+        .get(server.url("/audit?actor=voice"))
+        .bearer_auth("s3cret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(audit.as_array().unwrap().iter().any(|e| e["action"] == "forget:5 default" && e["ok"] == true), "{audit}");
+}
+
+#[tokio::test]
+async fn keys_come_from_a_private_file_stay_out_of_logs_and_rotate() {
+    let openai = MockServer::start().await;
+    let old_key = "sk-revoked-2f9c41";
+    Mock::given(method("POST"))
+        .and(path("/v1/audio/transcriptions"))
+        .and(wiremock::matchers::header("authorization", format!("Bearer {old_key}").as_str()))
+        .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+            "error": { "message": format!("Incorrect API key provided: {old_key}.") }
+        })))
+        .mount(&openai)
+        .await;
+    whisper().respond_with(transcript("is the oven still on")).mount(&openai).await;
+    chat().respond_with(completion("Someone is asking about the oven.")).mount(&openai).await;
+
+    let secrets = tempfile::TempDir::new().unwrap();
+    let key_file = secrets.path().join("openai.key");
+    std::fs::write(&key_file, format!("{old_key}\n")).unwrap();
+    std::fs::set_permissions(&key_file, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+    let reference = format!("file:{}", key_file.display());
+    let env = [("OPENAI_API_KEY", reference.as_str()), ("ADMIN_TOKEN", "s3cret")];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+
+    assert_ne!(server.post("/record_once").await.status(), 200);
+    let status = server.http.get(server.url("/status")).send().await.unwrap().text().await.unwrap();
+    assert!(!status.contains(old_key), "{status}");
+
+    let rotate = |provider: &str, key: &str| {
+        let body = serde_json::json!({ "provider": provider, "key": key });
+        server.http.post(server.url("/admin/keys")).bearer_auth("s3cret").json(&body).send()
+    };
+    assert_eq!(rotate("discord", "abc").await.unwrap().status(), 400);
+    let rotated: Value = rotate("openai", common::API_KEY).await.unwrap().json().await.unwrap();
+    assert_eq!(rotated["setting"], "openai.api_key");
+    assert_eq!(rotated["stored_in"], "file");
+    assert_eq!(std::fs::read_to_string(&key_file).unwrap().trim(), common::API_KEY);
+
+    let resp = server.post("/record_once").await;
+    assert_eq!(resp.status(), 200);
+    let chunk: Value = resp.json().await.unwrap();
+    assert_eq!(chunk["transcript"], "is the oven still on");
+    let log = std::fs::read_to_string(server.dir.path().join("server.log")).unwrap();
+    assert!(log.contains("********") && !log.contains(old_key), "{log}");
+}
+
+#[tokio::test]
+async fn anonymous_speakers_get_labels_that_last_a_session() {
+    let openai = mock_openai("is the oven still on", "Someone is asking about the oven.").await;
+    let embeddings = MockServer::start().await;
+    let voices = |segments: Value| ResponseTemplate::new(200).set_body_json(serde_json::json!({ "segments": segments }));
+    // Two people, the second listed first, then only the second
+    Mock::given(method("POST"))
+        .respond_with(voices(serde_json::json!([
+            { "start": 0.05, "end": 0.1, "embedding": [0.0, 1.0, 0.0] },
+            { "start": 0.0, "end": 0.05, "embedding": [1.0, 0.0, 0.0] },
+        ])))
+        .up_to_n_times(1)
+        .mount(&embeddings)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(voices(serde_json::json!([{ "start": 0.0, "end": 0.1, "embedding": [0.1, 0.95, 0.0] }])))
+        .mount(&embeddings)
+        .await;
+    let env = [("SPEAKERS_MODE", "anonymous"), ("SPEAKERS_EMBEDDING_URL", &embeddings.uri())];
+    let server = TestServer::start_with_env(&openai.uri(), &env).await;
+    let transcripts = || async {
+        let records = server.log_records().await;
+        records.into_iter().filter(|r| r["source"] == "Microphone").collect::<Vec<Value>>()
+    };
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server.wait_until(|| async { transcripts().await.len() >= 2 }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    server.wait_until(|| async { server.get_json("/status").await["tasks"]["recording"].is_null() }).await;
+    let first = transcripts().await;
+    assert_eq!(first[0]["speakers"], serde_json::json!(["Speaker A", "Speaker B"]));
+    assert_eq!(first[1]["speakers"], serde_json::json!(["Speaker B"]));
+
+    // A new session hasn't heard anyone yet
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server.wait_until(|| async { transcripts().await.len() > first.len() }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    let second = transcripts().await;
+    assert_eq!(second[first.len()]["speakers"], serde_json::json!(["Speaker A"]));
+    let responses = server.log_records().await;
+    assert!(responses.iter().filter(|r| r["source"] == "OPENAI RESPONSE").all(|r| r.get("speakers").is_none()));
+}
+
+#[tokio::test]
+async fn each_kind_of_data_goes_only_where_routing_allows() {
+    use wiremock::matchers::{header, query_param};
+    // [openai], which nothing may go to here
+    let openai = MockServer::start().await;
+    let local_whisper = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/audio/transcriptions"))
+        .respond_with(transcript("the boiler is making that noise again"))
+        .mount(&local_whisper)
+        .await;
+    let azure_eu = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/deployments/gpt-4o/chat/completions"))
+        .and(query_param("api-version", "2024-06-01"))
+        .and(header("api-key", "eu-key"))
+        .respond_with(completion("The boiler is noisy again."))
+        .mount(&azure_eu)
+        .await;
+    // Nothing listens on port 1, so audio falls back to the next provider
+    let config = format!(
+        r#"
+[providers.down]
+base_url = "http://127.0.0.1:1/v1"
+region = "local"
+
+[providers.local_whisper]
+base_url = "{local}/v1"
+region = "local"
+
+[providers.azure_eu]
+base_url = "{eu}/deployments/gpt-4o"
+api_key = "eu-key"
+auth = "api-key"
+api_version = "2024-06-01"
+region = "eu"
+
+[routing]
+audio = ["down", "local_whisper"]
+transcripts = ["azure_eu"]
+summaries = ["azure_eu"]
+regions = ["local", "eu"]
+"#,
+        local = local_whisper.uri(),
+        eu = azure_eu.uri(),
+    );
+    let server = TestServer::start_with_config(&openai.uri(), &config, &[]).await;
+
+    let status = server.get_json("/status").await;
+    assert_eq!(status["routing"]["routes"]["audio"], serde_json::json!(["down", "local_whisper"]));
+    assert_eq!(status["backends"]["stt"], "down/whisper-1");
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server
+        .wait_until(|| async { server.log_records().await.iter().any(|r| r["text"] == "The boiler is noisy again.") })
+        .await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    let audio = local_whisper.received_requests().await.unwrap();
+    assert!(audio.iter().all(|r| r.url.path() == "/v1/audio/transcriptions"));
+    assert!(audio.iter().all(|r| !r.headers.contains_key("authorization")));
+    let text = azure_eu.received_requests().await.unwrap();
+    assert!(!text.is_empty() && text.iter().all(|r| r.url.path() == "/deployments/gpt-4o/chat/completions"));
+    assert!(openai.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn past_sessions_can_be_browsed_as_html() {
+    let openai = mock_openai("is <b>dinner</b> ready", "Someone is asking about dinner.").await;
+    let server = TestServer::start(&openai.uri()).await;
+    let page = |path: &'static str| {
+        let server = &server;
+        async move {
+            let resp = server.http.get(server.url(path)).send().await.expect("GET");
+            let status = resp.status();
+            let html_type = resp.headers()["content-type"].to_str().unwrap().starts_with("text/html");
+            (status, html_type, resp.text().await.unwrap())
+        }
+    };
+    let (status, html, body) = page("/sessions").await;
+    assert_eq!(status, 200);
+    assert!(html);
+    assert!(body.contains("No sessions yet."));
+
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+    server.wait_until(|| async { server.log_records().await.len() >= 2 }).await;
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    let session_id = server.log_records().await[0]["session_id"].as_str().unwrap().to_string();
+
+    let (_, _, list) = page("/sessions").await;
+    assert!(list.contains(&format!("<a href=\"/sessions/{session_id}\">{session_id}</a>")), "{list}");
+
+    let resp = server.http.get(server.url(&format!("/sessions/{session_id}"))).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let transcript = resp.text().await.unwrap();
+    // Escaped, not rendered
+    assert!(transcript.contains("is &lt;b&gt;dinner&lt;/b&gt; ready"), "{transcript}");
+    assert!(!transcript.contains("<b>dinner"));
+    assert!(transcript.contains("&gt; Someone is asking about dinner."));
+
+    let (status, _, _) = page("/sessions/19990101-000000").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn meetings_get_minutes_when_they_stop() {
+    let openai = MockServer::start().await;
+    whisper().respond_with(transcript("Ada will write the release notes, we ship on Friday")).mount(&openai).await;
+    let minutes = serde_json::json!({
+        "summary": "The team agreed to ship.",
+        "decisions": ["Ship on Friday"],
+        "action_items": [
+            { "task": "Write the release notes", "owner": "Ada Lovelace" },
+            { "task": "Book the room", "owner": "Someone else" },
+        ],
+    });
+    chat()
+        .and(body_string_contains("You take the minutes"))
+        .and(body_string_contains("Attendees: Ada Lovelace; Alan Turing"))
+        .respond_with(completion(&format!("```json\n{minutes}\n```")))
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("Noted.")).mount(&openai).await;
+    let server = TestServer::start_with_env(
+        &openai.uri(),
+        &[("OBSIDIAN_DIR", "vault"), ("EXPORT_ON_SESSION_CLOSE", "true")],
+    )
+    .await;
+
+    let start = |body: Value| {
+        let server = &server;
+        async move { server.http.post(server.url("/meetings")).json(&body).send().await.expect("POST") }
+    };
+    assert_eq!(start(serde_json::json!({ "title": " " })).await.status(), 400);
+    assert_eq!(start(serde_json::json!({ "title": "Weekly sync", "source": "nope" })).await.status(), 404);
+    let resp = start(serde_json::json!({ "title": "Weekly sync", "attendees": ["Ada Lovelace", "Alan Turing", "Ada Lovelace"] })).await;
+    assert_eq!(resp.status(), 200);
+    let meeting: Value = resp.json().await.unwrap();
+    assert_eq!(meeting["title"], "Weekly sync");
+    assert_eq!(meeting["attendees"], serde_json::json!(["Ada Lovelace", "Alan Turing"]));
+    let session = meeting["session_id"].as_str().unwrap().to_string();
+    let minutes_path = format!("/meetings/{session}/minutes");
+    assert_eq!(server.http.get(server.url(&minutes_path)).send().await.unwrap().status(), 404);
+
+    server.wait_until(|| async { server.log_records().await.len() >= 2 }).await;
+    assert_eq!(server.log_records().await[0]["session_title"], "Weekly sync");
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+
+    // Written on stop, then exported with them
+    let vault = server.dir.path().join("vault");
+    server.wait_until(|| async { std::fs::read_dir(&vault).is_ok_and(|mut d| d.next().is_some()) }).await;
+    let minutes = server.get_json(&minutes_path).await;
+    assert_eq!(minutes["summary"], "The team agreed to ship.");
+    assert_eq!(minutes["decisions"], serde_json::json!(["Ship on Friday"]));
+    assert_eq!(minutes["action_items"][0]["owner"], "Ada Lovelace");
+    // Not an attendee
+    assert_eq!(minutes["action_items"][1]["owner"], Value::Null);
+    let note = std::fs::read_dir(&vault).unwrap().next().unwrap().unwrap().path();
+    let note = std::fs::read_to_string(note).unwrap();
+    assert!(note.contains("## Decisions\n\n- Ship on Friday\n"), "{note}");
+    assert!(note.contains("## Action items\n\n- [ ] Write the release notes (Ada Lovelace)\n- [ ] Book the room\n"), "{note}");
+
+    assert_eq!(server.post("/meetings/nope/minutes").await.status(), 404);
+    assert_eq!(server.post(&minutes_path).await.status(), 200);
+}
+
+#[tokio::test]
+async fn captions_stream_each_word_at_its_time() {
+    let openai = MockServer::start().await;
+    whisper()
+        .respond_with(|request: &wiremock::Request| {
+            let asked = request.body.windows(b"timestamp_granularities[]".len()).any(|w| w == b"timestamp_granularities[]");
+            if !asked {
+                return transcript("the roof leaks.");
+            }
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "text": "the roof leaks.",
+                "words": [
+                    { "word": "the", "start": 0.0, "end": 0.2 },
+                    { "word": "roof", "start": 0.2, "end": 0.5 },
+                    { "word": "leaks", "start": 0.5, "end": 0.9 },
+                ],
+            }))
+        })
+        .mount(&openai)
+        .await;
+    chat().respond_with(completion("Call a roofer.")).mount(&openai).await;
+    let server = TestServer::start_with_env(&openai.uri(), &[("CAPTIONS_ENABLED", "true")]).await;
+
+    let unknown = server.http.get(server.url("/captions?source=nope")).send().await.unwrap();
+    assert_eq!(unknown.status(), 404);
+    let mut captions = SseStream::open(&server, "/captions?source=default").await;
+    assert_eq!(server.post("/start_recording").await.status(), 200);
+
+    // The first chunk's; later chunks' words come in between
+    let mut words: Vec<Value> = Vec::new();
+    loop {
+        let ev = captions.next().await;
+        assert_eq!(ev.event.as_deref(), Some("caption"));
+        if words.first().is_some_and(|first| first["chunk_id"] != ev.data["chunk_id"]) {
+            continue;
+        }
+        let last = ev.data["last"] == true;
+        words.push(ev.data);
+        if last {
+            break;
+        }
+    }
+    assert_eq!(server.post("/stop_recording").await.status(), 200);
+    // The transcript's words, with Whisper's times
+    let said: Vec<&str> = words.iter().map(|w| w["word"].as_str().unwrap()).collect();
+    assert_eq!(said, ["the", "roof", "leaks."]);
+    assert_eq!(words[2]["index"], 2);
+    assert_eq!(words[2]["start"], 0.5);
+    assert_eq!(words[2]["end"], 0.9);
+    assert_eq!(words[0]["audio_source"], "default");
+    assert!(words[0]["session_id"].is_string() && words[0]["chunk_id"].is_string());
+}